@@ -0,0 +1,258 @@
+//! Interchangeable ways of solving a puzzle, each with a name.
+//!
+//! A day which has more than one algorithm lists them in `DAYS` and
+//! registers them with `Registry::for_day`, so that `--algorithm NAME`
+//! chooses between them and the `crosscheck` command can run them all
+//! and check that they agree.
+use std::fmt;
+use std::time::{Duration, Instant};
+
+/// The days which have more than one algorithm, with the names of
+/// their algorithms.  `aoc crosscheck` works for exactly these days.
+pub const DAYS: &[(u32, &[&str])] = &[
+    (1, &["hash", "bitset"]),
+    (2, &["regex", "bytes"]),
+    (9, &["windows", "scan", "parallel"]),
+    (10, &["runs", "dp", "matrix"]),
+    (13, &["crt", "sieve"]),
+    (17, &["sparse", "dense", "symmetric", "memo"]),
+    (18, &["tree", "closure", "vm"]),
+    (19, &["earley", "cyk", "regex"]),
+    (20, &["greedy", "backtracking"]),
+    (24, &["sparse", "chunked"]),
+    (25, &["brute-force", "bsgs", "pohlig-hellman"]),
+];
+
+/// The names of the algorithms of `day`, or None if it has only one.
+pub fn names(day: u32) -> Option<&'static [&'static str]> {
+    DAYS.iter()
+        .find(|(d, _)| *d == day)
+        .map(|(_, names)| *names)
+}
+
+pub struct Algorithm<S> {
+    pub name: &'static str,
+    /// A short description, such as "prefix sums".
+    pub description: &'static str,
+    /// Whatever the day needs to run the algorithm; usually a function
+    /// or a value of an enum.
+    pub solver: S,
+}
+
+pub struct Registry<S> {
+    /// The day whose algorithms these are, if they are listed in `DAYS`.
+    day: Option<u32>,
+    algorithms: Vec<Algorithm<S>>,
+}
+
+impl<S> Default for Registry<S> {
+    fn default() -> Registry<S> {
+        Registry {
+            day: None,
+            algorithms: Vec::new(),
+        }
+    }
+}
+
+impl<S> Registry<S> {
+    pub fn new() -> Registry<S> {
+        Registry::default()
+    }
+
+    /// Makes an empty registry for the algorithms of `day`, which must
+    /// be listed in `DAYS`.  Only the algorithms listed there can be
+    /// registered, and `crosscheck` insists on all of them.
+    pub fn for_day(day: u32) -> Registry<S> {
+        assert!(names(day).is_some(), "day {} is not listed in DAYS", day);
+        Registry {
+            day: Some(day),
+            algorithms: Vec::new(),
+        }
+    }
+
+    pub fn register(
+        mut self,
+        name: &'static str,
+        description: &'static str,
+        solver: S,
+    ) -> Registry<S> {
+        assert!(
+            self.algorithms.iter().all(|a| a.name != name),
+            "algorithm {} is registered twice",
+            name
+        );
+        if let Some(day) = self.day {
+            assert!(
+                names(day).unwrap_or(&[]).contains(&name),
+                "algorithm {} is not listed in DAYS for day {}",
+                name,
+                day
+            );
+        }
+        self.algorithms.push(Algorithm {
+            name,
+            description,
+            solver,
+        });
+        self
+    }
+
+    pub fn algorithms(&self) -> &[Algorithm<S>] {
+        &self.algorithms
+    }
+
+    /// The names of the algorithms, for messages: "a, b or c".
+    pub fn choices(&self) -> String {
+        let names: Vec<&str> = self.algorithms.iter().map(|a| a.name).collect();
+        match names.split_last() {
+            Some((last, rest)) if !rest.is_empty() => format!("{} or {}", rest.join(", "), last),
+            _ => names.join(""),
+        }
+    }
+
+    pub fn get(&self, name: &str) -> Result<&Algorithm<S>, String> {
+        self.algorithms
+            .iter()
+            .find(|a| a.name == name)
+            .ok_or_else(|| format!("unknown algorithm '{}'; choose {}", name, self.choices()))
+    }
+
+    /// Runs each algorithm in turn with `solve`, and checks that they
+    /// all give the same answer.  Fails if any of them fails or
+    /// disagrees with the first.
+    pub fn crosscheck<O, F>(&self, mut solve: F) -> Result<Crosscheck<O>, String>
+    where
+        O: PartialEq + fmt::Display,
+        F: FnMut(&S) -> Result<O, String>,
+    {
+        if let Some(day) = self.day {
+            for name in names(day).unwrap_or(&[]) {
+                if self.algorithms.iter().all(|a| a.name != *name) {
+                    return Err(format!("the {} algorithm is not registered", name));
+                }
+            }
+        }
+        let mut outcomes: Vec<Outcome<O>> = Vec::with_capacity(self.algorithms.len());
+        for algorithm in self.algorithms.iter() {
+            let start = Instant::now();
+            let answer = solve(&algorithm.solver)
+                .map_err(|e| format!("the {} algorithm failed: {}", algorithm.name, e))?;
+            let time = start.elapsed();
+            if let Some(first) = outcomes.first() {
+                if answer != first.answer {
+                    return Err(format!(
+                        "the {} algorithm found {} but the {} algorithm found {}",
+                        algorithm.name, answer, first.name, first.answer
+                    ));
+                }
+            }
+            outcomes.push(Outcome {
+                name: algorithm.name,
+                description: algorithm.description,
+                answer,
+                time,
+            });
+        }
+        Ok(Crosscheck { outcomes })
+    }
+}
+
+pub struct Outcome<O> {
+    pub name: &'static str,
+    pub description: &'static str,
+    pub answer: O,
+    pub time: Duration,
+}
+
+/// The answers of all the algorithms, which agree.
+pub struct Crosscheck<O> {
+    pub outcomes: Vec<Outcome<O>>,
+}
+
+impl<O: fmt::Display> fmt::Display for Crosscheck<O> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let width = self
+            .outcomes
+            .iter()
+            .map(|o| o.name.len())
+            .max()
+            .unwrap_or(0);
+        for o in self.outcomes.iter() {
+            writeln!(
+                f,
+                "{:width$}  {} in {:?} ({})",
+                o.name,
+                o.answer,
+                o.time,
+                o.description,
+                width = width
+            )?;
+        }
+        write!(f, "all {} algorithms agree", self.outcomes.len())
+    }
+}
+
+type Sum = fn(u32) -> u32;
+
+fn sum_by_loop(n: u32) -> u32 {
+    (1..=n).sum()
+}
+
+fn sum_by_formula(n: u32) -> u32 {
+    n * (n + 1) / 2
+}
+
+fn sum_wrongly(n: u32) -> u32 {
+    (1..n).sum()
+}
+
+pub fn self_test() -> Result<(), String> {
+    let registry: Registry<Sum> = Registry::new()
+        .register("loop", "adds one at a time", sum_by_loop as Sum)
+        .register("formula", "n(n+1)/2", sum_by_formula)
+        .register("wrong", "forgets n", sum_wrongly);
+    if registry.choices() != "loop, formula or wrong" {
+        return Err(format!("FAIL: choices are {}", registry.choices()));
+    }
+    match registry.get("formula") {
+        Ok(a) if (a.solver)(10) == 55 => (),
+        Ok(a) => return Err(format!("FAIL: formula gave {}", (a.solver)(10))),
+        Err(e) => return Err(format!("FAIL: {}", e)),
+    }
+    if registry.get("guess").is_ok() {
+        return Err("FAIL: found an algorithm which was not registered".to_string());
+    }
+    match registry.crosscheck(|solver| Ok(solver(10))) {
+        Err(e) if e == "the wrong algorithm found 45 but the loop algorithm found 55" => (),
+        Err(e) => return Err(format!("FAIL: crosscheck failed with '{}'", e)),
+        Ok(_) => return Err("FAIL: crosscheck should find a disagreement".to_string()),
+    }
+    let agreeing: Registry<Sum> = Registry::new()
+        .register("loop", "adds one at a time", sum_by_loop as Sum)
+        .register("formula", "n(n+1)/2", sum_by_formula);
+    match agreeing.crosscheck(|solver| Ok(solver(100))) {
+        Ok(c) if c.outcomes.len() == 2 && c.outcomes[1].answer == 5050 => (),
+        Ok(c) => return Err(format!("FAIL: crosscheck gave\n{}", c)),
+        Err(e) => return Err(format!("FAIL: {}", e)),
+    }
+    // Day 13 has two algorithms, so one on its own is not enough.
+    let partial: Registry<Sum> = Registry::for_day(13).register("crt", "", sum_by_formula as Sum);
+    match partial.crosscheck(|solver| Ok(solver(1))) {
+        Err(e) if e == "the sieve algorithm is not registered" => (),
+        Err(e) => return Err(format!("FAIL: crosscheck failed with '{}'", e)),
+        Ok(_) => return Err("FAIL: crosscheck ignored a missing algorithm".to_string()),
+    }
+    if names(5).is_some() || names(25).map(|n| n.len()) != Some(3) {
+        return Err("FAIL: wrong list of days with several algorithms".to_string());
+    }
+    match agreeing.crosscheck(|solver| match solver(1) {
+        1 => Err("too small".to_string()),
+        n => Ok(n),
+    }) {
+        Err(e) if e == "the loop algorithm failed: too small" => Ok(()),
+        other => Err(format!(
+            "FAIL: crosscheck ignored a failure: {:?}",
+            other.map(|c| c.to_string())
+        )),
+    }
+}
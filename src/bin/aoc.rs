@@ -0,0 +1,78 @@
+//! Commands which apply to more than one day.
+//!
+//! `aoc crosscheck DAY [OPTION...]` runs each of the algorithms which
+//! DAY has on its input (read from the standard input) and checks that
+//! they agree.  The options are passed on to DAY, so for example
+//! `aoc crosscheck 9 --preamble 5 < samples/day09-pt1.txt` works.
+extern crate aor2020;
+
+use aor2020::algorithm;
+use std::env;
+use std::process::Command;
+
+const USAGE: &str = "usage: aoc crosscheck DAY [OPTION...]";
+
+/// The name of the program for `day`, which may be given as "day09",
+/// "day9", "09" or "9".
+fn day_program(day: &str) -> Result<String, String> {
+    let number = day.strip_prefix("day").unwrap_or(day);
+    let n: u32 = number
+        .parse()
+        .map_err(|_| format!("'{}' is not a day", day))?;
+    let name = format!("day{:02}", n);
+    match algorithm::names(n) {
+        Some(_) => Ok(name),
+        None => {
+            let days: Vec<String> = algorithm::DAYS
+                .iter()
+                .map(|(d, _)| format!("day{:02}", d))
+                .collect();
+            Err(format!(
+                "{} has only one algorithm; crosscheck works for {}",
+                name,
+                days.join(", ")
+            ))
+        }
+    }
+}
+
+/// Runs `crosscheck` with the program for `day`, which is in the same
+/// directory as this one.
+fn crosscheck(day: &str, options: &[String]) -> Result<(), String> {
+    let name = day_program(day)?;
+    let program = env::current_exe()
+        .map_err(|e| format!("cannot find the day programs: {}", e))?
+        .with_file_name(format!("{}{}", name, env::consts::EXE_SUFFIX));
+    let status = Command::new(&program)
+        .arg("crosscheck")
+        .args(options)
+        .status()
+        .map_err(|e| format!("failed to run {}: {}", program.display(), e))?;
+    if status.success() {
+        Ok(())
+    } else {
+        Err(format!("{} crosscheck failed ({})", name, status))
+    }
+}
+
+fn run() -> Result<(), String> {
+    let args: Vec<String> = env::args().skip(1).collect();
+    match args.split_first() {
+        Some((command, rest)) if command == "crosscheck" => match rest.split_first() {
+            Some((day, options)) => crosscheck(day, options),
+            None => Err(USAGE.to_string()),
+        },
+        Some((command, _)) => Err(format!("unknown command {}; {}", command, USAGE)),
+        None => Err(USAGE.to_string()),
+    }
+}
+
+fn main() {
+    std::process::exit(match run() {
+        Ok(_) => 0,
+        Err(err) => {
+            eprintln!("error: {}", err);
+            1
+        }
+    });
+}
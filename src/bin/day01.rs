@@ -1,3 +1,10 @@
+extern crate aor2020;
+#[cfg(test)]
+extern crate insta;
+
+use aor2020::algorithm::Registry;
+use aor2020::output::write_error;
+use aor2020::rng::Lcg;
 use std::collections::HashSet;
 use std::io;
 use std::io::prelude::*;
@@ -27,13 +34,251 @@ fn findtriple(h: &HashSet<u64>, total: &u64) -> Result<(u64, u64, u64), &'static
     Err("did not find triple")
 }
 
-fn main() {
-    let h: HashSet<u64> = io::BufReader::new(io::stdin())
+/// The values from 0 up to some limit which are present, one bit each.
+struct Bitset {
+    words: Vec<u64>,
+}
+
+impl Bitset {
+    /// Makes a set of those `values` which are no larger than `limit`;
+    /// larger ones can't be part of a pair or triple adding up to it.
+    fn new(values: &[u64], limit: u64) -> Bitset {
+        let mut set = Bitset {
+            words: vec![0; (limit / 64 + 1) as usize],
+        };
+        for v in values.iter().filter(|v| **v <= limit) {
+            set.words[(v / 64) as usize] |= 1 << (v % 64);
+        }
+        set
+    }
+
+    fn contains(&self, v: u64) -> bool {
+        match self.words.get((v / 64) as usize) {
+            Some(word) => word & (1 << (v % 64)) != 0,
+            None => false,
+        }
+    }
+
+    /// The values in the set, in increasing order.
+    fn iter<'a>(&'a self) -> impl Iterator<Item = u64> + 'a {
+        self.words.iter().enumerate().flat_map(|(i, word)| {
+            let mut bits = *word;
+            std::iter::from_fn(move || {
+                if bits == 0 {
+                    None
+                } else {
+                    let bit = bits.trailing_zeros() as u64;
+                    bits &= bits - 1;
+                    Some(i as u64 * 64 + bit)
+                }
+            })
+        })
+    }
+}
+
+/// Like `findpair`, but for a `Bitset`.  Returns the pair whose smaller
+/// member is least.
+fn findpair_bitset(set: &Bitset, total: u64, exclude: u64) -> Result<(u64, u64), &'static str> {
+    for x in set.iter().take_while(|x| x * 2 < total) {
+        let y = total - x;
+        if x != exclude && y != exclude && set.contains(y) {
+            return Ok((x, y));
+        }
+    }
+    Err("did not find pair")
+}
+
+fn findtriple_bitset(set: &Bitset, total: u64) -> Result<(u64, u64, u64), &'static str> {
+    for c in set.iter().take_while(|c| *c < total) {
+        if let Ok((a, b)) = findpair_bitset(set, total - c, c) {
+            return Ok((a, b, c));
+        }
+    }
+    Err("did not find triple")
+}
+
+/// The pair and the triple which add up to the total.
+type Answers = ((u64, u64), (u64, u64, u64));
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Engine {
+    Hash,
+    Bitset,
+}
+
+impl Engine {
+    fn solve(&self, values: &[u64], total: u64) -> Result<Answers, String> {
+        match self {
+            Engine::Hash => {
+                let h: HashSet<u64> = values.iter().cloned().collect();
+                Ok((findpair(&h, &total, &0)?, findtriple(&h, &total)?))
+            }
+            Engine::Bitset => {
+                let set = Bitset::new(values, total);
+                Ok((
+                    findpair_bitset(&set, total, 0)?,
+                    findtriple_bitset(&set, total)?,
+                ))
+            }
+        }
+    }
+}
+
+fn engines() -> Registry<Engine> {
+    Registry::for_day(1)
+        .register("hash", "looks up the numbers in a hash set", Engine::Hash)
+        .register(
+            "bitset",
+            "looks up the numbers in a bit set",
+            Engine::Bitset,
+        )
+}
+
+fn self_test() -> Result<(), String> {
+    let sample = [1721, 979, 366, 299, 675, 1456];
+    for engine in engines().algorithms() {
+        let ((a, b), (x, y, z)) = engine.solver.solve(&sample, 2020)?;
+        if a * b != 514579 || x * y * z != 241861950 {
+            return Err(format!(
+                "FAIL: {} engine gave the wrong answer",
+                engine.name
+            ));
+        }
+    }
+    // Compare the engines on dense inputs, where there are many
+    // answers (so they need not find the same one).
+    let mut rng = Lcg::new(2020);
+    for size in &[10, 100, 1000] {
+        let mut values: Vec<u64> = (0..*size).map(|_| 1 + rng.below(2019) as u64).collect();
+        values.sort_unstable();
+        values.dedup();
+        let hash = Engine::Hash.solve(&values, 2020);
+        let bitset = Engine::Bitset.solve(&values, 2020);
+        match (&hash, &bitset) {
+            (Ok(((a, b), (x, y, z))), Ok(((c, d), (u, v, w)))) => {
+                for sum in &[a + b, x + y + z, c + d, u + v + w] {
+                    if *sum != 2020 {
+                        return Err(format!(
+                            "FAIL: {:?} and {:?} for {:?}",
+                            hash, bitset, values
+                        ));
+                    }
+                }
+                if a == b || x == y || y == z || x == z {
+                    return Err(format!(
+                        "FAIL: hash engine gave {:?} for {:?}",
+                        hash, values
+                    ));
+                }
+                if c == d || u == v || v == w || u == w {
+                    return Err(format!("FAIL: bitset gave {:?} for {:?}", bitset, values));
+                }
+            }
+            (Err(_), Err(_)) => (),
+            _ => {
+                return Err(format!(
+                    "FAIL: hash engine gave {:?} but bitset gave {:?} for {:?}",
+                    hash, bitset, values
+                ))
+            }
+        }
+    }
+    Ok(())
+}
+
+#[derive(Default)]
+struct Options {
+    crosscheck: bool,
+    engine: Option<Engine>,
+}
+
+fn parse_args() -> Result<Options, String> {
+    let mut options = Options::default();
+    let mut args = std::env::args().skip(1);
+    while let Some(arg) = args.next() {
+        let mut value = || {
+            args.next()
+                .ok_or_else(|| format!("option {} requires an argument", arg))
+        };
+        match arg.as_str() {
+            "crosscheck" => options.crosscheck = true,
+            "--algorithm" | "--engine" => options.engine = Some(engines().get(&value()?)?.solver),
+            _ => return Err(format!("unknown option {}", arg)),
+        }
+    }
+    Ok(options)
+}
+
+fn run(input: impl BufRead, options: &Options, out: &mut impl Write) -> Result<(), String> {
+    let values: Vec<u64> = input
         .lines()
         .map(|s| s.unwrap().parse::<u64>().unwrap())
         .collect();
-    let (a, b) = findpair(&h, &2020, &0).unwrap();
-    println!("Part 1: {}*{} = {}", a, b, a * b);
-    let (a, b, c) = findtriple(&h, &2020).unwrap();
-    println!("Part 2: {}*{}*{} = {}", a, b, c, a * b * c);
+    if options.crosscheck {
+        let report = engines().crosscheck(|engine| {
+            let ((a, b), (x, y, z)) = engine.solve(&values, 2020)?;
+            Ok(format!("part 1 {}, part 2 {}", a * b, x * y * z))
+        })?;
+        writeln!(out, "{}", report).map_err(write_error)?;
+        return Ok(());
+    }
+    let engine = options.engine.unwrap_or(Engine::Hash);
+    let ((a, b), (x, y, z)) = engine.solve(&values, 2020)?;
+    // The hash engine finds the numbers in no particular order, so
+    // sort them to print the same thing every time.
+    let mut pair = [a, b];
+    pair.sort_unstable();
+    let mut triple = [x, y, z];
+    triple.sort_unstable();
+    writeln!(
+        out,
+        "Part 1: {}*{} = {}",
+        pair[0],
+        pair[1],
+        pair.iter().product::<u64>()
+    )
+    .map_err(write_error)?;
+    writeln!(
+        out,
+        "Part 2: {}*{}*{} = {}",
+        triple[0],
+        triple[1],
+        triple[2],
+        triple.iter().product::<u64>()
+    )
+    .map_err(write_error)?;
+    Ok(())
+}
+
+fn main() {
+    let result = parse_args().and_then(|options| {
+        self_test()?;
+        run(io::stdin().lock(), &options, &mut io::stdout())
+    });
+    std::process::exit(match result {
+        Ok(_) => 0,
+        Err(err) => {
+            eprintln!("error: {:?}", err);
+            1
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn output(input: &str, options: &Options) -> String {
+        let mut out = Vec::new();
+        run(input.as_bytes(), options, &mut out).unwrap();
+        String::from_utf8(out).unwrap()
+    }
+
+    #[test]
+    fn sample() {
+        insta::assert_snapshot!(output(
+            include_str!("../../samples/day01-sample.txt"),
+            &Options::default()
+        ));
+    }
 }
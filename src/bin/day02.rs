@@ -1,7 +1,18 @@
+use std::fmt;
 use std::io;
+extern crate aor2020;
+#[cfg(test)]
+extern crate insta;
+extern crate memchr;
 extern crate regex;
 use std::io::BufRead;
+use std::io::Write;
+use std::time::Instant;
 
+use aor2020::algorithm::Registry;
+use aor2020::output::write_error;
+use aor2020::rng::Lcg;
+use memchr::memchr;
 use regex::Regex;
 
 fn atpos(pos: usize, expected: &str, passwd: &str) -> usize {
@@ -20,21 +31,32 @@ fn valid1(n1: usize, n2: usize, ch: &str, passwd: &str) -> bool {
     actual >= n1 && actual <= n2
 }
 
-fn run() -> Result<(), std::io::Error> {
+#[derive(Debug, Default, PartialEq, Eq)]
+struct Tally {
+    total: u32,
+    count1: u32,
+    count2: u32,
+}
+
+impl Tally {
+    fn add(&mut self, valid1: bool, valid2: bool) {
+        self.total += 1;
+        self.count1 += valid1 as u32;
+        self.count2 += valid2 as u32;
+    }
+}
+
+fn tally_regex(reader: impl BufRead) -> Result<Tally, String> {
     // Example input lines:
     // 1-3 a: abcde
     // 1-3 b: cdefg
     // 2-9 c: ccccccccc
-    let mut total: u32 = 0;
-    let mut count1: u32 = 0;
-    let mut count2: u32 = 0;
+    let mut tally = Tally::default();
     let re = Regex::new(r"^(\d+)-(\d+) (.): (.*)$").unwrap();
-    let reader = io::BufReader::new(io::stdin());
     for line_or_fail in reader.lines() {
         match line_or_fail {
             Ok(line) => {
                 let s = line.trim_end();
-                total += 1;
                 match re.captures(s) {
                     None => {
                         panic!("error: invalid input line {}", s);
@@ -47,27 +69,297 @@ fn run() -> Result<(), std::io::Error> {
                         }
                         let ch = &cap[3];
                         let password = &cap[4];
-                        if valid1(n1, n2, ch, password) {
-                            count1 += 1;
-                        }
-                        if valid2(n1, n2, ch, password) {
-                            count2 += 1;
-                        }
+                        tally.add(valid1(n1, n2, ch, password), valid2(n1, n2, ch, password));
                     }
                 }
             }
             Err(e) => {
-                return Err(e);
+                return Err(e.to_string());
+            }
+        }
+    }
+    Ok(tally)
+}
+
+/// Parses a rule's count or position, which must be a positive
+/// decimal number.
+fn parse_position(field: &[u8]) -> Option<usize> {
+    if field.is_empty() {
+        return None;
+    }
+    let mut n: usize = 0;
+    for b in field {
+        if !b.is_ascii_digit() {
+            return None;
+        }
+        n = n.checked_mul(10)?.checked_add((b - b'0') as usize)?;
+    }
+    if n == 0 {
+        None
+    } else {
+        Some(n)
+    }
+}
+
+/// Checks one line (without its newline) against both policies,
+/// without allocating.  The letter in the rule has to be ASCII.
+fn check_line_bytes(line: &[u8]) -> Option<(bool, bool)> {
+    let dash = memchr(b'-', line)?;
+    // The rule's letter is just before the colon, unless the letter is
+    // itself a colon.
+    let mut colon = dash + memchr(b':', &line[dash..])?;
+    if line.get(colon + 1) == Some(&b':') {
+        colon += 1;
+    }
+    if colon < dash + 4 || line[colon - 2] != b' ' || line.get(colon + 1) != Some(&b' ') {
+        return None;
+    }
+    let n1 = parse_position(&line[..dash])?;
+    let n2 = parse_position(&line[dash + 1..colon - 2])?;
+    let ch = line[colon - 1];
+    if !ch.is_ascii() {
+        return None;
+    }
+    let password = &line[colon + 2..];
+    let count = password.iter().filter(|b| **b == ch).count();
+    let at = |pos: usize| password.get(pos - 1) == Some(&ch);
+    Some((count >= n1 && count <= n2, at(n1) != at(n2)))
+}
+
+/// Does the same job as `tally_regex`, but works directly on the bytes
+/// of the input.
+fn tally_bytes(input: &[u8]) -> Result<Tally, String> {
+    let mut tally = Tally::default();
+    let mut rest = input;
+    while !rest.is_empty() {
+        let (line, next) = match memchr(b'\n', rest) {
+            Some(newline) => (&rest[..newline], &rest[newline + 1..]),
+            None => (rest, &rest[rest.len()..]),
+        };
+        rest = next;
+        let end = line
+            .iter()
+            .rposition(|b| !b.is_ascii_whitespace())
+            .map_or(0, |i| i + 1);
+        match check_line_bytes(&line[..end]) {
+            Some((v1, v2)) => tally.add(v1, v2),
+            None => {
+                return Err(format!(
+                    "invalid input line {}",
+                    String::from_utf8_lossy(line)
+                ))
             }
         }
     }
-    println!("Part 1: {} valid passwords out of {}", count1, total);
-    println!("Part 2: {} valid passwords out of {}", count2, total);
+    Ok(tally)
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Engine {
+    /// Match each line with a regular expression.
+    Regex,
+    /// Split each line by hand, without allocating (`tally_bytes`).
+    Bytes,
+}
+
+impl Engine {
+    fn tally(&self, input: &[u8]) -> Result<Tally, String> {
+        match self {
+            Engine::Regex => tally_regex(input),
+            Engine::Bytes => tally_bytes(input),
+        }
+    }
+}
+
+impl fmt::Display for Engine {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match self {
+            Engine::Regex => "regex",
+            Engine::Bytes => "bytes",
+        })
+    }
+}
+
+fn engines() -> Registry<Engine> {
+    Registry::for_day(2)
+        .register(
+            "regex",
+            "matches each line with a regular expression",
+            Engine::Regex,
+        )
+        .register("bytes", "splits each line by hand", Engine::Bytes)
+}
+
+/// Makes up `lines` lines of input.
+fn generate_input(lines: usize) -> Vec<u8> {
+    let mut rng = Lcg::new(20201202);
+    let mut below = |limit: usize| rng.below(limit);
+    let mut input = Vec::with_capacity(lines * 24);
+    for _ in 0..lines {
+        let n1 = 1 + below(10);
+        let n2 = n1 + 1 + below(10);
+        let letter = b'a' + below(26) as u8;
+        input.extend_from_slice(format!("{}-{} {}: ", n1, n2, letter as char).as_bytes());
+        for _ in 0..(n2 + below(8)) {
+            // Favour the rule's letter, so that some passwords are valid.
+            input.push(if below(3) == 0 {
+                letter
+            } else {
+                b'a' + below(26) as u8
+            });
+        }
+        input.push(b'\n');
+    }
+    input
+}
+
+fn benchmark(lines: usize, out: &mut impl Write) -> Result<(), String> {
+    let input = generate_input(lines);
+    let megabytes = input.len() as f64 / 1e6;
+    let mut results = Vec::new();
+    for engine in engines().algorithms() {
+        let start = Instant::now();
+        let tally = engine.solver.tally(&input)?;
+        results.push((engine.solver, tally, start.elapsed()));
+    }
+    let (_, regex_tally, regex_time) = &results[0];
+    for (engine, tally, time) in results.iter() {
+        writeln!(
+            out,
+            "{}: {} lines ({:.1} MB) in {:?}, {:.1} MB/s (speedup {:.1}x)",
+            engine,
+            tally.total,
+            megabytes,
+            time,
+            megabytes / time.as_secs_f64(),
+            regex_time.as_secs_f64() / time.as_secs_f64()
+        )
+        .map_err(write_error)?;
+        if tally != regex_tally {
+            return Err(format!(
+                "{} engine gave {:?}, but the regex engine gave {:?}",
+                engine, tally, regex_tally
+            ));
+        }
+    }
+    Ok(())
+}
+
+fn self_test() -> Result<(), String> {
+    let sample = "1-3 a: abcde\n1-3 b: cdefg\n2-9 c: ccccccccc\n";
+    let expected = Tally {
+        total: 3,
+        count1: 2,
+        count2: 1,
+    };
+    for engine in engines().algorithms() {
+        let engine = engine.solver;
+        let got = engine.tally(sample.as_bytes())?;
+        if got != expected {
+            return Err(format!("FAIL: {} engine gave {:?}", engine, got));
+        }
+    }
+    let awkward = "2-3 :: a:b:c  \r\n10-12 x: xxxxxxxxxxx\n1-2 q: aq";
+    let expected = Engine::Regex.tally(awkward.as_bytes())?;
+    let got = Engine::Bytes.tally(awkward.as_bytes())?;
+    if got != expected {
+        return Err(format!(
+            "FAIL: bytes engine gave {:?} but regex gave {:?}",
+            got, expected
+        ));
+    }
+    let generated = generate_input(1000);
+    if Engine::Bytes.tally(&generated)? != Engine::Regex.tally(&generated)? {
+        return Err("FAIL: engines disagree on generated input".to_string());
+    }
+    for bad in &[
+        "1-3 a abcde",
+        "1-3a: abc",
+        "0-3 a: abc",
+        "-3 a: abc",
+        "1-x a: abc",
+    ] {
+        if tally_bytes(bad.as_bytes()).is_ok() {
+            return Err(format!("FAIL: bytes engine accepted '{}'", bad));
+        }
+    }
+    Ok(())
+}
+
+/// The number of lines of made-up input for `--benchmark`.
+const BENCHMARK_LINES: usize = 5_000_000;
+
+#[derive(Default)]
+struct Options {
+    benchmark: bool,
+    crosscheck: bool,
+    engine: Option<Engine>,
+}
+
+fn parse_args() -> Result<Options, String> {
+    let mut options = Options::default();
+    let mut args = std::env::args().skip(1);
+    while let Some(arg) = args.next() {
+        let mut value = || {
+            args.next()
+                .ok_or_else(|| format!("option {} requires an argument", arg))
+        };
+        match arg.as_str() {
+            "--benchmark" => options.benchmark = true,
+            "crosscheck" => options.crosscheck = true,
+            "--algorithm" | "--engine" => options.engine = Some(engines().get(&value()?)?.solver),
+            _ => return Err(format!("unknown option {}", arg)),
+        }
+    }
+    Ok(options)
+}
+
+fn run(mut input: impl BufRead, options: &Options, out: &mut impl Write) -> Result<(), String> {
+    if options.benchmark {
+        return benchmark(BENCHMARK_LINES, out);
+    }
+    if options.crosscheck {
+        let mut bytes = Vec::new();
+        input.read_to_end(&mut bytes).map_err(|e| e.to_string())?;
+        let report = engines().crosscheck(|engine| {
+            let tally = engine.tally(&bytes)?;
+            Ok(format!(
+                "{} passwords, {} valid in part 1, {} in part 2",
+                tally.total, tally.count1, tally.count2
+            ))
+        })?;
+        writeln!(out, "{}", report).map_err(write_error)?;
+        return Ok(());
+    }
+    let tally = match options.engine.unwrap_or(Engine::Regex) {
+        Engine::Regex => tally_regex(input)?,
+        Engine::Bytes => {
+            let mut bytes = Vec::new();
+            input.read_to_end(&mut bytes).map_err(|e| e.to_string())?;
+            tally_bytes(&bytes)?
+        }
+    };
+    writeln!(
+        out,
+        "Part 1: {} valid passwords out of {}",
+        tally.count1, tally.total
+    )
+    .map_err(write_error)?;
+    writeln!(
+        out,
+        "Part 2: {} valid passwords out of {}",
+        tally.count2, tally.total
+    )
+    .map_err(write_error)?;
     Ok(())
 }
 
 fn main() {
-    std::process::exit(match run() {
+    let result = parse_args().and_then(|options| {
+        self_test()?;
+        run(io::stdin().lock(), &options, &mut io::stdout())
+    });
+    std::process::exit(match result {
         Ok(_) => 0,
         Err(err) => {
             eprintln!("error: {:?}", err);
@@ -75,3 +367,22 @@ fn main() {
         }
     });
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn output(input: &str, options: &Options) -> String {
+        let mut out = Vec::new();
+        run(input.as_bytes(), options, &mut out).unwrap();
+        String::from_utf8(out).unwrap()
+    }
+
+    #[test]
+    fn sample() {
+        insta::assert_snapshot!(output(
+            include_str!("../../samples/day02-sample.txt"),
+            &Options::default()
+        ));
+    }
+}
@@ -1,101 +1,67 @@
-use std::cmp::max;
-use std::collections::HashSet;
+extern crate aor2020;
+#[cfg(test)]
+extern crate insta;
+
+use aor2020::grid::Grid;
+use aor2020::output::write_error;
 use std::io;
 use std::io::BufRead;
+use std::io::Write;
 
-#[derive(Hash, Eq, PartialEq, Debug)]
-struct Pos {
-    x: usize,
-    y: usize,
-}
-
-fn count_trees(
-    tree_positions: &HashSet<Pos>,
-    dx: usize,
-    dy: usize,
-    map_width: usize,
-    slope_height: usize,
-) -> usize {
-    let mut x: usize = 0;
-    let mut y: usize = 0;
-    let mut count: usize = 0;
-    while y < slope_height {
-        if tree_positions.contains(&Pos { x, y }) {
-            count += 1;
-        }
-        x = (x + dx) % map_width;
-        y += dy;
-    }
-    count
-}
-
-fn part1(tree_positions: &HashSet<Pos>, map_width: usize, slope_height: usize) -> usize {
-    count_trees(tree_positions, 3, 1, map_width, slope_height)
+fn count_trees(map: &Grid, dx: usize, dy: usize) -> usize {
+    (0..map.height())
+        .step_by(dy)
+        .enumerate()
+        .filter(|(i, y)| map.is_blocked((i * dx) as i64, *y as i64))
+        .count()
 }
 
-fn part2(tree_positions: &HashSet<Pos>, map_width: usize, slope_height: usize) -> usize {
-    [
-        Pos { x: 1, y: 1 },
-        Pos { x: 3, y: 1 },
-        Pos { x: 5, y: 1 },
-        Pos { x: 7, y: 1 },
-        Pos { x: 1, y: 2 },
-    ]
-    .iter()
-    .map(|d| count_trees(tree_positions, d.x, d.y, map_width, slope_height))
-    .product()
+fn part1(map: &Grid) -> usize {
+    count_trees(map, 3, 1)
 }
 
-fn read_line(line: &str, y: usize, tree_positions: &mut HashSet<Pos>) -> usize {
-    let mut width = 0;
-    for (x, c) in line.chars().enumerate() {
-        width = x + 1;
-        if c == '#' {
-            tree_positions.insert(Pos { x, y });
-        }
-    }
-    width
+fn part2(map: &Grid) -> usize {
+    [(1, 1), (3, 1), (5, 1), (7, 1), (1, 2)]
+        .iter()
+        .map(|(dx, dy)| count_trees(map, *dx, *dy))
+        .product()
 }
 
-fn read_map(reader: impl BufRead) -> Result<(HashSet<Pos>, usize, usize), io::Error> {
-    let mut tree_positions = HashSet::new();
-    let mut map_width: usize = 0;
-    let mut slope_height: usize = 0;
-    for (y, line_or_fail) in reader.lines().enumerate() {
-        match line_or_fail {
-            Ok(line) => {
-                let w = read_line(&line, y, &mut tree_positions);
-                map_width = max(w, map_width);
-                slope_height = y + 1;
-            }
-            Err(e) => {
-                return Err(e);
-            }
-        }
-    }
-    Ok((tree_positions, map_width, slope_height))
-}
-
-fn run() -> Result<(), std::io::Error> {
-    let reader = io::BufReader::new(io::stdin());
-    let (tree_positions, map_width, slope_height) = read_map(reader)?;
-    println!(
-        "Part 1: encountered {} trees",
-        part1(&tree_positions, map_width, slope_height)
-    );
-    println!(
-        "Part 2: product is {}",
-        part2(&tree_positions, map_width, slope_height)
-    );
+fn run(mut input: impl BufRead, out: &mut impl Write) -> Result<(), String> {
+    let mut text = String::new();
+    input
+        .read_to_string(&mut text)
+        .map_err(|e| format!("I/O error: {}", e))?;
+    let map = Grid::parse(&text);
+    writeln!(out, "Part 1: encountered {} trees", part1(&map)).map_err(write_error)?;
+    writeln!(out, "Part 2: product is {}", part2(&map)).map_err(write_error)?;
     Ok(())
 }
 
 fn main() {
-    std::process::exit(match run() {
+    let result =
+        aor2020::grid::self_test().and_then(|_| run(io::stdin().lock(), &mut io::stdout()));
+    std::process::exit(match result {
         Ok(_) => 0,
         Err(err) => {
-            eprintln!("error: {:?}", err);
+            eprintln!("error: {}", err);
             1
         }
     });
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn output(input: &str) -> String {
+        let mut out = Vec::new();
+        run(input.as_bytes(), &mut out).unwrap();
+        String::from_utf8(out).unwrap()
+    }
+
+    #[test]
+    fn sample() {
+        insta::assert_snapshot!(output(include_str!("../../samples/day03.txt")));
+    }
+}
@@ -1,10 +1,16 @@
 use std::collections::HashMap;
 use std::collections::HashSet;
+extern crate aor2020;
+#[cfg(test)]
+extern crate insta;
 extern crate lazy_static;
 extern crate regex;
+use aor2020::output::write_error;
+use aor2020::rng::Lcg;
 use lazy_static::lazy_static;
 use std::io;
 use std::io::BufRead;
+use std::io::Write;
 use std::string::String; // 1.3.0
 
 use regex::Regex;
@@ -156,19 +162,262 @@ fn read_input(reader: impl BufRead) -> Result<Vec<Passport>, io::Error> {
     Ok(result)
 }
 
-fn run() -> Result<(), std::io::Error> {
-    let passports = read_input(io::BufReader::new(io::stdin())).unwrap();
-    println!(
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum PassportKind {
+    /// Valid under the rules of both parts.
+    Valid,
+    /// Lacks at least one required field, so it is invalid in both parts.
+    MissingField,
+    /// Has every required field, but one of them has a bad value, so
+    /// it is only valid in part 1.
+    OutOfRange,
+}
+
+const REQUIRED_FIELDS: [&str; 7] = ["byr", "iyr", "eyr", "hgt", "hcl", "ecl", "pid"];
+
+/// Makes up a value for `field` which is valid, or (if `valid` is
+/// false) one which is not.
+fn field_value(field: &str, valid: bool, rng: &mut Lcg) -> String {
+    let year = |rng: &mut Lcg, low: i32, high: i32| {
+        if valid {
+            rng.between(low, high)
+        } else if rng.below(2) == 0 {
+            rng.between(low - 30, low - 1)
+        } else {
+            rng.between(high + 1, high + 30)
+        }
+        .to_string()
+    };
+    match (field, valid) {
+        ("byr", _) => year(rng, 1920, 2002),
+        ("iyr", _) => year(rng, 2010, 2020),
+        ("eyr", _) => year(rng, 2020, 2030),
+        ("hgt", true) => match rng.below(2) {
+            0 => format!("{}cm", rng.between(150, 193)),
+            _ => format!("{}in", rng.between(59, 76)),
+        },
+        ("hgt", false) => match rng.below(3) {
+            0 => format!("{}cm", rng.between(100, 149)),
+            1 => format!("{}in", rng.between(77, 99)),
+            _ => rng.between(59, 193).to_string(),
+        },
+        ("hcl", true) => format!("#{:06x}", rng.below(1 << 24)),
+        ("hcl", false) => match rng.below(3) {
+            0 => format!("{:06x}", rng.below(1 << 24)),
+            1 => format!("#{:05x}", rng.below(1 << 20)),
+            _ => format!("#{:05x}g", rng.below(1 << 20)),
+        },
+        ("ecl", true) => rng
+            .choose(&["amb", "blu", "brn", "gry", "grn", "hzl", "oth"])
+            .to_string(),
+        ("ecl", false) => rng.choose(&["red", "blk", "xyz", "gmt"]).to_string(),
+        ("pid", true) => rng.digits(9),
+        ("pid", false) => match rng.below(2) {
+            0 => rng.digits(8),
+            _ => rng.digits(10),
+        },
+        ("cid", _) => rng.between(100, 350).to_string(),
+        _ => panic!("unknown passport field {}", field),
+    }
+}
+
+/// Makes up a passport of the given kind, in the format of the puzzle
+/// input: its fields are in a random order, spread over a few lines.
+fn generate_passport(kind: PassportKind, rng: &mut Lcg) -> String {
+    let mut fields: Vec<&str> = REQUIRED_FIELDS.to_vec();
+    let mut bad_field = None;
+    match kind {
+        PassportKind::Valid => (),
+        PassportKind::MissingField => {
+            for _ in 0..=rng.below(3) {
+                fields.remove(rng.below(fields.len()));
+            }
+        }
+        PassportKind::OutOfRange => bad_field = Some(*rng.choose(&REQUIRED_FIELDS)),
+    }
+    if rng.below(2) == 0 {
+        fields.push("cid");
+    }
+    rng.shuffle(&mut fields);
+    let mut text = String::new();
+    for (i, field) in fields.iter().enumerate() {
+        if i > 0 {
+            text.push(if rng.below(3) == 0 { '\n' } else { ' ' });
+        }
+        let valid = bad_field != Some(*field);
+        text.push_str(&format!("{}:{}", field, field_value(field, valid, rng)));
+    }
+    text
+}
+
+/// Makes up `count` passports, choosing the kind of each one with
+/// probability proportional to its weight in `mix`.
+fn generate_batch(count: usize, mix: &[(PassportKind, usize)], rng: &mut Lcg) -> Vec<PassportKind> {
+    let total: usize = mix.iter().map(|(_, weight)| weight).sum();
+    (0..count)
+        .map(|_| {
+            let mut pick = rng.below(total);
+            for (kind, weight) in mix {
+                if pick < *weight {
+                    return *kind;
+                }
+                pick -= weight;
+            }
+            unreachable!()
+        })
+        .collect()
+}
+
+fn generate_text(kinds: &[PassportKind], rng: &mut Lcg) -> String {
+    let passports: Vec<String> = kinds.iter().map(|k| generate_passport(*k, rng)).collect();
+    let mut text = passports.join("\n\n");
+    text.push('\n');
+    text
+}
+
+/// Parses the `--mix` option, which gives the relative weights of
+/// valid, missing-field and out-of-range passports, as in "2,1,1".
+fn parse_mix(s: &str) -> Result<Vec<(PassportKind, usize)>, String> {
+    let weights: Vec<usize> = s
+        .split(',')
+        .map(|w| {
+            w.trim()
+                .parse()
+                .map_err(|e| format!("invalid weight '{}' in --mix: {}", w, e))
+        })
+        .collect::<Result<_, _>>()?;
+    if weights.len() != 3 {
+        return Err(format!(
+            "--mix needs three weights (valid, missing-field and out-of-range), not '{}'",
+            s
+        ));
+    }
+    if weights.iter().sum::<usize>() == 0 {
+        return Err("at least one of the --mix weights must be positive".to_string());
+    }
+    Ok(vec![
+        (PassportKind::Valid, weights[0]),
+        (PassportKind::MissingField, weights[1]),
+        (PassportKind::OutOfRange, weights[2]),
+    ])
+}
+
+fn count_kind(kinds: &[PassportKind], kind: PassportKind) -> usize {
+    kinds.iter().filter(|k| **k == kind).count()
+}
+
+fn self_test() -> Result<(), String> {
+    let mut rng = Lcg::new(4);
+    let mix = parse_mix("2,1,1")?;
+    let kinds = generate_batch(500, &mix, &mut rng);
+    let text = generate_text(&kinds, &mut rng);
+    let passports = read_input(text.as_bytes()).map_err(|e| e.to_string())?;
+    let valid = count_kind(&kinds, PassportKind::Valid);
+    let out_of_range = count_kind(&kinds, PassportKind::OutOfRange);
+    if passports.len() != kinds.len() {
+        return Err(format!(
+            "FAIL: generated {} passports but read {}",
+            kinds.len(),
+            passports.len()
+        ));
+    }
+    for (kind, passport) in kinds.iter().zip(passports.iter()) {
+        let expected = match kind {
+            PassportKind::Valid => (true, true),
+            PassportKind::MissingField => (false, false),
+            PassportKind::OutOfRange => (true, false),
+        };
+        if (passport.valid1(), passport.valid2()) != expected {
+            return Err(format!(
+                "FAIL: generated a {:?} passport which is not: {:?}",
+                kind, passport.attributes
+            ));
+        }
+    }
+    if part1(&passports) != valid + out_of_range || part2(&passports) != valid {
+        return Err("FAIL: wrong counts for generated passports".to_string());
+    }
+    if valid == 0 || out_of_range == 0 || valid + out_of_range == kinds.len() {
+        return Err("FAIL: generated batch does not have all kinds of passport".to_string());
+    }
+    Ok(())
+}
+
+/// Writes made-up passports to stdout, for "day04 generate".
+fn generate_passports(options: &Options, out: &mut impl Write) -> Result<(), String> {
+    let mut rng = Lcg::new(options.seed.unwrap_or(20201204));
+    let mix = match &options.mix {
+        Some(mix) => parse_mix(mix)?,
+        None => parse_mix("2,1,1")?,
+    };
+    let kinds = generate_batch(options.count.unwrap_or(300), &mix, &mut rng);
+    write!(out, "{}", generate_text(&kinds, &mut rng)).map_err(write_error)?;
+    eprintln!(
+        "generated {} valid, {} missing-field and {} out-of-range passports",
+        count_kind(&kinds, PassportKind::Valid),
+        count_kind(&kinds, PassportKind::MissingField),
+        count_kind(&kinds, PassportKind::OutOfRange)
+    );
+    Ok(())
+}
+
+#[derive(Default)]
+struct Options {
+    count: Option<usize>,
+    generate: bool,
+    mix: Option<String>,
+    seed: Option<u64>,
+}
+
+fn parse_args() -> Result<Options, String> {
+    let mut options = Options::default();
+    let mut args = std::env::args().skip(1);
+    let number = |option: &str, v: String| -> Result<u64, String> {
+        v.parse()
+            .map_err(|e| format!("invalid value '{}' for {}: {}", v, option, e))
+    };
+    while let Some(arg) = args.next() {
+        let mut value = || {
+            args.next()
+                .ok_or_else(|| format!("option {} requires an argument", arg))
+        };
+        match arg.as_str() {
+            "generate" => options.generate = true,
+            "--count" => options.count = Some(number(&arg, value()?)? as usize),
+            "--mix" => options.mix = Some(value()?),
+            "--seed" => options.seed = Some(number(&arg, value()?)?),
+            _ => return Err(format!("unknown option {}", arg)),
+        }
+    }
+    let generating = options.count.is_some() || options.mix.is_some() || options.seed.is_some();
+    if generating && !options.generate {
+        return Err("--count, --mix and --seed are options of generate".to_string());
+    }
+    Ok(options)
+}
+
+fn run(input: impl BufRead, options: &Options, out: &mut impl Write) -> Result<(), String> {
+    if options.generate {
+        return generate_passports(options, out);
+    }
+    let passports = read_input(input).map_err(|e| e.to_string())?;
+    writeln!(
+        out,
         "There are a total of {} passports in the input",
         passports.len()
-    );
-    println!("Part 1: {} passports are valid", part1(&passports));
-    println!("Part 2: {} passports are valid", part2(&passports));
+    )
+    .map_err(write_error)?;
+    writeln!(out, "Part 1: {} passports are valid", part1(&passports)).map_err(write_error)?;
+    writeln!(out, "Part 2: {} passports are valid", part2(&passports)).map_err(write_error)?;
     Ok(())
 }
 
 fn main() {
-    std::process::exit(match run() {
+    let result = parse_args().and_then(|options| {
+        self_test()?;
+        run(io::stdin().lock(), &options, &mut io::stdout())
+    });
+    std::process::exit(match result {
         Ok(_) => 0,
         Err(err) => {
             eprintln!("error: {:?}", err);
@@ -176,3 +425,38 @@ fn main() {
         }
     });
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn output(input: &str, options: &Options) -> String {
+        let mut out = Vec::new();
+        run(input.as_bytes(), options, &mut out).unwrap();
+        String::from_utf8(out).unwrap()
+    }
+
+    #[test]
+    fn sample() {
+        insta::assert_snapshot!(output(
+            include_str!("../../samples/day04.txt"),
+            &Options::default()
+        ));
+    }
+
+    #[test]
+    fn part2_invalid() {
+        insta::assert_snapshot!(output(
+            include_str!("../../samples/day04-part2-invalid.txt"),
+            &Options::default()
+        ));
+    }
+
+    #[test]
+    fn part2_valid() {
+        insta::assert_snapshot!(output(
+            include_str!("../../samples/day04-part2-valid.txt"),
+            &Options::default()
+        ));
+    }
+}
@@ -1,80 +1,26 @@
-extern crate thiserror;
+extern crate aor2020;
+#[cfg(test)]
+extern crate insta;
+
+use aor2020::day05::{self, Layout, SeatError};
+use aor2020::output::write_error;
 use std::collections::BTreeSet;
 use std::io;
 use std::io::BufRead;
-use thiserror::Error;
-
-#[derive(Error, Debug)]
-pub enum SeatError {
-    #[error("empty starting range")]
-    EmptyStartingRange,
-    #[error("input is empty")]
-    NoInput,
-    #[error("input is invalid; {0}")]
-    InvalidInput(String),
-    #[error("Read error")]
-    ReadError { source: std::io::Error },
-}
-
-fn binary_search(
-    mut lower_incl: i32,
-    mut upper_excl: i32,
-    lower_directive: char,
-    upper_directive: char,
-    choices: &str,
-) -> Result<i32, SeatError> {
-    if lower_incl >= upper_excl {
-        return Err(SeatError::EmptyStartingRange);
-    }
-    for choice in choices.chars() {
-        if upper_excl == lower_incl + 1 {
-            return Err(SeatError::InvalidInput(
-                "too many seat directions".to_string(),
-            ));
-        }
-        let mid = lower_incl + (upper_excl - lower_incl) / 2;
-        if choice == lower_directive {
-            upper_excl = mid
-        } else if choice == upper_directive {
-            lower_incl = mid
-        } else {
-            return Err(SeatError::InvalidInput(format!(
-                "invalid direction character '{}'",
-                choice
-            )));
-        }
-    }
-    if upper_excl == lower_incl + 1 {
-        Ok(lower_incl)
-    } else {
-        Err(SeatError::InvalidInput(
-            "insufficient seat directions".to_string(),
-        ))
-    }
-}
+use std::io::Write;
 
-fn decode_seat(directions: &str) -> Result<i32, SeatError> {
-    let r = binary_search(0, 128, 'F', 'B', &directions[0..7])?;
-    let c = binary_search(0, 8, 'L', 'R', &directions[7..])?;
-    Ok(r * 8 + c)
-}
-
-fn part1(seats: &BTreeSet<i32>) -> Result<(), SeatError> {
+fn part1(seats: &BTreeSet<i32>) -> Result<i32, SeatError> {
     match seats.iter().next_back() {
-        Some(n) => {
-            println!("Part 1: largest seat ID is {}", n);
-            Ok(())
-        }
+        Some(n) => Ok(*n),
         None => Err(SeatError::NoInput),
     }
 }
 
-fn part2(seats: &BTreeSet<i32>) -> Result<(), SeatError> {
+fn part2(seats: &BTreeSet<i32>) -> Result<i32, SeatError> {
     for seat in seats.iter() {
         let following = seat + 1;
         if !seats.contains(&following) {
-            println!("Part 2: my seat is {}", following);
-            return Ok(());
+            return Ok(following);
         }
     }
     Err(SeatError::InvalidInput(
@@ -82,21 +28,77 @@ fn part2(seats: &BTreeSet<i32>) -> Result<(), SeatError> {
     ))
 }
 
-fn run() -> Result<(), SeatError> {
-    let seats = io::BufReader::new(io::stdin())
+#[derive(Default)]
+struct Options {
+    column_width: Option<u32>,
+    encode: Vec<String>,
+    row_width: Option<u32>,
+}
+
+fn parse_args() -> Result<Options, String> {
+    let mut options = Options::default();
+    let mut args = std::env::args().skip(1);
+    while let Some(arg) = args.next() {
+        let mut value = || {
+            args.next()
+                .ok_or_else(|| format!("option {} requires an argument", arg))
+        };
+        let mut width = || -> Result<u32, String> {
+            let v = value()?;
+            v.parse()
+                .map_err(|e| format!("invalid width '{}': {}", v, e))
+        };
+        match arg.as_str() {
+            "--column-width" => options.column_width = Some(width()?),
+            "--encode" => options.encode.push(value()?),
+            "--row-width" => options.row_width = Some(width()?),
+            _ => return Err(format!("unknown option {}", arg)),
+        }
+    }
+    Ok(options)
+}
+
+fn run(input: impl BufRead, options: &Options, out: &mut impl Write) -> Result<(), String> {
+    let default = Layout::default();
+    let layout = Layout::new(
+        options.row_width.unwrap_or_else(|| default.row_width()),
+        options
+            .column_width
+            .unwrap_or_else(|| default.column_width()),
+    )
+    .map_err(|e| e.to_string())?;
+    if !options.encode.is_empty() {
+        for seat in options.encode.iter() {
+            writeln!(
+                out,
+                "{}",
+                layout.encode_str(seat).map_err(|e| e.to_string())?
+            )
+            .map_err(write_error)?;
+        }
+        return Ok(());
+    }
+    let seats = input
         .lines()
         .map(|x| match x {
             Err(source) => Err(SeatError::ReadError { source }),
-            Ok(line) => decode_seat(line.as_str()),
+            Ok(line) => layout.decode(line.as_str()),
         })
-        .collect::<Result<BTreeSet<i32>, _>>()?;
-    part1(&seats)?;
-    part2(&seats)?;
+        .collect::<Result<BTreeSet<i32>, _>>()
+        .map_err(|e| e.to_string())?;
+    let largest = part1(&seats).map_err(|e| e.to_string())?;
+    writeln!(out, "Part 1: largest seat ID is {}", largest).map_err(write_error)?;
+    let mine = part2(&seats).map_err(|e| e.to_string())?;
+    writeln!(out, "Part 2: my seat is {}", mine).map_err(write_error)?;
     Ok(())
 }
 
 fn main() {
-    std::process::exit(match run() {
+    let result = parse_args().and_then(|options| {
+        day05::self_test()?;
+        run(io::stdin().lock(), &options, &mut io::stdout())
+    });
+    std::process::exit(match result {
         Ok(_) => 0,
         Err(err) => {
             eprintln!("error: {:?}", err);
@@ -104,3 +106,22 @@ fn main() {
         }
     });
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn output(input: &str, options: &Options) -> String {
+        let mut out = Vec::new();
+        run(input.as_bytes(), options, &mut out).unwrap();
+        String::from_utf8(out).unwrap()
+    }
+
+    #[test]
+    fn sample() {
+        insta::assert_snapshot!(output(
+            "FBFBBFFRLR\nBFFFBBFRRR\nFFFBBBFRRR\nBBFFBBFRLL\n",
+            &Options::default()
+        ));
+    }
+}
@@ -1,81 +1,77 @@
-use std::collections::BTreeSet;
+extern crate aor2020;
+#[cfg(test)]
+extern crate insta;
+
+use aor2020::day06::{self, group_stats, read_groups, total, GroupStats};
+use aor2020::output::write_error;
+use std::fs::File;
 use std::io;
 use std::io::BufRead;
+use std::io::Write;
 
-extern crate itertools;
-
-fn union(s1: String, s2: &str) -> String {
-    let u2: BTreeSet<char> = s2.chars().collect();
-    s1.chars().collect::<BTreeSet<char>>().union(&u2).collect()
-}
-
-fn intersection(s1: String, s2: &str) -> String {
-    let u2: BTreeSet<char> = s2.chars().collect();
-    s1.chars()
-        .collect::<BTreeSet<char>>()
-        .intersection(&u2)
-        .collect()
+#[derive(Default)]
+struct Options {
+    diff: bool,
+    files: Vec<String>,
 }
 
-// Each person is represented by a String containing their unique answers.
-// Each group is a Vec<String> containing the answers of each person in the group.
-// Hence the input - which is all the groups - is represented by a Vec<Vec<String>>.
-fn read_input(reader: impl BufRead) -> Result<Vec<Vec<String>>, io::Error> {
-    let empty_string = "".to_string();
-    let mut current_group = Vec::new();
-    let mut result = Vec::new();
-    for line_or_fail in reader.lines() {
-        match line_or_fail {
-            Ok(line) => {
-                if line.is_empty() {
-                    // Groups are separated by a blank line.
-                    result.push(current_group);
-                    current_group = Vec::new();
-                } else {
-                    current_group.push(union(line, &empty_string));
-                }
-            }
-            Err(e) => {
-                return Err(e);
-            }
+fn parse_args() -> Result<Options, String> {
+    let mut options = Options::default();
+    for arg in std::env::args().skip(1) {
+        match arg.as_str() {
+            "--diff" => options.diff = true,
+            _ if arg.starts_with('-') => return Err(format!("unknown option {}", arg)),
+            _ => options.files.push(arg),
         }
     }
-    if !current_group.is_empty() {
-        result.push(current_group);
+    if options.diff && options.files.len() < 2 {
+        return Err("--diff needs at least two input files".to_string());
     }
-    Ok(result)
+    if !options.diff && options.files.len() > 1 {
+        return Err("only one input file is allowed without --diff".to_string());
+    }
+    Ok(options)
 }
 
-fn count_anyone(g: &[String]) -> usize {
-    g.iter().fold(String::new(), |acc, s| union(acc, s)).len()
+fn read_file(filename: &str) -> Result<Vec<GroupStats>, String> {
+    let f = File::open(filename).map_err(|e| format!("failed to open {}: {}", filename, e))?;
+    let groups = read_groups(io::BufReader::new(f))
+        .map_err(|e| format!("failed to read {}: {}", filename, e))?;
+    Ok(group_stats(&groups))
 }
 
-fn count_everyone(g: &[String]) -> usize {
-    let mut it = g.iter();
-    match it.next() {
-        Some(s) => it
-            .fold(s.to_string(), |acc, s| intersection(acc, s.as_str()))
-            .len(),
-        None => 0,
+fn run(input: impl BufRead, options: &Options, out: &mut impl Write) -> Result<(), String> {
+    if options.diff {
+        let inputs = options
+            .files
+            .iter()
+            .map(|f| read_file(f))
+            .collect::<Result<Vec<_>, String>>()?;
+        writeln!(out, "{}", day06::diff_report(&options.files, &inputs)).map_err(write_error)?;
+        return Ok(());
     }
-}
-
-fn run() -> Result<(), std::io::Error> {
-    let groups: Vec<Vec<String>> = read_input(io::BufReader::new(io::stdin()))?;
-    println!("There are a total of {} groups in the input", groups.len());
-    println!(
-        "Part 1: {}",
-        groups.iter().fold(0, |total, g| total + count_anyone(g))
-    );
-    println!(
-        "Part 2: {}",
-        groups.iter().fold(0, |total, g| total + count_everyone(g))
-    );
+    let stats = match options.files.first() {
+        Some(filename) => read_file(filename)?,
+        None => group_stats(&read_groups(input).map_err(|e| e.to_string())?),
+    };
+    writeln!(
+        out,
+        "There are a total of {} groups in the input",
+        stats.len()
+    )
+    .map_err(write_error)?;
+    let t = total(&stats);
+    writeln!(out, "Part 1: {}", t.anyone).map_err(write_error)?;
+    writeln!(out, "Part 2: {}", t.everyone).map_err(write_error)?;
     Ok(())
 }
 
 fn main() {
-    std::process::exit(match run() {
+    let result = parse_args().and_then(|options| {
+        day06::self_test()?;
+        run(io::stdin().lock(), &options, &mut io::stdout())
+    });
+    std::process::exit(match result {
         Ok(_) => 0,
         Err(err) => {
             eprintln!("error: {:?}", err);
@@ -83,3 +79,22 @@ fn main() {
         }
     });
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn output(input: &str, options: &Options) -> String {
+        let mut out = Vec::new();
+        run(input.as_bytes(), options, &mut out).unwrap();
+        String::from_utf8(out).unwrap()
+    }
+
+    #[test]
+    fn sample() {
+        insta::assert_snapshot!(output(
+            include_str!("../../samples/day06.txt"),
+            &Options::default()
+        ));
+    }
+}
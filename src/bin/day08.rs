@@ -1,6 +1,13 @@
+extern crate aor2020;
+#[cfg(test)]
+extern crate insta;
+extern crate itertools;
+
+use aor2020::output::write_error;
 use std::fmt;
 use std::io;
 use std::io::BufRead;
+use std::io::Write;
 
 #[derive(PartialEq, Copy, Clone)]
 enum Operation {
@@ -70,10 +77,11 @@ impl GameConsole {
         self.code.len()
     }
 
-    fn disassemble(&mut self, start: usize, n: usize) {
+    fn disassemble(&mut self, start: usize, n: usize, out: &mut impl Write) -> Result<(), String> {
         for (offset, instr) in self.code[start..].iter().take(n).enumerate() {
-            println!("{:>4} {}", (start + offset), instr);
+            writeln!(out, "{:>4} {}", (start + offset), instr).map_err(write_error)?;
         }
+        Ok(())
     }
 
     fn get_instr(&self, loc: usize, change: &CodeChange) -> Instruction {
@@ -136,6 +144,246 @@ impl GameConsole {
         };
         self.run_with_change(pc, verbose, &inaccessible_change)
     }
+
+    /// The location of the instruction executed after the one at
+    /// `loc`, or None if the program terminates there instead.
+    fn successor(&self, loc: usize) -> Option<usize> {
+        let next = match self.code[loc].op {
+            Operation::Nop | Operation::Acc => loc.checked_add(1),
+            Operation::Jmp => loc.checked_add_signed(self.code[loc].arg as isize),
+        };
+        next.filter(|n| *n < self.code.len())
+    }
+}
+
+/// Finds the strongly connected components of the jump graph, in
+/// which each instruction leads to the one executed after it, using
+/// Tarjan's algorithm.  Each component is sorted, and the components
+/// are in order of their first instruction.
+fn strongly_connected_components(console: &GameConsole) -> Vec<Vec<usize>> {
+    let n = console.codesize();
+    let mut index: Vec<Option<usize>> = vec![None; n];
+    let mut lowlink: Vec<usize> = vec![0; n];
+    let mut on_stack: Vec<bool> = vec![false; n];
+    let mut stack: Vec<usize> = Vec::new();
+    let mut next_index = 0;
+    let mut result: Vec<Vec<usize>> = Vec::new();
+    for start in 0..n {
+        if index[start].is_some() {
+            continue;
+        }
+        // Since each instruction has at most one successor, the
+        // depth-first search is a walk along a single path.
+        let mut path: Vec<usize> = Vec::new();
+        let mut v = Some(start);
+        while let Some(loc) = v {
+            index[loc] = Some(next_index);
+            lowlink[loc] = next_index;
+            next_index += 1;
+            stack.push(loc);
+            on_stack[loc] = true;
+            path.push(loc);
+            v = match console.successor(loc) {
+                Some(w) if index[w].is_none() => Some(w),
+                Some(w) => {
+                    if on_stack[w] {
+                        lowlink[loc] = lowlink[loc].min(index[w].unwrap());
+                    }
+                    None
+                }
+                None => None,
+            };
+        }
+        // Unwind the path, as the recursive algorithm would.
+        while let Some(loc) = path.pop() {
+            if let Some(&parent) = path.last() {
+                lowlink[parent] = lowlink[parent].min(lowlink[loc]);
+            }
+            if Some(lowlink[loc]) == index[loc] {
+                let mut component = Vec::new();
+                while let Some(w) = stack.pop() {
+                    on_stack[w] = false;
+                    component.push(w);
+                    if w == loc {
+                        break;
+                    }
+                }
+                component.sort_unstable();
+                result.push(component);
+            }
+        }
+    }
+    result.sort();
+    result
+}
+
+/// The loops in a program and the instructions which never run.
+#[derive(Debug, PartialEq, Eq)]
+struct Analysis {
+    /// Each set of instructions which can repeat forever, in order of
+    /// their first instruction.
+    loops: Vec<Vec<usize>>,
+    /// The index in `loops` of the loop which the program enters when
+    /// started at PC 0, if it does not terminate.
+    entered: Option<usize>,
+    /// The instructions which do not run when the program is started
+    /// at PC 0.
+    dead: Vec<usize>,
+}
+
+fn analyse(console: &GameConsole) -> Analysis {
+    let loops: Vec<Vec<usize>> = strongly_connected_components(console)
+        .into_iter()
+        .filter(|c| c.len() > 1 || console.successor(c[0]) == Some(c[0]))
+        .collect();
+    let mut executed = vec![false; console.codesize()];
+    let mut pc = if console.codesize() > 0 {
+        Some(0)
+    } else {
+        None
+    };
+    while let Some(loc) = pc {
+        if executed[loc] {
+            break;
+        }
+        executed[loc] = true;
+        pc = console.successor(loc);
+    }
+    let entered = pc.map(|loc| {
+        loops
+            .iter()
+            .position(|l| l.binary_search(&loc).is_ok())
+            .expect("a repeated instruction is in a loop")
+    });
+    Analysis {
+        loops,
+        entered,
+        dead: (0..console.codesize())
+            .filter(|loc| !executed[*loc])
+            .collect(),
+    }
+}
+
+/// Describes a sorted list of locations as ranges, for example
+/// "1-4, 6, 8-9".
+fn describe_locations(locations: &[usize]) -> String {
+    let mut ranges: Vec<(usize, usize)> = Vec::new();
+    for loc in locations {
+        match ranges.last_mut() {
+            Some((_, end)) if *end + 1 == *loc => *end = *loc,
+            _ => ranges.push((*loc, *loc)),
+        }
+    }
+    itertools::join(
+        ranges.iter().map(|(start, end)| {
+            if start == end {
+                start.to_string()
+            } else {
+                format!("{}-{}", start, end)
+            }
+        }),
+        ", ",
+    )
+}
+
+/// Describes `count` of `noun`, adding an "s" unless `count` is 1.
+fn plural(count: usize, noun: &str) -> String {
+    if count == 1 {
+        format!("{} {}", count, noun)
+    } else {
+        format!("{} {}s", count, noun)
+    }
+}
+
+fn report_analysis(analysis: &Analysis, out: &mut impl Write) -> Result<(), String> {
+    writeln!(
+        out,
+        "Analysis: the program has {}",
+        plural(analysis.loops.len(), "loop")
+    )
+    .map_err(write_error)?;
+    for (i, l) in analysis.loops.iter().enumerate() {
+        writeln!(
+            out,
+            "Analysis: loop of {} at {}{}",
+            plural(l.len(), "instruction"),
+            describe_locations(l),
+            if analysis.entered == Some(i) {
+                " (entered from PC 0)"
+            } else {
+                ""
+            }
+        )
+        .map_err(write_error)?;
+    }
+    if analysis.entered.is_none() {
+        writeln!(out, "Analysis: started at PC 0, the program terminates").map_err(write_error)?;
+    }
+    match analysis.dead.len() {
+        0 => writeln!(out, "Analysis: every instruction runs").map_err(write_error)?,
+        1 => writeln!(
+            out,
+            "Analysis: 1 instruction never runs: {}",
+            describe_locations(&analysis.dead)
+        )
+        .map_err(write_error)?,
+        n => writeln!(
+            out,
+            "Analysis: {} instructions never run: {}",
+            n,
+            describe_locations(&analysis.dead)
+        )
+        .map_err(write_error)?,
+    }
+    Ok(())
+}
+
+/// Writes the jump graph in Graphviz DOT format.  Each loop found by
+/// `analyse` is drawn as a cluster (in red if the program enters it
+/// from PC 0), and instructions which never run are grey.
+fn write_dot(console: &GameConsole, analysis: &Analysis) -> String {
+    let mut out = String::from("digraph program {\n  node [shape=box, fontname=monospace];\n");
+    let node = |loc: usize| -> String {
+        let colour = if analysis.dead.binary_search(&loc).is_ok() {
+            ", style=filled, fillcolor=grey"
+        } else {
+            ""
+        };
+        format!(
+            "n{} [label=\"{:>4} {}\"{}];",
+            loc, loc, console.code[loc], colour
+        )
+    };
+    let mut clustered = vec![false; console.codesize()];
+    for (i, l) in analysis.loops.iter().enumerate() {
+        out.push_str(&format!("  subgraph cluster_loop{} {{\n", i));
+        out.push_str(&format!(
+            "    label=\"loop {}\";\n    color={};\n",
+            i,
+            if analysis.entered == Some(i) {
+                "red"
+            } else {
+                "black"
+            }
+        ));
+        for loc in l {
+            clustered[*loc] = true;
+            out.push_str(&format!("    {}\n", node(*loc)));
+        }
+        out.push_str("  }\n");
+    }
+    for loc in (0..console.codesize()).filter(|loc| !clustered[*loc]) {
+        out.push_str(&format!("  {}\n", node(loc)));
+    }
+    out.push_str("  end [shape=doublecircle, label=\"end\"];\n");
+    for loc in 0..console.codesize() {
+        match console.successor(loc) {
+            Some(next) => out.push_str(&format!("  n{} -> n{};\n", loc, next)),
+            None => out.push_str(&format!("  n{} -> end;\n", loc)),
+        }
+    }
+    out.push_str("}\n");
+    out
 }
 
 fn decode_instruction(line: &str) -> Result<Instruction, String> {
@@ -170,14 +418,18 @@ fn decode_instruction(line: &str) -> Result<Instruction, String> {
     }
 }
 
-fn read_program() -> Result<GameConsole, String> {
-    let mut console = GameConsole {
-        code: Vec::new(),
+fn new_console(code: Vec<Instruction>) -> GameConsole {
+    GameConsole {
+        code,
         visits: Vec::new(),
         accumulator: 0,
         program_counter: 0,
-    };
-    for thing in io::BufReader::new(io::stdin()).lines() {
+    }
+}
+
+fn read_program(input: impl BufRead) -> Result<GameConsole, String> {
+    let mut console = new_console(Vec::new());
+    for thing in input.lines() {
         match thing {
             Err(e) => return Err(format!("I/O error: {}", e)),
             Ok(line) => match decode_instruction(&line) {
@@ -191,9 +443,10 @@ fn read_program() -> Result<GameConsole, String> {
     Ok(console)
 }
 
-fn part1(console: &mut GameConsole) {
+fn part1(console: &mut GameConsole, out: &mut impl Write) -> Result<(), String> {
     let (terminated, pc) = console.run(0, false);
-    println!(
+    writeln!(
+        out,
         "Part 1: code {} at PC={} with ACC={:>+4}",
         (match terminated {
             true => "terminated normally",
@@ -201,10 +454,11 @@ fn part1(console: &mut GameConsole) {
         }),
         pc,
         console.accumulator
-    );
+    )
+    .map_err(write_error)
 }
 
-fn part2(console: &mut GameConsole) {
+fn part2(console: &mut GameConsole, out: &mut impl Write) -> Result<(), String> {
     let changes: Vec<CodeChange> = console
         .code
         .iter()
@@ -218,31 +472,106 @@ fn part2(console: &mut GameConsole) {
     for change in &changes {
         let (terminated, pc) = console.run_with_change(0, false, change);
         if terminated {
-            println!(
+            writeln!(
+                out,
                 "Part 2: console code with flip at {} terminated normally at PC={} with ACC={:>+4}",
                 change.location, pc, console.accumulator
-            );
-            println!(
+            )
+            .map_err(write_error)?;
+            writeln!(
+                out,
                 "Part 2: code before flip: {}",
                 console.code[change.location]
-            );
-            println!("Part 2: code  after flip: {}", change.instruction);
-            return;
+            )
+            .map_err(write_error)?;
+            writeln!(out, "Part 2: code  after flip: {}", change.instruction)
+                .map_err(write_error)?;
+            return Ok(());
         }
     }
-    println!("Part 2: failed to find a suitable flip");
+    writeln!(out, "Part 2: failed to find a suitable flip").map_err(write_error)
 }
 
-fn run() -> Result<(), String> {
-    let mut console = read_program()?;
-    console.disassemble(0, console.codesize());
-    part1(&mut console);
-    part2(&mut console);
+fn self_test() -> Result<(), String> {
+    let program = |text: &str| -> Result<GameConsole, String> {
+        Ok(new_console(
+            text.lines()
+                .map(decode_instruction)
+                .collect::<Result<Vec<Instruction>, String>>()?,
+        ))
+    };
+    let sample =
+        program("nop +0\nacc +1\njmp +4\nacc +3\njmp -3\nacc -99\nacc +1\njmp -4\nacc +6")?;
+    let expected = Analysis {
+        loops: vec![vec![1, 2, 3, 4, 6, 7]],
+        entered: Some(0),
+        dead: vec![5, 8],
+    };
+    if analyse(&sample) != expected {
+        return Err(format!("FAIL: sample analysed as {:?}", analyse(&sample)));
+    }
+    // A jump to itself is a loop, and a jump off either end of the
+    // program terminates it.
+    let other = program("jmp +3\njmp +0\njmp -9\njmp -1\nnop +0\njmp -1")?;
+    let expected = Analysis {
+        loops: vec![vec![1], vec![4, 5]],
+        entered: None,
+        dead: vec![1, 4, 5],
+    };
+    if analyse(&other) != expected {
+        return Err(format!("FAIL: program analysed as {:?}", analyse(&other)));
+    }
+    if plural(1, "loop") != "1 loop" || plural(2, "loop") != "2 loops" {
+        return Err("FAIL: wrong plural".to_string());
+    }
+    let dot = write_dot(&sample, &analyse(&sample));
+    if !dot.contains("n8 -> end;") || !dot.contains("color=red") {
+        return Err(format!("FAIL: bad DOT output:\n{}", dot));
+    }
+    if describe_locations(&[1, 2, 3, 4, 6, 8, 9]) != "1-4, 6, 8-9" {
+        return Err("FAIL: wrong description of locations".to_string());
+    }
     Ok(())
 }
 
+#[derive(Default)]
+struct Options {
+    analyse: bool,
+    dot: bool,
+}
+
+fn parse_args() -> Result<Options, String> {
+    let mut options = Options::default();
+    for arg in std::env::args().skip(1) {
+        match arg.as_str() {
+            "analyse" => options.analyse = true,
+            "dot" => options.dot = true,
+            _ => return Err(format!("unknown option {}", arg)),
+        }
+    }
+    Ok(options)
+}
+
+fn run(input: impl BufRead, options: &Options, out: &mut impl Write) -> Result<(), String> {
+    let mut console = read_program(input)?;
+    if options.analyse {
+        return report_analysis(&analyse(&console), out);
+    }
+    if options.dot {
+        write!(out, "{}", write_dot(&console, &analyse(&console))).map_err(write_error)?;
+        return Ok(());
+    }
+    console.disassemble(0, console.codesize(), out)?;
+    part1(&mut console, out)?;
+    part2(&mut console, out)
+}
+
 fn main() {
-    std::process::exit(match run() {
+    let result = parse_args().and_then(|options| {
+        self_test()?;
+        run(io::stdin().lock(), &options, &mut io::stdout())
+    });
+    std::process::exit(match result {
         Ok(_) => 0,
         Err(err) => {
             eprintln!("error: {:?}", err);
@@ -250,3 +579,44 @@ fn main() {
         }
     });
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn output(input: &str, options: &Options) -> String {
+        let mut out = Vec::new();
+        run(input.as_bytes(), options, &mut out).unwrap();
+        String::from_utf8(out).unwrap()
+    }
+
+    #[test]
+    fn sample() {
+        insta::assert_snapshot!(output(
+            include_str!("../../samples/day08-pt1.txt"),
+            &Options::default()
+        ));
+    }
+
+    #[test]
+    fn analysis() {
+        insta::assert_snapshot!(output(
+            include_str!("../../samples/day08-pt1.txt"),
+            &Options {
+                analyse: true,
+                ..Options::default()
+            }
+        ));
+    }
+
+    #[test]
+    fn dot() {
+        insta::assert_snapshot!(output(
+            include_str!("../../samples/day08-pt1.txt"),
+            &Options {
+                dot: true,
+                ..Options::default()
+            }
+        ));
+    }
+}
@@ -1,8 +1,21 @@
+extern crate aor2020;
+#[cfg(test)]
+extern crate insta;
 extern crate itertools;
+extern crate rayon;
+extern crate serde_json;
 
+use aor2020::algorithm::{self, Registry};
+use aor2020::explain::{self, Explanation};
+use aor2020::output::write_error;
+use aor2020::rng::Lcg;
+use rayon::prelude::*;
 use std::collections::VecDeque;
 use std::io;
 use std::io::BufRead;
+use std::io::Write;
+use std::ops::Range;
+use std::time::Instant;
 
 fn read_i64(thing: Result<String, std::io::Error>) -> Result<i64, String> {
     match thing {
@@ -57,39 +70,430 @@ fn min_max_sum<U: Ord + std::ops::AddAssign + Copy, T: std::iter::Iterator<Item
     }
 }
 
-fn solve2(all_input: &Vec<i64>, target: i64) -> Option<(i64, i64)> {
+/// Finds the shortest range of at least two numbers which adds up to
+/// `target` (the one with the lowest start, if there are several).
+fn shortest_range(all_input: &[i64], target: i64) -> Option<Range<usize>> {
     for windowsize in 2..all_input.len() {
-        for w in all_input.windows(windowsize) {
-            if let Some((wmin, wmax, wsum)) = min_max_sum(w.iter().cloned()) {
-                if wsum == target {
-                    return Some((wmin, wmax));
-                }
+        for (start, w) in all_input.windows(windowsize).enumerate() {
+            if w.iter().sum::<i64>() == target {
+                return Some(start..start + windowsize);
             }
         }
     }
     None
 }
 
-fn run() -> Result<(), String> {
-    let numbers: Vec<i64> = match io::BufReader::new(io::stdin())
-        .lines()
-        .map(read_i64)
-        .collect()
+fn solve2(all_input: &[i64], target: i64) -> Option<(i64, i64)> {
+    shortest_range(all_input, target).and_then(|r| range_min_max(all_input, r))
+}
+
+/// Running totals of `numbers`, so that the sum of `numbers[s..e]` is
+/// `prefix[e] - prefix[s]`.
+fn prefix_sums(numbers: &[i64]) -> Vec<i64> {
+    let mut prefix = Vec::with_capacity(numbers.len() + 1);
+    let mut total = 0;
+    prefix.push(total);
+    for n in numbers {
+        total += n;
+        prefix.push(total);
+    }
+    prefix
+}
+
+/// Looks for a range of at least two numbers which adds up to
+/// `target`, starting at one of `starts`.  Returns the range with the
+/// lowest start (and the shortest of those).  Since none of the numbers
+/// are negative, the end of the range never needs to move backwards,
+/// so this takes time proportional to the length of `starts` plus the
+/// length of the range found.
+fn scan_starts(prefix: &[i64], target: i64, starts: Range<usize>) -> Option<Range<usize>> {
+    let last = prefix.len() - 1;
+    let mut end = starts.start;
+    for start in starts {
+        end = end.max(start + 2);
+        while end <= last && prefix[end] - prefix[start] < target {
+            end += 1;
+        }
+        if end > last {
+            return None;
+        }
+        if prefix[end] - prefix[start] == target {
+            return Some(start..end);
+        }
+    }
+    None
+}
+
+fn check_not_negative(numbers: &[i64]) -> Result<(), String> {
+    match numbers.iter().find(|n| **n < 0) {
+        Some(n) => Err(format!(
+            "this search only works when no number is negative, but the input contains {}",
+            n
+        )),
+        None => Ok(()),
+    }
+}
+
+fn range_min_max(numbers: &[i64], range: Range<usize>) -> Option<(i64, i64)> {
+    min_max_sum(numbers[range].iter().cloned()).map(|(least, most, _)| (least, most))
+}
+
+/// The O(n) single-threaded scan.
+fn solve2_scan(numbers: &[i64], target: i64) -> Result<Option<(i64, i64)>, String> {
+    check_not_negative(numbers)?;
+    let prefix = prefix_sums(numbers);
+    Ok(scan_starts(&prefix, target, 0..numbers.len()).and_then(|r| range_min_max(numbers, r)))
+}
+
+/// Like `solve2_scan`, but the starting positions are divided into
+/// chunks of `chunk_size` which are searched in parallel.  The result
+/// is always the one from the lowest chunk that has one, so it is the
+/// same range `solve2_scan` finds.
+fn solve2_parallel(
+    numbers: &[i64],
+    target: i64,
+    chunk_size: usize,
+) -> Result<Option<(i64, i64)>, String> {
+    check_not_negative(numbers)?;
+    let prefix = prefix_sums(numbers);
+    let chunk_size = chunk_size.max(1);
+    let chunks = numbers.len().div_ceil(chunk_size);
+    let found = (0..chunks).into_par_iter().find_map_first(|chunk| {
+        let first = chunk * chunk_size;
+        let last = (first + chunk_size).min(numbers.len());
+        scan_starts(&prefix, target, first..last)
+    });
+    Ok(found.and_then(|r| range_min_max(numbers, r)))
+}
+
+/// How many starting positions each thread of the parallel search
+/// looks at in one go.
+const PARALLEL_CHUNK_SIZE: usize = 65536;
+
+/// How to search for part 2.  `Windows` finds the shortest range
+/// (taking the lowest start among those), while `Scan` and `Parallel`
+/// find the range with the lowest start.  The puzzle input has only one
+/// answer, so they agree on that.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Algorithm {
+    Windows,
+    Scan,
+    Parallel,
+}
+
+fn algorithms() -> Registry<Algorithm> {
+    Registry::for_day(9)
+        .register(
+            "windows",
+            "tries every window, shortest first",
+            Algorithm::Windows,
+        )
+        .register("scan", "prefix sums", Algorithm::Scan)
+        .register("parallel", "prefix sums, in parallel", Algorithm::Parallel)
+}
+
+fn solve2_with(
+    algorithm: Algorithm,
+    numbers: &[i64],
+    target: i64,
+) -> Result<Option<(i64, i64)>, String> {
+    match algorithm {
+        Algorithm::Windows => Ok(solve2(numbers, target)),
+        Algorithm::Scan => solve2_scan(numbers, target),
+        Algorithm::Parallel => solve2_parallel(numbers, target, PARALLEL_CHUNK_SIZE),
+    }
+}
+
+/// Solves part 2 with each algorithm, for the crosscheck command.
+fn crosscheck(numbers: &[i64], preamble_len: usize, out: &mut impl Write) -> Result<(), String> {
+    let target = solve1(numbers.iter().cloned(), preamble_len)
+        .ok_or_else(|| "Part 1: did not find the invalid number".to_string())?;
+    let report =
+        algorithms().crosscheck(
+            |algorithm| match solve2_with(*algorithm, numbers, target)? {
+                Some((least, most)) => Ok(format!("{} + {} = {}", least, most, least + most)),
+                None => Err("did not find a solution to part 2".to_string()),
+            },
+        )?;
+    writeln!(out, "Part 1: invalid number is {}", target).map_err(write_error)?;
+    writeln!(out, "Part 2:\n{}", report).map_err(write_error)?;
+    Ok(())
+}
+
+/// How many numbers are in the stream used by `--benchmark`.
+const BENCHMARK_NUMBERS: usize = 10_000_000;
+
+/// Times the scan and the parallel search on a synthetic stream of
+/// pseudo-random even numbers.  One number near the end is odd, and the
+/// target is its sum with the number before it, so that pair is the
+/// only answer and both searches have to cover the whole stream.
+fn benchmark(count: usize, out: &mut impl Write) -> Result<(), String> {
+    let mut rng = Lcg::new(0x2020_0909);
+    let mut numbers: Vec<i64> = (0..count)
+        .map(|_| (rng.below(1000) + 1) as i64 * 2)
+        .collect();
+    let odd = count - 100;
+    numbers[odd] += 1;
+    let target = numbers[odd] + numbers[odd - 1];
+
+    let mut results = Vec::new();
+    for algorithm in &[Algorithm::Scan, Algorithm::Parallel] {
+        let begin = Instant::now();
+        let result = solve2_with(*algorithm, &numbers, target)?;
+        results.push((*algorithm, result, begin.elapsed()));
+    }
+    let (_, scan_result, scan_time) = results[0];
+    for (algorithm, result, time) in &results {
+        writeln!(
+            out,
+            "{:?}: {} numbers, target {}: {:?} in {:?} (speedup {:.1}x)",
+            algorithm,
+            count,
+            target,
+            result,
+            time,
+            scan_time.as_secs_f64() / time.as_secs_f64()
+        )
+        .map_err(write_error)?;
+        if *result != scan_result {
+            return Err(format!(
+                "{:?} found {:?} but the scan found {:?}",
+                algorithm, result, scan_result
+            ));
+        }
+    }
+    Ok(())
+}
+
+/// Describes how each part is solved: which two earlier numbers add
+/// up to each number until the invalid one, and then the range which
+/// `algorithm` finds adding up to the invalid number.
+fn explain(
+    numbers: &[i64],
+    preamble_len: usize,
+    algorithm: Algorithm,
+) -> Result<Explanation, String> {
+    let mut explanation = Explanation::new();
+    let mut invalid = None;
+    for (k, n) in numbers.iter().enumerate().skip(preamble_len) {
+        let window: VecDeque<i64> = numbers[k - preamble_len..k].iter().cloned().collect();
+        match find_pair(&window, n) {
+            Some((a, b)) => {
+                explanation
+                    .step(1, "sum", format!("{} = {} + {}", n, a, b))
+                    .fact("number", *n)
+                    .fact("pair", vec![a, b]);
+            }
+            None => {
+                explanation
+                    .step(
+                        1,
+                        "invalid",
+                        format!(
+                            "{} is not the sum of two of the {} numbers before it",
+                            n, preamble_len
+                        ),
+                    )
+                    .fact("number", *n)
+                    .fact("position", k + 1);
+                invalid = Some(*n);
+                break;
+            }
+        }
+    }
+    let target = match invalid {
+        Some(n) => n,
+        None => return Ok(explanation),
+    };
+    let (found, which) = match algorithm {
+        Algorithm::Windows => (shortest_range(numbers, target), "shortest"),
+        Algorithm::Scan | Algorithm::Parallel => {
+            check_not_negative(numbers)?;
+            (
+                scan_starts(&prefix_sums(numbers), target, 0..numbers.len()),
+                "first",
+            )
+        }
+    };
+    match found {
+        Some(range) => {
+            let (least, most) = range_min_max(numbers, range.clone()).unwrap();
+            explanation
+                .step(
+                    2,
+                    "range",
+                    format!(
+                        "the {} range adding up to {} is the {} numbers from position {} to {}",
+                        which,
+                        target,
+                        range.len(),
+                        range.start + 1,
+                        range.end
+                    ),
+                )
+                .fact("from", range.start + 1)
+                .fact("to", range.end);
+            explanation
+                .step(
+                    2,
+                    "answer",
+                    format!(
+                        "the smallest of them is {} and the largest is {}, so the answer is {}",
+                        least,
+                        most,
+                        least + most
+                    ),
+                )
+                .fact("answer", least + most);
+        }
+        None => {
+            explanation.step(
+                2,
+                "none",
+                format!("no range of numbers adds up to {}", target),
+            );
+        }
+    }
+    Ok(explanation)
+}
+
+fn write_explanation(
+    explanation: &Explanation,
+    format: explain::Format,
+    out: &mut impl Write,
+) -> Result<(), String> {
+    match format {
+        explain::Format::Text => write!(out, "{}", explanation).map_err(write_error)?,
+        explain::Format::Json => writeln!(
+            out,
+            "{}",
+            serde_json::to_string_pretty(explanation).map_err(|e| e.to_string())?
+        )
+        .map_err(write_error)?,
+    }
+    Ok(())
+}
+
+fn self_test() -> Result<(), String> {
+    algorithm::self_test()?;
+    let sample: Vec<i64> = vec![
+        35, 20, 15, 25, 47, 40, 62, 55, 65, 95, 102, 117, 150, 182, 127, 219, 299, 277, 309, 576,
+    ];
+    let target = solve1(sample.iter().cloned(), 5);
+    if target != Some(127) {
+        return Err(format!("FAIL: part 1 of the sample gave {:?}", target));
+    }
+    for algorithm in algorithms().algorithms() {
+        let got = solve2_with(algorithm.solver, &sample, 127)?;
+        if got != Some((15, 47)) {
+            return Err(format!(
+                "FAIL: {} gave {:?} for the sample",
+                algorithm.name, got
+            ));
+        }
+    }
+    // The lowest-start range wins, even when a later one is shorter,
+    // and a single number equal to the target does not count.
+    let numbers: Vec<i64> = vec![9, 1, 2, 0, 3, 4, 5, 7, 2, 1];
+    for chunk_size in 1..=numbers.len() {
+        let got = solve2_parallel(&numbers, 9, chunk_size)?;
+        if got != Some((0, 4)) {
+            return Err(format!(
+                "FAIL: parallel search with chunks of {} gave {:?}",
+                chunk_size, got
+            ));
+        }
+    }
+    if solve2_scan(&numbers, 100)?.is_some() {
+        return Err("FAIL: scan found a range which is not there".to_string());
+    }
+    if solve2_scan(&[1, -1, 2], 2).is_ok() {
+        return Err("FAIL: scan accepted a negative number".to_string());
+    }
+    let explanation = explain(&sample, 5, Algorithm::Scan)?;
+    let kinds: Vec<&str> = explanation.steps().iter().map(|s| s.kind).collect();
+    if kinds
+        != ["sum"; 9]
+            .iter()
+            .chain(&["invalid", "range", "answer"])
+            .cloned()
+            .collect::<Vec<_>>()
+        || explanation.steps()[9].facts["number"] != 127i64.into()
+        || explanation.steps()[11].facts["answer"] != 62i64.into()
     {
+        return Err(format!("FAIL: sample was explained as:\n{}", explanation));
+    }
+    Ok(())
+}
+
+#[derive(Default)]
+struct Options {
+    algorithm: Option<Algorithm>,
+    benchmark: bool,
+    crosscheck: bool,
+    explain: Option<explain::Format>,
+    preamble: Option<usize>,
+}
+
+fn parse_args() -> Result<Options, String> {
+    let mut options = Options::default();
+    let mut args = std::env::args().skip(1);
+    while let Some(arg) = args.next() {
+        let mut value = || {
+            args.next()
+                .ok_or_else(|| format!("option {} requires an argument", arg))
+        };
+        match arg.as_str() {
+            "crosscheck" => options.crosscheck = true,
+            "--algorithm" => options.algorithm = Some(algorithms().get(&value()?)?.solver),
+            "--benchmark" => options.benchmark = true,
+            "--explain" => options.explain = Some(explain::Format::Text),
+            "--explain-format" => options.explain = Some(value()?.parse()?),
+            "--preamble" => {
+                options.preamble = Some(
+                    value()?
+                        .parse()
+                        .map_err(|e| format!("invalid preamble length: {}", e))?,
+                )
+            }
+            _ => return Err(format!("unknown option {}", arg)),
+        }
+    }
+    Ok(options)
+}
+
+fn run(input: impl BufRead, options: &Options, out: &mut impl Write) -> Result<(), String> {
+    if options.benchmark {
+        return benchmark(BENCHMARK_NUMBERS, out);
+    }
+    let numbers: Vec<i64> = match input.lines().map(read_i64).collect() {
         Err(e) => return Err(e),
         Ok(numbers) => numbers,
     };
-    let preamble_len = 25;
+    // The puzzle uses a preamble of 25 numbers, but the sample only 5.
+    let preamble_len = options.preamble.unwrap_or(25);
+    if options.crosscheck {
+        return crosscheck(&numbers, preamble_len, out);
+    }
+    if let Some(format) = options.explain {
+        let algorithm = options.algorithm.unwrap_or(Algorithm::Windows);
+        write_explanation(&explain(&numbers, preamble_len, algorithm)?, format, out)?;
+        if format == explain::Format::Json {
+            return Ok(());
+        }
+    }
     let n = match solve1(numbers.iter().cloned(), preamble_len) {
         Some(n) => n,
         None => {
             return Err("Part 1: did not find the invalid number".to_string());
         }
     };
-    println!("Part 1: invalid number is {}", n);
-    match solve2(&numbers, n) {
+    writeln!(out, "Part 1: invalid number is {}", n).map_err(write_error)?;
+    let algorithm = options.algorithm.unwrap_or(Algorithm::Windows);
+    match solve2_with(algorithm, &numbers, n)? {
         Some((least, most)) => {
-            println!("Part 2: {} + {} = {}", least, most, (least + most));
+            writeln!(out, "Part 2: {} + {} = {}", least, most, (least + most))
+                .map_err(write_error)?;
             Ok(())
         }
         None => Err("did not find a solution to part 2".to_string()),
@@ -97,7 +501,11 @@ fn run() -> Result<(), String> {
 }
 
 fn main() {
-    std::process::exit(match run() {
+    let result = parse_args().and_then(|options| {
+        self_test()?;
+        run(io::stdin().lock(), &options, &mut io::stdout())
+    });
+    std::process::exit(match result {
         Ok(_) => 0,
         Err(err) => {
             eprintln!("error: {:?}", err);
@@ -105,3 +513,25 @@ fn main() {
         }
     });
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn output(input: &str, options: &Options) -> String {
+        let mut out = Vec::new();
+        run(input.as_bytes(), options, &mut out).unwrap();
+        String::from_utf8(out).unwrap()
+    }
+
+    #[test]
+    fn sample() {
+        insta::assert_snapshot!(output(
+            include_str!("../../samples/day09-pt1.txt"),
+            &Options {
+                preamble: Some(5),
+                ..Options::default()
+            }
+        ));
+    }
+}
@@ -1,6 +1,17 @@
+extern crate aor2020;
+#[cfg(test)]
+extern crate insta;
+extern crate serde_json;
+
+use aor2020::algorithm::Registry;
+use aor2020::explain::{self, Explanation};
+use aor2020::modular::{mat_identity, mat_mul, mat_pow, Arithmetic, Matrix};
+use aor2020::output::write_error;
 use std::collections::BTreeMap;
+use std::fmt;
 use std::io;
 use std::io::BufRead;
+use std::io::Write;
 
 fn differences(ratings: &Vec<i64>) -> Vec<(i64, i64)> {
     let mut result: Vec<(i64, i64)> = Vec::new();
@@ -24,12 +35,8 @@ fn read_i64(thing: Result<String, std::io::Error>) -> Result<i64, String> {
     }
 }
 
-fn sorted_integer_input() -> Result<Vec<i64>, String> {
-    let mut items: Vec<i64> = match io::BufReader::new(io::stdin())
-        .lines()
-        .map(read_i64)
-        .collect()
-    {
+fn sorted_integer_input(input: impl BufRead) -> Result<Vec<i64>, String> {
+    let mut items: Vec<i64> = match input.lines().map(read_i64).collect() {
         Err(e) => return Err(e),
         Ok(numbers) => numbers,
     };
@@ -37,10 +44,10 @@ fn sorted_integer_input() -> Result<Vec<i64>, String> {
     Ok(items)
 }
 
-fn part1(ratings: &Vec<i64>) -> (Vec<(i64, i64)>, i64) {
+fn part1(ratings: &Vec<i64>, out: &mut impl Write) -> Result<(Vec<(i64, i64)>, i64), String> {
     let diffs = differences(ratings);
     let my_device_rating: i64 = (*diffs.last().unwrap()).1;
-    println!("Part 1: my device rating is {}", my_device_rating);
+    writeln!(out, "Part 1: my device rating is {}", my_device_rating).map_err(write_error)?;
     let mut counts: BTreeMap<i64, usize> = BTreeMap::new();
     for (d, _) in &diffs {
         match d {
@@ -53,8 +60,8 @@ fn part1(ratings: &Vec<i64>) -> (Vec<(i64, i64)>, i64) {
         }
     }
     let solution: usize = counts.get(&1).unwrap_or(&0) * counts.get(&3).unwrap_or(&0);
-    println!("Part 1: answer is {}", solution);
-    (diffs, my_device_rating)
+    writeln!(out, "Part 1: answer is {}", solution).map_err(write_error)?;
+    Ok((diffs, my_device_rating))
 }
 
 fn add_rating(r: i64, prev: &mut Option<i64>, runs: &mut Vec<i64>, run_length: &mut i64) {
@@ -124,6 +131,236 @@ impl TribEval {
     }
 }
 
+fn count_by_runs(ratings: &[i64], my_device_rating: i64) -> i64 {
+    // Based on a hint from reddit.com/r/AdventOfCode.
+    let runs = find_run_lengths(&bookend(ratings, 0, my_device_rating));
+    let mut result: i64 = 1;
+    let mut te = TribEval::new();
+    for run_len in runs {
+        result *= te.tribonacci(run_len);
+    }
+    result
+}
+
+/// The error for counts which are too large for exact arithmetic.
+fn overflow() -> String {
+    "there are too many arrangements to count exactly; try --modulus".to_string()
+}
+
+/// Counts the arrangements by dynamic programming: the number of
+/// ways to reach each adapter is the sum of the number of ways to
+/// reach the adapters up to 3 jolts below it.
+fn count_by_dp(ratings: &[i64], my_device_rating: i64, arith: Arithmetic) -> Result<u64, String> {
+    let joltages = bookend(ratings, 0, my_device_rating);
+    let mut ways: Vec<u64> = Vec::with_capacity(joltages.len());
+    for (i, joltage) in joltages.iter().enumerate() {
+        if i == 0 {
+            ways.push(1);
+            continue;
+        }
+        let mut total = 0;
+        for j in i.saturating_sub(3)..i {
+            if joltage - joltages[j] <= 3 {
+                total = arith.add(total, ways[j]).ok_or_else(overflow)?;
+            }
+        }
+        ways.push(total);
+    }
+    Ok(*ways.last().unwrap())
+}
+
+/// Counts the arrangements with a transfer matrix.  The state at
+/// joltage j is the number of ways to reach each of j, j - 1 and j -
+/// 2.  Stepping to a joltage which has an adapter sums the three;
+/// stepping to one which has none gives 0 ways to reach it.  A gap
+/// of g jolts between adapters is g - 1 steps of the second kind and
+/// one of the first, and runs of equal gaps (usually long runs of
+/// consecutive adapters) are taken in one go by fast exponentiation.
+fn count_by_matrix(
+    ratings: &[i64],
+    my_device_rating: i64,
+    arith: Arithmetic,
+) -> Result<u64, String> {
+    const ADAPTER: Matrix<3> = [[1, 1, 1], [1, 0, 0], [0, 1, 0]];
+    const NO_ADAPTER: Matrix<3> = [[0, 0, 0], [1, 0, 0], [0, 1, 0]];
+    let joltages = bookend(ratings, 0, my_device_rating);
+    let mut gaps: Vec<(u64, u64)> = Vec::new();
+    for pair in joltages.windows(2) {
+        let gap = (pair[1] - pair[0]) as u64;
+        match gaps.last_mut() {
+            Some((g, count)) if *g == gap => *count += 1,
+            _ => gaps.push((gap, 1)),
+        }
+    }
+    let mut total = mat_identity();
+    for (gap, count) in gaps {
+        if gap == 0 || gap > 3 {
+            return Ok(0);
+        }
+        let step = mat_pow(&NO_ADAPTER, gap - 1, arith)
+            .and_then(|m| mat_mul(&ADAPTER, &m, arith))
+            .ok_or_else(overflow)?;
+        total = mat_pow(&step, count, arith)
+            .and_then(|m| mat_mul(&m, &total, arith))
+            .ok_or_else(overflow)?;
+    }
+    // We start with one way to reach joltage 0.
+    Ok(total[0][0])
+}
+
+/// The ways of counting the arrangements of adapters (part 2).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Algorithm {
+    Runs,
+    Dp,
+    Matrix,
+}
+
+impl fmt::Display for Algorithm {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match self {
+            Algorithm::Runs => "runs",
+            Algorithm::Dp => "dp",
+            Algorithm::Matrix => "matrix",
+        })
+    }
+}
+
+fn algorithms() -> Registry<Algorithm> {
+    Registry::for_day(10)
+        .register(
+            "runs",
+            "multiplies the ways through each run",
+            Algorithm::Runs,
+        )
+        .register("dp", "counts the ways to reach each adapter", Algorithm::Dp)
+        .register(
+            "matrix",
+            "raises a transfer matrix to a power",
+            Algorithm::Matrix,
+        )
+}
+
+/// Describes how each part is solved: counting the sizes of the gaps
+/// between adapters, and then (when the gaps are all of 1 or 3 jolts)
+/// how many ways there are through each run of adapters 1 jolt apart.
+fn explain(ratings: &[i64]) -> Result<Explanation, String> {
+    let mut explanation = Explanation::new();
+    let device = ratings.last().map_or(0, |r| r + 3);
+    let chain = bookend(ratings, 0, device);
+    let gaps: Vec<i64> = chain.windows(2).map(|w| w[1] - w[0]).collect();
+    for size in 1..=3 {
+        let count = gaps.iter().filter(|g| **g == size).count();
+        explanation
+            .step(
+                1,
+                "gaps",
+                format!("there are {} gaps of {} jolts", count, size),
+            )
+            .fact("jolts", size)
+            .fact("count", count);
+    }
+    if let Some(g) = gaps.iter().find(|g| **g < 1 || **g > 3) {
+        explanation
+            .step(
+                1,
+                "impossible",
+                format!("a gap of {} jolts cannot be crossed", g),
+            )
+            .fact("jolts", *g);
+        return Ok(explanation);
+    }
+    let ones = gaps.iter().filter(|g| **g == 1).count();
+    let threes = gaps.iter().filter(|g| **g == 3).count();
+    explanation
+        .step(
+            1,
+            "answer",
+            format!("{} * {} = {}", ones, threes, ones * threes),
+        )
+        .fact("answer", ones * threes);
+
+    if gaps.contains(&2) {
+        let total = count_by_dp(ratings, device, Arithmetic::exact())?;
+        explanation
+            .step(
+                2,
+                "answer",
+                format!(
+                    "since some gaps are of 2 jolts, the runs of adapters 1 jolt apart \
+                     are not independent; counting by dynamic programming gives {}",
+                    total
+                ),
+            )
+            .fact("answer", total as i64);
+        return Ok(explanation);
+    }
+    // Adapters separated by 3 jolts are always needed, so the
+    // arrangements of each run of adapters 1 jolt apart can be chosen
+    // independently.
+    let mut te = TribEval::new();
+    let mut total: i64 = 1;
+    let mut trivial = 0;
+    let mut start = 0;
+    for (i, gap) in gaps.iter().chain(std::iter::once(&3)).enumerate() {
+        if *gap == 1 {
+            continue;
+        }
+        let length = (i - start) as i64;
+        let ways = te.tribonacci(length);
+        if ways == 1 {
+            trivial += 1;
+        } else {
+            explanation
+                .step(
+                    2,
+                    "run",
+                    format!(
+                        "from {} to {} jolts there are {} gaps of 1 jolt, which can be \
+                         crossed in {} ways",
+                        chain[start], chain[i], length, ways
+                    ),
+                )
+                .fact("from", chain[start])
+                .fact("to", chain[i])
+                .fact("gaps", length)
+                .fact("ways", ways);
+            total = total
+                .checked_mul(ways)
+                .ok_or_else(|| "too many arrangements to explain".to_string())?;
+        }
+        start = i + 1;
+    }
+    explanation
+        .step(
+            2,
+            "answer",
+            format!(
+                "the other {} runs can be crossed in only one way, so there are {} arrangements",
+                trivial, total
+            ),
+        )
+        .fact("answer", total);
+    Ok(explanation)
+}
+
+fn write_explanation(
+    explanation: &Explanation,
+    format: explain::Format,
+    out: &mut impl Write,
+) -> Result<(), String> {
+    match format {
+        explain::Format::Text => write!(out, "{}", explanation).map_err(write_error)?,
+        explain::Format::Json => writeln!(
+            out,
+            "{}",
+            serde_json::to_string_pretty(explanation).map_err(|e| e.to_string())?
+        )
+        .map_err(write_error)?,
+    }
+    Ok(())
+}
+
 fn self_test() {
     let mut te = TribEval::new();
     assert_eq!(te.tribonacci(0), 1);
@@ -133,30 +370,162 @@ fn self_test() {
     assert_eq!(te.tribonacci(4), 7);
     assert_eq!(te.tribonacci(5), 13);
     assert_eq!(te.tribonacci(6), 24);
+    let exact = Arithmetic::exact();
+    let examples: &[(&[i64], u64)] = &[
+        (&[16, 10, 15, 5, 1, 11, 7, 19, 6, 12, 4], 8),
+        (
+            &[
+                28, 33, 18, 42, 31, 14, 46, 20, 48, 47, 24, 23, 49, 45, 19, 38, 39, 11, 1, 32, 25,
+                35, 8, 17, 7, 9, 4, 2, 34, 10, 3,
+            ],
+            19208,
+        ),
+    ];
+    for (ratings, expected) in examples {
+        let mut ratings = ratings.to_vec();
+        ratings.sort_unstable();
+        let device = ratings.last().unwrap() + 3;
+        assert_eq!(count_by_runs(&ratings, device), *expected as i64);
+        assert_eq!(count_by_dp(&ratings, device, exact), Ok(*expected));
+        assert_eq!(count_by_matrix(&ratings, device, exact), Ok(*expected));
+        let explanation = explain(&ratings).unwrap();
+        let last = explanation.steps().last().unwrap();
+        assert_eq!(last.facts["answer"], (*expected as i64).into());
+    }
+    let explanation = explain(&[1, 3, 4]).unwrap();
+    assert_eq!(
+        explanation.steps().last().unwrap().facts["answer"],
+        3i64.into()
+    );
+    // Gaps of 2 jolts, and a gap too big to cross.
+    for ratings in &[&[2, 3, 5, 6, 8][..], &[1, 2, 4, 6, 7], &[1, 2, 6, 7]] {
+        assert_eq!(
+            count_by_dp(ratings, ratings.last().unwrap() + 3, exact),
+            count_by_matrix(ratings, ratings.last().unwrap() + 3, exact)
+        );
+    }
+    assert_eq!(count_by_matrix(&[1, 2, 6, 7], 10, exact), Ok(0));
+    // Far too many arrangements to count exactly.
+    let ratings: Vec<i64> = (1..=100_000).collect();
+    let modulo = Arithmetic::modulo(1_000_000_007);
+    assert!(count_by_matrix(&ratings, 100_003, exact).is_err());
+    assert_eq!(
+        count_by_matrix(&ratings, 100_003, modulo),
+        count_by_dp(&ratings, 100_003, modulo)
+    );
 }
 
-fn part2(ratings: &[i64], my_device_rating: i64) -> i64 {
-    // Based on a hint from reddit.com/r/AdventOfCode.
-    let runs = find_run_lengths(&bookend(ratings, 0, my_device_rating));
-    let mut result: i64 = 1;
-    let mut te = TribEval::new();
-    for run_len in runs {
-        result *= te.tribonacci(run_len);
-    }
-    println!("Part 2: answer is {}", result);
-    result
+fn count_arrangements(
+    ratings: &[i64],
+    my_device_rating: i64,
+    algorithm: Algorithm,
+    arith: Arithmetic,
+) -> Result<u64, String> {
+    let result = match algorithm {
+        Algorithm::Runs => {
+            if arith.modulus.is_some() {
+                return Err("the runs algorithm does not support --modulus".to_string());
+            }
+            count_by_runs(ratings, my_device_rating) as u64
+        }
+        Algorithm::Dp => count_by_dp(ratings, my_device_rating, arith)?,
+        Algorithm::Matrix => {
+            let result = count_by_matrix(ratings, my_device_rating, arith)?;
+            let dp_result = count_by_dp(ratings, my_device_rating, arith)?;
+            if result != dp_result {
+                return Err(format!(
+                    "the matrix algorithm found {} arrangements but the DP found {}",
+                    result, dp_result
+                ));
+            }
+            result
+        }
+    };
+    Ok(result)
 }
 
-fn run() -> Result<(), String> {
-    self_test();
-    let ratings = sorted_integer_input()?;
-    let (_, my_device_rating) = part1(&ratings);
-    part2(&ratings, my_device_rating);
+fn part2(
+    ratings: &[i64],
+    my_device_rating: i64,
+    options: &Options,
+    out: &mut impl Write,
+) -> Result<(), String> {
+    let arith = Arithmetic {
+        modulus: options.modulus,
+    };
+    let algorithm = options.algorithm.unwrap_or(Algorithm::Runs);
+    let result = count_arrangements(ratings, my_device_rating, algorithm, arith)?;
+    match options.modulus {
+        Some(m) => {
+            writeln!(out, "Part 2: answer is {} (modulo {})", result, m).map_err(write_error)?
+        }
+        None => writeln!(out, "Part 2: answer is {}", result).map_err(write_error)?,
+    }
     Ok(())
 }
 
+#[derive(Default)]
+struct Options {
+    algorithm: Option<Algorithm>,
+    crosscheck: bool,
+    explain: Option<explain::Format>,
+    modulus: Option<u64>,
+}
+
+fn parse_args() -> Result<Options, String> {
+    let mut options = Options::default();
+    let mut args = std::env::args().skip(1);
+    while let Some(arg) = args.next() {
+        let mut value = || {
+            args.next()
+                .ok_or_else(|| format!("option {} requires an argument", arg))
+        };
+        match arg.as_str() {
+            "crosscheck" => options.crosscheck = true,
+            "--algorithm" => options.algorithm = Some(algorithms().get(&value()?)?.solver),
+            "--explain" => options.explain = Some(explain::Format::Text),
+            "--explain-format" => options.explain = Some(value()?.parse()?),
+            "--modulus" => {
+                let v = value()?;
+                match v.parse() {
+                    Ok(m) if m > 0 => options.modulus = Some(m),
+                    _ => return Err(format!("invalid modulus '{}'", v)),
+                }
+            }
+            _ => return Err(format!("unknown option {}", arg)),
+        }
+    }
+    Ok(options)
+}
+
+fn run(input: impl BufRead, options: &Options, out: &mut impl Write) -> Result<(), String> {
+    let ratings = sorted_integer_input(input)?;
+    if let Some(format) = options.explain {
+        write_explanation(&explain(&ratings)?, format, out)?;
+        if format == explain::Format::Json {
+            return Ok(());
+        }
+    }
+    let (_, my_device_rating) = part1(&ratings, out)?;
+    if options.crosscheck {
+        let arith = Arithmetic {
+            modulus: options.modulus,
+        };
+        let report = algorithms().crosscheck(|algorithm| {
+            count_arrangements(&ratings, my_device_rating, *algorithm, arith)
+        })?;
+        writeln!(out, "{}", report).map_err(write_error)?;
+        return Ok(());
+    }
+    part2(&ratings, my_device_rating, options, out)
+}
+
 fn main() {
-    std::process::exit(match run() {
+    let result = parse_args().and_then(|options| {
+        self_test();
+        run(io::stdin().lock(), &options, &mut io::stdout())
+    });
+    std::process::exit(match result {
         Ok(_) => 0,
         Err(err) => {
             eprintln!("error: {:?}", err);
@@ -164,3 +533,30 @@ fn main() {
         }
     });
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn output(input: &str, options: &Options) -> String {
+        let mut out = Vec::new();
+        run(input.as_bytes(), options, &mut out).unwrap();
+        String::from_utf8(out).unwrap()
+    }
+
+    #[test]
+    fn sample_1() {
+        insta::assert_snapshot!(output(
+            include_str!("../../samples/day10-1.txt"),
+            &Options::default()
+        ));
+    }
+
+    #[test]
+    fn sample_2() {
+        insta::assert_snapshot!(output(
+            include_str!("../../samples/day10-2.txt"),
+            &Options::default()
+        ));
+    }
+}
@@ -1,8 +1,15 @@
+extern crate aor2020;
+#[cfg(test)]
+extern crate insta;
 extern crate itertools;
 
+use aor2020::output::write_error;
+use aor2020::visualization::{render_grid, Canvas, Rgb, GREEN, RED, WHITE};
 use std::fmt;
 use std::io;
 use std::io::BufRead;
+use std::io::Write;
+use std::path::Path;
 use std::string::String;
 
 #[derive(Clone, Copy, Debug)]
@@ -14,6 +21,35 @@ static FLOOR: &str = ".";
 static OCCUPIED: &str = "#";
 static EMPTY: &str = "L";
 
+// The colours of the cells of a seating plan drawn as an image.
+const FLOOR_COLOUR: Rgb = WHITE;
+const EMPTY_COLOUR: Rgb = GREEN;
+const OCCUPIED_COLOUR: Rgb = RED;
+
+fn colour_of(p: &Position) -> Rgb {
+    match p {
+        Position::Seat(true) => OCCUPIED_COLOUR,
+        Position::Seat(false) => EMPTY_COLOUR,
+        Position::Floor => FLOOR_COLOUR,
+    }
+}
+
+/// Finds the kind of position whose colour is closest to `colour`, so
+/// that images need not use exactly the colours of `colour_of`.
+fn position_of_colour(colour: Rgb) -> Position {
+    let distance = |other: Rgb| -> i32 {
+        colour
+            .iter()
+            .zip(other.iter())
+            .map(|(a, b)| (*a as i32 - *b as i32).pow(2))
+            .sum()
+    };
+    *[Position::Floor, Position::Seat(false), Position::Seat(true)]
+        .iter()
+        .min_by_key(|p| distance(colour_of(p)))
+        .unwrap()
+}
+
 #[derive(Debug)]
 struct Direction {
     dx: i64,
@@ -185,7 +221,7 @@ impl Grid {
         let mut next: Vec<Vec<Position>> = Vec::new();
         next.resize_with(self.height(), Vec::new);
         for (y, row) in self.seats.iter().enumerate() {
-            next[y].resize(self.height(), Position::Floor);
+            next[y].resize(self.grid_width, Position::Floor);
             for (x, current) in row.iter().enumerate() {
                 let (p, change) = get_next(
                     current,
@@ -206,6 +242,37 @@ impl Grid {
             changed,
         )
     }
+
+    /// Reads a grid from an image in which each position is a square
+    /// of `scale` pixels; the colour of the pixel in the middle of the
+    /// square decides what it is.
+    fn from_canvas(canvas: &Canvas, scale: usize) -> Result<Grid, String> {
+        if scale == 0 || canvas.width() % scale != 0 || canvas.height() % scale != 0 {
+            return Err(format!(
+                "a {}x{} image is not made of squares {} pixels across",
+                canvas.width(),
+                canvas.height(),
+                scale
+            ));
+        }
+        let grid_width = canvas.width() / scale;
+        let seats = (0..canvas.height() / scale)
+            .map(|y| {
+                (0..grid_width)
+                    .map(|x| {
+                        position_of_colour(canvas.get(x * scale + scale / 2, y * scale + scale / 2))
+                    })
+                    .collect()
+            })
+            .collect();
+        Ok(Grid { seats, grid_width })
+    }
+
+    fn to_canvas(&self, scale: usize) -> Canvas {
+        render_grid(self.grid_width, self.height(), scale, |x, y| {
+            colour_of(&self.seats[y][x])
+        })
+    }
 }
 
 fn read_input(reader: impl BufRead) -> Result<Grid, String> {
@@ -235,7 +302,6 @@ where
     for iteration in 1.. {
         let (next, changed) = current.iterate(occ_counter, overcrowding_limit);
         if !changed {
-            println!("Stable at iteration {}:\n{}", iteration, current);
             return (iteration, current);
         }
         current = next
@@ -243,40 +309,147 @@ where
     unreachable!()
 }
 
-fn solve<OccCounter>(description: &str, initial: &Grid, occ_counter: &OccCounter, limit: usize)
+fn solve<OccCounter>(
+    description: &str,
+    initial: &Grid,
+    occ_counter: &OccCounter,
+    limit: usize,
+    out: &mut impl Write,
+) -> Result<(), String>
 where
     OccCounter: Fn(&Grid, i64, i64) -> usize,
 {
-    println!("{}: initial state:\n{}", description, initial);
-    println!(
+    writeln!(out, "{}: initial state:\n{}", description, initial).map_err(write_error)?;
+    writeln!(
+        out,
         "{}: initial seat occupation is {}",
         description,
         initial.total_occupation()
-    );
+    )
+    .map_err(write_error)?;
     let (iterations, final_grid) = iterate_until_stable(initial, occ_counter, limit);
-    println!(
+    writeln!(out, "Stable at iteration {}:\n{}", iterations, final_grid).map_err(write_error)?;
+    writeln!(
+        out,
         "Done:\n{}\n{}: stable after {} iterations; {} seats are occupied.",
         final_grid,
         description,
         iterations,
         final_grid.total_occupation()
-    );
+    )
+    .map_err(write_error)?;
+    Ok(())
+}
+
+fn read_image(filename: &str, scale: usize) -> Result<Grid, String> {
+    let canvas = Canvas::load(Path::new(filename))?;
+    Grid::from_canvas(&canvas, scale).map_err(|e| format!("{}: {}", filename, e))
+}
+
+fn self_test() -> Result<(), String> {
+    let lines: Vec<String> = ["L.#L", "#..L", "LL.#"]
+        .iter()
+        .map(|s| s.to_string())
+        .collect();
+    let grid = Grid::new(&lines)?;
+    for scale in 1..4 {
+        let mut png: Vec<u8> = Vec::new();
+        grid.to_canvas(scale).write_png(&mut png)?;
+        let mut bmp: Vec<u8> = Vec::new();
+        grid.to_canvas(scale).write_bmp(&mut bmp)?;
+        for (format, canvas) in &[
+            ("PNG", Canvas::read_png(png.as_slice())?),
+            ("BMP", Canvas::read_bmp(bmp.as_slice())?),
+        ] {
+            let decoded = Grid::from_canvas(canvas, scale)?;
+            if decoded.to_string() != grid.to_string() {
+                return Err(format!(
+                    "FAIL: expected\n{}after a round trip through {}, got\n{}",
+                    grid, format, decoded
+                ));
+            }
+        }
+    }
+    if Grid::from_canvas(&grid.to_canvas(2), 3).is_ok() {
+        return Err("FAIL: an image of the wrong size was accepted".to_string());
+    }
+    // Colours which are a little off are good enough.
+    let mut canvas = Canvas::new(3, 1, [250, 240, 245]);
+    canvas.set(1, 0, [60, 140, 20]);
+    canvas.set(2, 0, [140, 0, 0]);
+    let decoded = Grid::from_canvas(&canvas, 1)?;
+    if decoded.to_string() != ".L#\n" {
+        return Err(format!("FAIL: wrong colour matching: {}", decoded));
+    }
+    // The grid need not be square.
+    let (next, _) = grid.iterate(Grid::immediate_neighbours_occupied, 4);
+    if next.seats.iter().any(|row| row.len() != grid.grid_width) {
+        return Err("FAIL: iterate changed the width of the grid".to_string());
+    }
+    Ok(())
 }
 
-fn run() -> Result<(), String> {
-    let initial = read_input(io::BufReader::new(io::stdin()))?;
-    solve("Part 1", &initial, &Grid::immediate_neighbours_occupied, 4);
+#[derive(Default)]
+struct Options {
+    image: Option<String>,
+    scale: Option<usize>,
+}
+
+fn parse_args() -> Result<Options, String> {
+    let mut options = Options::default();
+    let mut args = std::env::args().skip(1);
+    while let Some(arg) = args.next() {
+        let mut value = || {
+            args.next()
+                .ok_or_else(|| format!("option {} requires an argument", arg))
+        };
+        match arg.as_str() {
+            "--image" => options.image = Some(value()?),
+            "--scale" => {
+                let v = value()?;
+                options.scale = Some(
+                    v.parse()
+                        .map_err(|e| format!("invalid value '{}' for {}: {}", v, arg, e))?,
+                );
+            }
+            _ => return Err(format!("unknown option {}", arg)),
+        }
+    }
+    if options.scale.is_some() && options.image.is_none() {
+        return Err("--scale is an option of --image".to_string());
+    }
+    Ok(options)
+}
+
+fn run(input: impl BufRead, options: &Options, out: &mut impl Write) -> Result<(), String> {
+    // With --image, the seating plan is a PNG or BMP image in which
+    // white is floor, green is an empty seat and red an occupied one.
+    let initial = match &options.image {
+        Some(filename) => read_image(filename, options.scale.unwrap_or(1))?,
+        None => read_input(input)?,
+    };
+    solve(
+        "Part 1",
+        &initial,
+        &Grid::immediate_neighbours_occupied,
+        4,
+        out,
+    )?;
     solve(
         "Part 2",
         &initial,
         &Grid::line_of_sight_neighbours_occupied,
         5,
-    );
-    Ok(())
+        out,
+    )
 }
 
 fn main() {
-    std::process::exit(match run() {
+    let result = parse_args().and_then(|options| {
+        self_test()?;
+        run(io::stdin().lock(), &options, &mut io::stdout())
+    });
+    std::process::exit(match result {
         Ok(_) => 0,
         Err(err) => {
             eprintln!("error: {:?}", err);
@@ -284,3 +457,22 @@ fn main() {
         }
     });
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn output(input: &str, options: &Options) -> String {
+        let mut out = Vec::new();
+        run(input.as_bytes(), options, &mut out).unwrap();
+        String::from_utf8(out).unwrap()
+    }
+
+    #[test]
+    fn sample() {
+        insta::assert_snapshot!(output(
+            include_str!("../../samples/day11.txt"),
+            &Options::default()
+        ));
+    }
+}
@@ -1,7 +1,14 @@
+extern crate aor2020;
+#[cfg(test)]
+extern crate insta;
 extern crate itertools;
 
+use aor2020::grid::Grid;
+use aor2020::output::write_error;
+use std::fs;
 use std::io;
 use std::io::BufRead;
+use std::io::Write;
 use std::string::String;
 
 static DIRECTIONS: &[(i64, i64, &str)] = &[
@@ -63,13 +70,129 @@ fn rotate(
     (waypoint_rel_x, waypoint_rel_y)
 }
 
-fn part2(instructions: &Vec<String>) -> Result<(), String> {
+/// An obstacle which the ship ran into.
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Collision {
+    x: i64,
+    y: i64,
+    /// The number of the instruction (from 1) which ran into it.
+    instruction: usize,
+}
+
+fn gcd(a: i64, b: i64) -> i64 {
+    if b == 0 {
+        a
+    } else {
+        gcd(b, a % b)
+    }
+}
+
+/// Moves the ship, checking its path against a map of obstacles (if
+/// there is one).  The top left of the map is at (0, 0), where the
+/// ship starts, and the map repeats in every direction.  Since north
+/// is up, row r of the map is at y = -r.
+struct Navigator<'a> {
+    obstacles: Option<&'a Grid>,
+    stop_at_collision: bool,
+    collisions: Vec<Collision>,
+}
+
+impl Navigator<'_> {
+    fn new(options: &Options) -> Navigator<'_> {
+        Navigator {
+            obstacles: options.obstacles.as_ref(),
+            stop_at_collision: options.stop_at_collision,
+            collisions: Vec::new(),
+        }
+    }
+
+    /// Moves the ship from `pos` by `step`, `times` times, returning
+    /// its new position.  On the way, the ship passes through every
+    /// point with integer coordinates on the straight line it
+    /// follows.  If it stops at an obstacle, it stays at the last
+    /// point before the obstacle.
+    fn travel(
+        &mut self,
+        pos: (i64, i64),
+        step: (i64, i64),
+        times: i64,
+        instruction: usize,
+    ) -> (i64, i64) {
+        let obstacles = match self.obstacles {
+            Some(grid) if step != (0, 0) => grid,
+            _ => return (pos.0 + step.0 * times, pos.1 + step.1 * times),
+        };
+        let (step, times) = if times < 0 {
+            ((-step.0, -step.1), -times)
+        } else {
+            (step, times)
+        };
+        let g = gcd(step.0.abs(), step.1.abs());
+        let unit = (step.0 / g, step.1 / g);
+        let mut pos = pos;
+        for _ in 0..(times * g) {
+            let next = (pos.0 + unit.0, pos.1 + unit.1);
+            if obstacles.is_blocked(next.0, -next.1) {
+                self.collisions.push(Collision {
+                    x: next.0,
+                    y: next.1,
+                    instruction,
+                });
+                if self.stop_at_collision {
+                    return pos;
+                }
+            }
+            pos = next;
+        }
+        pos
+    }
+
+    fn stopped(&self) -> bool {
+        self.stop_at_collision && !self.collisions.is_empty()
+    }
+
+    fn report(
+        &self,
+        part: u32,
+        instructions: &[String],
+        out: &mut impl Write,
+    ) -> Result<(), String> {
+        if self.obstacles.is_none() {
+            return Ok(());
+        }
+        for c in self.collisions.iter() {
+            writeln!(
+                out,
+                "Part {}: {} the obstacle at ({},{}) in instruction {} ({})",
+                part,
+                if self.stop_at_collision {
+                    "stopped before"
+                } else {
+                    "collided with"
+                },
+                c.x,
+                c.y,
+                c.instruction,
+                instructions[c.instruction - 1]
+            )
+            .map_err(write_error)?;
+        }
+        if !self.stop_at_collision {
+            writeln!(out, "Part {}: {} collisions", part, self.collisions.len())
+                .map_err(write_error)?;
+        }
+        Ok(())
+    }
+}
+
+fn part2(instructions: &[String], options: &Options, out: &mut impl Write) -> Result<(), String> {
+    let mut navigator = Navigator::new(options);
     let mut ship_x: i64 = 0;
     let mut ship_y: i64 = 0;
     let mut waypoint_rel_x: i64 = 10;
     let mut waypoint_rel_y: i64 = 1;
 
-    for line in instructions {
+    for (i, line) in instructions.iter().enumerate() {
         let (instruction, amount) = parse_instr(line)?;
         match instruction.as_str() {
             "N" => waypoint_rel_y += amount,
@@ -82,66 +205,160 @@ fn part2(instructions: &Vec<String>) -> Result<(), String> {
                 waypoint_rel_y = rotated.1;
             }
             "F" => {
-                ship_x += waypoint_rel_x * amount;
-                ship_y += waypoint_rel_y * amount;
+                (ship_x, ship_y) = navigator.travel(
+                    (ship_x, ship_y),
+                    (waypoint_rel_x, waypoint_rel_y),
+                    amount,
+                    i + 1,
+                );
             }
             _ => {
                 return Err(format!("unknown instruction: {}", instruction));
             }
         }
-        println!(
+        writeln!(
+            out,
             "After instruction {:>6}, ship position=({:>6},{:>6}), waypoint=({:>3},{:3>})",
             line, ship_x, ship_y, waypoint_rel_x, waypoint_rel_y
-        );
+        )
+        .map_err(write_error)?;
+        if navigator.stopped() {
+            break;
+        }
     }
-    println!(
+    navigator.report(2, instructions, out)?;
+    writeln!(
+        out,
         "Part 2: manhattan distance {}",
         (ship_x.abs() + ship_y.abs())
-    );
+    )
+    .map_err(write_error)?;
     Ok(())
 }
 
-fn part1(instructions: &Vec<String>) -> Result<(), String> {
+fn part1(instructions: &[String], options: &Options, out: &mut impl Write) -> Result<(), String> {
+    let mut navigator = Navigator::new(options);
     let mut heading: i64 = 0;
     let mut x: i64 = 0;
     let mut y: i64 = 0;
-    for line in instructions {
+    for (i, line) in instructions.iter().enumerate() {
         let (instruction, amount) = parse_instr(line)?;
+        let mut travel = |step: (i64, i64)| navigator.travel((x, y), step, amount, i + 1);
         match instruction.as_str() {
-            "N" => y += amount,
-            "E" => x += amount,
-            "S" => y -= amount,
-            "W" => x -= amount,
+            "N" => (x, y) = travel((0, 1)),
+            "E" => (x, y) = travel((1, 0)),
+            "S" => (x, y) = travel((0, -1)),
+            "W" => (x, y) = travel((-1, 0)),
             "R" => heading += amount / 90,
             "L" => heading -= amount / 90,
             "F" => {
                 assert!(heading >= 0);
-                x += DIRECTIONS[heading as usize].0 * amount;
-                y += DIRECTIONS[heading as usize].1 * amount;
+                let (dx, dy, _) = DIRECTIONS[heading as usize];
+                (x, y) = travel((dx, dy));
             }
             _ => {
                 return Err(format!("unknown instruction: {}", instruction));
             }
         }
         heading = normalise_heading(heading);
-        println!(
+        writeln!(
+            out,
             "After instruction {:>6}, position=({:>6},{:>6}), heading={:<5}",
             line, x, y, DIRECTIONS[heading as usize].2
-        );
+        )
+        .map_err(write_error)?;
+        if navigator.stopped() {
+            break;
+        }
     }
-    println!("Part 1: manhattan distance {}", (x.abs() + y.abs()));
+    navigator.report(1, instructions, out)?;
+    writeln!(out, "Part 1: manhattan distance {}", (x.abs() + y.abs())).map_err(write_error)?;
     Ok(())
 }
 
-fn run() -> Result<(), String> {
-    let instructions = read_input(io::BufReader::new(io::stdin()))?;
-    part1(&instructions)?;
-    part2(&instructions)?;
+fn self_test() -> Result<(), String> {
+    aor2020::grid::self_test()?;
+    // Obstacles at (2,0), (0,-1) and (2,-2), repeating every 3 cells.
+    let options = Options {
+        obstacles: Some(Grid::parse("..#\n#..\n..#\n")),
+        stop_at_collision: false,
+    };
+    let mut navigator = Navigator::new(&options);
+    let cases = [
+        ((0, 0), (1, 0), 4, (4, 0)),
+        ((0, 0), (0, -1), 2, (0, -2)),
+        ((0, 0), (1, -1), 2, (2, -2)),
+        ((0, 0), (2, -2), 1, (2, -2)),
+        ((0, 0), (-1, 0), 2, (-2, 0)),
+    ];
+    for (i, (pos, step, times, end)) in cases.iter().enumerate() {
+        if navigator.travel(*pos, *step, *times, i + 1) != *end {
+            return Err(format!("FAIL: travel {} ended in the wrong place", i + 1));
+        }
+    }
+    let hits: Vec<(i64, i64, usize)> = navigator
+        .collisions
+        .iter()
+        .map(|c| (c.x, c.y, c.instruction))
+        .collect();
+    let expected = [(2, 0, 1), (0, -1, 2), (2, -2, 3), (2, -2, 4), (-1, 0, 5)];
+    if hits != expected {
+        return Err(format!("FAIL: wrong collisions {:?}", hits));
+    }
+    let options = Options {
+        stop_at_collision: true,
+        ..options
+    };
+    let mut navigator = Navigator::new(&options);
+    if navigator.travel((0, 0), (1, 0), 4, 1) != (1, 0) || !navigator.stopped() {
+        return Err("FAIL: the ship should stop before the obstacle".to_string());
+    }
     Ok(())
 }
 
+#[derive(Default)]
+struct Options {
+    obstacles: Option<Grid>,
+    stop_at_collision: bool,
+}
+
+fn parse_args() -> Result<Options, String> {
+    let mut options = Options::default();
+    let mut args = std::env::args().skip(1);
+    while let Some(arg) = args.next() {
+        let mut value = || {
+            args.next()
+                .ok_or_else(|| format!("option {} requires an argument", arg))
+        };
+        match arg.as_str() {
+            "--obstacles" => {
+                let filename = value()?;
+                let text = fs::read_to_string(&filename)
+                    .map_err(|e| format!("failed to read {}: {}", filename, e))?;
+                options.obstacles = Some(Grid::parse(&text));
+            }
+            "--stop-at-collision" => options.stop_at_collision = true,
+            _ => return Err(format!("unknown option {}", arg)),
+        }
+    }
+    if options.stop_at_collision && options.obstacles.is_none() {
+        return Err("--stop-at-collision needs a map of --obstacles".to_string());
+    }
+    Ok(options)
+}
+
+fn run(input: impl BufRead, options: &Options, out: &mut impl Write) -> Result<(), String> {
+    let instructions = read_input(input)?;
+    part1(&instructions, options, out)?;
+    part2(&instructions, options, out)
+}
+
 fn main() {
-    std::process::exit(match run() {
+    let result = parse_args().and_then(|options| {
+        self_test()?;
+        run(io::stdin().lock(), &options, &mut io::stdout())
+    });
+    std::process::exit(match result {
         Ok(_) => 0,
         Err(err) => {
             eprintln!("error: {:?}", err);
@@ -149,3 +366,22 @@ fn main() {
         }
     });
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn output(input: &str, options: &Options) -> String {
+        let mut out = Vec::new();
+        run(input.as_bytes(), options, &mut out).unwrap();
+        String::from_utf8(out).unwrap()
+    }
+
+    #[test]
+    fn sample() {
+        insta::assert_snapshot!(output(
+            include_str!("../../samples/day12.txt"),
+            &Options::default()
+        ));
+    }
+}
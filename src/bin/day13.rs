@@ -1,6 +1,16 @@
+extern crate aor2020;
+#[cfg(test)]
+extern crate insta;
 extern crate itertools;
+extern crate serde_json;
+
+use aor2020::algorithm::{self, Algorithm, Registry};
+use aor2020::explain::{self, Explanation};
+use aor2020::modular;
+use aor2020::output::write_error;
 use std::io;
 use std::io::BufRead;
+use std::io::Write;
 use std::string::String;
 use std::vec::Vec;
 
@@ -49,7 +59,7 @@ fn solve1(earliest_departure: i64, bus_ids: &[String]) -> Result<(i64, i64), Str
     }
 }
 
-fn part1(earliest: &i64, bus_ids: &[String]) -> Result<(), String> {
+fn part1(earliest: &i64, bus_ids: &[String], out: &mut impl Write) -> Result<(), String> {
     let (first_bus, departure) = match solve1(*earliest, bus_ids) {
         Err(e) => {
             return Err(e);
@@ -57,14 +67,16 @@ fn part1(earliest: &i64, bus_ids: &[String]) -> Result<(), String> {
         Ok((a, b)) => (a, b),
     };
     let wait = departure - earliest;
-    println!(
+    writeln!(
+        out,
         "Part 1: we depart on bus {} in {} minutes; {}*{} = {}",
         first_bus,
         wait,
         first_bus,
         wait,
         (first_bus * wait)
-    );
+    )
+    .map_err(write_error)?;
     Ok(())
 }
 
@@ -138,12 +150,223 @@ fn solve2(bus_ids: &[String]) -> Result<i64, String> {
     }
 }
 
-fn part2(bus_ids: &[String]) -> Result<(), String> {
-    println!("Part 2: result is {}", solve2(bus_ids)?);
+/// Solves part 2 by suiting the buses one at a time.  Once the
+/// timestamp suits some of the buses, adding the product of their IDs
+/// keeps it that way, so we step by that product until it suits the
+/// next bus too.  Like `crt`, this needs the IDs to be coprime, as they
+/// are in the puzzle.
+fn sieve2(bus_ids: &[String]) -> Result<i64, String> {
+    let buses: Vec<(i64, i64)> = ids_with_positions(bus_ids)?;
+    let mut t = 0;
+    let mut step = 1;
+    for (offset, bus) in buses {
+        // When step and bus are coprime, the first `bus` steps reach
+        // every residue modulo bus.
+        let mut steps = 0;
+        while (t + offset) % bus != 0 {
+            if steps == bus {
+                return Err(format!(
+                    "no timestamp suits bus {} as well as the buses before it",
+                    bus
+                ));
+            }
+            t += step;
+            steps += 1;
+        }
+        step *= bus;
+    }
+    // Like solve2, we want a positive timestamp.
+    Ok(if t == 0 { step } else { t })
+}
+
+type Solver2 = fn(&[String]) -> Result<i64, String>;
+
+fn algorithms() -> Registry<Solver2> {
+    Registry::for_day(13)
+        .register("crt", "the Chinese remainder theorem", solve2 as Solver2)
+        .register("sieve", "steps by the product of the IDs so far", sieve2)
+}
+
+/// The largest number of departures of the bus with the largest ID
+/// which `brute_force2` will consider.
+const VERIFY_LIMIT: i64 = 100_000_000;
+
+/// Solves part 2 by trying each departure time of the bus with the
+/// largest ID in turn and checking the other buses.  Returns the
+/// earliest positive timestamp that works, or None if there is none
+/// within `limit` departures.
+fn brute_force2(bus_ids: &[String], limit: i64) -> Result<Option<i64>, String> {
+    let buses: Vec<(i64, i64)> = ids_with_positions(bus_ids)?;
+    let (offset, largest) = match buses.iter().max_by_key(|(_, bus)| *bus) {
+        Some(b) => *b,
+        None => return Err("there are no buses".to_string()),
+    };
+    for k in 1..=limit {
+        let t = k * largest - offset;
+        if t > 0 && buses.iter().all(|(i, bus)| (t + i) % bus == 0) {
+            return Ok(Some(t));
+        }
+    }
+    Ok(None)
+}
+
+fn part2(
+    bus_ids: &[String],
+    algorithm: &Algorithm<Solver2>,
+    verify: bool,
+    out: &mut impl Write,
+) -> Result<(), String> {
+    let result = (algorithm.solver)(bus_ids)?;
+    writeln!(out, "Part 2: result is {}", result).map_err(write_error)?;
+    if verify {
+        match brute_force2(bus_ids, VERIFY_LIMIT)? {
+            Some(t) if t == result => {
+                writeln!(out, "Part 2: a brute-force search agrees").map_err(write_error)?
+            }
+            Some(t) => {
+                return Err(format!(
+                    "a brute-force search found {}, but the {} algorithm found {}",
+                    t, algorithm.name, result
+                ));
+            }
+            None => writeln!(
+                out,
+                "Part 2: the result is too large to verify with a brute-force search"
+            )
+            .map_err(write_error)?,
+        }
+    }
+    Ok(())
+}
+
+/// Describes how each part is solved: in part 1, when each bus next
+/// leaves, and in part 2, the congruence each bus imposes on the
+/// timestamp and how they combine.
+fn explain(earliest: i64, bus_ids: &[String]) -> Result<Explanation, String> {
+    let mut explanation = Explanation::new();
+    let buses = ids_with_positions(bus_ids)?;
+    for (_, bus) in buses.iter() {
+        let next = (earliest + bus - 1) / bus * bus;
+        explanation
+            .step(
+                1,
+                "departure",
+                format!(
+                    "bus {} next leaves at {}, {} minutes after {}",
+                    bus,
+                    next,
+                    next - earliest,
+                    earliest
+                ),
+            )
+            .fact("bus", *bus)
+            .fact("departs", next)
+            .fact("wait", next - earliest);
+    }
+    let (first_bus, departure) = solve1(earliest, bus_ids)?;
+    explanation
+        .step(
+            1,
+            "choice",
+            format!("bus {} leaves first, at {}", first_bus, departure),
+        )
+        .fact("bus", first_bus)
+        .fact("departs", departure);
+
+    let mut merged: Option<(u64, u64)> = None;
+    for (offset, bus) in buses.iter() {
+        let m = *bus as u64;
+        let r = (-offset).rem_euclid(*bus) as u64;
+        explanation
+            .step(
+                2,
+                "congruence",
+                format!(
+                    "bus {} leaves {} minutes after t, so t = {} (mod {})",
+                    bus, offset, r, m
+                ),
+            )
+            .fact("bus", *bus)
+            .fact("offset", *offset)
+            .fact("residue", r as i64)
+            .fact("modulus", *bus);
+        merged = Some(match merged {
+            None => (r, m),
+            Some((t, product)) => {
+                let (t2, product2) = modular::crt(&[(t, product), (r, m)]).ok_or_else(|| {
+                    format!(
+                        "cannot combine t = {} (mod {}) with t = {} (mod {})",
+                        t, product, r, m
+                    )
+                })?;
+                explanation
+                    .step(
+                        2,
+                        "merge",
+                        format!(
+                            "combining t = {} (mod {}) with t = {} (mod {}) gives t = {} (mod {})",
+                            t, product, r, m, t2, product2
+                        ),
+                    )
+                    .fact("residue", t2 as i64)
+                    .fact("modulus", product2 as i64);
+                (t2, product2)
+            }
+        });
+    }
+    if let Some((t, product)) = merged {
+        // solve2 wants a positive timestamp.
+        let answer = if t == 0 { product } else { t };
+        explanation
+            .step(
+                2,
+                "answer",
+                format!("the earliest positive such timestamp is {}", answer),
+            )
+            .fact("timestamp", answer as i64);
+    }
+    Ok(explanation)
+}
+
+fn write_explanation(
+    explanation: &Explanation,
+    format: explain::Format,
+    out: &mut impl Write,
+) -> Result<(), String> {
+    match format {
+        explain::Format::Text => write!(out, "{}", explanation).map_err(write_error)?,
+        explain::Format::Json => writeln!(
+            out,
+            "{}",
+            serde_json::to_string_pretty(explanation).map_err(|e| e.to_string())?
+        )
+        .map_err(write_error)?,
+    }
+    Ok(())
+}
+
+fn self_test_explain() -> Result<(), String> {
+    explain::self_test()?;
+    let bus_ids: Vec<String> = "7,13,x,x,59,x,31,19"
+        .split(',')
+        .map(|s| s.to_string())
+        .collect();
+    let explanation = explain(939, &bus_ids)?;
+    let last = |part: u32| explanation.steps().iter().rfind(|s| s.part == part);
+    match last(1) {
+        Some(step) if step.text == "bus 59 leaves first, at 944" => (),
+        other => return Err(format!("FAIL: part 1 explanation ended with {:?}", other)),
+    }
+    match last(2) {
+        Some(step) if step.facts.get("timestamp") == Some(&1068781i64.into()) => (),
+        other => return Err(format!("FAIL: part 2 explanation ended with {:?}", other)),
+    }
     Ok(())
 }
 
 fn self_test() -> Result<(), String> {
+    algorithm::self_test()?;
+    self_test_explain()?;
     let cases: &[(&str, &str, i64)] = &[
         ("example-0", "7,13,x,x,59,x,31,19", 1068781),
         ("example-1", "17,x,13,19", 3417),
@@ -154,11 +377,20 @@ fn self_test() -> Result<(), String> {
     ];
     fn run_test_case(label: &str, input: &str, expected: i64) -> Result<(), String> {
         let id_list: Vec<String> = input.split(',').map(|s| s.to_string()).collect();
-        let got: i64 = solve2(&id_list)?;
-        if got != expected {
+        for algorithm in algorithms().algorithms() {
+            let got: i64 = (algorithm.solver)(&id_list)?;
+            if got != expected {
+                return Err(format!(
+                    "FAIL: {}: for input {}, expected {} but the {} algorithm got {}",
+                    label, input, expected, algorithm.name, got
+                ));
+            }
+        }
+        let searched = brute_force2(&id_list, VERIFY_LIMIT)?;
+        if searched != Some(expected) {
             return Err(format!(
-                "FAIL: {}: for input {}, expected {} but got {}",
-                label, input, expected, got
+                "FAIL: {}: for input {}, expected {} but brute force found {:?}",
+                label, input, expected, searched
             ));
         }
         Ok(())
@@ -201,16 +433,64 @@ fn read_input(reader: impl BufRead) -> Result<(i64, Vec<String>), String> {
     Ok((earliest, result))
 }
 
-fn run() -> Result<(), String> {
-    self_test()?;
-    let (earliest, bus_ids) = read_input(io::BufReader::new(io::stdin()))?;
-    part1(&earliest, &bus_ids)?;
-    part2(&bus_ids)?;
-    Ok(())
+#[derive(Default)]
+struct Options {
+    algorithm: Option<String>,
+    crosscheck: bool,
+    explain: Option<explain::Format>,
+    verify: bool,
+}
+
+fn parse_args() -> Result<Options, String> {
+    let mut options = Options::default();
+    let mut args = std::env::args().skip(1);
+    while let Some(arg) = args.next() {
+        let mut value = || {
+            args.next()
+                .ok_or_else(|| format!("option {} requires an argument", arg))
+        };
+        match arg.as_str() {
+            "crosscheck" => options.crosscheck = true,
+            "--algorithm" => options.algorithm = Some(value()?),
+            "--explain" => options.explain = Some(explain::Format::Text),
+            "--explain-format" => options.explain = Some(value()?.parse()?),
+            "--verify" => options.verify = true,
+            _ => return Err(format!("unknown option {}", arg)),
+        }
+    }
+    Ok(options)
+}
+
+fn run(input: impl BufRead, options: &Options, out: &mut impl Write) -> Result<(), String> {
+    let algorithms = algorithms();
+    let algorithm = algorithms.get(options.algorithm.as_deref().unwrap_or("crt"))?;
+    let (earliest, bus_ids) = read_input(input)?;
+    if options.crosscheck {
+        part1(&earliest, &bus_ids, out)?;
+        writeln!(
+            out,
+            "Part 2:\n{}",
+            algorithms.crosscheck(|solver| solver(&bus_ids))?
+        )
+        .map_err(write_error)?;
+        return Ok(());
+    }
+    if let Some(format) = options.explain {
+        write_explanation(&explain(earliest, &bus_ids)?, format, out)?;
+        if format == explain::Format::Json {
+            return Ok(());
+        }
+    }
+    part1(&earliest, &bus_ids, out)?;
+    part2(&bus_ids, algorithm, options.verify, out)
 }
 
 fn main() {
-    std::process::exit(match run() {
+    let result = parse_args().and_then(|options| {
+        self_test()?;
+        run(io::stdin().lock(), &options, &mut io::stdout())
+    });
+    std::process::exit(match result {
         Ok(_) => 0,
         Err(err) => {
             eprintln!("error: {:?}", err);
@@ -218,3 +498,22 @@ fn main() {
         }
     });
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn output(input: &str, options: &Options) -> String {
+        let mut out = Vec::new();
+        run(input.as_bytes(), options, &mut out).unwrap();
+        String::from_utf8(out).unwrap()
+    }
+
+    #[test]
+    fn sample() {
+        insta::assert_snapshot!(output(
+            include_str!("../../samples/day13.txt"),
+            &Options::default()
+        ));
+    }
+}
@@ -1,148 +1,748 @@
-extern crate lazy_static;
-extern crate regex;
+extern crate aor2020;
+#[cfg(test)]
+extern crate insta;
+extern crate serde;
+extern crate serde_json;
 
-use lazy_static::lazy_static;
-use regex::Regex;
+use aor2020::day14::{AddressSet, Mask, WORD_MAX};
+use aor2020::output::write_error;
+use serde::{Deserialize, Serialize};
 use std::collections::BTreeMap;
+use std::fmt;
+use std::fs::File;
 use std::io;
 use std::io::BufRead;
+use std::io::Write;
+use std::str::FromStr;
 
-lazy_static! {
-    static ref SETMASK_RE: Regex = Regex::new(r"mask = ([01X]{36})$").unwrap();
-    // Sigh, a character class in Rust cannot just contain ], it must be escaped.
-    static ref STORE_RE: Regex = Regex::new(
-    r"mem[\[]([0-9]+)[\]] = ([0-9]+)$").unwrap();
+/// The version of the decoder chip, which determines what a store does.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Decoder {
+    /// Version 1: the mask modifies the value being written.
+    V1,
+    /// Version 2: the mask modifies (and may fan out) the address.
+    V2,
+}
+
+impl fmt::Display for Decoder {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match self {
+            Decoder::V1 => "v1",
+            Decoder::V2 => "v2",
+        })
+    }
+}
+
+impl FromStr for Decoder {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Decoder, String> {
+        match s {
+            "v1" => Ok(Decoder::V1),
+            "v2" => Ok(Decoder::V2),
+            _ => Err(format!("unknown decoder version '{}'", s)),
+        }
+    }
+}
+
+enum Value {
+    Immediate(i64),
+    Register(u32),
 }
 
 enum Operation {
-    SetMask(String),
-    Store(i64, i64),
+    SetMask(Mask),
+    /// mem[addr] = value
+    Store(i64, Value),
+    /// rN = mem[addr]
+    Load(u32, i64),
+    /// skip count if rN == value (or != value)
+    SkipIf {
+        count: usize,
+        reg: u32,
+        equal: bool,
+        value: i64,
+    },
+    /// Print the current sum of memory.
+    Sum,
+}
+
+#[derive(Debug, PartialEq)]
+struct ParseError {
+    line: usize,
+    column: usize,
+    reason: String,
 }
 
-fn parse_line(s: &str) -> Result<Operation, String> {
-    match SETMASK_RE.captures(s) {
-        Some(caps) => Ok(Operation::SetMask(caps[1].to_string())),
-        None => match STORE_RE.captures(s) {
-            Some(caps) => Ok(Operation::Store(
-                caps[1].parse().expect("invalid address"),
-                caps[2].parse().expect("invalid value"),
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "line {}, column {}: {}",
+            self.line, self.column, self.reason
+        )
+    }
+}
+
+/// Parses a single line of a docking program, keeping track of where
+/// we are so that errors can point at the offending text.
+struct LineParser<'a> {
+    line: usize,
+    text: &'a str,
+    pos: usize,
+}
+
+impl<'a> LineParser<'a> {
+    fn new(line: usize, text: &'a str) -> LineParser<'a> {
+        // Anything after a '#' is a comment.
+        let text = match text.find('#') {
+            Some(n) => &text[0..n],
+            None => text,
+        };
+        LineParser { line, text, pos: 0 }
+    }
+
+    fn error_at(&self, pos: usize, reason: String) -> ParseError {
+        ParseError {
+            line: self.line,
+            column: pos + 1,
+            reason,
+        }
+    }
+
+    fn rest(&self) -> &'a str {
+        &self.text[self.pos..]
+    }
+
+    fn skip_space(&mut self) {
+        let rest = self.rest();
+        self.pos += rest.len() - rest.trim_start().len();
+    }
+
+    fn at_end(&mut self) -> bool {
+        self.skip_space();
+        self.pos == self.text.len()
+    }
+
+    fn lookahead(&mut self, s: &str) -> bool {
+        self.skip_space();
+        self.rest().starts_with(s)
+    }
+
+    fn expect(&mut self, s: &str) -> Result<(), ParseError> {
+        if self.lookahead(s) {
+            self.pos += s.len();
+            Ok(())
+        } else {
+            Err(self.error_at(self.pos, format!("expected '{}'", s)))
+        }
+    }
+
+    fn expect_end(&mut self) -> Result<(), ParseError> {
+        if self.at_end() {
+            Ok(())
+        } else {
+            Err(self.error_at(
+                self.pos,
+                format!("unexpected trailing text '{}'", self.rest()),
+            ))
+        }
+    }
+
+    fn word(&mut self) -> (usize, &'a str) {
+        self.skip_space();
+        let start = self.pos;
+        let rest = self.rest();
+        let len = rest
+            .find(|c: char| !c.is_ascii_alphanumeric())
+            .unwrap_or(rest.len());
+        self.pos += len;
+        (start, &rest[0..len])
+    }
+
+    fn number(&mut self, what: &str, max: i64) -> Result<i64, ParseError> {
+        let (start, digits) = self.word();
+        if digits.is_empty() || !digits.chars().all(|c| c.is_ascii_digit()) {
+            return Err(self.error_at(start, format!("expected {}", what)));
+        }
+        match digits.parse::<i64>() {
+            Ok(n) if n <= max => Ok(n),
+            _ => Err(self.error_at(
+                start,
+                format!("{} {} is out of range (maximum is {})", what, digits, max),
             )),
-            None => Err(format!("unrecognised line: '{}'", s)),
-        },
+        }
+    }
+
+    fn register(&mut self) -> Result<u32, ParseError> {
+        let (start, word) = self.word();
+        match word.strip_prefix('r').map(|n| n.parse::<u32>()) {
+            Some(Ok(reg)) => Ok(reg),
+            _ => Err(self.error_at(start, format!("expected a register, got '{}'", word))),
+        }
+    }
+
+    fn value(&mut self) -> Result<Value, ParseError> {
+        if self.lookahead("r") {
+            Ok(Value::Register(self.register()?))
+        } else {
+            Ok(Value::Immediate(self.number("value", WORD_MAX)?))
+        }
+    }
+
+    fn address(&mut self) -> Result<i64, ParseError> {
+        self.expect("mem[")?;
+        let addr = self.number("address", WORD_MAX)?;
+        self.expect("]")?;
+        Ok(addr)
     }
+
+    fn mask(&mut self) -> Result<Mask, ParseError> {
+        let (start, mask) = self.word();
+        if let Some(n) = mask.find(|c| !matches!(c, '0' | '1' | 'X')) {
+            return Err(self.error_at(
+                start + n,
+                format!("invalid mask character '{}'", &mask[n..n + 1]),
+            ));
+        }
+        mask.parse().map_err(|e| self.error_at(start, e))
+    }
+
+    /// Returns None for blank lines and comments.
+    fn operation(&mut self) -> Result<Option<Operation>, ParseError> {
+        if self.at_end() {
+            return Ok(None);
+        }
+        let op = if self.lookahead("mask") {
+            self.expect("mask")?;
+            self.expect("=")?;
+            Operation::SetMask(self.mask()?)
+        } else if self.lookahead("mem[") {
+            let addr = self.address()?;
+            self.expect("=")?;
+            Operation::Store(addr, self.value()?)
+        } else if self.lookahead("skip") {
+            self.expect("skip")?;
+            let count = self.number("skip count", WORD_MAX)? as usize;
+            self.expect("if")?;
+            let reg = self.register()?;
+            let equal = if self.lookahead("==") {
+                self.expect("==")?;
+                true
+            } else {
+                self.expect("!=")?;
+                false
+            };
+            let value = self.number("value", WORD_MAX)?;
+            Operation::SkipIf {
+                count,
+                reg,
+                equal,
+                value,
+            }
+        } else if self.lookahead("sum") {
+            self.expect("sum")?;
+            Operation::Sum
+        } else if self.lookahead("r") {
+            let reg = self.register()?;
+            self.expect("=")?;
+            Operation::Load(reg, self.address()?)
+        } else {
+            return Err(self.error_at(
+                self.pos,
+                format!("unrecognised operation '{}'", self.rest()),
+            ));
+        };
+        self.expect_end()?;
+        Ok(Some(op))
+    }
+}
+
+fn parse_line(line: usize, s: &str) -> Result<Option<Operation>, ParseError> {
+    LineParser::new(line, s).operation()
+}
+
+/// The state of the docking computer: the most recently set mask
+/// (if any), the registers, and the contents of memory.  This is what
+/// we save and load, so that a long program can be resumed.
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct MachineState {
+    mask: Option<String>,
+    #[serde(default)]
+    registers: BTreeMap<u32, i64>,
+    memory: BTreeMap<i64, i64>,
 }
 
-fn execute_v2<'a, OPZ>(operations: OPZ) -> Result<i64, String>
-where
-    OPZ: Iterator<Item = &'a Operation>,
-{
-    let mut data: BTreeMap<i64, i64> = BTreeMap::new();
-    let mut or_mask: i64 = 0;
-    let mut float_mask: i64 = 0;
-    for op in operations {
+impl MachineState {
+    fn sum(&self) -> i64 {
+        self.memory.values().sum()
+    }
+
+    fn register(&self, reg: u32) -> i64 {
+        self.registers.get(&reg).copied().unwrap_or(0)
+    }
+
+    fn value(&self, v: &Value) -> i64 {
+        match v {
+            Value::Immediate(n) => *n,
+            Value::Register(reg) => self.register(*reg),
+        }
+    }
+}
+
+/// Machine state for both decoder chip versions.
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct SavedState {
+    v1: MachineState,
+    v2: MachineState,
+}
+
+fn store_v2(state: &mut MachineState, mask: &Mask, addr: i64, val: i64) {
+    for loc in mask.addresses(addr) {
+        state.memory.insert(loc, val);
+    }
+}
+
+fn store_v1(state: &mut MachineState, mask: &Mask, addr: i64, val: i64) {
+    state.memory.insert(addr, mask.apply(val));
+}
+
+/// A machine executes the program using the store semantics of a
+/// specific decoder chip.
+struct Machine<'a> {
+    decoder: Decoder,
+    state: &'a mut MachineState,
+    mask: Mask,
+}
+
+impl<'a> Machine<'a> {
+    fn new(decoder: Decoder, state: &'a mut MachineState) -> Result<Machine<'a>, String> {
+        let mask: Mask = match &state.mask {
+            Some(s) => s.parse()?,
+            None => Mask::default(),
+        };
+        Ok(Machine {
+            decoder,
+            state,
+            mask,
+        })
+    }
+
+    /// Performs a single operation, returning the number of following
+    /// operations to skip.
+    fn perform(&mut self, op: &Operation, out: &mut impl Write) -> Result<usize, String> {
+        let state = &mut self.state;
         match op {
-            Operation::SetMask(s) => {
-                or_mask = 0;
-                float_mask = 0;
-                for (bitpos, ch) in s.chars().rev().enumerate() {
-                    let bit = 1 << bitpos;
-                    match ch {
-                        'X' => float_mask |= bit,
-                        '1' => or_mask |= bit,
-                        '0' => (),
-                        _ => {
-                            panic!("v2: unexpected character '{}'", ch);
-                        }
-                    }
-                }
+            Operation::SetMask(m) => {
+                self.mask = *m;
+                state.mask = Some(m.to_string());
             }
-            Operation::Store(addr, val) => {
-                let mut locations: Vec<i64> = vec![addr | or_mask];
-                locations.reserve(72);
-                for bitnum in 0..=36 {
-                    let mask = 1 << bitnum;
-                    if (float_mask & mask) != 0 {
-                        for i in 0..locations.len() {
-                            let old = locations[i];
-                            locations[i] |= mask; // X turns to 1
-                            locations.push(old & !mask); // X turns to 0
-                        }
-                    }
+            Operation::Store(addr, v) => {
+                let val = state.value(v);
+                match self.decoder {
+                    Decoder::V1 => store_v1(state, &self.mask, *addr, val),
+                    Decoder::V2 => store_v2(state, &self.mask, *addr, val),
                 }
-                for loc in locations {
-                    data.insert(loc, *val);
+            }
+            Operation::Load(reg, addr) => {
+                let val = state.memory.get(addr).copied().unwrap_or(0);
+                state.registers.insert(*reg, val);
+            }
+            Operation::SkipIf {
+                count,
+                reg,
+                equal,
+                value,
+            } => {
+                if (state.register(*reg) == *value) == *equal {
+                    return Ok(*count);
                 }
             }
+            Operation::Sum => {
+                writeln!(out, "{}: sum = {}", self.decoder, state.sum()).map_err(write_error)?;
+            }
         }
+        Ok(0)
     }
-    Ok(data.values().sum())
 }
 
-fn execute_v1<'a, OPZ>(operations: OPZ) -> Result<i64, String>
-where
-    OPZ: Iterator<Item = &'a Operation>,
-{
-    let mut data: BTreeMap<i64, i64> = BTreeMap::new();
-    let mut or_mask: i64 = 0;
-    let mut and_mask: i64 = 0;
-    for op in operations {
+/// Run the program once, on all the machines in lock-step.  The
+/// machines must agree about which operations are skipped.
+fn execute_together(
+    machines: &mut [Machine],
+    program: &[Operation],
+    out: &mut impl Write,
+) -> Result<(), String> {
+    let mut pc: usize = 0;
+    while let Some(op) = program.get(pc) {
+        let skips = machines
+            .iter_mut()
+            .map(|m| m.perform(op, out))
+            .collect::<Result<Vec<usize>, String>>()?;
+        if skips.windows(2).any(|w| w[0] != w[1]) {
+            return Err(format!(
+                "the decoders disagree about whether to skip after instruction {}",
+                pc + 1
+            ));
+        }
+        pc += 1 + skips.first().copied().unwrap_or(0);
+    }
+    Ok(())
+}
+
+/// Run the program, using the store semantics of the specified
+/// decoder chip, and return the sum of the values left in memory.
+fn execute(
+    decoder: Decoder,
+    state: &mut MachineState,
+    program: &[Operation],
+    out: &mut impl Write,
+) -> Result<i64, String> {
+    execute_together(&mut [Machine::new(decoder, state)?], program, out)?;
+    Ok(state.sum())
+}
+
+/// Run the program once for both decoder chips, sharing the parsed
+/// program and its masks, and return the two sums.
+fn execute_both(
+    state: &mut SavedState,
+    program: &[Operation],
+    out: &mut impl Write,
+) -> Result<(i64, i64), String> {
+    execute_together(
+        &mut [
+            Machine::new(Decoder::V1, &mut state.v1)?,
+            Machine::new(Decoder::V2, &mut state.v2)?,
+        ],
+        program,
+        out,
+    )?;
+    Ok((state.v1.sum(), state.v2.sum()))
+}
+
+/// What a store does with the version 2 decoder.
+#[derive(Debug, PartialEq, Eq)]
+struct StoreAnalysis {
+    addr: i64,
+    mask: Mask,
+    /// The number of addresses the store writes.
+    touched: u64,
+    /// The number of those which no earlier store wrote.
+    new: u64,
+    /// The number of those which no later store overwrites.
+    surviving: u64,
+}
+
+/// Works out how many addresses each store writes with the version 2
+/// decoder, and how many distinct addresses are written in total,
+/// without listing the addresses.  Since the value of a register
+/// depends on the contents of memory, the program may not contain
+/// loads or skips.
+fn analyse_stores(program: &[Operation]) -> Result<(Vec<StoreAnalysis>, u64), String> {
+    let mut mask = Mask::default();
+    let mut stores: Vec<(i64, Mask)> = Vec::new();
+    for op in program {
         match op {
-            Operation::SetMask(s) => {
-                and_mask = 0;
-                or_mask = 0;
-                for (bitpos, ch) in s.chars().rev().enumerate() {
-                    let bit = 1 << bitpos;
-                    match ch {
-                        'X' => {
-                            and_mask |= bit;
-                        }
-                        '1' => {
-                            or_mask |= bit;
-                            and_mask |= bit;
-                        }
-                        '0' => (),
-                        _ => {
-                            panic!("v1: unexpected character '{}'", ch);
-                        }
-                    }
-                }
-            }
-            Operation::Store(addr, val) => {
-                let v = (val & and_mask) | or_mask;
-                data.insert(*addr, v);
+            Operation::SetMask(m) => mask = *m,
+            Operation::Store(addr, _) => stores.push((*addr, mask)),
+            Operation::Sum => (),
+            Operation::Load(..) | Operation::SkipIf { .. } => {
+                return Err("programs containing loads or skips cannot be analysed".to_string());
             }
         }
     }
-    Ok(data.values().sum())
+    let mut written = AddressSet::new();
+    let new: Vec<u64> = stores
+        .iter()
+        .map(|(addr, mask)| written.insert(mask.address_pattern(*addr)))
+        .collect();
+    // Working backwards, the addresses a store adds are those which
+    // no later store overwrites.
+    let mut overwritten = AddressSet::new();
+    let mut surviving: Vec<u64> = stores
+        .iter()
+        .rev()
+        .map(|(addr, mask)| overwritten.insert(mask.address_pattern(*addr)))
+        .collect();
+    surviving.reverse();
+    let analyses = stores
+        .iter()
+        .zip(new.iter().zip(surviving.iter()))
+        .map(|((addr, mask), (new, surviving))| StoreAnalysis {
+            addr: *addr,
+            mask: *mask,
+            touched: mask.address_count(),
+            new: *new,
+            surviving: *surviving,
+        })
+        .collect();
+    Ok((analyses, written.len()))
+}
+
+fn report_analysis(
+    analyses: &[StoreAnalysis],
+    total: u64,
+    out: &mut impl Write,
+) -> Result<(), String> {
+    for (i, a) in analyses.iter().enumerate() {
+        writeln!(
+            out,
+            "Analysis: store {} (to {} through mask {}) writes {} addresses, \
+             {} new, {} not overwritten later",
+            i + 1,
+            a.addr,
+            a.mask,
+            a.touched,
+            a.new,
+            a.surviving
+        )
+        .map_err(write_error)?;
+    }
+    writeln!(
+        out,
+        "Analysis: {} stores write {} addresses, of which {} are distinct",
+        analyses.len(),
+        analyses.iter().map(|a| a.touched).sum::<u64>(),
+        total
+    )
+    .map_err(write_error)
 }
 
 fn read_input(reader: impl BufRead) -> Result<Vec<Operation>, String> {
     let mut ops: Vec<Operation> = Vec::new();
-    for thing in reader.lines() {
+    for (lineno, thing) in reader.lines().enumerate() {
         match thing {
             Err(e) => return Err(format!("I/O error: {}", e)),
-            Ok(line) => ops.push(parse_line(&line)?),
+            Ok(line) => {
+                if let Some(op) = parse_line(lineno + 1, &line).map_err(|e| e.to_string())? {
+                    ops.push(op);
+                }
+            }
         }
     }
     Ok(ops)
 }
 
-fn run() -> Result<(), String> {
-    let operations =
-        read_input(io::BufReader::new(io::stdin())).expect("unable to read operations list");
-    println!(
-        "Part 1: sum = {}",
-        execute_v1(operations.iter()).expect("part 1 execute failed")
-    );
-    println!(
-        "Part 2: sum = {}",
-        execute_v2(operations.iter()).expect("part 2 execute failed")
-    );
+fn self_test() -> Result<(), String> {
+    aor2020::day14::self_test()?;
+    let mask = "XXXXXXXXXXXXXXXXXXXXXXXXXXXXX1XXXX0X";
+    let good: &[&str] = &[
+        "",
+        "   ",
+        "# a comment",
+        "mask = XXXXXXXXXXXXXXXXXXXXXXXXXXXXX1XXXX0X",
+        "mem[8] = 11 # trailing comment",
+        "mem[68719476735] = 68719476735",
+        "r0 = mem[7]",
+        "mem[7] = r12",
+        "skip 2 if r0 != 4",
+        "sum",
+    ];
+    for input in good {
+        if let Err(e) = parse_line(1, input) {
+            return Err(format!("FAIL: failed to parse '{}': {}", input, e));
+        }
+    }
+    let bad: Vec<(String, usize, &str)> = vec![
+        (
+            format!("mask = {}Y", &mask[1..]),
+            43,
+            "invalid mask character 'Y'",
+        ),
+        (
+            format!("mask = {}", &mask[1..]),
+            8,
+            "mask has 35 bits but should have 36",
+        ),
+        (
+            format!("mask = {}0", mask),
+            8,
+            "mask has 37 bits but should have 36",
+        ),
+        ("mask 0".to_string(), 6, "expected '='"),
+        (
+            "mem[68719476736] = 1".to_string(),
+            5,
+            "address 68719476736 is out of range (maximum is 68719476735)",
+        ),
+        (
+            "mem[1] = 99999999999999999999".to_string(),
+            10,
+            "value 99999999999999999999 is out of range (maximum is 68719476735)",
+        ),
+        ("mem[-1] = 4".to_string(), 5, "expected address"),
+        ("mem[1 = 4".to_string(), 7, "expected ']'"),
+        (
+            "mem[1] = 4 5".to_string(),
+            12,
+            "unexpected trailing text '5'",
+        ),
+        (
+            "rx = mem[1]".to_string(),
+            1,
+            "expected a register, got 'rx'",
+        ),
+        ("skip 1 if r0 < 4".to_string(), 14, "expected '!='"),
+        ("jump 4".to_string(), 1, "unrecognised operation 'jump 4'"),
+    ];
+    for (input, column, reason) in bad.iter() {
+        let expected = ParseError {
+            line: 3,
+            column: *column,
+            reason: reason.to_string(),
+        };
+        match parse_line(3, input) {
+            Ok(_) => {
+                return Err(format!("FAIL: '{}' should not have parsed", input));
+            }
+            Err(e) if e != expected => {
+                return Err(format!(
+                    "FAIL: for '{}' expected error '{}', got '{}'",
+                    input, expected, e
+                ));
+            }
+            Err(_) => (),
+        }
+    }
+    let program: Vec<Operation> = [
+        "mask = 000000000000000000000000000000X1001X",
+        "mem[42] = 100",
+        "mask = 00000000000000000000000000000000X0XX",
+        "mem[26] = 1",
+        "mem[18] = 7",
+    ]
+    .iter()
+    .enumerate()
+    .filter_map(|(i, line)| parse_line(i + 1, line).transpose())
+    .collect::<Result<Vec<Operation>, ParseError>>()
+    .map_err(|e| e.to_string())?;
+    let (analyses, total) = analyse_stores(&program)?;
+    let counts: Vec<(u64, u64, u64)> = analyses
+        .iter()
+        .map(|a| (a.touched, a.new, a.surviving))
+        .collect();
+    if counts != [(4, 4, 2), (8, 6, 0), (8, 0, 8)] || total != 10 {
+        return Err(format!(
+            "FAIL: wrong analysis {:?} ({} in total)",
+            counts, total
+        ));
+    }
+    let mut state = MachineState::default();
+    execute(Decoder::V2, &mut state, &program, &mut io::sink())?;
+    if state.memory.len() as u64 != total {
+        return Err(format!(
+            "FAIL: analysis found {} addresses, execution {}",
+            total,
+            state.memory.len()
+        ));
+    }
+    Ok(())
+}
+
+/// Write the memory contents of both machines as hex (address, value) pairs.
+fn dump_memory(filename: &str, state: &SavedState) -> Result<(), String> {
+    let write = || -> io::Result<()> {
+        let mut f = File::create(filename)?;
+        for (label, machine) in &[("v1", &state.v1), ("v2", &state.v2)] {
+            writeln!(f, "# {}", label)?;
+            for (addr, val) in machine.memory.iter() {
+                writeln!(f, "{:09x} {:09x}", addr, val)?;
+            }
+        }
+        Ok(())
+    };
+    write().map_err(|e| format!("failed to write {}: {}", filename, e))
+}
+
+fn load_state(filename: &str) -> Result<SavedState, String> {
+    let f = File::open(filename).map_err(|e| format!("failed to open {}: {}", filename, e))?;
+    serde_json::from_reader(io::BufReader::new(f))
+        .map_err(|e| format!("failed to load state from {}: {}", filename, e))
+}
+
+fn save_state(filename: &str, state: &SavedState) -> Result<(), String> {
+    let f = File::create(filename).map_err(|e| format!("failed to create {}: {}", filename, e))?;
+    serde_json::to_writer(io::BufWriter::new(f), state)
+        .map_err(|e| format!("failed to save state to {}: {}", filename, e))
+}
+
+#[derive(Default)]
+struct Options {
+    analyse: bool,
+    decoders: Vec<Decoder>,
+    dump_mem: Option<String>,
+    load_state: Option<String>,
+    save_state: Option<String>,
+    single_pass: bool,
+}
+
+fn parse_args() -> Result<Options, String> {
+    let mut options = Options::default();
+    let mut args = std::env::args().skip(1);
+    while let Some(arg) = args.next() {
+        let mut value = || {
+            args.next()
+                .ok_or_else(|| format!("option {} requires an argument", arg))
+        };
+        match arg.as_str() {
+            "--analyse" => options.analyse = true,
+            "--decoder" => options.decoders.push(value()?.parse()?),
+            "--dump-mem" => options.dump_mem = Some(value()?),
+            "--load-state" => options.load_state = Some(value()?),
+            "--save-state" => options.save_state = Some(value()?),
+            "--single-pass" => options.single_pass = true,
+            _ => return Err(format!("unknown option {}", arg)),
+        }
+    }
+    Ok(options)
+}
+
+fn run(input: impl BufRead, options: &Options, out: &mut impl Write) -> Result<(), String> {
+    let operations = read_input(input)?;
+    if options.analyse {
+        let (analyses, total) = analyse_stores(&operations)?;
+        return report_analysis(&analyses, total, out);
+    }
+    let mut state = match &options.load_state {
+        Some(filename) => load_state(filename)?,
+        None => SavedState::default(),
+    };
+    if options.single_pass {
+        if !options.decoders.is_empty() {
+            return Err("--single-pass always uses both decoders".to_string());
+        }
+        let (sum1, sum2) = execute_both(&mut state, &operations, out)?;
+        writeln!(out, "Part 1: sum = {}", sum1).map_err(write_error)?;
+        writeln!(out, "Part 2: sum = {}", sum2).map_err(write_error)?;
+    }
+    let decoders = if options.single_pass || !options.decoders.is_empty() {
+        options.decoders.clone()
+    } else {
+        vec![Decoder::V1, Decoder::V2]
+    };
+    for decoder in decoders {
+        let (part, machine) = match decoder {
+            Decoder::V1 => (1, &mut state.v1),
+            Decoder::V2 => (2, &mut state.v2),
+        };
+        let sum = execute(decoder, machine, &operations, out)
+            .map_err(|e| format!("part {} execute failed: {}", part, e))?;
+        writeln!(out, "Part {}: sum = {}", part, sum).map_err(write_error)?;
+    }
+    if let Some(filename) = &options.dump_mem {
+        dump_memory(filename, &state)?;
+    }
+    if let Some(filename) = &options.save_state {
+        save_state(filename, &state)?;
+    }
     Ok(())
 }
 
 fn main() {
-    std::process::exit(match run() {
+    let result = self_test()
+        .and_then(|_| parse_args())
+        .and_then(|options| run(io::stdin().lock(), &options, &mut io::stdout()));
+    std::process::exit(match result {
         Ok(_) => 0,
         Err(err) => {
             eprintln!("error: {:?}", err);
@@ -150,3 +750,36 @@ fn main() {
         }
     });
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn output(input: &str, options: &Options) -> String {
+        let mut out = Vec::new();
+        run(input.as_bytes(), options, &mut out).unwrap();
+        String::from_utf8(out).unwrap()
+    }
+
+    // The first sample has too many floating bits for the version 2
+    // decoder to finish.
+
+    #[test]
+    fn sample_1() {
+        insta::assert_snapshot!(output(
+            include_str!("../../samples/day14-1.txt"),
+            &Options {
+                decoders: vec![Decoder::V1],
+                ..Options::default()
+            }
+        ));
+    }
+
+    #[test]
+    fn sample_2() {
+        insta::assert_snapshot!(output(
+            include_str!("../../samples/day14-2.txt"),
+            &Options::default()
+        ));
+    }
+}
@@ -1,11 +1,28 @@
+extern crate aor2020;
+#[cfg(test)]
+extern crate insta;
+extern crate rayon;
 extern crate regex;
+extern crate serde;
+extern crate serde_json;
 
+use aor2020::output::write_error;
+use rayon::prelude::*;
+use serde::Serialize;
 use std::collections::HashMap;
 use std::collections::VecDeque;
+use std::fs;
+use std::fs::File;
 use std::io;
 use std::io::BufRead;
+use std::io::Read;
+use std::io::Write;
+use std::mem::size_of;
+use std::time::Instant;
 
-fn playgame(start_numbers: &[usize], turns: &usize, verbose: bool) -> Option<usize> {
+/// Play the game, keeping the full history of when each number was
+/// spoken.  This is slow, but allows us to explain what is happening.
+fn playgame_with_history(start_numbers: &[usize], turns: &usize, verbose: bool) -> Option<usize> {
     let mut turns_spoken: HashMap<usize, VecDeque<usize>> = HashMap::new();
     let mut last_number: Option<usize> = None;
     let mut this_number: usize;
@@ -23,7 +40,7 @@ fn playgame(start_numbers: &[usize], turns: &usize, verbose: bool) -> Option<usi
             }
         } else {
             let ln = last_number.unwrap();
-            turns_spoken.entry(ln).or_insert_with(VecDeque::new);
+            turns_spoken.entry(ln).or_default();
             let when = turns_spoken.get_mut(&ln).unwrap();
             if when.len() == 1 {
                 if verbose {
@@ -49,9 +66,7 @@ fn playgame(start_numbers: &[usize], turns: &usize, verbose: bool) -> Option<usi
             }
         }
         last_number = Some(this_number);
-        let history = &mut turns_spoken
-            .entry(this_number)
-            .or_insert_with(VecDeque::new);
+        let history = &mut turns_spoken.entry(this_number).or_default();
         if verbose {
             print!("history of {} is {:?}; ", this_number, history);
         }
@@ -66,6 +81,379 @@ fn playgame(start_numbers: &[usize], turns: &usize, verbose: bool) -> Option<usi
     last_number
 }
 
+/// Why a number was spoken.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum TurnKind {
+    /// It is one of the start numbers.
+    Start,
+    /// The previous number had not been spoken before, so this is 0.
+    New,
+    /// The previous number had been spoken before, so this is the gap
+    /// since then.
+    Repeat,
+}
+
+/// Receives a notification of each number spoken in a game.
+trait Observer {
+    fn spoken(&mut self, turn: usize, number: usize, kind: TurnKind);
+}
+
+struct NullObserver {}
+
+impl Observer for NullObserver {
+    #[inline(always)]
+    fn spoken(&mut self, _turn: usize, _number: usize, _kind: TurnKind) {}
+}
+
+/// Statistics about the sequence of numbers spoken in a game.
+#[derive(Debug, Default, Serialize)]
+struct SequenceStats {
+    turns: usize,
+    max_number: usize,
+    start_turns: usize,
+    new_turns: usize,
+    repeat_turns: usize,
+    /// gap_histogram[i] counts the repeat turns whose gap is in the
+    /// range [2^i, 2^(i+1)).
+    gap_histogram: Vec<u64>,
+}
+
+impl Observer for SequenceStats {
+    fn spoken(&mut self, turn: usize, number: usize, kind: TurnKind) {
+        self.turns = turn;
+        self.max_number = self.max_number.max(number);
+        match kind {
+            TurnKind::Start => self.start_turns += 1,
+            TurnKind::New => self.new_turns += 1,
+            TurnKind::Repeat => {
+                self.repeat_turns += 1;
+                // For a repeat, the number spoken is the gap, which is
+                // at least 1.
+                let bucket = (usize::BITS - 1 - number.leading_zeros()) as usize;
+                if self.gap_histogram.len() <= bucket {
+                    self.gap_histogram.resize(bucket + 1, 0);
+                }
+                self.gap_histogram[bucket] += 1;
+            }
+        }
+    }
+}
+
+#[derive(Debug, Serialize)]
+struct Analysis {
+    start_numbers: Vec<usize>,
+    answer: usize,
+    stats: SequenceStats,
+}
+
+fn analyse(start_numbers: &[usize], turns: usize, budget: Option<usize>) -> Option<Analysis> {
+    if turns == 0 {
+        return None;
+    }
+    let mut game = Game::with_memory_budget(start_numbers, budget)?;
+    let mut stats = SequenceStats::default();
+    stats.spoken(1, game.last_number, TurnKind::Start);
+    game.play_until_observed(turns, &mut stats);
+    Some(Analysis {
+        start_numbers: start_numbers.to_vec(),
+        answer: game.last_number,
+        stats,
+    })
+}
+
+/// Identifies a checkpoint file (and its format version).
+const CHECKPOINT_MAGIC: &[u8; 8] = b"AOC15CP2";
+/// Checkpoints written before numbers could be kept in a hash map.
+const CHECKPOINT_MAGIC_V1: &[u8; 8] = b"AOC15CP1";
+
+/// How much memory a game is using to remember when numbers were
+/// spoken.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct MemoryUsage {
+    dense_entries: usize,
+    sparse_entries: usize,
+    bytes: usize,
+}
+
+impl std::fmt::Display for MemoryUsage {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(
+            f,
+            "{} bytes ({} numbers in the array, {} in the hash map)",
+            self.bytes, self.dense_entries, self.sparse_entries
+        )
+    }
+}
+
+/// Parses a size in bytes, optionally with a K, M or G suffix (powers
+/// of 1024).
+fn parse_size(s: &str) -> Result<usize, String> {
+    let (digits, multiplier) = match s.chars().last().map(|c| c.to_ascii_uppercase()) {
+        Some('K') => (&s[..s.len() - 1], 1 << 10),
+        Some('M') => (&s[..s.len() - 1], 1 << 20),
+        Some('G') => (&s[..s.len() - 1], 1 << 30),
+        _ => (s, 1),
+    };
+    digits
+        .parse::<usize>()
+        .ok()
+        .and_then(|n| n.checked_mul(multiplier))
+        .ok_or_else(|| format!("invalid size '{}'", s))
+}
+
+/// The state of a game, remembering only the last turn in which each
+/// number was spoken.  Since each number spoken is either a start
+/// number or smaller than the number of turns played so far, a flat
+/// array is enough.  But the array can be limited by a memory budget,
+/// in which case larger numbers (which are spoken rarely) are kept in
+/// a hash map instead.  Turn numbers are stored as u32, so games are
+/// limited to u32::MAX turns.
+struct Game {
+    start_numbers: Vec<usize>,
+    /// The number of turns played so far.
+    turn: usize,
+    /// The number spoken in the most recent turn.
+    last_number: usize,
+    /// last_seen[n] is the (1-based) turn in which n was most
+    /// recently spoken, not counting the most recent turn, or 0 if
+    /// never.
+    last_seen: Vec<u32>,
+    /// The same information for numbers too large for last_seen.
+    last_seen_sparse: HashMap<usize, u32>,
+    /// The largest size to which last_seen may grow.
+    dense_limit: usize,
+}
+
+impl Game {
+    /// Start a new game, in which the first start number has already
+    /// been spoken.
+    fn new(start_numbers: &[usize]) -> Option<Game> {
+        Game::with_memory_budget(start_numbers, None)
+    }
+
+    /// Start a new game whose array of turns may use at most
+    /// `budget` bytes (if specified).
+    fn with_memory_budget(start_numbers: &[usize], budget: Option<usize>) -> Option<Game> {
+        let first = *start_numbers.first()?;
+        let dense_limit = budget.map_or(usize::MAX, |b| b / size_of::<u32>());
+        let size = start_numbers.iter().max().unwrap() + 1;
+        Some(Game {
+            start_numbers: start_numbers.to_vec(),
+            turn: 1,
+            last_number: first,
+            last_seen: vec![0; size.min(dense_limit)],
+            last_seen_sparse: HashMap::new(),
+            dense_limit,
+        })
+    }
+
+    fn set_memory_budget(&mut self, budget: Option<usize>) {
+        self.dense_limit = budget.map_or(usize::MAX, |b| b / size_of::<u32>());
+    }
+
+    /// An estimate of the memory in use; for the hash map, this
+    /// counts the space for each entry plus a control byte.
+    fn memory_usage(&self) -> MemoryUsage {
+        MemoryUsage {
+            dense_entries: self.last_seen.len(),
+            sparse_entries: self.last_seen_sparse.len(),
+            bytes: self.last_seen.capacity() * size_of::<u32>()
+                + self.last_seen_sparse.capacity() * (size_of::<(usize, u32)>() + 1),
+        }
+    }
+
+    #[inline(always)]
+    fn when_seen(&self, n: usize) -> u32 {
+        match self.last_seen.get(n) {
+            Some(when) => *when,
+            None => self.last_seen_sparse.get(&n).copied().unwrap_or(0),
+        }
+    }
+
+    #[inline(always)]
+    fn set_seen(&mut self, n: usize, turn: u32) {
+        match self.last_seen.get_mut(n) {
+            Some(when) => *when = turn,
+            None => {
+                self.last_seen_sparse.insert(n, turn);
+            }
+        }
+    }
+
+    /// Keep playing until `turns` turns have been played in total.
+    fn play_until(&mut self, turns: usize) {
+        self.play_until_observed(turns, &mut NullObserver {})
+    }
+
+    /// Keep playing until `turns` turns have been played in total,
+    /// telling `observer` about each turn.
+    fn play_until_observed<O: Observer>(&mut self, turns: usize, observer: &mut O) {
+        let size = turns.min(self.dense_limit);
+        if self.last_seen.len() < size {
+            let old_size = self.last_seen.len();
+            self.last_seen.resize(size, 0);
+            // Move any numbers which now fit in the array.
+            if !self.last_seen_sparse.is_empty() {
+                for n in old_size..size {
+                    if let Some(when) = self.last_seen_sparse.remove(&n) {
+                        self.last_seen[n] = when;
+                    }
+                }
+            }
+        }
+        while self.turn < turns {
+            let turn = self.turn;
+            let (this_number, kind) = if turn < self.start_numbers.len() {
+                (self.start_numbers[turn], TurnKind::Start)
+            } else {
+                match self.when_seen(self.last_number) {
+                    0 => (0, TurnKind::New),
+                    when => (turn - when as usize, TurnKind::Repeat),
+                }
+            };
+            observer.spoken(turn + 1, this_number, kind);
+            self.set_seen(self.last_number, turn as u32);
+            self.last_number = this_number;
+            self.turn += 1;
+        }
+    }
+
+    fn save(&self, filename: &str) -> Result<(), String> {
+        // Write to a temporary file first so that an interruption
+        // doesn't destroy the previous checkpoint.
+        let tmpname = format!("{}.tmp", filename);
+        let write = || -> io::Result<()> {
+            let mut f = io::BufWriter::new(File::create(&tmpname)?);
+            f.write_all(CHECKPOINT_MAGIC)?;
+            for n in &[self.turn, self.last_number, self.start_numbers.len()] {
+                f.write_all(&(*n as u64).to_le_bytes())?;
+            }
+            for n in self.start_numbers.iter() {
+                f.write_all(&(*n as u64).to_le_bytes())?;
+            }
+            f.write_all(&(self.last_seen.len() as u64).to_le_bytes())?;
+            for when in self.last_seen.iter() {
+                f.write_all(&when.to_le_bytes())?;
+            }
+            f.write_all(&(self.last_seen_sparse.len() as u64).to_le_bytes())?;
+            for (n, when) in self.last_seen_sparse.iter() {
+                f.write_all(&(*n as u64).to_le_bytes())?;
+                f.write_all(&when.to_le_bytes())?;
+            }
+            f.flush()?;
+            drop(f);
+            fs::rename(&tmpname, filename)
+        };
+        write().map_err(|e| format!("failed to write checkpoint {}: {}", filename, e))
+    }
+
+    fn load(filename: &str) -> Result<Game, String> {
+        let read = || -> io::Result<Game> {
+            let f = File::open(filename)?;
+            let len = f.metadata()?.len();
+            Game::read_from(io::BufReader::new(f), len)
+        };
+        read().map_err(|e| format!("failed to read checkpoint {}: {}", filename, e))
+    }
+
+    /// Reads a checkpoint of `len` bytes.  The counts in a corrupt
+    /// checkpoint are checked against `len` before anything is
+    /// allocated for them.
+    fn read_from(mut f: impl Read, len: u64) -> io::Result<Game> {
+        let check_count = |count: usize, item_size: u64, what: &str| -> io::Result<()> {
+            match (count as u64).checked_mul(item_size) {
+                Some(bytes) if bytes <= len => Ok(()),
+                _ => Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!(
+                        "{} {} is too large for a checkpoint of {} bytes",
+                        what, count, len
+                    ),
+                )),
+            }
+        };
+        let mut magic = [0u8; 8];
+        f.read_exact(&mut magic)?;
+        if &magic != CHECKPOINT_MAGIC && &magic != CHECKPOINT_MAGIC_V1 {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "not a day 15 checkpoint file",
+            ));
+        }
+        let turn = read_u64(&mut f)?;
+        let last_number = read_u64(&mut f)?;
+        let count = read_u64(&mut f)?;
+        check_count(count, 8, "start number count")?;
+        let start_numbers = (0..count)
+            .map(|_| read_u64(&mut f))
+            .collect::<io::Result<Vec<usize>>>()?;
+        let size = read_u64(&mut f)?;
+        check_count(size, 4, "table size")?;
+        let last_seen = (0..size)
+            .map(|_| read_u32(&mut f))
+            .collect::<io::Result<Vec<u32>>>()?;
+        let mut last_seen_sparse = HashMap::new();
+        if &magic == CHECKPOINT_MAGIC {
+            for _ in 0..read_u64(&mut f)? {
+                let n = read_u64(&mut f)?;
+                last_seen_sparse.insert(n, read_u32(&mut f)?);
+            }
+        }
+        Ok(Game {
+            start_numbers,
+            turn,
+            last_number,
+            last_seen,
+            last_seen_sparse,
+            dense_limit: usize::MAX,
+        })
+    }
+}
+
+fn read_u64(f: &mut impl Read) -> io::Result<usize> {
+    let mut buf = [0u8; 8];
+    f.read_exact(&mut buf)?;
+    Ok(u64::from_le_bytes(buf) as usize)
+}
+
+fn read_u32(f: &mut impl Read) -> io::Result<u32> {
+    let mut buf = [0u8; 4];
+    f.read_exact(&mut buf)?;
+    Ok(u32::from_le_bytes(buf))
+}
+
+fn playgame_fast(start_numbers: &[usize], turns: usize) -> Option<usize> {
+    if turns == 0 {
+        return None;
+    }
+    let mut game = Game::new(start_numbers)?;
+    game.play_until(turns);
+    Some(game.last_number)
+}
+
+/// Play the game with the array of turns limited to `budget` bytes
+/// (if specified), reporting how much memory was actually used.
+fn playgame_within_budget(
+    start_numbers: &[usize],
+    turns: usize,
+    budget: Option<usize>,
+) -> Option<(usize, MemoryUsage)> {
+    if turns == 0 {
+        return None;
+    }
+    let mut game = Game::with_memory_budget(start_numbers, budget)?;
+    game.play_until(turns);
+    Some((game.last_number, game.memory_usage()))
+}
+
+fn playgame(start_numbers: &[usize], turns: &usize, verbose: bool) -> Option<usize> {
+    if verbose {
+        playgame_with_history(start_numbers, turns, verbose)
+    } else {
+        playgame_fast(start_numbers, *turns)
+    }
+}
+
 fn run_one_test(
     label: &str,
     start_numbers: &[usize],
@@ -108,6 +496,19 @@ fn runtests(verbose: bool) -> Result<(), String> {
     for scenario in scenarios {
         let (label, start_numbers, turns, expected): &(&str, &[usize], usize, Option<usize>) =
             scenario;
+        // run_one_test uses whichever implementation the verbose
+        // flag selects, so check the other one here.
+        let other = if verbose {
+            playgame_fast(start_numbers, *turns)
+        } else {
+            playgame_with_history(start_numbers, turns, false)
+        };
+        if other != *expected {
+            return Err(format!(
+                "FAIL: '{}': {:?} turn {}: expected {:?}, got {:?}",
+                label, start_numbers, turns, expected, other
+            ));
+        }
         match run_one_test(label, start_numbers, turns, expected, verbose) {
             Ok(()) => (),
             Err(_) => {
@@ -118,33 +519,354 @@ fn runtests(verbose: bool) -> Result<(), String> {
     Ok(())
 }
 
+/// Check that games played with a limited array agree with those
+/// played without one, and that numbers beyond the limit go in the
+/// hash map.
+fn test_memory_budget() -> Result<(), String> {
+    for (budget, expected) in &[
+        ("16", Ok(16)),
+        ("2k", Ok(2048)),
+        ("1M", Ok(1 << 20)),
+        ("x", Err(())),
+    ] {
+        if parse_size(budget).map_err(|_| ()) != *expected {
+            return Err(format!("FAIL: wrong result parsing size '{}'", budget));
+        }
+    }
+    let start_numbers: &[usize] = &[3, 1, 2];
+    let turns = 2020;
+    let expected = playgame_fast(start_numbers, turns);
+    for budget in &[0, 3, 16, 400, 4 * 2020, 1 << 20] {
+        let got = playgame_within_budget(start_numbers, turns, Some(*budget));
+        if got.map(|(n, _)| n) != expected {
+            return Err(format!(
+                "FAIL: with a budget of {} bytes got {:?}, expected {:?}",
+                budget, got, expected
+            ));
+        }
+        let usage = got.unwrap().1;
+        let limit = (budget / 4).min(turns);
+        if usage.dense_entries != limit || (limit < turns && usage.sparse_entries == 0) {
+            return Err(format!(
+                "FAIL: with a budget of {} bytes, memory used is {}",
+                budget, usage
+            ));
+        }
+    }
+    // Raising the budget part way through a game moves numbers into
+    // the array.
+    let mut game = Game::with_memory_budget(start_numbers, Some(40)).unwrap();
+    game.play_until(1000);
+    game.set_memory_budget(None);
+    game.play_until(turns);
+    if Some(game.last_number) != expected || !game.last_seen_sparse.is_empty() {
+        return Err(format!(
+            "FAIL: after raising the budget got {} with memory used {}",
+            game.last_number,
+            game.memory_usage()
+        ));
+    }
+    Ok(())
+}
+
 fn self_test() -> Result<(), String> {
-    runtests(true)
-}
-
-fn run() -> Result<(), String> {
-    self_test()?;
-    let input = io::BufReader::new(io::stdin());
-    let start_numbers: Vec<usize> = match input.lines().next() {
-        None => return Err("no numbers were specified in the input".to_string()),
-        Some(Err(e)) => return Err(format!("I/O error: {}", e)),
-        Some(Ok(s)) => s
-            .split(',')
-            .map(|s| (*s).parse::<usize>().expect("failed to parse integer"))
-            .collect(),
+    runtests(true)?;
+    test_memory_budget()
+}
+
+/// Compare the speed of the two implementations.
+fn benchmark(start_numbers: &[usize], turns: usize, out: &mut impl Write) -> Result<(), String> {
+    let start = Instant::now();
+    let slow = playgame_with_history(start_numbers, &turns, false);
+    let slow_time = start.elapsed();
+    let start = Instant::now();
+    let fast = playgame_fast(start_numbers, turns);
+    let fast_time = start.elapsed();
+    writeln!(
+        out,
+        "{} turns: history map {:?} ({:?}), flat array {:?} ({:?}), speedup {:.1}x",
+        turns,
+        slow,
+        slow_time,
+        fast,
+        fast_time,
+        slow_time.as_secs_f64() / fast_time.as_secs_f64()
+    )
+    .map_err(write_error)
+}
+
+/// Print the result of a game, and (if there is a memory budget) how
+/// much memory it needed.
+fn report_game(
+    label: &str,
+    start_numbers: &[usize],
+    turns: usize,
+    budget: Option<usize>,
+    out: &mut impl Write,
+) -> Result<(), String> {
+    let result = playgame_within_budget(start_numbers, turns, budget);
+    writeln!(out, "{}: number = {:?}", label, result.map(|(n, _)| n)).map_err(write_error)?;
+    if let (Some(_), Some((_, usage))) = (budget, result) {
+        writeln!(out, "{}: memory used: {}", label, usage).map_err(write_error)?;
+    }
+    Ok(())
+}
+
+fn parse_start_numbers(s: &str) -> Result<Vec<usize>, String> {
+    s.trim()
+        .split(',')
+        .map(|n| {
+            n.trim()
+                .parse::<usize>()
+                .map_err(|e| format!("invalid start number '{}': {}", n, e))
+        })
+        .collect()
+}
+
+/// Play a game for each line of the input (in parallel, since the
+/// games are independent) and report the results in input order.
+fn run_batch(
+    input: impl BufRead,
+    turns: &[usize],
+    budget: Option<usize>,
+    out: &mut impl Write,
+) -> Result<(), String> {
+    let mut games: Vec<(usize, Vec<usize>)> = Vec::new();
+    for (lineno, line) in input.lines().enumerate() {
+        let line = line.map_err(|e| format!("I/O error: {}", e))?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        games.push((
+            lineno + 1,
+            parse_start_numbers(&line).map_err(|e| format!("line {}: {}", lineno + 1, e))?,
+        ));
+    }
+    let results: Vec<Vec<Option<usize>>> = games
+        .par_iter()
+        .map(|(_, start_numbers)| {
+            turns
+                .iter()
+                .map(|t| playgame_within_budget(start_numbers, *t, budget).map(|(n, _)| n))
+                .collect()
+        })
+        .collect();
+    for ((lineno, start_numbers), answers) in games.iter().zip(results.iter()) {
+        let answers: Vec<String> = turns
+            .iter()
+            .zip(answers.iter())
+            .map(|(t, answer)| format!("turn {} = {:?}", t, answer))
+            .collect();
+        writeln!(
+            out,
+            "line {} {:?}: {}",
+            lineno,
+            start_numbers,
+            answers.join(", ")
+        )
+        .map_err(write_error)?;
+    }
+    Ok(())
+}
+
+/// Play a single game up to each of the specified turns, saving a
+/// checkpoint every `every` turns (if a checkpoint file is specified).
+fn run_checkpointed(
+    mut game: Game,
+    turns: &[usize],
+    checkpoint: Option<&str>,
+    every: usize,
+    out: &mut impl Write,
+) -> Result<(), String> {
+    if turns.is_empty() {
+        return Err("specify the turns to play with --turns".to_string());
+    }
+    let mut targets = turns.to_vec();
+    targets.sort_unstable();
+    for target in targets {
+        if target < game.turn {
+            return Err(format!(
+                "cannot report turn {} as the game is already at turn {}",
+                target, game.turn
+            ));
+        }
+        while game.turn < target {
+            game.play_until(target.min(game.turn.saturating_add(every)));
+            if let Some(filename) = checkpoint {
+                game.save(filename)?;
+                eprintln!("saved checkpoint at turn {}", game.turn);
+            }
+        }
+        writeln!(
+            out,
+            "Turn {}: number = {:?}",
+            target,
+            Some(game.last_number)
+        )
+        .map_err(write_error)?;
+        eprintln!("memory used at turn {}: {}", target, game.memory_usage());
+    }
+    Ok(())
+}
+
+#[derive(Default)]
+struct Options {
+    analyse: bool,
+    batch: bool,
+    benchmark: bool,
+    checkpoint: Option<String>,
+    checkpoint_every: Option<usize>,
+    memory_budget: Option<usize>,
+    resume: Option<String>,
+    turns: Vec<usize>,
+    start_numbers: Option<Vec<usize>>,
+}
+
+fn parse_args() -> Result<Options, String> {
+    let mut options = Options::default();
+    let mut args = std::env::args().skip(1);
+    while let Some(arg) = args.next() {
+        let mut value = || {
+            args.next()
+                .ok_or_else(|| format!("option {} requires an argument", arg))
+        };
+        match arg.as_str() {
+            "--analyse" => options.analyse = true,
+            "--batch" => options.batch = true,
+            "--benchmark" => options.benchmark = true,
+            "--checkpoint" => options.checkpoint = Some(value()?),
+            "--checkpoint-every" => {
+                let v = value()?;
+                options.checkpoint_every = Some(
+                    v.parse()
+                        .map_err(|e| format!("invalid turn count '{}': {}", v, e))?,
+                );
+            }
+            "--memory-budget" => options.memory_budget = Some(parse_size(&value()?)?),
+            "--resume" => options.resume = Some(value()?),
+            "--turns" => {
+                let v = value()?;
+                options.turns.push(
+                    v.parse()
+                        .map_err(|e| format!("invalid turn count '{}': {}", v, e))?,
+                );
+            }
+            _ if arg.starts_with('-') => return Err(format!("unknown option {}", arg)),
+            _ if options.start_numbers.is_none() => {
+                options.start_numbers = Some(parse_start_numbers(&arg)?);
+            }
+            _ => return Err(format!("unexpected argument {}", arg)),
+        }
+    }
+    if options.turns.iter().any(|t| *t > u32::MAX as usize) {
+        return Err(format!("at most {} turns are supported", u32::MAX));
+    }
+    Ok(options)
+}
+
+fn run(input: impl BufRead, options: &Options, out: &mut impl Write) -> Result<(), String> {
+    if options.batch {
+        if options.start_numbers.is_some() {
+            return Err("--batch reads the start numbers from the input".to_string());
+        }
+        let turns = if options.turns.is_empty() {
+            vec![2020, 30000000]
+        } else {
+            options.turns.clone()
+        };
+        return run_batch(input, &turns, options.memory_budget, out);
+    }
+    if let Some(filename) = &options.resume {
+        if options.start_numbers.is_some() {
+            return Err("--resume takes the start numbers from the checkpoint".to_string());
+        }
+        let mut game = Game::load(filename)?;
+        game.set_memory_budget(options.memory_budget);
+        eprintln!("resuming {:?} from turn {}", game.start_numbers, game.turn);
+        return run_checkpointed(
+            game,
+            &options.turns,
+            options.checkpoint.as_deref(),
+            options.checkpoint_every.unwrap_or(10_000_000),
+            out,
+        );
+    }
+    let start_numbers: Vec<usize> = match &options.start_numbers {
+        Some(numbers) => numbers.clone(),
+        None => match input.lines().next() {
+            None => return Err("no numbers were specified in the input".to_string()),
+            Some(Err(e)) => return Err(format!("I/O error: {}", e)),
+            Some(Ok(s)) => parse_start_numbers(&s)?,
+        },
     };
-    for (part, which_num) in &[(1, 2020), (2, 30000000)] {
-        println!(
-            "Part {}: number = {:?}",
-            part,
-            playgame(&start_numbers, which_num, false)
+    if options.analyse {
+        let budget = options.memory_budget;
+        let turns = if options.turns.is_empty() {
+            vec![2020, 30000000]
+        } else {
+            options.turns.clone()
+        };
+        let analyses: Vec<Analysis> = turns
+            .iter()
+            .filter_map(|t| analyse(&start_numbers, *t, budget))
+            .collect();
+        writeln!(
+            out,
+            "{}",
+            serde_json::to_string_pretty(&analyses).map_err(|e| e.to_string())?
+        )
+        .map_err(write_error)?;
+        return Ok(());
+    }
+    if let Some(filename) = &options.checkpoint {
+        let game = match Game::with_memory_budget(&start_numbers, options.memory_budget) {
+            Some(game) => game,
+            None => return Err("no start numbers were specified".to_string()),
+        };
+        return run_checkpointed(
+            game,
+            &options.turns,
+            Some(filename),
+            options.checkpoint_every.unwrap_or(10_000_000),
+            out,
         );
     }
+    if options.turns.is_empty() {
+        if options.benchmark {
+            for turns in &[2020, 30000000] {
+                benchmark(&start_numbers, *turns, out)?;
+            }
+            return Ok(());
+        }
+        for (part, which_num) in &[(1, 2020), (2, 30000000)] {
+            let label = format!("Part {}", part);
+            report_game(
+                &label,
+                &start_numbers,
+                *which_num,
+                options.memory_budget,
+                out,
+            )?;
+        }
+    } else {
+        for turns in options.turns.iter() {
+            if options.benchmark {
+                benchmark(&start_numbers, *turns, out)?;
+            } else {
+                let label = format!("Turn {}", turns);
+                report_game(&label, &start_numbers, *turns, options.memory_budget, out)?;
+            }
+        }
+    }
     Ok(())
 }
 
 fn main() {
-    std::process::exit(match run() {
+    let result = parse_args().and_then(|options| {
+        self_test()?;
+        run(io::stdin().lock(), &options, &mut io::stdout())
+    });
+    std::process::exit(match result {
         Ok(_) => 0,
         Err(err) => {
             eprintln!("error: {:?}", err);
@@ -152,3 +874,51 @@ fn main() {
         }
     });
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn output(input: &str, options: &Options) -> String {
+        let mut out = Vec::new();
+        run(input.as_bytes(), options, &mut out).unwrap();
+        String::from_utf8(out).unwrap()
+    }
+
+    #[test]
+    fn sample() {
+        insta::assert_snapshot!(output(
+            "0,3,6\n",
+            &Options {
+                turns: vec![4, 10, 2020],
+                ..Options::default()
+            }
+        ));
+    }
+
+    #[test]
+    fn batch() {
+        insta::assert_snapshot!(output(
+            "0,3,6\n1,3,2\n\n2,1,3\n",
+            &Options {
+                batch: true,
+                turns: vec![10, 2020],
+                ..Options::default()
+            }
+        ));
+    }
+
+    #[test]
+    fn corrupt_checkpoint() {
+        // A checkpoint claiming far more start numbers than it holds.
+        let mut checkpoint = CHECKPOINT_MAGIC.to_vec();
+        for n in &[10u64, 0, u64::MAX / 2] {
+            checkpoint.extend_from_slice(&n.to_le_bytes());
+        }
+        let len = checkpoint.len() as u64;
+        match Game::read_from(checkpoint.as_slice(), len) {
+            Err(e) => assert_eq!(e.kind(), io::ErrorKind::InvalidData),
+            Ok(_) => panic!("a corrupt checkpoint was accepted"),
+        }
+    }
+}
@@ -1,335 +1,525 @@
-extern crate itertools;
-extern crate lazy_static;
-extern crate regex;
+extern crate aor2020;
+#[cfg(test)]
+extern crate insta;
+extern crate log;
+extern crate pretty_env_logger;
+extern crate serde_json;
 
-use lazy_static::lazy_static;
-use regex::Regex;
+use aor2020::constraint::{Cause, Deduction, Problem, Rule};
+use aor2020::day16::{Field, Input, Ticket};
+use aor2020::explain::{self, Explanation};
+use aor2020::output::write_error;
+use aor2020::rng::Lcg;
+use std::collections::BTreeMap;
+use std::collections::BTreeSet;
 use std::collections::HashMap;
-use std::collections::HashSet;
-use std::fmt;
 use std::io;
-use std::io::BufRead;
-use std::ops::RangeInclusive;
+use std::io::Read;
+use std::io::Write;
+use std::time::Instant;
 
-lazy_static! {
-    static ref FIELD_RE: Regex = Regex::new(r"^([^:]*): (\d+)-(\d+) or (\d+)-(\d+)$").unwrap();
+/// Returns the sum of the invalid values, and the valid tickets.
+fn part1(input: &Input) -> (i32, Vec<Ticket>) {
+    let mut invalid_values: Vec<i32> = Vec::new();
+    let mut valid_tickets: Vec<Ticket> = Vec::new();
+    for t in &input.nearby {
+        let mut this_ticket_valid = true;
+        for v in &t.values {
+            if !input.is_valid_value(v) {
+                invalid_values.push(*v);
+                this_ticket_valid = false;
+            }
+        }
+        if this_ticket_valid {
+            valid_tickets.push(t.clone());
+        }
+    }
+    (invalid_values.iter().sum(), valid_tickets)
 }
 
-#[derive(Clone)]
-struct Field {
-    name: String,
-    first: RangeInclusive<i32>,
-    second: RangeInclusive<i32>,
+/// Make a constraint problem in which each field is a variable whose
+/// candidate values are the positions at which every valid ticket has
+/// a valid value for it.
+fn make_problem(fields: &[Field], valid_tickets: &[Ticket]) -> Problem {
+    let nf: usize = valid_tickets
+        .first()
+        .map(|t| t.values.len())
+        .unwrap_or(fields.len());
+    let candidates: Vec<BTreeSet<usize>> = fields
+        .iter()
+        .map(|f| {
+            (0..nf)
+                .filter(|i| {
+                    valid_tickets
+                        .iter()
+                        .all(|t| t.values.get(*i).is_some_and(|v| f.is_valid_value(v)))
+                })
+                .collect()
+        })
+        .collect();
+    for (f, positions) in fields.iter().zip(candidates.iter()) {
+        log::debug!("candidates for field {}: {:?}", f.name, positions);
+    }
+    Problem::new(nf, candidates)
 }
 
-impl Field {
-    fn is_valid_value(&self, v: &i32) -> bool {
-        self.first.contains(v) || self.second.contains(v)
+/// Work out which position on the ticket each field occupies.
+fn resolve_positions(
+    fields: &[Field],
+    valid_tickets: &[Ticket],
+) -> Result<HashMap<String, usize>, String> {
+    let nf: usize = match valid_tickets.first() {
+        Some(t) => t.values.len(),
+        None => return Err("there are no valid tickets".to_string()),
+    };
+    if valid_tickets.iter().any(|t| t.values.len() != nf) {
+        return Err("the valid tickets do not all have the same length".to_string());
     }
+    let mut problem = make_problem(fields, valid_tickets);
+    if problem.propagate().is_err() {
+        return Err("there is no valid assignment of fields to positions".to_string());
+    }
+    for deduction in problem.deductions() {
+        if let Deduction::Assigned {
+            var, value, round, ..
+        } = deduction
+        {
+            log::debug!(
+                "round {}: field {} must be at position {}",
+                round,
+                fields[*var].name,
+                value
+            );
+        }
+    }
+    let unknown: Vec<&str> = fields
+        .iter()
+        .enumerate()
+        .filter(|(i, _)| problem.value_of(*i).is_none())
+        .map(|(_, f)| f.name.as_str())
+        .collect();
+    if !unknown.is_empty() {
+        return Err(format!(
+            "the positions of fields {:?} are ambiguous",
+            unknown
+        ));
+    }
+    Ok(fields
+        .iter()
+        .enumerate()
+        .filter_map(|(i, f)| problem.value_of(i).map(|pos| (f.name.clone(), pos)))
+        .collect())
 }
 
-impl fmt::Display for Field {
-    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        write!(
-            f,
-            "{}: {}-{} or {}-{}",
-            self.name,
-            self.first.start(),
-            self.first.end(),
-            self.second.start(),
-            self.second.end()
-        )
-    }
+/// Map each field name to its value on the ticket.
+fn decode_ticket(
+    ticket: &Ticket,
+    positions: &HashMap<String, usize>,
+) -> Result<BTreeMap<String, i32>, String> {
+    positions
+        .iter()
+        .map(|(name, pos)| match ticket.values.get(*pos) {
+            Some(value) => Ok((name.clone(), *value)),
+            None => Err(format!(
+                "your ticket has no value at position {} (for field {})",
+                pos, name
+            )),
+        })
+        .collect()
 }
 
-impl Field {
-    fn new(s: &str) -> Result<Field, String> {
-        match FIELD_RE.captures(s) {
-            None => Err(format!("'{}' is not a valid field input line", s)),
-            Some(caps) => Ok(Field {
-                name: caps[1].to_string(),
-                first: RangeInclusive::new(
-                    caps[2].parse().expect("invalid from1"),
-                    caps[3].parse().expect("invalid to1"),
-                ),
-                second: RangeInclusive::new(
-                    caps[4].parse().expect("invalid from2"),
-                    caps[5].parse().expect("invalid to2"),
-                ),
-            }),
-        }
-    }
+fn part2(decoded: &BTreeMap<String, i32>) -> i64 {
+    decoded
+        .iter()
+        .filter(|(name, _)| name.starts_with("departure"))
+        .map(|(_, value)| *value as i64)
+        .product()
 }
 
-#[derive(Clone)]
-struct Ticket {
-    values: Vec<i32>,
+/// Generate a large synthetic input and compare the time taken to
+/// validate it by checking every field's ranges in turn with the time
+/// taken using the merged interval set.
+fn benchmark(nfields: usize, ntickets: usize, out: &mut impl Write) -> Result<(), String> {
+    let mut rng = Lcg::new(20201216);
+    let mut random = |limit: i32| -> i32 { rng.below(limit as usize) as i32 };
+    let fields: Vec<Field> = (0..nfields)
+        .map(|i| {
+            let a = random(900);
+            let b = a + random(100);
+            let c = b + 2 + random(50);
+            let d = c + random(100);
+            Field::from_ranges(&format!("field{}", i), a..=b, c..=d)
+        })
+        .collect();
+    let nearby: Vec<Ticket> = (0..ntickets)
+        .map(|_| Ticket {
+            values: (0..nfields).map(|_| random(1200)).collect(),
+        })
+        .collect();
+    let input = Input::new(fields, Ticket { values: Vec::new() }, nearby);
+
+    let start = Instant::now();
+    let naive: usize = input
+        .nearby
+        .iter()
+        .flat_map(|t| t.values.iter())
+        .filter(|v| {
+            input.fields.iter().any(|f| {
+                f.ranges.iter().any(|r| r.contains(v))
+                    && !f.exclusions.iter().any(|r| r.contains(v))
+            })
+        })
+        .count();
+    let naive_time = start.elapsed();
+    let start = Instant::now();
+    let merged: usize = input
+        .nearby
+        .iter()
+        .flat_map(|t| t.values.iter())
+        .filter(|v| input.is_valid_value(v))
+        .count();
+    let merged_time = start.elapsed();
+    writeln!(
+        out,
+        "{} fields, {} tickets: per-field ranges found {} valid values in {:?}, \
+         merged intervals found {} in {:?}",
+        nfields, ntickets, naive, naive_time, merged, merged_time
+    )
+    .map_err(write_error)
 }
 
-impl fmt::Display for Ticket {
-    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        f.write_str(&itertools::join(&self.values, ","))
-    }
+/// Print (as JSON) whether the field positions are ambiguous, how
+/// many valid assignments there are, and up to `limit` of them.
+fn report_assignments(
+    fields: &[Field],
+    valid_tickets: &[Ticket],
+    limit: Option<usize>,
+    out: &mut impl Write,
+) -> Result<(), String> {
+    let result = make_problem(fields, valid_tickets).solutions(limit);
+    let assignments: Vec<BTreeMap<&str, usize>> = result
+        .assignments
+        .iter()
+        .map(|a| {
+            fields
+                .iter()
+                .zip(a.iter())
+                .map(|(f, pos)| (f.name.as_str(), *pos))
+                .collect()
+        })
+        .collect();
+    let report = serde_json::json!({
+        "ambiguous": result.count > 1,
+        "count": result.count,
+        "assignments": assignments,
+    });
+    writeln!(
+        out,
+        "{}",
+        serde_json::to_string_pretty(&report).map_err(|e| e.to_string())?
+    )
+    .map_err(write_error)
 }
 
-fn read_ticket(s: &str) -> Result<Ticket, String> {
-    let mut vs: Vec<i32> = Vec::new();
-    for value in s.split(',') {
-        match value.parse() {
-            Err(e) => {
-                return Err(format!("failed to parse value '{}': {}", value, e));
+/// Describes how each part is solved: which values are invalid, and
+/// then how the fields are matched up with the positions on the
+/// tickets.
+fn explain(input: &Input) -> Explanation {
+    let mut explanation = Explanation::new();
+    for (n, t) in input.nearby.iter().enumerate() {
+        for v in t.values.iter().filter(|v| !input.is_valid_value(v)) {
+            explanation
+                .step(
+                    1,
+                    "invalid-value",
+                    format!(
+                        "nearby ticket {} is invalid, since no field can have the value {}",
+                        n + 1,
+                        v
+                    ),
+                )
+                .fact("ticket", n + 1)
+                .fact("value", *v as i64);
+        }
+    }
+    let (total, valid_tickets) = part1(input);
+    explanation
+        .step(
+            1,
+            "total",
+            format!("the invalid values add up to {}", total),
+        )
+        .fact("total", total as i64);
+
+    let fields = &input.fields;
+    let mut problem = make_problem(fields, &valid_tickets);
+    for (i, f) in fields.iter().enumerate() {
+        let positions: Vec<usize> = problem.candidates(i).iter().cloned().collect();
+        explanation
+            .step(
+                2,
+                "candidates",
+                format!(
+                    "every valid ticket allows {} at positions {:?}",
+                    f.name, positions
+                ),
+            )
+            .fact("field", f.name.as_str())
+            .fact("positions", positions);
+    }
+    if problem.propagate().is_err() {
+        explanation.step(
+            2,
+            "unsatisfiable",
+            "there is no valid assignment of fields to positions".to_string(),
+        );
+        return explanation;
+    }
+    for deduction in problem.deductions() {
+        match *deduction {
+            Deduction::Assigned {
+                var,
+                value,
+                round,
+                rule,
+            } => {
+                let why = match rule {
+                    Rule::OnlyValue => "it is the only position left for it".to_string(),
+                    Rule::OnlyVariable => format!("no other field can be at position {}", value),
+                };
+                explanation
+                    .step(
+                        2,
+                        "assigned",
+                        format!(
+                            "round {}: {} is at position {}, since {}",
+                            round, fields[var].name, value, why
+                        ),
+                    )
+                    .fact("field", fields[var].name.as_str())
+                    .fact("position", value)
+                    .fact("round", round);
             }
-            Ok(n) => {
-                vs.push(n);
+            Deduction::Eliminated { var, value, cause } => {
+                let why = match cause {
+                    Cause::ValueTaken(other) => format!("{} is there", fields[other].name),
+                    Cause::Unmatchable => {
+                        "then the other fields could not all be placed".to_string()
+                    }
+                    // This just restates the assignment we have
+                    // already described.
+                    Cause::VariableAssigned(_) => continue,
+                };
+                explanation
+                    .step(
+                        2,
+                        "eliminated",
+                        format!(
+                            "{} cannot be at position {}, since {}",
+                            fields[var].name, value, why
+                        ),
+                    )
+                    .fact("field", fields[var].name.as_str())
+                    .fact("position", value);
             }
         }
     }
-    Ok(Ticket { values: vs })
-}
-
-struct Input {
-    fields: Vec<Field>,
-    my_ticket: Ticket,
-    nearby: Vec<Ticket>,
+    if !problem.is_solved() {
+        explanation.step(
+            2,
+            "ambiguous",
+            "the remaining fields could be in more than one order".to_string(),
+        );
+    }
+    explanation
 }
 
-impl Input {
-    fn is_valid_value(&self, v: &i32) -> bool {
-        self.fields.iter().any(|f| f.is_valid_value(v))
+fn write_explanation(
+    explanation: &Explanation,
+    format: explain::Format,
+    out: &mut impl Write,
+) -> Result<(), String> {
+    match format {
+        explain::Format::Text => write!(out, "{}", explanation).map_err(write_error)?,
+        explain::Format::Json => writeln!(
+            out,
+            "{}",
+            serde_json::to_string_pretty(explanation).map_err(|e| e.to_string())?
+        )
+        .map_err(write_error)?,
     }
+    Ok(())
 }
 
-impl fmt::Display for Input {
-    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        write!(
-            f,
-            "{}\n\nyour ticket:\n{}\n\nnearby tickets:\n{}",
-            itertools::join(self.fields.iter(), "\n"),
-            self.my_ticket,
-            itertools::join(self.nearby.iter(), "\n")
-        )
+fn self_test_explain() -> Result<(), String> {
+    explain::self_test()?;
+    let input = Input::parse(
+        "class: 0-1 or 4-19\nrow: 0-5 or 8-19\nseat: 0-13 or 16-19\n\n\
+         your ticket:\n11,12,13\n\n\
+         nearby tickets:\n3,9,18\n15,1,5\n5,14,9\n20,1,1\n",
+    )
+    .map_err(|e| e.to_string())?;
+    let explanation = explain(&input);
+    let assigned: Vec<String> = explanation
+        .steps()
+        .iter()
+        .filter(|s| s.kind == "assigned")
+        .map(|s| s.text.clone())
+        .collect();
+    let expected = [
+        "round 1: seat is at position 2, since it is the only position left for it",
+        "round 1: row is at position 0, since no other field can be at position 0",
+        "round 2: class is at position 1, since it is the only position left for it",
+    ];
+    if assigned != expected {
+        return Err(format!("FAIL: day 16 explanation made {:?}", assigned));
+    }
+    match explanation
+        .steps()
+        .iter()
+        .find(|s| s.kind == "invalid-value")
+    {
+        Some(step) if step.facts.get("value") == Some(&20i64.into()) => Ok(()),
+        other => Err(format!("FAIL: day 16 invalid value step was {:?}", other)),
     }
 }
 
-fn read_input(input: Vec<String>) -> Result<Input, String> {
-    // Read the fields.
-    let mut fields: Vec<Field> = Vec::new();
-    let mut lines = input.iter();
-    let premature_eof = Err("premature end-of-input".to_string());
+fn self_test() -> Result<(), String> {
+    aor2020::constraint::self_test()?;
+    aor2020::day16::self_test()?;
+    self_test_explain()
+}
 
-    loop {
-        match lines.next() {
-            None => {
-                return Err("my ticket is not here".to_string());
-            }
-            Some(s) if s.is_empty() => {
-                println!("reached the end of the field descriptions");
-                break;
-            }
-            Some(s) => {
-                println!("parsing a field description '{}'", s);
-                match Field::new(s) {
-                    Err(e) => return Err(e),
-                    Ok(f) => {
-                        fields.push(f);
-                    }
-                }
-            }
-        }
+/// Quote a CSV field if necessary.
+fn csv_field(s: &str) -> String {
+    if s.contains([',', '"', '\n']) {
+        format!("\"{}\"", s.replace('"', "\"\""))
+    } else {
+        s.to_string()
     }
+}
 
-    match lines.next() {
-        Some(s) if s == "your ticket:" => (),
-        Some(s) => {
-            return Err(format!("unexpected: {}", s));
-        }
-        None => {
-            return premature_eof;
-        }
-    };
-    let my_ticket = match lines.next() {
-        None => {
-            return premature_eof;
-        }
-        Some(s) => match read_ticket(s) {
-            Err(e) => {
-                return Err(e);
-            }
-            Ok(t) => t,
-        },
-    };
-    match lines.next() {
-        Some(s) if s.is_empty() => (),
-        Some(s) => {
-            return Err(format!("expected a blank line, not {}", s));
-        }
-        None => {
-            return premature_eof;
-        }
-    };
-    match lines.next() {
-        Some(s) if s == "nearby tickets:" => (),
-        Some(s) => {
-            return Err(format!("unexpected: {}", s));
-        }
-        None => {
-            return premature_eof;
-        }
-    };
-
-    let mut nearby: Vec<Ticket> = Vec::new();
-    loop {
-        match lines.next() {
-            None => {
-                break;
-            }
-            Some(s) => match read_ticket(s) {
-                Err(e) => {
-                    return Err(e);
-                }
-                Ok(t) => {
-                    nearby.push(t);
-                }
-            },
-        }
+/// Write the valid tickets as CSV, with a header row giving the
+/// field name at each position.
+fn export_csv(
+    out: &mut impl Write,
+    positions: &HashMap<String, usize>,
+    valid_tickets: &[Ticket],
+) -> io::Result<()> {
+    let mut names: Vec<(&usize, &String)> = positions.iter().map(|(n, p)| (p, n)).collect();
+    names.sort();
+    let header: Vec<String> = names.iter().map(|(_, name)| csv_field(name)).collect();
+    writeln!(out, "{}", header.join(","))?;
+    for t in valid_tickets {
+        writeln!(out, "{}", t)?;
     }
+    Ok(())
+}
 
-    Ok(Input {
-        fields,
-        my_ticket,
-        nearby,
-    })
+/// What to print.
+#[derive(Default)]
+enum Mode {
+    /// The puzzle answers.
+    #[default]
+    Answers,
+    /// My ticket, as a map from field name to value.
+    Decode,
+    /// The possible assignments of fields to positions.
+    Assignments,
+    /// The valid tickets, as CSV.
+    ExportCsv,
 }
 
-fn part1(input: &Input) -> Result<Vec<Ticket>, String> {
-    let mut invalid_values: Vec<i32> = Vec::new();
-    let mut valid_tickets: Vec<Ticket> = Vec::new();
-    for t in &input.nearby {
-        let mut this_ticket_valid = true;
-        for v in &t.values {
-            if !input.is_valid_value(v) {
-                invalid_values.push(*v);
-                this_ticket_valid = false;
-            }
-        }
-        if this_ticket_valid {
-            valid_tickets.push(t.clone());
-        }
-    }
-    let total: i32 = invalid_values.iter().sum();
-    println!("Part 1: total {}", total);
-    Ok(valid_tickets)
+#[derive(Default)]
+struct Options {
+    benchmark: bool,
+    explain_format: Option<explain::Format>,
+    limit: Option<usize>,
+    mode: Mode,
 }
 
-fn compute_candidates(
-    fields_by_name: &HashMap<String, Field>,
-    field_position: &HashMap<String, i32>,
-    nf: usize,
-    valid_tickets: &Vec<Ticket>,
-) -> HashMap<String, Vec<i32>> {
-    let mut candidates: HashMap<String, Vec<i32>> = HashMap::new();
-    let known_field_numbers: HashSet<i32> = field_position.values().cloned().collect();
-    for i in 0..nf {
-        if known_field_numbers.contains(&(i as i32)) {
-            continue;
-        }
-        for (field_name, f) in fields_by_name.iter() {
-            if field_position.contains_key(field_name) {
-                continue;
-            }
-            let mut invalid: bool = false;
-            for t in valid_tickets {
-                let v = &t.values.get(i).unwrap();
-                if !f.is_valid_value(v) {
-                    invalid = true;
-                    break;
+fn parse_args() -> Result<Options, String> {
+    let mut options = Options::default();
+    let mut args = std::env::args().skip(1);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--assignments" => options.mode = Mode::Assignments,
+            "--limit" => {
+                options.limit = match args.next().map(|s| s.parse::<usize>()) {
+                    Some(Ok(n)) => Some(n),
+                    _ => return Err("--limit requires a number".to_string()),
                 }
             }
-            if !invalid {
-                candidates
-                    .entry(field_name.to_string())
-                    .or_insert(Vec::new())
-                    .push(i as i32)
+            "--benchmark" => options.benchmark = true,
+            "--decode" => options.mode = Mode::Decode,
+            "--explain" => options.explain_format = Some(explain::Format::Text),
+            "--explain-format" => {
+                options.explain_format = match args.next() {
+                    Some(format) => Some(format.parse()?),
+                    None => return Err("--explain-format requires a format".to_string()),
+                }
             }
+            "--export" => match args.next().as_deref() {
+                Some("csv") => options.mode = Mode::ExportCsv,
+                Some(format) => return Err(format!("unsupported export format {}", format)),
+                None => return Err("--export requires a format".to_string()),
+            },
+            _ => return Err(format!("unknown option {}", arg)),
         }
     }
-    candidates
+    Ok(options)
 }
 
-fn part2(input: &Input, valid_tickets: &Vec<Ticket>) -> Result<(), String> {
-    let nf: usize = valid_tickets.get(0).unwrap().values.len();
-    let odd_length_tickets: usize = valid_tickets
-        .iter()
-        .filter(|t| t.values.len() != nf)
-        .count();
-    assert_eq!(0, odd_length_tickets);
-    let mut fields_by_name: HashMap<String, Field> = HashMap::new();
-    let mut fields_todo: HashSet<String> = HashSet::new();
-    let mut field_positions: HashMap<String, i32> = HashMap::new();
-    for f in &input.fields {
-        fields_by_name.insert(f.name.clone(), f.clone());
-        fields_todo.insert(f.name.clone());
+fn run(mut input: impl Read, options: &Options, out: &mut impl Write) -> Result<(), String> {
+    if options.benchmark {
+        return benchmark(300, 5000, out);
+    }
+    let mut text = String::new();
+    if let Err(e) = input.read_to_string(&mut text) {
+        return Err(format!("I/O error: {}", e));
     }
-    for iter in 1.. {
-        if fields_todo.is_empty() {
-            break;
+    let input = Input::parse(&text).map_err(|e| e.to_string())?;
+    log::debug!("Day 16: input:\n{}", input);
+    if let Some(format) = options.explain_format {
+        write_explanation(&explain(&input), format, out)?;
+        if format == explain::Format::Json {
+            return Ok(());
         }
-        let mut progress = false;
-        println!(
-            "iteration {}: unknown: {} fields ({:?})\nknown: {:?}",
-            iter,
-            fields_todo.len(),
-            fields_todo,
-            field_positions
-        );
-
-        let candidates = compute_candidates(&fields_by_name, &field_positions, nf, valid_tickets);
-        for (name, cands) in candidates {
-            println!("candidates for field {}: {:?}", name, cands);
-            if cands.len() == 1 {
-                let only = cands.first().unwrap();
-                field_positions.insert(name.to_string(), *only);
-                println!(
-                    "field {} must be at position {:?}",
-                    name,
-                    &field_positions.get(&name).unwrap()
-                );
-                assert!(fields_todo.remove(&name));
-                progress = true;
-            }
+    }
+    let (total, valid_tickets) = part1(&input);
+    match options.mode {
+        Mode::Assignments => {
+            return report_assignments(&input.fields, &valid_tickets, options.limit, out);
         }
-        assert!(progress);
+        Mode::Answers => writeln!(out, "Part 1: total {}", total).map_err(write_error)?,
+        _ => (),
     }
-    let ans: i64 = field_positions
-        .iter()
-        .filter(|(name, _)| name.starts_with("departure"))
-        .map(|(_, pos)| input.my_ticket.values[*pos as usize] as i64)
-        .product();
-    println!("Part 2: product = {}", ans);
-    Ok(())
-}
-
-fn run() -> Result<(), String> {
-    let mut input_lines: Vec<String> = Vec::new();
-    for input_item in io::BufReader::new(io::stdin()).lines() {
-        match input_item {
-            Err(e) => {
-                return Err(format!("I/O error: {}", e));
-            }
-            Ok(item) => {
-                input_lines.push(item);
-            }
+    let positions = resolve_positions(&input.fields, &valid_tickets)?;
+    let decoded = decode_ticket(&input.my_ticket, &positions)?;
+    match options.mode {
+        Mode::Decode => writeln!(
+            out,
+            "{}",
+            serde_json::to_string_pretty(&decoded).map_err(|e| e.to_string())?
+        )
+        .map_err(write_error)?,
+        Mode::ExportCsv => {
+            export_csv(out, &positions, &valid_tickets).map_err(|e| format!("I/O error: {}", e))?
         }
+        _ => writeln!(out, "Part 2: product = {}", part2(&decoded)).map_err(write_error)?,
     }
-    let input = read_input(input_lines)?;
-    println!("Day 16: input:\n{}", input);
-    let valid_tickets = part1(&input)?;
-    part2(&input, &valid_tickets)?;
     Ok(())
 }
 
 fn main() {
-    std::process::exit(match run() {
+    // the env logger is configured with $RUST_LOG.
+    // For example RUST_LOG=debug day16
+    pretty_env_logger::init();
+    let result = parse_args().and_then(|options| {
+        self_test()?;
+        run(io::stdin().lock(), &options, &mut io::stdout())
+    });
+    std::process::exit(match result {
         Ok(_) => 0,
         Err(err) => {
             eprintln!("error: {:?}", err);
@@ -337,3 +527,35 @@ fn main() {
         }
     });
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn output(input: &str, options: &Options) -> String {
+        let mut out = Vec::new();
+        run(input.as_bytes(), options, &mut out).unwrap();
+        String::from_utf8(out).unwrap()
+    }
+
+    #[test]
+    fn sample() {
+        insta::assert_snapshot!(output(
+            include_str!("../../samples/day16.txt"),
+            &Options::default()
+        ));
+    }
+
+    #[test]
+    fn short_ticket() {
+        let positions: HashMap<String, usize> = [("row".to_string(), 0), ("seat".to_string(), 2)]
+            .iter()
+            .cloned()
+            .collect();
+        let ticket = Ticket { values: vec![7, 1] };
+        assert_eq!(
+            decode_ticket(&ticket, &positions),
+            Err("your ticket has no value at position 2 (for field seat)".to_string())
+        );
+    }
+}
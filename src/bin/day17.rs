@@ -1,247 +1,271 @@
-use std::cmp::{max, min};
-use std::collections::HashMap;
-use std::collections::HashSet;
-use std::fmt;
-use std::io::{self, Read};
-use std::ops::RangeInclusive;
-
-type Ordinate = i64;
-type OrdinateRange = RangeInclusive<i64>;
-
-#[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
-struct Pos4 {
-    x: Ordinate,
-    y: Ordinate,
-    z: Ordinate,
-    w: Ordinate,
-}
-
-type Pos4Set = HashSet<Pos4>;
-type Pos4Counter = HashMap<Pos4, usize>;
+extern crate aor2020;
+#[cfg(test)]
+extern crate insta;
+extern crate serde_json;
 
-struct Lattice {
-    xrange: OrdinateRange,
-    yrange: OrdinateRange,
-    zrange: OrdinateRange,
-    wrange: OrdinateRange,
-    active: Pos4Set,
-    use_w: bool,
-}
+use aor2020::algorithm::Registry;
+use aor2020::day17::{
+    simulate_with_stats, Engine, Lattice, Ordinate, OrdinateRange, Rule, Stats, MEMO_MAX_DIMS,
+};
+use aor2020::output::write_error;
+use std::fs::{self, File};
+use std::io::{self, Read, Write};
+use std::path::Path;
 
-fn update_range(n: &Ordinate, r: OrdinateRange) -> OrdinateRange {
-    if r.contains(n) {
-        r
-    } else {
-        min(*n, *r.start())..=max(*n, *r.end())
+fn read_input(mut input: impl Read) -> Result<Lattice, String> {
+    let mut buffer = String::new();
+    match input.read_to_string(&mut buffer) {
+        Ok(_) => Lattice::from_string(&buffer),
+        Err(e) => Err(format!("I/O error: {}", e)),
     }
 }
 
-fn range_size(r: &OrdinateRange) -> usize {
-    if r.end() < r.start() {
-        0
-    } else {
-        let result: Ordinate = r.end() - r.start() + 1;
-        assert!(result > 0);
-        result as usize
-    }
+fn engines() -> Registry<Engine> {
+    Registry::for_day(17)
+        .register("sparse", "a set of the active cells", Engine::Sparse)
+        .register("dense", "an array of every cell", Engine::Dense)
+        .register(
+            "symmetric",
+            "an array of one of each set of mirror images",
+            Engine::Symmetric,
+        )
+        .register("memo", "memoised blocks of cells", Engine::Memo)
 }
 
-impl Clone for Lattice {
-    fn clone(&self) -> Lattice {
-        Lattice {
-            xrange: self.xrange.clone(),
-            yrange: self.yrange.clone(),
-            zrange: self.zrange.clone(),
-            wrange: self.wrange.clone(),
-            active: self.active.clone(),
-            use_w: self.use_w,
+/// Runs each of `runs` with `engine`, describing the final population
+/// of each.
+fn populations(
+    initial: &Lattice,
+    runs: &[(String, usize)],
+    options: &Options,
+    engine: Engine,
+) -> Result<String, String> {
+    let cycles = options.cycles.unwrap_or(6);
+    let rule = options.rule.clone().unwrap_or_default();
+    let mut populations = Vec::with_capacity(runs.len());
+    for (label, dims) in runs {
+        if engine == Engine::Memo && *dims > MEMO_MAX_DIMS {
+            return Err(format!("it handles at most {} dimensions", MEMO_MAX_DIMS));
         }
+        let (last, _) = simulate_with_stats(initial, *dims, cycles, &rule, engine);
+        populations.push(format!("{} {}", label, last.popcount()));
     }
+    Ok(populations.join(", "))
 }
 
-impl Lattice {
-    fn popcount(&self) -> usize {
-        self.active.len()
-    }
+fn export_obj(lattice: &Lattice, higher: &[Ordinate], filename: &str) -> Result<(), String> {
+    let fail = |e: io::Error| format!("failed to write {}: {}", filename, e);
+    let f = File::create(filename).map_err(fail)?;
+    lattice
+        .write_obj(higher, &mut io::BufWriter::new(f))
+        .map_err(fail)
+}
 
-    fn slice_as_str(&self, w: Ordinate, z: Ordinate) -> String {
-        let mut output =
-            String::with_capacity((range_size(&self.xrange) + 1) * range_size(&self.yrange));
-        for y in self.yrange.clone() {
-            for x in self.xrange.clone() {
-                let pos = Pos4 { x, y, z, w };
-                output.push(if self.active.contains(&pos) { '#' } else { '.' })
-            }
-            output.push('\n');
-        }
-        output
-    }
+/// The number of pixels along each side of a cell in rendered images.
+const RENDER_SCALE: usize = 8;
 
-    fn from_string(s: &str) -> Result<Lattice, String> {
-        let mut xrange: OrdinateRange = 0..=0;
-        let mut yrange: OrdinateRange = 0..=0;
-        let mut x = 0;
-        let mut y = 0;
-        let mut cells: Pos4Set = Pos4Set::new();
-        for ch in s.chars() {
-            match ch {
-                '#' => {
-                    cells.insert(Pos4 { x, y, z: 0, w: 0 });
-                    xrange = update_range(&x, xrange.clone());
-                    yrange = update_range(&y, yrange.clone());
-                    x += 1;
-                }
-                '.' => {
-                    x += 1;
-                }
-                '\n' => {
-                    x = 0;
-                    y += 1;
-                }
-                _ => {
-                    return Err(format!("unexpected character '{}' in input", ch));
-                }
-            }
-        }
-        Ok(Lattice {
-            xrange,
-            yrange,
-            zrange: 0..=0,
-            wrange: 0..=0,
-            active: cells,
-            use_w: false,
-        })
+/// Writes the x-y slices of `lattice` at each z (and at `higher` for
+/// the axes beyond z) as PNG images.  Every image covers `envelope`,
+/// so the images of every iteration line up.
+fn render_iteration(
+    lattice: &Lattice,
+    envelope: &[OrdinateRange],
+    higher: &[Ordinate],
+    dir: &Path,
+    iteration: usize,
+) -> Result<(), String> {
+    let dims = lattice.dims();
+    let (xrange, yrange) = (&envelope[0], &envelope[1]);
+    if dims == 2 {
+        let filename = dir.join(format!("{}d-{:02}.png", dims, iteration));
+        return lattice
+            .render_slice(&[], xrange, yrange, RENDER_SCALE)
+            .save_png(&filename);
     }
-
-    fn neighbours_of(&self, pos: &Pos4) -> Vec<Pos4> {
-        let wrange = if self.use_w {
-            (pos.w - 1)..=(pos.w + 1)
-        } else {
-            pos.w..=pos.w
-        };
-        let mut result = Vec::with_capacity(range_size(&wrange) * 3 * 3 * 3);
-        for w in wrange {
-            for z in (pos.z - 1)..=(pos.z + 1) {
-                for y in (pos.y - 1)..=(pos.y + 1) {
-                    for x in (pos.x - 1)..=(pos.x + 1) {
-                        let neighbour = Pos4 { w, z, y, x };
-                        if neighbour != *pos {
-                            // can't be my own neighbour
-                            result.push(neighbour);
-                        }
-                    }
-                }
-            }
-        }
-        result
+    for z in envelope[2].clone() {
+        let mut slice = vec![z];
+        slice.extend_from_slice(higher);
+        let filename = dir.join(format!("{}d-{:02}-z{}.png", dims, iteration, z));
+        lattice
+            .render_slice(&slice, xrange, yrange, RENDER_SCALE)
+            .save_png(&filename)?;
     }
+    Ok(())
+}
 
-    fn insert(&mut self, pos: Pos4) {
-        if !self.use_w {
-            assert!(pos.w == 0)
-        }
-        self.active.insert(pos);
-        self.xrange = update_range(&pos.x, self.xrange.clone());
-        self.yrange = update_range(&pos.y, self.yrange.clone());
-        self.zrange = update_range(&pos.z, self.zrange.clone());
-        self.wrange = update_range(&pos.w, self.wrange.clone());
+/// Runs the simulation in a lattice of `dims` dimensions, exporting
+/// or rendering each iteration (including the initial state) if asked
+/// to.  The statistics of each state are returned alongside the final
+/// state.
+fn run_dims(
+    initial: &Lattice,
+    dims: usize,
+    options: &Options,
+) -> Result<(Lattice, Vec<Stats>), String> {
+    let cycles = options.cycles.unwrap_or(6);
+    let engine = options.engine.unwrap_or(Engine::Sparse);
+    let rule = options.rule.clone().unwrap_or_default();
+    if options.export_obj.is_none() && options.render.is_none() {
+        return Ok(simulate_with_stats(initial, dims, cycles, &rule, engine));
     }
-
-    fn count_neighbours(&self) -> Pos4Counter {
-        let mut neighbour_count: Pos4Counter = Pos4Counter::new();
-        for neighbour in self.active.iter().flat_map(|pos| self.neighbours_of(pos)) {
-            neighbour_count
-                .entry(neighbour)
-                .and_modify(|e| *e += 1)
-                .or_insert(1);
-        }
-        neighbour_count
+    // Ordinates beyond the last axis of the lattice are ignored.
+    let higher: Vec<Ordinate> = (3..dims)
+        .map(|axis| options.slice.get(axis - 3).copied().unwrap_or(0))
+        .collect();
+    let mut current = initial.with_dims(dims);
+    // Cells spread by at most one cell per cycle.
+    let envelope: Vec<OrdinateRange> = current
+        .ranges()
+        .iter()
+        .map(|r| (r.start() - cycles as Ordinate)..=(r.end() + cycles as Ordinate))
+        .collect();
+    if let Some(dir) = &options.render {
+        fs::create_dir_all(dir).map_err(|e| format!("failed to create {}: {}", dir, e))?;
     }
-
-    fn decide_actives(&self, neighbour_count: &Pos4Counter) -> Lattice {
-        let mut result = Lattice {
-            xrange: 0..=0,
-            yrange: 0..=0,
-            zrange: 0..=0,
-            wrange: 0..=0,
-            use_w: self.use_w,
-            active: Pos4Set::new(),
-        };
-        for p in itertools::chain(
-            // First, consider possible state changes in the cells
-            // that are currently active.
-            self.active.iter().filter(|currently_active| {
-                // Active cells with 3 active neighbours are inserted
-                // as a result of the pipeline acting on
-                // neighbour_count, below, and so we omit them here to
-                // avoid duplicate work.
-                matches!(neighbour_count.get(currently_active), Some(2))
-            }),
-            // Second, consider possible state changes in the cells
-            // that are not currently active.
-            neighbour_count
-                .iter()
-                // The filter acceps cells that are inactive but will
-                // become active, and also active cells with 3 neighbours
-                // (which should stay active).
-                .filter(|(_, num_neighbours)| **num_neighbours == 3)
-                .map(|(pos, _)| pos),
-        ) {
-            result.insert(*p) // updates ranges also.
+    let mut series = vec![current.stats()];
+    for iteration in 0..=cycles {
+        if iteration > 0 {
+            let (next, mut stats) = simulate_with_stats(&current, dims, 1, &rule, engine);
+            current = next;
+            series.extend(stats.pop());
+        }
+        if let Some(prefix) = &options.export_obj {
+            export_obj(
+                &current,
+                &higher,
+                &format!("{}-{}d-{:02}.obj", prefix, dims, iteration),
+            )?;
+        }
+        if let Some(dir) = &options.render {
+            render_iteration(&current, &envelope, &higher, Path::new(dir), iteration)?;
         }
-        result
     }
+    Ok((current, series))
+}
 
-    fn iterate(&self) -> Lattice {
-        let neighbour_count = self.count_neighbours();
-        self.decide_actives(&neighbour_count)
-    }
+fn write_stats(runs: &[serde_json::Value], filename: &str) -> Result<(), String> {
+    let fail = |e: String| format!("failed to write {}: {}", filename, e);
+    let f = File::create(filename).map_err(|e| fail(e.to_string()))?;
+    serde_json::to_writer_pretty(io::BufWriter::new(f), runs).map_err(|e| fail(e.to_string()))
 }
 
-impl fmt::Display for Lattice {
-    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        for w in self.wrange.clone() {
-            for z in self.zrange.clone() {
-                write!(f, "z={},w={}\n{}", z, w, self.slice_as_str(w, z))?
-            }
-        }
-        Ok(())
-    }
+fn self_test() -> Result<(), String> {
+    aor2020::visualization::self_test()?;
+    aor2020::day17::self_test()
 }
 
-fn read_input() -> Result<Lattice, String> {
-    let mut buffer = String::new();
-    match io::stdin().read_to_string(&mut buffer) {
-        Ok(_) => Lattice::from_string(&buffer),
-        Err(e) => Err(format!("I/O error: {}", e)),
-    }
+#[derive(Default)]
+struct Options {
+    crosscheck: bool,
+    cycles: Option<usize>,
+    dims: Option<usize>,
+    engine: Option<Engine>,
+    export_obj: Option<String>,
+    render: Option<String>,
+    rule: Option<Rule>,
+    slice: Vec<Ordinate>,
+    stats: Option<String>,
 }
 
-fn part_n(part_num: i32, initial: &Lattice, num_cycles: usize, use_w: bool) {
-    let mut begin: Lattice = initial.clone();
-    begin.use_w = use_w;
-    let mut current: Lattice = begin.iterate();
-    for _iteration in 1..num_cycles {
-        current = current.iterate();
+fn parse_args() -> Result<Options, String> {
+    let mut options = Options::default();
+    let mut args = std::env::args().skip(1);
+    while let Some(arg) = args.next() {
+        let mut value = || {
+            args.next()
+                .ok_or_else(|| format!("option {} requires an argument", arg))
+        };
+        match arg.as_str() {
+            "crosscheck" => options.crosscheck = true,
+            "--cycles" => {
+                let v = value()?;
+                options.cycles = Some(
+                    v.parse()
+                        .map_err(|e| format!("invalid cycle count '{}': {}", v, e))?,
+                );
+            }
+            "--dims" => {
+                let v = value()?;
+                let dims: usize = v
+                    .parse()
+                    .map_err(|e| format!("invalid dimension count '{}': {}", v, e))?;
+                if dims < 2 {
+                    return Err("the lattice must have at least 2 dimensions".to_string());
+                }
+                options.dims = Some(dims);
+            }
+            "--algorithm" | "--engine" => options.engine = Some(engines().get(&value()?)?.solver),
+            "--export-obj" => options.export_obj = Some(value()?),
+            "--render" => options.render = Some(value()?),
+            "--rule" => options.rule = Some(value()?.parse()?),
+            "--stats" => options.stats = Some(value()?),
+            "--slice" => {
+                let v = value()?;
+                options.slice = v
+                    .split(',')
+                    .map(|n| n.parse())
+                    .collect::<Result<Vec<Ordinate>, _>>()
+                    .map_err(|e| format!("invalid slice '{}': {}", v, e))?;
+            }
+            _ if arg.starts_with('-') => return Err(format!("unknown option {}", arg)),
+            _ => return Err(format!("unexpected argument {}", arg)),
+        }
     }
-    println!(
-        "Part {}: after {} iterations, population is {}",
-        part_num,
-        num_cycles,
-        current.popcount()
-    );
+    if options.engine == Some(Engine::Memo) && options.dims.unwrap_or(4) > MEMO_MAX_DIMS {
+        return Err(format!(
+            "the memo algorithm handles at most {} dimensions",
+            MEMO_MAX_DIMS
+        ));
+    }
+    Ok(options)
 }
 
-fn run() -> Result<(), String> {
-    let initial = read_input()?;
-    println!("Initial state is:\n{}", initial);
-    part_n(1, &initial, 6, false);
-    part_n(2, &initial, 6, true);
+fn run(input: impl Read, options: &Options, out: &mut impl Write) -> Result<(), String> {
+    let initial = read_input(input)?;
+    if options.render.is_none() && !options.crosscheck {
+        // Large lattices are better inspected with --render.
+        writeln!(out, "Initial state is:\n{}", initial).map_err(write_error)?;
+    }
+    let cycles = options.cycles.unwrap_or(6);
+    let runs: Vec<(String, usize)> = match options.dims {
+        Some(dims) => vec![(format!("{} dimensions", dims), dims)],
+        None => vec![("Part 1".to_string(), 3), ("Part 2".to_string(), 4)],
+    };
+    if options.crosscheck {
+        let report =
+            engines().crosscheck(|engine| populations(&initial, &runs, options, *engine))?;
+        writeln!(out, "{}", report).map_err(write_error)?;
+        return Ok(());
+    }
+    let mut stats_runs = Vec::with_capacity(runs.len());
+    for (label, dims) in runs {
+        let (last, series) = run_dims(&initial, dims, options)?;
+        writeln!(
+            out,
+            "{}: after {} iterations, population is {}",
+            label,
+            cycles,
+            last.popcount()
+        )
+        .map_err(write_error)?;
+        stats_runs.push(serde_json::json!({
+            "dims": dims,
+            "rule": options.rule.clone().unwrap_or_default().to_string(),
+            "cycles": series,
+        }));
+    }
+    if let Some(filename) = &options.stats {
+        write_stats(&stats_runs, filename)?;
+    }
     Ok(())
 }
 
 fn main() {
-    std::process::exit(match run() {
+    let result = parse_args().and_then(|options| {
+        self_test()?;
+        run(io::stdin().lock(), &options, &mut io::stdout())
+    });
+    std::process::exit(match result {
         Ok(_) => 0,
         Err(err) => {
             eprintln!("error: {:?}", err);
@@ -249,3 +273,22 @@ fn main() {
         }
     });
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn output(input: &str, options: &Options) -> String {
+        let mut out = Vec::new();
+        run(input.as_bytes(), options, &mut out).unwrap();
+        String::from_utf8(out).unwrap()
+    }
+
+    #[test]
+    fn sample() {
+        insta::assert_snapshot!(output(
+            include_str!("../../samples/day17.txt"),
+            &Options::default()
+        ));
+    }
+}
@@ -1,270 +1,217 @@
+extern crate aor2020;
+#[cfg(test)]
+extern crate insta;
+extern crate serde_json;
+
+use aor2020::algorithm::Registry;
+use aor2020::day18::{eval, Closure, Engine, Environment, Expr, Parser, Program, Rules, Statement};
+use aor2020::output::write_error;
 use std::io;
 use std::io::BufRead;
+use std::io::Write;
+use std::str::FromStr;
+use std::time::Instant;
 
-#[derive(Debug, Clone, Copy, PartialEq)]
-enum Token {
-    Number(i64),
-    LeftParen,
-    RightParen,
-    Operator(char),
-}
-
-struct Lexer {
-    data: Vec<char>,
-    pos: usize,
-    current: Option<Token>,
+/// The ways in which --show-ast can print the parsed expressions.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum AstFormat {
+    SExpr,
+    Tree,
+    Json,
 }
 
-impl Lexer {
-    fn new(s: &str) -> Lexer {
-        let mut result = Lexer {
-            data: s.chars().collect(),
-            pos: 0,
-            current: None,
-        };
-        result.consume();
-        result
-    }
+impl FromStr for AstFormat {
+    type Err = String;
 
-    fn unread_input(&self) -> String {
-        (self.pos..self.data.len()).map(|i| self.data[i]).collect()
-    }
-
-    fn next(&self) -> Option<Token> {
-        self.current
+    fn from_str(s: &str) -> Result<AstFormat, String> {
+        match s {
+            "sexpr" => Ok(AstFormat::SExpr),
+            "tree" => Ok(AstFormat::Tree),
+            "json" => Ok(AstFormat::Json),
+            _ => Err(format!("unknown AST format '{}'", s)),
+        }
     }
+}
 
-    fn consume(&mut self) {
-        // replace current ...
-        self.current = {
-            // ... after skipping any spaces ...
-            while self.pos != self.data.len() {
-                if self.data[self.pos] != ' ' {
-                    break;
-                }
-                self.pos += 1;
-            }
-            // ... and bailing out if we already reached end-of-input ...
-            if self.pos == self.data.len() {
-                None
-            } else {
-                // ... with the next token
-                let ch = self.data[self.pos];
-                match ch {
-                    '(' => {
-                        self.pos += 1;
-                        Some(Token::LeftParen)
-                    }
-                    ')' => {
-                        self.pos += 1;
-                        Some(Token::RightParen)
-                    }
-                    '+' | '*' => {
-                        self.pos += 1;
-                        Some(Token::Operator(ch))
-                    }
-                    '0' | '1' | '2' | '3' | '4' | '5' | '6' | '7' | '8' | '9' => {
-                        let mut result = String::new();
-                        while self.pos < self.data.len() {
-                            match self.data[self.pos] {
-                                '0' | '1' | '2' | '3' | '4' | '5' | '6' | '7' | '8' | '9' => {
-                                    result.push(self.data[self.pos]);
-                                    self.pos += 1
-                                }
-                                _ => {
-                                    break;
-                                }
-                            }
-                        }
-                        Some(Token::Number(
-                            result.parse().expect("surprisingly invalid integer"),
-                        ))
-                    }
-                    _ => {
-                        panic!("unexpected character '{}' in input", ch);
-                    }
-                }
+impl AstFormat {
+    fn format(&self, tree: &Expr) -> String {
+        match self {
+            AstFormat::SExpr => format!("{}\n", tree),
+            AstFormat::Tree => tree.to_tree(),
+            AstFormat::Json => {
+                serde_json::to_string(tree).expect("expressions can always be serialized") + "\n"
             }
         }
     }
 }
 
-#[derive(Debug)]
-enum Expr {
-    Constant(i64),                  // some integer
-    Op(Box<Expr>, char, Box<Expr>), // some binary expression
-}
-
-#[derive(Clone, Copy)]
-struct Rules {
-    precedence_plus: i64,
-    precedence_times: i64,
-}
-
-struct Parser {
-    rules: Rules,
+/// Parses line n (counting from 0) of the input, returning the name
+/// it binds (if it is a let) and its expression.
+fn parse_line(p: &Parser, n: usize, line: &str) -> Result<(Option<String>, Expr), String> {
+    let statement = p
+        .parse_statement(line)
+        .map_err(|e| format!("line {}: {}\n{}", n + 1, e, e.render(line)))?;
+    Ok(match statement {
+        Statement::Let(name, tree) => (Some(name), tree),
+        Statement::Expr(tree) => (None, tree),
+    })
 }
 
-// A recursive descent parser for arithmetic expressions which uses
-// configurable precedence values to avoid having separate
-// implementations for parts 1 and 2.
-//
-// Based on http://www.engr.mun.ca/~theo/Misc/exp_parsing.htm
-impl Parser {
-    fn prec(&self, b: &Option<Token>) -> Result<i64, String> {
-        match b {
-            None => Ok(-1),
-            Some(Token::Operator('+')) => Ok(self.rules.precedence_plus),
-            Some(Token::Operator('*')) => Ok(self.rules.precedence_times),
-            Some(Token::Operator(ch)) => Err(format!("[E0200] unknown operator {}", ch)),
-            Some(t) => Err(format!("[E0300] unexpected token {:?}", t)),
-        }
-    }
-
-    fn right_prec(&self, b: &Option<Token>) -> Result<i64, String> {
-        self.prec(b).map(|p| if p < 0 { p } else { p + 1 })
-    }
-
-    fn next_prec(&self, b: &Option<Token>) -> Result<i64, String> {
-        self.prec(b)
-    }
-
-    fn prec_is_between(&self, low: i64, t: &Option<Token>, high: i64) -> bool {
-        if let Ok(p) = self.prec(t) {
-            p >= low && p <= high
-        } else {
-            false
+/// Totals the values of the `lines` lines of the input which are not
+/// lets.  `evaluate(i, env)` gives the name line i binds (if it is a
+/// let) and its value.
+fn total<F>(lines: usize, mut evaluate: F) -> Result<i64, String>
+where
+    F: FnMut(usize, &Environment) -> Result<(Option<String>, i64), String>,
+{
+    let mut total: i64 = 0;
+    let mut env = Environment::new();
+    for i in 0..lines {
+        let (name, value) = evaluate(i, &env)?;
+        match name {
+            Some(name) => {
+                env.insert(name, value);
+            }
+            None => total += value,
         }
     }
+    Ok(total)
+}
 
-    fn parse_paren_expr(&self, lex: &mut Lexer) -> Result<Expr, String> {
-        lex.consume(); // consume the (
-        self.parse_expression(0, lex) // and the contents
-            .and_then(|r| {
-                match lex.next() {
-                    Some(Token::RightParen) => {
-                        // and finally the closing )
-                        lex.consume();
-                        Ok(r)
-                    }
-                    _ => Err("expected a closing ')'".to_string()),
-                }
-            })
-    }
+/// The number of times --benchmark evaluates the whole input, unless
+/// --repeat says otherwise.
+const BENCHMARK_ROUNDS: usize = 1000;
 
-    fn parse_operand(&self, lex: &mut Lexer) -> Result<Expr, String> {
-        match lex.next() {
-            Some(Token::LeftParen) => self.parse_paren_expr(lex),
-            Some(Token::Number(n)) => {
-                lex.consume();
-                Ok(Expr::Constant(n))
-            }
-            _ => Err("[E0600] expected a number or an open parenthesis".to_string()),
-        }
+/// Compares the speed of the tree-walking evaluator, the closures and
+/// the stack machine, evaluating the whole input `rounds` times with
+/// each.  Only evaluation is timed, not parsing or compilation.
+fn benchmark(
+    label: &str,
+    input: &[String],
+    rules: &Rules,
+    rounds: usize,
+    out: &mut impl Write,
+) -> Result<(), String> {
+    let p = Parser::new(rules.clone());
+    let mut names = Vec::with_capacity(input.len());
+    let mut trees = Vec::with_capacity(input.len());
+    for (n, line) in input.iter().enumerate() {
+        let (name, tree) = parse_line(&p, n, line)?;
+        names.push(name);
+        trees.push(tree);
     }
+    let closures: Vec<Closure> = trees.iter().map(Closure::compile).collect();
+    let programs: Vec<Program> = trees.iter().map(Program::compile).collect();
 
-    fn parse_expression(&self, precedence: i64, lex: &mut Lexer) -> Result<Expr, String> {
-        assert!(precedence >= 0);
-        let mut lhs: Expr = self.parse_operand(lex)?;
-        let mut stop_at_prec: i64 = 1000;
-        while self.prec_is_between(precedence, &lex.next(), stop_at_prec) {
-            let operator = lex.next();
-            lex.consume();
-            lhs = match operator {
-                Some(Token::Operator(ch)) if (ch == '+' || ch == '*') => {
-                    let prec = self.right_prec(&operator)?;
-                    let rhs = self.parse_expression(prec, lex)?;
-                    Expr::Op(Box::new(lhs), ch, Box::new(rhs))
-                }
-                _ => {
-                    return Err(format!("[E1050] expected '+' or '*', got '{:?}'", operator));
-                }
+    let mut results = Vec::new();
+    for engine in engines().algorithms() {
+        let engine = engine.solver;
+        let start = Instant::now();
+        let mut engine_total = 0;
+        for _ in 0..rounds {
+            engine_total = match engine {
+                Engine::Tree => total(names.len(), |i, env| {
+                    Ok((names[i].clone(), eval(&trees[i], env)?))
+                })?,
+                Engine::Closure => total(names.len(), |i, env| {
+                    Ok((names[i].clone(), closures[i].run(env)?))
+                })?,
+                Engine::Vm => total(names.len(), |i, env| {
+                    Ok((names[i].clone(), programs[i].run(env)?))
+                })?,
             };
-            stop_at_prec = self.next_prec(&operator)?;
         }
-        Ok(lhs)
+        results.push((engine, engine_total, start.elapsed()));
     }
-
-    fn parse(&self, expr_str: &str) -> Result<Expr, String> {
-        let mut lex = Lexer::new(expr_str);
-        let tree = self.parse_expression(0, &mut lex)?;
-        // check we parsed the whole expression
-        match lex.next() {
-            None => Ok(tree),
-            Some(token) => Err(format!(
-                "[E1100] unexpected {:?}; unread input is '{}'",
-                token,
-                lex.unread_input()
-            )),
-        }
+    let (_, tree_total, tree_time) = results[0];
+    let reports: Vec<String> = results
+        .iter()
+        .map(|(engine, engine_total, time)| {
+            format!(
+                "{} {} ({:?}, speedup {:.1}x)",
+                engine,
+                engine_total,
+                time,
+                tree_time.as_secs_f64() / time.as_secs_f64()
+            )
+        })
+        .collect();
+    writeln!(out, "{}: {} rounds: {}", label, rounds, reports.join(", ")).map_err(write_error)?;
+    if let Some((engine, engine_total, _)) = results.iter().find(|(_, t, _)| *t != tree_total) {
+        return Err(format!(
+            "{}: {} gives a total of {} but tree gives {}",
+            label, engine, engine_total, tree_total
+        ));
     }
+    Ok(())
 }
 
-fn eval(tree: &Expr) -> i64 {
-    match tree {
-        Expr::Constant(n) => *n,
-        Expr::Op(e1, op, e2) => {
-            let v1 = eval(e1);
-            let v2 = eval(e2);
-            match op {
-                '+' => v1 + v2,
-                '*' => v1 * v2,
-                _ => {
-                    panic!("unexpected operator '{}'", op);
-                }
-            }
+fn engines() -> Registry<Engine> {
+    Registry::for_day(18)
+        .register("tree", "walks the syntax tree", Engine::Tree)
+        .register("closure", "calls nested closures", Engine::Closure)
+        .register("vm", "runs a stack machine", Engine::Vm)
+}
+
+/// Totals the values of the unnamed expressions in `input`, as
+/// evaluated by `engine`, writing the syntax trees and calculations
+/// to `out` if `options` asks for them.
+fn evaluate_all(
+    p: &Parser,
+    input: &[String],
+    engine: Engine,
+    options: &Options,
+    out: &mut impl Write,
+) -> Result<i64, String> {
+    total(input.len(), |n, env| {
+        let line = &input[n];
+        let (name, tree) = parse_line(p, n, line)?;
+        if let Some(format) = options.show_ast {
+            write!(out, "{}", format.format(&tree)).map_err(write_error)?;
         }
-    }
+        let value = engine.evaluate(&tree, env)?;
+        if options.show_calcs {
+            writeln!(out, "{} -> {}", line, value).map_err(write_error)?;
+        }
+        Ok((name, value))
+    })
 }
 
 fn parse_evaluate_and_total(
-    part_number: i32,
-    input: &Vec<String>,
-    show_calcs: bool,
+    label: &str,
+    input: &[String],
+    options: &Options,
     rules: &Rules,
+    out: &mut impl Write,
 ) -> Result<(), String> {
-    let p = Parser { rules: *rules };
-
-    let mut total: i64 = 0;
-    for line in input {
-        let tree = p.parse(line)?;
-        let value = eval(&tree);
-        if show_calcs {
-            println!("{} -> {}", line, value);
-        }
-        total += value;
+    if options.benchmark || options.repeat.is_some() {
+        let rounds = options.repeat.unwrap_or(BENCHMARK_ROUNDS);
+        return benchmark(label, input, rules, rounds, out);
     }
-    println!("Part {}: total = {:?}", part_number, total);
-    Ok(())
+    let p = Parser::new(rules.clone());
+    if options.crosscheck {
+        let report = engines().crosscheck(|engine| {
+            evaluate_all(&p, input, *engine, &Options::default(), &mut io::sink())
+        })?;
+        writeln!(out, "{}:\n{}", label, report).map_err(write_error)?;
+        return Ok(());
+    }
+    let engine = options.engine.unwrap_or(Engine::Tree);
+    let total = evaluate_all(&p, input, engine, options, out)?;
+    writeln!(out, "{}: total = {:?}", label, total).map_err(write_error)
 }
 
-fn part1(input: &Vec<String>, show_calcs: bool) -> Result<(), String> {
-    parse_evaluate_and_total(
-        1,
-        input,
-        show_calcs,
-        &Rules {
-            precedence_plus: 20,
-            precedence_times: 20,
-        },
-    )
+fn part1(input: &[String], options: &Options, out: &mut impl Write) -> Result<(), String> {
+    parse_evaluate_and_total("Part 1", input, options, &Rules::part1(), out)
 }
 
-fn part2(input: &Vec<String>, show_calcs: bool) -> Result<(), String> {
-    parse_evaluate_and_total(
-        2,
-        input,
-        show_calcs,
-        &Rules {
-            precedence_plus: 30,
-            precedence_times: 20,
-        },
-    )
+fn part2(input: &[String], options: &Options, out: &mut impl Write) -> Result<(), String> {
+    parse_evaluate_and_total("Part 2", input, options, &Rules::part2(), out)
 }
 
-fn read_input() -> Result<Vec<String>, String> {
+fn read_input(input: impl BufRead) -> Result<Vec<String>, String> {
     let mut input_lines: Vec<String> = Vec::new();
-    for input_item in io::BufReader::new(io::stdin()).lines() {
+    for input_item in input.lines() {
         match input_item {
             Err(e) => {
                 return Err(format!("I/O error: {}", e));
@@ -277,16 +224,101 @@ fn read_input() -> Result<Vec<String>, String> {
     Ok(input_lines)
 }
 
-fn run() -> Result<(), String> {
-    let input: Vec<String> = read_input()?;
-    let show_calcs = false;
-    part1(&input, show_calcs)?;
-    part2(&input, show_calcs)?;
+fn self_test() -> Result<(), String> {
+    aor2020::day18::self_test()?;
+    let p = Parser::new(Rules::part1());
+    if AstFormat::Json.format(&p.parse("-1")?) != "{\"Neg\":{\"Constant\":1}}\n" {
+        return Err("FAIL: wrong JSON for '-1'".to_string());
+    }
+    Ok(())
+}
+
+#[derive(Default)]
+struct Options {
+    benchmark: bool,
+    crosscheck: bool,
+    engine: Option<Engine>,
+    repeat: Option<usize>,
+    rules: Option<Rules>,
+    show_ast: Option<AstFormat>,
+    show_calcs: bool,
+    show_rules: bool,
+}
+
+fn parse_args() -> Result<Options, String> {
+    let mut options = Options::default();
+    let mut args = std::env::args().skip(1);
+    while let Some(arg) = args.next() {
+        let mut value = || {
+            args.next()
+                .ok_or_else(|| format!("option {} requires an argument", arg))
+        };
+        match arg.as_str() {
+            "--benchmark" => options.benchmark = true,
+            "crosscheck" => options.crosscheck = true,
+            "--algorithm" | "--engine" => options.engine = Some(engines().get(&value()?)?.solver),
+            "--operator" => {
+                let spec = value()?;
+                options
+                    .rules
+                    .get_or_insert_with(Rules::default)
+                    .add(&spec)?;
+            }
+            "--preset" => {
+                options.rules = Some(match value()?.as_str() {
+                    "part1" => Rules::part1(),
+                    "part2" => Rules::part2(),
+                    other => return Err(format!("unknown preset '{}'", other)),
+                });
+            }
+            "--repeat" => {
+                let v = value()?;
+                options.repeat = Some(
+                    v.parse()
+                        .map_err(|e| format!("invalid repeat count '{}': {}", v, e))?,
+                );
+            }
+            "--rules" => {
+                let filename = value()?;
+                options
+                    .rules
+                    .get_or_insert_with(Rules::default)
+                    .load(&filename)?;
+            }
+            "--show-ast" => options.show_ast = Some(value()?.parse()?),
+            "--show-calcs" => options.show_calcs = true,
+            "--show-rules" => options.show_rules = true,
+            _ => return Err(format!("unknown option {}", arg)),
+        }
+    }
+    Ok(options)
+}
+
+fn run(input: impl BufRead, options: &Options, out: &mut impl Write) -> Result<(), String> {
+    let input: Vec<String> = read_input(input)?;
+    match &options.rules {
+        // Options are applied in order, so for example "--preset
+        // part2 --operator '/ 30'" modifies the part 2 rules.
+        Some(rules) => {
+            if options.show_rules {
+                write!(out, "{}", rules).map_err(write_error)?;
+            }
+            parse_evaluate_and_total("Custom rules", &input, options, rules, out)?;
+        }
+        None => {
+            part1(&input, options, out)?;
+            part2(&input, options, out)?;
+        }
+    }
     Ok(())
 }
 
 fn main() {
-    std::process::exit(match run() {
+    let result = parse_args().and_then(|options| {
+        self_test()?;
+        run(io::stdin().lock(), &options, &mut io::stdout())
+    });
+    std::process::exit(match result {
         Ok(_) => 0,
         Err(err) => {
             eprintln!("error: {}", err);
@@ -294,3 +326,22 @@ fn main() {
         }
     });
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn output(input: &str, options: &Options) -> String {
+        let mut out = Vec::new();
+        run(input.as_bytes(), options, &mut out).unwrap();
+        String::from_utf8(out).unwrap()
+    }
+
+    #[test]
+    fn sample() {
+        insta::assert_snapshot!(output(
+            include_str!("../../samples/day18.txt"),
+            &Options::default()
+        ));
+    }
+}
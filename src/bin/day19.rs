@@ -1,28 +1,38 @@
+extern crate aor2020;
+#[cfg(test)]
+extern crate insta;
 extern crate itertools;
 extern crate lazy_static;
 extern crate regex;
+extern crate serde;
+extern crate serde_json;
+use aor2020::algorithm::Registry;
+use aor2020::day19::{CharClass, Cyk, Derivations, Grammar, Nfa, RuleId, Symbol};
+use aor2020::output::write_error;
 use itertools::Itertools;
 use lazy_static::lazy_static;
-use regex::Regex;
+use regex::{Regex, RegexBuilder};
+use serde::{Deserialize, Serialize};
 use std::cmp;
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap};
 use std::fmt;
+use std::fs::File;
 use std::io;
 use std::io::BufRead;
+use std::io::Write;
 
 lazy_static! {
     static ref RULE_RE: Regex = Regex::new(r"^(\d+): (.*)$").expect("RULE_RE");
-    static ref LIT_RE: Regex = Regex::new("\"(.)\"$").expect("LIT_RE");
+    static ref LIT_RE: Regex = Regex::new("^\\s*\"([^\"]+)\"\\s*$").expect("LIT_RE");
     static ref ALT_RE: Regex = Regex::new(r"([^|]+)[|]([^|]+)$").expect("ALT_RE");
 }
 
-type RuleId = i32;
-
-#[derive(PartialEq, Debug)]
+#[derive(PartialEq, Debug, Clone)]
 enum Rule {
     Sequence(Vec<RuleId>),                 // e.g. [28: 16 1]
     Alternative(Vec<RuleId>, Vec<RuleId>), // e.g. [26: 14 22 | 1 20]
-    Literal(char),                         // e.g. [14: "b"]
+    Literal(String),                       // e.g. [14: "b"] or [3: "abc"]
+    Class(CharClass),                      // e.g. [7: [a-z]]
     CannedRegex(String),                   // used for part 2.
 }
 
@@ -30,7 +40,7 @@ fn make_group(pattern: &str) -> String {
     // By using non-capturing groups we save memory and compute when
     // performing pattern matching (and, probably, memory when
     // compiling the regex).
-    format!("(:?{})", pattern)
+    format!("(?:{})", pattern)
 }
 
 fn repeat(s: &str, n: usize) -> String {
@@ -86,7 +96,8 @@ fn translate_to_regex_pattern(
             translate_alternative(left_items, right_items, rules)
         }
         Some(Rule::CannedRegex(pattern)) => Ok(pattern.to_string()),
-        Some(Rule::Literal(ch)) => Ok(ch.to_string()),
+        Some(Rule::Literal(s)) => Ok(regex::escape(s)),
+        Some(Rule::Class(class)) => Ok(class.to_regex()),
     }
 }
 
@@ -114,7 +125,8 @@ impl fmt::Display for Rule {
         match self {
             Rule::Sequence(idlist) => f.write_str(&seq_fmt(idlist)),
             Rule::Alternative(left, right) => write!(f, "{} | {}", seq_fmt(left), seq_fmt(right)),
-            Rule::Literal(ch) => write!(f, "\"{}\"", ch),
+            Rule::Literal(s) => write!(f, "\"{}\"", s),
+            Rule::Class(class) => write!(f, "{}", class),
             Rule::CannedRegex(pattern) => write!(f, "[canned] {}", pattern),
         }
     }
@@ -154,13 +166,16 @@ fn parse_alternative(s: &str) -> Result<Rule, String> {
 
 fn parse_literal(s: &str) -> Result<Rule, String> {
     if let Some(caps) = LIT_RE.captures(s) {
-        let ch = caps[1].chars().next().expect("LIT_RE should select a char");
-        Ok(Rule::Literal(ch))
+        Ok(Rule::Literal(caps[1].to_string()))
     } else {
         Err(format!("'{}' is not a literal rule", s))
     }
 }
 
+fn parse_class(s: &str) -> Result<Rule, String> {
+    CharClass::parse(s).map(Rule::Class)
+}
+
 fn parse_line(s: &str) -> Result<(RuleId, Rule), String> {
     let (rule_id, body) = match RULE_RE.captures(s) {
         None => {
@@ -176,7 +191,12 @@ fn parse_line(s: &str) -> Result<(RuleId, Rule), String> {
             Ok(id) => (id, caps[2].to_string()),
         },
     };
-    let funcs = [parse_alternative, parse_sequence, parse_literal];
+    let funcs = [
+        parse_alternative,
+        parse_sequence,
+        parse_literal,
+        parse_class,
+    ];
     for f in &funcs {
         if let Ok(def) = f(&body) {
             return Ok((rule_id, def));
@@ -185,9 +205,68 @@ fn parse_line(s: &str) -> Result<(RuleId, Rule), String> {
     Err(format!("failed to parse rule body '{}'", body))
 }
 
-fn read_lines() -> Result<Vec<String>, String> {
+/// The grammar given by the rules, which must not include canned
+/// regular expressions.
+fn grammar_of(rules: &HashMap<RuleId, Rule>) -> Result<Grammar, String> {
+    let seq =
+        |items: &[RuleId]| -> Vec<Symbol> { items.iter().map(|id| Symbol::Rule(*id)).collect() };
+    let mut grammar = Grammar::new();
+    for (id, rule) in rules.iter().sorted_by_key(|(id, _)| **id) {
+        match rule {
+            Rule::Sequence(items) => grammar.add_alternative(*id, seq(items))?,
+            Rule::Alternative(left, right) => {
+                grammar.add_alternative(*id, seq(left))?;
+                grammar.add_alternative(*id, seq(right))?;
+            }
+            Rule::Literal(s) => {
+                grammar.add_alternative(*id, s.chars().map(Symbol::Char).collect())?
+            }
+            Rule::Class(class) => {
+                let symbol = grammar.add_class(class.clone());
+                grammar.add_alternative(*id, vec![symbol])?;
+            }
+            Rule::CannedRegex(_) => {
+                return Err(format!("rule {} is a regular expression", id));
+            }
+        }
+    }
+    grammar.validate()?;
+    Ok(grammar)
+}
+
+/// The ways in which we can match messages against the rules.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Engine {
+    /// Parse messages with the grammar given by the rules.
+    Earley,
+    /// Convert the grammar to Chomsky Normal Form and use the CYK
+    /// algorithm.
+    Cyk,
+    /// Translate the rules into a regular expression.  The recursive
+    /// rules of part 2 can only be approximated.
+    Regex,
+}
+
+impl fmt::Display for Engine {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match self {
+            Engine::Earley => "earley",
+            Engine::Cyk => "cyk",
+            Engine::Regex => "regex",
+        })
+    }
+}
+
+fn engines() -> Registry<Engine> {
+    Registry::for_day(19)
+        .register("earley", "an Earley parser", Engine::Earley)
+        .register("cyk", "the CYK algorithm", Engine::Cyk)
+        .register("regex", "a regular expression", Engine::Regex)
+}
+
+fn read_lines(input: impl BufRead) -> Result<Vec<String>, String> {
     let mut input_lines: Vec<String> = Vec::new();
-    for input_item in io::BufReader::new(io::stdin()).lines() {
+    for input_item in input.lines() {
         match input_item {
             Err(e) => {
                 return Err(format!("I/O error: {}", e));
@@ -200,28 +279,444 @@ fn read_lines() -> Result<Vec<String>, String> {
     Ok(input_lines)
 }
 
-fn count_matches(rx: &Regex, messages: &[String], show_matches: &bool) -> usize {
-    let printer = |m: &str| {
+fn count_matches<F>(
+    is_match: F,
+    messages: &[String],
+    show_matches: &bool,
+    out: &mut impl Write,
+) -> Result<usize, String>
+where
+    F: Fn(&str) -> bool,
+{
+    let mut count = 0;
+    for m in messages.iter().filter(|m| is_match(m)) {
         if *show_matches {
-            println!("{}", m);
+            writeln!(out, "{}", m).map_err(write_error)?;
         }
-    };
-    messages
+        count += 1;
+    }
+    Ok(count)
+}
+
+/// Computes a hash of the rules.  Unlike the hashes computed by
+/// `std::collections::hash_map::DefaultHasher`, this is the same from
+/// one run of the program to the next (it is FNV-1a).
+fn rules_hash(rules: &HashMap<RuleId, Rule>) -> u64 {
+    let text: String = rules
         .iter()
-        .filter(|m| rx.is_match(m))
-        .inspect(|s| printer(s.as_str()))
-        .count()
+        .sorted_by_key(|(id, _)| **id)
+        .map(|(id, rule)| format!("{}: {}\n", id, rule))
+        .collect();
+    text.bytes().fold(0xcbf2_9ce4_8422_2325, |hash, b| {
+        (hash ^ b as u64).wrapping_mul(0x0100_0000_01b3)
+    })
+}
+
+/// The version of the format of the cache file.  A cache file with any
+/// other version is ignored.
+const CACHE_VERSION: u32 = 1;
+
+/// The compiled form of a set of rules.
+#[derive(Serialize, Deserialize)]
+enum Compiled {
+    /// The regular expression for rule 0.
+    Pattern(String),
+    /// The grammar in Chomsky Normal Form, ready for the CYK engine.
+    Cnf(Cyk),
 }
 
-fn compile_regex(pattern: &str) -> Regex {
-    let anchored = format!("^{}$", make_group(pattern));
-    Regex::new(&anchored).expect("failed to compile regex")
+/// Compiled forms of sets of rules, keyed by the kind of compiled form
+/// and a hash of the rules (part 2 changes some of the rules, so there
+/// is more than one set).  The cache can be saved to a file and loaded
+/// on the next run, which saves compiling the rules again.  The whole
+/// cache is discarded when the rules in the input change.
+#[derive(Serialize, Deserialize)]
+struct Cache {
+    version: u32,
+    /// The hash of the rules in the input.
+    input: u64,
+    entries: BTreeMap<String, Compiled>,
+    #[serde(skip)]
+    lookups: usize,
+    /// The number of lookups which found an entry.
+    #[serde(skip)]
+    hits: usize,
 }
 
-fn run() -> Result<(), String> {
-    let show_matches = false;
-    let show_patterns = false;
-    let lines = read_lines()?;
+impl Cache {
+    /// Makes an empty cache for the rules in the input, whose hash is
+    /// `input`.
+    fn new(input: u64) -> Cache {
+        Cache {
+            version: CACHE_VERSION,
+            input,
+            entries: BTreeMap::new(),
+            lookups: 0,
+            hits: 0,
+        }
+    }
+
+    /// Reads a cache, returning None if it is unreadable, in another
+    /// format, or for other input rules.
+    fn from_reader<R: io::Read>(reader: R, input: u64) -> Option<Cache> {
+        serde_json::from_reader::<_, Cache>(reader)
+            .ok()
+            .filter(|cache| cache.version == CACHE_VERSION && cache.input == input)
+    }
+
+    /// Loads a cache from `filename`.  If the file does not exist or
+    /// holds an out-of-date cache, the cache starts empty.
+    fn load(filename: &str, input: u64) -> Result<Cache, String> {
+        let f = match File::open(filename) {
+            Ok(f) => f,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(Cache::new(input)),
+            Err(e) => return Err(format!("failed to open {}: {}", filename, e)),
+        };
+        Ok(
+            Cache::from_reader(io::BufReader::new(f), input).unwrap_or_else(|| {
+                eprintln!("warning: ignoring out-of-date cache {}", filename);
+                Cache::new(input)
+            }),
+        )
+    }
+
+    fn save(&self, filename: &str) -> Result<(), String> {
+        let f =
+            File::create(filename).map_err(|e| format!("failed to create {}: {}", filename, e))?;
+        serde_json::to_writer(io::BufWriter::new(f), self)
+            .map_err(|e| format!("failed to save cache to {}: {}", filename, e))
+    }
+
+    /// Finds the `kind` of compiled form of `rules`, calling `compile`
+    /// to make it if it is not in the cache.
+    fn lookup<F>(
+        &mut self,
+        kind: &str,
+        rules: &HashMap<RuleId, Rule>,
+        compile: F,
+    ) -> Result<&Compiled, String>
+    where
+        F: FnOnce() -> Result<Compiled, String>,
+    {
+        let key = format!("{}-{:016x}", kind, rules_hash(rules));
+        self.lookups += 1;
+        if self.entries.contains_key(&key) {
+            self.hits += 1;
+        } else {
+            self.entries.insert(key.clone(), compile()?);
+        }
+        Ok(&self.entries[&key])
+    }
+
+    /// Finds the regular expression for rule 0.
+    fn pattern(&mut self, rules: &HashMap<RuleId, Rule>) -> Result<&str, String> {
+        match self.lookup("regex", rules, || {
+            translate_to_regex_pattern(&0, rules).map(Compiled::Pattern)
+        })? {
+            Compiled::Pattern(pattern) => Ok(pattern),
+            Compiled::Cnf(_) => Err("cache holds the wrong kind of entry for a regex".to_string()),
+        }
+    }
+
+    /// Finds the CYK tables for `grammar`, which is the grammar of
+    /// `rules`.
+    fn cyk(&mut self, rules: &HashMap<RuleId, Rule>, grammar: &Grammar) -> Result<&Cyk, String> {
+        match self.lookup("cyk", rules, || Ok(Compiled::Cnf(Cyk::new(grammar))))? {
+            Compiled::Cnf(cyk) => Ok(cyk),
+            Compiled::Pattern(_) => {
+                Err("cache holds the wrong kind of entry for CYK tables".to_string())
+            }
+        }
+    }
+}
+
+/// Counts the messages matching rule 0 by parsing them with `engine`,
+/// which must not be the regex engine.
+fn count_grammar_matches(
+    rules: &HashMap<RuleId, Rule>,
+    messages: &[String],
+    engine: Engine,
+    show_matches: &bool,
+    cache: &mut Cache,
+    out: &mut impl Write,
+) -> Result<usize, String> {
+    let grammar = grammar_of(rules)?;
+    match engine {
+        Engine::Cyk => {
+            let cyk = cache.cyk(rules, &grammar)?;
+            count_matches(|m| cyk.matches(0, m), messages, show_matches, out)
+        }
+        _ => count_matches(|m| grammar.matches(0, m), messages, show_matches, out),
+    }
+}
+
+/// The size limit for compiled regular expressions; this is the
+/// default limit of the regex crate.
+const REGEX_SIZE_LIMIT: usize = 10 * (1 << 20);
+
+/// A compiled pattern for matching messages.
+enum Matcher {
+    Regex(Regex),
+    /// Used when the pattern is too large for the regex crate.
+    Nfa(Nfa),
+}
+
+impl Matcher {
+    /// Compiles `pattern` with the regex crate, or if the result would
+    /// be larger than `size_limit` bytes, as an NFA.
+    fn compile(pattern: &str, size_limit: usize) -> Result<Matcher, String> {
+        let anchored = format!("^{}$", make_group(pattern));
+        match RegexBuilder::new(&anchored).size_limit(size_limit).build() {
+            Ok(rx) => Ok(Matcher::Regex(rx)),
+            Err(regex::Error::CompiledTooBig(_)) => Nfa::compile(pattern).map(Matcher::Nfa),
+            Err(e) => Err(format!("failed to compile regex: {}", e)),
+        }
+    }
+
+    fn is_match(&self, message: &str) -> bool {
+        match self {
+            Matcher::Regex(rx) => rx.is_match(message),
+            Matcher::Nfa(nfa) => nfa.is_match(message),
+        }
+    }
+}
+
+/// Counts the messages matching rule 0 by translating the rules into
+/// a regular expression.
+fn count_regex_matches(
+    label: &str,
+    rules: &HashMap<RuleId, Rule>,
+    messages: &[String],
+    show_patterns: bool,
+    show_matches: &bool,
+    cache: &mut Cache,
+    out: &mut impl Write,
+) -> Result<usize, String> {
+    let pattern = cache.pattern(rules)?;
+    if show_patterns {
+        writeln!(out, "{}: regex for 0 is {}", label, pattern).map_err(write_error)?;
+    }
+    let matcher = Matcher::compile(pattern, REGEX_SIZE_LIMIT)?;
+    if let Matcher::Nfa(nfa) = &matcher {
+        writeln!(
+            out,
+            "{}: the regex is too large for the regex crate, matching with an NFA of {} states instead",
+            label,
+            nfa.state_count()
+        )
+        .map_err(write_error)?;
+    }
+    count_matches(|m| matcher.is_match(m), messages, show_matches, out)
+}
+
+/// The length of the shortest string which `rule` matches.
+fn min_match_len(rule: &RuleId, rules: &HashMap<RuleId, Rule>) -> Result<usize, String> {
+    let seq_len = |items: &[RuleId]| -> Result<usize, String> {
+        items.iter().map(|id| min_match_len(id, rules)).sum()
+    };
+    match rules.get(rule) {
+        None => Err(format!("missing definition for rule {}", rule)),
+        Some(Rule::Sequence(items)) => seq_len(items),
+        Some(Rule::Alternative(left, right)) => Ok(cmp::min(seq_len(left)?, seq_len(right)?)),
+        Some(Rule::Literal(s)) => Ok(s.chars().count()),
+        Some(Rule::Class(_)) => Ok(1),
+        Some(Rule::CannedRegex(_)) => Err(format!("rule {} is a regular expression", rule)),
+    }
+}
+
+/// Replaces rules 8 and 11 with the recursive rules of part 2.
+fn recursive_part2_rules(rules: &mut HashMap<RuleId, Rule>) {
+    rules.insert(8, Rule::Alternative(vec![42], vec![42, 8]));
+    rules.insert(11, Rule::Alternative(vec![42, 31], vec![42, 11, 31]));
+}
+
+/// Counts the messages matching rule 0 with `engine`, for both parts,
+/// without showing anything.
+fn count_both(
+    engine: Engine,
+    rules: &HashMap<RuleId, Rule>,
+    messages: &[String],
+    cache: &mut Cache,
+) -> Result<(usize, usize), String> {
+    let maxlen: usize = messages.iter().map(|m| m.len()).max().unwrap_or(0);
+    let mut part2_rules = rules.clone();
+    match engine {
+        Engine::Earley | Engine::Cyk => {
+            recursive_part2_rules(&mut part2_rules);
+            let out = &mut io::sink();
+            Ok((
+                count_grammar_matches(rules, messages, engine, &false, cache, out)?,
+                count_grammar_matches(&part2_rules, messages, engine, &false, cache, out)?,
+            ))
+        }
+        Engine::Regex => {
+            approximate_part2_rules(&mut part2_rules, maxlen)?;
+            let out = &mut io::sink();
+            Ok((
+                count_regex_matches("Part 1", rules, messages, false, &false, cache, out)?,
+                count_regex_matches("Part 2", &part2_rules, messages, false, &false, cache, out)?,
+            ))
+        }
+    }
+}
+
+/// Replaces rules 8 and 11 with regular expressions which approximate
+/// the recursive rules of part 2.
+fn approximate_part2_rules(rules: &mut HashMap<RuleId, Rule>, maxlen: usize) -> Result<(), String> {
+    let rule31_pattern = translate_to_regex_pattern(&31, rules)?;
+    let rule42_pattern = translate_to_regex_pattern(&42, rules)?;
+    rules.insert(8, Rule::CannedRegex(format!("(({})+)", rule42_pattern)));
+    // Rule 11 should match XY, XXYY, XXXYYY, ... without limit, which
+    // cannot be represented in a regex, so we have to choose an upper
+    // limit.  Each repeat matches at least as many characters as the
+    // shortest strings matching rules 42 and 31, so that gives us an
+    // upper limit which is still exact for these messages.  The
+    // pattern may still be too large for the Rust Regex
+    // implementation, but count_regex_matches then falls back on an
+    // NFA.
+    let shortest = min_match_len(&42, rules)? + min_match_len(&31, rules)?;
+    let repeats = cmp::max(maxlen / shortest, 1);
+    rules.insert(
+        11,
+        Rule::CannedRegex(balanced(&rule42_pattern, &rule31_pattern, repeats)),
+    );
+    Ok(())
+}
+
+#[derive(Default)]
+struct Options {
+    cache: Option<String>,
+    count_derivations: bool,
+    crosscheck: bool,
+    derive: Vec<String>,
+    engine: Option<Engine>,
+    generate: Option<usize>,
+    show_matches: bool,
+    show_patterns: bool,
+}
+
+fn parse_args() -> Result<Options, String> {
+    let mut options = Options::default();
+    let mut args = std::env::args().skip(1);
+    while let Some(arg) = args.next() {
+        let mut value = || {
+            args.next()
+                .ok_or_else(|| format!("option {} requires an argument", arg))
+        };
+        match arg.as_str() {
+            "crosscheck" => options.crosscheck = true,
+            "--algorithm" | "--engine" => options.engine = Some(engines().get(&value()?)?.solver),
+            "--cache" => options.cache = Some(value()?),
+            "--count-derivations" => options.count_derivations = true,
+            "--derive" => options.derive.push(value()?),
+            "--generate" => {
+                let v = value()?;
+                options.generate = Some(
+                    v.parse()
+                        .map_err(|e| format!("invalid length '{}': {}", v, e))?,
+                );
+            }
+            "--show-matches" => options.show_matches = true,
+            "--show-patterns" => options.show_patterns = true,
+            _ => return Err(format!("unknown option {}", arg)),
+        }
+    }
+    if options.engine == Some(Engine::Regex)
+        && (options.count_derivations || !options.derive.is_empty() || options.generate.is_some())
+    {
+        return Err(
+            "--count-derivations, --derive and --generate cannot be used with the regex engine"
+                .to_string(),
+        );
+    }
+    Ok(options)
+}
+
+/// Prints the number of derivations of each message matching rule 0.
+fn show_derivation_counts(
+    label: &str,
+    rules: &HashMap<RuleId, Rule>,
+    messages: &[String],
+    out: &mut impl Write,
+) -> Result<(), String> {
+    let grammar = grammar_of(rules)?;
+    let mut matching = 0;
+    let mut ambiguous = 0;
+    for message in messages {
+        let count = grammar.count_derivations(0, message);
+        if count == Derivations::Finite(0) {
+            continue;
+        }
+        matching += 1;
+        if count != Derivations::Finite(1) {
+            ambiguous += 1;
+        }
+        writeln!(out, "{}: {} has {} derivations", label, message, count).map_err(write_error)?;
+    }
+    writeln!(
+        out,
+        "{}: {} of {} matching messages are ambiguous",
+        label, ambiguous, matching
+    )
+    .map_err(write_error)?;
+    Ok(())
+}
+
+/// Prints how each of `messages` derives from rule 0.
+fn show_derivations(
+    label: &str,
+    rules: &HashMap<RuleId, Rule>,
+    messages: &[String],
+    out: &mut impl Write,
+) -> Result<(), String> {
+    let grammar = grammar_of(rules)?;
+    for message in messages {
+        match grammar.derive(0, message) {
+            Some(derivation) => write!(
+                out,
+                "{}: {} derives from rule 0 like this:\n{}",
+                label,
+                message,
+                grammar.derivation_tree(&derivation)
+            )
+            .map_err(write_error)?,
+            None => writeln!(out, "{}: {} does not match rule 0", label, message)
+                .map_err(write_error)?,
+        }
+    }
+    Ok(())
+}
+
+/// The largest number of strings --generate will consider.
+const GENERATE_LIMIT: usize = 1_000_000;
+
+/// Prints the strings of up to `maxlen` characters which rule 0
+/// matches.
+fn show_generated(
+    label: &str,
+    rules: &HashMap<RuleId, Rule>,
+    maxlen: usize,
+    out: &mut impl Write,
+) -> Result<(), String> {
+    let grammar = grammar_of(rules)?;
+    let generated = grammar.generate(0, maxlen, GENERATE_LIMIT)?;
+    writeln!(
+        out,
+        "{}: rule 0 matches {} strings of up to {} characters",
+        label,
+        generated.len(),
+        maxlen
+    )
+    .map_err(write_error)?;
+    for s in generated {
+        writeln!(out, "{}", s).map_err(write_error)?;
+    }
+    Ok(())
+}
+
+fn run(input: impl BufRead, options: &Options, out: &mut impl Write) -> Result<(), String> {
+    let engine = options.engine.unwrap_or(Engine::Earley);
+    let lines = read_lines(input)?;
     let mut rules: HashMap<RuleId, Rule> = HashMap::new();
     let mut messages: Vec<String> = Vec::new();
     let mut saw_blank = false;
@@ -236,67 +731,276 @@ fn run() -> Result<(), String> {
         }
     }
 
-    let pat1 = translate_to_regex_pattern(&0, &rules)?;
-    if show_patterns {
-        println!("Part 1: regex for 0 is {}", pat1);
+    if messages.is_empty() && options.derive.is_empty() && options.generate.is_none() {
+        writeln!(out, "No messages, nothing to do").map_err(write_error)?;
+        return Ok(());
     }
-    let rx1 = compile_regex(&pat1);
-
-    let maxlen: usize = match messages.iter().map(|m| m.len()).max() {
-        None => {
-            println!("No messages, nothing to do");
-            return Ok(());
-        }
-        Some(n) => n,
+    let maxlen: usize = messages.iter().map(|m| m.len()).max().unwrap_or(0);
+    let mut cache = match &options.cache {
+        Some(filename) => Cache::load(filename, rules_hash(&rules))?,
+        None => Cache::new(rules_hash(&rules)),
     };
 
-    println!(
-        "Part 1: {} matches",
-        count_matches(&rx1, &messages, &show_matches)
-    );
+    if options.crosscheck {
+        let report = engines().crosscheck(|engine| {
+            let (part1, part2) = count_both(*engine, &rules, &messages, &mut cache)?;
+            Ok(format!(
+                "part 1 {} matches, part 2 {} matches",
+                part1, part2
+            ))
+        })?;
+        writeln!(out, "{}", report).map_err(write_error)?;
+        return Ok(());
+    }
 
-    // Customisations for part 2.
-    let rule31_pattern = translate_to_regex_pattern(&31, &rules)?;
-    let rule42_pattern = translate_to_regex_pattern(&42, &rules)?;
-    rules.insert(8, Rule::CannedRegex(format!("(({})+)", rule42_pattern)));
-    // Rule 11 should match XY, XXYY, XXXYYY, ... without limit, which
-    // cannot be represented in a regex, so we have to choose an upper
-    // limit.  The obvious upper limit is the maximum message length,
-    // but if we choose that, the Rust Regex implementation will
-    // refuse to compile the pattern.  At lower levels (e.g. 10), the
-    // implementation will try but run out of memory or take a long
-    // time.  Hence we determined experimentally that a maximum repeat
-    // count of 5 gets us the right answer.
-    let repeats = cmp::min(maxlen, 5);
-    rules.insert(
-        11,
-        Rule::CannedRegex(balanced(&rule42_pattern, &rule31_pattern, repeats)),
-    );
+    // Part 1 has no recursive rules, so the engines should agree.
+    // Only the selected engine shows the matches.
+    let show_matches = |e: Engine| options.show_matches && e == engine;
+    let grammar_engine = match engine {
+        Engine::Regex => Engine::Earley,
+        other => other,
+    };
+    let regex_count = count_regex_matches(
+        "Part 1",
+        &rules,
+        &messages,
+        options.show_patterns,
+        &show_matches(Engine::Regex),
+        &mut cache,
+        out,
+    )?;
+    let grammar_count = count_grammar_matches(
+        &rules,
+        &messages,
+        grammar_engine,
+        &show_matches(grammar_engine),
+        &mut cache,
+        out,
+    )?;
+    if regex_count != grammar_count {
+        return Err(format!(
+            "the regex engine finds {} matches for part 1 but the {} engine finds {}",
+            regex_count, grammar_engine, grammar_count
+        ));
+    }
+    writeln!(out, "Part 1: {} matches", grammar_count).map_err(write_error)?;
+    show_derivations("Part 1", &rules, &options.derive, out)?;
+    if options.count_derivations {
+        show_derivation_counts("Part 1", &rules, &messages, out)?;
+    }
+    if let Some(maxlen) = options.generate {
+        show_generated("Part 1", &rules, maxlen, out)?;
+    }
 
-    let pat2 = translate_to_regex_pattern(&0, &rules)?;
-    if show_patterns {
-        println!("Part 2: regex for 0 is {}", pat2);
+    // Customisations for part 2.
+    match engine {
+        Engine::Earley | Engine::Cyk => {
+            recursive_part2_rules(&mut rules);
+            let count = count_grammar_matches(
+                &rules,
+                &messages,
+                engine,
+                &options.show_matches,
+                &mut cache,
+                out,
+            )?;
+            writeln!(out, "Part 2: {} matches", count).map_err(write_error)?;
+            show_derivations("Part 2", &rules, &options.derive, out)?;
+            if options.count_derivations {
+                show_derivation_counts("Part 2", &rules, &messages, out)?;
+            }
+            if let Some(maxlen) = options.generate {
+                show_generated("Part 2", &rules, maxlen, out)?;
+            }
+        }
+        Engine::Regex => {
+            approximate_part2_rules(&mut rules, maxlen)?;
+            let count = count_regex_matches(
+                "Part 2",
+                &rules,
+                &messages,
+                options.show_patterns,
+                &options.show_matches,
+                &mut cache,
+                out,
+            )?;
+            writeln!(out, "Part 2: {} matches", count).map_err(write_error)?;
+        }
     }
-    let rx2 = compile_regex(&pat2);
-    println!(
-        "Part 2: {} matches",
-        count_matches(&rx2, &messages, &show_matches)
-    );
 
+    if let Some(filename) = &options.cache {
+        writeln!(
+            out,
+            "Cache: {} of {} compiled rule sets were found in {}",
+            cache.hits, cache.lookups, filename
+        )
+        .map_err(write_error)?;
+        cache.save(filename)?;
+    }
     Ok(())
 }
 
+fn parse_rules(lines: &[&str]) -> HashMap<RuleId, Rule> {
+    lines
+        .iter()
+        .map(|line| parse_line(line).expect("test rules should be valid"))
+        .collect()
+}
+
 fn self_test() {
-    assert_eq!(parse_literal(" \"x\""), Ok(Rule::Literal('x')));
+    assert_eq!(parse_literal(" \"x\""), Ok(Rule::Literal("x".to_string())));
+    assert_eq!(
+        parse_line("3: \"abc\""),
+        Ok((3, Rule::Literal("abc".to_string())))
+    );
+    let class = CharClass::parse("[^a-cx-]").expect("valid class");
+    assert_eq!(class.ranges, vec![('a', 'c'), ('x', 'x'), ('-', '-')]);
+    assert!(class.negated);
+    assert!(class.contains('d') && !class.contains('b') && !class.contains('-'));
+    assert_eq!(class.to_string(), "[^a-cx-]");
+    for bad in &["[]", "[^]", "[z-a]", "a-z"] {
+        assert!(CharClass::parse(bad).is_err(), "{}", bad);
+    }
     assert_eq!(
         parse_alternative(" 1 2 | 9 43"),
         Ok(Rule::Alternative(vec![1, 2], vec![9, 43]))
     );
+    // The example from the puzzle.
+    let rules = parse_rules(&[
+        "0: 4 1 5",
+        "1: 2 3 | 3 2",
+        "2: 4 4 | 5 5",
+        "3: 4 5 | 5 4",
+        "4: \"a\"",
+        "5: \"b\"",
+    ]);
+    let grammar = grammar_of(&rules).expect("valid grammar");
+    for (message, expected) in &[
+        ("ababbb", true),
+        ("abbbab", true),
+        ("bababa", false),
+        ("aaabbb", false),
+        ("aaaabbb", false),
+        ("", false),
+    ] {
+        assert_eq!(grammar.matches(0, message), *expected, "{}", message);
+    }
+    let generated = grammar.generate(0, 10, 100).expect("few strings");
+    assert_eq!(generated.len(), 8);
+    assert!(generated
+        .iter()
+        .all(|m| m.len() == 6 && grammar.matches(0, m)));
+    // Compiled rules survive a round trip through the cache, which
+    // is discarded when the input rules change.
+    let input = rules_hash(&rules);
+    let mut cache = Cache::new(input);
+    let pattern = cache.pattern(&rules).expect("valid rules").to_string();
+    cache.cyk(&rules, &grammar).expect("valid grammar");
+    assert_eq!(cache.hits, 0);
+    let json = serde_json::to_string(&cache).expect("cache serializes");
+    let mut reloaded = Cache::from_reader(json.as_bytes(), input).expect("cache is current");
+    assert_eq!(reloaded.pattern(&rules), Ok(pattern.as_str()));
+    let cyk = reloaded.cyk(&rules, &grammar).expect("valid grammar");
+    assert!(cyk.matches(0, "ababbb") && !cyk.matches(0, "bababa"));
+    assert_eq!((reloaded.hits, reloaded.lookups), (2, 2));
+    let changed = parse_rules(&["0: 4 1 5", "1: 5 4", "4: \"a\"", "5: \"b\""]);
+    assert_eq!(reloaded.pattern(&changed), Ok("abab"));
+    assert_eq!((reloaded.hits, reloaded.lookups), (2, 3));
+    assert_ne!(rules_hash(&changed), input);
+    assert!(Cache::from_reader(json.as_bytes(), rules_hash(&changed)).is_none());
+    assert!(Cache::from_reader("junk".as_bytes(), input).is_none());
+    // Recursive rules like those of part 2.
+    let rules = parse_rules(&[
+        "0: 8 11",
+        "8: 1 | 1 8",
+        "11: 1 2 | 1 11 2",
+        "1: \"a\"",
+        "2: \"b\"",
+    ]);
+    let grammar = grammar_of(&rules).expect("valid grammar");
+    for (message, expected) in &[
+        ("aab", true),
+        ("aaaaaaabbb", true),
+        ("aaabbb", false),
+        ("ab", false),
+        ("aaba", false),
+    ] {
+        assert_eq!(grammar.matches(0, message), *expected, "{}", message);
+    }
+    let derivation = grammar.derive(0, "aaab").expect("aaab matches");
+    assert_eq!(
+        grammar.derivation_tree(&derivation),
+        concat!(
+            "0: 8 11 = aaab\n",
+            "  8: 1 8 = aa\n",
+            "    1: \"a\" = a\n",
+            "    8: 1 = a\n",
+            "      1: \"a\" = a\n",
+            "  11: 1 2 = ab\n",
+            "    1: \"a\" = a\n",
+            "    2: \"b\" = b\n",
+        )
+    );
+    assert_eq!(grammar.derive(0, "abab"), None);
+    // Since 11 must match as many a's as b's, there is only one way
+    // to split these between 8 and 11.
+    assert_eq!(grammar.count_derivations(0, "aaab"), Derivations::Finite(1));
+    assert_eq!(grammar.count_derivations(0, "aab"), Derivations::Finite(1));
+    assert_eq!(grammar.count_derivations(0, "abab"), Derivations::Finite(0));
+    assert_eq!(
+        grammar.generate(0, 5, 100),
+        Ok(vec![
+            "aab".to_string(),
+            "aaab".to_string(),
+            "aaaab".to_string(),
+            "aaabb".to_string()
+        ])
+    );
+    // An ambiguous grammar with a rule which refers to itself
+    // without consuming any input.
+    let rules = parse_rules(&["0: 1 1", "1: 2 | 3", "2: \"a\"", "3: 2 | 1"]);
+    let grammar = grammar_of(&rules).expect("valid grammar");
+    assert_eq!(grammar.count_derivations(0, "aa"), Derivations::Infinite);
+    let rules = parse_rules(&["0: 1 1 1", "1: 2 | 3", "2: \"a\"", "3: \"a\""]);
+    let grammar = grammar_of(&rules).expect("valid grammar");
+    assert_eq!(grammar.count_derivations(0, "aaa"), Derivations::Finite(8));
+    assert!(grammar_of(&parse_rules(&["0: 1 2", "1: \"a\""])).is_err());
+    // Strings and character classes, in both engines.
+    let rules = parse_rules(&["0: 1 2 | 2 1", "1: \"a.b\"", "2: [x-z0]"]);
+    let grammar = grammar_of(&rules).expect("valid grammar");
+    let pattern = translate_to_regex_pattern(&0, &rules).expect("valid rules");
+    let rx = Matcher::compile(&pattern, REGEX_SIZE_LIMIT).expect("valid pattern");
+    assert!(matches!(rx, Matcher::Regex(_)));
+    // A tiny size limit forces the use of an NFA.
+    let nfa = Matcher::compile(&pattern, 10).expect("valid pattern");
+    assert!(matches!(nfa, Matcher::Nfa(_)));
+    for (message, expected) in &[
+        ("a.bx", true),
+        ("0a.b", true),
+        ("a.bw", false),
+        ("aXbx", false),
+        ("a.b", false),
+        ("xa.bz", false),
+    ] {
+        assert_eq!(grammar.matches(0, message), *expected, "{}", message);
+        assert_eq!(rx.is_match(message), *expected, "{}", message);
+        assert_eq!(nfa.is_match(message), *expected, "{}", message);
+    }
+    assert_eq!(grammar.generate(0, 4, 100).expect("few strings").len(), 8);
+    let derivation = grammar.derive(0, "za.b").expect("za.b matches");
+    assert_eq!(
+        grammar.derivation_tree(&derivation),
+        "0: 2 1 = za.b\n  2: [x-z0] = z\n  1: \"a.b\" = a.b\n"
+    );
 }
 
 fn main() {
     self_test();
-    std::process::exit(match run() {
+    let result = parse_args().and_then(|options| {
+        aor2020::day19::self_test()?;
+        run(io::stdin().lock(), &options, &mut io::stdout())
+    });
+    std::process::exit(match result {
         Ok(_) => 0,
         Err(err) => {
             eprintln!("error: {}", err);
@@ -304,3 +1008,38 @@ fn main() {
         }
     });
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn output(input: &str, options: &Options) -> String {
+        let mut out = Vec::new();
+        run(input.as_bytes(), options, &mut out).unwrap();
+        String::from_utf8(out).unwrap()
+    }
+
+    #[test]
+    fn sample_1() {
+        insta::assert_snapshot!(output(
+            include_str!("../../samples/day19-1.txt"),
+            &Options::default()
+        ));
+    }
+
+    #[test]
+    fn sample_2() {
+        insta::assert_snapshot!(output(
+            include_str!("../../samples/day19-2.txt"),
+            &Options::default()
+        ));
+    }
+
+    #[test]
+    fn sample_4() {
+        insta::assert_snapshot!(output(
+            include_str!("../../samples/day19-4.txt"),
+            &Options::default()
+        ));
+    }
+}
@@ -1,60 +1,34 @@
+extern crate aor2020;
+#[cfg(test)]
+extern crate insta;
 extern crate log;
-extern crate pretty_env_logger;
-#[macro_use]
-extern crate lazy_static;
 extern crate ndarray;
-extern crate regex;
-
+extern crate pretty_env_logger;
+extern crate rayon;
+extern crate serde;
+extern crate serde_json;
+
+use aor2020::algorithm::Registry;
+use aor2020::day20::{Direction, EdgeKey, EdgePattern, Manipulation, Rotation, Tile, TileId};
+use aor2020::output::write_error;
+use aor2020::rng::Lcg;
+use aor2020::visualization::{render_grid, Canvas, BLACK, BLUE, GREY, RED, WHITE};
 use ndarray::prelude::*;
 use ndarray::s;
 use ndarray::Zip;
-use regex::Regex;
+use rayon::prelude::*;
+use serde::{Deserialize, Serialize};
 use std::cmp;
+use std::collections::BTreeSet;
 use std::collections::HashMap;
 use std::collections::HashSet;
 use std::fmt;
+use std::fs::{self, File};
 use std::io;
 use std::io::Read;
-use std::num::ParseIntError;
-use std::str::FromStr;
-
-lazy_static! {
-    static ref TILE_TITLE_RE: Regex = Regex::new("^Tile ([0-9]*):$").unwrap();
-}
-
-#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash, PartialOrd, Ord)]
-enum Direction {
-    N,
-    E,
-    S,
-    W,
-}
-
-impl Direction {
-    fn all() -> [Direction; 4] {
-        [Direction::N, Direction::E, Direction::S, Direction::W]
-    }
-}
-
-impl fmt::Display for Direction {
-    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        f.write_str(match self {
-            Direction::N => "N",
-            Direction::E => "E",
-            Direction::S => "S",
-            Direction::W => "W",
-        })
-    }
-}
-
-fn opposite_direction(d: &Direction) -> Direction {
-    match d {
-        Direction::N => Direction::S,
-        Direction::E => Direction::W,
-        Direction::S => Direction::N,
-        Direction::W => Direction::E,
-    }
-}
+use std::io::Write;
+use std::path::Path;
+use std::time::{Duration, Instant};
 
 #[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
 struct Position {
@@ -68,298 +42,12 @@ impl fmt::Display for Position {
     }
 }
 
-#[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
-struct TileId {
-    val: i32,
-}
-
-impl From<i32> for TileId {
-    fn from(n: i32) -> Self {
-        TileId { val: n }
-    }
-}
-
-impl From<&i32> for TileId {
-    fn from(n: &i32) -> Self {
-        TileId { val: *n }
-    }
-}
-
-impl FromStr for TileId {
-    type Err = ParseIntError;
-
-    fn from_str(s: &str) -> Result<Self, Self::Err> {
-        match s.parse() {
-            Err(e) => Err(e),
-            Ok(n) => Ok(TileId { val: n }),
-        }
-    }
-}
-
-impl fmt::Display for TileId {
-    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        write!(f, "{}", self.val)
-    }
-}
-
-#[derive(Debug, Copy, Clone)]
-enum Rotation {
-    Zero,
-    One,
-    Two,
-    Three,
-}
-
-#[derive(Copy, Clone)]
-struct Manipulation {
-    rot: Rotation,
-    flip: bool,
-}
-
-impl Manipulation {
-    fn new(n: i32) -> Manipulation {
-        Manipulation {
-            rot: match n & 0x03 {
-                0 => Rotation::Zero,
-                1 => Rotation::One,
-                2 => Rotation::Two,
-                3 => Rotation::Three,
-                _ => panic!("implossible"),
-            },
-            flip: match n & 0x04 {
-                0 => false,
-                4 => true,
-                _ => panic!("implossible"),
-            },
-        }
-    }
-
-    fn noop() -> Manipulation {
-        Manipulation {
-            rot: Rotation::Zero,
-            flip: false,
-        }
-    }
-
-    fn all() -> Vec<Manipulation> {
-        (0..8).map(Manipulation::new).collect()
-    }
-
-    fn as_string(&self) -> String {
-        format!(
-            "R{}F{}",
-            match self.rot {
-                Rotation::Zero => 0,
-                Rotation::One => 1,
-                Rotation::Two => 2,
-                Rotation::Three => 3,
-            },
-            if self.flip { "Y" } else { "N" }
-        )
-    }
-
-    fn do_rot(&self, tiledata: Array2<u8>) -> Array2<u8> {
-        // rotations are counter-clockwise.
-        match self.rot {
-            Rotation::Zero => tiledata,
-            Rotation::One => tiledata.slice(s![.., ..;-1]).reversed_axes().into_owned(),
-            Rotation::Two => tiledata.slice(s![..;-1, ..;-1]).into_owned(),
-            Rotation::Three => tiledata.slice(s![..;-1, ..]).reversed_axes().into_owned(),
-        }
-    }
-
-    fn do_flip(&self, tiledata: Array2<u8>) -> Array2<u8> {
-        if self.flip {
-            tiledata.slice(s![.., ..;-1]).into_owned()
-        } else {
-            tiledata
-        }
-    }
-
-    fn on(&self, m: &Array2<u8>) -> Array2<u8> {
-        self.do_rot(self.do_flip(m.to_owned()))
-    }
-}
-
-impl FromStr for Manipulation {
-    type Err = String;
-
-    fn from_str(s: &str) -> Result<Self, Self::Err> {
-        let mut it = s.chars();
-        if let Some(ch) = it.next() {
-            if ch != 'R' {
-                return Err("must begin with R".to_string());
-            }
-        }
-        let rot = match it.next() {
-            None => {
-                return Err("string is too short".to_string());
-            }
-            Some('0') => Rotation::Zero,
-            Some('1') => Rotation::One,
-            Some('2') => Rotation::Two,
-            Some('3') => Rotation::Three,
-            Some(ch) => {
-                return Err(format!("invalid rotation {}", ch));
-            }
-        };
-        if let Some(ch) = it.next() {
-            if ch != 'F' {
-                return Err("must have F as the third character".to_string());
-            }
-        }
-        let flip = match it.next() {
-            Some('Y') => true,
-            Some('N') => false,
-            _ => {
-                return Err("flip must be Y or N".to_string());
-            }
-        };
-        match it.next() {
-            None => Ok(Manipulation { rot, flip }),
-            _ => Err("trailing garbage at the end of the string".to_string()),
-        }
-    }
-}
-
-impl fmt::Display for Manipulation {
-    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        f.write_str(&self.as_string())
-    }
-}
-
-impl fmt::Debug for Manipulation {
-    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        f.write_str(&self.as_string())
-    }
-}
-
-#[derive(Debug)]
-struct Tile {
-    id: TileId,
-    d: Array2<u8>,
-}
-
-impl Tile {
-    fn from_string(s: &str) -> Result<Tile, String> {
-        let lines: Vec<String> = s.split('\n').map(str::to_string).collect();
-        if lines.is_empty() {
-            return Err("Tiles must not be empty".to_string());
-        }
-        let id: TileId = match TILE_TITLE_RE.captures(&lines[0]) {
-            Some(caps) => match caps[1].parse() {
-                Ok(n) => n,
-                Err(e) => {
-                    return Err(format!(
-                        "failed to parse '{}' as an integer: {}",
-                        &caps[1], e
-                    ));
-                }
-            },
-            None => {
-                return Err(format!("tile is missing a title:\n{}", s));
-            }
-        };
-        log::debug!("tile id is {}", id);
-        let width = lines[1].len();
-        let height = lines.len() - 1;
-        if width != height {
-            return Err(format!(
-                "Tiles should be square but this has {} rows, {} columns: {:?}",
-                height, width, lines
-            ));
-        }
-        let tiledata = s[lines[0].len() + 1..].chars().collect::<Vec<char>>();
-        let d = Array::from_shape_fn((height, width), |(r, c)| {
-            decode_ascii_tile(&id, r, c, &width, &tiledata)
-        });
-        if d.iter().filter(|x| **x == 2).count() > 0 {
-            log::debug!("bad tile data:\n{}", &s[width + 1..]);
-            return Err("tile data contained unexpected characters".to_string());
-        }
-        Ok(Tile { id, d })
-    }
-
-    fn manipulated(&self, how: &Manipulation) -> Array2<u8> {
-        how.on(&self.d)
-    }
-
-    fn interior(&self, how: &Manipulation) -> Array2<u8> {
-        self.manipulated(how)
-            .slice(s![1..self.d.nrows() - 1, 1..self.d.ncols() - 1])
-            .into_owned()
-    }
-}
-
 #[derive(Debug, Clone)]
 struct TileIndexEntry {
     tile_id: TileId,
     manipulation: Manipulation,
 }
 
-#[derive(Debug, Clone, Copy, Hash, PartialEq, Eq, PartialOrd, Ord)]
-struct EdgePattern {
-    bits: i32,
-}
-
-impl EdgePattern {
-    fn from_edge(edge: &ArrayView1<u8>) -> EdgePattern {
-        EdgePattern {
-            bits: edge.iter().fold(0, |bits, elem| {
-                let bit = match elem {
-                    0 => 0,
-                    1 => 1,
-                    _ => panic!("matrix should be 0/1 only"),
-                };
-                (bits << 1) | bit
-            }),
-        }
-    }
-
-    fn from_matrix(d: &Direction, m: &ArrayView2<u8>) -> EdgePattern {
-        EdgePattern::from_edge(&m.slice(&match d {
-            Direction::N => s![0, ..].to_owned(),
-            Direction::E => s![.., m.ncols() - 1].to_owned(),
-            Direction::S => s![m.nrows() - 1, ..].to_owned(),
-            Direction::W => s![.., 0].to_owned(),
-        }))
-    }
-}
-
-impl fmt::Display for EdgePattern {
-    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        fmt::Binary::fmt(&self.bits, f)
-    }
-}
-
-#[derive(Debug, Clone, Copy, Hash, PartialEq, Eq, PartialOrd, Ord)]
-struct EdgeKey {
-    direction: Direction,
-    pattern: EdgePattern,
-}
-
-impl EdgeKey {
-    fn from_matrix(d: &Direction, m: &ArrayView2<u8>) -> EdgeKey {
-        EdgeKey {
-            direction: *d,
-            pattern: EdgePattern::from_matrix(d, m),
-        }
-    }
-
-    fn opposing(&self) -> EdgeKey {
-        EdgeKey {
-            direction: opposite_direction(&self.direction),
-            pattern: self.pattern,
-        }
-    }
-}
-
-impl fmt::Display for EdgeKey {
-    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        write!(f, "({},{})", self.direction, self.pattern)
-    }
-}
-
 #[derive(Debug, Clone, Copy)]
 struct ExposedEdge {
     edge_pattern: EdgePattern,
@@ -407,14 +95,13 @@ impl TileLocationSolution {
             .collect();
         // Add new exposures for any edges of the new piece that are not touching
         // already-placed pieces.
-        let tile_bits = tile.manipulated(how);
         for d in Direction::all().iter() {
             // Get the location of the possible neighbour in this direction.
             let n = get_neighbour(pos, *d);
             // If that position is not already occupied, this edge is exposed.
             if !self.occupied(&n) {
                 self.exposed_edges.push(ExposedEdge {
-                    edge_pattern: EdgePattern::from_matrix(d, &tile_bits.view()),
+                    edge_pattern: tile.edge(how, *d),
                     pos: *pos,
                     direction: *d,
                 });
@@ -422,6 +109,28 @@ impl TileLocationSolution {
         }
     }
 
+    /// Removes a tile placed by `place_tile`, exposing again the edges
+    /// of its neighbours which it covered.
+    fn remove_tile(&mut self, tile_id: &TileId, tiles: &HashMap<TileId, Tile>) {
+        let (pos, _) = self
+            .tile_to_position
+            .remove(tile_id)
+            .expect("the tile to be removed has not been placed");
+        self.position_to_tile.remove(&pos);
+        self.exposed_edges.retain(|exposure| exposure.pos != pos);
+        for d in Direction::all().iter() {
+            let n = get_neighbour(&pos, *d);
+            if let Some((neighbour_id, how)) = self.get_tile_at_position(&n) {
+                let facing = d.opposite();
+                self.exposed_edges.push(ExposedEdge {
+                    edge_pattern: tiles[&neighbour_id].edge(&how, facing),
+                    pos: n,
+                    direction: facing,
+                });
+            }
+        }
+    }
+
     fn get_position_of_tile(&self, tile_id: &TileId) -> Option<&(Position, Manipulation)> {
         self.tile_to_position.get(tile_id)
     }
@@ -443,20 +152,8 @@ impl TileLocationSolution {
     }
 }
 
-fn decode_ascii_tile(id: &TileId, r: usize, c: usize, width: &usize, s: &[char]) -> u8 {
-    let pos: usize = (width + 1) * r + c;
-    match s[pos] {
-        '#' => 1,
-        '.' => 0,
-        _ => {
-            log::debug!("decode_ascii_tile: unexpected character {} at r={}, c={} in defintiion for tile {}",
-		     s[pos], r, c, id);
-            2 //signal an error.
-        }
-    }
-}
-
 fn self_test() -> Result<(), String> {
+    aor2020::day20::self_test()?;
     let case1 = "Tile 1:
 .###.
 #...#
@@ -527,58 +224,220 @@ Tile 4:
 
     // Check 1<->2 match
     assert!(edge_match(
-        &tiles1.get(&TileId { val: 1 }).unwrap().d.view(),
+        &tiles1[&TileId { val: 1 }],
+        &Manipulation::noop(),
         &Direction::E,
-        &tiles1.get(&TileId { val: 2 }).unwrap().d.view()
+        &tiles1[&TileId { val: 2 }],
+        &Manipulation::noop()
     ));
     assert!(edge_match(
-        &tiles1.get(&TileId { val: 2 }).unwrap().d.view(),
+        &tiles1[&TileId { val: 2 }],
+        &Manipulation::noop(),
         &Direction::W,
-        &tiles1.get(&TileId { val: 1 }).unwrap().d.view()
+        &tiles1[&TileId { val: 1 }],
+        &Manipulation::noop()
     ));
 
     // Check 1<->3 match
     assert!(edge_match(
-        &tiles1.get(&TileId { val: 1 }).unwrap().d.view(),
+        &tiles1[&TileId { val: 1 }],
+        &Manipulation::noop(),
         &Direction::S,
-        &tiles1.get(&TileId { val: 3 }).unwrap().d.view()
+        &tiles1[&TileId { val: 3 }],
+        &Manipulation::noop()
     ));
     assert!(edge_match(
-        &tiles1.get(&TileId { val: 3 }).unwrap().d.view(),
+        &tiles1[&TileId { val: 3 }],
+        &Manipulation::noop(),
         &Direction::N,
-        &tiles1.get(&TileId { val: 1 }).unwrap().d.view()
+        &tiles1[&TileId { val: 1 }],
+        &Manipulation::noop()
     ));
 
     // Check 2<->4 match
     assert!(edge_match(
-        &tiles1.get(&TileId { val: 2 }).unwrap().d.view(),
+        &tiles1[&TileId { val: 2 }],
+        &Manipulation::noop(),
         &Direction::S,
-        &tiles1.get(&TileId { val: 4 }).unwrap().d.view()
+        &tiles1[&TileId { val: 4 }],
+        &Manipulation::noop()
     ));
     assert!(edge_match(
-        &tiles1.get(&TileId { val: 4 }).unwrap().d.view(),
+        &tiles1[&TileId { val: 4 }],
+        &Manipulation::noop(),
         &Direction::N,
-        &tiles1.get(&TileId { val: 2 }).unwrap().d.view()
+        &tiles1[&TileId { val: 2 }],
+        &Manipulation::noop()
     ));
 
     // Check 3<->4 match
     assert!(edge_match(
-        &tiles1.get(&TileId { val: 3 }).unwrap().d.view(),
+        &tiles1[&TileId { val: 3 }],
+        &Manipulation::noop(),
         &Direction::E,
-        &tiles1.get(&TileId { val: 4 }).unwrap().d.view()
+        &tiles1[&TileId { val: 4 }],
+        &Manipulation::noop()
     ));
     assert!(edge_match(
-        &tiles1.get(&TileId { val: 4 }).unwrap().d.view(),
+        &tiles1[&TileId { val: 4 }],
+        &Manipulation::noop(),
         &Direction::W,
-        &tiles1.get(&TileId { val: 3 }).unwrap().d.view()
+        &tiles1[&TileId { val: 3 }],
+        &Manipulation::noop()
     ));
 
     let ix1 = make_tile_index(&tiles1);
     log::debug!("self_test: tile index is: {:?}", ix1);
     let initial_manip = &Manipulation::noop();
-    let sol1 = solve1(&tiles1, &ix1, initial_manip);
+    let sol1 = solve1(&tiles1, &ix1, initial_manip)?;
     log::debug!("self_test: solution is {:?}", sol1);
 
+    // Every edge of these tiles is blank, so any tile fits anywhere
+    // and the greedy solver cannot make progress.  Their interiors
+    // differ and look different in each orientation.
+    let case2 = "Tile 1:
+.....
+.###.
+.#...
+.....
+.....
+
+Tile 2:
+.....
+.##..
+.#...
+.#...
+.....
+
+Tile 3:
+.....
+.#...
+.###.
+.....
+.....
+
+Tile 4:
+.....
+..#..
+..#..
+.##..
+.....
+"
+    .to_string();
+    let tiles2 = read_tiles(&case2);
+    let ix2 = make_tile_index(&tiles2);
+    let sol2 = solve1(&tiles2, &ix2, initial_manip)?;
+    assert_eq!(sol2.len(), 4);
+    assert_eq!(corner_product(&sol2), 24);
+
+    // Without tile 4, there is a hole in the square.
+    let mut tiles3 = read_tiles(&case1);
+    tiles3.remove(&TileId { val: 4 });
+    let ix3 = make_tile_index(&tiles3);
+    let sol3 = solve1(&tiles3, &ix3, initial_manip)?;
+    assert_eq!(holes(&sol3).len(), 1);
+    assert_eq!(corner_product(&sol3), 6);
+    assert_eq!(holes(&sol1), vec![]);
+
+    // Whichever way up the solver starts, the canonical arrangement
+    // has the smallest corner tile, unflipped, at the top left.
+    let expected = Layout::from_solution(&canonical(&sol1, &tiles1));
+    match &expected.rows[0][0] {
+        Some(first) => assert!(first.tile == TileId { val: 1 } && !first.manipulation.flip),
+        None => panic!("the top left of the canonical arrangement is empty"),
+    }
+    for manip in Manipulation::all() {
+        let sol = solve1(&tiles1, &ix1, &manip)?;
+        assert_eq!(Layout::from_solution(&canonical(&sol, &tiles1)), expected);
+    }
+    // Backtracking from the start finds the same arrangement, but
+    // cannot leave a hole.
+    for assembler in assemblers().algorithms() {
+        let sol = (assembler.solver)(&tiles1, &ix1, initial_manip)?;
+        assert_eq!(Layout::from_solution(&canonical(&sol, &tiles1)), expected);
+    }
+    assert!(solve1_backtracking(&tiles3, &ix3, initial_manip).is_err());
+
+    // A layout survives a round trip through JSON, and a layout which
+    // does not fit the tiles is rejected.
+    let layout = Layout::from_solution(&sol1);
+    let json = serde_json::to_string(&layout).map_err(|e| e.to_string())?;
+    let reloaded: Layout = serde_json::from_str(&json).map_err(|e| e.to_string())?;
+    assert_eq!(reloaded, layout);
+    assert_eq!(
+        corner_product(&reloaded.to_solution(&tiles1)?),
+        corner_product(&sol1)
+    );
+    assert!(layout.to_solution(&tiles3).is_err());
+    let mut misfit = reloaded;
+    if let Some(placement) = misfit.rows[0][0].as_mut() {
+        placement.manipulation = placement.manipulation.then(&Manipulation::new(2));
+    }
+    assert!(misfit.to_solution(&tiles1).is_err());
+
+    // Monster locations are (x, y) of the top left of the mask, and
+    // rendering shows the cells of the monsters in red.
+    let mut haystack: Array2<u8> = Array::zeros((4, 22));
+    let nessie_mask = nessie();
+    haystack.slice_mut(s![1..4, 2..22]).assign(&nessie_mask);
+    let locations = find_image_locations(&haystack, &nessie_mask);
+    assert_eq!(locations, vec![(2, 1)]);
+    let canvas = render_image(&haystack, &locations, &nessie_mask, Some(11));
+    assert_eq!(canvas.get(20 * RENDER_SCALE, 1 + RENDER_SCALE), RED);
+    assert_eq!(canvas.get(0, 0), WHITE);
+    assert_eq!(canvas.get(11 * RENDER_SCALE, 0), GREY);
+
+    let drawn = "                  # \n#    ##    ##    ###\n #  #  #  #  #  #\n";
+    assert_eq!(parse_mask(drawn), Ok(nessie()));
+    assert!(parse_mask("..\n..").is_err());
+
+    // A generated puzzle assembles into the image it was made from.
+    let mut image: Array2<u8> = Array::zeros((20, 24));
+    image.slice_mut(s![2..5, 3..23]).assign(&nessie_mask);
+    image[[19, 0]] = 1;
+    let text = generate(&image, 8, &mut Lcg::new(20))?;
+    assert_eq!(text, generate(&image, 8, &mut Lcg::new(20))?);
+    let generated = read_tiles(&text);
+    assert_eq!(generated.len(), 16);
+    let solved = solve1(
+        &generated,
+        &make_tile_index(&generated),
+        &Manipulation::noop(),
+    )?;
+    let assembled = assemble_big_bitmap(&generated, &solved);
+    let fast: i64 = fast_corners(&generated)?
+        .iter()
+        .map(|tid| tid.val as i64)
+        .product();
+    assert_eq!(fast, corner_product(&solved));
+    // With blank edges, nothing stands out as a corner.
+    assert!(fast_corners(&tiles2).is_err());
+    let mut padded: Array2<u8> = Array::zeros((24, 24));
+    padded.slice_mut(s![0..20, ..]).assign(&image);
+    assert!(Manipulation::all()
+        .iter()
+        .any(|m| m.on(&assembled) == padded));
+    // Turning the mask finds the monsters in the same places as
+    // turning the image, whichever way up the image is.
+    for m in Manipulation::all() {
+        let turned = m.on(&padded);
+        let sighting = find_monsters(&turned, &nessie_mask).expect("the monster should be found");
+        let expected = find_monsters_by_turning_image(&turned, &nessie_mask);
+        assert_eq!(Some((sighting.manip, sighting.locations.clone())), expected);
+        assert_eq!(sighting.counts.iter().sum::<usize>(), 1);
+        assert_eq!(
+            find_image_locations(&sighting.bitmap, &nessie_mask),
+            sighting.locations
+        );
+    }
+
+    // Cells shared by overlapping monsters are only counted once.
+    let mask = parse_mask("##")?;
+    let bitmap = arr2(&[[1, 1, 1, 0, 1]]);
+    let locations = find_image_locations(&bitmap, &mask);
+    assert_eq!(locations, vec![(0, 0), (1, 0)]);
+    assert_eq!(measure_roughness(&bitmap, &locations, &mask), 1);
+
     Ok(())
 }
 
@@ -595,41 +454,45 @@ fn read_tiles(s: &str) -> HashMap<TileId, Tile> {
     r.unwrap_or_else(|_| panic!("tiles are not in the expected format"))
 }
 
-fn get_edge_keys(m: &ArrayView2<u8>) -> [EdgeKey; 4] {
-    [
-        EdgeKey::from_matrix(&Direction::N, m),
-        EdgeKey::from_matrix(&Direction::E, m),
-        EdgeKey::from_matrix(&Direction::S, m),
-        EdgeKey::from_matrix(&Direction::W, m),
-    ]
+/// The edge patterns of every manipulation of `t`.
+fn tile_index_entries(t: &Tile) -> Vec<(EdgePattern, TileIndexEntry)> {
+    let variants: Vec<(Manipulation, TileId, Array2<u8>)> = Manipulation::all()
+        .iter()
+        .map(|manip| (*manip, t.id, manip.on(&t.d)))
+        .collect();
+    let mut seen_keys: HashMap<String, Vec<(TileId, Manipulation)>> = HashMap::new();
+    let mut result = Vec::with_capacity(variants.len() * 4);
+    for (manip, tid, v) in &variants {
+        let mk: String = v.iter().map(|x| x.to_string()).collect();
+        seen_keys
+            .entry(mk.clone())
+            .or_default()
+            .push((*tid, *manip));
+        assert_eq!(seen_keys.get(&mk).unwrap().len(), 1);
+
+        for pattern in t.edges(manip).iter() {
+            result.push((
+                *pattern,
+                TileIndexEntry {
+                    tile_id: *tid,
+                    manipulation: *manip,
+                },
+            ));
+        }
+    }
+    result
 }
 
 fn make_tile_index(tiles: &HashMap<TileId, Tile>) -> HashMap<EdgePattern, Vec<TileIndexEntry>> {
+    // Manipulating the tiles is the expensive part, and each tile
+    // can be done independently.
+    let entries: Vec<Vec<(EdgePattern, TileIndexEntry)>> = tiles
+        .par_iter()
+        .map(|(_, t)| tile_index_entries(t))
+        .collect();
     let mut result: HashMap<EdgePattern, Vec<TileIndexEntry>> = HashMap::new();
-    for t in tiles.values() {
-        let variants: Vec<(Manipulation, TileId, Array2<u8>)> = Manipulation::all()
-            .iter()
-            .map(|manip| (*manip, t.id, manip.on(&t.d)))
-            .collect();
-        let mut seen_keys: HashMap<String, Vec<(TileId, Manipulation)>> = HashMap::new();
-        for (manip, tid, v) in &variants {
-            let mk: String = v.iter().map(|x| x.to_string()).collect();
-            seen_keys
-                .entry(mk.clone())
-                .or_insert_with(Vec::new)
-                .push((*tid, *manip));
-            assert_eq!(seen_keys.get(&mk).unwrap().len(), 1);
-
-            for edge_key in get_edge_keys(&v.view()).iter() {
-                result
-                    .entry(edge_key.pattern)
-                    .or_insert_with(Vec::new)
-                    .push(TileIndexEntry {
-                        tile_id: *tid,
-                        manipulation: *manip,
-                    });
-            }
-        }
+    for (pattern, entry) in entries.into_iter().flatten() {
+        result.entry(pattern).or_default().push(entry);
     }
     result
 }
@@ -684,14 +547,25 @@ fn place(
     solution.place_tile(tile, how, pos);
 }
 
+/// Returns true if `candidate` (manipulated by `how`) fits against
+/// `neighbour` (manipulated by `neighbour_how`), which is on its
+/// `neighbour_direction` side.
 fn edge_match(
-    candidate: &ArrayView2<u8>,
+    candidate: &Tile,
+    how: &Manipulation,
     neighbour_direction: &Direction,
-    neighbour: &ArrayView2<u8>,
+    neighbour: &Tile,
+    neighbour_how: &Manipulation,
 ) -> bool {
-    let candidate_edge_key = EdgeKey::from_matrix(neighbour_direction, candidate);
-    let neighbour_edge_key =
-        EdgeKey::from_matrix(&opposite_direction(neighbour_direction), neighbour);
+    let candidate_edge_key = EdgeKey {
+        direction: *neighbour_direction,
+        pattern: candidate.edge(how, *neighbour_direction),
+    };
+    let facing = neighbour_direction.opposite();
+    let neighbour_edge_key = EdgeKey {
+        direction: facing,
+        pattern: neighbour.edge(neighbour_how, facing),
+    };
     let opposing = neighbour_edge_key.opposing();
     let result = opposing == candidate_edge_key;
     let desc = if result { "match" } else { "no match" };
@@ -747,9 +621,11 @@ fn candidate_fits_neighbours(
         );
         let neighbour: &Tile = tiles.get(&neighbour_id).expect("missing neighbour");
         if !edge_match(
-            &candidate_tile.manipulated(&cand.manipulation).view(),
+            candidate_tile,
+            &cand.manipulation,
             neighbour_direction,
-            &neighbour.manipulated(&neighbour_manipulation).view(),
+            neighbour,
+            &neighbour_manipulation,
         ) {
             log::debug!("candidate_fits_neighbours: no, tile {} cannot be placed at {} becauise it does not match its neighbour {} at {}",
 		     cand.tile_id, proposed_pos, neighbour_id, neighbour_pos);
@@ -801,12 +677,25 @@ fn get_candidates(
     result
 }
 
+/// Reverses the effect of `place`.
+fn unplace(
+    tile_id: &TileId,
+    tiles: &HashMap<TileId, Tile>,
+    solution: &mut TileLocationSolution,
+    todo: &mut HashSet<TileId>,
+) {
+    solution.remove_tile(tile_id, tiles);
+    assert!(todo.insert(*tile_id));
+}
+
+/// Places one tile, at a position where only that tile fits in only
+/// one way.  Returns false if there is no such position.
 fn solve1x(
     tiles: &HashMap<TileId, Tile>,
     ix: &HashMap<EdgePattern, Vec<TileIndexEntry>>,
     solution: &mut TileLocationSolution,
     todo: &mut HashSet<TileId>,
-) {
+) -> bool {
     if solution.len() == tiles.len() {
         panic!("solve1x was called with all tiles already placed");
     }
@@ -815,18 +704,26 @@ fn solve1x(
         log::debug!("solve1x: tile {} is at {} ({})", t, pos, manip);
     }
 
-    for exposed_edge in solution.exposed_edges.iter() {
-        let pos = get_neighbour(&exposed_edge.pos, exposed_edge.direction);
-        let candidates: HashMap<TileId, Vec<Manipulation>> =
-            get_candidates(tiles, ix, solution, exposed_edge);
-        log::debug!(
-            "There are {} candidate tiles for occupation of {}",
-            candidates.len(),
-            pos
-        );
-        if candidates.len() > 1 {
-            log::debug!("Since there's more than one option for {} we will defer filling that spot for now.", pos);
-        } else {
+    // The candidates for each exposed edge are independent, so we
+    // look at the edges in parallel.  find_map_first still chooses the
+    // first edge (in order) at which a tile can be placed, so the result
+    // does not depend on the timing of the threads, and the edges after
+    // that one need not be finished.
+    let found = solution
+        .exposed_edges
+        .par_iter()
+        .find_map_first(|exposed_edge| {
+            let pos = get_neighbour(&exposed_edge.pos, exposed_edge.direction);
+            let candidates = get_candidates(tiles, ix, solution, exposed_edge);
+            log::debug!(
+                "There are {} candidate tiles for occupation of {}",
+                candidates.len(),
+                pos
+            );
+            if candidates.len() > 1 {
+                log::debug!("Since there's more than one option for {} we will defer filling that spot for now.", pos);
+                return None;
+            }
             for (tile_id, manipulations) in candidates.iter() {
                 log::debug!(
                     "Tile {} will fit at {} in {} different ways",
@@ -835,34 +732,141 @@ fn solve1x(
                     manipulations.len()
                 );
                 match manipulations.as_slice() {
-                    [manip] => {
-                        place(tile_id, manip, &pos, tiles, solution, todo);
-                        return;
-                    }
+                    [manip] => return Some((*tile_id, *manip, pos)),
                     [] => (),
                     _ => {
                         log::debug!("Since there's more than one way to fit {} into {} we will defer filling that spot for now.", tile_id, pos);
                     }
                 }
             }
+            None
+        });
+    match found {
+        Some((tile_id, manip, pos)) => {
+            place(&tile_id, &manip, &pos, tiles, solution, todo);
+            true
         }
+        None => false,
     }
-    panic!("solve1x: failed to make any progress");
 }
 
-fn solve1(
+/// The ways in which tiles could be placed at the empty neighbour of
+/// `exposed_edge`, as (position, tile, manipulation).
+fn placements(
     tiles: &HashMap<TileId, Tile>,
     ix: &HashMap<EdgePattern, Vec<TileIndexEntry>>,
-    initial_manip: &Manipulation,
-) -> TileLocationSolution {
-    let mut todo: HashSet<TileId> = tiles.keys().copied().collect();
-    let initial = match tiles.keys().min() {
-        Some(n) => n,
+    solution: &TileLocationSolution,
+    exposed_edge: &ExposedEdge,
+) -> Vec<(Position, TileId, Manipulation)> {
+    let pos = get_neighbour(&exposed_edge.pos, exposed_edge.direction);
+    let mut result: Vec<(Position, TileId, Manipulation)> =
+        get_candidates(tiles, ix, solution, exposed_edge)
+            .into_iter()
+            .flat_map(|(tile_id, manips)| manips.into_iter().map(move |m| (pos, tile_id, m)))
+            .collect();
+    // Try the tiles in a predictable order.
+    result.sort_by_key(|(_, tile_id, manip)| (*tile_id, manip.index()));
+    result
+}
+
+/// Places the remaining tiles by trying each tile which fits at some
+/// position in turn, undoing the placement if the other tiles then
+/// cannot all be placed.  The tiles must form a square whose sides are
+/// `side` tiles long.  Returns false if there is no way to place the
+/// remaining tiles.
+fn solve_backtracking(
+    tiles: &HashMap<TileId, Tile>,
+    ix: &HashMap<EdgePattern, Vec<TileIndexEntry>>,
+    solution: &mut TileLocationSolution,
+    todo: &mut HashSet<TileId>,
+    side: i32,
+) -> bool {
+    if todo.is_empty() {
+        return true;
+    }
+    // The finished square contains the rectangle enclosing the tiles
+    // placed so far, so every empty position in that rectangle must be
+    // filled.  We choose the one with the fewest candidates.
+    let (minx, maxx, miny, maxy) = extrema(solution);
+    let inside = |pos: &Position| (minx..=maxx).contains(&pos.x) && (miny..=maxy).contains(&pos.y);
+    let mut options: Option<Vec<(Position, TileId, Manipulation)>> = None;
+    for exposed_edge in solution.exposed_edges.iter() {
+        if !inside(&get_neighbour(&exposed_edge.pos, exposed_edge.direction)) {
+            continue;
+        }
+        let here = placements(tiles, ix, solution, exposed_edge);
+        if options
+            .as_ref()
+            .map_or(true, |best| here.len() < best.len())
+        {
+            options = Some(here);
+        }
+    }
+    let options = match options {
+        Some(options) => options,
         None => {
-            // No tiles, so nothing to do.
-            return TileLocationSolution::new();
+            // The placed tiles fill the rectangle, so it must grow
+            // along a side which is still too short.  If it grows
+            // east, there is a tile just east of its south-east
+            // corner, and similarly for the other directions.
+            let growth = if maxx - minx + 1 < side {
+                [
+                    Position {
+                        x: maxx + 1,
+                        y: miny,
+                    },
+                    Position {
+                        x: minx - 1,
+                        y: miny,
+                    },
+                ]
+            } else if maxy - miny + 1 < side {
+                [
+                    Position {
+                        x: minx,
+                        y: maxy + 1,
+                    },
+                    Position {
+                        x: minx,
+                        y: miny - 1,
+                    },
+                ]
+            } else {
+                return false;
+            };
+            solution
+                .exposed_edges
+                .iter()
+                .filter(|e| growth.contains(&get_neighbour(&e.pos, e.direction)))
+                .flat_map(|e| placements(tiles, ix, solution, e))
+                .collect()
         }
     };
+    for (pos, tile_id, manip) in options {
+        log::debug!(
+            "solve_backtracking: trying tile {} ({}) at {}",
+            tile_id,
+            manip,
+            pos
+        );
+        place(&tile_id, &manip, &pos, tiles, solution, todo);
+        if solve_backtracking(tiles, ix, solution, todo, side) {
+            return true;
+        }
+        unplace(&tile_id, tiles, solution, todo);
+    }
+    false
+}
+
+/// Places the tile with the smallest ID at (0,0), turned by
+/// `initial_manip`, returning the solution so far and the tiles left
+/// to place.  Returns None if there are no tiles.
+fn place_first(
+    tiles: &HashMap<TileId, Tile>,
+    initial_manip: &Manipulation,
+) -> Option<(TileLocationSolution, HashSet<TileId>)> {
+    let mut todo: HashSet<TileId> = tiles.keys().copied().collect();
+    let initial = tiles.keys().min()?;
     log::debug!(
         "Placing intitial tile {} at (0,0) with manipulation {}",
         initial,
@@ -877,17 +881,107 @@ fn solve1(
         &mut solution,
         &mut todo,
     );
+    Some((solution, todo))
+}
+
+/// The length of the sides of the square which the tiles form, for
+/// `solve_backtracking`.  `left` is the number of tiles still to place.
+fn square_side(tiles: usize, left: usize) -> Result<i32, String> {
+    let side = (1..).find(|n| n * n >= tiles).unwrap_or(0);
+    if side * side != tiles {
+        return Err(format!(
+            "{} tiles cannot form a square, so there is no way to place the last {}",
+            tiles, left
+        ));
+    }
+    Ok(side as i32)
+}
+
+fn solve1(
+    tiles: &HashMap<TileId, Tile>,
+    ix: &HashMap<EdgePattern, Vec<TileIndexEntry>>,
+    initial_manip: &Manipulation,
+) -> Result<TileLocationSolution, String> {
+    let (mut solution, mut todo) = match place_first(tiles, initial_manip) {
+        Some(started) => started,
+        None => {
+            // No tiles, so nothing to do.
+            return Ok(TileLocationSolution::new());
+        }
+    };
     while !todo.is_empty() {
         log::debug!("solve1: {}/{} tiles left to place", todo.len(), tiles.len());
-        let before = todo.len();
-        solve1x(tiles, ix, &mut solution, &mut todo);
-        let after = todo.len();
-        if after >= before {
-            panic!("solve1: no progress was made in call to solve1x.");
+        if !solve1x(tiles, ix, &mut solution, &mut todo) {
+            log::info!(
+                "solve1: no tile fits in just one place, searching for a solution with {} tiles left",
+                todo.len()
+            );
+            let side = square_side(tiles.len(), todo.len())?;
+            if !solve_backtracking(tiles, ix, &mut solution, &mut todo, side) {
+                return Err("there is no way to assemble the tiles into a square".to_string());
+            }
         }
     }
     log::debug!("solve1: all {} tiles are in place.", tiles.len());
-    solution
+    Ok(solution)
+}
+
+/// Like `solve1`, but searches with `solve_backtracking` from the
+/// start instead of only when no tile fits in just one place.  Unlike
+/// `solve1`, this needs the tiles to form a square.
+fn solve1_backtracking(
+    tiles: &HashMap<TileId, Tile>,
+    ix: &HashMap<EdgePattern, Vec<TileIndexEntry>>,
+    initial_manip: &Manipulation,
+) -> Result<TileLocationSolution, String> {
+    let (mut solution, mut todo) = match place_first(tiles, initial_manip) {
+        Some(started) => started,
+        None => return Ok(TileLocationSolution::new()),
+    };
+    let side = square_side(tiles.len(), todo.len())?;
+    if !solve_backtracking(tiles, ix, &mut solution, &mut todo, side) {
+        return Err("there is no way to assemble the tiles into a square".to_string());
+    }
+    Ok(solution)
+}
+
+type Assembler = fn(
+    &HashMap<TileId, Tile>,
+    &HashMap<EdgePattern, Vec<TileIndexEntry>>,
+    &Manipulation,
+) -> Result<TileLocationSolution, String>;
+
+fn assemblers() -> Registry<Assembler> {
+    Registry::for_day(20)
+        .register(
+            "greedy",
+            "places tiles which fit in only one way, backtracking when stuck",
+            solve1 as Assembler,
+        )
+        .register(
+            "backtracking",
+            "backtracks from the first tile",
+            solve1_backtracking,
+        )
+}
+
+/// Solves both parts with each assembler, for the crosscheck command.
+fn crosscheck(tiles: &HashMap<TileId, Tile>, out: &mut impl Write) -> Result<(), String> {
+    let ix = make_tile_index(tiles);
+    let nessie_mask = nessie();
+    let report = assemblers().crosscheck(|assemble| {
+        let solution = canonical(&assemble(tiles, &ix, &Manipulation::noop())?, tiles);
+        let big_bitmap = assemble_big_bitmap(tiles, &solution);
+        let sighting = find_monsters(&big_bitmap, &nessie_mask)
+            .ok_or_else(|| "found no sea monsters".to_string())?;
+        Ok(format!(
+            "corner product {}, roughness {}",
+            corner_product(&solution),
+            measure_roughness(&sighting.bitmap, &sighting.locations, &nessie_mask)
+        ))
+    })?;
+    writeln!(out, "{}", report).map_err(write_error)?;
+    Ok(())
 }
 
 fn min_and_max<T>(things: T) -> (i32, i32)
@@ -908,30 +1002,139 @@ fn extrema(solution: &TileLocationSolution) -> (i32, i32, i32, i32) {
 
 fn corners(solution: &TileLocationSolution) -> [Position; 4] {
     let (minx, maxx, miny, maxy) = extrema(solution);
-    let result = [
+    [
         Position { x: minx, y: miny },
         Position { x: minx, y: maxy },
         Position { x: maxx, y: miny },
         Position { x: maxx, y: maxy },
-    ];
-    for pos in &result {
-        match solution.position_to_tile.get(pos) {
-            None => {
-                panic!("solution is not rectangular; {} is not occupied", pos);
-            }
-            Some(_) => (),
-        }
-    }
-    result
+    ]
+}
+
+/// The empty positions within the rectangle enclosing the placed
+/// tiles, from the top left, row by row.
+fn holes(solution: &TileLocationSolution) -> Vec<Position> {
+    let (minx, maxx, miny, maxy) = extrema(solution);
+    (miny..=maxy)
+        .rev()
+        .flat_map(|y| (minx..=maxx).map(move |x| Position { x, y }))
+        .filter(|pos| !solution.occupied(pos))
+        .collect()
 }
 
+/// The product of the IDs of the tiles in the corners.  If the
+/// arrangement has holes, empty corners are ignored.
 fn corner_product(solution: &TileLocationSolution) -> i64 {
     corners(solution)
         .iter()
-        .map(|pos| solution.position_to_tile.get(pos).unwrap().val as i64)
+        .filter_map(|pos| solution.position_to_tile.get(pos))
+        .map(|tid| tid.val as i64)
         .product()
 }
 
+/// Finds the corner tiles without assembling the picture.  Each side
+/// of a corner tile except two matches a side of some other tile (in
+/// either orientation), while edge tiles have one unmatched side and
+/// the rest have none.  This relies on the matching sides being
+/// unique, as they are in the real puzzle; if some are not, fewer
+/// than four tiles look like corners and the full solver is needed.
+fn fast_corners(tiles: &HashMap<TileId, Tile>) -> Result<Vec<TileId>, String> {
+    // The number of tiles with each pattern on some side.  Every
+    // pattern appears forwards and backwards among the manipulations
+    // of the tile.
+    let mut tiles_with_pattern: HashMap<EdgePattern, usize> = HashMap::new();
+    for tile in tiles.values() {
+        let patterns: HashSet<EdgePattern> = Manipulation::all()
+            .iter()
+            .flat_map(|how| tile.edges(how).iter().copied())
+            .collect();
+        for pattern in patterns {
+            *tiles_with_pattern.entry(pattern).or_insert(0) += 1;
+        }
+    }
+    let mut result: Vec<TileId> = tiles
+        .values()
+        .filter(|tile| {
+            let unmatched = tile
+                .edges(&Manipulation::noop())
+                .iter()
+                .filter(|pattern| tiles_with_pattern[pattern] == 1)
+                .count();
+            unmatched == 2
+        })
+        .map(|tile| tile.id)
+        .collect();
+    if result.len() != 4 {
+        return Err(format!(
+            "{} tiles have two unmatched sides, but a square has 4 corners",
+            result.len()
+        ));
+    }
+    result.sort_by_key(|tid| tid.val);
+    Ok(result)
+}
+
+/// Solves part 1 with `fast_corners`, for --fast-part1.
+fn fast_part1(tiles: &HashMap<TileId, Tile>, out: &mut impl Write) -> Result<(), String> {
+    let corners = fast_corners(tiles)?;
+    let listing: Vec<String> = corners.iter().map(|tid| tid.to_string()).collect();
+    writeln!(out, "Part 1: corner tiles are {}", listing.join(" ")).map_err(write_error)?;
+    writeln!(
+        out,
+        "Part 1: corner product is {}",
+        corners.iter().map(|tid| tid.val as i64).product::<i64>()
+    )
+    .map_err(write_error)?;
+    Ok(())
+}
+
+/// Turns the whole arrangement over so that it comes out the same
+/// whichever way round the solver happened to build it: the corner
+/// tile with the smallest ID goes at the top left, unflipped.  The
+/// positions are renumbered so that the bottom left one is (0,0).
+fn canonical(
+    solution: &TileLocationSolution,
+    tiles: &HashMap<TileId, Tile>,
+) -> TileLocationSolution {
+    let (minx, maxx, miny, maxy) = extrema(solution);
+    // The arrangement as a matrix, top row first.
+    let grid: Array2<Option<(TileId, Manipulation)>> = Array::from_shape_fn(
+        ((maxy - miny + 1) as usize, (maxx - minx + 1) as usize),
+        |(r, c)| {
+            solution.get_tile_at_position(&Position {
+                x: minx + c as i32,
+                y: maxy - r as i32,
+            })
+        },
+    );
+    let first: Option<TileId> = corners(solution)
+        .iter()
+        .filter_map(|pos| solution.position_to_tile.get(pos))
+        .min()
+        .copied();
+    // Of the two ways to put the first tile at the top left, one is
+    // the reflection of the other, so exactly one leaves it unflipped.
+    let (turn, grid) = Manipulation::all()
+        .into_iter()
+        .map(|g| (g, g.on(&grid)))
+        .find(|(g, turned)| match turned[[0, 0]] {
+            Some((id, how)) => Some(id) == first && !how.then(g).flip,
+            None => false,
+        })
+        .unwrap_or_else(|| (Manipulation::noop(), grid));
+    let mut result = TileLocationSolution::new();
+    let top = grid.nrows() as i32 - 1;
+    for ((r, c), cell) in grid.indexed_iter() {
+        if let Some((id, how)) = cell {
+            let pos = Position {
+                x: c as i32,
+                y: top - r as i32,
+            };
+            result.place_tile(&tiles[id], &how.then(&turn), &pos);
+        }
+    }
+    result
+}
+
 fn solution_as_string(solution: &TileLocationSolution) -> String {
     let column_width = 1 + cmp::max(
         4, // width of manipulation representation
@@ -939,7 +1142,7 @@ fn solution_as_string(solution: &TileLocationSolution) -> String {
     );
     let mut result = String::new();
     let (minx, maxx, miny, maxy) = extrema(solution);
-    for y in miny..=maxy {
+    for y in (miny..=maxy).rev() {
         let mut id_row = String::new();
         let mut manip_row = String::new();
         for x in minx..=maxx {
@@ -959,35 +1162,176 @@ fn solution_as_string(solution: &TileLocationSolution) -> String {
     result
 }
 
-fn part1(tiles: &HashMap<TileId, Tile>) -> Result<TileLocationSolution, String> {
+/// A tile and its orientation in a saved layout.
+#[derive(Debug, PartialEq, Serialize, Deserialize)]
+struct Placement {
+    tile: TileId,
+    manipulation: Manipulation,
+}
+
+/// A solved arrangement of the tiles, as written by --save-layout and
+/// read by --layout.  The rows run from the top (y = maxy) down and
+/// the columns from x = minx; empty positions are null.
+#[derive(Debug, PartialEq, Serialize, Deserialize)]
+struct Layout {
+    minx: i32,
+    maxx: i32,
+    miny: i32,
+    maxy: i32,
+    rows: Vec<Vec<Option<Placement>>>,
+}
+
+impl Layout {
+    fn from_solution(solution: &TileLocationSolution) -> Layout {
+        let (minx, maxx, miny, maxy) = extrema(solution);
+        let rows = (miny..=maxy)
+            .rev()
+            .map(|y| {
+                (minx..=maxx)
+                    .map(|x| {
+                        solution
+                            .get_tile_at_position(&Position { x, y })
+                            .map(|(tile, manipulation)| Placement { tile, manipulation })
+                    })
+                    .collect()
+            })
+            .collect();
+        Layout {
+            minx,
+            maxx,
+            miny,
+            maxy,
+            rows,
+        }
+    }
+
+    /// Places the tiles as the layout says, checking that the layout
+    /// is for these tiles and that neighbouring tiles fit together.
+    fn to_solution(&self, tiles: &HashMap<TileId, Tile>) -> Result<TileLocationSolution, String> {
+        let width = (self.maxx - self.minx + 1) as usize;
+        if self.rows.len() != (self.maxy - self.miny + 1) as usize
+            || self.rows.iter().any(|row| row.len() != width)
+        {
+            return Err("the rows of the layout do not match its extents".to_string());
+        }
+        let mut solution = TileLocationSolution::new();
+        for (row, y) in self.rows.iter().zip((self.miny..=self.maxy).rev()) {
+            for (cell, x) in row.iter().zip(self.minx..) {
+                let placement = match cell {
+                    Some(placement) => placement,
+                    None => continue,
+                };
+                let tile = tiles.get(&placement.tile).ok_or_else(|| {
+                    format!("the layout contains unknown tile {}", placement.tile)
+                })?;
+                if solution.get_position_of_tile(&tile.id).is_some() {
+                    return Err(format!("the layout contains tile {} twice", tile.id));
+                }
+                let pos = Position { x, y };
+                for d in Direction::all().iter() {
+                    if let Some((n, how)) = solution.get_tile_at_position(&get_neighbour(&pos, *d))
+                    {
+                        if !edge_match(tile, &placement.manipulation, d, &tiles[&n], &how) {
+                            return Err(format!(
+                                "tile {} at {} does not fit against tile {}",
+                                tile.id, pos, n
+                            ));
+                        }
+                    }
+                }
+                solution.place_tile(tile, &placement.manipulation, &pos);
+            }
+        }
+        if solution.len() != tiles.len() {
+            return Err(format!(
+                "the layout places {} of the {} tiles",
+                solution.len(),
+                tiles.len()
+            ));
+        }
+        Ok(solution)
+    }
+}
+
+fn load_layout(
+    filename: &str,
+    tiles: &HashMap<TileId, Tile>,
+) -> Result<TileLocationSolution, String> {
+    let f = File::open(filename).map_err(|e| format!("failed to open {}: {}", filename, e))?;
+    let layout: Layout = serde_json::from_reader(io::BufReader::new(f))
+        .map_err(|e| format!("failed to load layout from {}: {}", filename, e))?;
+    layout
+        .to_solution(tiles)
+        .map_err(|e| format!("{}: {}", filename, e))
+}
+
+fn save_layout(filename: &str, solution: &TileLocationSolution) -> Result<(), String> {
+    let f = File::create(filename).map_err(|e| format!("failed to create {}: {}", filename, e))?;
+    serde_json::to_writer_pretty(io::BufWriter::new(f), &Layout::from_solution(solution))
+        .map_err(|e| format!("failed to save layout to {}: {}", filename, e))
+}
+
+fn part1(
+    tiles: &HashMap<TileId, Tile>,
+    options: &Options,
+    out: &mut impl Write,
+) -> Result<TileLocationSolution, String> {
     let ix = make_tile_index(tiles);
     log::debug!("part1: tile index is: {:?}", ix);
-    // For convenience in debugging we choose an initial manipulation
-    // that makes the result come out lookikng like the sample in the
-    // question.
-    let initial_manip = &Manipulation {
-        rot: Rotation::Zero,
-        flip: true,
-    };
-    let sol = solve1(tiles, &ix, initial_manip);
-    println!("Part 1: Solution is:\n{}", solution_as_string(&sol));
-    println!("Part 1: corner product is {}", corner_product(&sol));
+    // The orientation of the first tile is arbitrary, so we turn the
+    // solution round into a canonical orientation.
+    let assemble = options.algorithm.unwrap_or(solve1);
+    let sol = canonical(&assemble(tiles, &ix, &Manipulation::noop())?, tiles);
+    // With --json, only the report from part 2 is written.
+    if options.json {
+        return Ok(sol);
+    }
+    writeln!(out, "Part 1: Solution is:\n{}", solution_as_string(&sol)).map_err(write_error)?;
+    let empty = holes(&sol);
+    if !empty.is_empty() {
+        let listing = empty
+            .iter()
+            .map(|pos| pos.to_string())
+            .collect::<Vec<_>>()
+            .join(" ");
+        if !options.allow_holes {
+            return Err(format!(
+                "the tiles do not form a rectangle; {} positions are empty: {}",
+                empty.len(),
+                listing
+            ));
+        }
+        writeln!(
+            out,
+            "Part 1: {} positions are empty: {}",
+            empty.len(),
+            listing
+        )
+        .map_err(write_error)?;
+    }
+    writeln!(out, "Part 1: corner product is {}", corner_product(&sol)).map_err(write_error)?;
     Ok(sol)
 }
 
+/// The interior of the tile at `pos`, or None if there is no tile
+/// there.
 fn interior_tile_at(
     pos: &Position,
     tiles: &HashMap<TileId, Tile>,
     solution: &TileLocationSolution,
-) -> Array2<u8> {
-    let tile_id = solution.position_to_tile.get(pos).expect("missing tile");
+) -> Option<Array2<u8>> {
+    let tile_id = solution.position_to_tile.get(pos)?;
     let (_, manip) = solution
         .tile_to_position
         .get(tile_id)
         .expect("inconsistent solution");
-    tiles.get(tile_id).expect("unknown tile").interior(manip)
+    Some(tiles.get(tile_id).expect("unknown tile").interior(manip))
 }
 
+/// The value of the cells of the assembled image where there is no
+/// tile.
+const GAP: u8 = 2;
+
 fn assemble_big_bitmap(
     tiles: &HashMap<TileId, Tile>,
     solution: &TileLocationSolution,
@@ -1005,26 +1349,26 @@ fn assemble_big_bitmap(
         (tile_height * (maxy - miny + 1)) as usize,
         (tile_width * (maxx - minx + 1)) as usize,
     );
-    // We use 2 as a marker value to determine whether we have
-    // correctly set all the elements in the output array from the
-    // data in the solution.
-    let mut result: Array2<u8> = Array::from_elem(shape, 2);
+    // Cells where there is no tile keep the value GAP.  We count
+    // them to check that we have correctly set all the other elements
+    // in the output array from the data in the solution.
+    let mut result: Array2<u8> = Array::from_elem(shape, GAP);
     for (i, y) in (miny..=maxy).rev().enumerate() {
         let ytop: i32 = i as i32 * tile_height;
         for (j, x) in (minx..=maxx).enumerate() {
             let xleft = j as i32 * tile_width;
-            let b = interior_tile_at(&Position { x, y }, tiles, solution);
-            result
-                .slice_mut(s![ytop..ytop + tile_height, xleft..xleft + tile_width])
-                .assign(&b);
+            if let Some(b) = interior_tile_at(&Position { x, y }, tiles, solution) {
+                result
+                    .slice_mut(s![ytop..ytop + tile_height, xleft..xleft + tile_width])
+                    .assign(&b);
+            }
         }
     }
-    match result.iter().max() {
-        None => panic!("output is empty"),
-        Some(0) | Some(1) => result,
-        Some(2) => panic!("some parts of the output array have not been update with the solution"),
-        _ => panic!("some parts of the output array have not been initialised"),
+    let gaps = result.iter().filter(|x| **x == GAP).count();
+    if gaps != holes(solution).len() * (tile_height * tile_width) as usize {
+        panic!("some parts of the output array have not been updated with the solution");
     }
+    result
 }
 
 fn render_bitmap(b: &Array2<u8>) -> String {
@@ -1057,15 +1401,44 @@ fn nessie() -> Array2<u8> {
     beastie.into_shape((3, 20)).unwrap()
 }
 
+/// Reads a mask drawn like the sea monster in the puzzle, with '#'
+/// for the cells which must be set and '.' or ' ' for the others.
+/// Short lines are padded with blanks.
+fn parse_mask(s: &str) -> Result<Array2<u8>, String> {
+    let lines: Vec<&str> = s.lines().filter(|line| !line.trim().is_empty()).collect();
+    let width = lines
+        .iter()
+        .map(|line| line.chars().count())
+        .max()
+        .unwrap_or(0);
+    let mut mask: Array2<u8> = Array::zeros((lines.len(), width));
+    for (r, line) in lines.iter().enumerate() {
+        for (c, ch) in line.chars().enumerate() {
+            mask[[r, c]] = match ch {
+                '#' => 1,
+                '.' | ' ' => 0,
+                _ => return Err(format!("unexpected character '{}' in mask", ch)),
+            };
+        }
+    }
+    if count_ones(&mask) == 0 {
+        return Err("a mask must contain at least one '#'".to_string());
+    }
+    Ok(mask)
+}
+
 fn mask_match(win: &ArrayView2<u8>, mask: &ArrayView2<u8>) -> bool {
     Zip::from(win)
         .and(mask)
         // We only care about the state of w where the mask is nonzero.
-        .all(|&w_elem, &m_elem| (m_elem == 0) || (w_elem != 0))
+        .all(|&w_elem, &m_elem| (m_elem == 0) || (w_elem == 1))
 }
 
 fn find_image_locations(haystack: &Array2<u8>, mask: &Array2<u8>) -> Vec<(i32, i32)> {
-    let haystack_width = haystack.shape()[1];
+    if mask.nrows() > haystack.nrows() || mask.ncols() > haystack.ncols() {
+        return Vec::new();
+    }
+    let windows_per_row = haystack.shape()[1] - mask.shape()[1] + 1;
     haystack
         .windows((mask.shape()[0], mask.shape()[1]))
         .into_iter()
@@ -1073,8 +1446,8 @@ fn find_image_locations(haystack: &Array2<u8>, mask: &Array2<u8>) -> Vec<(i32, i
         .filter_map(|(i, w)| {
             if mask_match(&w.view(), &mask.view()) {
                 log::debug!("found sea monster at i={}", i);
-                let y = i / haystack_width;
-                let x = i % haystack_width;
+                let y = i / windows_per_row;
+                let x = i % windows_per_row;
                 Some((x as i32, y as i32))
             } else {
                 None
@@ -1087,14 +1460,137 @@ fn count_ones(m: &Array2<u8>) -> usize {
     m.iter().filter(|&&x| x == 1).count()
 }
 
-fn measure_roughness(bitmap: &Array2<u8>, locations: &Vec<(i32, i32)>, mask: &Array2<u8>) -> usize {
-    count_ones(bitmap) - (locations.len() * count_ones(mask))
+/// Marks the cells of `bitmap` covered by the set cells of `mask` at
+/// each of `locations`.
+fn monster_cells(bitmap: &Array2<u8>, locations: &[(i32, i32)], mask: &Array2<u8>) -> Array2<bool> {
+    let mut monster: Array2<bool> = Array::from_elem(bitmap.dim(), false);
+    for (x, y) in locations {
+        for ((r, c), m) in mask.indexed_iter() {
+            if *m != 0 {
+                monster[[*y as usize + r, *x as usize + c]] = true;
+            }
+        }
+    }
+    monster
 }
 
-fn solve2(tiles: &HashMap<TileId, Tile>, solution: &TileLocationSolution) -> usize {
-    let big_bitmap = assemble_big_bitmap(tiles, solution);
-    log::debug!("big bitmap is:\n{}", render_bitmap(&big_bitmap));
-    let nessie_mask = nessie();
+/// Counts the set cells of `bitmap` which are not part of a monster.
+/// Monsters may overlap, so we cannot simply subtract the size of the
+/// mask once for each of them.
+fn measure_roughness(bitmap: &Array2<u8>, locations: &[(i32, i32)], mask: &Array2<u8>) -> usize {
+    let monster = monster_cells(bitmap, locations, mask);
+    Zip::from(bitmap)
+        .and(&monster)
+        .fold(0, |n, &b, &m| if b == 1 && !m { n + 1 } else { n })
+}
+
+/// Describes a sighting for --json.  Locations are the (x, y) of the
+/// top left corner of the mask in the image turned by `manip`.
+fn sighting_report(
+    manip: &Manipulation,
+    locations: &[(i32, i32)],
+    counts: &[usize; 8],
+    roughness: usize,
+) -> serde_json::Value {
+    let monsters: Vec<serde_json::Value> = locations
+        .iter()
+        .map(|(x, y)| serde_json::json!({"x": x, "y": y}))
+        .collect();
+    let matches: serde_json::Map<String, serde_json::Value> = Manipulation::all()
+        .iter()
+        .map(|m| {
+            (
+                m.to_string(),
+                serde_json::Value::from(counts[m.index() as usize]),
+            )
+        })
+        .collect();
+    serde_json::json!({
+        "orientation": manip.to_string(),
+        "monsters": monsters,
+        "matches_by_orientation": matches,
+        "roughness": roughness,
+    })
+}
+
+fn write_json(value: &serde_json::Value, out: &mut impl Write) -> Result<(), String> {
+    writeln!(
+        out,
+        "{}",
+        serde_json::to_string_pretty(value).map_err(|e| e.to_string())?
+    )
+    .map_err(write_error)?;
+    Ok(())
+}
+
+/// The orientation of the image in which the most monsters appear,
+/// the image in that orientation, and the locations of the monsters
+/// in it.
+struct Sighting {
+    manip: Manipulation,
+    bitmap: Array2<u8>,
+    locations: Vec<(i32, i32)>,
+    /// The number of monsters in each orientation of the image,
+    /// indexed by `Manipulation::index`.
+    counts: [usize; 8],
+}
+
+/// Finds the orientation of `big_bitmap` in which the most sea
+/// monsters appear.  Returns None if there are no monsters in any
+/// orientation.  Rather than turning the whole image each way, we
+/// turn the (much smaller) mask the opposite way and search the image
+/// as it is; only the image in the best orientation is made.
+fn find_monsters(big_bitmap: &Array2<u8>, nessie_mask: &Array2<u8>) -> Option<Sighting> {
+    let mut counts = [0; 8];
+    let mut best: Option<(Manipulation, Vec<(i32, i32)>)> = None;
+    // When there is a tie, prefer unflipped orientations of each
+    // rotation, as we always have.
+    let orientations = [0, 4, 1, 5, 2, 6, 3, 7]
+        .iter()
+        .map(|i| Manipulation::new(*i));
+    for manip in orientations {
+        let mask = manip.inverse().on(nessie_mask);
+        let locations = find_image_locations(big_bitmap, &mask);
+        log::info!("Part 2: monster locations ({}): {:?}", manip, locations);
+        counts[manip.index() as usize] = locations.len();
+        let more = match &best {
+            Some((_, most)) => locations.len() > most.len(),
+            None => !locations.is_empty(),
+        };
+        if more {
+            best = Some((manip, locations));
+        }
+    }
+    let (manip, locations) = best?;
+    // The top left corner of the mask in the turned image is where
+    // the corner of the turned mask which ends up there was found.
+    let (rows, cols) = manip.inverse().on(nessie_mask).dim();
+    let mut locations: Vec<(i32, i32)> = locations
+        .iter()
+        .map(|(x, y)| {
+            let (x, y) = (*x as usize, *y as usize);
+            let (r1, c1) = manip.cell((y, x), big_bitmap.dim());
+            let (r2, c2) = manip.cell((y + rows - 1, x + cols - 1), big_bitmap.dim());
+            (c1.min(c2) as i32, r1.min(r2) as i32)
+        })
+        .collect();
+    locations.sort_by_key(|(x, y)| (*y, *x));
+    Some(Sighting {
+        manip,
+        bitmap: manip.on(big_bitmap),
+        locations,
+        counts,
+    })
+}
+
+/// Finds the orientation of `big_bitmap` in which the most sea
+/// monsters appear by turning the whole image each way.  This is how
+/// `find_monsters` used to work; we keep it to compare with.
+fn find_monsters_by_turning_image(
+    big_bitmap: &Array2<u8>,
+    nessie_mask: &Array2<u8>,
+) -> Option<(Manipulation, Vec<(i32, i32)>)> {
+    let mut best: Option<(Manipulation, Vec<(i32, i32)>)> = None;
     for rot in [
         Rotation::Zero,
         Rotation::One,
@@ -1103,42 +1599,485 @@ fn solve2(tiles: &HashMap<TileId, Tile>, solution: &TileLocationSolution) -> usi
     ] {
         for flip in [false, true] {
             let manip = Manipulation { rot, flip };
-            let tweaked = manip.on(&big_bitmap);
-            let locations = find_image_locations(&tweaked, &nessie_mask);
-            log::info!("Part 2: monster locations ({}): {:?}", manip, locations);
-            if !locations.is_empty() {
-                return measure_roughness(&tweaked, &locations, &nessie_mask);
+            let tweaked = manip.on(big_bitmap);
+            let locations = find_image_locations(&tweaked, nessie_mask);
+            let more = match &best {
+                Some((_, most)) => locations.len() > most.len(),
+                None => !locations.is_empty(),
+            };
+            if more {
+                best = Some((manip, locations));
+            }
+        }
+    }
+    best
+}
+
+/// The number of pixels along each side of a cell in rendered images.
+const RENDER_SCALE: usize = 4;
+
+/// Draws the assembled image with the cells of the sea monsters in red.
+/// If `tile_size` is given, grid lines are drawn between the tiles,
+/// which are that many cells across.
+fn render_image(
+    bitmap: &Array2<u8>,
+    locations: &[(i32, i32)],
+    mask: &Array2<u8>,
+    tile_size: Option<usize>,
+) -> Canvas {
+    let monster = monster_cells(bitmap, locations, mask);
+    let (height, width) = bitmap.dim();
+    let mut canvas = render_grid(width, height, RENDER_SCALE, |x, y| {
+        if monster[[y, x]] {
+            RED
+        } else {
+            match bitmap[[y, x]] {
+                0 => WHITE,
+                GAP => BLACK,
+                _ => BLUE,
+            }
+        }
+    });
+    if let Some(size) = tile_size {
+        for x in (size..width).step_by(size) {
+            canvas.fill_rect(x * RENDER_SCALE, 0, 1, canvas.height(), GREY);
+        }
+        for y in (size..height).step_by(size) {
+            canvas.fill_rect(0, y * RENDER_SCALE, canvas.width(), 1, GREY);
+        }
+    }
+    canvas
+}
+
+fn part2(
+    tiles: &HashMap<TileId, Tile>,
+    solution: &TileLocationSolution,
+    options: &Options,
+    out: &mut impl Write,
+) -> Result<(), String> {
+    let big_bitmap = assemble_big_bitmap(tiles, solution);
+    log::debug!("big bitmap is:\n{}", render_bitmap(&big_bitmap));
+    if !options.masks.is_empty() {
+        return search_masks(&big_bitmap, &options.masks, options.json, out);
+    }
+    let nessie_mask = nessie();
+    let Sighting {
+        manip,
+        bitmap,
+        locations,
+        counts,
+    } = match find_monsters(&big_bitmap, &nessie_mask) {
+        Some(found) => found,
+        None => return Err("found no sea monsters".to_string()),
+    };
+    let roughness = measure_roughness(&bitmap, &locations, &nessie_mask);
+    if options.json {
+        let mut report = sighting_report(&manip, &locations, &counts, roughness);
+        report["corner_product"] = serde_json::Value::from(corner_product(solution));
+        write_json(&report, out)?;
+    } else {
+        writeln!(out, "Part 2: roughness is {}", roughness).map_err(write_error)?;
+    }
+    if let Some(filename) = &options.render {
+        // Tiles lose their borders when the image is assembled.
+        let tile_size = tiles.values().next().map(|t| t.d.nrows() - 2);
+        render_image(
+            &bitmap,
+            &locations,
+            &nessie_mask,
+            tile_size.filter(|_| options.grid),
+        )
+        .save_png(Path::new(filename))?;
+    }
+    Ok(())
+}
+
+/// Searches the image for each of the masks in the files `filenames`,
+/// reporting the number of matches and the roughness for each.  With
+/// `json`, the report is a JSON array with an entry for each mask.
+fn search_masks(
+    big_bitmap: &Array2<u8>,
+    filenames: &[String],
+    json: bool,
+    out: &mut impl Write,
+) -> Result<(), String> {
+    let mut reports = Vec::with_capacity(filenames.len());
+    for filename in filenames {
+        let text = fs::read_to_string(filename)
+            .map_err(|e| format!("failed to read {}: {}", filename, e))?;
+        let mask = parse_mask(&text).map_err(|e| format!("{}: {}", filename, e))?;
+        let (manip, locations, counts, roughness) = match find_monsters(big_bitmap, &mask) {
+            Some(sighting) => {
+                let roughness = measure_roughness(&sighting.bitmap, &sighting.locations, &mask);
+                (
+                    sighting.manip,
+                    sighting.locations,
+                    sighting.counts,
+                    roughness,
+                )
+            }
+            None => (
+                Manipulation::noop(),
+                Vec::new(),
+                [0; 8],
+                count_ones(big_bitmap),
+            ),
+        };
+        if json {
+            let mut report = sighting_report(&manip, &locations, &counts, roughness);
+            report["mask"] = serde_json::Value::from(filename.as_str());
+            reports.push(report);
+        } else if locations.is_empty() {
+            writeln!(
+                out,
+                "Part 2: {}: no matches, roughness is {}",
+                filename, roughness
+            )
+            .map_err(write_error)?;
+        } else {
+            writeln!(
+                out,
+                "Part 2: {}: {} matches ({}), roughness is {}",
+                filename,
+                locations.len(),
+                manip,
+                roughness
+            )
+            .map_err(write_error)?;
+        }
+    }
+    if json {
+        write_json(&serde_json::Value::from(reports), out)?;
+    }
+    Ok(())
+}
+
+/// The number of times `generate` redraws the edges which are not
+/// unique before giving up.
+const GENERATE_ATTEMPTS: usize = 1000;
+
+/// Makes up a puzzle whose assembled image is `image`, padded with
+/// blank cells to a whole number of tiles.  The tiles are `tile_size`
+/// cells across.  Their borders are random, but no edge is the same
+/// as any other (even reversed), so as in the real puzzle there is
+/// only one way to fit each pair of tiles together.  The tiles are
+/// manipulated and shuffled at random, and returned in the format of
+/// the puzzle input.
+fn generate(image: &Array2<u8>, tile_size: usize, rng: &mut Lcg) -> Result<String, String> {
+    if !(3..=31).contains(&tile_size) {
+        return Err("tiles must be between 3 and 31 cells across".to_string());
+    }
+    let inner = tile_size - 2;
+    let step = tile_size - 1;
+    let rows = image.nrows().div_ceil(inner);
+    let cols = image.ncols().div_ceil(inner);
+    if rows == 0 || cols == 0 {
+        return Err("the image is empty".to_string());
+    }
+    // Neighbouring tiles share the line of cells between them, which
+    // is the border of both.
+    let mut g: Array2<u8> = Array::from_shape_fn((rows * step + 1, cols * step + 1), |(r, c)| {
+        if r % step == 0 || c % step == 0 {
+            rng.below(2) as u8
+        } else {
+            let y = (r / step) * inner + r % step - 1;
+            let x = (c / step) * inner + c % step - 1;
+            image.get((y, x)).copied().unwrap_or(0)
+        }
+    });
+    let mut segments: Vec<Vec<(usize, usize)>> = Vec::new();
+    for i in 0..=rows {
+        for j in 0..cols {
+            segments.push((0..tile_size).map(|k| (i * step, j * step + k)).collect());
+        }
+    }
+    for i in 0..rows {
+        for j in 0..=cols {
+            segments.push((0..tile_size).map(|k| (i * step + k, j * step)).collect());
+        }
+    }
+    let pattern = |g: &Array2<u8>, cells: &mut dyn Iterator<Item = &(usize, usize)>| {
+        cells.fold(0, |bits, (r, c)| (bits << 1) | g[[*r, *c]] as i32)
+    };
+    for _ in 0..GENERATE_ATTEMPTS {
+        let mut seen: HashSet<i32> = HashSet::new();
+        // The edges are redrawn in order, so that the same seed always
+        // makes the same puzzle.
+        let mut bad: BTreeSet<usize> = BTreeSet::new();
+        for (i, cells) in segments.iter().enumerate() {
+            let forward = pattern(&g, &mut cells.iter());
+            let backward = pattern(&g, &mut cells.iter().rev());
+            if forward == backward {
+                bad.insert(i);
+            }
+            if !seen.insert(cmp::min(forward, backward)) {
+                bad.insert(i);
+            }
+        }
+        if bad.is_empty() {
+            return Ok(cut_tiles(&g, rows, cols, tile_size, rng));
+        }
+        // The corners are shared with other edges, so we leave them be.
+        for i in bad {
+            for (r, c) in segments[i][1..tile_size - 1].iter() {
+                g[[*r, *c]] = rng.below(2) as u8;
             }
         }
     }
-    panic!("found no sea monsters");
+    Err(format!(
+        "failed to make the edges of {} tiles unique; try larger tiles",
+        rows * cols
+    ))
 }
 
-fn part2(tiles: &HashMap<TileId, Tile>, solution: &TileLocationSolution) -> Result<(), String> {
-    println!("Part 2: roughness is {}", solve2(tiles, solution));
+/// Cuts `g` into tiles for `generate`.
+fn cut_tiles(g: &Array2<u8>, rows: usize, cols: usize, tile_size: usize, rng: &mut Lcg) -> String {
+    let step = tile_size - 1;
+    let mut ids: Vec<usize> = (1000..1000 + cmp::max(9000, rows * cols)).collect();
+    rng.shuffle(&mut ids);
+    let mut tiles: Vec<String> = Vec::with_capacity(rows * cols);
+    for i in 0..rows {
+        for j in 0..cols {
+            let cells = g
+                .slice(s![
+                    i * step..i * step + tile_size,
+                    j * step..j * step + tile_size
+                ])
+                .to_owned();
+            let how = Manipulation::new(rng.below(8) as i32);
+            let picture: Vec<String> = how
+                .on(&cells)
+                .outer_iter()
+                .map(|row| {
+                    row.iter()
+                        .map(|x| if *x == 1 { '#' } else { '.' })
+                        .collect()
+                })
+                .collect();
+            tiles.push(format!(
+                "Tile {}:\n{}",
+                ids[tiles.len()],
+                picture.join("\n")
+            ));
+        }
+    }
+    rng.shuffle(&mut tiles);
+    tiles.join("\n\n") + "\n"
+}
+
+/// Reads a PNG image, in which dark pixels are set cells.
+fn load_image(filename: &str) -> Result<Array2<u8>, String> {
+    let canvas = Canvas::load_png(Path::new(filename))?;
+    Ok(Array::from_shape_fn(
+        (canvas.height(), canvas.width()),
+        |(y, x)| {
+            let brightness: u32 = canvas.get(x, y).iter().map(|v| *v as u32).sum();
+            if brightness < 3 * 128 {
+                1
+            } else {
+                0
+            }
+        },
+    ))
+}
+
+/// Writes a made-up puzzle to `out`, for "day20 generate".
+fn generate_puzzle(options: &Options, out: &mut impl Write) -> Result<(), String> {
+    let tile_size = options.tile_size.unwrap_or(10);
+    let mut rng = Lcg::new(options.seed.unwrap_or(20201220));
+    let image = match &options.image {
+        Some(filename) => load_image(filename)?,
+        None => {
+            // The real puzzle has 12 tiles along each side.
+            let side = options.tiles.unwrap_or(12) * tile_size.saturating_sub(2);
+            Array::from_shape_fn((side, side), |_| rng.below(2) as u8)
+        }
+    };
+    write!(out, "{}", generate(&image, tile_size, &mut rng)?).map_err(write_error)?;
     Ok(())
 }
 
-fn run() -> Result<(), String> {
-    self_test()?;
+#[derive(Default)]
+struct Options {
+    algorithm: Option<Assembler>,
+    allow_holes: bool,
+    benchmark: bool,
+    crosscheck: bool,
+    fast_part1: bool,
+    generate: bool,
+    grid: bool,
+    image: Option<String>,
+    json: bool,
+    layout: Option<String>,
+    masks: Vec<String>,
+    render: Option<String>,
+    save_layout: Option<String>,
+    seed: Option<u64>,
+    tile_size: Option<usize>,
+    tiles: Option<usize>,
+}
+
+fn parse_args() -> Result<Options, String> {
+    let mut options = Options::default();
+    let mut args = std::env::args().skip(1);
+    let number = |option: &str, v: String| -> Result<usize, String> {
+        v.parse()
+            .map_err(|e| format!("invalid value '{}' for {}: {}", v, option, e))
+    };
+    while let Some(arg) = args.next() {
+        let mut value = || {
+            args.next()
+                .ok_or_else(|| format!("option {} requires an argument", arg))
+        };
+        match arg.as_str() {
+            "crosscheck" => options.crosscheck = true,
+            "generate" => options.generate = true,
+            "--algorithm" => options.algorithm = Some(assemblers().get(&value()?)?.solver),
+            "--allow-holes" => options.allow_holes = true,
+            "--benchmark" => options.benchmark = true,
+            "--fast-part1" => options.fast_part1 = true,
+            "--grid" => options.grid = true,
+            "--image" => options.image = Some(value()?),
+            "--json" => options.json = true,
+            "--layout" => options.layout = Some(value()?),
+            "--mask" => options.masks.push(value()?),
+            "--render" => options.render = Some(value()?),
+            "--save-layout" => options.save_layout = Some(value()?),
+            "--seed" => options.seed = Some(number(&arg, value()?)? as u64),
+            "--tile-size" => options.tile_size = Some(number(&arg, value()?)?),
+            "--tiles" => options.tiles = Some(number(&arg, value()?)?),
+            _ => return Err(format!("unknown option {}", arg)),
+        }
+    }
+    let generating = options.image.is_some()
+        || options.seed.is_some()
+        || options.tile_size.is_some()
+        || options.tiles.is_some();
+    if generating && !options.generate {
+        return Err("--image, --seed, --tile-size and --tiles are options of generate".to_string());
+    }
+    if options.image.is_some() && options.tiles.is_some() {
+        return Err("the size of the image determines the number of tiles".to_string());
+    }
+    Ok(options)
+}
+
+/// The number of times --benchmark assembles the tiles.
+const BENCHMARK_ROUNDS: usize = 10;
+
+/// Compares the time taken to index and assemble the tiles using a
+/// single thread with the time taken using all of rayon's threads,
+/// and the time taken to find the sea monsters by turning the mask
+/// with the time taken by turning the whole image.  The real input
+/// has 144 tiles.
+fn benchmark(tiles: &HashMap<TileId, Tile>, out: &mut impl Write) -> Result<(), String> {
+    let time_with = |threads: usize| -> Result<(i64, Duration), String> {
+        let pool = rayon::ThreadPoolBuilder::new()
+            .num_threads(threads)
+            .build()
+            .map_err(|e| e.to_string())?;
+        let start = Instant::now();
+        let mut product = 0;
+        for _ in 0..BENCHMARK_ROUNDS {
+            let solution = pool.install(|| {
+                let ix = make_tile_index(tiles);
+                solve1(tiles, &ix, &Manipulation::noop())
+            })?;
+            product = corner_product(&solution);
+        }
+        Ok((product, start.elapsed()))
+    };
+    let threads = rayon::current_num_threads();
+    let (serial, serial_time) = time_with(1)?;
+    let (parallel, parallel_time) = time_with(threads)?;
+    writeln!(
+        out,
+        "{} tiles, {} rounds: 1 thread {} ({:?}), {} threads {} ({:?}), speedup {:.1}x",
+        tiles.len(),
+        BENCHMARK_ROUNDS,
+        serial,
+        serial_time,
+        threads,
+        parallel,
+        parallel_time,
+        serial_time.as_secs_f64() / parallel_time.as_secs_f64()
+    )
+    .map_err(write_error)?;
+
+    let ix = make_tile_index(tiles);
+    let big_bitmap = assemble_big_bitmap(tiles, &solve1(tiles, &ix, &Manipulation::noop())?);
+    let nessie_mask = nessie();
+    let start = Instant::now();
+    let mut by_image = None;
+    for _ in 0..BENCHMARK_ROUNDS {
+        by_image = find_monsters_by_turning_image(&big_bitmap, &nessie_mask);
+    }
+    let image_time = start.elapsed();
+    let start = Instant::now();
+    let mut by_mask = None;
+    for _ in 0..BENCHMARK_ROUNDS {
+        by_mask = find_monsters(&big_bitmap, &nessie_mask);
+    }
+    let mask_time = start.elapsed();
+    let count = |found: Option<usize>| found.unwrap_or(0);
+    writeln!(
+        out,
+        "{}x{} image, {} rounds: turning the image {} monsters ({:?}), turning the mask {} monsters ({:?}), speedup {:.1}x",
+        big_bitmap.nrows(),
+        big_bitmap.ncols(),
+        BENCHMARK_ROUNDS,
+        count(by_image.map(|(_, locations)| locations.len())),
+        image_time,
+        count(by_mask.map(|sighting| sighting.locations.len())),
+        mask_time,
+        image_time.as_secs_f64() / mask_time.as_secs_f64()
+    )
+    .map_err(write_error)?;
+    Ok(())
+}
+
+fn run(mut input: impl Read, options: &Options, out: &mut impl Write) -> Result<(), String> {
+    if options.generate {
+        return generate_puzzle(options, out);
+    }
 
     let mut buffer = String::new();
-    let tiles: HashMap<TileId, Tile> = match io::stdin().read_to_string(&mut buffer) {
+    let tiles: HashMap<TileId, Tile> = match input.read_to_string(&mut buffer) {
         Ok(_) => read_tiles(&buffer),
         Err(e) => {
             return Err(format!("I/O error: {}", e));
         }
     };
-    let solution = part1(&tiles)?;
-    part2(&tiles, &solution)?;
-    Ok(())
+    if options.benchmark {
+        return benchmark(&tiles, out);
+    }
+    if options.crosscheck {
+        return crosscheck(&tiles, out);
+    }
+    if options.fast_part1 {
+        return fast_part1(&tiles, out);
+    }
+    // A saved layout saves solving part 1 again when experimenting
+    // with part 2.
+    let solution = match &options.layout {
+        Some(filename) => load_layout(filename, &tiles)?,
+        None => part1(&tiles, options, out)?,
+    };
+    if let Some(filename) = &options.save_layout {
+        save_layout(filename, &solution)?;
+    }
+    part2(&tiles, &solution, options, out)
 }
 
 fn main() {
     // the env logger is configured with $RUST_LOG.
     // For example RUST_LOG=debug day20
     pretty_env_logger::init();
-    std::process::exit(match run() {
+    let result = parse_args().and_then(|options| {
+        self_test()?;
+        run(io::stdin().lock(), &options, &mut io::stdout())
+    });
+    std::process::exit(match result {
         Ok(_) => 0,
         Err(err) => {
             eprintln!("error: {}", err);
@@ -1146,3 +2085,22 @@ fn main() {
         }
     });
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn output(input: &str, options: &Options) -> String {
+        let mut out = Vec::new();
+        run(input.as_bytes(), options, &mut out).unwrap();
+        String::from_utf8(out).unwrap()
+    }
+
+    #[test]
+    fn sample() {
+        insta::assert_snapshot!(output(
+            include_str!("../../samples/day20.txt"),
+            &Options::default()
+        ));
+    }
+}
@@ -1,249 +1,143 @@
-extern crate log;
+extern crate aor2020;
+#[cfg(test)]
+extern crate insta;
 extern crate pretty_env_logger;
-#[macro_use]
-extern crate lazy_static;
-extern crate regex;
+extern crate serde_json;
 
-use regex::Regex;
-use std::collections::BTreeMap;
-use std::collections::HashMap;
-use std::collections::HashSet;
+use aor2020::day21::{parse, parse_strict, solve, Solution};
+use aor2020::explain::{self, Explanation};
+use aor2020::output::write_error;
 use std::io;
 use std::io::BufRead;
-
-type StringSet = HashSet<String>;
-
-lazy_static! {
-    static ref LINE_RE: Regex = Regex::new("^(.*) [(]contains ([^)]*)[)]$").unwrap();
+use std::io::Write;
+use std::str::FromStr;
+
+/// The ways in which the solution can be printed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Format {
+    Text,
+    Json,
+    Csv,
 }
 
-fn parse(lines: &Vec<String>) -> Result<Vec<(StringSet, StringSet)>, String> {
-    let mut result: Vec<(StringSet, StringSet)> = Vec::new();
-    for line in lines {
-        match LINE_RE.captures(line) {
-            None => {
-                return Err("invalid input line".to_string());
-            }
-            Some(cap) => {
-                result.push((
-                    cap[1].split(' ').map(str::to_owned).collect(),
-                    cap[2].split(", ").map(str::to_owned).collect(),
-                ));
-            }
-        }
-    }
-    Ok(result)
-}
-
-#[derive(Debug)]
-struct Ingredient {
-    name: String,
-    possible_allergens: StringSet,
-    excluded_allergens: StringSet,
-    definite_allergen: Option<String>,
-}
+impl FromStr for Format {
+    type Err = String;
 
-impl Ingredient {
-    fn new(name: &str, all_allergens: &StringSet) -> Ingredient {
-        Ingredient {
-            name: name.to_string(),
-            possible_allergens: all_allergens.clone(),
-            excluded_allergens: StringSet::new(),
-            definite_allergen: None,
-        }
-    }
-
-    fn is_non_allergenic(&self) -> Option<bool> {
-        if self.definite_allergen.is_some() {
-            Some(false)
-        } else if !self.possible_allergens.is_empty() {
-            None
-        } else {
-            Some(true)
+    fn from_str(s: &str) -> Result<Format, String> {
+        match s {
+            "text" => Ok(Format::Text),
+            "json" => Ok(Format::Json),
+            "csv" => Ok(Format::Csv),
+            _ => Err(format!("unknown format '{}'", s)),
         }
     }
+}
 
-    // If the set contains only one item, return it.  Otherwise return
-    // None.
-    fn sole_possible_allergen(&self) -> Option<String> {
-        let mut it = self.possible_allergens.iter();
-        match it.next() {
-            None => None, // no items in the set
-            Some(candidate) => {
-                // we found an item
-                match it.next() {
-                    None => Some(candidate.to_string()), // it was the only one
-                    _ => None,                           // there were others
-                }
-            }
-        }
+fn write_text(solution: &Solution, out: &mut impl Write) -> Result<(), String> {
+    let width: usize = solution
+        .allergens
+        .values()
+        .map(|n| n.len())
+        .max()
+        .unwrap_or(1);
+    for (allergen, ing_name) in &solution.allergens {
+        writeln!(
+            out,
+            "{:<width$} contains {}",
+            ing_name,
+            allergen,
+            width = width
+        )
+        .map_err(write_error)?;
     }
+    writeln!(
+        out,
+        "Part 1: non-allergen count is {}",
+        solution.safe_mentions
+    )
+    .map_err(write_error)?;
+    writeln!(
+        out,
+        "Part 2: ingredients sorted by allergen name: {}",
+        solution.canonical_dangerous_list()
+    )
+    .map_err(write_error)
+}
 
-    fn conclude_must_contain(&mut self, allergen: &str) {
-        assert!(!self.excluded_allergens.contains(allergen));
-        assert!(self.definite_allergen.is_none());
-        self.definite_allergen = Some(allergen.to_string());
-        self.possible_allergens.remove(allergen);
-        assert!(self.possible_allergens.is_empty());
+/// Writes a row for each ingredient, giving the allergen it contains;
+/// the allergen of a safe ingredient is empty.
+fn write_csv(solution: &Solution, out: &mut impl Write) -> Result<(), String> {
+    writeln!(out, "ingredient,allergen").map_err(write_error)?;
+    for (allergen, ing_name) in &solution.allergens {
+        writeln!(out, "{},{}", ing_name, allergen).map_err(write_error)?;
     }
-
-    fn conclude_must_not_contain(&mut self, allergen: &str) {
-        match &self.definite_allergen {
-            None => (),
-            Some(a) => {
-                assert!(
-                    a != allergen,
-                    "we already thought this ingredient did contain that"
-                );
-            }
-        }
-        self.excluded_allergens.insert(allergen.to_string());
-        self.possible_allergens.remove(allergen);
+    for ing_name in &solution.safe {
+        writeln!(out, "{},", ing_name).map_err(write_error)?;
     }
+    Ok(())
 }
 
-fn count_unknowns(all_ingredients: &HashMap<String, Ingredient>) -> usize {
-    all_ingredients
-        .values()
-        .map(|ing| ing.possible_allergens.len())
-        .sum()
+fn write_json(solution: &Solution, out: &mut impl Write) -> Result<(), String> {
+    writeln!(
+        out,
+        "{}",
+        serde_json::to_string_pretty(solution).map_err(|e| e.to_string())?
+    )
+    .map_err(write_error)
 }
 
-fn deduce_sole_possible_allergens(
-    all_ingredients: &HashMap<String, Ingredient>,
-) -> Vec<(String, String)> {
-    let mut discoveries: Vec<(String, String)> = Vec::new();
-    for (ing_name, ing) in all_ingredients {
-        match ing.sole_possible_allergen() {
-            None => (),
-            Some(candidate) => {
-                discoveries.push((ing_name.to_string(), candidate.to_string()));
-            }
-        }
+fn write_explanation(
+    explanation: &Explanation,
+    format: explain::Format,
+    out: &mut impl Write,
+) -> Result<(), String> {
+    match format {
+        explain::Format::Text => write!(out, "{}", explanation).map_err(write_error)?,
+        explain::Format::Json => writeln!(
+            out,
+            "{}",
+            serde_json::to_string_pretty(explanation).map_err(|e| e.to_string())?
+        )
+        .map_err(write_error)?,
     }
-    discoveries
+    Ok(())
 }
 
-fn solve1(
-    lines: &Vec<String>,
-) -> Result<(Vec<(StringSet, StringSet)>, HashMap<String, Ingredient>), String> {
-    let parsed_input: Vec<(StringSet, StringSet)> = parse(lines)?;
-    let all_allergens: StringSet = parsed_input.iter().flat_map(|(_, a)| a).cloned().collect();
-    let mut all_ingredients: HashMap<String, Ingredient> = parsed_input
-        .iter()
-        .flat_map(|(i, _)| i)
-        .map(|ing_name| {
-            (
-                ing_name.to_string(),
-                Ingredient::new(ing_name, &all_allergens),
-            )
-        })
-        .collect();
-    let all_ingredient_names: StringSet = all_ingredients.keys().cloned().collect();
-
-    for (ingredients, allergens) in &parsed_input {
-        // One of these ingredients listed on this line contains each
-        // of these allergens.  Therefore any ingredient not listed
-        // here cannot contain any of these allergens, since we are
-        // told that any allergen occurs in just one ingredient.
-        for allergen in allergens {
-            for ing_name in all_ingredient_names.difference(ingredients) {
-                all_ingredients
-                    .get_mut(ing_name)
-                    .unwrap()
-                    .conclude_must_not_contain(allergen);
-            }
-        }
-    }
-
-    loop {
-        let prev_unknowns = count_unknowns(&all_ingredients);
-        if prev_unknowns == 0 {
-            log::info!("part1: solved");
-            break;
-        }
-        log::debug!("all_ingredients: {:?}", all_ingredients);
-        let discoveries = deduce_sole_possible_allergens(&all_ingredients);
-
-        for (has_it, allergen) in discoveries {
-            for (ing_name, ing) in all_ingredients.iter_mut() {
-                if *ing_name == has_it {
-                    ing.conclude_must_contain(&allergen);
-                } else {
-                    ing.conclude_must_not_contain(&allergen);
-                }
-            }
-        }
-        let curr_unknowns = count_unknowns(&all_ingredients);
-        if curr_unknowns == prev_unknowns {
-            return Err("not solvable".to_string());
-        }
-    }
-
-    let allergenic_ingredients: HashMap<String, String> = all_ingredients
-        .values()
-        .filter_map(|ing| {
-            ing.definite_allergen
-                .as_ref()
-                .map(|a| (ing.name.to_string(), a.to_string()))
-        })
-        .collect();
-    let width: usize = allergenic_ingredients
-        .iter()
-        .map(|(n, _)| n.len())
-        .max()
-        .unwrap_or(1);
-    for (ing_name, allergen) in &allergenic_ingredients {
-        println!("{:<width$} contains {}", ing_name, allergen, width = width);
-    }
-    Ok((parsed_input, all_ingredients))
+fn self_test() -> Result<(), String> {
+    aor2020::constraint::self_test()?;
+    aor2020::explain::self_test()?;
+    aor2020::day21::self_test()
 }
 
-fn part1(lines: &Vec<String>) -> Result<HashMap<String, Ingredient>, String> {
-    let (parsed_input, ingredients) = solve1(lines)?;
-    let non_alllergenic: StringSet = ingredients
-        .values()
-        .filter_map(|ing| match ing.is_non_allergenic() {
-            Some(true) => Some(ing.name.to_string()),
-            _ => None,
-        })
-        .collect();
-    let mentions = parsed_input
-        .iter()
-        .flat_map(|(ingredent_names, _)| ingredent_names.iter().cloned())
-        .filter(|ing_name| non_alllergenic.contains(ing_name))
-        .count();
-
-    println!("Part 1: non-allergen count is {}", mentions);
-    Ok(ingredients)
+#[derive(Default)]
+struct Options {
+    explain: Option<explain::Format>,
+    format: Option<Format>,
+    strict: bool,
 }
 
-fn part2(all_ingredients: &HashMap<String, Ingredient>) -> Result<(), String> {
-    // We use a BTreeMap here specifically because we want to rely on
-    // iteration in key order.
-    let allergen_to_ingredient_map: BTreeMap<String, String> = all_ingredients
-        .values()
-        .filter_map(|ing| {
-            ing.definite_allergen
-                .as_ref()
-                .map(|a| (a.to_string(), ing.name.to_string()))
-        })
-        .collect();
-    println!(
-        "Part 2: ingredients sorted by allergen name: {}",
-        itertools::join(
-            allergen_to_ingredient_map
-                .iter()
-                .map(|(_, ingredient_name)| ingredient_name),
-            ","
-        )
-    );
-    Ok(())
+fn parse_args() -> Result<Options, String> {
+    let mut options = Options::default();
+    let mut args = std::env::args().skip(1);
+    while let Some(arg) = args.next() {
+        let mut value = || {
+            args.next()
+                .ok_or_else(|| format!("option {} requires an argument", arg))
+        };
+        match arg.as_str() {
+            "--explain" => options.explain = Some(explain::Format::Text),
+            "--explain-format" => options.explain = Some(value()?.parse()?),
+            "--format" => options.format = Some(value()?.parse()?),
+            "--strict" => options.strict = true,
+            _ => return Err(format!("unknown option {}", arg)),
+        }
+    }
+    Ok(options)
 }
 
-fn run() -> Result<(), String> {
+fn run(input: impl BufRead, options: &Options, out: &mut impl Write) -> Result<(), String> {
     let mut lines: Vec<String> = Vec::new();
-    for line_or_err in io::BufReader::new(io::stdin()).lines() {
+    for line_or_err in input.lines() {
         match line_or_err {
             Err(e) => {
                 return Err(format!("I/O error: {}", e));
@@ -253,16 +147,38 @@ fn run() -> Result<(), String> {
             }
         }
     }
-    let ingredients = part1(&lines)?;
-    part2(&ingredients)?;
-    Ok(())
+    let foods = if options.strict {
+        parse_strict(&lines)?
+    } else {
+        let (foods, errors) = parse(&lines);
+        for e in errors {
+            eprintln!("warning: ignoring {}", e);
+        }
+        foods
+    };
+    let solution = solve(&foods)?;
+    if let Some(format) = options.explain {
+        write_explanation(&solution.explain(), format, out)?;
+        if format == explain::Format::Json {
+            return Ok(());
+        }
+    }
+    match options.format.unwrap_or(Format::Text) {
+        Format::Text => write_text(&solution, out),
+        Format::Json => write_json(&solution, out),
+        Format::Csv => write_csv(&solution, out),
+    }
 }
 
 fn main() {
     // the env logger is configured with $RUST_LOG.
     // For example RUST_LOG=debug day21
     pretty_env_logger::init();
-    std::process::exit(match run() {
+    let result = parse_args().and_then(|options| {
+        self_test()?;
+        run(io::stdin().lock(), &options, &mut io::stdout())
+    });
+    std::process::exit(match result {
         Ok(_) => 0,
         Err(err) => {
             eprintln!("error: {}", err);
@@ -270,3 +186,22 @@ fn main() {
         }
     });
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn output(input: &str, options: &Options) -> String {
+        let mut out = Vec::new();
+        run(input.as_bytes(), options, &mut out).unwrap();
+        String::from_utf8(out).unwrap()
+    }
+
+    #[test]
+    fn sample() {
+        insta::assert_snapshot!(output(
+            include_str!("../../samples/day21.txt"),
+            &Options::default()
+        ));
+    }
+}
@@ -1,289 +1,1284 @@
-#[macro_use]
-extern crate lazy_static;
+extern crate aor2020;
+#[cfg(test)]
+extern crate insta;
 extern crate log;
 extern crate pretty_env_logger;
-extern crate regex;
+extern crate serde;
+extern crate serde_json;
 
-use std::collections::HashSet;
-use std::collections::VecDeque;
+use aor2020::day22::{
+    highest_card_winner, parse_decks, winnings, Card, GameState, Player, PlayerHand,
+};
+use aor2020::output::write_error;
+use aor2020::rng::Lcg;
+use std::collections::HashMap;
 use std::fmt;
+use std::fs::File;
 use std::io;
-use std::io::BufRead;
+use std::io::{BufRead, Write};
+use std::iter::Peekable;
+use std::mem::size_of;
+use std::str::FromStr;
 
-use regex::Regex;
+use serde::{Deserialize, Serialize};
 
-lazy_static! {
-    static ref PLAYER_RE: Regex = Regex::new("^Player ([12]):$").unwrap();
+/// The names of the players in the input.
+fn player_names(lines: &[String]) -> Result<Vec<String>, String> {
+    match parse_decks(lines) {
+        Ok(decks) => Ok(decks.into_iter().map(|(name, _)| name).collect()),
+        Err(e) => Err(e.to_string()),
+    }
 }
 
-type Card = u32;
+/// When a round is settled by playing a sub-game.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+enum Recursion {
+    /// Never; the highest card always wins.
+    Never,
+    /// When every player has at least as many cards left as the
+    /// value of the card they just played.
+    EnoughCards,
+}
 
-fn get_deck(who: i32, lines: &[String]) -> Result<VecDeque<Card>, String> {
-    let mut result: VecDeque<Card> = VecDeque::new();
-    let mut player = 0;
-    for line in lines.iter().filter(|s| !s.is_empty()) {
-        match PLAYER_RE.captures(line) {
-            None => (),
-            Some(cap) => match cap[1].parse() {
-                Ok(n) => {
-                    player = n;
-                    continue;
-                }
-                Err(e) => {
-                    return Err(format!("cannot parse '{}' as an integer: {}", &cap[1], e));
-                }
-            },
-        }
-        if player == who {
-            match line.parse() {
-                Ok(n) => result.push_back(n),
-                Err(e) => {
-                    return Err(format!("cannot parse '{}' as an integer: {}", line, e));
-                }
-            }
+impl FromStr for Recursion {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Recursion, String> {
+        match s {
+            "never" => Ok(Recursion::Never),
+            "enough-cards" => Ok(Recursion::EnoughCards),
+            _ => Err(format!("unknown recursion rule '{}'", s)),
         }
     }
-    Ok(result)
 }
 
-#[derive(PartialOrd, Ord, PartialEq, Eq, Hash)]
-struct PlayerHand {
-    cards: VecDeque<Card>,
+/// The rules of a variant of Combat.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+struct RulesConfig {
+    recursion: Recursion,
+    /// If set, rounds of games this deeply nested are settled by the
+    /// highest card even if the recursion rule calls for a sub-game.
+    /// The top-level game has depth 0.
+    max_depth: Option<usize>,
+    /// Whether a game which returns to an earlier state ends at once,
+    /// with a win for the first player who still has cards.
+    detect_loops: bool,
+    /// If set, a game which reaches this many rounds ends, and is won
+    /// by the player holding the most cards (the first of them, if
+    /// there is a tie).
+    max_rounds: Option<usize>,
+    /// If set, a player takes at most this many cards into a sub-game.
+    card_cap: Option<usize>,
 }
 
-impl Clone for PlayerHand {
-    fn clone(&self) -> PlayerHand {
-        PlayerHand {
-            cards: self.cards.clone(),
+impl RulesConfig {
+    /// The rules of part 1, Combat.
+    fn part1() -> RulesConfig {
+        RulesConfig {
+            recursion: Recursion::Never,
+            max_depth: None,
+            detect_loops: false,
+            max_rounds: None,
+            card_cap: None,
+        }
+    }
+
+    /// The rules of part 2, Recursive Combat.
+    fn part2() -> RulesConfig {
+        RulesConfig {
+            recursion: Recursion::EnoughCards,
+            detect_loops: true,
+            ..RulesConfig::part1()
         }
     }
 }
 
-impl PlayerHand {
-    fn new(who: i32, config_lines: &[String]) -> Result<PlayerHand, String> {
-        let cards = get_deck(who, config_lines)?;
-        Ok(PlayerHand { cards })
+/// How the winner of a round was decided.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+enum Decision {
+    HighestCard,
+    SubGame,
+    /// By a sub-game which had already been played.
+    Remembered,
+}
+
+/// Why a game ended.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+enum Ending {
+    /// Only one player has any cards.
+    LastPlayer,
+    /// The game returned to an earlier state.
+    Loop,
+    /// The game reached the maximum number of rounds.
+    OutOfRounds,
+}
+
+/// An entry in the log of a game (written by --log and checked by
+/// replay).  The log of a sub-game appears between the round which
+/// called for it and the next round of the enclosing game.  Players
+/// are numbered from 0.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "event", rename_all = "snake_case")]
+enum Event {
+    /// Starts the log of a top-level game.
+    Rules {
+        rules: RulesConfig,
+    },
+    GameStart {
+        game: usize,
+        hands: Vec<Vec<Card>>,
+    },
+    Round {
+        game: usize,
+        round: usize,
+        played: Vec<(Player, Card)>,
+        winner: Player,
+        decided_by: Decision,
+    },
+    GameEnd {
+        game: usize,
+        /// The round in which the game ended (which was not played).
+        round: usize,
+        winner: Player,
+        score: usize,
+        ending: Ending,
+        /// The earlier round which the game repeated, if it looped.
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        repeats: Option<usize>,
+    },
+}
+
+/// Says where the rule against infinite games ended a game.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct LoopReport {
+    game: usize,
+    /// The round in which the state of an earlier round came up again.
+    round: usize,
+    /// The round in which the state first came up.
+    first_seen: usize,
+}
+
+impl fmt::Display for LoopReport {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "round {} of game {} repeated the cards of round {}",
+            self.round, self.game, self.first_seen
+        )
     }
+}
 
-    fn score(&self) -> usize {
-        let total = self
-            .cards
+/// The winner of a game which has ended in the way given.
+fn game_result(state: &GameState, ending: Ending) -> Player {
+    let players = state.players_in();
+    match ending {
+        // This is the only player with any cards (or the first
+        // player, if nobody has any).
+        Ending::LastPlayer => players.first().copied().unwrap_or(0),
+        Ending::Loop => players[0],
+        // max_by_key would pick the last of several players with
+        // the most cards.
+        Ending::OutOfRounds => players
             .iter()
+            .copied()
             .rev()
-            .enumerate()
-            .map(|(i, c)| (i + 1) * (*c) as usize)
-            .sum();
-        total
+            .max_by_key(|p| state.hand(*p).num_cards())
+            .expect("there are players"),
     }
+}
 
-    fn play_next_card(&mut self) -> Card {
-        self.cards.pop_front().expect("hand should not be empty")
+/// Returns the initial state of the sub-game which decides the
+/// round in which `played` were played, or None if `rules` do not
+/// call for one at this depth.
+fn subgame(
+    state: &GameState,
+    played: &[(Player, Card)],
+    rules: &RulesConfig,
+    depth: usize,
+) -> Option<GameState> {
+    let recurse = match rules.recursion {
+        Recursion::Never => false,
+        Recursion::EnoughCards => played
+            .iter()
+            .all(|(p, c)| (*c as usize) <= state.hand(*p).num_cards()),
+    } && rules.max_depth.map_or(true, |max| depth < max);
+    if !recurse {
+        return None;
     }
-
-    fn win_cards(&mut self, first: Card, second: Card) {
-        self.cards.push_back(first);
-        self.cards.push_back(second);
+    let mut subgame = state.clone();
+    for (p, c) in played.iter() {
+        let n = *c as usize;
+        subgame
+            .hand_mut(*p)
+            .truncate(rules.card_cap.map_or(n, |cap| n.min(cap)));
     }
+    Some(subgame)
+}
 
-    fn truncate(&mut self, max_cards: usize) {
-        self.cards.truncate(max_cards);
-    }
+/// Plays a game according to `context.rules`, returning the
+/// winner, their final hand and their score.
+fn play<'a>(
+    game: &'a mut GameState,
+    context: &mut RecursionContext,
+) -> (Player, &'a PlayerHand, usize) {
+    let rules = context.rules.clone();
+    let mut previous_states = SeenStates::new(context.tracking);
+    let this_game = context.game_counter;
+    log::debug!("== Game {} ===", this_game);
+    context.record(|| Event::GameStart {
+        game: this_game,
+        hands: game.cards(),
+    });
+    let ncards = game.total_card_count();
+    let mut round = 0;
+    let mut repeats: Option<usize> = None;
+    let ending = loop {
+        round += 1;
+        if game.players_in().len() < 2 {
+            break Ending::LastPlayer;
+        }
+        if rules.max_rounds.is_some_and(|max| round > max) {
+            break Ending::OutOfRounds;
+        }
+        log::debug!("");
+        log::debug!("-- Round {} (Game {}) --", round, this_game);
+        if log::log_enabled!(log::Level::Info) {
+            for s in game.to_string().split('\n') {
+                log::info!("{}", s);
+            }
+        }
+        assert!(game.total_card_count() == ncards);
+        context.rounds += 1;
+        if rules.detect_loops {
+            repeats = previous_states.insert(game, round, context);
+            if repeats.is_some() {
+                break Ending::Loop;
+            }
+        }
 
-    fn num_cards(&self) -> usize {
-        self.cards.len()
+        let played = game.play_cards();
+        for (p, c) in played.iter() {
+            log::debug!("Player {} plays: {}", p + 1, c);
+        }
+        // The round is logged before any sub-game, so we fill in
+        // the winner later.
+        let entry = context.events.as_ref().map(|events| events.len());
+        context.record(|| Event::Round {
+            game: this_game,
+            round,
+            played: played.clone(),
+            winner: 0,
+            decided_by: Decision::HighestCard,
+        });
+        let (winner, decision) = match subgame(game, &played, &rules, context.depth) {
+            Some(subgame) => context.play_subgame(subgame),
+            None => (highest_card_winner(&played), Decision::HighestCard),
+        };
+        if let (Some(events), Some(i)) = (&mut context.events, entry) {
+            if let Event::Round {
+                winner: w,
+                decided_by,
+                ..
+            } = &mut events[i]
+            {
+                *w = winner;
+                *decided_by = decision;
+            }
+        }
+        log::debug!(
+            "Player {} wins round {} of game {}!",
+            winner + 1,
+            round,
+            this_game
+        );
+        game.hand_mut(winner).win_cards(&winnings(&played, winner));
+    };
+    context.seen_state_bytes -= previous_states.bytes;
+    let winner = game_result(game, ending);
+    let score = game.hand(winner).score();
+    match ending {
+        Ending::OutOfRounds => log::info!(
+            "Game {} ran out of rounds; player {} has the most cards",
+            this_game,
+            winner + 1
+        ),
+        _ => log::info!("The winner of game {} is player {}", this_game, winner + 1),
     }
-
-    fn is_empty(&self) -> bool {
-        self.cards.is_empty()
+    if let Some(first_seen) = repeats {
+        let report = LoopReport {
+            game: this_game,
+            round,
+            first_seen,
+        };
+        log::info!("Game {} ended because {}", this_game, report);
+        context.loops.push(report);
+    }
+    if context.depth == 0 {
+        context.ending = Some(ending);
     }
+    context.record(|| Event::GameEnd {
+        game: this_game,
+        round,
+        winner,
+        score,
+        ending,
+        repeats,
+    });
+    (winner, game.hand(winner), score)
 }
 
-impl fmt::Display for PlayerHand {
-    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        f.write_str(&itertools::join(
-            self.cards.iter().map(|c| format!("{}", c)),
-            ", ",
-        ))
+/// The ways of remembering which states a game has been in.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum StateTracking {
+    /// Keep a copy of each state.
+    Full,
+    /// Keep the packed encoding of each state, which is much smaller.
+    Packed,
+}
+
+impl FromStr for StateTracking {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<StateTracking, String> {
+        match s {
+            "full" => Ok(StateTracking::Full),
+            "packed" => Ok(StateTracking::Packed),
+            _ => Err(format!("unknown state tracking method '{}'", s)),
+        }
     }
 }
 
-#[derive(PartialEq, Eq, Hash)]
-struct GameState {
-    p1: PlayerHand,
-    p2: PlayerHand,
+/// The states a single game has been in, and the round in which each
+/// first came up.
+enum SeenStateSet {
+    Full(HashMap<GameState, usize>),
+    Packed(HashMap<Vec<u8>, usize>),
 }
 
-impl Clone for GameState {
-    fn clone(&self) -> GameState {
-        GameState {
-            p1: self.p1.clone(),
-            p2: self.p2.clone(),
+struct SeenStates {
+    states: SeenStateSet,
+    /// Roughly how many bytes of memory the remembered states take
+    /// up (not counting the overhead of the hash table itself).
+    bytes: usize,
+}
+
+impl SeenStates {
+    fn new(tracking: StateTracking) -> SeenStates {
+        SeenStates {
+            states: match tracking {
+                StateTracking::Full => SeenStateSet::Full(HashMap::new()),
+                StateTracking::Packed => SeenStateSet::Packed(HashMap::new()),
+            },
+            bytes: 0,
         }
     }
+
+    /// Remembers that the game was in `state` in `round`.  If it had
+    /// already been in that state, the round in which it first was is
+    /// returned instead.  The memory used is accounted for in
+    /// `context`.
+    fn insert(
+        &mut self,
+        state: &GameState,
+        round: usize,
+        context: &mut RecursionContext,
+    ) -> Option<usize> {
+        let bytes = match &mut self.states {
+            SeenStateSet::Full(states) => {
+                if let Some(first_seen) = states.get(state) {
+                    return Some(*first_seen);
+                }
+                states.insert(state.clone(), round);
+                state.bytes()
+            }
+            SeenStateSet::Packed(states) => {
+                let packed = state.packed();
+                if let Some(first_seen) = states.get(&packed) {
+                    return Some(*first_seen);
+                }
+                let bytes = size_of::<Vec<u8>>() + packed.capacity();
+                states.insert(packed, round);
+                bytes
+            }
+        } + size_of::<usize>();
+        self.bytes += bytes;
+        context.seen_state_bytes += bytes;
+        context.peak_seen_state_bytes = context.peak_seen_state_bytes.max(context.seen_state_bytes);
+        None
+    }
 }
 
-impl GameState {
-    fn new(lines: &[String]) -> Result<GameState, String> {
-        let p1 = PlayerHand::new(1, lines)?;
-        let p2 = PlayerHand::new(2, lines)?;
-        Ok(GameState { p1, p2 })
+/// Identifies a sub-game by its depth (only if the rules limit the
+/// depth of recursion) and its initial state.
+type SubgameKey = (Option<usize>, GameState);
+
+/// The state shared by all the games of a game of Recursive Combat.
+struct RecursionContext {
+    rules: RulesConfig,
+    /// The number of the game most recently started.
+    game_counter: usize,
+    /// How deeply the game being played is nested; the top-level game
+    /// has depth 0.
+    depth: usize,
+    /// The results of the sub-games played so far, or None if we do
+    /// not remember them.
+    memo: Option<HashMap<SubgameKey, (Player, usize)>>,
+    /// The number of sub-games whose result came from `memo`.
+    memo_hits: usize,
+    /// The number of rounds played, in all games.
+    rounds: usize,
+    /// The games which were ended by the rule against infinite games.
+    loops: Vec<LoopReport>,
+    /// How the top-level game ended, once it has.
+    ending: Option<Ending>,
+    /// The log of the games played, if we are keeping one.
+    events: Option<Vec<Event>>,
+    /// How we remember the states of each game.
+    tracking: StateTracking,
+    /// Roughly how much memory is used to remember the states of the
+    /// games currently being played, and the most it has ever been.
+    seen_state_bytes: usize,
+    peak_seen_state_bytes: usize,
+}
+
+impl RecursionContext {
+    fn new(rules: RulesConfig, memoize: bool, tracking: StateTracking) -> RecursionContext {
+        RecursionContext {
+            rules,
+            game_counter: 1,
+            depth: 0,
+            memo: if memoize { Some(HashMap::new()) } else { None },
+            memo_hits: 0,
+            rounds: 0,
+            loops: Vec::new(),
+            ending: None,
+            events: None,
+            tracking,
+            seen_state_bytes: 0,
+            peak_seen_state_bytes: 0,
+        }
     }
 
-    fn decks_as_string(&self) -> String {
-        format!("Player 1: {}\nPlayer 2: {}", self.p1, self.p2)
+    fn record<F>(&mut self, event: F)
+    where
+        F: FnOnce() -> Event,
+    {
+        if let Some(events) = &mut self.events {
+            events.push(event());
+        }
     }
 
-    fn truncate_hands(&mut self, p1max: usize, p2max: usize) {
-        self.p1.truncate(p1max);
-        self.p2.truncate(p2max);
+    /// Returns the winner of a sub-game which starts in state
+    /// `subgame`, and whether we already knew it.
+    fn play_subgame(&mut self, mut subgame: GameState) -> (Player, Decision) {
+        // The result of a game depends only on its initial state
+        // (and its depth, if that is limited), and the same sub-games
+        // come up over and over.
+        let key = match &self.memo {
+            Some(memo) => {
+                let depth = self.rules.max_depth.map(|_| self.depth + 1);
+                let key = (depth, subgame.clone());
+                if let Some((winner, _)) = memo.get(&key) {
+                    self.memo_hits += 1;
+                    log::debug!("We already know the winner of this sub-game");
+                    return (*winner, Decision::Remembered);
+                }
+                Some(key)
+            }
+            None => None,
+        };
+        self.game_counter += 1;
+        log::debug!("Playing a sub-game to determine the winner...");
+        self.depth += 1;
+        let (winner, _, score) = play(&mut subgame, self);
+        self.depth -= 1;
+        if let (Some(memo), Some(key)) = (&mut self.memo, key) {
+            memo.insert(key, (winner, score));
+        }
+        (winner, Decision::SubGame)
     }
+}
+
+/// Plays a game according to `context.rules`, returning the winner
+/// and their score.  The statistics of the game are left in `context`.
+fn play_game(lines: &[String], context: &mut RecursionContext) -> Result<(Player, usize), String> {
+    let mut game_state = GameState::new(lines)?;
+    let (winner, _, score) = play(&mut game_state, context);
+    log::info!("== Post-game results ==\n{}", &game_state);
+    log::info!(
+        "Played {} rounds in {} games; {} sub-game results were remembered",
+        context.rounds,
+        context.game_counter,
+        context.memo_hits
+    );
+    log::info!(
+        "{} games were ended by the rule against infinite games",
+        context.loops.len()
+    );
+    log::info!(
+        "Remembering previous states took at most {} bytes",
+        context.peak_seen_state_bytes
+    );
+    Ok((winner, score))
+}
+
+/// Checks a log of games against the rules given in the log.
+struct Replay<I: Iterator<Item = (usize, Event)>> {
+    /// Each event, with its line number.
+    events: Peekable<I>,
+    rules: RulesConfig,
+    game_counter: usize,
+    /// The winner of each sub-game checked so far.
+    memo: HashMap<SubgameKey, Player>,
+}
 
-    fn total_card_count(&self) -> usize {
-        self.p1.num_cards() + self.p2.num_cards()
+impl<I: Iterator<Item = (usize, Event)>> Replay<I> {
+    fn next_event(&mut self) -> Result<(usize, Event), String> {
+        self.events
+            .next()
+            .ok_or_else(|| "the log ends in the middle of a game".to_string())
     }
 
-    fn score(&self, who: i32) -> usize {
-        match who {
-            1 => self.p1.score(),
-            2 => self.p2.score(),
-            _ => panic!("There is no player {}", who),
+    /// Checks the log of a game; `initial` is the state the game
+    /// should start in, if we know it.  Returns the winner and their
+    /// score.
+    fn game(
+        &mut self,
+        initial: Option<GameState>,
+        depth: usize,
+    ) -> Result<(Player, usize), String> {
+        let (line, event) = self.next_event()?;
+        let this_game = self.game_counter + 1;
+        let mut state = match event {
+            Event::GameStart { game, hands } if game == this_game => {
+                let logged = GameState::from_cards(&hands);
+                match initial {
+                    Some(state) if state != logged => {
+                        return Err(format!(
+                            "line {}: game {} should start with\n{}",
+                            line, game, state
+                        ));
+                    }
+                    _ => logged,
+                }
+            }
+            other => {
+                return Err(format!(
+                    "line {}: expected the start of game {}, got {:?}",
+                    line, this_game, other
+                ))
+            }
+        };
+        self.game_counter = this_game;
+        let mut seen: HashMap<Vec<u8>, usize> = HashMap::new();
+        for round in 1.. {
+            let mut repeats = None;
+            let ending = if state.players_in().len() < 2 {
+                Some(Ending::LastPlayer)
+            } else if self.rules.max_rounds.is_some_and(|max| round > max) {
+                Some(Ending::OutOfRounds)
+            } else if self.rules.detect_loops {
+                repeats = seen.get(&state.packed()).copied();
+                seen.entry(state.packed()).or_insert(round);
+                repeats.map(|_| Ending::Loop)
+            } else {
+                None
+            };
+            let (line, event) = self.next_event()?;
+            let fail = |what: String| Err(format!("line {}: {}", line, what));
+            match (ending, event) {
+                (
+                    Some(ending),
+                    Event::GameEnd {
+                        game,
+                        round: logged_round,
+                        winner,
+                        score,
+                        ending: logged_ending,
+                        repeats: logged_repeats,
+                    },
+                ) if game == this_game
+                    && logged_round == round
+                    && logged_ending == ending
+                    && logged_repeats == repeats =>
+                {
+                    let expected = game_result(&state, ending);
+                    let expected_score = state.hand(expected).score();
+                    if (winner, score) != (expected, expected_score) {
+                        return fail(format!(
+                            "game {} should be won by player {} with score {}",
+                            game,
+                            expected + 1,
+                            expected_score
+                        ));
+                    }
+                    return Ok((winner, score));
+                }
+                (Some(ending), other) => {
+                    let why = match repeats {
+                        Some(first_seen) => format!(" (repeating round {})", first_seen),
+                        None => String::new(),
+                    };
+                    return fail(format!(
+                        "game {} should end ({:?}{}) in round {}, but got {:?}",
+                        this_game, ending, why, round, other
+                    ));
+                }
+                (
+                    None,
+                    Event::Round {
+                        game,
+                        round: logged_round,
+                        played,
+                        winner,
+                        decided_by,
+                    },
+                ) if game == this_game && logged_round == round => {
+                    let expected_played = state.play_cards();
+                    if played != expected_played {
+                        return fail(format!("the cards played should be {:?}", expected_played));
+                    }
+                    let expected = match (subgame(&state, &played, &self.rules, depth), decided_by)
+                    {
+                        (None, Decision::HighestCard) => highest_card_winner(&played),
+                        (None, _) => return fail("there should be no sub-game".to_string()),
+                        (Some(_), Decision::HighestCard) => {
+                            return fail("there should be a sub-game".to_string());
+                        }
+                        (Some(subgame), Decision::SubGame) => {
+                            let key = (self.rules.max_depth.map(|_| depth + 1), subgame.clone());
+                            let (sub_winner, _) = self.game(Some(subgame), depth + 1)?;
+                            self.memo.insert(key, sub_winner);
+                            sub_winner
+                        }
+                        (Some(subgame), Decision::Remembered) => {
+                            let key = (self.rules.max_depth.map(|_| depth + 1), subgame);
+                            match self.memo.get(&key) {
+                                Some(w) => *w,
+                                None => {
+                                    return fail(
+                                        "this sub-game has not been played before".to_string(),
+                                    );
+                                }
+                            }
+                        }
+                    };
+                    if winner != expected {
+                        return fail(format!(
+                            "the round should be won by player {}",
+                            expected + 1
+                        ));
+                    }
+                    state.hand_mut(winner).win_cards(&winnings(&played, winner));
+                }
+                (None, other) => {
+                    return fail(format!(
+                        "expected round {} of game {}, got {:?}",
+                        round, this_game, other
+                    ));
+                }
+            }
         }
+        unreachable!()
     }
+}
 
-    fn game_result(&self) -> Option<(i32, &PlayerHand, usize)> {
-        match (self.p1.is_empty(), self.p2.is_empty()) {
-            (true, false) => Some((2, &self.p2, self.p2.score())),
-            (false, true) => Some((1, &self.p1, self.p1.score())),
-            (false, false) => None,
-            (true, true) => {
-                // In the game loop we assert that the total card
-                // count is invariant, so that assertion should fire
-                // before we get here.
-                panic!("impossible");
+/// Checks each game in a log against the rules, returning the winner
+/// and score of each top-level game.
+fn replay<I>(events: I) -> Result<Vec<(Player, usize)>, String>
+where
+    I: Iterator<Item = (usize, Event)>,
+{
+    let mut replay = Replay {
+        events: events.peekable(),
+        rules: RulesConfig::part2(),
+        game_counter: 0,
+        memo: HashMap::new(),
+    };
+    let mut results = Vec::new();
+    while let Some((line, event)) = replay.events.next() {
+        match event {
+            Event::Rules { rules } => {
+                replay.rules = rules;
+                replay.game_counter = 0;
+                replay.memo.clear();
+                results.push(replay.game(None, 0)?);
+            }
+            other => {
+                return Err(format!("line {}: expected rules, got {:?}", line, other));
             }
         }
     }
+    if results.is_empty() {
+        return Err("the log contains no games".to_string());
+    }
+    Ok(results)
+}
+
+/// Reads a log, which has an event (in JSON) on each line.
+fn read_log(filename: &str) -> Result<Vec<(usize, Event)>, String> {
+    let f = File::open(filename).map_err(|e| format!("failed to open {}: {}", filename, e))?;
+    let mut events = Vec::new();
+    for (n, line) in io::BufReader::new(f).lines().enumerate() {
+        let line = line.map_err(|e| format!("failed to read {}: {}", filename, e))?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        let event: Event = serde_json::from_str(&line)
+            .map_err(|e| format!("{}: line {}: {}", filename, n + 1, e))?;
+        events.push((n + 1, event));
+    }
+    Ok(events)
+}
+
+fn write_log(filename: &str, events: &[Event]) -> Result<(), String> {
+    let fail = |e: String| format!("failed to write {}: {}", filename, e);
+    let f = File::create(filename).map_err(|e| fail(e.to_string()))?;
+    let mut w = io::BufWriter::new(f);
+    for event in events {
+        let json = serde_json::to_string(event).map_err(|e| fail(e.to_string()))?;
+        writeln!(w, "{}", json).map_err(|e| fail(e.to_string()))?;
+    }
+    w.flush().map_err(|e| fail(e.to_string()))
+}
+
+fn new_context(rules: RulesConfig, options: &Options) -> RecursionContext {
+    let mut context = RecursionContext::new(
+        rules.clone(),
+        !options.no_memo,
+        options.state_tracking.unwrap_or(StateTracking::Packed),
+    );
+    if options.log.is_some() {
+        context.events = Some(vec![Event::Rules { rules }]);
+    }
+    context
+}
 
-    fn play_one_basic_round(&mut self, round: i32) -> bool {
-        log::info!("\n--Round {} --\n", round);
-        if log::log_enabled!(log::Level::Debug) {
-            for s in self.decks_as_string().split('\n') {
-                log::debug!("{}", s);
+/// Simulated games which would otherwise never end (because the rules
+/// do not detect loops) are stopped after this many rounds.
+const SIMULATION_MAX_ROUNDS: usize = 10_000;
+
+/// The results of playing many deals under the same rules.
+#[derive(Debug, PartialEq, Eq)]
+struct SimulationStats {
+    games: usize,
+    /// The scores of each player's wins.
+    scores: Vec<Vec<usize>>,
+    /// The number of games ended by the rule against infinite games.
+    loops: usize,
+    /// The number of games which ran out of rounds.
+    out_of_rounds: usize,
+}
+
+impl SimulationStats {
+    fn report(&self, label: &str, names: &[String]) -> String {
+        let mut result = format!("{}: {} games\n", label, self.games);
+        for (name, scores) in names.iter().zip(self.scores.iter()) {
+            let mut sorted = scores.clone();
+            sorted.sort_unstable();
+            result.push_str(&format!(
+                "  {}: won {} ({:.1}%)",
+                name,
+                sorted.len(),
+                100.0 * sorted.len() as f64 / self.games.max(1) as f64
+            ));
+            if let (Some(min), Some(max)) = (sorted.first(), sorted.last()) {
+                let mean = sorted.iter().sum::<usize>() as f64 / sorted.len() as f64;
+                result.push_str(&format!(
+                    "; winning scores: min {}, median {}, mean {:.1}, max {}",
+                    min,
+                    sorted[sorted.len() / 2],
+                    mean,
+                    max
+                ));
             }
+            result.push('\n');
+        }
+        if self.loops > 0 {
+            result.push_str(&format!(
+                "  {} games were ended by the rule against infinite games\n",
+                self.loops
+            ));
         }
+        if self.out_of_rounds > 0 {
+            result.push_str(&format!(
+                "  {} games ran out of rounds\n",
+                self.out_of_rounds
+            ));
+        }
+        result
+    }
+}
 
-        let (c1, c2) = (self.p1.play_next_card(), self.p2.play_next_card());
-        if c2 > c1 {
-            self.p2.win_cards(c2, c1);
-        } else {
-            self.p1.win_cards(c1, c2);
+/// Shuffles all the cards of `initial` together and deals them out
+/// again, giving each player as many cards as they had before.
+fn deal_at_random(initial: &GameState, rng: &mut Lcg) -> GameState {
+    let mut hands = initial.cards();
+    let mut deck: Vec<Card> = hands.iter().flatten().copied().collect();
+    rng.shuffle(&mut deck);
+    let mut rest = deck.as_slice();
+    for hand in hands.iter_mut() {
+        let (dealt, remaining) = rest.split_at(hand.len());
+        hand.copy_from_slice(dealt);
+        rest = remaining;
+    }
+    GameState::from_cards(&hands)
+}
+
+/// Plays `games` random deals of the cards in `initial` under
+/// `rules`.
+fn simulate(
+    initial: &GameState,
+    rules: &RulesConfig,
+    games: usize,
+    rng: &mut Lcg,
+    options: &Options,
+) -> SimulationStats {
+    let mut rules = rules.clone();
+    if !rules.detect_loops && rules.max_rounds.is_none() {
+        rules.max_rounds = Some(SIMULATION_MAX_ROUNDS);
+    }
+    let mut stats = SimulationStats {
+        games,
+        scores: vec![Vec::new(); initial.hands().len()],
+        loops: 0,
+        out_of_rounds: 0,
+    };
+    for _ in 0..games {
+        let mut state = deal_at_random(initial, rng);
+        let mut context = RecursionContext::new(
+            rules.clone(),
+            !options.no_memo,
+            options.state_tracking.unwrap_or(StateTracking::Packed),
+        );
+        let (winner, _, score) = play(&mut state, &mut context);
+        stats.scores[winner].push(score);
+        match context.ending {
+            Some(Ending::Loop) => stats.loops += 1,
+            Some(Ending::OutOfRounds) => stats.out_of_rounds += 1,
+            _ => (),
         }
-        self.p1.is_empty() || self.p2.is_empty()
     }
+    stats
+}
 
-    fn play_basic(&mut self) -> (i32, &PlayerHand, usize) {
-        let ncards = self.total_card_count();
-        for round in 1.. {
-            assert!(ncards == self.total_card_count());
-            if self.play_one_basic_round(round) {
-                break; // someone won
+/// Says whether the top-level game was ended by the rule against
+/// infinite games.
+fn report_loop(
+    label: &str,
+    context: &RecursionContext,
+    out: &mut impl Write,
+) -> Result<(), String> {
+    if let Some(report) = context.loops.iter().find(|r| r.game == 1) {
+        writeln!(out, "{}: the game was ended because {}", label, report).map_err(write_error)?;
+    }
+    Ok(())
+}
+
+fn part1(
+    lines: &[String],
+    names: &[String],
+    options: &Options,
+    log: &mut Vec<Event>,
+    out: &mut impl Write,
+) -> Result<(), String> {
+    let mut context = new_context(RulesConfig::part1(), options);
+    let mut game_state = GameState::new(lines)?;
+    let (winner, hand, score) = play(&mut game_state, &mut context);
+    writeln!(
+        out,
+        "Part 1: winner is {} with hand {}; score is {}",
+        names[winner], hand, score
+    )
+    .map_err(write_error)?;
+    log.extend(context.events.unwrap_or_default());
+    Ok(())
+}
+
+fn part2(
+    lines: &[String],
+    names: &[String],
+    options: &Options,
+    log: &mut Vec<Event>,
+    out: &mut impl Write,
+) -> Result<(), String> {
+    let mut context = new_context(RulesConfig::part2(), options);
+    let (winner, score) = play_game(lines, &mut context)?;
+    writeln!(
+        out,
+        "Part 2: winner is {}; score is {}",
+        names[winner], score
+    )
+    .map_err(write_error)?;
+    report_loop("Part 2", &context, out)?;
+    log.extend(context.events.unwrap_or_default());
+    Ok(())
+}
+
+/// Decks for which Recursive Combat plays the same sub-games many times.
+const ADVERSARIAL_P1: [Card; 13] = [24, 7, 5, 3, 25, 18, 19, 14, 12, 10, 8, 20, 21];
+const ADVERSARIAL_P2: [Card; 13] = [16, 6, 4, 2, 1, 9, 26, 15, 13, 11, 17, 22, 23];
+
+/// Splits `s` into lines.
+fn lines_of(s: &str) -> Vec<String> {
+    s.lines().map(str::to_string).collect()
+}
+
+/// Makes the input lines for a game with the given decks.
+fn deal(p1: &[Card], p2: &[Card]) -> Vec<String> {
+    let mut lines = vec!["Player 1:".to_string()];
+    lines.extend(p1.iter().map(|c| c.to_string()));
+    lines.push(String::new());
+    lines.push("Player 2:".to_string());
+    lines.extend(p2.iter().map(|c| c.to_string()));
+    lines
+}
+
+fn self_test() -> Result<(), String> {
+    aor2020::day22::self_test()?;
+    // Game states can be saved and loaded as JSON.
+    let state = GameState::from_slices(&[&[], &[3, 2, 10, 6, 8, 5, 9, 4, 7, 1]]);
+    let json = serde_json::to_string(&state).map_err(|e| e.to_string())?;
+    if json != "[[],[3,2,10,6,8,5,9,4,7,1]]" {
+        return Err(format!("FAIL: serialised a game state as {}", json));
+    }
+    let reloaded: GameState = serde_json::from_str(&json).map_err(|e| e.to_string())?;
+    if reloaded != state || reloaded.scores() != vec![0, 306] {
+        return Err("FAIL: a game state did not survive a round trip through JSON".to_string());
+    }
+    let sample = lines_of("Player 1:\n9\n2\n6\n3\n1\n\nPlayer 2:\n5\n8\n4\n7\n10\n");
+    let mut context = RecursionContext::new(RulesConfig::part1(), false, StateTracking::Full);
+    let (winner, score) = play_game(&sample, &mut context)?;
+    if (winner, score) != (1, 306) {
+        return Err(format!(
+            "FAIL: sample part 1 gave player {} score {}",
+            winner, score
+        ));
+    }
+    for memoize in &[false, true] {
+        for tracking in &[StateTracking::Full, StateTracking::Packed] {
+            let mut context = RecursionContext::new(RulesConfig::part2(), *memoize, *tracking);
+            let (winner, score) = play_game(&sample, &mut context)?;
+            if (winner, score) != (1, 291) {
+                return Err(format!(
+                    "FAIL: sample part 2 (memoize={}, tracking={:?}) gave player {} score {}",
+                    memoize, tracking, winner, score
+                ));
             }
         }
-        if log::log_enabled!(log::Level::Info) {
-            log::info!("\n== Post-game results ==\n{}", self.decks_as_string());
+    }
+    // The same sub-games come up again and again in this game, so
+    // remembering their results saves a lot of work.
+    let adversarial = deal(&ADVERSARIAL_P1, &ADVERSARIAL_P2);
+    let mut without_memo = RecursionContext::new(RulesConfig::part2(), false, StateTracking::Full);
+    let (w1, s1) = play_game(&adversarial, &mut without_memo)?;
+    let mut with_memo = RecursionContext::new(RulesConfig::part2(), true, StateTracking::Full);
+    let (w2, s2) = play_game(&adversarial, &mut with_memo)?;
+    let (rounds_without_memo, rounds_with_memo) = (without_memo.rounds, with_memo.rounds);
+    if (w1, s1) != (w2, s2) {
+        return Err(format!(
+            "FAIL: memoization changed the result from player {} score {} to player {} score {}",
+            w1, s1, w2, s2
+        ));
+    }
+    if rounds_with_memo * 10 > rounds_without_memo {
+        return Err(format!(
+            "FAIL: memoization only reduced the number of rounds from {} to {}",
+            rounds_without_memo, rounds_with_memo
+        ));
+    }
+    let mut packed = RecursionContext::new(RulesConfig::part2(), true, StateTracking::Packed);
+    if play_game(&adversarial, &mut packed)? != (w2, s2) {
+        return Err("FAIL: packed state tracking changed the result".to_string());
+    }
+    if packed.peak_seen_state_bytes * 2 > with_memo.peak_seen_state_bytes {
+        return Err(format!(
+            "FAIL: packed state tracking took {} bytes, against {} for full tracking",
+            packed.peak_seen_state_bytes, with_memo.peak_seen_state_bytes
+        ));
+    }
+    self_test_variants()?;
+    self_test_replay()?;
+    self_test_loops()?;
+    self_test_simulation()
+}
+
+fn self_test_simulation() -> Result<(), String> {
+    let initial = GameState::new(&deal(&ADVERSARIAL_P1, &ADVERSARIAL_P2))?;
+    let mut rng = Lcg::new(1);
+    let dealt = deal_at_random(&initial, &mut rng);
+    let mut before: Vec<Card> = initial.cards().concat();
+    let mut after: Vec<Card> = dealt.cards().concat();
+    before.sort_unstable();
+    after.sort_unstable();
+    if before != after || dealt.hand(0).num_cards() != initial.hand(0).num_cards() {
+        return Err(format!("FAIL: dealing changed the cards:\n{}", dealt));
+    }
+    let options = Options::default();
+    for rules in &[RulesConfig::part1(), RulesConfig::part2()] {
+        let first = simulate(&initial, rules, 20, &mut Lcg::new(2), &options);
+        let wins: usize = first.scores.iter().map(Vec::len).sum();
+        if wins != 20 {
+            return Err(format!("FAIL: 20 simulated games had {} winners", wins));
         }
-        match self.game_result() {
-            Some(r) => r,
-            None => {
-                panic!("terminated play_basic before game was over");
-            }
+        if simulate(&initial, rules, 20, &mut Lcg::new(2), &options) != first {
+            return Err("FAIL: simulation with the same seed gave different results".to_string());
         }
     }
+    Ok(())
+}
 
-    fn play_recursive(&mut self, game_counter: &mut usize) -> (i32, &PlayerHand, usize) {
-        let mut previous_states: HashSet<GameState> = HashSet::new();
-        let this_game = *game_counter;
-        log::debug!("== Game {} ===", this_game);
-        let ncards = self.total_card_count();
-        for round in 1.. {
-            log::debug!("");
-            log::debug!("-- Round {} (Game {}) --", round, this_game);
-            if log::log_enabled!(log::Level::Info) {
-                for s in self.decks_as_string().split('\n') {
-                    log::info!("{}", s);
-                }
-            }
-            assert!(self.total_card_count() == ncards);
-            if previous_states.contains(self) {
-                // player 1 wins the game.
-                return (1, &self.p1, self.score(1));
-            }
-            previous_states.insert(self.clone());
-
-            let c1 = self.p1.play_next_card();
-            log::debug!("Player 1 plays: {}", c1);
-            let c2 = self.p2.play_next_card();
-            log::debug!("Player 2 plays: {}", c2);
-            //assert!(c1 >= 0 && c2 >= 0);
-            let recurse =
-                (c1 as usize) <= self.p1.num_cards() && (c2 as usize) <= self.p2.num_cards();
-            let winner = if recurse {
-                let mut subgame = self.clone();
-                subgame.truncate_hands(c1 as usize, c2 as usize);
-                *game_counter += 1;
-                log::debug!("Playing a sub-game to determine the winner...");
-                let (winner, _, _) = subgame.play_recursive(game_counter);
-                winner
-            } else if c2 > c1 {
-                2
-            } else {
-                1
-            };
-            log::debug!(
-                "Player {} wins round {} of game {}!",
-                winner,
-                round,
-                this_game
-            );
-            match winner {
-                1 => self.p1.win_cards(c1, c2),
-                2 => self.p2.win_cards(c2, c1),
-                _ => {
-                    panic!("unexpectedly, player N enters the game!");
-                }
-            }
-            if self.p1.is_empty() || self.p2.is_empty() {
-                let (winner, hand, score) = self.game_result().expect("someone won");
-                log::info!("The winner of game {} is player {}", this_game, winner);
-                return (winner, hand, score);
+fn self_test_loops() -> Result<(), String> {
+    // This is the example of an infinite game from the puzzle.
+    let infinite = deal(&[43, 19], &[2, 29, 14]);
+    for tracking in &[StateTracking::Full, StateTracking::Packed] {
+        let mut context = RecursionContext::new(RulesConfig::part2(), true, *tracking);
+        context.events = Some(Vec::new());
+        let (winner, _) = play_game(&infinite, &mut context)?;
+        let expected = LoopReport {
+            game: 1,
+            round: 7,
+            first_seen: 1,
+        };
+        if winner != 0 || context.loops != [expected] {
+            return Err(format!(
+                "FAIL: infinite game gave winner {} and loops {:?}",
+                winner, context.loops
+            ));
+        }
+        match context.events.as_ref().and_then(|events| events.last()) {
+            Some(Event::GameEnd {
+                round: 7,
+                ending: Ending::Loop,
+                repeats: Some(1),
+                ..
+            }) => (),
+            other => {
+                return Err(format!("FAIL: infinite game ended with {:?}", other));
             }
         }
-        panic!("did not expect to fall out of the bottom of the loop");
     }
+    Ok(())
 }
 
-fn part1(lines: &[String]) -> Result<(), String> {
-    let mut game_state = GameState::new(lines)?;
-    let (winner, hand, score) = game_state.play_basic();
-    println!(
-        "Part 1: winner is player {} with hand {}; score is {}",
-        winner, hand, score
-    );
+fn self_test_replay() -> Result<(), String> {
+    let sample = lines_of("Player 1:\n9\n2\n6\n3\n1\n\nPlayer 2:\n5\n8\n4\n7\n10\n");
+    let adversarial = deal(&ADVERSARIAL_P1, &ADVERSARIAL_P2);
+    let mut log: Vec<Event> = Vec::new();
+    let mut expected = Vec::new();
+    for (lines, rules) in &[
+        (&sample, RulesConfig::part1()),
+        (&sample, RulesConfig::part2()),
+        (&adversarial, RulesConfig::part2()),
+    ] {
+        let mut context = RecursionContext::new(rules.clone(), true, StateTracking::Packed);
+        context.events = Some(vec![Event::Rules {
+            rules: rules.clone(),
+        }]);
+        expected.push(play_game(lines, &mut context)?);
+        log.extend(context.events.unwrap_or_default());
+    }
+    // Each event survives a round trip through JSON.
+    let mut events = Vec::with_capacity(log.len());
+    for (i, event) in log.iter().enumerate() {
+        let json = serde_json::to_string(event).map_err(|e| e.to_string())?;
+        events.push((
+            i + 1,
+            serde_json::from_str(&json).map_err(|e| e.to_string())?,
+        ));
+    }
+    let results = replay(events.iter().cloned())?;
+    if results != expected {
+        return Err(format!(
+            "FAIL: replay gave results {:?}, expected {:?}",
+            results, expected
+        ));
+    }
+    // Tampering with the winner of any round is caught.
+    let tampered: Vec<(usize, Event)> = events
+        .into_iter()
+        .map(|(line, event)| match event {
+            Event::Round {
+                game,
+                round: 2,
+                played,
+                winner,
+                decided_by,
+            } if game == 1 => (
+                line,
+                Event::Round {
+                    game,
+                    round: 2,
+                    played,
+                    winner: 1 - winner,
+                    decided_by,
+                },
+            ),
+            other => (line, other),
+        })
+        .collect();
+    if replay(tampered.into_iter()).is_ok() {
+        return Err("FAIL: replay accepted a log with the wrong winner".to_string());
+    }
     Ok(())
 }
 
-fn part2(lines: &[String]) -> Result<(), String> {
-    let mut game_counter: usize = 1;
-    let mut game_state = GameState::new(lines)?;
-    let (winner, _, score) = game_state.play_recursive(&mut game_counter);
-    log::info!("== Post-game results ==\n{}", &game_state.decks_as_string());
-    println!("Part 2: winner is player {}; score is {}", winner, score);
+fn self_test_variants() -> Result<(), String> {
+    let sample = lines_of("Player 1:\n9\n2\n6\n3\n1\n\nPlayer 2:\n5\n8\n4\n7\n10\n");
+    let three = lines_of("Player 1:\n3\nPlayer 2:\n1\nPlayer 3:\n2\n");
+    let limited = |rules: RulesConfig| RulesConfig {
+        max_rounds: Some(1),
+        ..rules
+    };
+    let shallow = RulesConfig {
+        max_depth: Some(0),
+        ..RulesConfig::part2()
+    };
+    let capped = RulesConfig {
+        card_cap: Some(1),
+        ..RulesConfig::part2()
+    };
+    let cases: &[(&[String], RulesConfig, (Player, usize))] = &[
+        // Player 1 wins all the cards in the first round, with their
+        // own first and the others in descending order.
+        (&three, RulesConfig::part1(), (0, 3 * 3 + 2 * 2 + 1)),
+        (&three, RulesConfig::part2(), (0, 3 * 3 + 2 * 2 + 1)),
+        // After one round player 1 holds 2, 6, 3, 1, 9, 5.
+        (&sample, limited(RulesConfig::part1()), (0, 80)),
+        (&sample, limited(RulesConfig::part2()), (0, 80)),
+        // Without sub-games, Recursive Combat is the same as Combat
+        // for the sample.
+        (&sample, shallow, (1, 306)),
+        // With one card each, a sub-game goes to the higher of the
+        // players' next cards.
+        (&sample, capped, (1, 374)),
+    ];
+    for (lines, rules, expected) in cases.iter() {
+        let mut context = RecursionContext::new(rules.clone(), true, StateTracking::Packed);
+        let got = play_game(lines, &mut context)?;
+        if got != *expected {
+            return Err(format!(
+                "FAIL: with rules {:?}, expected {:?} but got {:?}",
+                rules, expected, got
+            ));
+        }
+    }
     Ok(())
 }
 
-fn run() -> Result<(), String> {
+#[derive(Default)]
+struct Options {
+    log: Option<String>,
+    no_memo: bool,
+    replay: Option<String>,
+    rules: Option<RulesConfig>,
+    seed: Option<u64>,
+    simulate: Option<usize>,
+    state_tracking: Option<StateTracking>,
+}
+
+fn parse_count(option: &str, v: &str) -> Result<usize, String> {
+    v.parse()
+        .map_err(|e| format!("invalid value '{}' for {}: {}", v, option, e))
+}
+
+fn parse_args() -> Result<Options, String> {
+    let mut options = Options::default();
+    let mut args = std::env::args().skip(1);
+    while let Some(arg) = args.next() {
+        let mut value = || {
+            args.next()
+                .ok_or_else(|| format!("option {} requires an argument", arg))
+        };
+        // Options which change the rules modify the part 2 rules
+        // unless --preset was given first.
+        match arg.as_str() {
+            "replay" => options.replay = Some(value()?),
+            "simulate" => options.simulate = Some(parse_count(&arg, &value()?)?),
+            "--card-cap" => {
+                let cap = parse_count(&arg, &value()?)?;
+                if cap == 0 {
+                    return Err("the card cap must be at least 1".to_string());
+                }
+                options
+                    .rules
+                    .get_or_insert_with(RulesConfig::part2)
+                    .card_cap = Some(cap);
+            }
+            "--loop-detection" => {
+                let detect_loops = match value()?.as_str() {
+                    "on" => true,
+                    "off" => false,
+                    other => return Err(format!("expected on or off, not '{}'", other)),
+                };
+                options
+                    .rules
+                    .get_or_insert_with(RulesConfig::part2)
+                    .detect_loops = detect_loops;
+            }
+            "--max-depth" => {
+                let depth = parse_count(&arg, &value()?)?;
+                options
+                    .rules
+                    .get_or_insert_with(RulesConfig::part2)
+                    .max_depth = Some(depth);
+            }
+            "--max-rounds" => {
+                let rounds = parse_count(&arg, &value()?)?;
+                options
+                    .rules
+                    .get_or_insert_with(RulesConfig::part2)
+                    .max_rounds = Some(rounds);
+            }
+            "--log" => options.log = Some(value()?),
+            "--no-memo" => options.no_memo = true,
+            "--preset" => {
+                options.rules = Some(match value()?.as_str() {
+                    "part1" => RulesConfig::part1(),
+                    "part2" => RulesConfig::part2(),
+                    other => return Err(format!("unknown preset '{}'", other)),
+                });
+            }
+            "--recursion" => {
+                let recursion = value()?.parse()?;
+                options
+                    .rules
+                    .get_or_insert_with(RulesConfig::part2)
+                    .recursion = recursion;
+            }
+            "--seed" => {
+                let v = value()?;
+                options.seed = Some(
+                    v.parse()
+                        .map_err(|e| format!("invalid seed '{}': {}", v, e))?,
+                );
+            }
+            "--state-tracking" => options.state_tracking = Some(value()?.parse()?),
+            _ => return Err(format!("unknown option {}", arg)),
+        }
+    }
+    Ok(options)
+}
+
+fn run(input: impl BufRead, options: &Options, out: &mut impl Write) -> Result<(), String> {
+    if let Some(filename) = &options.replay {
+        let results = replay(read_log(filename)?.into_iter())?;
+        for (winner, score) in results {
+            writeln!(
+                out,
+                "Replay: verified a game won by player {} with score {}",
+                winner + 1,
+                score
+            )
+            .map_err(write_error)?;
+        }
+        return Ok(());
+    }
     let mut lines: Vec<String> = Vec::new();
-    for line_or_err in io::BufReader::new(io::stdin()).lines() {
+    for line_or_err in input.lines() {
         match line_or_err {
             Err(e) => {
                 return Err(format!("I/O error: {}", e));
@@ -293,8 +1288,45 @@ fn run() -> Result<(), String> {
             }
         }
     }
-    part1(&lines)?;
-    part2(&lines)?;
+    let names = player_names(&lines)?;
+    if let Some(games) = options.simulate {
+        let initial = GameState::new(&lines)?;
+        let mut rng = Lcg::new(options.seed.unwrap_or(1));
+        let variants: Vec<(&str, RulesConfig)> = match &options.rules {
+            Some(rules) => vec![("Custom rules", rules.clone())],
+            None => vec![
+                ("Part 1", RulesConfig::part1()),
+                ("Part 2", RulesConfig::part2()),
+            ],
+        };
+        for (label, rules) in variants {
+            let stats = simulate(&initial, &rules, games, &mut rng, options);
+            write!(out, "{}", stats.report(label, &names)).map_err(write_error)?;
+        }
+        return Ok(());
+    }
+    let mut log: Vec<Event> = Vec::new();
+    match &options.rules {
+        Some(rules) => {
+            let mut context = new_context(rules.clone(), options);
+            let (winner, score) = play_game(&lines, &mut context)?;
+            writeln!(
+                out,
+                "Custom rules: winner is {}; score is {}",
+                names[winner], score
+            )
+            .map_err(write_error)?;
+            report_loop("Custom rules", &context, out)?;
+            log.extend(context.events.unwrap_or_default());
+        }
+        None => {
+            part1(&lines, &names, options, &mut log, out)?;
+            part2(&lines, &names, options, &mut log, out)?;
+        }
+    }
+    if let Some(filename) = &options.log {
+        write_log(filename, &log)?;
+    }
     Ok(())
 }
 
@@ -302,7 +1334,11 @@ fn main() {
     // the env logger is configured with $RUST_LOG.
     // For example RUST_LOG=debug day22
     pretty_env_logger::init();
-    std::process::exit(match run() {
+    let result = parse_args().and_then(|options| {
+        self_test()?;
+        run(io::stdin().lock(), &options, &mut io::stdout())
+    });
+    std::process::exit(match result {
         Ok(_) => 0,
         Err(err) => {
             eprintln!("error: {}", err);
@@ -310,3 +1346,30 @@ fn main() {
         }
     });
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn output(input: &str, options: &Options) -> String {
+        let mut out = Vec::new();
+        run(input.as_bytes(), options, &mut out).unwrap();
+        String::from_utf8(out).unwrap()
+    }
+
+    #[test]
+    fn part1() {
+        insta::assert_snapshot!(output(
+            include_str!("../../samples/day22-part1.txt"),
+            &Options::default()
+        ));
+    }
+
+    #[test]
+    fn part2() {
+        insta::assert_snapshot!(output(
+            include_str!("../../samples/day22-part2.txt"),
+            &Options::default()
+        ));
+    }
+}
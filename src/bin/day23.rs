@@ -1,8 +1,14 @@
+extern crate aor2020;
+#[cfg(test)]
+extern crate insta;
 extern crate log;
 extern crate pretty_env_logger;
-use std::collections::HashSet;
+use aor2020::output::write_error;
+use aor2020::progress::Progress;
 use std::fmt;
 use std::io::Read;
+use std::io::Write;
+use std::str::FromStr;
 
 use std::io;
 
@@ -10,26 +16,39 @@ use std::io;
 enum Check {
     Always,
     Never,
+    /// Check every N moves, and at the end.
+    Sampled(usize),
 }
 
-#[derive(Debug)]
-struct Cup {
-    label: u32,
-    succ: usize,
-}
+impl FromStr for Check {
+    type Err = String;
 
-impl fmt::Display for Cup {
-    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        write!(f, "{}", self.label)
+    fn from_str(s: &str) -> Result<Check, String> {
+        match s {
+            "always" => Ok(Check::Always),
+            "never" => Ok(Check::Never),
+            _ => match s.strip_prefix("sampled:").map(|n| n.parse::<usize>()) {
+                Some(Ok(n)) if n > 0 => Ok(Check::Sampled(n)),
+                _ => Err(format!(
+                    "unknown check mode '{}' (expected never, sampled:N or always)",
+                    s
+                )),
+            },
+        }
     }
 }
 
+/// The cups, as a circular linked list indexed by label.
 struct CupCircle {
     verbose: bool,
     checks: Check,
-    cups: Vec<Cup>,
-    label_to_pos: Vec<usize>,
-    current_pos: usize,
+    /// `next[label]` is the label of the cup clockwise of the cup
+    /// labelled `label`.  Labels start at 1, so `next[0]` is unused.
+    next: Vec<u32>,
+    /// The label of the cup which was first in the input; we list
+    /// the cups starting from here.
+    first_label: u32,
+    current_label: u32,
     max_label: u32,
 }
 
@@ -37,170 +56,222 @@ impl fmt::Debug for CupCircle {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         write!(
             f,
-            "CupCircle{{\nverbose: {}\nchecks: {:?}\ncups: [\n",
+            "CupCircle{{\nverbose: {}\nchecks: {:?}\nnext: [\n",
             self.verbose, self.checks
         )?;
-        for i in 0..self.cups.len() {
-            write!(f, "  [{}] = {:?}", i, self.cups[i])?;
-        }
-        f.write_str("]\nlabel_to_pos: [\n")?;
-        for i in 0..self.label_to_pos.len() {
-            write!(f, "  [{}] for {} = {:?}", i, i + 1, self.label_to_pos[i])?;
+        for (label, next) in self.next.iter().enumerate().skip(1) {
+            writeln!(f, "  [{}] = {}", label, next)?;
         }
         write!(
             f,
-            "]\ncurrent_pos={}, max_label={}\n}}",
-            self.current_pos, self.max_label
+            "]\nfirst_label={}, current_label={}, max_label={}\n}}",
+            self.first_label, self.current_label, self.max_label
         )
     }
 }
 
 impl CupCircle {
-    fn new(verbose: bool, checks: Check, v: &Vec<u32>) -> CupCircle {
+    fn new(verbose: bool, checks: Check, v: &[u32]) -> CupCircle {
         if v.is_empty() {
             panic!("CupCircle::new cannot accept an empty Vec");
         }
+        let max_label = *v.iter().max().expect("Vec should not be empty");
+        let mut next = vec![0; max_label as usize + 1];
+        for (i, label) in v.iter().enumerate() {
+            next[*label as usize] = v[(i + 1) % v.len()];
+        }
         CupCircle {
             verbose,
             checks,
-            current_pos: 0,
-            cups: (0..v.len())
-                .map(|i| Cup {
-                    label: v[i] as u32,
-                    succ: if i + 1 >= v.len() { 0 } else { i + 1 },
-                })
-                .collect(),
-            label_to_pos: itertools::sorted(v.iter().enumerate().map(|(pos, label)| (*label, pos)))
-                .into_iter()
-                .map(|(_, pos)| pos)
-                .collect(),
-            max_label: *v.iter().max().expect("Vec should not be empty"),
+            next,
+            first_label: v[0],
+            current_label: v[0],
+            max_label,
         }
     }
 
-    fn label_of_current(&self) -> u32 {
-        self.cups[self.current_pos].label
+    fn succ(&self, label: u32) -> u32 {
+        self.next[label as usize]
     }
 
     fn play(&mut self, move_number: usize) {
         if self.verbose {
             println!("\n-- move {} --\ncups: {}", move_number, self);
         }
-        self.check();
+        if self.checks == Check::Always {
+            self.check();
+        }
         // remove 3 cups.
-        let pos1 = self.cups[self.current_pos].succ;
-        let pos2 = self.cups[pos1].succ;
-        let pos3 = self.cups[pos2].succ;
+        let current = self.current_label;
+        let pick1 = self.succ(current);
+        let pick2 = self.succ(pick1);
+        let pick3 = self.succ(pick2);
         if self.verbose {
-            println!(
-                "pick up: {}, {}, {}",
-                self.cups[pos1].label, self.cups[pos2].label, self.cups[pos3].label
-            );
+            println!("pick up: {}, {}, {}", pick1, pick2, pick3);
         }
-        self.cups[self.current_pos].succ = self.cups[pos3].succ;
+        self.next[current as usize] = self.succ(pick3);
         // select the destination cup.
-        let mut dest_label = self.cups[self.current_pos].label - 1;
+        let mut dest_label = current - 1;
         if dest_label < 1 {
             dest_label = self.max_label;
         }
-        while dest_label == self.cups[pos1].label
-            || dest_label == self.cups[pos2].label
-            || dest_label == self.cups[pos3].label
-        {
+        while dest_label == pick1 || dest_label == pick2 || dest_label == pick3 {
             dest_label -= 1;
             if dest_label < 1 {
                 dest_label = self.max_label;
             }
         }
-        let dest_pos = self.get_pos(dest_label);
         if self.verbose {
-            println!("destination: {} (at position {})", dest_label, dest_pos);
+            println!("destination: {}", dest_label);
         }
         // Splice the 3 taken cups back in immediately after the
         // destination cup.
-        let tail_pos = self.cups[dest_pos].succ;
-        self.cups[pos3].succ = tail_pos;
-        self.cups[dest_pos].succ = pos1;
+        self.next[pick3 as usize] = self.succ(dest_label);
+        self.next[dest_label as usize] = pick1;
         // Select a new current cup.
-        self.current_pos = self.cups[self.current_pos].succ;
-        assert!(self.current_pos < self.cups.len());
-        self.check();
+        self.current_label = self.succ(current);
+        match self.checks {
+            Check::Always => self.check(),
+            Check::Sampled(n) if move_number % n == 0 => self.check(),
+            _ => (),
+        }
+    }
+
+    /// Plays moves `first` to `last` inclusive.  With the unsafe-opt
+    /// feature, moves which need neither checks nor output are played
+    /// by `play_unchecked`.
+    fn play_range(&mut self, first: usize, last: usize) {
+        #[cfg(feature = "unsafe-opt")]
+        {
+            if !self.verbose && self.checks == Check::Never {
+                self.validate();
+                for _ in first..=last {
+                    self.play_unchecked();
+                }
+                return;
+            }
+        }
+        for move_number in first..=last {
+            self.play(move_number);
+        }
+    }
+
+    /// Plays a move like `play`, but without bounds checks, output or
+    /// checking.  The caller must first establish (with `validate`)
+    /// that the circle is valid: the successors in `next` are each
+    /// label from 1 to `max_label` exactly once, `next.len()` is
+    /// `max_label + 1` and `current_label` is in range.  A move only
+    /// relinks cups, so the circle stays valid, and every label we
+    /// index with comes from `next` or is a destination label, which
+    /// is kept in the range 1 to `max_label`.
+    #[cfg(feature = "unsafe-opt")]
+    #[inline(always)]
+    fn play_unchecked(&mut self) {
+        let max_label = self.max_label;
+        let next = self.next.as_mut_slice();
+        // SAFETY: see above; all these labels are in range.
+        unsafe {
+            let current = self.current_label;
+            let pick1 = *next.get_unchecked(current as usize);
+            let pick2 = *next.get_unchecked(pick1 as usize);
+            let pick3 = *next.get_unchecked(pick2 as usize);
+            *next.get_unchecked_mut(current as usize) = *next.get_unchecked(pick3 as usize);
+            // Step down from the current label, wrapping from 0 to
+            // max_label without a branch.
+            let wrap = |label: u32| label + max_label * (label == 0) as u32;
+            let mut dest_label = wrap(current - 1);
+            while dest_label == pick1 || dest_label == pick2 || dest_label == pick3 {
+                dest_label = wrap(dest_label - 1);
+            }
+            *next.get_unchecked_mut(pick3 as usize) = *next.get_unchecked(dest_label as usize);
+            *next.get_unchecked_mut(dest_label as usize) = pick1;
+            self.current_label = *next.get_unchecked(current as usize);
+        }
     }
 
     fn check(&self) {
         if self.checks == Check::Never {
             return;
         }
-        if self.current_pos >= self.cups.len() {
-            panic!("current_pos {} is out-of-range", self.current_pos);
-        }
-        if self.max_label as usize != self.cups.len() {
+        self.validate();
+    }
+
+    /// Panics unless the cups form a single circle of labels from 1
+    /// to `max_label`.
+    fn validate(&self) {
+        if self.max_label as usize + 1 != self.next.len() {
             panic!(
                 "max_label {} is unexpected; should be {}",
                 self.max_label,
-                self.cups.len()
+                self.next.len() - 1
             );
         }
-        let mut labels_seen: HashSet<u32> = HashSet::new();
-        let mut succ_seen: HashSet<usize> = HashSet::new();
-        for (i, c) in self.cups.iter().enumerate() {
-            if c.succ >= self.cups.len() {
-                panic!("cup {i} has out-of-range succ value {}", c.succ);
+        for label in &[self.first_label, self.current_label] {
+            if *label < 1 || *label > self.max_label {
+                panic!("label {} is out-of-range", label);
             }
-            if c.label < 1 || c.label as usize > self.cups.len() {
-                panic!("cup {i} has out-of-range label value {}", c.label);
+        }
+        // Every cup must have a successor, and following them from
+        // the current cup must visit every cup once before returning.
+        let mut seen = vec![false; self.next.len()];
+        let mut seen_count: usize = 0;
+        let mut label = self.current_label;
+        loop {
+            let next = self.succ(label);
+            if next < 1 || next > self.max_label {
+                panic!("cup {} has out-of-range successor {}", label, next);
             }
-            if labels_seen.contains(&c.label) {
-                panic!("duplicate label {}", c.label);
+            if seen[next as usize] {
+                panic!("cup {} is the successor of more than one cup", next);
             }
-            labels_seen.insert(c.label);
-            if succ_seen.contains(&c.succ) {
-                panic!("duplicate successor {}", c.succ);
+            seen[next as usize] = true;
+            seen_count += 1;
+            if next == self.current_label {
+                break;
             }
-            succ_seen.insert(c.succ);
+            label = next;
         }
-        drop(labels_seen);
-        drop(succ_seen);
-
-        let mut pos_seen: HashSet<usize> = HashSet::new();
-        for pos in &self.label_to_pos {
-            if pos_seen.contains(pos) {
-                panic!("duplicate position {}", pos);
-            }
-            pos_seen.insert(*pos);
-            assert_eq!(*pos, self.get_pos(self.cups[*pos].label));
+        if seen_count != self.max_label as usize {
+            panic!(
+                "the circle holds {} cups but should hold {}",
+                seen_count, self.max_label
+            );
         }
-        drop(pos_seen);
-    }
-
-    fn get_pos(&self, label: u32) -> usize {
-        let lab = label as usize;
-        assert!(label > 0);
-        assert!((lab - 1) < self.label_to_pos.len());
-        self.label_to_pos[lab - 1]
     }
 
+    /// Adds cups after the last one (the one before the first),
+    /// labelled in order up to `want`.
     fn extend(&mut self, want: u32) {
-        assert!(!self.cups.is_empty());
-        self.cups.iter_mut().rev().next().unwrap().succ = self.cups.len();
-        self.cups.reserve(want as usize);
-        self.label_to_pos.reserve(want as usize);
+        if want <= self.max_label {
+            return;
+        }
+        let last = (1..=self.max_label)
+            .find(|label| self.succ(*label) == self.first_label)
+            .expect("some cup should precede the first");
+        self.next.reserve((want - self.max_label) as usize);
+        self.next[last as usize] = self.max_label + 1;
         while want > self.max_label {
             self.max_label += 1;
-            let here = self.cups.len();
-            self.cups.push(Cup {
-                label: self.max_label,
-                succ: here + 1,
-            });
-            self.label_to_pos.push(here);
+            self.next.push(self.max_label + 1);
+        }
+        self.next[want as usize] = self.first_label;
+    }
+
+    /// Iterates over the labels of the cups clockwise, starting with
+    /// `label` and stopping before we get back to it.
+    fn iter_from(&self, label: u32) -> CupCircleIter<'_> {
+        self.check();
+        CupCircleIter {
+            start: label,
+            label: Some(label),
+            circle: self,
         }
-        self.cups.iter_mut().rev().next().unwrap().succ = 0;
     }
 }
 
 impl fmt::Display for CupCircle {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        let current = self.label_of_current();
+        let current = self.current_label;
         let mut prefix = "";
         for c in self {
             if c == current {
@@ -215,7 +286,9 @@ impl fmt::Display for CupCircle {
 }
 
 struct CupCircleIter<'r> {
-    pos: Option<usize>,
+    /// The label at which the iteration started (and will stop).
+    start: u32,
+    label: Option<u32>,
     circle: &'r CupCircle,
 }
 
@@ -223,17 +296,20 @@ impl Iterator for CupCircleIter<'_> {
     type Item = u32;
 
     fn next(&mut self) -> Option<Self::Item> {
-        match self.pos {
+        match self.label {
             None => None,
-            Some(p) => {
-                if p >= self.circle.cups.len() {
-                    panic!("CupCircleIter: pos {} out of range:\n{:?}", p, self.circle);
+            Some(label) => {
+                if label < 1 || label > self.circle.max_label {
+                    panic!(
+                        "CupCircleIter: label {} out of range:\n{:?}",
+                        label, self.circle
+                    );
                 }
-                self.pos = match self.circle.cups[p].succ {
-                    0 => None,
+                self.label = match self.circle.succ(label) {
+                    next if next == self.start => None,
                     next => Some(next),
                 };
-                Some(self.circle.cups[p].label)
+                Some(label)
             }
         }
     }
@@ -244,80 +320,377 @@ impl<'a> IntoIterator for &'a CupCircle {
     type IntoIter = CupCircleIter<'a>;
 
     fn into_iter(self) -> Self::IntoIter {
-        self.check();
-        CupCircleIter {
-            pos: if self.cups.is_empty() { None } else { Some(0) },
-            circle: self,
-        }
+        self.iter_from(self.first_label)
     }
 }
 
 fn show(part: u32, label: &str, cups: &CupCircle) {
     println!("Part {}: {} cups are:", part, label);
-    for (i, c) in cups.cups.iter().enumerate() {
-        println!(
-            "{:>2}: {:?} succ={} (label_to_pos[{}]={})",
-            i,
-            c.label,
-            c.succ,
-            c,
-            cups.get_pos(c.label)
-        );
+    for (i, c) in cups.into_iter().enumerate() {
+        println!("{:>2}: {} succ={}", i, c, cups.succ(c));
     }
 }
 
+/// The label of the cup `n` places clockwise of `label`; `n` must be
+/// less than the number of cups.
 fn cups_succ(label: u32, n: usize, cups: &CupCircle) -> u32 {
-    let mut pos = cups.get_pos(label);
-    for _ in 0..n {
-        pos = cups.cups[pos].succ;
+    cups.iter_from(label)
+        .nth(n)
+        .expect("n should be less than the number of cups")
+}
+
+/// The labels of (at most) `count` cups clockwise of `label`.
+fn cups_after(label: u32, count: usize, cups: &CupCircle) -> Result<Vec<u32>, String> {
+    if label < 1 || label > cups.max_label {
+        return Err(format!(
+            "there is no cup {}: labels run from 1 to {}",
+            label, cups.max_label
+        ));
+    }
+    Ok(cups.iter_from(label).skip(1).take(count).collect())
+}
+
+/// Writes the labels of the `count` cups clockwise of `label`.
+fn show_after(
+    part: u32,
+    label: u32,
+    count: usize,
+    cups: &CupCircle,
+    out: &mut impl Write,
+) -> Result<(), String> {
+    let labels = cups_after(label, count, cups)?;
+    writeln!(
+        out,
+        "Part {}: the {} cups after {} are: {}",
+        part,
+        labels.len(),
+        label,
+        Answer::Labels(labels)
+    )
+    .map_err(write_error)?;
+    Ok(())
+}
+
+/// A question about the cups at the end of the game.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Query {
+    /// The labels of the K cups clockwise of cup L.
+    LabelsAfter(u32, usize),
+    /// The product of the labels of the K cups clockwise of cup L.
+    ProductAfter(u32, usize),
+}
+
+impl Query {
+    fn answer(&self, cups: &CupCircle) -> Result<Answer, String> {
+        match *self {
+            Query::LabelsAfter(label, count) => Ok(Answer::Labels(cups_after(label, count, cups)?)),
+            Query::ProductAfter(label, count) => {
+                let factors = cups_after(label, count, cups)?;
+                let product = factors
+                    .iter()
+                    .try_fold(1_u128, |acc, f| acc.checked_mul(*f as u128))
+                    .ok_or_else(|| format!("the {} is too large", self))?;
+                Ok(Answer::Product { factors, product })
+            }
+        }
+    }
+}
+
+impl fmt::Display for Query {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Query::LabelsAfter(label, count) => {
+                write!(f, "labels of {} cups after {}", count, label)
+            }
+            Query::ProductAfter(label, count) => {
+                write!(f, "product of {} cups after {}", count, label)
+            }
+        }
+    }
+}
+
+/// The answer to a `Query`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Answer {
+    Labels(Vec<u32>),
+    Product { factors: Vec<u32>, product: u128 },
+}
+
+impl fmt::Display for Answer {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let (labels, separator) = match self {
+            Answer::Labels(labels) => (labels, " "),
+            Answer::Product { factors, .. } => (factors, " * "),
+        };
+        let mut prefix = "";
+        for label in labels {
+            write!(f, "{}{}", prefix, label)?;
+            prefix = separator;
+        }
+        if let Answer::Product { product, .. } = self {
+            write!(f, " = {}", product)?;
+        }
+        Ok(())
     }
-    cups.cups[pos].label
+}
+
+fn answer_queries(
+    part: u32,
+    cups: &CupCircle,
+    options: &Options,
+    out: &mut impl Write,
+) -> Result<(), String> {
+    for query in &options.queries {
+        writeln!(out, "Part {}: {}: {}", part, query, query.answer(cups)?).map_err(write_error)?;
+    }
+    Ok(())
 }
 
 fn play_moves(count: usize, cups: &mut CupCircle) {
-    for move_number in 1..=count {
-        cups.play(move_number);
+    cups.play_range(1, count);
+    cups.check();
+}
+
+/// Like `play_moves`, but reports progress.  The moves are played in
+/// batches between reports, so the inner loop is the same.
+fn play_moves_with_progress(count: usize, cups: &mut CupCircle, progress: &mut Progress) {
+    let mut done: usize = 0;
+    while done < count {
+        let until = progress.due() as usize;
+        cups.play_range(done + 1, until);
+        done = until;
+        progress.report(done as u64);
     }
+    cups.check();
 }
 
-fn part1(initial: &Vec<u32>) -> Result<(), String> {
-    let mut cups = CupCircle::new(true, Check::Always, initial);
+/// The labels of the cups after cup 1, which is the answer to part 1.
+fn labels_after_1(cups: &CupCircle) -> String {
+    cups.iter_from(1).skip(1).map(|c| c.to_string()).collect()
+}
+
+fn part1(initial: &[u32], options: &Options, out: &mut impl Write) -> Result<(), String> {
+    let mut cups = CupCircle::new(true, options.check.unwrap_or(Check::Always), initial);
     show(1, "initial", &cups);
     cups.check();
-    play_moves(100, &mut cups);
+    play_moves(options.moves.unwrap_or(100), &mut cups);
     if cups.verbose {
         println!("\n-- final --\ncups: {}", cups);
     }
-    print!("Part 1: labels after 1: ");
-    let mut pos: usize = cups.cups[cups.get_pos(1)].succ as usize;
-    for _ in 0..(cups.cups.len() - 1) {
-        let cup = &cups.cups[pos as usize];
-        print!("{}", cup.label);
-        pos = cup.succ as usize;
+    writeln!(out, "Part 1: labels after 1: {}", labels_after_1(&cups)).map_err(write_error)?;
+    if let Some((label, count)) = options.show_after {
+        show_after(1, label, count, &cups, out)?;
     }
-    println!();
-    Ok(())
+    answer_queries(1, &cups, options, out)
 }
 
-fn part2(initial: &Vec<u32>) -> Result<(), String> {
-    let mut cups = CupCircle::new(false, Check::Never, initial);
+fn part2(initial: &[u32], options: &Options, out: &mut impl Write) -> Result<(), String> {
+    let mut cups = CupCircle::new(false, options.check.unwrap_or(Check::Never), initial);
     show(2, "initial (before extending)", &cups);
-    cups.extend(1000000);
-    play_moves(10 * 1000 * 1000, &mut cups);
-    let succ1 = cups_succ(1, 1, &cups);
-    let succ2 = cups_succ(1, 2, &cups);
-    println!(
-        "Part 2: product is {} * {} = {}",
-        succ1,
-        succ2,
-        (succ1 as usize) * (succ2 as usize)
-    );
+    cups.extend(options.cups.unwrap_or(1000 * 1000));
+    let moves = options.moves.unwrap_or(10 * 1000 * 1000);
+    if options.progress {
+        let every = options.progress_every.unwrap_or(1000 * 1000);
+        let mut progress = Progress::new("moves", moves as u64, every as u64);
+        play_moves_with_progress(moves, &mut cups, &mut progress);
+    } else {
+        play_moves(moves, &mut cups);
+    }
+    writeln!(
+        out,
+        "Part 2: product is {}",
+        Query::ProductAfter(1, 2).answer(&cups)?
+    )
+    .map_err(write_error)?;
+    if let Some((label, count)) = options.show_after {
+        show_after(2, label, count, &cups, out)?;
+    }
+    answer_queries(2, &cups, options, out)
+}
+
+fn self_test() -> Result<(), String> {
+    aor2020::progress::self_test()?;
+    let example = [3, 8, 9, 1, 2, 5, 4, 6, 7];
+    for (moves, expected, checks) in &[
+        (10, "92658374", Check::Always),
+        (100, "67384529", Check::Always),
+        (100, "67384529", Check::Sampled(7)),
+        (100, "67384529", Check::Never),
+    ] {
+        let mut cups = CupCircle::new(false, *checks, &example);
+        play_moves(*moves, &mut cups);
+        let got = labels_after_1(&cups);
+        if got != *expected {
+            return Err(format!(
+                "FAIL: after {} moves, expected {} but got {}",
+                moves, expected, got
+            ));
+        }
+    }
+    let mut cups = CupCircle::new(false, Check::Always, &example);
+    cups.extend(20);
+    if cups.into_iter().collect::<Vec<u32>>()
+        != [
+            3, 8, 9, 1, 2, 5, 4, 6, 7, 10, 11, 12, 13, 14, 15, 16, 17, 18, 19, 20,
+        ]
+    {
+        return Err(format!("FAIL: wrong cups after extending: {}", cups));
+    }
+    let after_19: Vec<u32> = cups.iter_from(19).take(4).collect();
+    if after_19 != [19, 20, 3, 8] {
+        return Err(format!("FAIL: wrong cups from 19: {:?}", after_19));
+    }
+    if cups.iter_from(5).count() != 20 || cups_succ(20, 2, &cups) != 8 {
+        return Err("FAIL: iteration from 5 should visit every cup once".to_string());
+    }
+    let mut cups = CupCircle::new(false, Check::Always, &example);
+    play_moves(100, &mut cups);
+    for (query, expected) in &[
+        (Query::LabelsAfter(1, 3), Answer::Labels(vec![6, 7, 3])),
+        (
+            Query::LabelsAfter(7, 20),
+            Answer::Labels(vec![3, 8, 4, 5, 2, 9, 1, 6]),
+        ),
+        (
+            Query::ProductAfter(4, 3),
+            Answer::Product {
+                factors: vec![5, 2, 9],
+                product: 90,
+            },
+        ),
+    ] {
+        match query.answer(&cups) {
+            Ok(ref got) if got == expected => (),
+            got => {
+                return Err(format!(
+                    "FAIL: {}: expected {:?} but got {:?}",
+                    query, expected, got
+                ))
+            }
+        }
+    }
+    if Query::LabelsAfter(10, 1).answer(&cups).is_ok() {
+        return Err("FAIL: there should be no cup 10".to_string());
+    }
+    for (input, expected) in &[
+        ("never", Ok(Check::Never)),
+        ("sampled:1000", Ok(Check::Sampled(1000))),
+        ("always", Ok(Check::Always)),
+    ] {
+        if input.parse::<Check>() != *expected {
+            return Err(format!("FAIL: wrong check mode for '{}'", input));
+        }
+    }
+    for input in &["sampled:0", "sampled:", "sometimes"] {
+        if input.parse::<Check>().is_ok() {
+            return Err(format!("FAIL: '{}' should not be a check mode", input));
+        }
+    }
+    #[cfg(feature = "unsafe-opt")]
+    self_test_unchecked()?;
     Ok(())
 }
 
-fn run() -> Result<(), String> {
+/// Checks that `play_unchecked` moves the cups exactly as `play`
+/// does, over millions of moves.
+#[cfg(feature = "unsafe-opt")]
+fn self_test_unchecked() -> Result<(), String> {
+    let example = [3, 8, 9, 1, 2, 5, 4, 6, 7];
+    // Sampled checks make play_range use the safe engine, but never
+    // actually check between the samples.
+    let mut safe = CupCircle::new(false, Check::Sampled(usize::MAX), &example);
+    let mut fast = CupCircle::new(false, Check::Never, &example);
+    for cups in &[9, 1000, 100_000] {
+        safe.extend(*cups);
+        fast.extend(*cups);
+        for round in 0..2 {
+            let first = round * 1_000_000 + 1;
+            safe.play_range(first, first + 999_999);
+            fast.play_range(first, first + 999_999);
+            if safe.next != fast.next || safe.current_label != fast.current_label {
+                return Err(format!(
+                    "FAIL: with {} cups, the unchecked engine differs after {} moves",
+                    cups,
+                    first + 999_999
+                ));
+            }
+        }
+    }
+    Ok(())
+}
+
+#[derive(Default)]
+struct Options {
+    check: Option<Check>,
+    cups: Option<u32>,
+    moves: Option<usize>,
+    progress: bool,
+    progress_every: Option<usize>,
+    queries: Vec<Query>,
+    show_after: Option<(u32, usize)>,
+}
+
+/// Parses an argument like "1,8", giving a cup label and a count of
+/// the cups after it.
+fn parse_label_count(s: &str) -> Result<(u32, usize), String> {
+    let bad = || format!("expected LABEL,COUNT but got '{}'", s);
+    let (label, count) = s.split_once(',').ok_or_else(bad)?;
+    Ok((
+        label.trim().parse().map_err(|_| bad())?,
+        count.trim().parse().map_err(|_| bad())?,
+    ))
+}
+
+fn parse_args() -> Result<Options, String> {
+    let mut options = Options::default();
+    let mut args = std::env::args().skip(1);
+    while let Some(arg) = args.next() {
+        let mut value = || {
+            args.next()
+                .ok_or_else(|| format!("option {} requires an argument", arg))
+        };
+        match arg.as_str() {
+            "--check" => options.check = Some(value()?.parse()?),
+            "--cups" => {
+                let v = value()?;
+                options.cups = Some(
+                    v.parse()
+                        .map_err(|e| format!("invalid cup count '{}': {}", v, e))?,
+                );
+            }
+            "--moves" => {
+                let v = value()?;
+                options.moves = Some(
+                    v.parse()
+                        .map_err(|e| format!("invalid move count '{}': {}", v, e))?,
+                );
+            }
+            "--progress" => options.progress = true,
+            "--progress-every" => {
+                let v = value()?;
+                options.progress_every = Some(
+                    v.parse()
+                        .map_err(|e| format!("invalid move count '{}': {}", v, e))?,
+                );
+            }
+            "--labels-after" => {
+                let (label, count) = parse_label_count(&value()?)?;
+                options.queries.push(Query::LabelsAfter(label, count));
+            }
+            "--product-after" => {
+                let (label, count) = parse_label_count(&value()?)?;
+                options.queries.push(Query::ProductAfter(label, count));
+            }
+            "--show-after" => options.show_after = Some(parse_label_count(&value()?)?),
+            _ => return Err(format!("unknown option {}", arg)),
+        }
+    }
+    Ok(options)
+}
+
+fn run(mut input: impl Read, options: &Options, out: &mut impl Write) -> Result<(), String> {
     let mut buffer = String::new();
-    match io::stdin().read_to_string(&mut buffer) {
+    match input.read_to_string(&mut buffer) {
         Ok(_) => (),
         Err(e) => {
             return Err(format!("I/O error: {}", e));
@@ -333,16 +706,19 @@ fn run() -> Result<(), String> {
             Some(d) => d,
         })
         .collect();
-    part1(&labels)?;
-    part2(&labels)?;
-    Ok(())
+    part1(&labels, options, out)?;
+    part2(&labels, options, out)
 }
 
 fn main() {
     // the env logger is configured with $RUST_LOG.
     // For example RUST_LOG=debug day23
     pretty_env_logger::init();
-    std::process::exit(match run() {
+    let result = parse_args().and_then(|options| {
+        self_test()?;
+        run(io::stdin().lock(), &options, &mut io::stdout())
+    });
+    std::process::exit(match result {
         Ok(_) => 0,
         Err(err) => {
             eprintln!("error: {}", err);
@@ -350,3 +726,19 @@ fn main() {
         }
     });
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn output(input: &str, options: &Options) -> String {
+        let mut out = Vec::new();
+        run(input.as_bytes(), options, &mut out).unwrap();
+        String::from_utf8(out).unwrap()
+    }
+
+    #[test]
+    fn sample() {
+        insta::assert_snapshot!(output("389125467\n", &Options::default()));
+    }
+}
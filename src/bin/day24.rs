@@ -1,178 +1,163 @@
+extern crate aor2020;
+#[cfg(test)]
+extern crate insta;
 extern crate log;
 extern crate pretty_env_logger;
-use std::collections::HashMap;
-use std::collections::HashSet;
+extern crate serde_json;
+use aor2020::algorithm::Registry;
+use aor2020::day24::{join_directions, lay_floor, simulate, Bounds, Engine, Floor, Rule, Stats};
+use aor2020::hex::Position;
+use aor2020::output::write_error;
+use std::cmp::Reverse;
+use std::fs;
 use std::io;
 use std::io::prelude::*;
+use std::path::Path;
+use std::str::FromStr;
 
-#[derive(Debug, PartialEq, Eq)]
-enum Direction {
-    // note that N and S are deliberately missing.
-    NE,
-    E,
-    SE,
-    SW,
-    W,
-    NW,
-}
-
-impl Direction {
-    // Directions here are vectors on a modified cartesian coordinate
-    // plane.  The locations are hexagons.  Visualise the hexagons
-    // with corners pointing North/South.  The X-axis runs riectly
-    // East/West (through the centres of two of the sides).  If we
-    // move NE then NW, this should leave the X co-ordinate unchanged.
-    // Moving only NE, though, does change the X co-ordinate.  To
-    // obtain these properties while keeping things simple, we adopt
-    // the convention that moving E or W changes the X coordinate by
-    // 2, not 1.
-    //
-    // You can visualise this as if alternate rows of hexagons have X
-    // coordinates in between the X coordinates of the rows above and
-    // below.
-    fn delta(&self) -> (i32, i32) {
-        // (dx, dy)
-        match self {
-            Direction::NE => (1, 1),
-            Direction::E => (2, 0),
-            Direction::SE => (1, -1),
-            Direction::SW => (-1, -1),
-            Direction::W => (-2, 0),
-            Direction::NW => (-1, 1),
-        }
-    }
+/// The ways in which --stats can write the statistics of each day.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum StatsFormat {
+    Json,
+    Csv,
+}
+
+impl FromStr for StatsFormat {
+    type Err = String;
 
-    fn all() -> &'static [Direction; 6] {
-        &[
-            Direction::NE,
-            Direction::E,
-            Direction::SE,
-            Direction::SW,
-            Direction::W,
-            Direction::NW,
-        ]
-    }
-}
-
-fn split_directions(s: &str) -> Vec<Direction> {
-    // The capacity here is an overestimate, but by at most a factor
-    // of 2.
-    let mut result = Vec::with_capacity(s.len());
-    let mut saved: Option<char> = None;
-    for ch in s.chars() {
-        let (s, d) = get_direction(&saved, ch);
-        match d {
-            None => (),
-            Some(dir) => result.push(dir),
+    fn from_str(s: &str) -> Result<StatsFormat, String> {
+        match s {
+            "json" => Ok(StatsFormat::Json),
+            "csv" => Ok(StatsFormat::Csv),
+            _ => Err(format!("unknown stats format '{}'", s)),
         }
-        saved = s;
     }
-    result
 }
 
-#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
-struct Position {
-    x: i32,
-    y: i32,
-}
-
-impl Position {
-    fn delta(&self, d: &Direction) -> Position {
-        let delta = d.delta();
-        Position {
-            x: self.x + delta.0,
-            y: self.y + delta.1,
+/// Formats the statistics of each day (starting with day 0, the
+/// initial layout).
+fn format_stats(series: &[Stats], format: StatsFormat) -> String {
+    match format {
+        StatsFormat::Json => {
+            serde_json::to_string_pretty(series).expect("stats can always be serialized") + "\n"
+        }
+        StatsFormat::Csv => {
+            let mut result = String::from(
+                "day,black_tiles,min_x,max_x,min_y,max_y,white_to_black,black_to_white\n",
+            );
+            for (day, stats) in series.iter().enumerate() {
+                let bounds = match &stats.bounds {
+                    Some(b) => format!("{},{},{},{}", b.min_x, b.max_x, b.min_y, b.max_y),
+                    None => ",,,".to_string(),
+                };
+                result.push_str(&format!(
+                    "{},{},{},{},{}\n",
+                    day, stats.black_tiles, bounds, stats.white_to_black, stats.black_to_white
+                ));
+            }
+            result
         }
     }
 }
 
-struct Floor {
-    // The default state of a tile is white, so we only record the
-    // locations of the black tiles.
-    black_tiles: HashSet<Position>,
+/// A tile to find a path to, for the path command.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Target {
+    Tile(Position),
+    /// The black tile of the final floor farthest from the reference
+    /// tile (the one with the smallest coordinates, if there are
+    /// several).
+    Farthest,
+    /// The black tile of the final floor nearest to the reference
+    /// tile (again, the one with the smallest coordinates).
+    Nearest,
 }
 
-impl Floor {
-    fn new() -> Floor {
-        Floor {
-            black_tiles: HashSet::new(),
-        }
-    }
+impl FromStr for Target {
+    type Err = String;
 
-    fn flip(&mut self, p: Position) {
-        if self.black_tiles.contains(&p) {
-            self.black_tiles.remove(&p);
-        } else {
-            self.black_tiles.insert(p);
+    fn from_str(s: &str) -> Result<Target, String> {
+        match s {
+            "farthest" => Ok(Target::Farthest),
+            "nearest" => Ok(Target::Nearest),
+            _ => {
+                let bad = || format!("expected X,Y, farthest or nearest but got '{}'", s);
+                let (x, y) = s.split_once(',').ok_or_else(bad)?;
+                let p = Position {
+                    x: x.trim().parse().map_err(|_| bad())?,
+                    y: y.trim().parse().map_err(|_| bad())?,
+                };
+                if (p.x + p.y) % 2 != 0 {
+                    return Err(format!("{} is not a tile; x + y must be even", p));
+                }
+                Ok(Target::Tile(p))
+            }
         }
     }
+}
 
-    fn count_black_tiles(&self) -> usize {
-        self.black_tiles.len()
+impl Target {
+    fn needs_floor(&self) -> bool {
+        !matches!(self, Target::Tile(_))
     }
 
-    fn obey(&mut self, line: &str) {
-        let final_pos = split_directions(line)
-            .iter()
-            .fold(Position { x: 0, y: 0 }, |p, d| p.delta(d));
-        self.flip(final_pos)
+    fn find(&self, floor: Option<&Floor>) -> Result<Position, String> {
+        let origin = Position::origin();
+        let black = || {
+            floor
+                .map(|f| f.black_tiles().iter())
+                .expect("the floor is laid for farthest and nearest")
+        };
+        let found = match self {
+            Target::Tile(p) => return Ok(*p),
+            Target::Farthest => black().max_by_key(|p| (p.distance(&origin), Reverse(**p))),
+            Target::Nearest => black().min_by_key(|p| (p.distance(&origin), **p)),
+        };
+        found
+            .copied()
+            .ok_or_else(|| "there are no black tiles".to_string())
     }
+}
 
-    fn iterate(&self) -> Floor {
-        let mut count_of_black_neighbours: HashMap<Position, usize> = HashMap::new();
-        for current_black_tile_pos in &self.black_tiles {
-            for direction in Direction::all() {
-                let neighbour: Position = current_black_tile_pos.delta(direction);
-                (*count_of_black_neighbours.entry(neighbour).or_insert(0)) += 1;
-            }
-        }
-        Floor {
-            black_tiles: count_of_black_neighbours
-                .iter()
-                .filter_map(|(pos, count)| {
-                    let is_currently_black = self.black_tiles.contains(pos);
-                    match count {
-                        0 | 3 | 4 | 5 | 6 if is_currently_black => None, // turns white
-                        2 if !is_currently_black => Some(pos),           // turns black
-                        _ => {
-                            if is_currently_black {
-                                Some(pos) // stays black
-                            } else {
-                                None // turns white
-                            }
-                        }
-                    }
-                })
-                .copied()
-                .collect(),
-        }
-    }
+fn engines() -> Registry<Engine> {
+    Registry::for_day(24)
+        .register("sparse", "a set of the black tiles", Engine::Sparse)
+        .register("chunked", "one bit per tile, in chunks", Engine::Chunked)
 }
 
-fn get_direction(saved: &Option<char>, current: char) -> (Option<char>, Option<Direction>) {
-    match (saved, current) {
-        // Note that there is no "North" or "South" output, so both
-        // 'n' and 's' will always be followed by 'e' or 'w'.
-        (None, 'n') => (Some(current), None), // next letter is 'e' or 'w'
-        (None, 's') => (Some(current), None), // next letter is 'e' or 'w'
-        (Some('n'), 'e') => (None, Some(Direction::NE)),
-        (None, 'e') => (None, Some(Direction::E)),
-        (Some('s'), 'e') => (None, Some(Direction::SE)),
-        (Some('s'), 'w') => (None, Some(Direction::SW)),
-        (None, 'w') => (None, Some(Direction::W)),
-        (Some('n'), 'w') => (None, Some(Direction::NW)),
-        _ => {
-            panic!(
-                "get_direction: invalid state, saved={:?}, current={:?}",
-                saved, current
-            );
-        }
+/// Prints a shortest line of directions from the reference tile to
+/// each of `targets`.  Those which depend on the floor use the floor
+/// after the days given by `options`.
+fn show_paths(
+    input: impl BufRead,
+    targets: &[Target],
+    options: &Options,
+    out: &mut impl Write,
+) -> Result<(), String> {
+    let floor = if targets.iter().any(Target::needs_floor) {
+        let initial = lay_floor(&read_lines(input)?).map_err(|e| e.to_string())?;
+        let rule = options.rule.clone().unwrap_or_default();
+        let engine = options.engine.unwrap_or(Engine::Sparse);
+        Some(simulate(&initial, options.days.unwrap_or(100), &rule, engine).0)
+    } else {
+        None
+    };
+    for target in targets {
+        let tile = target.find(floor.as_ref())?;
+        writeln!(
+            out,
+            "{}: {}",
+            tile,
+            join_directions(&Position::origin().path_to(&tile))
+        )
+        .map_err(write_error)?;
     }
+    Ok(())
 }
 
-fn read_lines() -> Result<Vec<String>, String> {
+fn read_lines(input: impl BufRead) -> Result<Vec<String>, String> {
     let mut lines: Vec<String> = Vec::new();
-    for line_or_err in io::BufReader::new(io::stdin()).lines() {
+    for line_or_err in input.lines() {
         match line_or_err {
             Err(e) => {
                 return Err(format!("I/O error: {}", e));
@@ -185,24 +170,168 @@ fn read_lines() -> Result<Vec<String>, String> {
     Ok(lines)
 }
 
-fn run() -> Result<(), String> {
-    let lines = read_lines()?;
-    let mut floor: Floor = Floor::new();
-    for line in lines.iter() {
-        floor.obey(line);
+fn write_svg(floor: &Floor, bounds: &Bounds, path: &Path) -> Result<(), String> {
+    fs::write(path, floor.render_svg(bounds))
+        .map_err(|e| format!("failed to write {}: {}", path.display(), e))
+}
+
+fn self_test() -> Result<(), String> {
+    aor2020::hex::self_test()?;
+    aor2020::day24::self_test()?;
+    let mut floor = Floor::new();
+    floor.flip(Position::origin());
+    let series = vec![floor.stats(), floor.iterate(&Rule::default()).1];
+    floor.flip(Position::origin());
+    let expected = concat!(
+        "day,black_tiles,min_x,max_x,min_y,max_y,white_to_black,black_to_white\n",
+        "0,1,0,0,0,0,0,0\n",
+        "1,0,,,,,0,1\n"
+    );
+    let got = format_stats(&series, StatsFormat::Csv);
+    if got != expected {
+        return Err(format!("FAIL: expected CSV\n{}but got\n{}", expected, got));
+    }
+    for (target, expected) in &[
+        ("-3,1", Ok(Target::Tile(Position { x: -3, y: 1 }))),
+        ("farthest", Ok(Target::Farthest)),
+        ("2,1", Err("(2,1) is not a tile; x + y must be even")),
+        ("2", Err("expected X,Y, farthest or nearest but got '2'")),
+    ] {
+        let got = target.parse::<Target>();
+        if got.as_ref().copied().map_err(String::as_str) != *expected {
+            return Err(format!("FAIL: {} was parsed as {:?}", target, got));
+        }
     }
-    println!(
+    floor.flip(Position { x: 4, y: 2 });
+    floor.flip(Position { x: -5, y: 1 });
+    floor.flip(Position { x: 1, y: -1 });
+    match (
+        Target::Farthest.find(Some(&floor)),
+        Target::Nearest.find(Some(&floor)),
+    ) {
+        (Ok(far), Ok(near))
+            if far == (Position { x: -5, y: 1 }) && near == (Position { x: 1, y: -1 }) => {}
+        other => return Err(format!("FAIL: found {:?}", other)),
+    }
+    Ok(())
+}
+
+#[derive(Default)]
+struct Options {
+    crosscheck: bool,
+    days: Option<usize>,
+    engine: Option<Engine>,
+    frames: Option<String>,
+    path: bool,
+    render: Option<String>,
+    rule: Option<Rule>,
+    stats: Option<String>,
+    stats_format: Option<StatsFormat>,
+    targets: Vec<Target>,
+}
+
+fn parse_args() -> Result<Options, String> {
+    let mut options = Options::default();
+    let mut args = std::env::args().skip(1);
+    while let Some(arg) = args.next() {
+        let mut value = || {
+            args.next()
+                .ok_or_else(|| format!("option {} requires an argument", arg))
+        };
+        match arg.as_str() {
+            "crosscheck" => options.crosscheck = true,
+            "path" => options.path = true,
+            "--days" => {
+                let v = value()?;
+                options.days = Some(
+                    v.parse()
+                        .map_err(|e| format!("invalid day count '{}': {}", v, e))?,
+                );
+            }
+            "--algorithm" | "--engine" => options.engine = Some(engines().get(&value()?)?.solver),
+            "--frames" => options.frames = Some(value()?),
+            "--render" => options.render = Some(value()?),
+            "--rule" => options.rule = Some(value()?.parse()?),
+            "--stats" => options.stats = Some(value()?),
+            "--stats-format" => options.stats_format = Some(value()?.parse()?),
+            // Targets such as "-2,0" look a bit like options.
+            _ if options.path && !arg.starts_with("--") => options.targets.push(arg.parse()?),
+            _ => return Err(format!("unknown option {}", arg)),
+        }
+    }
+    if options.path && options.targets.is_empty() {
+        return Err("path needs at least one target (X,Y, farthest or nearest)".to_string());
+    }
+    Ok(options)
+}
+
+fn run(input: impl BufRead, options: &Options, out: &mut impl Write) -> Result<(), String> {
+    if options.path {
+        return show_paths(input, &options.targets, options, out);
+    }
+    let lines = read_lines(input)?;
+    let mut floor = lay_floor(&lines).map_err(|e| e.to_string())?;
+    writeln!(
+        out,
         "Part 1: number of black-side-up tiles: {}",
         floor.count_black_tiles()
-    );
+    )
+    .map_err(write_error)?;
 
-    for _ in 0..100 {
-        floor = floor.iterate();
+    let days = options.days.unwrap_or(100);
+    let rule = options.rule.clone().unwrap_or_default();
+    if options.crosscheck {
+        let report = engines().crosscheck(|engine| {
+            Ok(simulate(&floor, days, &rule, *engine).0.count_black_tiles())
+        })?;
+        writeln!(out, "Part 2:\n{}", report).map_err(write_error)?;
+        return Ok(());
+    }
+    let engine = options.engine.unwrap_or(Engine::Sparse);
+    let mut series = vec![floor.stats()];
+    match &options.frames {
+        None => {
+            let (last, stats) = simulate(&floor, days, &rule, engine);
+            floor = last;
+            series.extend(stats);
+        }
+        Some(dir) => {
+            // Tiles spread by at most one tile per day, so every
+            // frame can cover the same area.
+            let envelope = floor
+                .bounds()
+                .unwrap_or_else(|| Bounds::around(Position::origin()))
+                .grown(days as u32);
+            fs::create_dir_all(dir).map_err(|e| format!("failed to create {}: {}", dir, e))?;
+            write_svg(&floor, &envelope, &Path::new(dir).join("day-000.svg"))?;
+            for day in 1..=days {
+                let (next, stats) = simulate(&floor, 1, &rule, engine);
+                floor = next;
+                series.extend(stats);
+                let filename = Path::new(dir).join(format!("day-{:03}.svg", day));
+                write_svg(&floor, &envelope, &filename)?;
+            }
+        }
     }
-    println!(
-        "Part 2: number of black-side-up tiles: {}",
+    writeln!(
+        out,
+        "Part 2: number of black-side-up tiles after {} days with rule {}: {}",
+        days,
+        rule,
         floor.count_black_tiles()
-    );
+    )
+    .map_err(write_error)?;
+    if let Some(filename) = &options.render {
+        let bounds = floor
+            .bounds()
+            .unwrap_or_else(|| Bounds::around(Position::origin()));
+        write_svg(&floor, &bounds, Path::new(filename))?;
+    }
+    if let Some(filename) = &options.stats {
+        let format = options.stats_format.unwrap_or(StatsFormat::Json);
+        fs::write(filename, format_stats(&series, format))
+            .map_err(|e| format!("failed to write {}: {}", filename, e))?;
+    }
     Ok(())
 }
 
@@ -210,7 +339,11 @@ fn main() {
     // the env logger is configured with $RUST_LOG.
     // For example RUST_LOG=debug day24
     pretty_env_logger::init();
-    std::process::exit(match run() {
+    let result = parse_args().and_then(|options| {
+        self_test()?;
+        run(io::stdin().lock(), &options, &mut io::stdout())
+    });
+    std::process::exit(match result {
         Ok(_) => 0,
         Err(err) => {
             eprintln!("error: {}", err);
@@ -218,3 +351,39 @@ fn main() {
         }
     });
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn output(input: &str, options: &Options) -> String {
+        let mut out = Vec::new();
+        run(input.as_bytes(), options, &mut out).unwrap();
+        String::from_utf8(out).unwrap()
+    }
+
+    #[test]
+    fn sample() {
+        insta::assert_snapshot!(output(
+            include_str!("../../samples/day24.txt"),
+            &Options::default()
+        ));
+    }
+
+    #[test]
+    fn path() {
+        insta::assert_snapshot!(output(
+            include_str!("../../samples/day24.txt"),
+            &Options {
+                path: true,
+                targets: vec![
+                    "6,-4".parse().unwrap(),
+                    "-3,1".parse().unwrap(),
+                    "farthest".parse().unwrap(),
+                    "nearest".parse().unwrap(),
+                ],
+                ..Options::default()
+            }
+        ));
+    }
+}
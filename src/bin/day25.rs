@@ -1,11 +1,17 @@
+extern crate aor2020;
+#[cfg(test)]
+extern crate insta;
 extern crate log;
-extern crate mod_exp;
 extern crate pretty_env_logger;
 extern crate thiserror;
 
-use mod_exp::mod_exp;
+use aor2020::algorithm::Registry;
+use aor2020::modular::{pow_mod, Solver};
+use aor2020::rng::Lcg;
 use std::io;
 use std::io::Read;
+use std::io::Write;
+use std::time::{Duration, Instant};
 use thiserror::Error;
 
 type Key = usize;
@@ -18,13 +24,28 @@ pub enum MyError {
     #[error("input is invalid: {0}")]
     InvalidInput(String),
 
+    #[error("{0}")]
+    InvalidOption(String),
+
+    #[error("no loop size turns subject number {subject} into public key {key}")]
+    NoLoopSize { subject: Key, key: Key },
+
+    #[error("crosscheck failed: {0}")]
+    Crosscheck(String),
+
+    #[error("simulated handshake {handshake} failed: {reason}")]
+    HandshakeFailed { handshake: usize, reason: String },
+
     #[error("Read error")]
     ReadError { source: std::io::Error },
+
+    #[error("Write error")]
+    WriteError { source: std::io::Error },
 }
 
-fn read_input() -> Result<Vec<Key>, MyError> {
+fn read_input(mut input: impl Read) -> Result<Vec<Key>, MyError> {
     let mut buffer = String::new();
-    match io::stdin().read_to_string(&mut buffer) {
+    match input.read_to_string(&mut buffer) {
         Ok(_) => (),
         Err(source) => {
             return Err(MyError::ReadError { source });
@@ -44,34 +65,190 @@ fn read_input() -> Result<Vec<Key>, MyError> {
     Ok(public_keys)
 }
 
-// Finds r such that 7^r = p mod 20201227
-fn find_loop_num(p: Key) -> usize {
-    let mut v = 1;
-    for r in 0.. {
-        // r is the loop number
-        if v == p {
-            return r;
+/// The modulus used by the handshake in the puzzle.
+const MODULUS: Key = 20201227;
+
+/// The subject number used to make public keys in the puzzle.
+const SUBJECT: Key = 7;
+
+// Finds r such that subject^r = p mod modulus
+fn find_loop_num(p: Key, options: &Options) -> Result<usize, MyError> {
+    let subject = options.subject.unwrap_or(SUBJECT);
+    let solver = options.solver.unwrap_or(Solver::PohligHellman);
+    solver
+        .log(
+            subject as u64,
+            p as u64,
+            options.modulus.unwrap_or(MODULUS) as u64,
+        )
+        .map(|r| r as usize)
+        .ok_or(MyError::NoLoopSize { subject, key: p })
+}
+
+// Finds p = k^r mod modulus
+fn make_private_key(k: Key, r: usize, options: &Options) -> Key {
+    pow_mod(
+        k as u64,
+        r as u64,
+        options.modulus.unwrap_or(MODULUS) as u64,
+    ) as Key
+}
+
+fn solvers() -> Registry<Solver> {
+    Registry::for_day(25)
+        .register("brute-force", "tries each loop size", Solver::BruteForce)
+        .register("bsgs", "baby-step giant-step", Solver::BabyStepGiantStep)
+        .register(
+            "pohlig-hellman",
+            "works modulo each prime factor of p-1",
+            Solver::PohligHellman,
+        )
+}
+
+/// Performs `count` handshakes between a card and a door with random
+/// loop sizes, checking that both sides derive the same encryption
+/// key.  Then, like an eavesdropper, recovers the loop sizes from the
+/// public keys with the chosen solver and checks that they give the
+/// same encryption key too.
+fn simulate(count: usize, rng: &mut Lcg, options: &Options) -> Result<Duration, MyError> {
+    let modulus = options.modulus.unwrap_or(MODULUS);
+    let subject = options.subject.unwrap_or(SUBJECT);
+    let mut solving_time = Duration::default();
+    for handshake in 1..=count {
+        let fail = |reason: String| MyError::HandshakeFailed { handshake, reason };
+        // Loop sizes are below the modulus (and below 2^31, which is
+        // as far as Lcg goes).
+        let card_loop = 1 + rng.below(modulus - 1);
+        let door_loop = 1 + rng.below(modulus - 1);
+        let card_public = make_private_key(subject, card_loop, options);
+        let door_public = make_private_key(subject, door_loop, options);
+        let card_key = make_private_key(door_public, card_loop, options);
+        let door_key = make_private_key(card_public, door_loop, options);
+        if card_key != door_key {
+            return Err(fail(format!(
+                "the card derived encryption key {} but the door derived {}",
+                card_key, door_key
+            )));
+        }
+        let start = Instant::now();
+        let found_card_loop = find_loop_num(card_public, options)?;
+        let found_door_loop = find_loop_num(door_public, options)?;
+        solving_time += start.elapsed();
+        let cracked = make_private_key(door_public, found_card_loop, options);
+        if cracked != card_key
+            || make_private_key(card_public, found_door_loop, options) != card_key
+        {
+            return Err(fail(format!(
+                "loop sizes {} and {} were found instead of {} and {}, giving encryption key {} instead of {}",
+                found_card_loop, found_door_loop, card_loop, door_loop, cracked, card_key
+            )));
         }
-        v = (v * 7) % 20201227;
     }
-    panic!("infinite loop terminated");
+    Ok(solving_time)
 }
 
-// Finds p = k^r mod 20201227
-fn make_private_key(k: Key, r: usize) -> Key {
-    mod_exp(k, r, 20201227)
+fn self_test() -> Result<(), MyError> {
+    aor2020::modular::self_test().map_err(MyError::InvalidInput)?;
+    let mut rng = Lcg::new(1);
+    for solver in solvers().algorithms() {
+        let options = Options {
+            modulus: Some(1009),
+            solver: Some(solver.solver),
+            ..Options::default()
+        };
+        simulate(20, &mut rng, &options)?;
+    }
+    Ok(())
+}
+
+#[derive(Default)]
+struct Options {
+    crosscheck: bool,
+    /// The modulus of the handshake, which must be prime.
+    modulus: Option<Key>,
+    seed: Option<u64>,
+    simulate: Option<usize>,
+    solver: Option<Solver>,
+    subject: Option<Key>,
+}
+
+fn parse_args() -> Result<Options, String> {
+    let mut options = Options::default();
+    let mut args = std::env::args().skip(1);
+    while let Some(arg) = args.next() {
+        let mut value = || {
+            args.next()
+                .ok_or_else(|| format!("option {} requires an argument", arg))
+        };
+        let mut number = || -> Result<Key, String> {
+            let v = value()?;
+            v.parse()
+                .map_err(|e| format!("invalid number '{}': {}", v, e))
+        };
+        match arg.as_str() {
+            "crosscheck" => options.crosscheck = true,
+            "simulate" => options.simulate = Some(number()?),
+            "--modulus" => options.modulus = Some(number()?),
+            "--seed" => {
+                let v = value()?;
+                options.seed = Some(
+                    v.parse()
+                        .map_err(|e| format!("invalid seed '{}': {}", v, e))?,
+                );
+            }
+            "--algorithm" | "--solver" => options.solver = Some(solvers().get(&value()?)?.solver),
+            "--subject" => options.subject = Some(number()?),
+            _ => return Err(format!("unknown option {}", arg)),
+        }
+    }
+    Ok(options)
 }
 
-fn run() -> Result<(), MyError> {
-    let public_keys = read_input()?;
+fn run(input: impl Read, options: &Options, out: &mut impl Write) -> Result<(), MyError> {
+    if let Some(count) = options.simulate {
+        let mut rng = Lcg::new(options.seed.unwrap_or(1));
+        let solving_time = simulate(count, &mut rng, options)?;
+        writeln!(
+            out,
+            "{} handshakes with modulus {} and subject number {} succeeded; {} recovered every encryption key in {:?}",
+            count,
+            options.modulus.unwrap_or(MODULUS),
+            options.subject.unwrap_or(SUBJECT),
+            options.solver.unwrap_or(Solver::PohligHellman),
+            solving_time
+        )
+        .map_err(|source| MyError::WriteError { source })?;
+        return Ok(());
+    }
+    let public_keys = read_input(input)?;
+    if options.crosscheck {
+        let report = solvers()
+            .crosscheck(|solver| {
+                let options = Options {
+                    solver: Some(*solver),
+                    ..*options
+                };
+                let loop_nums = public_keys
+                    .iter()
+                    .map(|k| find_loop_num(*k, &options).map(|n| n.to_string()))
+                    .collect::<Result<Vec<String>, MyError>>()
+                    .map_err(|e| e.to_string())?;
+                Ok(format!("loop numbers {}", loop_nums.join(", ")))
+            })
+            .map_err(MyError::Crosscheck)?;
+        writeln!(out, "{}", report).map_err(|source| MyError::WriteError { source })?;
+        return Ok(());
+    }
     let keys_and_loop_numbers: Vec<(Key, usize)> = public_keys
         .iter()
-        .map(|k| (*k, find_loop_num(*k)))
-        .collect();
+        .map(|k| Ok((*k, find_loop_num(*k, options)?)))
+        .collect::<Result<_, MyError>>()?;
     for (pk, loop_num) in &keys_and_loop_numbers {
-        println!("public key is  {:>8}", pk);
-        println!("loop number is {:>8}", loop_num);
-        println!();
+        writeln!(out, "public key is  {:>8}", pk)
+            .map_err(|source| MyError::WriteError { source })?;
+        writeln!(out, "loop number is {:>8}", loop_num)
+            .map_err(|source| MyError::WriteError { source })?;
+        writeln!(out).map_err(|source| MyError::WriteError { source })?;
     }
     match keys_and_loop_numbers.len() {
         2 => (),
@@ -82,9 +259,18 @@ fn run() -> Result<(), MyError> {
             )));
         }
     }
-    let ek0 = make_private_key(keys_and_loop_numbers[0].0, keys_and_loop_numbers[1].1);
-    println!("encryption key is {:>8}", ek0);
-    let ek1 = make_private_key(keys_and_loop_numbers[1].0, keys_and_loop_numbers[0].1);
+    let ek0 = make_private_key(
+        keys_and_loop_numbers[0].0,
+        keys_and_loop_numbers[1].1,
+        options,
+    );
+    writeln!(out, "encryption key is {:>8}", ek0)
+        .map_err(|source| MyError::WriteError { source })?;
+    let ek1 = make_private_key(
+        keys_and_loop_numbers[1].0,
+        keys_and_loop_numbers[0].1,
+        options,
+    );
     assert_eq!(ek0, ek1);
     Ok(())
 }
@@ -93,7 +279,13 @@ fn main() {
     // the env logger is configured with $RUST_LOG.
     // For example RUST_LOG=debug day24
     pretty_env_logger::init();
-    std::process::exit(match run() {
+    let result = parse_args()
+        .map_err(MyError::InvalidOption)
+        .and_then(|options| {
+            self_test()?;
+            run(io::stdin().lock(), &options, &mut io::stdout())
+        });
+    std::process::exit(match result {
         Ok(_) => 0,
         Err(err) => {
             eprintln!("error: {}", err);
@@ -101,3 +293,19 @@ fn main() {
         }
     });
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn output(input: &str, options: &Options) -> String {
+        let mut out = Vec::new();
+        run(input.as_bytes(), options, &mut out).unwrap();
+        String::from_utf8(out).unwrap()
+    }
+
+    #[test]
+    fn sample() {
+        insta::assert_snapshot!(output("5764801\n17807724\n", &Options::default()));
+    }
+}
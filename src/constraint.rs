@@ -0,0 +1,415 @@
+//! Giving each of a set of variables a different value.
+//!
+//! Several puzzles come down to this: on day 16 each ticket field is
+//! at a different position, and on day 21 each allergen is in a
+//! different ingredient.  Each variable has a set of candidate values,
+//! and a `Problem` narrows these down by propagation, a round at a
+//! time:
+//!
+//! * a variable with only one candidate takes that value, and then no
+//!   other variable can take it;
+//! * when there are as many variables as values, every value is taken,
+//!   so a value which is a candidate for only one variable is taken by
+//!   that one.
+//!
+//! When neither rule applies, each pairing of a variable and a value
+//! which is part of no complete assignment is ruled out, by looking
+//! for matchings in the bipartite graph of candidates.  If that rules
+//! nothing out either, every remaining candidate is part of some
+//! complete assignment, so there is more than one of them, and
+//! `Problem::solutions` can list them by backtracking.
+use std::collections::BTreeSet;
+use thiserror::Error;
+
+/// How a variable came to take a value.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Rule {
+    /// The value was the only candidate for the variable.
+    OnlyValue,
+    /// The variable was the only one which could take the value.
+    OnlyVariable,
+}
+
+/// Why a variable cannot take a value.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Cause {
+    /// This other variable took the value.
+    ValueTaken(usize),
+    /// The variable took this other value.
+    VariableAssigned(usize),
+    /// No complete assignment gives the variable this value.
+    Unmatchable,
+}
+
+/// A conclusion reached by `Problem::propagate`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Deduction {
+    /// In round `round` (counting from 1), `var` took `value`.
+    Assigned {
+        var: usize,
+        value: usize,
+        round: usize,
+        rule: Rule,
+    },
+    /// `var` cannot take `value`.
+    Eliminated {
+        var: usize,
+        value: usize,
+        cause: Cause,
+    },
+}
+
+#[derive(Error, Debug, Clone, Copy, PartialEq, Eq)]
+#[error("the variables cannot all take different values")]
+pub struct Unsatisfiable;
+
+/// The complete assignments found by `Problem::solutions`.
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct Solutions {
+    /// The total number of complete assignments.
+    pub count: usize,
+    /// The value of each variable, in each of the assignments kept.
+    pub assignments: Vec<Vec<usize>>,
+}
+
+pub struct Problem {
+    nvalues: usize,
+    candidates: Vec<BTreeSet<usize>>,
+    assigned: Vec<Option<usize>>,
+    /// What propagation concluded, in order.
+    deductions: Vec<Deduction>,
+}
+
+/// Finds a value for each variable, all different, from among its
+/// candidates, using augmenting paths.  Returns None if there is no
+/// such assignment.
+fn find_matching(candidates: &[BTreeSet<usize>], nvalues: usize) -> Option<Vec<usize>> {
+    // Looks for a way to give `var` a value, perhaps by moving the
+    // variables which already have values to other values.
+    fn augment(
+        var: usize,
+        candidates: &[BTreeSet<usize>],
+        owner: &mut [Option<usize>],
+        visited: &mut [bool],
+    ) -> bool {
+        for value in candidates[var].iter() {
+            if visited[*value] {
+                continue;
+            }
+            visited[*value] = true;
+            let free = match owner[*value] {
+                None => true,
+                Some(other) => augment(other, candidates, owner, visited),
+            };
+            if free {
+                owner[*value] = Some(var);
+                return true;
+            }
+        }
+        false
+    }
+
+    let mut owner: Vec<Option<usize>> = vec![None; nvalues];
+    for var in 0..candidates.len() {
+        if !augment(var, candidates, &mut owner, &mut vec![false; nvalues]) {
+            return None;
+        }
+    }
+    let mut result = vec![0; candidates.len()];
+    for (value, var) in owner.iter().enumerate() {
+        if let Some(var) = var {
+            result[*var] = value;
+        }
+    }
+    Some(result)
+}
+
+impl Problem {
+    /// Makes a problem in which `candidates[v]` is the set of values
+    /// (each less than `nvalues`) which variable v could take.
+    pub fn new(nvalues: usize, candidates: Vec<BTreeSet<usize>>) -> Problem {
+        assert!(candidates.iter().flatten().all(|value| *value < nvalues));
+        let assigned = vec![None; candidates.len()];
+        Problem {
+            nvalues,
+            candidates,
+            assigned,
+            deductions: Vec::new(),
+        }
+    }
+
+    pub fn candidates(&self, var: usize) -> &BTreeSet<usize> {
+        &self.candidates[var]
+    }
+
+    /// The value of `var`, if propagation has found it.
+    pub fn value_of(&self, var: usize) -> Option<usize> {
+        self.assigned[var]
+    }
+
+    pub fn is_solved(&self) -> bool {
+        self.assigned.iter().all(Option::is_some)
+    }
+
+    pub fn deductions(&self) -> &[Deduction] {
+        &self.deductions
+    }
+
+    fn eliminate(&mut self, var: usize, value: usize, cause: Cause) {
+        if self.candidates[var].remove(&value) {
+            self.deductions
+                .push(Deduction::Eliminated { var, value, cause });
+        }
+    }
+
+    fn assign(&mut self, var: usize, value: usize, round: usize, rule: Rule) {
+        self.assigned[var] = Some(value);
+        self.deductions.push(Deduction::Assigned {
+            var,
+            value,
+            round,
+            rule,
+        });
+        let others: Vec<usize> = self.candidates[var]
+            .iter()
+            .filter(|v| **v != value)
+            .copied()
+            .collect();
+        for other in others {
+            self.eliminate(var, other, Cause::VariableAssigned(value));
+        }
+        for other in 0..self.candidates.len() {
+            if other != var {
+                self.eliminate(other, value, Cause::ValueTaken(var));
+            }
+        }
+    }
+
+    /// Finds the unassigned variables whose values are forced by
+    /// either of the two rules.
+    fn singles(&self) -> Vec<(usize, usize, Rule)> {
+        let mut result = Vec::new();
+        let mut found = vec![false; self.candidates.len()];
+        for (var, candidates) in self.candidates.iter().enumerate() {
+            if self.assigned[var].is_none() && candidates.len() == 1 {
+                result.push((var, *candidates.iter().next().unwrap(), Rule::OnlyValue));
+                found[var] = true;
+            }
+        }
+        if self.candidates.len() == self.nvalues {
+            for value in 0..self.nvalues {
+                let mut takers =
+                    (0..self.candidates.len()).filter(|var| self.candidates[*var].contains(&value));
+                if let (Some(var), None) = (takers.next(), takers.next()) {
+                    if self.assigned[var].is_none() && !found[var] {
+                        result.push((var, value, Rule::OnlyVariable));
+                        found[var] = true;
+                    }
+                }
+            }
+        }
+        result
+    }
+
+    /// Rules out each candidate which is part of no complete
+    /// assignment.  Returns true if it ruled anything out.
+    fn exclude_unmatchable(&mut self) -> bool {
+        let matching = match find_matching(&self.candidates, self.nvalues) {
+            Some(m) => m,
+            None => return false,
+        };
+        let mut impossible: Vec<(usize, usize)> = Vec::new();
+        for (var, candidates) in self.candidates.iter().enumerate() {
+            for value in candidates.iter() {
+                if *value == matching[var] {
+                    continue;
+                }
+                // Is there an assignment which gives var this value?
+                let forced: Vec<BTreeSet<usize>> = self
+                    .candidates
+                    .iter()
+                    .enumerate()
+                    .map(|(other, others)| {
+                        if other == var {
+                            std::iter::once(*value).collect()
+                        } else {
+                            others.iter().filter(|v| *v != value).copied().collect()
+                        }
+                    })
+                    .collect();
+                if find_matching(&forced, self.nvalues).is_none() {
+                    impossible.push((var, *value));
+                }
+            }
+        }
+        for (var, value) in impossible.iter() {
+            self.eliminate(*var, *value, Cause::Unmatchable);
+        }
+        !impossible.is_empty()
+    }
+
+    /// Narrows down the candidates as far as possible.  Afterwards
+    /// either every variable has a value, or there is more than one
+    /// complete assignment.
+    pub fn propagate(&mut self) -> Result<(), Unsatisfiable> {
+        if find_matching(&self.candidates, self.nvalues).is_none() {
+            return Err(Unsatisfiable);
+        }
+        let mut round = 0;
+        while !self.is_solved() {
+            round += 1;
+            let singles = self.singles();
+            // The rules are quick, so we only fall back on matching
+            // when they get stuck.
+            if singles.is_empty() && !self.exclude_unmatchable() {
+                break;
+            }
+            for (var, value, rule) in singles {
+                if !self.candidates[var].contains(&value) {
+                    return Err(Unsatisfiable);
+                }
+                self.assign(var, value, round, rule);
+            }
+        }
+        Ok(())
+    }
+
+    /// Finds every complete assignment allowed by the candidates,
+    /// keeping at most `limit` of them (but counting them all).
+    pub fn solutions(&self, limit: Option<usize>) -> Solutions {
+        fn search(
+            order: &[usize],
+            candidates: &[BTreeSet<usize>],
+            used: &mut [bool],
+            current: &mut [usize],
+            limit: Option<usize>,
+            result: &mut Solutions,
+        ) {
+            match order.split_first() {
+                None => {
+                    result.count += 1;
+                    if limit.map_or(true, |n| result.assignments.len() < n) {
+                        result.assignments.push(current.to_vec());
+                    }
+                }
+                Some((var, rest)) => {
+                    for value in candidates[*var].iter() {
+                        if !used[*value] {
+                            used[*value] = true;
+                            current[*var] = *value;
+                            search(rest, candidates, used, current, limit, result);
+                            used[*value] = false;
+                        }
+                    }
+                }
+            }
+        }
+
+        // Trying the most constrained variables first keeps the search
+        // small.
+        let mut order: Vec<usize> = (0..self.candidates.len()).collect();
+        order.sort_by_key(|var| self.candidates[*var].len());
+        let mut result = Solutions::default();
+        search(
+            &order,
+            &self.candidates,
+            &mut vec![false; self.nvalues],
+            &mut vec![0; self.candidates.len()],
+            limit,
+            &mut result,
+        );
+        result
+    }
+}
+
+pub fn self_test() -> Result<(), String> {
+    let sets = |lists: &[&[usize]]| -> Vec<BTreeSet<usize>> {
+        lists
+            .iter()
+            .map(|list| list.iter().copied().collect())
+            .collect()
+    };
+    // The example from part 2 of day 16: as many fields as positions,
+    // so position 0 must be for the only field which could be there.
+    let mut problem = Problem::new(3, sets(&[&[1, 2], &[0, 1, 2], &[2]]));
+    problem.propagate().map_err(|e| format!("FAIL: {}", e))?;
+    let values: Vec<Option<usize>> = (0..3).map(|var| problem.value_of(var)).collect();
+    if values != [Some(1), Some(0), Some(2)] {
+        return Err(format!("FAIL: found values {:?}", values));
+    }
+    let expected = [
+        Deduction::Assigned {
+            var: 2,
+            value: 2,
+            round: 1,
+            rule: Rule::OnlyValue,
+        },
+        Deduction::Eliminated {
+            var: 0,
+            value: 2,
+            cause: Cause::ValueTaken(2),
+        },
+        Deduction::Eliminated {
+            var: 1,
+            value: 2,
+            cause: Cause::ValueTaken(2),
+        },
+        Deduction::Assigned {
+            var: 1,
+            value: 0,
+            round: 1,
+            rule: Rule::OnlyVariable,
+        },
+        Deduction::Eliminated {
+            var: 1,
+            value: 1,
+            cause: Cause::VariableAssigned(0),
+        },
+        Deduction::Assigned {
+            var: 0,
+            value: 1,
+            round: 2,
+            rule: Rule::OnlyValue,
+        },
+    ];
+    if problem.deductions() != expected {
+        return Err(format!("FAIL: wrong deductions {:?}", problem.deductions()));
+    }
+
+    // Neither rule applies here, but variable 2 cannot take either of
+    // the values which variables 0 and 1 need.  Variables 0 and 1 can
+    // have their values either way round.
+    let mut problem = Problem::new(5, sets(&[&[0, 1], &[0, 1], &[0, 1, 2], &[2, 3]]));
+    if !problem.singles().is_empty() {
+        return Err("FAIL: the rules should not apply".to_string());
+    }
+    problem.propagate().map_err(|e| format!("FAIL: {}", e))?;
+    if problem.is_solved() || problem.value_of(2) != Some(2) || problem.value_of(3) != Some(3) {
+        return Err("FAIL: wrong conclusions from matching".to_string());
+    }
+    if !problem.deductions().contains(&Deduction::Eliminated {
+        var: 2,
+        value: 0,
+        cause: Cause::Unmatchable,
+    }) {
+        return Err("FAIL: matching did not rule out value 0 for variable 2".to_string());
+    }
+    let expected = Solutions {
+        count: 2,
+        assignments: vec![vec![0, 1, 2, 3], vec![1, 0, 2, 3]],
+    };
+    if problem.solutions(None) != expected {
+        return Err(format!("FAIL: found {:?}", problem.solutions(None)));
+    }
+    let limited = problem.solutions(Some(1));
+    if limited.count != 2 || limited.assignments.len() != 1 {
+        return Err(format!("FAIL: limit not respected: {:?}", limited));
+    }
+
+    for impossible in &[sets(&[&[0], &[0]]), sets(&[&[0, 1], &[], &[2]])] {
+        if Problem::new(3, impossible.clone()).propagate().is_ok() {
+            return Err(format!("FAIL: {:?} should be unsatisfiable", impossible));
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,217 @@
+//! Binary Boarding (day 5): boarding passes are seat numbers written
+//! in binary, with F/B for the bits of the row and L/R for the bits of
+//! the column.  The plane in the puzzle has 7 bits of row and 3 of
+//! column, but other layouts are allowed.
+use thiserror::Error;
+
+#[derive(Error, Debug)]
+pub enum SeatError {
+    #[error("empty starting range")]
+    EmptyStartingRange,
+    #[error("input is empty")]
+    NoInput,
+    #[error("input is invalid; {0}")]
+    InvalidInput(String),
+    #[error("there is no seat {0} on this plane")]
+    NoSuchSeat(String),
+    #[error("Read error")]
+    ReadError { source: std::io::Error },
+}
+
+/// The number of direction characters for the row and for the column.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Layout {
+    row_width: u32,
+    column_width: u32,
+}
+
+impl Default for Layout {
+    fn default() -> Layout {
+        Layout {
+            row_width: 7,
+            column_width: 3,
+        }
+    }
+}
+
+fn binary_search(
+    mut lower_incl: i32,
+    mut upper_excl: i32,
+    lower_directive: char,
+    upper_directive: char,
+    choices: &str,
+) -> Result<i32, SeatError> {
+    if lower_incl >= upper_excl {
+        return Err(SeatError::EmptyStartingRange);
+    }
+    for choice in choices.chars() {
+        if upper_excl == lower_incl + 1 {
+            return Err(SeatError::InvalidInput(
+                "too many seat directions".to_string(),
+            ));
+        }
+        let mid = lower_incl + (upper_excl - lower_incl) / 2;
+        if choice == lower_directive {
+            upper_excl = mid
+        } else if choice == upper_directive {
+            lower_incl = mid
+        } else {
+            return Err(SeatError::InvalidInput(format!(
+                "invalid direction character '{}'",
+                choice
+            )));
+        }
+    }
+    if upper_excl == lower_incl + 1 {
+        Ok(lower_incl)
+    } else {
+        Err(SeatError::InvalidInput(
+            "insufficient seat directions".to_string(),
+        ))
+    }
+}
+
+/// The inverse of `binary_search`: writes `value` as `width` binary
+/// digits, most significant first.
+fn write_directions(out: &mut String, value: i32, width: u32, zero: char, one: char) {
+    for bit in (0..width).rev() {
+        out.push(if (value >> bit) & 1 == 0 { zero } else { one });
+    }
+}
+
+impl Layout {
+    /// Seat IDs have to fit in an i32, so there can be at most 30 bits
+    /// of row and column between them.
+    pub fn new(row_width: u32, column_width: u32) -> Result<Layout, SeatError> {
+        if row_width + column_width > 30 {
+            return Err(SeatError::InvalidInput(format!(
+                "{} row bits and {} column bits is too many",
+                row_width, column_width
+            )));
+        }
+        Ok(Layout {
+            row_width,
+            column_width,
+        })
+    }
+
+    pub fn row_width(&self) -> u32 {
+        self.row_width
+    }
+
+    pub fn column_width(&self) -> u32 {
+        self.column_width
+    }
+
+    pub fn rows(&self) -> i32 {
+        1 << self.row_width
+    }
+
+    pub fn columns(&self) -> i32 {
+        1 << self.column_width
+    }
+
+    pub fn seat_id(&self, row: i32, column: i32) -> i32 {
+        row * self.columns() + column
+    }
+
+    /// Converts a boarding pass into a seat ID.
+    pub fn decode(&self, directions: &str) -> Result<i32, SeatError> {
+        let split = directions
+            .char_indices()
+            .nth(self.row_width as usize)
+            .map(|(i, _)| i)
+            .unwrap_or_else(|| directions.len());
+        let r = binary_search(0, self.rows(), 'F', 'B', &directions[..split])?;
+        let c = binary_search(0, self.columns(), 'L', 'R', &directions[split..])?;
+        Ok(self.seat_id(r, c))
+    }
+
+    /// Converts a seat ID into a boarding pass.
+    pub fn encode(&self, id: i32) -> Result<String, SeatError> {
+        if id < 0 || id >= self.rows() * self.columns() {
+            return Err(SeatError::NoSuchSeat(id.to_string()));
+        }
+        self.encode_seat(id / self.columns(), id % self.columns())
+    }
+
+    /// Converts a row and column into a boarding pass.
+    pub fn encode_seat(&self, row: i32, column: i32) -> Result<String, SeatError> {
+        if row < 0 || row >= self.rows() || column < 0 || column >= self.columns() {
+            return Err(SeatError::NoSuchSeat(format!("{},{}", row, column)));
+        }
+        let mut pass = String::with_capacity((self.row_width + self.column_width) as usize);
+        write_directions(&mut pass, row, self.row_width, 'F', 'B');
+        write_directions(&mut pass, column, self.column_width, 'L', 'R');
+        Ok(pass)
+    }
+
+    /// Converts a seat given either as an ID ("357") or as a row and
+    /// column ("44,5") into a boarding pass.
+    pub fn encode_str(&self, seat: &str) -> Result<String, SeatError> {
+        let number = |s: &str| {
+            s.trim()
+                .parse::<i32>()
+                .map_err(|e| SeatError::InvalidInput(format!("bad seat '{}': {}", seat, e)))
+        };
+        match seat.split_once(',') {
+            Some((row, column)) => self.encode_seat(number(row)?, number(column)?),
+            None => self.encode(number(seat)?),
+        }
+    }
+}
+
+pub fn self_test() -> Result<(), String> {
+    let layout = Layout::default();
+    for (pass, id) in &[
+        ("FBFBBFFRLR", 357),
+        ("BFFFBBFRRR", 567),
+        ("FFFBBBFRRR", 119),
+        ("BBFFBBFRLL", 820),
+    ] {
+        match layout.decode(pass) {
+            Ok(got) if got == *id => (),
+            other => return Err(format!("FAIL: {} decoded as {:?}", pass, other)),
+        }
+        match layout.encode(*id) {
+            Ok(ref got) if got == pass => (),
+            other => return Err(format!("FAIL: {} encoded as {:?}", id, other)),
+        }
+    }
+    match layout.encode_str("44,5") {
+        Ok(ref got) if got == "FBFBBFFRLR" => (),
+        other => return Err(format!("FAIL: row 44 column 5 encoded as {:?}", other)),
+    }
+    // Every seat on a few different planes survives the round trip, and
+    // nothing outside them can be encoded.
+    for (row_width, column_width) in &[(7, 3), (0, 0), (1, 4), (5, 0), (4, 6)] {
+        let layout = Layout::new(*row_width, *column_width).map_err(|e| e.to_string())?;
+        let seats = layout.rows() * layout.columns();
+        for id in 0..seats {
+            let pass = layout.encode(id).map_err(|e| e.to_string())?;
+            if pass.len() != (row_width + column_width) as usize {
+                return Err(format!("FAIL: {} encoded as {} for {:?}", id, pass, layout));
+            }
+            match layout.decode(&pass) {
+                Ok(got) if got == id => (),
+                other => {
+                    return Err(format!(
+                        "FAIL: {} encoded as {} which decoded as {:?} for {:?}",
+                        id, pass, other, layout
+                    ))
+                }
+            }
+        }
+        for id in &[-1, seats] {
+            if layout.encode(*id).is_ok() {
+                return Err(format!("FAIL: {} should not exist in {:?}", id, layout));
+            }
+        }
+    }
+    for bad in &["FBFBBFFRL", "FBFBBFFRLRR", "FBFBBFFRLX", "FBFBBF"] {
+        if layout.decode(bad).is_ok() {
+            return Err(format!("FAIL: {} should not decode", bad));
+        }
+    }
+    Ok(())
+}
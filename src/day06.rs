@@ -0,0 +1,213 @@
+//! Custom Customs (day 6): per-group statistics of the questions
+//! which anyone, or everyone, in each group answered "yes" to, and a
+//! comparison of those statistics between several inputs.
+use std::collections::BTreeSet;
+use std::fmt;
+use std::io;
+use std::io::BufRead;
+
+/// The answers of one group, one set per person.
+pub type Group = Vec<BTreeSet<char>>;
+
+/// Groups are separated by a blank line, and each person's answers
+/// are on a line of their own.
+pub fn read_groups(reader: impl BufRead) -> Result<Vec<Group>, io::Error> {
+    let mut current_group = Vec::new();
+    let mut result = Vec::new();
+    for line in reader.lines() {
+        let line = line?;
+        if line.is_empty() {
+            result.push(current_group);
+            current_group = Vec::new();
+        } else {
+            current_group.push(line.chars().collect());
+        }
+    }
+    if !current_group.is_empty() {
+        result.push(current_group);
+    }
+    Ok(result)
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct GroupStats {
+    pub people: usize,
+    /// How many questions anyone in the group answered (part 1).
+    pub anyone: usize,
+    /// How many questions everyone in the group answered (part 2).
+    pub everyone: usize,
+}
+
+impl GroupStats {
+    pub fn of(group: &[BTreeSet<char>]) -> GroupStats {
+        let mut it = group.iter();
+        let (anyone, everyone) = match it.next() {
+            Some(first) => it.fold((first.clone(), first.clone()), |(any, every), person| {
+                (
+                    any.union(person).cloned().collect(),
+                    every.intersection(person).cloned().collect(),
+                )
+            }),
+            None => (BTreeSet::new(), BTreeSet::new()),
+        };
+        GroupStats {
+            people: group.len(),
+            anyone: anyone.len(),
+            everyone: everyone.len(),
+        }
+    }
+}
+
+pub fn group_stats(groups: &[Group]) -> Vec<GroupStats> {
+    groups.iter().map(|g| GroupStats::of(g)).collect()
+}
+
+pub fn total(stats: &[GroupStats]) -> GroupStats {
+    stats
+        .iter()
+        .fold(GroupStats::default(), |acc, s| GroupStats {
+            people: acc.people + s.people,
+            anyone: acc.anyone + s.anyone,
+            everyone: acc.everyone + s.everyone,
+        })
+}
+
+/// The statistics of the group at `index` in each input, where `None`
+/// means that input has fewer groups.
+#[derive(Debug, PartialEq, Eq)]
+pub struct GroupDiff {
+    pub index: usize,
+    pub stats: Vec<Option<GroupStats>>,
+}
+
+fn join_counts<F>(stats: &[Option<GroupStats>], field: F) -> String
+where
+    F: Fn(&GroupStats) -> usize,
+{
+    stats
+        .iter()
+        .map(|s| match s {
+            Some(s) => field(s).to_string(),
+            None => "-".to_string(),
+        })
+        .collect::<Vec<_>>()
+        .join(" / ")
+}
+
+impl fmt::Display for GroupDiff {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "group {}: anyone {}, everyone {}",
+            self.index + 1,
+            join_counts(&self.stats, |s| s.anyone),
+            join_counts(&self.stats, |s| s.everyone)
+        )
+    }
+}
+
+/// Matches up the groups of each input by position, and returns those
+/// whose anyone or everyone counts are not the same in all inputs.
+pub fn diff(inputs: &[Vec<GroupStats>]) -> Vec<GroupDiff> {
+    let groups = inputs.iter().map(|stats| stats.len()).max().unwrap_or(0);
+    (0..groups)
+        .map(|index| GroupDiff {
+            index,
+            stats: inputs
+                .iter()
+                .map(|stats| stats.get(index).cloned())
+                .collect(),
+        })
+        .filter(|d| {
+            let counts = |s: &Option<GroupStats>| s.map(|s| (s.anyone, s.everyone));
+            d.stats.iter().any(|s| counts(s) != counts(&d.stats[0]))
+        })
+        .collect()
+}
+
+/// Describes the differences between the inputs, finishing with the
+/// totals of each and how they differ from the first.
+pub fn diff_report(names: &[String], inputs: &[Vec<GroupStats>]) -> String {
+    let mut lines: Vec<String> = diff(inputs).iter().map(|d| d.to_string()).collect();
+    let groups = inputs.iter().map(|stats| stats.len()).max().unwrap_or(0);
+    lines.push(format!("{} of {} groups differ", lines.len(), groups));
+    let totals: Vec<GroupStats> = inputs.iter().map(|stats| total(stats)).collect();
+    for (name, (stats, t)) in names.iter().zip(inputs.iter().zip(totals.iter())) {
+        let change = |now: usize, then: usize| {
+            if now == then {
+                String::new()
+            } else {
+                format!(" ({:+})", now as i64 - then as i64)
+            }
+        };
+        lines.push(format!(
+            "{}: {} groups, anyone {}{}, everyone {}{}",
+            name,
+            stats.len(),
+            t.anyone,
+            change(t.anyone, totals[0].anyone),
+            t.everyone,
+            change(t.everyone, totals[0].everyone)
+        ));
+    }
+    lines.join("\n")
+}
+
+pub fn self_test() -> Result<(), String> {
+    let sample = "abc\n\na\nb\nc\n\nab\nac\n\na\na\na\na\n\nb\n";
+    let groups = read_groups(sample.as_bytes()).map_err(|e| e.to_string())?;
+    let stats = group_stats(&groups);
+    let t = total(&stats);
+    if stats.len() != 5 || t.people != 11 || t.anyone != 11 || t.everyone != 6 {
+        return Err(format!("FAIL: wrong totals {:?} for the sample", t));
+    }
+    let changed = "abc\n\na\nb\nc\n\nab\nab\n\na\na\na\na\n";
+    let other = group_stats(&read_groups(changed.as_bytes()).map_err(|e| e.to_string())?);
+    let inputs = vec![stats.clone(), other, stats];
+    let got = diff(&inputs);
+    let expected = vec![
+        GroupDiff {
+            index: 2,
+            stats: vec![
+                Some(GroupStats {
+                    people: 2,
+                    anyone: 3,
+                    everyone: 1,
+                }),
+                Some(GroupStats {
+                    people: 2,
+                    anyone: 2,
+                    everyone: 2,
+                }),
+                Some(GroupStats {
+                    people: 2,
+                    anyone: 3,
+                    everyone: 1,
+                }),
+            ],
+        },
+        GroupDiff {
+            index: 4,
+            stats: vec![
+                Some(GroupStats {
+                    people: 1,
+                    anyone: 1,
+                    everyone: 1,
+                }),
+                None,
+                Some(GroupStats {
+                    people: 1,
+                    anyone: 1,
+                    everyone: 1,
+                }),
+            ],
+        },
+    ];
+    if got != expected {
+        return Err(format!("FAIL: diff gave {:?}", got));
+    }
+    if got[1].to_string() != "group 5: anyone 1 / - / 1, everyone 1 / - / 1" {
+        return Err(format!("FAIL: group 5 was described as '{}'", got[1]));
+    }
+    Ok(())
+}
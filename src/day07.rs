@@ -0,0 +1,439 @@
+//! Handy Haversacks (day 7): the graph of which bags must contain
+//! which others, and a small query language for asking questions
+//! about it.
+//!
+//! A query names an operation and a bag colour, optionally followed
+//! by a limit on how deeply nested the bags may be:
+//!
+//! - `contains("shiny gold")` lists the bags which can (eventually)
+//!   contain a shiny gold bag; this is part 1 of the puzzle.
+//! - `inside("shiny gold")` lists the bags which a shiny gold bag must
+//!   (eventually) contain.
+//! - `count-inside("shiny gold")` counts the bags inside a shiny gold
+//!   bag; this is part 2 of the puzzle.
+//!
+//! With `depth<=N`, only bags nested at most N levels deep count; a
+//! bag which directly contains another is at depth 1.
+use lazy_static::lazy_static;
+use regex::Regex;
+use std::collections::HashMap;
+use std::collections::HashSet;
+use std::collections::VecDeque;
+use std::fmt;
+use std::str::FromStr;
+use thiserror::Error;
+
+lazy_static! {
+    static ref CONTAINS_BAG_RE: Regex = Regex::new(r" ?(\d*) (.*) bags?").unwrap();
+    static ref EMPTY_RE: Regex = Regex::new(r"no other bags$").unwrap();
+    static ref LINE_RE: Regex = Regex::new(r"^(.*) bags contain (.*)$").unwrap();
+    static ref QUERY_RE: Regex = Regex::new(r#"^\s*([a-z-]+)\s*\(\s*"([^"]*)"\s*\)(.*)$"#).unwrap();
+    static ref DEPTH_RE: Regex = Regex::new(r"^depth\s*<=\s*(\d+)$").unwrap();
+}
+
+#[derive(Debug)]
+struct Bag {
+    required_children: HashMap<String, u64>,
+    allowed_parents: HashSet<String>,
+}
+
+impl Bag {
+    fn new(_col: &str) -> Bag {
+        Bag {
+            required_children: HashMap::new(),
+            allowed_parents: HashSet::new(),
+        }
+    }
+
+    fn add_required_child(&mut self, colour: &str, quantity: &u64) {
+        self.required_children.insert(colour.to_string(), *quantity);
+    }
+
+    fn add_allowed_parent(&mut self, colour: &str) {
+        self.allowed_parents.insert(colour.to_string());
+    }
+}
+
+pub struct BagDefs {
+    definitions: HashMap<String, Bag>,
+}
+
+impl BagDefs {
+    fn maybe_add_bag(&mut self, colour: &str) {
+        if !self.definitions.contains_key(colour) {
+            self.definitions
+                .insert(colour.to_string(), Bag::new(colour));
+        }
+    }
+
+    fn get_or_add_bag(&mut self, colour: &str) -> &mut Bag {
+        self.maybe_add_bag(colour);
+        self.definitions.get_mut(colour).unwrap()
+    }
+
+    fn add_bag(&mut self, parent_colour: &str, child_colour: &str, quantity: &u64) {
+        let parent: &mut Bag = self.get_or_add_bag(parent_colour);
+        parent.add_required_child(child_colour, quantity);
+
+        let child: &mut Bag = self.get_or_add_bag(child_colour);
+        child.add_allowed_parent(parent_colour);
+    }
+
+    pub fn new() -> BagDefs {
+        BagDefs {
+            definitions: HashMap::new(),
+        }
+    }
+
+    /// Adds the rule in one line of the puzzle input.
+    pub fn add_line(&mut self, line: &str) -> Result<(), String> {
+        let (parent_colour, contents) = parse_line(line)?;
+        self.maybe_add_bag(&parent_colour);
+        for (child_colour, quantity) in contents.iter() {
+            self.add_bag(&parent_colour, child_colour, quantity);
+        }
+        Ok(())
+    }
+
+    pub fn parse<S: AsRef<str>>(lines: &[S]) -> Result<BagDefs, String> {
+        let mut definitions = BagDefs::new();
+        for line in lines {
+            definitions.add_line(line.as_ref())?;
+        }
+        Ok(definitions)
+    }
+
+    fn get(&self, colour: &str) -> Result<&Bag, String> {
+        self.definitions
+            .get(colour)
+            .ok_or_else(|| format!("we know nothing about {} bags", colour))
+    }
+
+    fn can_contain(&self, parent_colour: &str, wanted: &str) -> bool {
+        let parent = match self.definitions.get(parent_colour) {
+            None => {
+                panic!("can_contain: unknown parent {}", parent_colour);
+            }
+            Some(parent) => parent,
+        };
+        if parent.required_children.contains_key(wanted) {
+            return true;
+        }
+        for child_colour in parent.required_children.keys() {
+            if self.can_contain(child_colour, wanted) {
+                return true;
+            }
+        }
+        false
+    }
+
+    /// The colours of bag which could contain `colour_wanted`, in
+    /// alphabetical order.
+    pub fn possible_parents(&self, colour_wanted: &str) -> Vec<String> {
+        let mut result: Vec<String> = Vec::new();
+        for parent_colour in self.definitions.keys() {
+            if self.can_contain(parent_colour, colour_wanted) {
+                result.push(parent_colour.to_string())
+            }
+        }
+        result.sort();
+        result
+    }
+
+    pub fn count_children(&self, parent_colour: &str) -> u64 {
+        match self.definitions.get(parent_colour) {
+            Some(bag) => bag
+                .required_children
+                .iter()
+                .fold(0, |acc, (colour, children)| {
+                    acc + children * (1 + self.count_children(colour))
+                }),
+            None => {
+                panic!(
+                    "we know nothing about what goes into {} bags",
+                    parent_colour
+                );
+            }
+        }
+    }
+
+    /// Finds the bags reachable from `colour` within `max_depth`
+    /// steps, following parents (if `outwards`) or children.  The
+    /// result is sorted and does not include `colour` itself.
+    fn reachable(
+        &self,
+        colour: &str,
+        outwards: bool,
+        max_depth: Option<usize>,
+    ) -> Result<Vec<String>, String> {
+        self.get(colour)?;
+        let mut seen: HashSet<&str> = HashSet::new();
+        let mut queue: VecDeque<(&str, usize)> = VecDeque::new();
+        queue.push_back((colour, 0));
+        while let Some((current, depth)) = queue.pop_front() {
+            if max_depth.is_some_and(|max| depth >= max) {
+                continue;
+            }
+            let bag = self.get(current)?;
+            let next: Vec<&str> = if outwards {
+                bag.allowed_parents.iter().map(|s| s.as_str()).collect()
+            } else {
+                bag.required_children.keys().map(|s| s.as_str()).collect()
+            };
+            for n in next {
+                if n != colour && seen.insert(n) {
+                    queue.push_back((n, depth + 1));
+                }
+            }
+        }
+        let mut result: Vec<String> = seen.into_iter().map(|s| s.to_string()).collect();
+        result.sort();
+        Ok(result)
+    }
+
+    /// Counts the bags inside a `colour` bag, nested at most
+    /// `max_depth` deep.
+    fn count_inside(&self, colour: &str, max_depth: Option<usize>) -> Result<u64, String> {
+        if max_depth == Some(0) {
+            return Ok(0);
+        }
+        let mut total = 0;
+        for (child, quantity) in self.get(colour)?.required_children.iter() {
+            total += quantity * (1 + self.count_inside(child, max_depth.map(|d| d - 1))?);
+        }
+        Ok(total)
+    }
+
+    pub fn query(&self, query: &Query) -> Result<QueryResult, String> {
+        match query.operation {
+            Operation::Contains => self
+                .reachable(&query.colour, true, query.max_depth)
+                .map(QueryResult::Bags),
+            Operation::Inside => self
+                .reachable(&query.colour, false, query.max_depth)
+                .map(QueryResult::Bags),
+            Operation::CountInside => self
+                .count_inside(&query.colour, query.max_depth)
+                .map(QueryResult::Count),
+        }
+    }
+}
+
+impl Default for BagDefs {
+    fn default() -> BagDefs {
+        BagDefs::new()
+    }
+}
+
+fn parse_line(orig_line: &str) -> Result<(String, HashMap<String, u64>), String> {
+    let line = orig_line.trim_end_matches('.');
+    let cap = match LINE_RE.captures(line) {
+        None => {
+            return Err(format!("malformed line '{}'", line));
+        }
+        Some(cap) => cap,
+    };
+    let parent_colour = cap[1].to_string();
+    let contents_str = &cap[2];
+    if EMPTY_RE.is_match(contents_str) {
+        return Ok((parent_colour, HashMap::new()));
+    }
+    let mut contents: HashMap<String, u64> = HashMap::new();
+    for item in contents_str.split(',') {
+        let cap = match CONTAINS_BAG_RE.captures(item) {
+            None => {
+                return Err(format!("'{}' does not describe some bags", item));
+            }
+            Some(cap) => cap,
+        };
+        let quantity = cap[1]
+            .parse()
+            .map_err(|e| format!("'{}' has a bad quantity: {}", item, e))?;
+        contents.insert(cap[2].to_string(), quantity);
+    }
+    Ok((parent_colour, contents))
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Operation {
+    Contains,
+    Inside,
+    CountInside,
+}
+
+impl fmt::Display for Operation {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match self {
+            Operation::Contains => "contains",
+            Operation::Inside => "inside",
+            Operation::CountInside => "count-inside",
+        })
+    }
+}
+
+/// A question about the bag graph (see the module documentation).
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Query {
+    pub operation: Operation,
+    pub colour: String,
+    pub max_depth: Option<usize>,
+}
+
+#[derive(Debug, Error, PartialEq, Eq)]
+pub enum QueryError {
+    #[error("'{0}' is not a query; try for example contains(\"shiny gold\")")]
+    Syntax(String),
+    #[error("unknown query operation '{0}'")]
+    UnknownOperation(String),
+    #[error("unknown query modifier '{0}'")]
+    UnknownModifier(String),
+}
+
+impl FromStr for Query {
+    type Err = QueryError;
+
+    fn from_str(s: &str) -> Result<Query, QueryError> {
+        let cap = QUERY_RE
+            .captures(s)
+            .ok_or_else(|| QueryError::Syntax(s.to_string()))?;
+        let operation = match &cap[1] {
+            "contains" => Operation::Contains,
+            "inside" => Operation::Inside,
+            "count-inside" => Operation::CountInside,
+            other => return Err(QueryError::UnknownOperation(other.to_string())),
+        };
+        let mut max_depth = None;
+        for modifier in cap[3].split_whitespace() {
+            match DEPTH_RE.captures(modifier) {
+                Some(depth) => {
+                    max_depth = Some(
+                        depth[1]
+                            .parse()
+                            .map_err(|_| QueryError::UnknownModifier(modifier.to_string()))?,
+                    )
+                }
+                None => return Err(QueryError::UnknownModifier(modifier.to_string())),
+            }
+        }
+        Ok(Query {
+            operation,
+            colour: cap[2].to_string(),
+            max_depth,
+        })
+    }
+}
+
+impl fmt::Display for Query {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}(\"{}\")", self.operation, self.colour)?;
+        if let Some(depth) = self.max_depth {
+            write!(f, " depth<={}", depth)?;
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum QueryResult {
+    Bags(Vec<String>),
+    Count(u64),
+}
+
+impl fmt::Display for QueryResult {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            QueryResult::Bags(bags) => write!(f, "{} bags: {}", bags.len(), bags.join(", ")),
+            QueryResult::Count(n) => write!(f, "{}", n),
+        }
+    }
+}
+
+pub fn self_test() -> Result<(), String> {
+    let defs = BagDefs::parse(&[
+        "light red bags contain 1 bright white bag, 2 muted yellow bags.",
+        "dark orange bags contain 3 bright white bags, 4 muted yellow bags.",
+        "bright white bags contain 1 shiny gold bag.",
+        "muted yellow bags contain 2 shiny gold bags, 9 faded blue bags.",
+        "shiny gold bags contain 1 dark olive bag, 2 vibrant plum bags.",
+        "dark olive bags contain 3 faded blue bags, 4 dotted black bags.",
+        "vibrant plum bags contain 5 faded blue bags, 6 dotted black bags.",
+        "faded blue bags contain no other bags.",
+        "dotted black bags contain no other bags.",
+    ])?;
+    let bags = |names: &[&str]| QueryResult::Bags(names.iter().map(|s| s.to_string()).collect());
+    for (text, expected) in &[
+        (
+            "contains(\"shiny gold\")",
+            bags(&["bright white", "dark orange", "light red", "muted yellow"]),
+        ),
+        (
+            "contains(\"shiny gold\") depth<=1",
+            bags(&["bright white", "muted yellow"]),
+        ),
+        (
+            " inside ( \"shiny gold\" )  depth<=1 ",
+            bags(&["dark olive", "vibrant plum"]),
+        ),
+        (
+            "inside(\"muted yellow\")",
+            bags(&[
+                "dark olive",
+                "dotted black",
+                "faded blue",
+                "shiny gold",
+                "vibrant plum",
+            ]),
+        ),
+        ("contains(\"light red\")", bags(&[])),
+        ("count-inside(\"shiny gold\")", QueryResult::Count(32)),
+        (
+            "count-inside(\"shiny gold\") depth<=1",
+            QueryResult::Count(3),
+        ),
+        (
+            "count-inside(\"shiny gold\") depth<=0",
+            QueryResult::Count(0),
+        ),
+        ("count-inside(\"faded blue\")", QueryResult::Count(0)),
+    ] {
+        let query: Query = text.parse().map_err(|e: QueryError| e.to_string())?;
+        let got = defs.query(&query)?;
+        if got != *expected {
+            return Err(format!(
+                "FAIL: query {} gave {}, expected {}",
+                query, got, expected
+            ));
+        }
+    }
+    if defs.count_children("shiny gold") != 32 || defs.possible_parents("shiny gold").len() != 4 {
+        return Err("FAIL: wrong answers for the example".to_string());
+    }
+    if defs.query(&"inside(\"mauve\")".parse().unwrap()).is_ok() {
+        return Err("FAIL: querying an unknown colour should fail".to_string());
+    }
+    for (text, expected) in &[
+        (
+            "contains shiny gold",
+            QueryError::Syntax("contains shiny gold".to_string()),
+        ),
+        (
+            "within(\"red\")",
+            QueryError::UnknownOperation("within".to_string()),
+        ),
+        (
+            "inside(\"red\") depth<2",
+            QueryError::UnknownModifier("depth<2".to_string()),
+        ),
+    ] {
+        match text.parse::<Query>() {
+            Err(e) if e == *expected => (),
+            other => {
+                return Err(format!(
+                    "FAIL: query '{}' should fail with {:?}, got {:?}",
+                    text, expected, other
+                ));
+            }
+        }
+    }
+    Ok(())
+}
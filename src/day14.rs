@@ -0,0 +1,365 @@
+//! Bitmasks for the docking program of the ferry (day 14).
+//!
+//! A mask is a string of 36 characters, most significant bit first.
+//! Each character is `0`, `1` or `X`.  The version 1 decoder chip
+//! uses a mask to modify values (`X` leaves the bit alone), while the
+//! version 2 decoder uses it to modify addresses (`X` is "floating"
+//! and takes both values).
+//!
+//! The addresses written by a version 2 store form an
+//! `AddressPattern`, and the addresses written by several stores can
+//! be counted without listing them by keeping them in an `AddressSet`.
+use std::fmt;
+use std::str::FromStr;
+
+/// The number of bits in an address or value.
+pub const WORD_BITS: usize = 36;
+
+/// The largest address or value the docking computer can hold.
+pub const WORD_MAX: i64 = (1 << WORD_BITS) - 1;
+
+/// A mask; the default mask is all zeros.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Mask {
+    /// Bits which are set to 1.
+    ones: i64,
+    /// Bits which are left alone (v1) or which float (v2).
+    floating: i64,
+}
+
+impl Mask {
+    /// The mask consisting entirely of `X`; applying it to a value
+    /// leaves the value unchanged.
+    pub fn identity() -> Mask {
+        Mask {
+            ones: 0,
+            floating: WORD_MAX,
+        }
+    }
+
+    /// Bits which this mask sets to 1.
+    pub fn ones(&self) -> i64 {
+        self.ones
+    }
+
+    /// Bits which this mask sets to 0.
+    pub fn zeros(&self) -> i64 {
+        WORD_MAX & !(self.ones | self.floating)
+    }
+
+    /// Bits which are `X` in this mask.
+    pub fn floating(&self) -> i64 {
+        self.floating
+    }
+
+    /// Applies the mask to a value, as the version 1 decoder does.
+    pub fn apply(&self, value: i64) -> i64 {
+        (value & self.floating) | self.ones
+    }
+
+    /// Returns the mask equivalent to applying `self` and then
+    /// `next` (with version 1 semantics), so that
+    /// `a.then(&b).apply(v) == b.apply(a.apply(v))`.
+    pub fn then(&self, next: &Mask) -> Mask {
+        Mask {
+            ones: (self.ones & next.floating) | next.ones,
+            floating: self.floating & next.floating,
+        }
+    }
+
+    /// Returns an iterator over every combination of the floating
+    /// bits (including the one where none of them is set).
+    pub fn floating_values(&self) -> FloatingValues {
+        FloatingValues {
+            floating: self.floating,
+            next: Some(0),
+        }
+    }
+
+    /// The number of addresses a version 2 write through this mask
+    /// touches.
+    pub fn address_count(&self) -> u64 {
+        1 << self.floating.count_ones()
+    }
+
+    /// Returns an iterator over the addresses written by the
+    /// version 2 decoder for a write to `addr`.
+    pub fn addresses(&self, addr: i64) -> impl Iterator<Item = i64> {
+        self.address_pattern(addr).addresses()
+    }
+
+    /// The addresses written by the version 2 decoder for a write to
+    /// `addr`.
+    pub fn address_pattern(&self, addr: i64) -> AddressPattern {
+        AddressPattern {
+            base: (addr | self.ones) & !self.floating,
+            floating: self.floating,
+        }
+    }
+}
+
+fn write_bits(f: &mut fmt::Formatter, ones: i64, floating: i64) -> fmt::Result {
+    for bitpos in (0..WORD_BITS).rev() {
+        let bit = 1 << bitpos;
+        let ch = if floating & bit != 0 {
+            'X'
+        } else if ones & bit != 0 {
+            '1'
+        } else {
+            '0'
+        };
+        write!(f, "{}", ch)?;
+    }
+    Ok(())
+}
+
+impl fmt::Display for Mask {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write_bits(f, self.ones, self.floating)
+    }
+}
+
+impl FromStr for Mask {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Mask, String> {
+        if s.len() != WORD_BITS {
+            return Err(format!(
+                "mask has {} bits but should have {}",
+                s.len(),
+                WORD_BITS
+            ));
+        }
+        let mut mask = Mask {
+            ones: 0,
+            floating: 0,
+        };
+        for (bitpos, ch) in s.chars().rev().enumerate() {
+            let bit = 1 << bitpos;
+            match ch {
+                'X' => mask.floating |= bit,
+                '1' => mask.ones |= bit,
+                '0' => (),
+                _ => {
+                    return Err(format!("invalid mask character '{}'", ch));
+                }
+            }
+        }
+        Ok(mask)
+    }
+}
+
+/// A set of addresses which agree in all but their floating bits,
+/// such as those written by a version 2 store.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct AddressPattern {
+    /// The bits which are not floating (the floating bits are 0).
+    base: i64,
+    floating: i64,
+}
+
+impl AddressPattern {
+    /// The number of addresses in the pattern.
+    pub fn len(&self) -> u64 {
+        1 << self.floating.count_ones()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        false
+    }
+
+    pub fn addresses(&self) -> impl Iterator<Item = i64> {
+        let base = self.base;
+        FloatingValues {
+            floating: self.floating,
+            next: Some(0),
+        }
+        .map(move |bits| base | bits)
+    }
+
+    /// Returns true if some address is in both patterns.
+    pub fn intersects(&self, other: &AddressPattern) -> bool {
+        (self.base ^ other.base) & !(self.floating | other.floating) == 0
+    }
+
+    /// The number of addresses in both patterns.
+    pub fn overlap(&self, other: &AddressPattern) -> u64 {
+        if self.intersects(other) {
+            1 << (self.floating & other.floating).count_ones()
+        } else {
+            0
+        }
+    }
+
+    /// Returns the addresses in `self` but not in `other`, as
+    /// disjoint patterns.
+    pub fn difference(&self, other: &AddressPattern) -> Vec<AddressPattern> {
+        if !self.intersects(other) {
+            return vec![*self];
+        }
+        let mut result = Vec::new();
+        let mut rest = *self;
+        // Fix each bit which floats here but not in `other` in turn.
+        // The addresses where it differs from `other` are not in
+        // `other`; the rest remain to be considered.
+        let mut bits = self.floating & !other.floating;
+        while bits != 0 {
+            let bit = bits & -bits;
+            bits &= !bit;
+            rest.floating &= !bit;
+            result.push(AddressPattern {
+                base: rest.base | (!other.base & bit),
+                floating: rest.floating,
+            });
+            rest.base |= other.base & bit;
+        }
+        // What is left of `self` is inside `other`.
+        result
+    }
+}
+
+impl fmt::Display for AddressPattern {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write_bits(f, self.base, self.floating)
+    }
+}
+
+/// A set of addresses, held as disjoint patterns so that very large
+/// sets can be counted without listing their members.
+#[derive(Debug, Default, Clone)]
+pub struct AddressSet {
+    patterns: Vec<AddressPattern>,
+}
+
+impl AddressSet {
+    pub fn new() -> AddressSet {
+        AddressSet::default()
+    }
+
+    /// The number of addresses in the set.
+    pub fn len(&self) -> u64 {
+        self.patterns.iter().map(AddressPattern::len).sum()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.patterns.is_empty()
+    }
+
+    /// Adds the addresses of `pattern`, returning the number of them
+    /// which were not already in the set.
+    pub fn insert(&mut self, pattern: AddressPattern) -> u64 {
+        let mut pieces = vec![pattern];
+        for existing in self.patterns.iter() {
+            pieces = pieces
+                .iter()
+                .flat_map(|piece| piece.difference(existing))
+                .collect();
+            if pieces.is_empty() {
+                break;
+            }
+        }
+        let added = pieces.iter().map(AddressPattern::len).sum();
+        self.patterns.extend(pieces);
+        added
+    }
+}
+
+/// Iterates over all the subsets of the floating bits of a mask.
+pub struct FloatingValues {
+    floating: i64,
+    next: Option<i64>,
+}
+
+impl Iterator for FloatingValues {
+    type Item = i64;
+
+    fn next(&mut self) -> Option<i64> {
+        let current = self.next?;
+        // Standard trick for enumerating the subsets of a bit set.
+        let following = (current - self.floating) & self.floating;
+        self.next = if following == 0 {
+            None
+        } else {
+            Some(following)
+        };
+        Some(current)
+    }
+}
+
+/// Checks the properties of `Mask`.
+pub fn self_test() -> Result<(), String> {
+    let parse = |s: &str| -> Result<Mask, String> { s.parse() };
+    let m1 = parse("XXXXXXXXXXXXXXXXXXXXXXXXXXXXX1XXXX0X")?;
+    let m2 = parse("000000000000000000000000000000X1001X")?;
+    let m3 = parse("00000000000000000000000000000000X0XX")?;
+    for m in &[m1, m2, m3, Mask::identity()] {
+        if parse(&m.to_string())? != *m {
+            return Err(format!("FAIL: {} does not survive a round trip", m));
+        }
+        if m.ones() | m.zeros() | m.floating() != WORD_MAX {
+            return Err(format!("FAIL: {} does not cover every bit", m));
+        }
+    }
+    for (value, expected) in &[(11, 73), (101, 101), (0, 64)] {
+        if m1.apply(*value) != *expected {
+            return Err(format!(
+                "FAIL: {} applied to {} should be {}",
+                m1, value, expected
+            ));
+        }
+    }
+    for a in &[m1, m2, m3, Mask::identity()] {
+        for b in &[m1, m2, m3, Mask::identity()] {
+            let composed = a.then(b);
+            for v in &[0, 11, 42, 101, WORD_MAX] {
+                if composed.apply(*v) != b.apply(a.apply(*v)) {
+                    return Err(format!(
+                        "FAIL: composition of {} and {} is wrong for {}",
+                        a, b, v
+                    ));
+                }
+            }
+        }
+    }
+    let mut addrs: Vec<i64> = m2.addresses(42).collect();
+    addrs.sort_unstable();
+    if addrs != vec![26, 27, 58, 59] {
+        return Err(format!("FAIL: wrong addresses for {}: {:?}", m2, addrs));
+    }
+    if m3.address_count() != 8 || m3.floating_values().count() != 8 {
+        return Err(format!("FAIL: {} should address 8 locations", m3));
+    }
+    let p2 = m2.address_pattern(42);
+    let p3 = m3.address_pattern(26);
+    if p2.to_string() != "000000000000000000000000000000X1101X" || p2.len() != 4 {
+        return Err(format!("FAIL: wrong address pattern {}", p2));
+    }
+    for (a, b) in &[(p2, p3), (p3, p2), (p2, p2), (p3, m3.address_pattern(1))] {
+        let in_b: Vec<i64> = a
+            .addresses()
+            .filter(|x| b.addresses().any(|y| y == *x))
+            .collect();
+        if a.overlap(b) != in_b.len() as u64 || a.intersects(b) == in_b.is_empty() {
+            return Err(format!("FAIL: wrong overlap of {} and {}", a, b));
+        }
+        let mut expected: Vec<i64> = a.addresses().filter(|x| !in_b.contains(x)).collect();
+        let mut got: Vec<i64> = a.difference(b).iter().flat_map(|p| p.addresses()).collect();
+        expected.sort_unstable();
+        got.sort_unstable();
+        if got != expected {
+            return Err(format!("FAIL: wrong difference of {} and {}", a, b));
+        }
+    }
+    let mut set = AddressSet::new();
+    let added: Vec<u64> = [p2, p3, p2].iter().map(|p| set.insert(*p)).collect();
+    // The two patterns share addresses 26 and 27.
+    if added != [4, 6, 0] || set.len() != 10 {
+        return Err(format!("FAIL: added {:?} to make {}", added, set.len()));
+    }
+    for bad in &["X", "XXXXXXXXXXXXXXXXXXXXXXXXXXXXX1XXXX0Y"] {
+        if parse(bad).is_ok() {
+            return Err(format!("FAIL: '{}' should not be a valid mask", bad));
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,467 @@
+//! Ticket fields, tickets and the puzzle input for day 16.
+use std::fmt;
+use std::ops::RangeInclusive;
+use std::str::FromStr;
+use thiserror::Error;
+
+/// A set of integers represented as sorted, disjoint, non-adjacent
+/// inclusive ranges.
+#[derive(Clone, Debug, Default)]
+pub struct IntervalSet {
+    ranges: Vec<(i32, i32)>,
+}
+
+impl IntervalSet {
+    pub fn new<'a, I>(ranges: I) -> IntervalSet
+    where
+        I: IntoIterator<Item = &'a RangeInclusive<i32>>,
+    {
+        IntervalSet::from_pairs(ranges.into_iter().map(|r| (*r.start(), *r.end())))
+    }
+
+    pub fn from_pairs<I>(ranges: I) -> IntervalSet
+    where
+        I: IntoIterator<Item = (i32, i32)>,
+    {
+        let mut sorted: Vec<(i32, i32)> = ranges.into_iter().filter(|(lo, hi)| lo <= hi).collect();
+        sorted.sort_unstable();
+        let mut merged: Vec<(i32, i32)> = Vec::with_capacity(sorted.len());
+        for (lo, hi) in sorted {
+            match merged.last_mut() {
+                Some(last) if lo <= last.1.saturating_add(1) => {
+                    last.1 = last.1.max(hi);
+                }
+                _ => merged.push((lo, hi)),
+            }
+        }
+        IntervalSet { ranges: merged }
+    }
+
+    pub fn union<'a, I>(sets: I) -> IntervalSet
+    where
+        I: IntoIterator<Item = &'a IntervalSet>,
+    {
+        IntervalSet::from_pairs(sets.into_iter().flat_map(|s| s.ranges.iter().copied()))
+    }
+
+    /// Returns the members of this set which are not in `other`.
+    pub fn difference(&self, other: &IntervalSet) -> IntervalSet {
+        let mut result: Vec<(i32, i32)> = Vec::new();
+        for (lo, hi) in self.ranges.iter() {
+            // start is the lowest value not yet ruled in or out.
+            let mut start: Option<i32> = Some(*lo);
+            for (xlo, xhi) in other.ranges.iter() {
+                let from = match start {
+                    Some(n) if n <= *hi => n,
+                    _ => break,
+                };
+                if *xhi < from {
+                    continue;
+                }
+                if *xlo > *hi {
+                    break;
+                }
+                if *xlo > from {
+                    result.push((from, xlo - 1));
+                }
+                start = xhi.checked_add(1);
+            }
+            match start {
+                Some(n) if n <= *hi => result.push((n, *hi)),
+                _ => (),
+            }
+        }
+        IntervalSet::from_pairs(result)
+    }
+
+    pub fn contains(&self, v: &i32) -> bool {
+        // Find the first range which ends at or after v.
+        let i = self.ranges.partition_point(|(_, hi)| hi < v);
+        match self.ranges.get(i) {
+            Some((lo, _)) => lo <= v,
+            None => false,
+        }
+    }
+}
+
+/// A ticket field.  Its valid values are those in any of its ranges,
+/// except for those in any of its exclusions.
+#[derive(Clone)]
+pub struct Field {
+    pub name: String,
+    pub ranges: Vec<RangeInclusive<i32>>,
+    pub exclusions: Vec<RangeInclusive<i32>>,
+    valid: IntervalSet,
+}
+
+impl Field {
+    pub fn is_valid_value(&self, v: &i32) -> bool {
+        self.valid.contains(v)
+    }
+}
+
+fn format_ranges(ranges: &[RangeInclusive<i32>]) -> String {
+    itertools::join(
+        ranges.iter().map(|r| {
+            if r.start() == r.end() {
+                r.start().to_string()
+            } else {
+                format!("{}-{}", r.start(), r.end())
+            }
+        }),
+        " or ",
+    )
+}
+
+impl fmt::Display for Field {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}: {}", self.name, format_ranges(&self.ranges))?;
+        if !self.exclusions.is_empty() {
+            write!(f, " except {}", format_ranges(&self.exclusions))?;
+        }
+        Ok(())
+    }
+}
+
+/// Parses a list of ranges such as "1-3 or 5 or 7-11".
+fn parse_ranges(s: &str) -> Result<Vec<RangeInclusive<i32>>, String> {
+    let parse_int = |n: &str| -> Result<i32, String> {
+        n.trim()
+            .parse()
+            .map_err(|e| format!("invalid number '{}': {}", n.trim(), e))
+    };
+    s.split(" or ")
+        .map(|r| match r.trim().split_once('-') {
+            Some((lo, hi)) => Ok(RangeInclusive::new(parse_int(lo)?, parse_int(hi)?)),
+            None => {
+                let n = parse_int(r)?;
+                Ok(RangeInclusive::new(n, n))
+            }
+        })
+        .collect()
+}
+
+impl Field {
+    /// Parses a field description such as "class: 1-3 or 5-7", which
+    /// may include any number of ranges and optionally a list of
+    /// exclusions, as in "row: 6-44 except 12 or 20-22".
+    pub fn new(s: &str) -> Result<Field, String> {
+        let (name, spec) = match s.split_once(':') {
+            Some(parts) => parts,
+            None => return Err(format!("'{}' is not a valid field input line", s)),
+        };
+        let (include, exclude) = match spec.split_once(" except ") {
+            Some((include, exclude)) => (include, Some(exclude)),
+            None => (spec, None),
+        };
+        let error = |e: String| format!("'{}' is not a valid field input line: {}", s, e);
+        let ranges = parse_ranges(include).map_err(error)?;
+        let exclusions = match exclude {
+            Some(x) => parse_ranges(x).map_err(error)?,
+            None => Vec::new(),
+        };
+        Ok(Field::with_exclusions(name, ranges, exclusions))
+    }
+
+    pub fn with_exclusions(
+        name: &str,
+        ranges: Vec<RangeInclusive<i32>>,
+        exclusions: Vec<RangeInclusive<i32>>,
+    ) -> Field {
+        let valid = IntervalSet::new(&ranges).difference(&IntervalSet::new(&exclusions));
+        Field {
+            name: name.to_string(),
+            ranges,
+            exclusions,
+            valid,
+        }
+    }
+
+    pub fn from_ranges(
+        name: &str,
+        first: RangeInclusive<i32>,
+        second: RangeInclusive<i32>,
+    ) -> Field {
+        Field::with_exclusions(name, vec![first, second], Vec::new())
+    }
+}
+
+#[derive(Clone)]
+pub struct Ticket {
+    pub values: Vec<i32>,
+}
+
+impl fmt::Display for Ticket {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(&itertools::join(&self.values, ","))
+    }
+}
+
+impl FromStr for Ticket {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Ticket, String> {
+        let mut vs: Vec<i32> = Vec::new();
+        for value in s.split(',') {
+            match value.parse() {
+                Err(e) => {
+                    return Err(format!("failed to parse value '{}': {}", value, e));
+                }
+                Ok(n) => {
+                    vs.push(n);
+                }
+            }
+        }
+        Ok(Ticket { values: vs })
+    }
+}
+
+pub struct Input {
+    pub fields: Vec<Field>,
+    pub my_ticket: Ticket,
+    pub nearby: Vec<Ticket>,
+    /// The values which are valid for at least one field.
+    all_valid: IntervalSet,
+}
+
+impl Input {
+    pub fn new(fields: Vec<Field>, my_ticket: Ticket, nearby: Vec<Ticket>) -> Input {
+        let all_valid = IntervalSet::union(fields.iter().map(|f| &f.valid));
+        Input {
+            fields,
+            my_ticket,
+            nearby,
+            all_valid,
+        }
+    }
+
+    pub fn is_valid_value(&self, v: &i32) -> bool {
+        self.all_valid.contains(v)
+    }
+
+    /// Parses the puzzle input.  Line numbers in errors start at 1.
+    pub fn parse(text: &str) -> Result<Input, ParseError> {
+        let mut lines = text.lines().enumerate().map(|(n, s)| (n + 1, s));
+        let mut section = Section::Fields;
+        let mut last_line: usize = 0;
+        let mut next_line = |section: Section| -> Result<(usize, &str), ParseError> {
+            match lines.next() {
+                Some((n, s)) => {
+                    last_line = n;
+                    Ok((n, s))
+                }
+                None => Err(ParseError::Truncated {
+                    section,
+                    line: last_line,
+                }),
+            }
+        };
+        let invalid = |section: Section, line: usize, reason: String| ParseError::Invalid {
+            section,
+            line,
+            reason,
+        };
+        let expect = |section: Section, (line, s): (usize, &str), expected: &str| {
+            if s == expected {
+                Ok(())
+            } else if expected.is_empty() {
+                Err(invalid(
+                    section,
+                    line,
+                    format!("expected a blank line, not '{}'", s),
+                ))
+            } else {
+                Err(invalid(
+                    section,
+                    line,
+                    format!("expected '{}', not '{}'", expected, s),
+                ))
+            }
+        };
+
+        let mut fields: Vec<Field> = Vec::new();
+        loop {
+            let (line, s) = next_line(section)?;
+            if s.is_empty() {
+                break;
+            }
+            fields.push(Field::new(s).map_err(|e| invalid(section, line, e))?);
+        }
+
+        section = Section::YourTicket;
+        expect(section, next_line(section)?, "your ticket:")?;
+        let (line, s) = next_line(section)?;
+        let my_ticket: Ticket = s.parse().map_err(|e| invalid(section, line, e))?;
+        expect(section, next_line(section)?, "")?;
+
+        section = Section::NearbyTickets;
+        expect(section, next_line(section)?, "nearby tickets:")?;
+        let mut nearby: Vec<Ticket> = Vec::new();
+        while let Ok((line, s)) = next_line(section) {
+            if s.is_empty() {
+                continue;
+            }
+            nearby.push(s.parse().map_err(|e| invalid(section, line, e))?);
+        }
+        Ok(Input::new(fields, my_ticket, nearby))
+    }
+}
+
+impl fmt::Display for Input {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{}\n\nyour ticket:\n{}\n\nnearby tickets:\n{}",
+            itertools::join(self.fields.iter(), "\n"),
+            self.my_ticket,
+            itertools::join(self.nearby.iter(), "\n")
+        )
+    }
+}
+
+/// The sections of the puzzle input.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Section {
+    Fields,
+    YourTicket,
+    NearbyTickets,
+}
+
+impl fmt::Display for Section {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match self {
+            Section::Fields => "fields",
+            Section::YourTicket => "your ticket",
+            Section::NearbyTickets => "nearby tickets",
+        })
+    }
+}
+
+#[derive(Error, Debug, PartialEq, Eq)]
+pub enum ParseError {
+    #[error("line {line} (in the {section} section): {reason}")]
+    Invalid {
+        section: Section,
+        line: usize,
+        reason: String,
+    },
+    #[error("input ends prematurely after line {line} (in the {section} section)")]
+    Truncated { section: Section, line: usize },
+}
+
+/// Checks the parsing of fields and of the puzzle input.
+pub fn self_test() -> Result<(), String> {
+    let cases: &[(&str, &[i32], &[i32])] = &[
+        ("class: 1-3 or 5-7", &[1, 3, 5, 7], &[0, 4, 8]),
+        ("one: 4", &[4], &[3, 5]),
+        ("many: 1-2 or 4-5 or 9-10", &[2, 4, 10], &[3, 6, 11]),
+        (
+            "row: 6-44 except 12 or 20-22 or 44",
+            &[6, 11, 13, 19, 23, 43],
+            &[5, 12, 20, 21, 22, 44, 45],
+        ),
+        ("all: 1-10 except 0-20", &[], &[0, 1, 10, 20]),
+    ];
+    for (input, valid, invalid) in cases {
+        let field = Field::new(input)?;
+        if field.to_string() != *input {
+            return Err(format!("FAIL: '{}' was displayed as '{}'", input, field));
+        }
+        if let Some(v) = valid.iter().find(|v| !field.is_valid_value(v)) {
+            return Err(format!("FAIL: {} should be valid for '{}'", v, input));
+        }
+        if let Some(v) = invalid.iter().find(|v| field.is_valid_value(v)) {
+            return Err(format!("FAIL: {} should not be valid for '{}'", v, input));
+        }
+    }
+    for bad in &[
+        "class 1-3",
+        "class: 1-x",
+        "class: 1-3 or",
+        "class: 1-3 except",
+    ] {
+        if Field::new(bad).is_ok() {
+            return Err(format!("FAIL: '{}' should not be a valid field", bad));
+        }
+    }
+    let good = "class: 1-3 or 5-7\nrow: 6-11 or 33-44\n\nyour ticket:\n7,1,14\n\n\
+                nearby tickets:\n7,3,47\n40,4,50\n";
+    match Input::parse(good) {
+        Ok(input) if input.fields.len() == 2 && input.nearby.len() == 2 => (),
+        Ok(_) => return Err("FAIL: the example input was parsed incorrectly".to_string()),
+        Err(e) => return Err(format!("FAIL: failed to parse the example input: {}", e)),
+    }
+    let truncated = |section: Section, line: usize| ParseError::Truncated { section, line };
+    let invalid = |section: Section, line: usize, reason: &str| ParseError::Invalid {
+        section,
+        line,
+        reason: reason.to_string(),
+    };
+    let bad_inputs: Vec<(&str, ParseError)> = vec![
+        ("", truncated(Section::Fields, 0)),
+        ("class: 1-3 or 5-7\n", truncated(Section::Fields, 1)),
+        ("class: 1-3 or 5-7\n\n", truncated(Section::YourTicket, 2)),
+        (
+            "class: 1-3 or 5-7\n\nyour ticket:\n7\n",
+            truncated(Section::YourTicket, 4),
+        ),
+        (
+            "class: 1-3 or 5-7\n\nyour ticket:\n7\n\n",
+            truncated(Section::NearbyTickets, 5),
+        ),
+        (
+            "class: 1-3 or x\n",
+            invalid(
+                Section::Fields,
+                1,
+                "'class: 1-3 or x' is not a valid field input line: \
+                 invalid number 'x': invalid digit found in string",
+            ),
+        ),
+        (
+            "class: 1-3\n\nyour tickets:\n",
+            invalid(
+                Section::YourTicket,
+                3,
+                "expected 'your ticket:', not 'your tickets:'",
+            ),
+        ),
+        (
+            "class: 1-3\n\nyour ticket:\n1,,2\n",
+            invalid(
+                Section::YourTicket,
+                4,
+                "failed to parse value '': cannot parse integer from empty string",
+            ),
+        ),
+        (
+            "class: 1-3\n\nyour ticket:\n1\nnearby tickets:\n",
+            invalid(
+                Section::YourTicket,
+                5,
+                "expected a blank line, not 'nearby tickets:'",
+            ),
+        ),
+        (
+            "class: 1-3\n\nyour ticket:\n1\n\nnearby tickets:\n1\n2;3\n",
+            invalid(
+                Section::NearbyTickets,
+                8,
+                "failed to parse value '2;3': invalid digit found in string",
+            ),
+        ),
+    ];
+    for (input, expected) in bad_inputs {
+        match Input::parse(input) {
+            Ok(_) => return Err(format!("FAIL: {:?} should not have parsed", input)),
+            Err(e) if e != expected => {
+                return Err(format!(
+                    "FAIL: for {:?} expected error '{}', got '{}'",
+                    input, expected, e
+                ));
+            }
+            Err(_) => (),
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,1281 @@
+//! Conway Cubes (day 17): life-like cellular automata in any number
+//! of dimensions.
+use fxhash::{FxHashMap, FxHashSet};
+use serde::Serialize;
+use smallvec::SmallVec;
+use std::cmp::{max, min};
+use std::fmt;
+use std::io::{self, Write};
+use std::ops::RangeInclusive;
+use std::str::FromStr;
+use visualization::{render_grid, Canvas, BLACK, WHITE};
+
+pub type Ordinate = i64;
+pub type OrdinateRange = RangeInclusive<i64>;
+
+/// A position in the lattice; there is one ordinate for each
+/// dimension, x first.  Up to 6 dimensions are stored inline, which
+/// avoids a heap allocation for every neighbour we count.
+pub type Pos = SmallVec<[Ordinate; 6]>;
+
+// Hashing dominates the running time of the sparse engine, so we
+// use a fast (though not DoS-resistant) hash function.
+pub type PosSet = FxHashSet<Pos>;
+type PosCounter = FxHashMap<Pos, usize>;
+
+fn update_range(n: &Ordinate, r: OrdinateRange) -> OrdinateRange {
+    if r.contains(n) {
+        r
+    } else {
+        min(*n, *r.start())..=max(*n, *r.end())
+    }
+}
+
+fn range_size(r: &OrdinateRange) -> usize {
+    if r.end() < r.start() {
+        0
+    } else {
+        let result: Ordinate = r.end() - r.start() + 1;
+        assert!(result > 0);
+        result as usize
+    }
+}
+
+/// Returns every position within `ranges`.  The first ordinate
+/// varies fastest.
+pub fn positions_within(ranges: &[OrdinateRange]) -> Vec<Pos> {
+    let mut result: Vec<Pos> = vec![Pos::new()];
+    for r in ranges {
+        let mut extended = Vec::with_capacity(result.len() * range_size(r));
+        for n in r.clone() {
+            for p in result.iter() {
+                let mut q = p.clone();
+                q.push(n);
+                extended.push(q);
+            }
+        }
+        result = extended;
+    }
+    result
+}
+
+/// Returns the offsets from a cell to each of its neighbours in a
+/// lattice of `dims` dimensions.  There are 3^dims - 1 of them.
+pub fn neighbour_offsets(dims: usize) -> Vec<Pos> {
+    let unit: Vec<OrdinateRange> = (0..dims).map(|_| -1..=1).collect();
+    positions_within(&unit)
+        .into_iter()
+        // can't be my own neighbour
+        .filter(|offset| offset.iter().any(|n| *n != 0))
+        .collect()
+}
+
+/// Returns the conventional name of an axis (x, y, z, w, then d4,
+/// d5, ...).
+pub fn axis_name(axis: usize) -> String {
+    match axis {
+        0 => "x".to_string(),
+        1 => "y".to_string(),
+        2 => "z".to_string(),
+        3 => "w".to_string(),
+        n => format!("d{}", n),
+    }
+}
+
+/// The rule of a life-like automaton: an inactive cell becomes
+/// active when its number of active neighbours is one of the birth
+/// counts, and an active cell stays active when its number of active
+/// neighbours is one of the survival counts.
+///
+/// Rules are written in the usual `B3/S23` notation.  Since cells in
+/// three or more dimensions can have more than 9 neighbours, the
+/// counts may instead be separated by commas, as in `B3,10/S2,3`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Rule {
+    birth: Vec<usize>,
+    survival: Vec<usize>,
+}
+
+impl Rule {
+    pub fn new(birth: &[usize], survival: &[usize]) -> Result<Rule, String> {
+        if birth.contains(&0) {
+            // Every cell in the infinite lattice would become active.
+            return Err("a rule cannot activate cells with no active neighbours".to_string());
+        }
+        let normalise = |counts: &[usize]| -> Vec<usize> {
+            let mut v = counts.to_vec();
+            v.sort_unstable();
+            v.dedup();
+            v
+        };
+        Ok(Rule {
+            birth: normalise(birth),
+            survival: normalise(survival),
+        })
+    }
+
+    pub fn birth(&self) -> &[usize] {
+        &self.birth
+    }
+
+    pub fn survival(&self) -> &[usize] {
+        &self.survival
+    }
+
+    /// Decides whether a cell is active in the next cycle.
+    pub fn next_state(&self, active: bool, neighbours: usize) -> bool {
+        if active {
+            self.survival.contains(&neighbours)
+        } else {
+            self.birth.contains(&neighbours)
+        }
+    }
+
+    /// Returns a table indexed by `neighbours * 2 + active` which
+    /// gives the result of `next_state` for up to `max_neighbours`
+    /// neighbours.
+    fn table(&self, max_neighbours: usize) -> Vec<bool> {
+        (0..=max_neighbours)
+            .flat_map(|n| vec![self.next_state(false, n), self.next_state(true, n)])
+            .collect()
+    }
+}
+
+impl Default for Rule {
+    /// The rule of the puzzle: B3/S23, the same as Conway's Game
+    /// of Life.
+    fn default() -> Rule {
+        Rule {
+            birth: vec![3],
+            survival: vec![2, 3],
+        }
+    }
+}
+
+fn write_counts(f: &mut fmt::Formatter, counts: &[usize]) -> fmt::Result {
+    let separator = if counts.iter().any(|n| *n > 9) {
+        ","
+    } else {
+        ""
+    };
+    let counts: Vec<String> = counts.iter().map(|n| n.to_string()).collect();
+    f.write_str(&counts.join(separator))
+}
+
+impl fmt::Display for Rule {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("B")?;
+        write_counts(f, &self.birth)?;
+        f.write_str("/S")?;
+        write_counts(f, &self.survival)
+    }
+}
+
+fn parse_counts(s: &str) -> Result<Vec<usize>, String> {
+    if s.contains(',') {
+        s.split(',')
+            .map(|n| {
+                n.parse()
+                    .map_err(|e| format!("invalid neighbour count '{}': {}", n, e))
+            })
+            .collect()
+    } else {
+        s.chars()
+            .map(|ch| {
+                ch.to_digit(10)
+                    .map(|d| d as usize)
+                    .ok_or_else(|| format!("invalid neighbour count '{}'", ch))
+            })
+            .collect()
+    }
+}
+
+impl FromStr for Rule {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Rule, String> {
+        let bad = || format!("rule '{}' should look like B3/S23", s);
+        let (b, s) = match s.split_once('/') {
+            Some((b, s)) => (b, s),
+            None => return Err(bad()),
+        };
+        match (b.strip_prefix('B'), s.strip_prefix('S')) {
+            (Some(birth), Some(survival)) => {
+                Rule::new(&parse_counts(birth)?, &parse_counts(survival)?)
+            }
+            _ => Err(bad()),
+        }
+    }
+}
+
+/// Statistics describing one state of the lattice.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct Stats {
+    pub population: usize,
+    /// The smallest and largest ordinate of the active cells along
+    /// each axis; empty if there are no active cells.
+    pub bounds: Vec<(Ordinate, Ordinate)>,
+    /// The number of cells which became active in the cycle that
+    /// produced this state.
+    pub births: usize,
+    /// The number of cells which became inactive in the cycle that
+    /// produced this state.
+    pub deaths: usize,
+}
+
+impl Stats {
+    /// Describes the cells `active` (in a lattice of `dims`
+    /// dimensions), with no births or deaths.
+    fn of<'a, I>(dims: usize, active: I) -> Stats
+    where
+        I: IntoIterator<Item = &'a Pos>,
+    {
+        let mut population = 0;
+        let mut bounds: Vec<(Ordinate, Ordinate)> = Vec::new();
+        for pos in active {
+            if bounds.is_empty() {
+                bounds = pos.iter().map(|n| (*n, *n)).collect();
+            }
+            for (n, (lo, hi)) in pos.iter().zip(bounds.iter_mut()) {
+                *lo = min(*lo, *n);
+                *hi = max(*hi, *n);
+            }
+            population += 1;
+        }
+        debug_assert!(bounds.is_empty() || bounds.len() == dims);
+        Stats {
+            population,
+            bounds,
+            births: 0,
+            deaths: 0,
+        }
+    }
+}
+
+#[derive(Clone)]
+pub struct Lattice {
+    /// The range of each ordinate; there is one for each dimension.
+    ranges: Vec<OrdinateRange>,
+    active: PosSet,
+}
+
+impl Lattice {
+    pub fn empty(dims: usize) -> Lattice {
+        Lattice::with_capacity(dims, 0)
+    }
+
+    /// Returns an empty lattice with room for `capacity` active
+    /// cells.
+    pub fn with_capacity(dims: usize, capacity: usize) -> Lattice {
+        let mut active = PosSet::default();
+        active.reserve(capacity);
+        Lattice {
+            ranges: vec![0..=0; dims],
+            active,
+        }
+    }
+
+    pub fn dims(&self) -> usize {
+        self.ranges.len()
+    }
+
+    /// The bounding box of the active cells (which always includes
+    /// the origin).
+    pub fn ranges(&self) -> &[OrdinateRange] {
+        &self.ranges
+    }
+
+    pub fn active(&self) -> &PosSet {
+        &self.active
+    }
+
+    pub fn popcount(&self) -> usize {
+        self.active.len()
+    }
+
+    /// Returns the x-y slice of the lattice at the position whose
+    /// other ordinates are `higher`.
+    pub fn slice_as_str(&self, higher: &[Ordinate]) -> String {
+        let (xrange, yrange) = (&self.ranges[0], &self.ranges[1]);
+        let mut output = String::with_capacity((range_size(xrange) + 1) * range_size(yrange));
+        for y in yrange.clone() {
+            for x in xrange.clone() {
+                let mut pos: Pos = Pos::from_slice(&[x, y]);
+                pos.extend_from_slice(higher);
+                output.push(if self.active.contains(&pos) { '#' } else { '.' })
+            }
+            output.push('\n');
+        }
+        output
+    }
+
+    /// Draws the part of the x-y slice at `higher` (as for
+    /// `slice_as_str`) which lies within `xrange` and `yrange`, with
+    /// each cell as a square of `scale` pixels.
+    pub fn render_slice(
+        &self,
+        higher: &[Ordinate],
+        xrange: &OrdinateRange,
+        yrange: &OrdinateRange,
+        scale: usize,
+    ) -> Canvas {
+        let mut pos: Pos = Pos::from_slice(&[0, 0]);
+        pos.extend_from_slice(higher);
+        render_grid(range_size(xrange), range_size(yrange), scale, |x, y| {
+            let mut pos = pos.clone();
+            pos[0] = xrange.start() + x as Ordinate;
+            pos[1] = yrange.start() + y as Ordinate;
+            if self.active.contains(&pos) {
+                BLACK
+            } else {
+                WHITE
+            }
+        })
+    }
+
+    /// Parses a two-dimensional lattice.
+    pub fn from_string(s: &str) -> Result<Lattice, String> {
+        let mut result = Lattice::empty(2);
+        let mut x = 0;
+        let mut y = 0;
+        for ch in s.chars() {
+            match ch {
+                '#' => {
+                    result.insert(Pos::from_slice(&[x, y]));
+                    x += 1;
+                }
+                '.' => {
+                    x += 1;
+                }
+                '\n' => {
+                    x = 0;
+                    y += 1;
+                }
+                _ => {
+                    return Err(format!("unexpected character '{}' in input", ch));
+                }
+            }
+        }
+        Ok(result)
+    }
+
+    /// Embeds this lattice in one with `dims` dimensions; the extra
+    /// ordinates of each active cell are zero.
+    pub fn with_dims(&self, dims: usize) -> Lattice {
+        assert!(dims >= self.dims());
+        let mut result = Lattice::empty(dims);
+        for pos in self.active.iter() {
+            let mut extended = pos.clone();
+            extended.resize(dims, 0);
+            result.insert(extended);
+        }
+        result
+    }
+
+    pub fn insert(&mut self, pos: Pos) {
+        assert_eq!(pos.len(), self.dims());
+        for (n, r) in pos.iter().zip(self.ranges.iter_mut()) {
+            *r = update_range(n, r.clone());
+        }
+        self.active.insert(pos);
+    }
+
+    fn count_neighbours(&self) -> PosCounter {
+        const NEIGHBOURS_PER_ACTIVE_CELL: usize = 8;
+        let offsets = neighbour_offsets(self.dims());
+        let mut neighbour_count: PosCounter = PosCounter::default();
+        // Most of the cells counted are neighbours of more than one
+        // active cell, so this is usually enough room.
+        neighbour_count.reserve(self.active.len() * NEIGHBOURS_PER_ACTIVE_CELL);
+        for pos in self.active.iter() {
+            for offset in offsets.iter() {
+                let neighbour: Pos = pos.iter().zip(offset.iter()).map(|(p, d)| p + d).collect();
+                neighbour_count
+                    .entry(neighbour)
+                    .and_modify(|e| *e += 1)
+                    .or_insert(1);
+            }
+        }
+        neighbour_count
+    }
+
+    fn decide_actives(&self, neighbour_count: &PosCounter, rule: &Rule) -> (Lattice, Stats) {
+        // The population doesn't usually change much from one cycle to
+        // the next.
+        let mut result = Lattice::with_capacity(self.dims(), self.popcount());
+        let mut survivors = 0;
+        for p in itertools::chain(
+            // First, consider the cells that are currently active.
+            // Those with no active neighbours do not appear in
+            // neighbour_count.
+            self.active
+                .iter()
+                .filter(|currently_active| {
+                    let n = neighbour_count.get(*currently_active).copied().unwrap_or(0);
+                    rule.next_state(true, n)
+                })
+                .inspect(|_| survivors += 1),
+            // Second, consider the cells that are not currently
+            // active; only cells with at least one active neighbour
+            // can become active, and those all appear in
+            // neighbour_count.
+            neighbour_count
+                .iter()
+                .filter(|(pos, num_neighbours)| {
+                    !self.active.contains(*pos) && rule.next_state(false, **num_neighbours)
+                })
+                .map(|(pos, _)| pos),
+        ) {
+            result.insert(p.clone()) // updates ranges also.
+        }
+        let stats = Stats {
+            births: result.popcount() - survivors,
+            deaths: self.popcount() - survivors,
+            ..result.stats()
+        };
+        (result, stats)
+    }
+
+    /// Runs one cycle, returning the new state and its statistics.
+    pub fn iterate(&self, rule: &Rule) -> (Lattice, Stats) {
+        let neighbour_count = self.count_neighbours();
+        self.decide_actives(&neighbour_count, rule)
+    }
+
+    /// Returns true if the lattice is unchanged by reflection in the
+    /// plane where any one of the ordinates beyond y is zero.
+    pub fn is_mirror_symmetric(&self) -> bool {
+        self.active.iter().all(|pos| {
+            (2..pos.len()).all(|axis| {
+                let mut image = pos.clone();
+                image[axis] = -image[axis];
+                self.active.contains(&image)
+            })
+        })
+    }
+
+    /// Returns the statistics of this state, without births or
+    /// deaths.
+    pub fn stats(&self) -> Stats {
+        Stats::of(self.dims(), self.active.iter())
+    }
+
+    /// Returns the active cells whose ordinates beyond z are
+    /// `higher`, as x, y, z triples in sorted order.  In a
+    /// two-dimensional lattice, z is zero.
+    pub fn cells_3d(&self, higher: &[Ordinate]) -> Vec<[Ordinate; 3]> {
+        let mut cells: Vec<[Ordinate; 3]> = self
+            .active
+            .iter()
+            .filter(|pos| pos.get(3..).unwrap_or(&[]) == higher)
+            .map(|pos| [pos[0], pos[1], pos.get(2).copied().unwrap_or(0)])
+            .collect();
+        cells.sort_unstable();
+        cells
+    }
+
+    /// Writes the active cells whose ordinates beyond z are `higher`
+    /// as a Wavefront OBJ model, with a unit cube for each cell.
+    pub fn write_obj<W: Write>(&self, higher: &[Ordinate], out: &mut W) -> io::Result<()> {
+        // The faces of a cube, as indexes into its vertices; vertex
+        // i is at offset (i & 1, (i >> 1) & 1, (i >> 2) & 1).  The
+        // vertices of each face are anticlockwise when seen from
+        // outside.
+        const FACES: [[usize; 4]; 6] = [
+            [0, 4, 6, 2],
+            [1, 3, 7, 5],
+            [0, 1, 5, 4],
+            [2, 6, 7, 3],
+            [0, 2, 3, 1],
+            [4, 5, 7, 6],
+        ];
+        for (n, [x, y, z]) in self.cells_3d(higher).into_iter().enumerate() {
+            writeln!(out, "o cell_{}_{}_{}", x, y, z)?;
+            for i in 0..8 {
+                writeln!(
+                    out,
+                    "v {} {} {}",
+                    x + (i & 1),
+                    y + ((i >> 1) & 1),
+                    z + ((i >> 2) & 1)
+                )?;
+            }
+            for face in FACES.iter() {
+                // OBJ vertex numbers start at 1.
+                let vertices: Vec<String> =
+                    face.iter().map(|i| (n * 8 + i + 1).to_string()).collect();
+                writeln!(out, "f {}", vertices.join(" "))?;
+            }
+        }
+        Ok(())
+    }
+}
+
+impl fmt::Display for Lattice {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for higher in positions_within(&self.ranges[2..]) {
+            if !higher.is_empty() {
+                let labels: Vec<String> = higher
+                    .iter()
+                    .enumerate()
+                    .map(|(i, n)| format!("{}={}", axis_name(i + 2), n))
+                    .collect();
+                writeln!(f, "{}", labels.join(","))?;
+            }
+            write!(f, "{}", self.slice_as_str(&higher))?
+        }
+        Ok(())
+    }
+}
+
+/// A lattice stored as a flat array of cells covering a fixed
+/// region.  Active cells can spread by at most one cell per cycle, so
+/// the region is the initial bounding box plus a margin large enough
+/// for the number of cycles we intend to run.
+///
+/// A symmetric dense lattice is one which is unchanged by reflection
+/// in the plane where any of the ordinates beyond y is zero (any
+/// two-dimensional lattice embedded in more dimensions is like this,
+/// and the rules preserve the symmetry).  Only the cells whose
+/// ordinates beyond y are non-negative are stored, and each of them
+/// stands for all its mirror images.
+pub struct DenseLattice {
+    /// The position of the cell with index 0.
+    origin: Pos,
+    extents: Vec<usize>,
+    strides: Vec<usize>,
+    cells: Vec<bool>,
+    symmetric: bool,
+}
+
+/// Returns a mask of the axes beyond y for which `pred` holds of the
+/// ordinate.
+fn higher_axes_where<F>(pos: &[Ordinate], pred: F) -> u32
+where
+    F: Fn(Ordinate) -> bool,
+{
+    pos.iter()
+        .enumerate()
+        .skip(2)
+        .filter(|(_, n)| pred(**n))
+        .fold(0, |mask, (axis, _)| mask | (1 << axis))
+}
+
+impl DenseLattice {
+    /// Builds a dense lattice able to hold the result of running
+    /// `cycles` cycles on `lattice`.
+    pub fn from_lattice(lattice: &Lattice, cycles: usize) -> DenseLattice {
+        DenseLattice::new(lattice, cycles, false)
+    }
+
+    /// Like `from_lattice`, but builds a symmetric dense lattice.
+    /// `lattice` must be mirror symmetric.
+    pub fn from_symmetric_lattice(lattice: &Lattice, cycles: usize) -> DenseLattice {
+        assert!(lattice.is_mirror_symmetric());
+        DenseLattice::new(lattice, cycles, true)
+    }
+
+    fn new(lattice: &Lattice, cycles: usize, symmetric: bool) -> DenseLattice {
+        // The extra layer means that no active cell is ever on the
+        // edge, so its neighbours always have valid indexes.  In a
+        // symmetric lattice, we never look at the neighbours below
+        // zero on the higher axes, so those need no extra layer.
+        let margin = cycles as Ordinate + 1;
+        let is_halved = |axis: usize| symmetric && axis >= 2;
+        let origin: Pos = lattice
+            .ranges
+            .iter()
+            .enumerate()
+            .map(|(axis, r)| {
+                if is_halved(axis) {
+                    0
+                } else {
+                    r.start() - margin
+                }
+            })
+            .collect();
+        let extents: Vec<usize> = lattice
+            .ranges
+            .iter()
+            .enumerate()
+            .map(|(axis, r)| {
+                if is_halved(axis) {
+                    (r.end() + margin + 1) as usize
+                } else {
+                    range_size(r) + 2 * margin as usize
+                }
+            })
+            .collect();
+        let mut strides = Vec::with_capacity(extents.len());
+        let mut size = 1;
+        for extent in extents.iter() {
+            strides.push(size);
+            size *= extent;
+        }
+        let mut result = DenseLattice {
+            origin,
+            extents,
+            strides,
+            cells: vec![false; size],
+            symmetric,
+        };
+        for pos in lattice.active.iter() {
+            if symmetric && pos.iter().skip(2).any(|n| *n < 0) {
+                continue;
+            }
+            let index = result.index_of(pos);
+            result.cells[index] = true;
+        }
+        result
+    }
+
+    fn index_of(&self, pos: &Pos) -> usize {
+        pos.iter()
+            .zip(self.origin.iter())
+            .zip(self.strides.iter())
+            .map(|((p, o), stride)| (p - o) as usize * stride)
+            .sum()
+    }
+
+    fn position_of(&self, mut index: usize) -> Pos {
+        let mut pos = Pos::with_capacity(self.extents.len());
+        for (extent, o) in self.extents.iter().zip(self.origin.iter()) {
+            pos.push(o + (index % extent) as Ordinate);
+            index /= extent;
+        }
+        pos
+    }
+
+    fn is_interior(&self, index: usize) -> bool {
+        self.position_of(index)
+            .iter()
+            .zip(self.origin.iter())
+            .zip(self.extents.iter())
+            .enumerate()
+            .all(|(axis, ((p, o), extent))| {
+                let above_start = if self.symmetric && axis >= 2 {
+                    *p >= *o
+                } else {
+                    *p > *o
+                };
+                above_start && ((p - o) as usize) < extent - 1
+            })
+    }
+
+    /// The number of cells that the cell at `index` stands for.
+    fn weight(&self, index: usize) -> usize {
+        if self.symmetric {
+            1 << higher_axes_where(&self.position_of(index), |n| n != 0).count_ones()
+        } else {
+            1
+        }
+    }
+
+    fn active_indexes<'a>(&'a self) -> impl Iterator<Item = usize> + 'a {
+        self.cells
+            .iter()
+            .enumerate()
+            .filter(|(_, active)| **active)
+            .map(|(index, _)| index)
+    }
+
+    /// Returns the position of each active cell, including the mirror
+    /// images in a symmetric lattice.
+    fn active_positions(&self) -> Vec<Pos> {
+        let mut result = Vec::new();
+        for index in self.active_indexes() {
+            let pos = self.position_of(index);
+            result.push(pos.clone());
+            if self.symmetric {
+                let nonzero = higher_axes_where(&pos, |n| n != 0);
+                // Visit each non-empty subset of the nonzero axes.
+                let mut flips = nonzero;
+                while flips != 0 {
+                    let mut image = pos.clone();
+                    for (axis, n) in image.iter_mut().enumerate() {
+                        if flips & (1 << axis) != 0 {
+                            *n = -*n;
+                        }
+                    }
+                    result.push(image);
+                    flips = (flips - 1) & nonzero;
+                }
+            }
+        }
+        result
+    }
+
+    /// Runs one cycle, returning the statistics of the new state.
+    pub fn iterate(&mut self, rule: &Rule) -> Stats {
+        let offsets = neighbour_offsets(self.extents.len());
+        let table = rule.table(offsets.len());
+        // Each offset as a difference in index, along with the axes
+        // beyond y where it steps downwards.
+        let offsets: Vec<(isize, u32)> = offsets
+            .iter()
+            .map(|offset| {
+                let delta = offset
+                    .iter()
+                    .zip(self.strides.iter())
+                    .map(|(d, stride)| *d as isize * *stride as isize)
+                    .sum();
+                (delta, higher_axes_where(offset, |d| d < 0))
+            })
+            .collect();
+        let mut neighbour_count: Vec<u16> = vec![0; self.cells.len()];
+        for index in self.active_indexes() {
+            debug_assert!(self.is_interior(index));
+            if self.symmetric {
+                let pos = self.position_of(index);
+                let zero = higher_axes_where(&pos, |n| n == 0);
+                let one = higher_axes_where(&pos, |n| n == 1);
+                for (delta, down) in offsets.iter() {
+                    if down & zero != 0 {
+                        // This neighbour is not stored; its mirror
+                        // image is counted by the opposite offset.
+                        continue;
+                    }
+                    // Stepping down from 1 to 0 on an axis reaches a
+                    // cell which also neighbours our mirror image at
+                    // -1 on that axis.
+                    neighbour_count[(index as isize + delta) as usize] +=
+                        1 << (down & one).count_ones();
+                }
+            } else {
+                for (delta, _) in offsets.iter() {
+                    neighbour_count[(index as isize + delta) as usize] += 1;
+                }
+            }
+        }
+        let (mut births, mut deaths) = (0, 0);
+        for index in 0..self.cells.len() {
+            let cell = self.cells[index];
+            let next = table[neighbour_count[index] as usize * 2 + cell as usize];
+            match (cell, next) {
+                (false, true) => births += self.weight(index),
+                (true, false) => deaths += self.weight(index),
+                _ => (),
+            }
+            self.cells[index] = next;
+        }
+        Stats {
+            births,
+            deaths,
+            ..Stats::of(self.extents.len(), self.active_positions().iter())
+        }
+    }
+
+    pub fn to_lattice(&self) -> Lattice {
+        let mut result = Lattice::empty(self.extents.len());
+        for pos in self.active_positions() {
+            result.insert(pos);
+        }
+        result
+    }
+}
+
+/// The most dimensions `MemoLattice` can handle; a block then has
+/// 2^6 = 64 cells, one for each bit of a u64.
+pub const MEMO_MAX_DIMS: usize = 6;
+
+/// The shape of the neighbourhood of a block in a `MemoLattice`: the
+/// block and those around it, which is a cube of 6 cells along each
+/// axis.
+struct Neighbourhood {
+    dims: usize,
+    /// The offsets of the blocks in the neighbourhood, in the order
+    /// of `positions_within`.  The middle one is the block itself.
+    block_offsets: Vec<Pos>,
+    /// For each block in the neighbourhood and each bit of its mask,
+    /// the index of the cell in the cube.
+    cell_index: Vec<Vec<usize>>,
+    /// The differences in index between a cell and its neighbours.
+    deltas: Vec<isize>,
+    /// The cells of the cube which are at least 1 (element 0) or 2
+    /// (element 1) cells from its surface.
+    interior: [Vec<usize>; 2],
+}
+
+impl Neighbourhood {
+    fn new(dims: usize) -> Neighbourhood {
+        let strides: Vec<usize> = (0..dims).map(|axis| 6_usize.pow(axis as u32)).collect();
+        let index = |cell: &[Ordinate]| -> usize {
+            cell.iter()
+                .zip(strides.iter())
+                .map(|(c, stride)| *c as usize * stride)
+                .sum()
+        };
+        let block_offsets = positions_within(&vec![-1..=1; dims]);
+        let cell_index = block_offsets
+            .iter()
+            .map(|offset| {
+                (0..1 << dims)
+                    .map(|bit: usize| {
+                        let cell: Pos = offset
+                            .iter()
+                            .enumerate()
+                            .map(|(axis, o)| 2 * (o + 1) + ((bit >> axis) & 1) as Ordinate)
+                            .collect();
+                        index(&cell)
+                    })
+                    .collect()
+            })
+            .collect();
+        let deltas = neighbour_offsets(dims)
+            .iter()
+            .map(|offset| {
+                offset
+                    .iter()
+                    .zip(strides.iter())
+                    .map(|(d, stride)| *d as isize * *stride as isize)
+                    .sum()
+            })
+            .collect();
+        let interior = |depth: Ordinate| -> Vec<usize> {
+            positions_within(&vec![depth..=5 - depth; dims])
+                .iter()
+                .map(|cell| index(cell))
+                .collect()
+        };
+        Neighbourhood {
+            dims,
+            block_offsets,
+            cell_index,
+            deltas,
+            interior: [interior(1), interior(2)],
+        }
+    }
+
+    fn middle(&self) -> usize {
+        self.block_offsets.len() / 2
+    }
+
+    /// Packs the masks of the blocks in a neighbourhood into a key
+    /// for the memo.  Blocks have a power of two number of bits, so
+    /// none of them straddles two words.
+    fn key(&self, masks: &[u64]) -> Vec<u64> {
+        let bits = 1 << self.dims;
+        let mut key = vec![0; (masks.len() * bits).div_ceil(64)];
+        for (i, mask) in masks.iter().enumerate() {
+            key[i * bits / 64] |= mask << ((i * bits) % 64);
+        }
+        key
+    }
+
+    /// Works out the next two states of the middle block, given the
+    /// masks of the blocks in its neighbourhood.
+    fn evolve(&self, masks: &[u64], table: &[bool]) -> (u64, u64) {
+        let mut cells = vec![false; 6_usize.pow(self.dims as u32)];
+        for (mask, indexes) in masks.iter().zip(self.cell_index.iter()) {
+            for (bit, index) in indexes.iter().enumerate() {
+                cells[*index] = mask & (1 << bit) != 0;
+            }
+        }
+        let mut results = [0; 2];
+        for (step, result) in results.iter_mut().enumerate() {
+            // Cells near the surface are missing some of their
+            // neighbours, so each step leaves them out; the middle
+            // block is 2 cells deep.
+            // Most cells are inactive, so we count from the active
+            // ones.  A cell on the surface can reach past the end of
+            // an axis into the far side of the cube, but only onto
+            // another surface cell, whose count we ignore.
+            let mut counts = vec![0_usize; cells.len()];
+            for (index, _) in cells.iter().enumerate().filter(|(_, active)| **active) {
+                for delta in self.deltas.iter() {
+                    let neighbour = index as isize + delta;
+                    if neighbour >= 0 && (neighbour as usize) < counts.len() {
+                        counts[neighbour as usize] += 1;
+                    }
+                }
+            }
+            let mut next = vec![false; cells.len()];
+            for index in self.interior[step].iter() {
+                next[*index] = table[counts[*index] * 2 + cells[*index] as usize];
+            }
+            cells = next;
+            *result = self.cell_index[self.middle()]
+                .iter()
+                .enumerate()
+                .filter(|(_, index)| cells[**index])
+                .fold(0, |mask, (bit, _)| mask | (1 << bit));
+        }
+        (results[0], results[1])
+    }
+}
+
+/// An experimental lattice in the spirit of HashLife.  The lattice is
+/// divided into blocks of 2 cells along each axis, each stored as a
+/// bit mask.  The next two states of a block depend only on the
+/// states of the blocks around it, and the same neighbourhoods turn
+/// up again and again, so their outcomes are memoised.  Each lookup
+/// moves a block on by two cycles.
+pub struct MemoLattice {
+    neighbourhood: Neighbourhood,
+    table: Vec<bool>,
+    /// The non-empty blocks, keyed by the position of their cell
+    /// with the smallest ordinates, halved.
+    blocks: FxHashMap<Pos, u64>,
+    /// The next two states of the middle block of each neighbourhood
+    /// seen so far.
+    memo: FxHashMap<Vec<u64>, (u64, u64)>,
+    hits: usize,
+}
+
+impl MemoLattice {
+    /// Builds a memoising lattice which follows `rule`.  There can be
+    /// no more than `MEMO_MAX_DIMS` dimensions.
+    pub fn new(lattice: &Lattice, rule: &Rule) -> MemoLattice {
+        let dims = lattice.dims();
+        assert!(dims <= MEMO_MAX_DIMS);
+        let mut blocks: FxHashMap<Pos, u64> = FxHashMap::default();
+        for pos in lattice.active.iter() {
+            let block: Pos = pos.iter().map(|n| n.div_euclid(2)).collect();
+            let bit = pos
+                .iter()
+                .enumerate()
+                .fold(0, |bit, (axis, n)| bit | (n.rem_euclid(2) << axis));
+            *blocks.entry(block).or_insert(0) |= 1 << bit;
+        }
+        MemoLattice {
+            neighbourhood: Neighbourhood::new(dims),
+            table: rule.table(3_usize.pow(dims as u32) - 1),
+            blocks,
+            memo: FxHashMap::default(),
+            hits: 0,
+        }
+    }
+
+    fn positions_of(blocks: &FxHashMap<Pos, u64>) -> Vec<Pos> {
+        let mut result = Vec::new();
+        for (block, mask) in blocks.iter() {
+            for bit in (0..64).filter(|bit| mask & (1 << bit) != 0) {
+                result.push(
+                    block
+                        .iter()
+                        .enumerate()
+                        .map(|(axis, b)| 2 * b + ((bit >> axis) & 1))
+                        .collect(),
+                );
+            }
+        }
+        result
+    }
+
+    /// Runs `cycles` cycles, returning the statistics of each new
+    /// state.
+    pub fn advance(&mut self, cycles: usize) -> Vec<Stats> {
+        let dims = self.neighbourhood.dims;
+        let mut series = Vec::with_capacity(cycles);
+        while series.len() < cycles {
+            // Only blocks next to a non-empty block can become
+            // non-empty.
+            let candidates: FxHashSet<Pos> = self
+                .blocks
+                .keys()
+                .flat_map(|block| {
+                    self.neighbourhood.block_offsets.iter().map(move |offset| {
+                        block
+                            .iter()
+                            .zip(offset.iter())
+                            .map(|(b, o)| b + o)
+                            .collect()
+                    })
+                })
+                .collect();
+            let mut next = [FxHashMap::default(), FxHashMap::default()];
+            let mut births = [0; 2];
+            let mut deaths = [0; 2];
+            for block in candidates {
+                let masks: Vec<u64> = self
+                    .neighbourhood
+                    .block_offsets
+                    .iter()
+                    .map(|offset| {
+                        let neighbour: Pos = block
+                            .iter()
+                            .zip(offset.iter())
+                            .map(|(b, o)| b + o)
+                            .collect();
+                        self.blocks.get(&neighbour).copied().unwrap_or(0)
+                    })
+                    .collect();
+                let key = self.neighbourhood.key(&masks);
+                let outcome = match self.memo.get(&key) {
+                    Some(outcome) => {
+                        self.hits += 1;
+                        *outcome
+                    }
+                    None => {
+                        let outcome = self.neighbourhood.evolve(&masks, &self.table);
+                        self.memo.insert(key, outcome);
+                        outcome
+                    }
+                };
+                let mut previous = masks[self.neighbourhood.middle()];
+                for (step, mask) in [outcome.0, outcome.1].iter().enumerate() {
+                    births[step] += (mask & !previous).count_ones() as usize;
+                    deaths[step] += (previous & !mask).count_ones() as usize;
+                    if *mask != 0 {
+                        next[step].insert(block.clone(), *mask);
+                    }
+                    previous = *mask;
+                }
+            }
+            let steps = (cycles - series.len()).min(2);
+            for step in 0..steps {
+                series.push(Stats {
+                    births: births[step],
+                    deaths: deaths[step],
+                    ..Stats::of(dims, MemoLattice::positions_of(&next[step]).iter())
+                });
+            }
+            let [first, second] = next;
+            self.blocks = if steps == 2 { second } else { first };
+        }
+        series
+    }
+
+    /// The number of neighbourhoods whose outcome has been memoised,
+    /// and the number of times one of those outcomes was reused.
+    pub fn memo_stats(&self) -> (usize, usize) {
+        (self.memo.len(), self.hits)
+    }
+
+    pub fn to_lattice(&self) -> Lattice {
+        let mut result = Lattice::empty(self.neighbourhood.dims);
+        for pos in MemoLattice::positions_of(&self.blocks) {
+            result.insert(pos);
+        }
+        result
+    }
+}
+
+/// The representation used to run the simulation.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Engine {
+    /// Only the active cells are stored, in a hash set.
+    Sparse,
+    /// Every cell that could become active is stored, in an array.
+    Dense,
+    /// Like `Dense`, but only one of each set of mirror images is
+    /// stored.  This needs a mirror symmetric initial state.
+    Symmetric,
+    /// Blocks of cells whose evolution is memoised; see `MemoLattice`.
+    Memo,
+}
+
+impl fmt::Display for Engine {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match self {
+            Engine::Sparse => "sparse",
+            Engine::Dense => "dense",
+            Engine::Symmetric => "symmetric",
+            Engine::Memo => "memo",
+        })
+    }
+}
+
+impl FromStr for Engine {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Engine, String> {
+        match s {
+            "sparse" => Ok(Engine::Sparse),
+            "dense" => Ok(Engine::Dense),
+            "symmetric" => Ok(Engine::Symmetric),
+            "memo" => Ok(Engine::Memo),
+            _ => Err(format!("unknown engine '{}'", s)),
+        }
+    }
+}
+
+/// Runs `num_cycles` cycles of the automaton with rule `rule` in a
+/// lattice of `dims` dimensions, returning the final state.
+pub fn simulate(
+    initial: &Lattice,
+    dims: usize,
+    num_cycles: usize,
+    rule: &Rule,
+    engine: Engine,
+) -> Lattice {
+    simulate_with_stats(initial, dims, num_cycles, rule, engine).0
+}
+
+/// Like `simulate`, but also returns the statistics of each state;
+/// element `i` of the series describes the state after `i` cycles.
+pub fn simulate_with_stats(
+    initial: &Lattice,
+    dims: usize,
+    num_cycles: usize,
+    rule: &Rule,
+    engine: Engine,
+) -> (Lattice, Vec<Stats>) {
+    let mut current: Lattice = initial.with_dims(dims);
+    let mut series = Vec::with_capacity(num_cycles + 1);
+    series.push(current.stats());
+    match engine {
+        Engine::Sparse => {
+            for _iteration in 0..num_cycles {
+                let (next, stats) = current.iterate(rule);
+                current = next;
+                series.push(stats);
+            }
+            (current, series)
+        }
+        Engine::Dense | Engine::Symmetric => {
+            let mut dense = if engine == Engine::Symmetric {
+                DenseLattice::from_symmetric_lattice(&current, num_cycles)
+            } else {
+                DenseLattice::from_lattice(&current, num_cycles)
+            };
+            for _iteration in 0..num_cycles {
+                series.push(dense.iterate(rule));
+            }
+            (dense.to_lattice(), series)
+        }
+        Engine::Memo => {
+            let mut memo = MemoLattice::new(&current, rule);
+            series.extend(memo.advance(num_cycles));
+            (memo.to_lattice(), series)
+        }
+    }
+}
+
+pub fn self_test() -> Result<(), String> {
+    for dims in 2..=5 {
+        let expected = 3_usize.pow(dims as u32) - 1;
+        let got = neighbour_offsets(dims).len();
+        if got != expected {
+            return Err(format!(
+                "FAIL: expected {} neighbours in {} dimensions, got {}",
+                expected, dims, got
+            ));
+        }
+    }
+    for (text, birth, survival) in &[
+        ("B3/S23", &[3][..], &[2, 3][..]),
+        ("B36/S23", &[3, 6], &[2, 3]),
+        ("B3,10/S", &[3, 10], &[]),
+        ("B2/S0,13,26", &[2], &[0, 13, 26]),
+    ] {
+        let rule: Rule = text.parse()?;
+        if rule.birth() != *birth || rule.survival() != *survival {
+            return Err(format!("FAIL: rule {} was parsed as {:?}", text, rule));
+        }
+        if rule.to_string() != *text {
+            return Err(format!("FAIL: rule {} was displayed as {}", text, rule));
+        }
+    }
+    for bad in &["B3S23", "S23/B3", "B3/S2x", "B0/S23", "B3,/S2"] {
+        if bad.parse::<Rule>().is_ok() {
+            return Err(format!("FAIL: '{}' should not be a valid rule", bad));
+        }
+    }
+    let life = Rule::default();
+    let sample = Lattice::from_string(".#.\n..#\n###\n")?;
+    let (_, series) = simulate_with_stats(&sample, 3, 1, &life, Engine::Sparse);
+    let expected = vec![
+        Stats {
+            population: 5,
+            bounds: vec![(0, 2), (0, 2), (0, 0)],
+            births: 0,
+            deaths: 0,
+        },
+        // See the worked example in the puzzle.
+        Stats {
+            population: 11,
+            bounds: vec![(0, 2), (1, 3), (-1, 1)],
+            births: 8,
+            deaths: 2,
+        },
+    ];
+    if series != expected {
+        return Err(format!(
+            "FAIL: wrong statistics for the sample: {:?}",
+            series
+        ));
+    }
+    let mut obj: Vec<u8> = Vec::new();
+    sample
+        .with_dims(4)
+        .write_obj(&[0], &mut obj)
+        .map_err(|e| e.to_string())?;
+    let obj = String::from_utf8(obj).map_err(|e| e.to_string())?;
+    let count_lines = |prefix: &str| obj.lines().filter(|l| l.starts_with(prefix)).count();
+    if (count_lines("o "), count_lines("v "), count_lines("f ")) != (5, 40, 30) {
+        return Err(format!("FAIL: wrong OBJ output for the sample:\n{}", obj));
+    }
+    let last_cube_end = concat!(
+        "v 3 3 1\n",
+        "f 33 37 39 35\n",
+        "f 34 36 40 38\n",
+        "f 33 34 38 37\n",
+        "f 35 39 40 36\n",
+        "f 33 35 36 34\n",
+        "f 37 38 40 39\n"
+    );
+    if !obj.ends_with(last_cube_end) {
+        return Err(format!(
+            "FAIL: wrong final cube in the OBJ output:\n{}",
+            obj
+        ));
+    }
+    for engine in &[
+        Engine::Sparse,
+        Engine::Dense,
+        Engine::Symmetric,
+        Engine::Memo,
+    ] {
+        for (dims, expected) in &[(3, 112), (4, 848)] {
+            let got = simulate(&sample, *dims, 6, &life, *engine).popcount();
+            if got != *expected {
+                return Err(format!(
+                    "FAIL: expected population {} in {} dimensions with the {} engine, got {}",
+                    expected, dims, engine, got
+                ));
+            }
+        }
+    }
+    // The engines should agree on every cell, not just the count.
+    for rule in &[life.clone(), "B36/S23".parse()?, "B4,5/S0,5".parse()?] {
+        for dims in 2..=4 {
+            for cycles in 0..=3 {
+                let (sparse, sparse_stats) =
+                    simulate_with_stats(&sample, dims, cycles, rule, Engine::Sparse);
+                if !sparse.is_mirror_symmetric() {
+                    return Err(format!(
+                        "FAIL: {} cycles of {} in {} dimensions broke the symmetry",
+                        cycles, rule, dims
+                    ));
+                }
+                for engine in &[Engine::Dense, Engine::Symmetric, Engine::Memo] {
+                    let (other, other_stats) =
+                        simulate_with_stats(&sample, dims, cycles, rule, *engine);
+                    if sparse.active != other.active || sparse_stats != other_stats {
+                        return Err(format!(
+                            "FAIL: the {} engine disagrees after {} cycles of {} in {} dimensions",
+                            engine, cycles, rule, dims
+                        ));
+                    }
+                }
+            }
+        }
+    }
+    // The memo engine is meant for long runs, in which
+    // neighbourhoods turn up again.
+    let (sparse, sparse_stats) = simulate_with_stats(&sample, 3, 12, &life, Engine::Sparse);
+    let mut memo = MemoLattice::new(&sample.with_dims(3), &life);
+    let memo_stats = memo.advance(12);
+    if memo.to_lattice().active != sparse.active || memo_stats[..] != sparse_stats[1..] {
+        return Err("FAIL: the memo engine disagrees after 12 cycles".to_string());
+    }
+    let (seen, hits) = memo.memo_stats();
+    if hits == 0 {
+        return Err(format!(
+            "FAIL: none of the {} neighbourhoods seen was reused",
+            seen
+        ));
+    }
+    Ok(())
+}
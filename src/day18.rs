@@ -0,0 +1,1176 @@
+//! Parsing and evaluation of the arithmetic expressions of day 18,
+//! with configurable operator precedence and associativity.
+use crate::rng::Lcg;
+use serde::Serialize;
+use std::collections::{BTreeMap, HashMap};
+use std::fmt;
+use std::fs;
+use std::str::FromStr;
+use thiserror::Error;
+
+/// A range of character (not byte) positions within a line of input,
+/// from `start` up to but not including `end`.  The end of the input
+/// is represented by an empty span at the length of the line.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct Span {
+    pub start: usize,
+    pub end: usize,
+}
+
+impl Span {
+    pub fn new(start: usize, end: usize) -> Span {
+        Span { start, end }
+    }
+}
+
+/// A syntax error in a line of input.
+#[derive(Error, Debug, Clone, PartialEq, Eq)]
+#[error("[{code}] {message}")]
+pub struct ParseError {
+    /// A stable identifier for the kind of error, such as "E1100".
+    pub code: &'static str,
+    pub message: String,
+    /// The part of the input at which the error was detected.
+    pub span: Span,
+}
+
+impl ParseError {
+    fn new(code: &'static str, message: String, span: Span) -> ParseError {
+        ParseError {
+            code,
+            message,
+            span,
+        }
+    }
+
+    /// Shows the line of input in which the error occurred, with the
+    /// offending part marked by carets on the line below it.
+    pub fn render(&self, source: &str) -> String {
+        let width = self.span.end.saturating_sub(self.span.start).max(1);
+        format!(
+            "{}\n{}{}",
+            source,
+            " ".repeat(self.span.start),
+            "^".repeat(width)
+        )
+    }
+}
+
+impl From<ParseError> for String {
+    fn from(e: ParseError) -> String {
+        e.to_string()
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    Number(i64),
+    LeftParen,
+    RightParen,
+    Operator(char),
+    Identifier(String),
+    Let,
+    Equals,
+}
+
+struct Lexer {
+    data: Vec<char>,
+    pos: usize,
+    current: Option<Token>,
+    /// The position of the current token.
+    span: Span,
+}
+
+impl Lexer {
+    fn new(s: &str) -> Result<Lexer, ParseError> {
+        let mut result = Lexer {
+            data: s.chars().collect(),
+            pos: 0,
+            current: None,
+            span: Span::default(),
+        };
+        result.consume()?;
+        Ok(result)
+    }
+
+    fn unread_input(&self) -> String {
+        (self.pos..self.data.len()).map(|i| self.data[i]).collect()
+    }
+
+    fn next(&self) -> Option<Token> {
+        self.current.clone()
+    }
+
+    fn span(&self) -> Span {
+        self.span
+    }
+
+    fn consume(&mut self) -> Result<(), ParseError> {
+        // Skip any spaces ...
+        while self.pos != self.data.len() && self.data[self.pos] == ' ' {
+            self.pos += 1;
+        }
+        let start = self.pos;
+        // ... and bail out if we already reached end-of-input ...
+        if self.pos == self.data.len() {
+            self.current = None;
+            self.span = Span::new(start, start);
+            return Ok(());
+        }
+        // ... otherwise replace current with the next token.
+        let ch = self.data[self.pos];
+        self.pos += 1;
+        let token = match ch {
+            '(' => Token::LeftParen,
+            ')' => Token::RightParen,
+            '+' | '-' | '*' | '/' => Token::Operator(ch),
+            '=' => Token::Equals,
+            _ if ch.is_alphabetic() || ch == '_' => {
+                while self.pos < self.data.len()
+                    && (self.data[self.pos].is_alphanumeric() || self.data[self.pos] == '_')
+                {
+                    self.pos += 1;
+                }
+                let name: String = self.data[start..self.pos].iter().collect();
+                if name == "let" {
+                    Token::Let
+                } else {
+                    Token::Identifier(name)
+                }
+            }
+            _ if ch.is_ascii_digit() => {
+                while self.pos < self.data.len() && self.data[self.pos].is_ascii_digit() {
+                    self.pos += 1;
+                }
+                let digits: String = self.data[start..self.pos].iter().collect();
+                match digits.parse() {
+                    Ok(n) => Token::Number(n),
+                    Err(_) => {
+                        return Err(ParseError::new(
+                            "E0110",
+                            format!("number {} is too large", digits),
+                            Span::new(start, self.pos),
+                        ));
+                    }
+                }
+            }
+            _ => {
+                return Err(ParseError::new(
+                    "E0100",
+                    format!("unexpected character '{}' in input", ch),
+                    Span::new(start, self.pos),
+                ));
+            }
+        };
+        self.current = Some(token);
+        self.span = Span::new(start, self.pos);
+        Ok(())
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub enum Expr {
+    Constant(i64),                  // some integer
+    Variable(String),               // a name bound by an earlier let
+    Neg(Box<Expr>),                 // unary minus
+    Op(Box<Expr>, char, Box<Expr>), // some binary expression
+}
+
+/// A line of input.
+#[derive(Debug)]
+pub enum Statement {
+    /// `let name = expr` binds a name for the lines which follow.
+    Let(String, Expr),
+    /// An expression whose value counts towards the total.
+    Expr(Expr),
+}
+
+/// The variables bound by earlier lines of the input.  Each part
+/// starts with no variables.  A later `let` of the same name replaces
+/// the binding, but only for the lines which follow it.
+pub type Environment = HashMap<String, i64>;
+
+impl Expr {
+    /// Writes the tree with one node per line, each child indented
+    /// under its parent.
+    fn write_tree(&self, f: &mut dyn fmt::Write, depth: usize) -> fmt::Result {
+        let indent = "  ".repeat(depth);
+        match self {
+            Expr::Constant(n) => writeln!(f, "{}{}", indent, n),
+            Expr::Variable(name) => writeln!(f, "{}{}", indent, name),
+            Expr::Neg(e) => {
+                writeln!(f, "{}neg", indent)?;
+                e.write_tree(f, depth + 1)
+            }
+            Expr::Op(e1, op, e2) => {
+                writeln!(f, "{}{}", indent, op)?;
+                e1.write_tree(f, depth + 1)?;
+                e2.write_tree(f, depth + 1)
+            }
+        }
+    }
+
+    pub fn to_tree(&self) -> String {
+        let mut result = String::new();
+        self.write_tree(&mut result, 0)
+            .expect("writing to a String cannot fail");
+        result
+    }
+}
+
+// Displays the expression as an S-expression, which shows the
+// structure the parser chose.
+impl fmt::Display for Expr {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Expr::Constant(n) => write!(f, "{}", n),
+            Expr::Variable(name) => f.write_str(name),
+            Expr::Neg(e) => write!(f, "(neg {})", e),
+            Expr::Op(e1, op, e2) => write!(f, "({} {} {})", op, e1, e2),
+        }
+    }
+}
+
+/// The binary operators the evaluator knows how to compute.
+pub const OPERATORS: &str = "+-*/";
+
+/// The highest precedence an operator can have.
+pub const MAX_PRECEDENCE: i64 = 999;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Associativity {
+    Left,
+    Right,
+}
+
+impl fmt::Display for Associativity {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match self {
+            Associativity::Left => "left",
+            Associativity::Right => "right",
+        })
+    }
+}
+
+impl FromStr for Associativity {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Associativity, String> {
+        match s {
+            "left" => Ok(Associativity::Left),
+            "right" => Ok(Associativity::Right),
+            _ => Err(format!("unknown associativity '{}'", s)),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct OperatorRule {
+    pub precedence: i64,
+    pub associativity: Associativity,
+}
+
+/// The precedence and associativity of each binary operator.
+/// Operators missing from the table are rejected by the parser.
+#[derive(Debug, Clone, Default)]
+pub struct Rules {
+    pub operators: BTreeMap<char, OperatorRule>,
+}
+
+impl Rules {
+    /// Builds rules in which every operator is left-associative,
+    /// from a list of operators and their precedences.
+    pub fn left_associative(precedences: &[(char, i64)]) -> Rules {
+        let mut rules = Rules::default();
+        for (op, precedence) in precedences {
+            rules.operators.insert(
+                *op,
+                OperatorRule {
+                    precedence: *precedence,
+                    associativity: Associativity::Left,
+                },
+            );
+        }
+        rules
+    }
+
+    /// The rules of part 1: everything has the same precedence.
+    pub fn part1() -> Rules {
+        Rules::left_associative(&[('+', 20), ('-', 20), ('*', 20), ('/', 20)])
+    }
+
+    /// The rules of part 2: addition (and subtraction) bind more
+    /// tightly than multiplication (and division).
+    pub fn part2() -> Rules {
+        Rules::left_associative(&[('+', 30), ('-', 30), ('*', 20), ('/', 20)])
+    }
+
+    /// Adds (or replaces) the rule for an operator, given as
+    /// "OPERATOR PRECEDENCE [left|right]".  Operators are
+    /// left-associative unless otherwise specified.
+    pub fn add(&mut self, spec: &str) -> Result<(), String> {
+        let fields: Vec<&str> = spec.split_whitespace().collect();
+        let (op, precedence, associativity) = match fields.as_slice() {
+            [op, precedence] => (op, precedence, Associativity::Left),
+            [op, precedence, associativity] => (op, precedence, associativity.parse()?),
+            _ => {
+                return Err(format!(
+                    "operator rule '{}' should look like '+ 20 left'",
+                    spec
+                ))
+            }
+        };
+        let op: char = match op.chars().collect::<Vec<char>>().as_slice() {
+            [ch] if OPERATORS.contains(*ch) => *ch,
+            _ => return Err(format!("unsupported operator '{}'", op)),
+        };
+        let precedence: i64 = precedence
+            .parse()
+            .map_err(|e| format!("invalid precedence '{}': {}", precedence, e))?;
+        if !(0..=MAX_PRECEDENCE).contains(&precedence) {
+            return Err(format!(
+                "precedence {} should be between 0 and {}",
+                precedence, MAX_PRECEDENCE
+            ));
+        }
+        self.operators.insert(
+            op,
+            OperatorRule {
+                precedence,
+                associativity,
+            },
+        );
+        Ok(())
+    }
+
+    /// Reads rules from a file with one operator per line (in the
+    /// format accepted by `add`).  Blank lines and comments starting
+    /// with '#' are ignored.
+    pub fn load(&mut self, filename: &str) -> Result<(), String> {
+        let text = fs::read_to_string(filename)
+            .map_err(|e| format!("failed to read {}: {}", filename, e))?;
+        for (n, line) in text.lines().enumerate() {
+            let line = line.split('#').next().unwrap_or("").trim();
+            if !line.is_empty() {
+                self.add(line)
+                    .map_err(|e| format!("{} line {}: {}", filename, n + 1, e))?;
+            }
+        }
+        Ok(())
+    }
+}
+
+impl fmt::Display for Rules {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for (op, rule) in self.operators.iter() {
+            writeln!(f, "{} {} {}", op, rule.precedence, rule.associativity)?;
+        }
+        Ok(())
+    }
+}
+
+pub struct Parser {
+    pub rules: Rules,
+}
+
+// A recursive descent parser for arithmetic expressions which uses
+// configurable precedence values to avoid having separate
+// implementations for parts 1 and 2.
+//
+// Based on http://www.engr.mun.ca/~theo/Misc/exp_parsing.htm
+impl Parser {
+    pub fn new(rules: Rules) -> Parser {
+        Parser { rules }
+    }
+
+    // The precedence of b, which was found at span.
+    fn prec(&self, b: &Option<Token>, span: Span) -> Result<i64, ParseError> {
+        match b {
+            None => Ok(-1),
+            Some(Token::Operator(ch)) => match self.rules.operators.get(ch) {
+                Some(rule) => Ok(rule.precedence),
+                None => Err(ParseError::new(
+                    "E0200",
+                    format!("unknown operator {}", ch),
+                    span,
+                )),
+            },
+            Some(t) => Err(ParseError::new(
+                "E0300",
+                format!("unexpected token {:?}", t),
+                span,
+            )),
+        }
+    }
+
+    fn is_right_associative(&self, b: &Option<Token>) -> bool {
+        match b {
+            Some(Token::Operator(ch)) => self
+                .rules
+                .operators
+                .get(ch)
+                .is_some_and(|rule| rule.associativity == Associativity::Right),
+            _ => false,
+        }
+    }
+
+    // The precedence of the right operand of b.
+    fn right_prec(&self, b: &Option<Token>, span: Span) -> Result<i64, ParseError> {
+        let right = self.is_right_associative(b);
+        self.prec(b, span)
+            .map(|p| if p < 0 || right { p } else { p + 1 })
+    }
+
+    // The highest precedence of an operator which may follow the
+    // right operand of b.
+    fn next_prec(&self, b: &Option<Token>, span: Span) -> Result<i64, ParseError> {
+        let right = self.is_right_associative(b);
+        self.prec(b, span).map(|p| if right { p - 1 } else { p })
+    }
+
+    fn prec_is_between(&self, low: i64, lex: &Lexer, high: i64) -> bool {
+        if let Ok(p) = self.prec(&lex.next(), lex.span()) {
+            p >= low && p <= high
+        } else {
+            false
+        }
+    }
+
+    fn parse_paren_expr(&self, lex: &mut Lexer) -> Result<Expr, ParseError> {
+        let open = lex.span();
+        lex.consume()?; // consume the (
+        let r = self.parse_expression(0, lex)?; // and the contents
+        match lex.next() {
+            Some(Token::RightParen) => {
+                // and finally the closing )
+                lex.consume()?;
+                Ok(r)
+            }
+            _ => Err(ParseError::new(
+                "E0400",
+                format!(
+                    "expected a ')' to match the '(' at column {}",
+                    open.start + 1
+                ),
+                lex.span(),
+            )),
+        }
+    }
+
+    fn parse_operand(&self, lex: &mut Lexer) -> Result<Expr, ParseError> {
+        match lex.next() {
+            Some(Token::LeftParen) => self.parse_paren_expr(lex),
+            Some(Token::Number(n)) => {
+                lex.consume()?;
+                Ok(Expr::Constant(n))
+            }
+            Some(Token::Identifier(name)) => {
+                lex.consume()?;
+                Ok(Expr::Variable(name))
+            }
+            Some(Token::Operator('-')) => {
+                // Unary minus binds more tightly than any binary
+                // operator.
+                lex.consume()?;
+                Ok(Expr::Neg(Box::new(self.parse_operand(lex)?)))
+            }
+            _ => Err(ParseError::new(
+                "E0600",
+                "expected a number, a name or an open parenthesis".to_string(),
+                lex.span(),
+            )),
+        }
+    }
+
+    fn parse_expression(&self, precedence: i64, lex: &mut Lexer) -> Result<Expr, ParseError> {
+        assert!(precedence >= 0);
+        let mut lhs: Expr = self.parse_operand(lex)?;
+        let mut stop_at_prec: i64 = MAX_PRECEDENCE + 1;
+        while self.prec_is_between(precedence, lex, stop_at_prec) {
+            let operator = lex.next();
+            let span = lex.span();
+            lex.consume()?;
+            lhs = match operator {
+                Some(Token::Operator(ch)) if OPERATORS.contains(ch) => {
+                    let prec = self.right_prec(&operator, span)?;
+                    let rhs = self.parse_expression(prec, lex)?;
+                    Expr::Op(Box::new(lhs), ch, Box::new(rhs))
+                }
+                _ => {
+                    return Err(ParseError::new(
+                        "E1050",
+                        format!("expected '+', '-', '*' or '/', got '{:?}'", operator),
+                        span,
+                    ));
+                }
+            };
+            stop_at_prec = self.next_prec(&operator, span)?;
+        }
+        Ok(lhs)
+    }
+
+    fn check_at_end(&self, lex: &Lexer) -> Result<(), ParseError> {
+        match lex.next() {
+            None => Ok(()),
+            Some(token) => {
+                let span = lex.span();
+                Err(ParseError::new(
+                    "E1100",
+                    format!(
+                        "unexpected {:?}; unread input is '{}'",
+                        token,
+                        lex.unread_input()
+                    ),
+                    // Everything from the unexpected token onwards.
+                    Span::new(span.start, lex.data.len()),
+                ))
+            }
+        }
+    }
+
+    pub fn parse(&self, expr_str: &str) -> Result<Expr, ParseError> {
+        let mut lex = Lexer::new(expr_str)?;
+        let tree = self.parse_expression(0, &mut lex)?;
+        // check we parsed the whole expression
+        self.check_at_end(&lex)?;
+        Ok(tree)
+    }
+
+    pub fn parse_statement(&self, line: &str) -> Result<Statement, ParseError> {
+        let mut lex = Lexer::new(line)?;
+        if lex.next() != Some(Token::Let) {
+            let tree = self.parse_expression(0, &mut lex)?;
+            self.check_at_end(&lex)?;
+            return Ok(Statement::Expr(tree));
+        }
+        lex.consume()?;
+        let name = match lex.next() {
+            Some(Token::Identifier(name)) => name,
+            other => {
+                return Err(ParseError::new(
+                    "E1200",
+                    format!("expected a name after let, got {:?}", other),
+                    lex.span(),
+                ))
+            }
+        };
+        lex.consume()?;
+        if lex.next() != Some(Token::Equals) {
+            return Err(ParseError::new(
+                "E1210",
+                format!("expected '=' after let {}", name),
+                lex.span(),
+            ));
+        }
+        lex.consume()?;
+        let tree = self.parse_expression(0, &mut lex)?;
+        self.check_at_end(&lex)?;
+        Ok(Statement::Let(name, tree))
+    }
+}
+
+pub fn eval(tree: &Expr, env: &Environment) -> Result<i64, String> {
+    match tree {
+        Expr::Constant(n) => Ok(*n),
+        Expr::Variable(name) => lookup(name, env),
+        Expr::Neg(e) => Ok(-eval(e, env)?),
+        Expr::Op(e1, op, e2) => {
+            let v1 = eval(e1, env)?;
+            let v2 = eval(e2, env)?;
+            match op {
+                '+' => Ok(v1 + v2),
+                '-' => Ok(v1 - v2),
+                '*' => Ok(v1 * v2),
+                '/' => divide(v1, v2),
+                _ => {
+                    panic!("unexpected operator '{}'", op);
+                }
+            }
+        }
+    }
+}
+
+/// Integer division, rounding towards zero.
+fn divide(v1: i64, v2: i64) -> Result<i64, String> {
+    if v2 == 0 {
+        Err(format!("[E2000] division by zero: {} / {}", v1, v2))
+    } else {
+        Ok(v1 / v2)
+    }
+}
+
+fn lookup(name: &str, env: &Environment) -> Result<i64, String> {
+    env.get(name)
+        .copied()
+        .ok_or_else(|| format!("[E2100] {} has not been defined", name))
+}
+
+/// An instruction for the stack machine which runs compiled
+/// expressions.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Instruction {
+    /// Pushes a constant.
+    Push(i64),
+    /// Pushes the value of a variable.
+    Load(String),
+    /// Negates the value on top of the stack.
+    Neg,
+    /// The binary operators pop the right operand and then the left
+    /// one, and push the result.
+    Add,
+    Sub,
+    Mul,
+    Div,
+}
+
+/// An expression compiled into instructions for a stack machine.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Program {
+    code: Vec<Instruction>,
+    /// The largest number of values on the stack at any one time.
+    max_depth: usize,
+}
+
+impl Program {
+    pub fn compile(tree: &Expr) -> Program {
+        let mut program = Program {
+            code: Vec::new(),
+            max_depth: 0,
+        };
+        program.emit(tree, 0);
+        program
+    }
+
+    // Appends the code for tree, which will run with depth values
+    // already on the stack.
+    fn emit(&mut self, tree: &Expr, depth: usize) {
+        match tree {
+            Expr::Constant(n) => self.code.push(Instruction::Push(*n)),
+            Expr::Variable(name) => self.code.push(Instruction::Load(name.clone())),
+            Expr::Neg(e) => {
+                self.emit(e, depth);
+                self.code.push(Instruction::Neg);
+                return;
+            }
+            Expr::Op(e1, op, e2) => {
+                self.emit(e1, depth);
+                self.emit(e2, depth + 1);
+                self.code.push(match op {
+                    '+' => Instruction::Add,
+                    '-' => Instruction::Sub,
+                    '*' => Instruction::Mul,
+                    '/' => Instruction::Div,
+                    _ => {
+                        panic!("unexpected operator '{}'", op);
+                    }
+                });
+                return;
+            }
+        }
+        self.max_depth = self.max_depth.max(depth + 1);
+    }
+
+    pub fn instructions(&self) -> &[Instruction] {
+        &self.code
+    }
+
+    pub fn run(&self, env: &Environment) -> Result<i64, String> {
+        let mut stack: Vec<i64> = Vec::with_capacity(self.max_depth);
+        for instruction in self.code.iter() {
+            let value = match instruction {
+                Instruction::Push(n) => *n,
+                Instruction::Load(name) => lookup(name, env)?,
+                Instruction::Neg => -stack.pop().expect("stack underflow"),
+                _ => {
+                    let v2 = stack.pop().expect("stack underflow");
+                    let v1 = stack.pop().expect("stack underflow");
+                    match instruction {
+                        Instruction::Add => v1 + v2,
+                        Instruction::Sub => v1 - v2,
+                        Instruction::Mul => v1 * v2,
+                        _ => divide(v1, v2)?,
+                    }
+                }
+            };
+            stack.push(value);
+        }
+        assert_eq!(stack.len(), 1);
+        Ok(stack[0])
+    }
+}
+
+type Evaluator = Box<dyn Fn(&Environment) -> Result<i64, String>>;
+
+/// An expression compiled into nested closures, one for each node of
+/// the tree.  Unlike `eval`, this decides what to do at each node
+/// just once, when the expression is compiled.
+pub struct Closure {
+    evaluate: Evaluator,
+}
+
+impl Closure {
+    pub fn compile(tree: &Expr) -> Closure {
+        Closure {
+            evaluate: Closure::build(tree),
+        }
+    }
+
+    fn build(tree: &Expr) -> Evaluator {
+        match tree {
+            Expr::Constant(n) => {
+                let n = *n;
+                Box::new(move |_| Ok(n))
+            }
+            Expr::Variable(name) => {
+                let name = name.clone();
+                Box::new(move |env| lookup(&name, env))
+            }
+            Expr::Neg(e) => {
+                let e = Closure::build(e);
+                Box::new(move |env| Ok(-e(env)?))
+            }
+            Expr::Op(e1, op, e2) => {
+                let e1 = Closure::build(e1);
+                let e2 = Closure::build(e2);
+                match op {
+                    '+' => Box::new(move |env| Ok(e1(env)? + e2(env)?)),
+                    '-' => Box::new(move |env| Ok(e1(env)? - e2(env)?)),
+                    '*' => Box::new(move |env| Ok(e1(env)? * e2(env)?)),
+                    '/' => Box::new(move |env| divide(e1(env)?, e2(env)?)),
+                    _ => {
+                        panic!("unexpected operator '{}'", op);
+                    }
+                }
+            }
+        }
+    }
+
+    pub fn run(&self, env: &Environment) -> Result<i64, String> {
+        (self.evaluate)(env)
+    }
+}
+
+/// The ways in which expressions can be evaluated.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Engine {
+    /// Walk the expression tree (`eval`).
+    Tree,
+    /// Compile the expression into closures (`Closure`).
+    Closure,
+    /// Compile the expression and run it on a stack machine
+    /// (`Program`).
+    Vm,
+}
+
+impl Engine {
+    pub fn all() -> &'static [Engine] {
+        &[Engine::Tree, Engine::Closure, Engine::Vm]
+    }
+
+    pub fn evaluate(&self, tree: &Expr, env: &Environment) -> Result<i64, String> {
+        match self {
+            Engine::Tree => eval(tree, env),
+            Engine::Closure => Closure::compile(tree).run(env),
+            Engine::Vm => Program::compile(tree).run(env),
+        }
+    }
+}
+
+impl fmt::Display for Engine {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match self {
+            Engine::Tree => "tree",
+            Engine::Closure => "closure",
+            Engine::Vm => "vm",
+        })
+    }
+}
+
+impl FromStr for Engine {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Engine, String> {
+        match s {
+            "tree" => Ok(Engine::Tree),
+            "closure" => Ok(Engine::Closure),
+            "vm" => Ok(Engine::Vm),
+            _ => Err(format!("unknown engine '{}'", s)),
+        }
+    }
+}
+
+/// Generates random expressions for differential testing of the
+/// parser.  Each expression comes in two forms: as it would be written,
+/// and with every binary operation parenthesized in the order given by
+/// the rules, which a correct parser must treat the same way.  The
+/// second form works out the grouping by repeatedly reducing the
+/// highest-precedence operator, which is quite unlike the way the
+/// parser does it.
+struct ExprGenerator<'a> {
+    rules: &'a Rules,
+    operators: Vec<char>,
+    rng: Lcg,
+}
+
+impl<'a> ExprGenerator<'a> {
+    fn new(rules: &'a Rules, seed: u64) -> ExprGenerator<'a> {
+        ExprGenerator {
+            rules,
+            operators: rules.operators.keys().copied().collect(),
+            rng: Lcg::new(seed),
+        }
+    }
+
+    fn random(&mut self, limit: usize) -> usize {
+        self.rng.below(limit)
+    }
+
+    // Small numbers and shallow nesting keep the values of the
+    // expressions well within the range of i64.
+    fn operand(&mut self, depth: usize) -> (String, String) {
+        match self.random(6) {
+            0 if depth < 2 => {
+                let (text, reference) = self.expression(depth + 1);
+                (format!("({})", text), format!("({})", reference))
+            }
+            1 => {
+                let (text, reference) = self.operand(depth);
+                (format!("-{}", text), format!("(-{})", reference))
+            }
+            _ => {
+                let n = self.random(5).to_string();
+                (n.clone(), n)
+            }
+        }
+    }
+
+    fn expression(&mut self, depth: usize) -> (String, String) {
+        let (mut text, reference) = self.operand(depth);
+        let mut operands = vec![reference];
+        let mut operators: Vec<char> = Vec::new();
+        for _ in 0..self.random(3) {
+            let choice = self.random(self.operators.len());
+            let op = self.operators[choice];
+            let (operand_text, operand_reference) = self.operand(depth);
+            text = format!("{} {} {}", text, op, operand_text);
+            operators.push(op);
+            operands.push(operand_reference);
+        }
+        while !operators.is_empty() {
+            // Reduce the operator which binds most tightly; of those
+            // with equal precedence, the leftmost if they associate to
+            // the left and the rightmost otherwise.
+            let rule_of = |i: usize| self.rules.operators[&operators[i]];
+            let best = (0..operators.len())
+                .map(rule_of)
+                .map(|rule| rule.precedence)
+                .max()
+                .expect("there is at least one operator");
+            let candidates = (0..operators.len()).filter(|i| rule_of(*i).precedence == best);
+            let i = match rule_of(candidates.clone().next().unwrap()).associativity {
+                Associativity::Left => candidates.min().unwrap(),
+                Associativity::Right => candidates.max().unwrap(),
+            };
+            let op = operators.remove(i);
+            let rhs = operands.remove(i + 1);
+            operands[i] = format!("({} {} {})", operands[i], op, rhs);
+        }
+        (text, operands.remove(0))
+    }
+}
+
+/// Checks that the parser groups random expressions in the same way as
+/// `ExprGenerator`, for each rule set.
+fn self_test_random() -> Result<(), String> {
+    let mut right = Rules::default();
+    for spec in &["+ 10", "* 20 right", "/ 20 right", "- 30 right"] {
+        right.add(spec)?;
+    }
+    let rule_sets = [
+        ("part 1", Rules::part1()),
+        ("part 2", Rules::part2()),
+        ("right", right),
+    ];
+    for (name, rules) in rule_sets.iter() {
+        let parser = Parser::new(rules.clone());
+        let mut generator = ExprGenerator::new(rules, 20201218);
+        for _ in 0..500 {
+            let (text, reference) = generator.expression(0);
+            let parse = |input: &str| {
+                parser.parse(input).map_err(|e| {
+                    format!(
+                        "FAIL: with the {} rules, '{}' should parse but got {}",
+                        name, input, e
+                    )
+                })
+            };
+            let got = parse(&text)?;
+            let expected = parse(&reference)?;
+            if got.to_string() != expected.to_string() {
+                return Err(format!(
+                    "FAIL: with the {} rules, '{}' should parse as {} (that is, {}) but got {}",
+                    name, text, expected, reference, got
+                ));
+            }
+            let env = Environment::new();
+            if eval(&got, &env) != eval(&expected, &env) {
+                return Err(format!(
+                    "FAIL: with the {} rules, '{}' and '{}' should have the same value",
+                    name, text, reference
+                ));
+            }
+        }
+    }
+    Ok(())
+}
+
+fn self_test_spans(p: &Parser) -> Result<(), String> {
+    let cases: &[(&str, &str, &str)] = &[
+        ("1 + 2)", "E1100", "1 + 2)\n     ^"),
+        ("1 + * 2", "E0600", "1 + * 2\n    ^"),
+        ("(1 + 2", "E0400", "(1 + 2\n      ^"),
+        ("3 $ 4", "E0100", "3 $ 4\n  ^"),
+        (
+            "1 + 99999999999999999999",
+            "E0110",
+            concat!("1 + 99999999999999999999\n", "    ^^^^^^^^^^^^^^^^^^^^"),
+        ),
+        ("let 4 = 4", "E1200", "let 4 = 4\n    ^"),
+        ("let x 4", "E1210", "let x 4\n      ^"),
+        ("2 3 4", "E1100", "2 3 4\n  ^^^"),
+        // Offsets count characters, not bytes.
+        ("é + 1)", "E1100", "é + 1)\n     ^"),
+    ];
+    for (input, code, expected) in cases {
+        match p.parse_statement(input) {
+            Err(e) if e.code == *code => {
+                let got = e.render(input);
+                if got != *expected {
+                    return Err(format!(
+                        "FAIL: diagnostic for '{}' should be\n{}\nbut is\n{}",
+                        input, expected, got
+                    ));
+                }
+            }
+            other => {
+                return Err(format!(
+                    "FAIL: '{}' should fail with {}, got {:?}",
+                    input, code, other
+                ));
+            }
+        }
+    }
+    let mut no_division = Rules::part1();
+    no_division.operators.remove(&'/');
+    match Parser::new(no_division).parse("8 * 4 / 2") {
+        Err(e) if e.code == "E1100" && e.span == Span::new(6, 9) => Ok(()),
+        other => Err(format!(
+            "FAIL: '/' should be rejected at 6..9, got {:?}",
+            other
+        )),
+    }
+}
+
+pub fn self_test() -> Result<(), String> {
+    let p1 = Parser::new(Rules::part1());
+    let p2 = Parser::new(Rules::part2());
+    let cases: &[(&str, i64, i64)] = &[
+        // Examples from the puzzle.
+        ("1 + 2 * 3 + 4 * 5 + 6", 71, 231),
+        ("2 * 3 + (4 * 5)", 26, 46),
+        ("5 + (8 * 3 + 9 + 3 * 4 * 3)", 437, 1445),
+        ("5 * 9 * (7 * 3 * 3 + 9 * 3 + (8 + 6 * 4))", 12240, 669060),
+        (
+            "((2 + 4 * 9) * (6 + 9 * 8 + 6) + 6) + 2 + 4 * 2",
+            13632,
+            23340,
+        ),
+        // Subtraction and division go with addition and
+        // multiplication respectively.
+        ("10 - 4 - 3", 3, 3),
+        ("100 / 10 / 5", 2, 2),
+        ("2 * 3 - 1", 5, 4),
+        ("8 / 2 + 2", 6, 2),
+        ("7 - 2 * 3 + 1", 16, 20),
+        ("20 / 3", 6, 6),
+        ("-7 / 2", -3, -3),
+        // Unary minus binds more tightly than anything else.
+        ("-(2 + 3) * -2", 10, 10),
+        ("2 - -3", 5, 5),
+        ("5 + -3 * 2", 4, 4),
+        ("--4", 4, 4),
+    ];
+    for (input, expected1, expected2) in cases {
+        for (part, parser, expected) in &[(1, &p1, expected1), (2, &p2, expected2)] {
+            let tree = parser.parse(input)?;
+            for engine in Engine::all() {
+                let got = engine.evaluate(&tree, &Environment::new())?;
+                if got != **expected {
+                    return Err(format!(
+                        "FAIL: part {} value of '{}' should be {} but {} gives {}",
+                        part, input, expected, engine, got
+                    ));
+                }
+            }
+        }
+    }
+    for input in &["1 / 0", "3 + 4 / (2 - 2)"] {
+        for engine in Engine::all() {
+            match p1
+                .parse(input)
+                .map_err(String::from)
+                .and_then(|tree| engine.evaluate(&tree, &Environment::new()))
+            {
+                Err(e) if e.starts_with("[E2000]") => (),
+                other => {
+                    return Err(format!(
+                        "FAIL: '{}' should fail with division by zero in {}, got {:?}",
+                        input, engine, other
+                    ));
+                }
+            }
+        }
+    }
+    // The stack machine code is the tree in postfix order.
+    let program = Program::compile(&p2.parse("x * -(2 + 3)")?);
+    let expected_code = vec![
+        Instruction::Load("x".to_string()),
+        Instruction::Push(2),
+        Instruction::Push(3),
+        Instruction::Add,
+        Instruction::Neg,
+        Instruction::Mul,
+    ];
+    if program.instructions() != expected_code.as_slice() || program.max_depth != 3 {
+        return Err(format!(
+            "FAIL: wrong code for 'x * -(2 + 3)': {:?}",
+            program
+        ));
+    }
+    let env: Environment = [("x".to_string(), 4)].iter().cloned().collect();
+    if program.run(&env) != Ok(-20) {
+        return Err("FAIL: 'x * -(2 + 3)' should be -20 when x is 4".to_string());
+    }
+    match program.run(&Environment::new()) {
+        Err(e) if e.starts_with("[E2100]") => (),
+        other => {
+            return Err(format!(
+                "FAIL: 'x * -(2 + 3)' should fail when x is undefined, got {:?}",
+                other
+            ));
+        }
+    }
+    for input in &["1 -", "* 2", "4 / / 2"] {
+        if p1.parse(input).is_ok() {
+            return Err(format!("FAIL: '{}' should not parse", input));
+        }
+    }
+    // The S-expression form shows how the parser has grouped things.
+    let ast_cases: &[(&str, &str, &str)] = &[
+        ("1 * 2 + 3", "(+ (* 1 2) 3)", "(* 1 (+ 2 3))"),
+        ("1 + 2 * 3", "(* (+ 1 2) 3)", "(* (+ 1 2) 3)"),
+        ("8 - 2 - 1", "(- (- 8 2) 1)", "(- (- 8 2) 1)"),
+        ("-(1 + 2) / 3", "(/ (neg (+ 1 2)) 3)", "(/ (neg (+ 1 2)) 3)"),
+    ];
+    for (input, expected1, expected2) in ast_cases {
+        for (part, parser, expected) in &[(1, &p1, expected1), (2, &p2, expected2)] {
+            let got = parser.parse(input)?.to_string();
+            if got != **expected {
+                return Err(format!(
+                    "FAIL: part {} should parse '{}' as {} but got {}",
+                    part, input, expected, got
+                ));
+            }
+        }
+    }
+    let tree = p2.parse("1 * -2 + 3")?;
+    if tree.to_tree() != "*\n  1\n  +\n    neg\n      2\n    3\n" {
+        return Err(format!(
+            "FAIL: wrong tree for '1 * -2 + 3':\n{}",
+            tree.to_tree()
+        ));
+    }
+    // Variables.
+    let total_of = |lines: &[&str], engine: &Engine| -> Result<i64, String> {
+        let mut env = Environment::new();
+        let mut total = 0;
+        for line in lines {
+            match p2.parse_statement(line)? {
+                Statement::Let(name, tree) => {
+                    let value = engine.evaluate(&tree, &env)?;
+                    env.insert(name, value);
+                }
+                Statement::Expr(tree) => total += engine.evaluate(&tree, &env)?,
+            }
+        }
+        Ok(total)
+    };
+    let programs: &[(&[&str], i64)] = &[
+        (&["let x = 2 * 3 + 1", "x", "x * x"], 8 + 64),
+        (
+            &["let a = 5", "let b = a - 1", "let a = a * b", "a + b"],
+            24,
+        ),
+        (&["let long_name2 = 7", "-long_name2 + 1"], -6),
+    ];
+    for (lines, expected) in programs {
+        for engine in Engine::all() {
+            let got = total_of(lines, engine)?;
+            if got != *expected {
+                return Err(format!(
+                    "FAIL: total of {:?} should be {} but {} gives {}",
+                    lines, expected, engine, got
+                ));
+            }
+        }
+    }
+    for (lines, code) in &[
+        (&["y + 1"][..], "[E2100]"),
+        (&["let x = x"], "[E2100]"),
+        (&["let = 4"], "[E1200]"),
+        (&["let 4 = 4"], "[E1200]"),
+        (&["let x 4"], "[E1210]"),
+        (&["let x = 4 = 5"], "[E1100]"),
+        (&["1 + let"], "[E0600]"),
+    ] {
+        match total_of(lines, &Engine::Tree) {
+            Err(e) if e.starts_with(code) => (),
+            other => {
+                return Err(format!(
+                    "FAIL: {:?} should fail with {}, got {:?}",
+                    lines, code, other
+                ));
+            }
+        }
+    }
+    self_test_spans(&p2)?;
+    self_test_random()?;
+    // A custom table: subtraction is right-associative and binds
+    // most tightly, and there is no division.
+    let mut custom = Rules::default();
+    for spec in &["+ 10", "* 10 left", "- 20 right"] {
+        custom.add(spec)?;
+    }
+    let p3 = Parser::new(custom);
+    for (input, expected) in &[("10 - 4 - 3", 9), ("2 * 5 - 3 - 1", 6), ("1 + 2 * 3", 9)] {
+        let got = eval(&p3.parse(input)?, &Environment::new())?;
+        if got != *expected {
+            return Err(format!(
+                "FAIL: custom value of '{}' should be {} but is {}",
+                input, expected, got
+            ));
+        }
+    }
+    if p3.parse("4 / 2").is_ok() {
+        return Err("FAIL: '/' should be unknown in the custom rules".to_string());
+    }
+    for bad in &["+", "+ x", "% 10", "+ 10 up", "+ 1000", "+ 1 left extra"] {
+        if Rules::default().add(bad).is_ok() {
+            return Err(format!(
+                "FAIL: '{}' should not be a valid operator rule",
+                bad
+            ));
+        }
+    }
+    Ok(())
+}
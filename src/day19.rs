@@ -0,0 +1,1296 @@
+//! Context-free grammars, as used for the message rules of day 19.
+//!
+//! A `Grammar` maps each rule to a list of alternatives, each of which
+//! is a sequence of symbols: other rules, characters, or character
+//! classes.  No alternative may be empty, so no rule matches the empty
+//! string.  Messages can be matched against a grammar with Earley's
+//! algorithm (which also finds derivations and counts them), or by
+//! first converting it to Chomsky Normal Form and using the CYK
+//! algorithm.
+use itertools::Itertools;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::fmt;
+
+pub type RuleId = i32;
+
+/// A set of characters, written like a character class in a regular
+/// expression: for example `[abc]`, `[a-z0-9]` or `[^ab]`.  There are
+/// no escapes; a '-' at the start or end stands for itself.
+#[derive(PartialEq, Eq, Debug, Clone, Serialize, Deserialize)]
+pub struct CharClass {
+    pub negated: bool,
+    pub ranges: Vec<(char, char)>,
+}
+
+impl CharClass {
+    pub fn parse(s: &str) -> Result<CharClass, String> {
+        let s = s.trim();
+        let inner = match s.strip_prefix('[').and_then(|rest| rest.strip_suffix(']')) {
+            Some(inner) => inner,
+            None => return Err(format!("'{}' is not a character class", s)),
+        };
+        let (negated, inner) = match inner.strip_prefix('^') {
+            Some(rest) => (true, rest),
+            None => (false, inner),
+        };
+        let chars: Vec<char> = inner.chars().collect();
+        if chars.is_empty() {
+            return Err(format!("character class '{}' is empty", s));
+        }
+        let mut ranges = Vec::new();
+        let mut i = 0;
+        while i < chars.len() {
+            if i + 2 < chars.len() && chars[i + 1] == '-' {
+                let (low, high) = (chars[i], chars[i + 2]);
+                if low > high {
+                    return Err(format!("range {}-{} in '{}' is backwards", low, high, s));
+                }
+                ranges.push((low, high));
+                i += 3;
+            } else {
+                ranges.push((chars[i], chars[i]));
+                i += 1;
+            }
+        }
+        Ok(CharClass { negated, ranges })
+    }
+
+    pub fn contains(&self, ch: char) -> bool {
+        self.ranges
+            .iter()
+            .any(|(low, high)| (*low..=*high).contains(&ch))
+            != self.negated
+    }
+
+    /// The characters in the class, in order, unless it is negated
+    /// (in which case there are too many to list).
+    pub fn members(&self) -> Result<Vec<char>, String> {
+        if self.negated {
+            return Err(format!("cannot list the members of {}", self));
+        }
+        let members: BTreeSet<char> = self
+            .ranges
+            .iter()
+            .flat_map(|(low, high)| *low..=*high)
+            .collect();
+        Ok(members.into_iter().collect())
+    }
+
+    pub fn to_regex(&self) -> String {
+        let esc = |ch: char| regex::escape(&ch.to_string());
+        let mut result = String::from(if self.negated { "[^" } else { "[" });
+        for (low, high) in self.ranges.iter() {
+            if low == high {
+                result.push_str(&esc(*low));
+            } else {
+                result.push_str(&format!("{}-{}", esc(*low), esc(*high)));
+            }
+        }
+        result.push(']');
+        result
+    }
+}
+
+impl fmt::Display for CharClass {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(if self.negated { "[^" } else { "[" })?;
+        for (low, high) in self.ranges.iter() {
+            if low == high {
+                write!(f, "{}", low)?;
+            } else {
+                write!(f, "{}-{}", low, high)?;
+            }
+        }
+        f.write_str("]")
+    }
+}
+
+/// An element of the right hand side of a grammar rule.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum Symbol {
+    Rule(RuleId),
+    Char(char),
+    /// Any character in a class which was added to the grammar with
+    /// `Grammar::add_class`.
+    Class(usize),
+}
+
+/// A context-free grammar, in which each rule has a list of
+/// alternatives, each of which is a non-empty sequence of symbols.
+/// Unlike regular expressions, this can represent recursive rules such
+/// as those of part 2.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Grammar {
+    productions: BTreeMap<RuleId, Vec<Vec<Symbol>>>,
+    classes: Vec<CharClass>,
+}
+
+/// A partially matched alternative of a rule: `dot` symbols of
+/// alternative `alt` of `rule` have matched the message from position
+/// `origin` up to the position of the Earley set holding the item.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+struct Item {
+    rule: RuleId,
+    alt: usize,
+    dot: usize,
+    origin: usize,
+}
+
+impl Grammar {
+    pub fn new() -> Grammar {
+        Grammar::default()
+    }
+
+    /// Adds an alternative to `rule`, after any it already has.
+    pub fn add_alternative(&mut self, rule: RuleId, symbols: Vec<Symbol>) -> Result<(), String> {
+        if symbols.is_empty() {
+            return Err(format!("rule {} has an empty alternative", rule));
+        }
+        self.productions.entry(rule).or_default().push(symbols);
+        Ok(())
+    }
+
+    /// Adds a character class, returning the symbol which stands for
+    /// it.
+    pub fn add_class(&mut self, class: CharClass) -> Symbol {
+        self.classes.push(class);
+        Symbol::Class(self.classes.len() - 1)
+    }
+
+    /// Checks that every rule used in an alternative is defined, and
+    /// that every class exists.
+    pub fn validate(&self) -> Result<(), String> {
+        for alternative in self.productions.values().flatten() {
+            for symbol in alternative.iter() {
+                match symbol {
+                    Symbol::Rule(id) if !self.productions.contains_key(id) => {
+                        return Err(format!("missing definition for rule {}", id));
+                    }
+                    Symbol::Class(i) if *i >= self.classes.len() => {
+                        return Err(format!("there is no character class {}", i));
+                    }
+                    _ => (),
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// The alternatives of `rule`, if it is defined.
+    pub fn alternatives(&self, rule: RuleId) -> Option<&[Vec<Symbol>]> {
+        self.productions.get(&rule).map(|alts| alts.as_slice())
+    }
+
+    /// The symbol after the dot of `item`, if the item is not
+    /// complete.
+    fn next_symbol(&self, item: &Item) -> Option<Symbol> {
+        self.productions[&item.rule][item.alt]
+            .get(item.dot)
+            .copied()
+    }
+
+    /// Runs Earley's algorithm over the whole of `input`, starting
+    /// from rule `start`.  Rules never match the empty string, which
+    /// keeps the algorithm simple.
+    fn chart(&self, start: RuleId, input: &[char]) -> Chart {
+        let n = input.len();
+        let mut chart = Chart {
+            sets: vec![Vec::new(); n + 1],
+            back: vec![Vec::new(); n + 1],
+            index: vec![HashMap::new(); n + 1],
+        };
+        for alt in 0..self.productions.get(&start).map_or(0, |alts| alts.len()) {
+            let item = Item {
+                rule: start,
+                alt,
+                dot: 0,
+                origin: 0,
+            };
+            chart.add(0, item, None);
+        }
+        for k in 0..=n {
+            // Items added to set k while we work through it are
+            // processed too.
+            let mut i = 0;
+            while i < chart.sets[k].len() {
+                let item = chart.sets[k][i];
+                i += 1;
+                match self.next_symbol(&item) {
+                    Some(Symbol::Rule(next)) => {
+                        // Predict.
+                        for alt in 0..self.productions[&next].len() {
+                            let predicted = Item {
+                                rule: next,
+                                alt,
+                                dot: 0,
+                                origin: k,
+                            };
+                            chart.add(k, predicted, None);
+                        }
+                    }
+                    Some(terminal) => {
+                        // Scan.
+                        if input.get(k).is_some_and(|ch| self.scans(terminal, *ch)) {
+                            let scanned = Item {
+                                dot: item.dot + 1,
+                                ..item
+                            };
+                            let from = BackPointer {
+                                set: k,
+                                child: None,
+                            };
+                            chart.add(k + 1, scanned, Some(from));
+                        }
+                    }
+                    None => {
+                        // Complete.  Since rules cannot match the
+                        // empty string, item.origin < k and so that
+                        // set is finished.
+                        let waiting: Vec<Item> = chart.sets[item.origin]
+                            .iter()
+                            .filter(|w| self.next_symbol(w) == Some(Symbol::Rule(item.rule)))
+                            .copied()
+                            .collect();
+                        for w in waiting {
+                            let advanced = Item {
+                                dot: w.dot + 1,
+                                ..w
+                            };
+                            let from = BackPointer {
+                                set: item.origin,
+                                child: Some(item),
+                            };
+                            chart.add(k, advanced, Some(from));
+                        }
+                    }
+                }
+            }
+        }
+        chart
+    }
+
+    /// The items in the last set of `chart` which show that `start`
+    /// matches the whole input.
+    fn accepting_items(&self, chart: &Chart, start: RuleId) -> Vec<Item> {
+        chart.sets[chart.sets.len() - 1]
+            .iter()
+            .filter(|item| {
+                item.rule == start && item.origin == 0 && self.next_symbol(item).is_none()
+            })
+            .copied()
+            .collect()
+    }
+
+    /// Determines whether `start` matches the whole of `message`.
+    pub fn matches(&self, start: RuleId, message: &str) -> bool {
+        let input: Vec<char> = message.chars().collect();
+        !input.is_empty()
+            && !self
+                .accepting_items(&self.chart(start, &input), start)
+                .is_empty()
+    }
+
+    /// Finds a way in which `message` derives from `start`, if it
+    /// matches.  If there is more than one, the first one found is
+    /// returned.
+    pub fn derive(&self, start: RuleId, message: &str) -> Option<Derivation> {
+        let input: Vec<char> = message.chars().collect();
+        if input.is_empty() {
+            return None;
+        }
+        let chart = self.chart(start, &input);
+        let item = *self.accepting_items(&chart, start).first()?;
+        Some(chart.derivation(input.len(), item, &input))
+    }
+
+    /// Counts the distinct ways in which `message` derives from
+    /// `start`.
+    pub fn count_derivations(&self, start: RuleId, message: &str) -> Derivations {
+        let input: Vec<char> = message.chars().collect();
+        if input.is_empty() {
+            return Derivations::Finite(0);
+        }
+        let chart = self.chart(start, &input);
+        let mut memo = HashMap::new();
+        self.accepting_items(&chart, start)
+            .into_iter()
+            .map(|item| chart.count(input.len(), item, &mut memo))
+            .fold(Derivations::Finite(0), Derivations::plus)
+    }
+
+    /// Determines whether `terminal` matches the character `ch`.
+    fn scans(&self, terminal: Symbol, ch: char) -> bool {
+        match terminal {
+            Symbol::Char(expected) => ch == expected,
+            Symbol::Class(i) => self.classes[i].contains(ch),
+            Symbol::Rule(_) => false,
+        }
+    }
+
+    /// The text of one alternative of a rule, as it would appear in
+    /// the puzzle input.  Consecutive characters are shown as a single
+    /// quoted string.
+    pub fn alternative_text(&self, rule: RuleId, alt: usize) -> String {
+        let mut words: Vec<String> = Vec::new();
+        let mut literal = String::new();
+        for symbol in self.productions[&rule][alt].iter() {
+            if let Symbol::Char(ch) = symbol {
+                literal.push(*ch);
+                continue;
+            }
+            if !literal.is_empty() {
+                words.push(format!("\"{}\"", literal));
+                literal.clear();
+            }
+            words.push(match symbol {
+                Symbol::Rule(id) => id.to_string(),
+                Symbol::Class(i) => self.classes[*i].to_string(),
+                Symbol::Char(_) => unreachable!(),
+            });
+        }
+        if !literal.is_empty() {
+            words.push(format!("\"{}\"", literal));
+        }
+        words.join(" ")
+    }
+
+    /// Writes a derivation with one rule per line, each indented under
+    /// the rule whose alternative it is part of.
+    fn write_derivation(
+        &self,
+        f: &mut dyn fmt::Write,
+        derivation: &Derivation,
+        depth: usize,
+    ) -> fmt::Result {
+        if let Derivation::Rule {
+            rule,
+            alt,
+            text,
+            children,
+        } = derivation
+        {
+            writeln!(
+                f,
+                "{}{}: {} = {}",
+                "  ".repeat(depth),
+                rule,
+                self.alternative_text(*rule, *alt),
+                text
+            )?;
+            for child in children.iter() {
+                self.write_derivation(f, child, depth + 1)?;
+            }
+        }
+        // Characters already appear in the alternative of their parent.
+        Ok(())
+    }
+
+    pub fn derivation_tree(&self, derivation: &Derivation) -> String {
+        let mut result = String::new();
+        self.write_derivation(&mut result, derivation, 0)
+            .expect("writing to a String cannot fail");
+        result
+    }
+
+    /// The strings of up to `maxlen` characters which `start`
+    /// matches, shortest first and otherwise in alphabetical order.
+    /// Languages grow very quickly with `maxlen`, so we give up once
+    /// we have more than `limit` strings in hand.
+    pub fn generate(
+        &self,
+        start: RuleId,
+        maxlen: usize,
+        limit: usize,
+    ) -> Result<Vec<String>, String> {
+        let too_many = || {
+            format!(
+                "there are more than {} strings of up to {} characters to consider",
+                limit, maxlen
+            )
+        };
+        // language[rule][len] holds the strings of length len which
+        // rule matches.  Since rules cannot match the empty string,
+        // the strings of each length are made by concatenating
+        // shorter strings, except for rules with a single symbol;
+        // those need repeated passes until nothing more is added.
+        let mut language: HashMap<RuleId, Vec<BTreeSet<String>>> = self
+            .productions
+            .keys()
+            .map(|id| (*id, vec![BTreeSet::new(); maxlen + 1]))
+            .collect();
+        let members: Vec<Vec<char>> = self
+            .classes
+            .iter()
+            .map(|class| class.members())
+            .collect::<Result<_, _>>()?;
+        let mut total = 0;
+        for len in 1..=maxlen {
+            loop {
+                let mut additions: Vec<(RuleId, String)> = Vec::new();
+                for (id, alternatives) in self.productions.iter() {
+                    for alternative in alternatives.iter() {
+                        let strings = concatenations(&language, &members, alternative, len, limit)
+                            .ok_or_else(too_many)?;
+                        for s in strings {
+                            if !language[id][len].contains(&s) {
+                                additions.push((*id, s));
+                            }
+                        }
+                    }
+                }
+                if additions.is_empty() {
+                    break;
+                }
+                for (id, s) in additions {
+                    if let Some(strings) = language.get_mut(&id) {
+                        if strings[len].insert(s) {
+                            total += 1;
+                        }
+                    }
+                }
+                if total > limit {
+                    return Err(too_many());
+                }
+            }
+        }
+        Ok(match language.get(&start) {
+            Some(strings) => strings.iter().flatten().cloned().collect(),
+            None => Vec::new(),
+        })
+    }
+}
+
+/// Converting grammars to Chomsky Normal Form, in which every
+/// alternative is either a pair of rules or a single terminal (a
+/// character or a character class).
+impl Grammar {
+    /// Returns true if every alternative is a pair of rules or a
+    /// single terminal.
+    pub fn is_cnf(&self) -> bool {
+        self.productions.values().flatten().all(|alt| {
+            matches!(
+                alt.as_slice(),
+                [Symbol::Rule(_), Symbol::Rule(_)] | [Symbol::Char(_)] | [Symbol::Class(_)]
+            )
+        })
+    }
+
+    /// Returns an equivalent grammar in Chomsky Normal Form.  Every
+    /// rule of this grammar matches the same strings in the result,
+    /// which may contain extra rules numbered above the existing ones.
+    ///
+    /// The conversion has three steps (there is no need for the usual
+    /// step which removes empty alternatives, since there are none):
+    ///
+    /// 1. Terminals in alternatives of more than one symbol are replaced
+    ///    by new rules which match just that terminal.
+    /// 2. Alternatives of more than two symbols are split into a chain,
+    ///    so that for example `0: 1 2 3` becomes `0: 1 4` and `4: 2 3`.
+    /// 3. Unit alternatives such as `2: 1` are replaced by the other
+    ///    alternatives of each rule which they (perhaps indirectly)
+    ///    refer to.  Cycles of unit alternatives are simply dropped.
+    pub fn to_cnf(&self) -> Grammar {
+        let mut next_id: RuleId = self.productions.keys().max().map_or(0, |id| id + 1);
+        let mut productions: BTreeMap<RuleId, Vec<Vec<Symbol>>> = BTreeMap::new();
+        let mut terminal_rules: HashMap<Symbol, RuleId> = HashMap::new();
+        for (id, alternatives) in self.productions.iter() {
+            for alt in alternatives.iter() {
+                if alt.len() == 1 {
+                    productions.entry(*id).or_default().push(alt.clone());
+                    continue;
+                }
+                // Step 1.
+                let mut symbols: Vec<Symbol> = Vec::with_capacity(alt.len());
+                for symbol in alt.iter() {
+                    if let Symbol::Rule(_) = symbol {
+                        symbols.push(*symbol);
+                        continue;
+                    }
+                    let rule = match terminal_rules.get(symbol) {
+                        Some(rule) => *rule,
+                        None => {
+                            let rule = next_id;
+                            next_id += 1;
+                            terminal_rules.insert(*symbol, rule);
+                            productions.insert(rule, vec![vec![*symbol]]);
+                            rule
+                        }
+                    };
+                    symbols.push(Symbol::Rule(rule));
+                }
+                // Step 2.
+                let mut lhs = *id;
+                while symbols.len() > 2 {
+                    let rest = next_id;
+                    next_id += 1;
+                    productions
+                        .entry(lhs)
+                        .or_default()
+                        .push(vec![symbols.remove(0), Symbol::Rule(rest)]);
+                    lhs = rest;
+                }
+                productions.entry(lhs).or_default().push(symbols);
+            }
+        }
+
+        // Step 3.  The unit closure of a rule is the set of rules
+        // reachable from it by unit alternatives, including itself.
+        let is_unit = |alt: &[Symbol]| matches!(alt, [Symbol::Rule(_)]);
+        let mut result = Grammar {
+            productions: BTreeMap::new(),
+            classes: self.classes.clone(),
+        };
+        for id in productions.keys() {
+            let mut closure: Vec<RuleId> = vec![*id];
+            let mut i = 0;
+            while i < closure.len() {
+                for alt in productions[&closure[i]].iter() {
+                    if let [Symbol::Rule(next)] = alt.as_slice() {
+                        if !closure.contains(next) {
+                            closure.push(*next);
+                        }
+                    }
+                }
+                i += 1;
+            }
+            let mut alternatives: Vec<Vec<Symbol>> = Vec::new();
+            for alt in closure.iter().flat_map(|rule| productions[rule].iter()) {
+                if !is_unit(alt) && !alternatives.contains(alt) {
+                    alternatives.push(alt.clone());
+                }
+            }
+            // A rule whose only alternatives are unit cycles matches
+            // nothing, and so it has no alternatives.  Alternatives
+            // which refer to it can never match either, but keeping
+            // it defined keeps the grammar valid.
+            result.productions.insert(*id, alternatives);
+        }
+        result
+    }
+}
+
+/// Matches messages with the CYK algorithm, which needs a grammar in
+/// Chomsky Normal Form.  Unlike Earley's algorithm this takes time
+/// proportional to the cube of the length of the message whatever the
+/// grammar.  A `Cyk` can be serialized, so that the conversion need
+/// not be repeated.
+#[derive(Serialize, Deserialize)]
+pub struct Cyk {
+    grammar: Grammar,
+    /// The dense index of each rule of the grammar.
+    index: HashMap<RuleId, usize>,
+    /// For each rule B, the pairs (A, C) for alternatives `A: B C`.
+    pairs: Vec<Vec<(usize, usize)>>,
+    /// The alternatives `A: t` for terminals t, as (A, t).
+    terminals: Vec<(usize, Symbol)>,
+}
+
+impl Cyk {
+    /// Prepares to match messages against `grammar`, converting it to
+    /// Chomsky Normal Form.
+    pub fn new(grammar: &Grammar) -> Cyk {
+        let grammar = grammar.to_cnf();
+        let index: HashMap<RuleId, usize> = grammar
+            .productions
+            .keys()
+            .enumerate()
+            .map(|(i, id)| (*id, i))
+            .collect();
+        let mut pairs = vec![Vec::new(); index.len()];
+        let mut terminals = Vec::new();
+        for (id, alternatives) in grammar.productions.iter() {
+            for alt in alternatives.iter() {
+                match alt.as_slice() {
+                    [Symbol::Rule(b), Symbol::Rule(c)] => {
+                        pairs[index[b]].push((index[id], index[c]));
+                    }
+                    [terminal] => terminals.push((index[id], *terminal)),
+                    _ => unreachable!("to_cnf produced a grammar which is not in CNF"),
+                }
+            }
+        }
+        Cyk {
+            grammar,
+            index,
+            pairs,
+            terminals,
+        }
+    }
+
+    /// The grammar in Chomsky Normal Form.
+    pub fn grammar(&self) -> &Grammar {
+        &self.grammar
+    }
+
+    /// Returns true if rule `start` matches the whole of `message`.
+    pub fn matches(&self, start: RuleId, message: &str) -> bool {
+        let start = match self.index.get(&start) {
+            Some(i) => *i,
+            None => return false,
+        };
+        let input: Vec<char> = message.chars().collect();
+        let n = input.len();
+        if n == 0 {
+            return false;
+        }
+        // Cell (i, len) lists the rules which match the len characters
+        // starting at i.  Most cells hold few of the rules, so sets of
+        // rules are represented by marking each member with a stamp
+        // which is different for each set.
+        let cell = |i: usize, len: usize| (len - 1) * n + i;
+        let mut cells: Vec<Vec<usize>> = vec![Vec::new(); n * n];
+        let mut in_cell: Vec<usize> = vec![0; self.pairs.len()];
+        let mut in_right: Vec<usize> = vec![0; self.pairs.len()];
+        let mut stamp = 0;
+        for (i, ch) in input.iter().enumerate() {
+            for (rule, terminal) in self.terminals.iter() {
+                if self.grammar.scans(*terminal, *ch) {
+                    cells[cell(i, 1)].push(*rule);
+                }
+            }
+        }
+        for len in 2..=n {
+            for i in 0..=(n - len) {
+                let mut found = Vec::new();
+                stamp += 1;
+                let cell_stamp = stamp;
+                for split in 1..len {
+                    stamp += 1;
+                    for c in cells[cell(i + split, len - split)].iter() {
+                        in_right[*c] = stamp;
+                    }
+                    for b in cells[cell(i, split)].iter() {
+                        for (a, c) in self.pairs[*b].iter() {
+                            if in_right[*c] == stamp && in_cell[*a] != cell_stamp {
+                                in_cell[*a] = cell_stamp;
+                                found.push(*a);
+                            }
+                        }
+                    }
+                }
+                cells[cell(i, len)] = found;
+            }
+        }
+        cells[cell(0, n)].contains(&start)
+    }
+}
+
+impl fmt::Display for Grammar {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for (id, alternatives) in self.productions.iter() {
+            let text = (0..alternatives.len())
+                .map(|alt| self.alternative_text(*id, alt))
+                .join(" | ");
+            writeln!(f, "{}: {}", id, text)?;
+        }
+        Ok(())
+    }
+}
+
+/// The strings of length `len` which the sequence `symbols` matches,
+/// given the strings which each rule matches and the members of each
+/// character class, or None if there are more than `limit` of them.
+fn concatenations(
+    language: &HashMap<RuleId, Vec<BTreeSet<String>>>,
+    members: &[Vec<char>],
+    symbols: &[Symbol],
+    len: usize,
+    limit: usize,
+) -> Option<Vec<String>> {
+    let (first, rest) = match symbols.split_first() {
+        None if len == 0 => return Some(vec![String::new()]),
+        None => return Some(Vec::new()),
+        Some(split) => split,
+    };
+    let mut result = Vec::new();
+    // Each of the rest of the symbols needs at least one character.
+    for n in 1..=len.saturating_sub(rest.len()) {
+        let heads: Vec<String> = match first {
+            Symbol::Char(ch) if n == 1 => vec![ch.to_string()],
+            Symbol::Class(i) if n == 1 => members[*i].iter().map(|ch| ch.to_string()).collect(),
+            Symbol::Char(_) | Symbol::Class(_) => Vec::new(),
+            Symbol::Rule(id) => language[id][n].iter().cloned().collect(),
+        };
+        if heads.is_empty() {
+            continue;
+        }
+        let tails = concatenations(language, members, rest, len - n, limit)?;
+        if result.len() + heads.len() * tails.len() > limit {
+            return None;
+        }
+        for head in heads.iter() {
+            for tail in tails.iter() {
+                result.push(format!("{}{}", head, tail));
+            }
+        }
+    }
+    Some(result)
+}
+
+/// Records how an item was added to an Earley set: it was made by
+/// advancing the dot over the next symbol of an item in `set`.  That
+/// symbol was either a character (`child` is `None`) or a rule, which
+/// was matched by `child`, an item completed in the same set as the
+/// new item.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct BackPointer {
+    set: usize,
+    child: Option<Item>,
+}
+
+/// The Earley sets for an input, with back pointers recording how each
+/// item got there.
+struct Chart {
+    sets: Vec<Vec<Item>>,
+    /// back[k][i] holds the back pointers of sets[k][i].
+    back: Vec<Vec<Vec<BackPointer>>>,
+    /// index[k] maps the items of sets[k] to their positions.
+    index: Vec<HashMap<Item, usize>>,
+}
+
+impl Chart {
+    fn add(&mut self, k: usize, item: Item, from: Option<BackPointer>) {
+        let sets = &mut self.sets;
+        let back = &mut self.back;
+        let i = *self.index[k].entry(item).or_insert_with(|| {
+            sets[k].push(item);
+            back[k].push(Vec::new());
+            sets[k].len() - 1
+        });
+        if let Some(from) = from {
+            if !self.back[k][i].contains(&from) {
+                self.back[k][i].push(from);
+            }
+        }
+    }
+
+    /// The derivations of the symbols before the dot of `item`, which
+    /// is in set k.  The first back pointer of each item is followed;
+    /// those always lead to items which were added earlier, so this
+    /// terminates.
+    fn children(&self, k: usize, item: Item, input: &[char]) -> Vec<Derivation> {
+        if item.dot == 0 {
+            return Vec::new();
+        }
+        let from = self.back[k][self.index[k][&item]][0];
+        let previous = Item {
+            dot: item.dot - 1,
+            ..item
+        };
+        let mut result = self.children(from.set, previous, input);
+        result.push(match from.child {
+            None => Derivation::Char(input[from.set]),
+            Some(child) => self.derivation(k, child, input),
+        });
+        result
+    }
+
+    /// Counts the ways in which the symbols before the dot of `item`
+    /// (which is in set k) can match, by following every back pointer.
+    /// An item which is reachable from itself (as happens with rules
+    /// like "1: 2" and "2: 1") has infinitely many derivations.
+    fn count(
+        &self,
+        k: usize,
+        item: Item,
+        memo: &mut HashMap<(usize, Item), Option<Derivations>>,
+    ) -> Derivations {
+        if item.dot == 0 {
+            return Derivations::Finite(1);
+        }
+        match memo.get(&(k, item)) {
+            Some(Some(count)) => return *count,
+            // We are already counting this item.
+            Some(None) => return Derivations::Infinite,
+            None => (),
+        }
+        memo.insert((k, item), None);
+        let previous = Item {
+            dot: item.dot - 1,
+            ..item
+        };
+        let mut total = Derivations::Finite(0);
+        for from in self.back[k][self.index[k][&item]].iter() {
+            let before = self.count(from.set, previous, memo);
+            let last = match from.child {
+                None => Derivations::Finite(1),
+                Some(child) => self.count(k, child, memo),
+            };
+            total = total.plus(before.times(last));
+        }
+        memo.insert((k, item), Some(total));
+        total
+    }
+
+    /// The derivation of `item`, a complete item in set k.
+    fn derivation(&self, k: usize, item: Item, input: &[char]) -> Derivation {
+        Derivation::Rule {
+            rule: item.rule,
+            alt: item.alt,
+            text: input[item.origin..k].iter().collect(),
+            children: self.children(k, item, input),
+        }
+    }
+}
+
+/// The number of distinct derivations of a message.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Derivations {
+    /// Counts too large to represent are capped at u128::MAX.
+    Finite(u128),
+    Infinite,
+}
+
+impl Derivations {
+    fn plus(self, other: Derivations) -> Derivations {
+        match (self, other) {
+            (Derivations::Finite(a), Derivations::Finite(b)) => {
+                Derivations::Finite(a.saturating_add(b))
+            }
+            _ => Derivations::Infinite,
+        }
+    }
+
+    fn times(self, other: Derivations) -> Derivations {
+        match (self, other) {
+            (Derivations::Finite(0), _) | (_, Derivations::Finite(0)) => Derivations::Finite(0),
+            (Derivations::Finite(a), Derivations::Finite(b)) => {
+                Derivations::Finite(a.saturating_mul(b))
+            }
+            _ => Derivations::Infinite,
+        }
+    }
+}
+
+impl fmt::Display for Derivations {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Derivations::Finite(n) => write!(f, "{}", n),
+            Derivations::Infinite => f.write_str("infinitely many"),
+        }
+    }
+}
+
+/// How (part of) a message derives from a rule.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Derivation {
+    Char(char),
+    /// Alternative `alt` of `rule` matched `text`; the children are
+    /// the derivations of the symbols of the alternative.
+    Rule {
+        rule: RuleId,
+        alt: usize,
+        text: String,
+        children: Vec<Derivation>,
+    },
+}
+
+/// A node of a parsed regular expression.
+#[derive(Debug)]
+enum RegexNode {
+    Char(char),
+    Class(CharClass),
+    Concat(Vec<RegexNode>),
+    Alt(Vec<RegexNode>),
+    Plus(Box<RegexNode>),
+    Star(Box<RegexNode>),
+    Optional(Box<RegexNode>),
+}
+
+/// Parses the subset of the syntax of the regex crate which the rules
+/// are translated into.
+struct RegexParser<'a> {
+    pattern: &'a str,
+    chars: Vec<char>,
+    pos: usize,
+}
+
+impl<'a> RegexParser<'a> {
+    fn error(&self, message: &str) -> String {
+        format!(
+            "{} at position {} of regular expression '{}'",
+            message, self.pos, self.pattern
+        )
+    }
+
+    fn peek(&self) -> Option<char> {
+        self.chars.get(self.pos).copied()
+    }
+
+    fn next(&mut self) -> Option<char> {
+        let ch = self.peek();
+        self.pos += 1;
+        ch
+    }
+
+    /// An escape sequence, after its backslash.  Only escaped
+    /// punctuation is supported.
+    fn escaped(&mut self) -> Result<char, String> {
+        match self.next() {
+            Some(ch) if ch.is_ascii_punctuation() || ch == ' ' => Ok(ch),
+            Some(ch) => Err(self.error(&format!("unsupported escape \\{}", ch))),
+            None => Err(self.error("unterminated escape")),
+        }
+    }
+
+    fn alternation(&mut self) -> Result<RegexNode, String> {
+        let mut branches = vec![self.concatenation()?];
+        while self.peek() == Some('|') {
+            self.pos += 1;
+            branches.push(self.concatenation()?);
+        }
+        Ok(if branches.len() == 1 {
+            branches.remove(0)
+        } else {
+            RegexNode::Alt(branches)
+        })
+    }
+
+    fn concatenation(&mut self) -> Result<RegexNode, String> {
+        let mut items = Vec::new();
+        while let Some(ch) = self.peek() {
+            if ch == '|' || ch == ')' {
+                break;
+            }
+            let atom = self.atom()?;
+            items.push(match self.peek() {
+                Some('+') => RegexNode::Plus(Box::new(atom)),
+                Some('*') => RegexNode::Star(Box::new(atom)),
+                Some('?') => RegexNode::Optional(Box::new(atom)),
+                _ => {
+                    items.push(atom);
+                    continue;
+                }
+            });
+            self.pos += 1;
+        }
+        Ok(RegexNode::Concat(items))
+    }
+
+    fn atom(&mut self) -> Result<RegexNode, String> {
+        match self.next() {
+            Some('(') => {
+                if self.chars[self.pos..].starts_with(&['?', ':']) {
+                    self.pos += 2;
+                }
+                let inner = self.alternation()?;
+                match self.next() {
+                    Some(')') => Ok(inner),
+                    _ => Err(self.error("expected ')'")),
+                }
+            }
+            Some('[') => self.class().map(RegexNode::Class),
+            Some('\\') => self.escaped().map(RegexNode::Char),
+            Some('.') => Ok(RegexNode::Class(CharClass {
+                negated: true,
+                ranges: vec![('\n', '\n')],
+            })),
+            Some(ch) if "+*?{}^$".contains(ch) => {
+                Err(self.error(&format!("unsupported use of '{}'", ch)))
+            }
+            Some(ch) => Ok(RegexNode::Char(ch)),
+            None => Err(self.error("unexpected end")),
+        }
+    }
+
+    /// A character class, after its opening bracket.
+    fn class(&mut self) -> Result<CharClass, String> {
+        let negated = self.peek() == Some('^');
+        if negated {
+            self.pos += 1;
+        }
+        let mut ranges = Vec::new();
+        loop {
+            let low = match self.next() {
+                Some(']') if !ranges.is_empty() => break,
+                Some('\\') => self.escaped()?,
+                Some(ch) => ch,
+                None => return Err(self.error("unterminated character class")),
+            };
+            let high = if self.peek() == Some('-') && self.chars.get(self.pos + 1) != Some(&']') {
+                self.pos += 1;
+                match self.next() {
+                    Some('\\') => self.escaped()?,
+                    Some(ch) => ch,
+                    None => return Err(self.error("unterminated character class")),
+                }
+            } else {
+                low
+            };
+            if high < low {
+                return Err(self.error(&format!("invalid range {}-{}", low, high)));
+            }
+            ranges.push((low, high));
+        }
+        Ok(CharClass { negated, ranges })
+    }
+}
+
+/// A state of an `Nfa`.
+#[derive(Debug)]
+enum NfaState {
+    /// Consume a character in the class, then go to the next state.
+    Scan(CharClass, usize),
+    /// Go to any of these states without consuming any input.
+    Split(Vec<usize>),
+    Match,
+}
+
+/// A nondeterministic finite automaton built from a regular expression
+/// by Thompson's construction.  Matching simulates all the possible
+/// paths through it at once, so it takes time proportional to the
+/// length of the message times the number of states.  This is slower
+/// than the regex crate, but the automaton is only about as large as
+/// the pattern, so there is no limit on the size of the pattern.
+///
+/// Only the parts of the regex syntax used when translating rules are
+/// supported: characters (with punctuation escaped by a backslash),
+/// character classes, `.`, groups (capturing or not), alternation and
+/// the `+`, `*` and `?` repetitions.
+#[derive(Debug)]
+pub struct Nfa {
+    states: Vec<NfaState>,
+    start: usize,
+}
+
+impl Nfa {
+    pub fn compile(pattern: &str) -> Result<Nfa, String> {
+        let mut parser = RegexParser {
+            pattern,
+            chars: pattern.chars().collect(),
+            pos: 0,
+        };
+        let tree = parser.alternation()?;
+        if parser.pos < parser.chars.len() {
+            return Err(parser.error("unmatched ')'"));
+        }
+        let mut nfa = Nfa {
+            states: vec![NfaState::Match],
+            start: 0,
+        };
+        nfa.start = nfa.build(&tree, 0);
+        Ok(nfa)
+    }
+
+    fn add(&mut self, state: NfaState) -> usize {
+        self.states.push(state);
+        self.states.len() - 1
+    }
+
+    /// Adds the states for `node`, which continue to state `next`,
+    /// returning the first of them.
+    fn build(&mut self, node: &RegexNode, next: usize) -> usize {
+        match node {
+            RegexNode::Char(ch) => self.add(NfaState::Scan(
+                CharClass {
+                    negated: false,
+                    ranges: vec![(*ch, *ch)],
+                },
+                next,
+            )),
+            RegexNode::Class(class) => self.add(NfaState::Scan(class.clone(), next)),
+            RegexNode::Concat(items) => items
+                .iter()
+                .rev()
+                .fold(next, |next, item| self.build(item, next)),
+            RegexNode::Alt(branches) => {
+                let starts = branches.iter().map(|b| self.build(b, next)).collect();
+                self.add(NfaState::Split(starts))
+            }
+            RegexNode::Optional(inner) => {
+                let start = self.build(inner, next);
+                self.add(NfaState::Split(vec![start, next]))
+            }
+            RegexNode::Plus(inner) | RegexNode::Star(inner) => {
+                // The loop state is filled in once we know where the
+                // body starts.
+                let repeat = self.add(NfaState::Split(Vec::new()));
+                let start = self.build(inner, repeat);
+                self.states[repeat] = NfaState::Split(vec![start, next]);
+                match node {
+                    RegexNode::Plus(_) => start,
+                    _ => repeat,
+                }
+            }
+        }
+    }
+
+    /// Adds `state` and the states reachable from it without consuming
+    /// input to `set`.  States already marked with `stamp` in `seen`
+    /// are skipped, and the others are marked.
+    fn add_closure(&self, state: usize, set: &mut Vec<usize>, seen: &mut [usize], stamp: usize) {
+        let mut stack = vec![state];
+        while let Some(s) = stack.pop() {
+            if seen[s] == stamp {
+                continue;
+            }
+            seen[s] = stamp;
+            match &self.states[s] {
+                NfaState::Split(targets) => stack.extend(targets.iter().rev()),
+                _ => set.push(s),
+            }
+        }
+    }
+
+    /// Returns true if the whole of `message` matches.
+    pub fn is_match(&self, message: &str) -> bool {
+        // seen[s] is the number of characters consumed when state s
+        // was last added, plus one.
+        let mut seen = vec![0; self.states.len()];
+        let mut current = Vec::new();
+        self.add_closure(self.start, &mut current, &mut seen, 1);
+        for (i, ch) in message.chars().enumerate() {
+            let mut next = Vec::new();
+            for s in current.iter() {
+                if let NfaState::Scan(class, to) = &self.states[*s] {
+                    if class.contains(ch) {
+                        self.add_closure(*to, &mut next, &mut seen, i + 2);
+                    }
+                }
+            }
+            if next.is_empty() {
+                return false;
+            }
+            current = next;
+        }
+        current
+            .iter()
+            .any(|s| matches!(self.states[*s], NfaState::Match))
+    }
+
+    /// The number of states of the automaton.
+    pub fn state_count(&self) -> usize {
+        self.states.len()
+    }
+}
+
+/// Builds a grammar from rules written like those of the puzzle, except
+/// that quoted strings may appear anywhere in an alternative.
+fn parse_test_grammar(rules: &[&str]) -> Result<Grammar, String> {
+    let mut grammar = Grammar::new();
+    for rule in rules.iter() {
+        let (id, body) = rule
+            .split_once(':')
+            .ok_or_else(|| format!("rule '{}' has no colon", rule))?;
+        let id: RuleId = id.trim().parse().map_err(|e| format!("{}: {}", rule, e))?;
+        for alt in body.split('|') {
+            let mut symbols = Vec::new();
+            for word in alt.split_whitespace() {
+                match word.strip_prefix('"').and_then(|w| w.strip_suffix('"')) {
+                    Some(text) => symbols.extend(text.chars().map(Symbol::Char)),
+                    None => symbols.push(Symbol::Rule(
+                        word.parse().map_err(|e| format!("{}: {}", rule, e))?,
+                    )),
+                }
+            }
+            grammar.add_alternative(id, symbols)?;
+        }
+    }
+    grammar.validate()?;
+    Ok(grammar)
+}
+
+/// All the strings of up to `maxlen` characters drawn from `alphabet`.
+fn all_strings(alphabet: &[char], maxlen: usize) -> Vec<String> {
+    let mut result = Vec::new();
+    let mut current = vec![String::new()];
+    for _ in 0..maxlen {
+        current = current
+            .iter()
+            .cartesian_product(alphabet.iter())
+            .map(|(s, ch)| format!("{}{}", s, ch))
+            .collect();
+        result.extend(current.iter().cloned());
+    }
+    result
+}
+
+fn self_test_cnf() -> Result<(), String> {
+    let grammar = parse_test_grammar(&["0: 1 2 3 | \"x\" 3", "1: \"a\"", "2: 1", "3: \"b\""])?;
+    if grammar.is_cnf() {
+        return Err("FAIL: grammar with a unit rule was taken to be in CNF".to_string());
+    }
+    let cnf = grammar.to_cnf();
+    let expected = "0: 1 4 | 5 3\n1: \"a\"\n2: \"a\"\n3: \"b\"\n4: 2 3\n5: \"x\"\n";
+    if cnf.to_string() != expected {
+        return Err(format!(
+            "FAIL: expected CNF grammar\n{}but got\n{}",
+            expected, cnf
+        ));
+    }
+    if !cnf.is_cnf() {
+        return Err("FAIL: to_cnf result is not in CNF".to_string());
+    }
+
+    // A unit cycle (rules 1 and 2) alongside real alternatives.
+    let grammar = parse_test_grammar(&["0: 1 \"ab\"", "1: 2 | \"c\"", "2: 1 | \"d\" 2"])?;
+    let cnf = grammar.to_cnf();
+    let expected = "0: 1 5\n1: \"c\" | 6 2\n2: 6 2 | \"c\"\n3: \"a\"\n4: \"b\"\n5: 3 4\n6: \"d\"\n";
+    if cnf.to_string() != expected {
+        return Err(format!(
+            "FAIL: expected CNF grammar\n{}but got\n{}",
+            expected, cnf
+        ));
+    }
+
+    // CYK on the CNF grammar should agree with Earley on the original.
+    let grammars = [
+        grammar,
+        parse_test_grammar(&[
+            "0: 8 11",
+            "8: 42 | 42 8",
+            "11: 42 31 | 42 11 31",
+            "42: \"a\" | \"b\" \"a\"",
+            "31: \"b\"",
+        ])?,
+        parse_test_grammar(&["0: 0 0 | 1", "1: 2", "2: \"a\" | \"b\" 0 \"c\""])?,
+    ];
+    for grammar in grammars.iter() {
+        let cyk = Cyk::new(grammar);
+        for message in all_strings(&['a', 'b', 'c', 'd'], 6) {
+            let expected = grammar.matches(0, &message);
+            if cyk.matches(0, &message) != expected {
+                return Err(format!(
+                    "FAIL: CYK and Earley disagree on whether '{}' matches\n{}",
+                    message, grammar
+                ));
+            }
+        }
+    }
+    Ok(())
+}
+
+fn self_test_nfa() -> Result<(), String> {
+    let messages = all_strings(&['a', 'b', 'c', 'd', '.'], 5);
+    for pattern in &[
+        "(?:a|bc)+d",
+        "[^a-c]b?",
+        "(a(?:b|c)*)|\\.d",
+        "[a.]\\.[b-d]",
+        "a(?:ab|ba)c|[b-c]+",
+        "(?:(?:ab)*)*c.",
+    ] {
+        let nfa = Nfa::compile(pattern)?;
+        let rx = regex::Regex::new(&format!("^(?:{})$", pattern)).map_err(|e| e.to_string())?;
+        for message in messages.iter() {
+            if nfa.is_match(message) != rx.is_match(message) {
+                return Err(format!(
+                    "FAIL: the NFA and the regex crate disagree on whether '{}' matches {}",
+                    message, pattern
+                ));
+            }
+        }
+    }
+    for bad in &["(a", "a)", "[a", "a{2}", "*a", "\\d"] {
+        if Nfa::compile(bad).is_ok() {
+            return Err(format!("FAIL: invalid pattern {} was accepted", bad));
+        }
+    }
+    Ok(())
+}
+
+pub fn self_test() -> Result<(), String> {
+    self_test_cnf()?;
+    self_test_nfa()
+}
@@ -0,0 +1,615 @@
+//! Tiles of the satellite image in day 20, and the ways of turning
+//! them over.
+//!
+//! A tile is a square matrix of 0s and 1s, indexed by (row, column)
+//! from the top left.  A `Manipulation` is one of the eight symmetries
+//! of a square: the tile is optionally flipped (left to right, that is
+//! reversing the order of the columns) and then rotated
+//! counter-clockwise by a number of quarter turns.  Together the
+//! manipulations form the dihedral group of order 8, and `then` and
+//! `inverse` give its group operations.
+use ndarray::prelude::*;
+use ndarray::s;
+use serde::de::{self, Deserializer};
+use serde::ser::Serializer;
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::num::ParseIntError;
+use std::str::FromStr;
+
+/// A side of a tile.  North is the top row and west is the left-hand
+/// column.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub enum Direction {
+    N,
+    E,
+    S,
+    W,
+}
+
+impl Direction {
+    pub fn all() -> [Direction; 4] {
+        [Direction::N, Direction::E, Direction::S, Direction::W]
+    }
+
+    /// The position of this direction in the result of `all`.
+    pub fn index(&self) -> usize {
+        match self {
+            Direction::N => 0,
+            Direction::E => 1,
+            Direction::S => 2,
+            Direction::W => 3,
+        }
+    }
+
+    pub fn opposite(&self) -> Direction {
+        match self {
+            Direction::N => Direction::S,
+            Direction::E => Direction::W,
+            Direction::S => Direction::N,
+            Direction::W => Direction::E,
+        }
+    }
+
+    /// The side which this one becomes when a tile is turned a
+    /// quarter turn counter-clockwise.
+    fn turned(&self) -> Direction {
+        match self {
+            Direction::N => Direction::W,
+            Direction::W => Direction::S,
+            Direction::S => Direction::E,
+            Direction::E => Direction::N,
+        }
+    }
+}
+
+impl fmt::Display for Direction {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match self {
+            Direction::N => "N",
+            Direction::E => "E",
+            Direction::S => "S",
+            Direction::W => "W",
+        })
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+#[serde(transparent)]
+pub struct TileId {
+    pub val: i32,
+}
+
+impl From<i32> for TileId {
+    fn from(n: i32) -> Self {
+        TileId { val: n }
+    }
+}
+
+impl From<&i32> for TileId {
+    fn from(n: &i32) -> Self {
+        TileId { val: *n }
+    }
+}
+
+impl FromStr for TileId {
+    type Err = ParseIntError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.parse() {
+            Err(e) => Err(e),
+            Ok(n) => Ok(TileId { val: n }),
+        }
+    }
+}
+
+impl fmt::Display for TileId {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.val)
+    }
+}
+
+/// A number of counter-clockwise quarter turns.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub enum Rotation {
+    Zero,
+    One,
+    Two,
+    Three,
+}
+
+impl Rotation {
+    /// Returns the rotation of `n` quarter turns; `n` is taken modulo 4.
+    pub fn from_turns(n: i32) -> Rotation {
+        match n.rem_euclid(4) {
+            0 => Rotation::Zero,
+            1 => Rotation::One,
+            2 => Rotation::Two,
+            3 => Rotation::Three,
+            _ => panic!("impossible"),
+        }
+    }
+
+    pub fn turns(&self) -> i32 {
+        match self {
+            Rotation::Zero => 0,
+            Rotation::One => 1,
+            Rotation::Two => 2,
+            Rotation::Three => 3,
+        }
+    }
+}
+
+/// A symmetry of a square tile: first `flip` (if set) reverses the
+/// order of the columns, then the tile is rotated counter-clockwise
+/// by `rot`.
+#[derive(Copy, Clone, PartialEq, Eq, Hash)]
+pub struct Manipulation {
+    pub rot: Rotation,
+    pub flip: bool,
+}
+
+impl Manipulation {
+    /// Returns manipulation number `n` (see `index`); only the bottom
+    /// three bits of `n` are used.
+    pub fn new(n: i32) -> Manipulation {
+        Manipulation {
+            rot: Rotation::from_turns(n & 0x03),
+            flip: n & 0x04 != 0,
+        }
+    }
+
+    pub fn noop() -> Manipulation {
+        Manipulation {
+            rot: Rotation::Zero,
+            flip: false,
+        }
+    }
+
+    /// Returns the eight manipulations, in the order of their index.
+    pub fn all() -> Vec<Manipulation> {
+        (0..8).map(Manipulation::new).collect()
+    }
+
+    /// A number from 0 to 7 identifying this manipulation;
+    /// `Manipulation::new(m.index()) == m`.
+    pub fn index(&self) -> i32 {
+        self.rot.turns() + if self.flip { 4 } else { 0 }
+    }
+
+    pub fn as_string(&self) -> String {
+        format!(
+            "R{}F{}",
+            self.rot.turns(),
+            if self.flip { "Y" } else { "N" }
+        )
+    }
+
+    /// Returns the manipulation which has the effect of applying
+    /// `self` and then `next`, so `self.then(next).on(m)` is
+    /// `next.on(&self.on(m))`.
+    pub fn then(&self, next: &Manipulation) -> Manipulation {
+        // Flipping after a rotation is the same as flipping before
+        // the opposite rotation.
+        let turns = if next.flip {
+            next.rot.turns() - self.rot.turns()
+        } else {
+            next.rot.turns() + self.rot.turns()
+        };
+        Manipulation {
+            rot: Rotation::from_turns(turns),
+            flip: self.flip != next.flip,
+        }
+    }
+
+    /// Returns the manipulation which undoes this one.
+    pub fn inverse(&self) -> Manipulation {
+        if self.flip {
+            // Every flipped manipulation is a reflection.
+            *self
+        } else {
+            Manipulation {
+                rot: Rotation::from_turns(-self.rot.turns()),
+                flip: false,
+            }
+        }
+    }
+
+    /// Returns the side of the manipulated tile on which side `d` of
+    /// the original tile ends up.
+    pub fn direction(&self, d: Direction) -> Direction {
+        let mut result = match (self.flip, d) {
+            (true, Direction::E) => Direction::W,
+            (true, Direction::W) => Direction::E,
+            _ => d,
+        };
+        for _ in 0..self.rot.turns() {
+            result = result.turned();
+        }
+        result
+    }
+
+    fn do_rot<T: Clone>(&self, tiledata: Array2<T>) -> Array2<T> {
+        // rotations are counter-clockwise.
+        match self.rot {
+            Rotation::Zero => tiledata,
+            Rotation::One => tiledata.slice(s![.., ..;-1]).reversed_axes().into_owned(),
+            Rotation::Two => tiledata.slice(s![..;-1, ..;-1]).into_owned(),
+            Rotation::Three => tiledata.slice(s![..;-1, ..]).reversed_axes().into_owned(),
+        }
+    }
+
+    fn do_flip<T: Clone>(&self, tiledata: Array2<T>) -> Array2<T> {
+        if self.flip {
+            tiledata.slice(s![.., ..;-1]).into_owned()
+        } else {
+            tiledata
+        }
+    }
+
+    pub fn on<T: Clone>(&self, m: &Array2<T>) -> Array2<T> {
+        self.do_rot(self.do_flip(m.to_owned()))
+    }
+
+    /// Returns the (row, column) at which cell (r, c) of an array of
+    /// shape `dim` (rows, columns) ends up when the array is
+    /// manipulated by `on`.  The array need not be square.
+    pub fn cell(&self, (r, c): (usize, usize), (rows, cols): (usize, usize)) -> (usize, usize) {
+        let c = if self.flip { cols - 1 - c } else { c };
+        match self.rot {
+            Rotation::Zero => (r, c),
+            Rotation::One => (cols - 1 - c, r),
+            Rotation::Two => (rows - 1 - r, cols - 1 - c),
+            Rotation::Three => (c, rows - 1 - r),
+        }
+    }
+}
+
+impl FromStr for Manipulation {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut it = s.chars();
+        if let Some(ch) = it.next() {
+            if ch != 'R' {
+                return Err("must begin with R".to_string());
+            }
+        }
+        let rot = match it.next() {
+            None => {
+                return Err("string is too short".to_string());
+            }
+            Some('0') => Rotation::Zero,
+            Some('1') => Rotation::One,
+            Some('2') => Rotation::Two,
+            Some('3') => Rotation::Three,
+            Some(ch) => {
+                return Err(format!("invalid rotation {}", ch));
+            }
+        };
+        if let Some(ch) = it.next() {
+            if ch != 'F' {
+                return Err("must have F as the third character".to_string());
+            }
+        }
+        let flip = match it.next() {
+            Some('Y') => true,
+            Some('N') => false,
+            _ => {
+                return Err("flip must be Y or N".to_string());
+            }
+        };
+        match it.next() {
+            None => Ok(Manipulation { rot, flip }),
+            _ => Err("trailing garbage at the end of the string".to_string()),
+        }
+    }
+}
+
+impl fmt::Display for Manipulation {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(&self.as_string())
+    }
+}
+
+impl fmt::Debug for Manipulation {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(&self.as_string())
+    }
+}
+
+/// Manipulations are serialized in the same form as they are
+/// displayed, for example "R1FY".
+impl Serialize for Manipulation {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&self.as_string())
+    }
+}
+
+impl<'de> Deserialize<'de> for Manipulation {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let s = String::deserialize(deserializer)?;
+        s.parse()
+            .map_err(|e| de::Error::custom(format!("invalid manipulation '{}': {}", s, e)))
+    }
+}
+
+/// The cells along one side of a tile, as a binary number.  The
+/// north and south sides are read from left to right, and the east
+/// and west sides from top to bottom, so two tiles which fit together
+/// have equal patterns on the sides which touch.
+#[derive(Debug, Clone, Copy, Hash, PartialEq, Eq, PartialOrd, Ord)]
+pub struct EdgePattern {
+    pub bits: i32,
+}
+
+impl EdgePattern {
+    pub fn from_edge(edge: &ArrayView1<u8>) -> EdgePattern {
+        EdgePattern {
+            bits: edge.iter().fold(0, |bits, elem| {
+                let bit = match elem {
+                    0 => 0,
+                    1 => 1,
+                    _ => panic!("matrix should be 0/1 only"),
+                };
+                (bits << 1) | bit
+            }),
+        }
+    }
+
+    pub fn from_matrix(d: &Direction, m: &ArrayView2<u8>) -> EdgePattern {
+        EdgePattern::from_edge(&m.slice(&match d {
+            Direction::N => s![0, ..].to_owned(),
+            Direction::E => s![.., m.ncols() - 1].to_owned(),
+            Direction::S => s![m.nrows() - 1, ..].to_owned(),
+            Direction::W => s![.., 0].to_owned(),
+        }))
+    }
+}
+
+impl fmt::Display for EdgePattern {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        fmt::Binary::fmt(&self.bits, f)
+    }
+}
+
+/// An edge pattern together with the side of the tile it is on.
+#[derive(Debug, Clone, Copy, Hash, PartialEq, Eq, PartialOrd, Ord)]
+pub struct EdgeKey {
+    pub direction: Direction,
+    pub pattern: EdgePattern,
+}
+
+impl EdgeKey {
+    pub fn from_matrix(d: &Direction, m: &ArrayView2<u8>) -> EdgeKey {
+        EdgeKey {
+            direction: *d,
+            pattern: EdgePattern::from_matrix(d, m),
+        }
+    }
+
+    /// Returns the key which a neighbouring tile must have on its
+    /// touching side in order to fit against this edge.
+    pub fn opposing(&self) -> EdgeKey {
+        EdgeKey {
+            direction: self.direction.opposite(),
+            pattern: self.pattern,
+        }
+    }
+}
+
+impl fmt::Display for EdgeKey {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "({},{})", self.direction, self.pattern)
+    }
+}
+
+#[derive(Debug)]
+pub struct Tile {
+    pub id: TileId,
+    pub d: Array2<u8>,
+    /// The edge patterns of each manipulation of the tile (indexed by
+    /// `Manipulation::index`) on each side (indexed by
+    /// `Direction::index`).  The reversed form of each edge is the
+    /// same edge of the reflected manipulation, so it is here too.
+    edges: [[EdgePattern; 4]; 8],
+}
+
+fn decode_ascii_tile(r: usize, c: usize, width: &usize, s: &[char]) -> u8 {
+    let pos: usize = (width + 1) * r + c;
+    match s[pos] {
+        '#' => 1,
+        '.' => 0,
+        _ => 2, //signal an error.
+    }
+}
+
+impl Tile {
+    /// Parses a tile in the format of the puzzle input: a title line
+    /// "Tile N:" followed by the rows of the tile.
+    pub fn from_string(s: &str) -> Result<Tile, String> {
+        let lines: Vec<String> = s.split('\n').map(str::to_string).collect();
+        if lines.is_empty() {
+            return Err("Tiles must not be empty".to_string());
+        }
+        let id: TileId = match lines[0]
+            .strip_prefix("Tile ")
+            .and_then(|rest| rest.strip_suffix(':'))
+        {
+            Some(digits) => digits
+                .parse()
+                .map_err(|e| format!("failed to parse '{}' as an integer: {}", digits, e))?,
+            None => {
+                return Err(format!("tile is missing a title:\n{}", s));
+            }
+        };
+        if lines.len() < 2 {
+            return Err(format!("tile {} has no rows", id));
+        }
+        let width = lines[1].len();
+        let height = lines.len() - 1;
+        if width != height || lines[1..].iter().any(|line| line.len() != width) {
+            return Err(format!(
+                "Tiles should be square but this has {} rows, {} columns: {:?}",
+                height, width, lines
+            ));
+        }
+        let tiledata = s[lines[0].len() + 1..].chars().collect::<Vec<char>>();
+        let d = Array::from_shape_fn((height, width), |(r, c)| {
+            decode_ascii_tile(r, c, &width, &tiledata)
+        });
+        if d.iter().any(|x| *x == 2) {
+            return Err(format!(
+                "data for tile {} contained unexpected characters",
+                id
+            ));
+        }
+        Ok(Tile::new(id, d))
+    }
+
+    pub fn new(id: TileId, d: Array2<u8>) -> Tile {
+        let mut edges = [[EdgePattern { bits: 0 }; 4]; 8];
+        for how in Manipulation::all() {
+            let m = how.on(&d);
+            for dir in Direction::all().iter() {
+                edges[how.index() as usize][dir.index()] = EdgePattern::from_matrix(dir, &m.view());
+            }
+        }
+        Tile { id, d, edges }
+    }
+
+    /// Returns the pattern on side `dir` of the tile manipulated by
+    /// `how`, without manipulating the tile.
+    pub fn edge(&self, how: &Manipulation, dir: Direction) -> EdgePattern {
+        self.edges[how.index() as usize][dir.index()]
+    }
+
+    /// Returns the patterns on the sides of the manipulated tile, in
+    /// the order of `Direction::all`.
+    pub fn edges(&self, how: &Manipulation) -> &[EdgePattern; 4] {
+        &self.edges[how.index() as usize]
+    }
+
+    pub fn manipulated(&self, how: &Manipulation) -> Array2<u8> {
+        how.on(&self.d)
+    }
+
+    /// Returns the manipulated tile without its border.
+    pub fn interior(&self, how: &Manipulation) -> Array2<u8> {
+        self.manipulated(how)
+            .slice(s![1..self.d.nrows() - 1, 1..self.d.ncols() - 1])
+            .into_owned()
+    }
+}
+
+fn self_test_group(x: &Array2<u8>) -> Result<(), String> {
+    let all = Manipulation::all();
+    for (i, a) in all.iter().enumerate() {
+        if Manipulation::new(a.index()) != *a || a.index() != i as i32 {
+            return Err(format!("FAIL: {} has the wrong index {}", a, a.index()));
+        }
+        if a.to_string().parse::<Manipulation>() != Ok(*a) {
+            return Err(format!("FAIL: {} does not survive a round trip", a));
+        }
+        if a.then(&a.inverse()) != Manipulation::noop()
+            || a.inverse().then(a) != Manipulation::noop()
+        {
+            return Err(format!("FAIL: {} is not undone by {}", a, a.inverse()));
+        }
+        for b in all.iter() {
+            if a.then(b).on(x) != b.on(&a.on(x)) {
+                return Err(format!(
+                    "FAIL: {} then {} is not the same as {}",
+                    a,
+                    b,
+                    a.then(b)
+                ));
+            }
+            if a != b && a.on(x) == b.on(x) {
+                return Err(format!("FAIL: {} and {} have the same result", a, b));
+            }
+        }
+        let wide = Array2::from_shape_fn((2, 3), |(r, c)| r * 3 + c);
+        let turned = a.on(&wide);
+        for ((r, c), v) in wide.indexed_iter() {
+            let (tr, tc) = a.cell((r, c), wide.dim());
+            if turned[[tr, tc]] != *v {
+                return Err(format!(
+                    "FAIL: {} moves cell ({}, {}) of a 2x3 array to the wrong place",
+                    a, r, c
+                ));
+            }
+        }
+    }
+    Ok(())
+}
+
+fn reversed(p: EdgePattern, len: usize) -> EdgePattern {
+    EdgePattern {
+        bits: (0..len).fold(0, |bits, i| (bits << 1) | ((p.bits >> i) & 1)),
+    }
+}
+
+fn self_test_edges(x: &Array2<u8>) -> Result<(), String> {
+    // y fits to the east of x.
+    let mut y = Array2::zeros(x.dim());
+    y.column_mut(0).assign(&x.column(x.ncols() - 1));
+    y[[1, 1]] = 1;
+    for m in Manipulation::all() {
+        let (mx, my) = (m.on(x), m.on(&y));
+        for d in Direction::all().iter() {
+            let before = EdgePattern::from_matrix(d, &x.view());
+            let after = EdgePattern::from_matrix(&m.direction(*d), &mx.view());
+            if after != before && after != reversed(before, x.nrows()) {
+                return Err(format!(
+                    "FAIL: side {} does not end up on side {} under {}",
+                    d,
+                    m.direction(*d),
+                    m
+                ));
+            }
+        }
+        let key = EdgeKey::from_matrix(&m.direction(Direction::E), &mx.view());
+        if key.opposing() != EdgeKey::from_matrix(&m.direction(Direction::W), &my.view()) {
+            return Err(format!("FAIL: tiles which fit no longer fit under {}", m));
+        }
+    }
+    Ok(())
+}
+
+fn self_test_parse() -> Result<(), String> {
+    let t = Tile::from_string("Tile 17:\n#..\n.#.\n..#")?;
+    if t.id != TileId::from(17) || t.d != Array2::eye(3) {
+        return Err(format!("FAIL: tile parsed incorrectly: {:?}", t));
+    }
+    for how in Manipulation::all() {
+        let m = t.manipulated(&how);
+        for dir in Direction::all().iter() {
+            if t.edge(&how, *dir) != EdgePattern::from_matrix(dir, &m.view()) {
+                return Err(format!(
+                    "FAIL: wrong {} edge for {} of tile {}",
+                    dir, how, t.id
+                ));
+            }
+        }
+    }
+    for bad in &[
+        "Tile x:\n#",
+        "Tile 4\n#",
+        "Tile 4:\n#?\n..",
+        "Tile 4:\n##\n.",
+    ] {
+        if Tile::from_string(bad).is_ok() {
+            return Err(format!("FAIL: accepted bad tile {:?}", bad));
+        }
+    }
+    Ok(())
+}
+
+pub fn self_test() -> Result<(), String> {
+    // This has no symmetries, so every manipulation of it is different.
+    let x = arr2(&[[1, 1, 1, 0], [1, 0, 0, 0], [0, 0, 0, 0], [0, 0, 1, 0]]);
+    self_test_group(&x)?;
+    self_test_edges(&x)?;
+    self_test_parse()
+}
@@ -0,0 +1,601 @@
+//! Working out which ingredients contain which allergens, for day 21.
+use crate::constraint::{Cause, Deduction, Problem};
+use crate::explain::Explanation;
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::collections::HashMap;
+use std::collections::HashSet;
+use std::fmt;
+use thiserror::Error;
+
+pub type StringSet = HashSet<String>;
+
+/// A line of the input.
+#[derive(Debug, PartialEq, Eq)]
+pub struct Food {
+    /// The line number, starting at 1.
+    pub line: usize,
+    pub ingredients: StringSet,
+    pub allergens: StringSet,
+}
+
+/// A line of the input which could not be parsed.  Line numbers start
+/// at 1.
+#[derive(Error, Debug, PartialEq, Eq)]
+#[error("line {line}: {reason}")]
+pub struct ParseError {
+    pub line: usize,
+    pub reason: String,
+}
+
+/// Parses a line listing the ingredients of a food, separated by
+/// whitespace, and optionally the allergens it contains, as in
+/// "mxmxvkd kfcds (contains dairy, fish)".  Returns None for a blank
+/// line.
+fn parse_line(line: &str) -> Result<Option<(StringSet, StringSet)>, String> {
+    let (ingredients, allergens) = match line.find('(') {
+        None => (line, None),
+        Some(open) => {
+            let rest = line[open + 1..].trim_end();
+            let list = rest
+                .strip_suffix(')')
+                .ok_or_else(|| "the allergen list should end with ')'".to_string())?;
+            let list = list
+                .trim_start()
+                .strip_prefix("contains")
+                .filter(|rest| rest.is_empty() || rest.starts_with(char::is_whitespace))
+                .ok_or_else(|| "the allergen list should begin with 'contains'".to_string())?;
+            (&line[..open], Some(list))
+        }
+    };
+    let ingredients: StringSet = ingredients.split_whitespace().map(str::to_owned).collect();
+    if let Some(bad) = ingredients
+        .iter()
+        .find(|name| name.contains(&[')', ','][..]))
+    {
+        return Err(format!("'{}' is not a valid ingredient name", bad));
+    }
+    let allergens: StringSet = match allergens.filter(|list| !list.trim().is_empty()) {
+        None => StringSet::new(),
+        Some(list) => list
+            .split(',')
+            .map(|a| match a.trim() {
+                "" => Err("the allergen list contains an empty name".to_string()),
+                name if name.contains(char::is_whitespace) || name.contains('(') => {
+                    Err(format!("'{}' is not a valid allergen name", name))
+                }
+                name => Ok(name.to_string()),
+            })
+            .collect::<Result<StringSet, String>>()?,
+    };
+    if ingredients.is_empty() {
+        if allergens.is_empty() && line.trim().is_empty() {
+            return Ok(None);
+        }
+        return Err("the line lists no ingredients".to_string());
+    }
+    Ok(Some((ingredients, allergens)))
+}
+
+/// Parses the puzzle input, returning the ingredients and allergens
+/// listed on each line which could be parsed, and the problems with
+/// the others.  Blank lines are ignored.
+pub fn parse(lines: &[String]) -> (Vec<Food>, Vec<ParseError>) {
+    let mut result: Vec<Food> = Vec::new();
+    let mut errors: Vec<ParseError> = Vec::new();
+    for (n, line) in lines.iter().enumerate() {
+        match parse_line(line) {
+            Ok(Some((ingredients, allergens))) => result.push(Food {
+                line: n + 1,
+                ingredients,
+                allergens,
+            }),
+            Ok(None) => (),
+            Err(reason) => errors.push(ParseError {
+                line: n + 1,
+                reason,
+            }),
+        }
+    }
+    (result, errors)
+}
+
+/// Parses the puzzle input, failing if any line cannot be parsed.
+pub fn parse_strict(lines: &[String]) -> Result<Vec<Food>, String> {
+    match parse(lines) {
+        (result, errors) if errors.is_empty() => Ok(result),
+        (_, errors) => Err(errors
+            .iter()
+            .map(|e| e.to_string())
+            .collect::<Vec<String>>()
+            .join("\n")),
+    }
+}
+
+/// Why we came to a conclusion about whether an ingredient contains
+/// an allergen.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Reason {
+    /// The line lists the allergen but not the ingredient.
+    NotOnLine(usize),
+    /// In this round of elimination (counting from 1), the ingredient
+    /// was the only one which could still contain the allergen, or the
+    /// allergen was the only one the ingredient could still contain.
+    SoleCandidate(usize),
+    /// This other ingredient contains the allergen.
+    Elsewhere(String),
+    /// The ingredient contains this other allergen.
+    ContainsAnother(String),
+    /// There is no way to put each allergen in a different
+    /// ingredient which puts the allergen in this ingredient.
+    Unmatchable,
+}
+
+impl fmt::Display for Reason {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Reason::NotOnLine(line) => write!(f, "line {} lists it but not this ingredient", line),
+            Reason::SoleCandidate(round) => {
+                write!(f, "it was the only possibility left in round {}", round)
+            }
+            Reason::Elsewhere(ing_name) => write!(f, "{} contains it", ing_name),
+            Reason::ContainsAnother(allergen) => write!(f, "it contains {}", allergen),
+            Reason::Unmatchable => f.write_str("then the other allergens could not all be placed"),
+        }
+    }
+}
+
+/// Something we concluded about an ingredient, in the order we
+/// concluded it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Conclusion {
+    pub ingredient: String,
+    pub allergen: String,
+    /// Whether the ingredient contains the allergen.
+    pub contains: bool,
+    pub reason: Reason,
+}
+
+#[derive(Debug)]
+struct Ingredient {
+    name: String,
+    possible_allergens: StringSet,
+    excluded_allergens: StringSet,
+    definite_allergen: Option<String>,
+    /// Why we concluded what we did about each allergen.  Only the
+    /// first reason for each conclusion is kept.
+    reasons: BTreeMap<String, Reason>,
+}
+
+impl Ingredient {
+    fn new(name: &str, all_allergens: &StringSet) -> Ingredient {
+        Ingredient {
+            name: name.to_string(),
+            possible_allergens: all_allergens.clone(),
+            excluded_allergens: StringSet::new(),
+            definite_allergen: None,
+            reasons: BTreeMap::new(),
+        }
+    }
+
+    fn is_non_allergenic(&self) -> Option<bool> {
+        if self.definite_allergen.is_some() {
+            Some(false)
+        } else if !self.possible_allergens.is_empty() {
+            None
+        } else {
+            Some(true)
+        }
+    }
+
+    fn conclude_must_contain(&mut self, allergen: &str, why: Reason, log: &mut Vec<Conclusion>) {
+        assert!(!self.excluded_allergens.contains(allergen));
+        log.push(Conclusion {
+            ingredient: self.name.clone(),
+            allergen: allergen.to_string(),
+            contains: true,
+            reason: why.clone(),
+        });
+        self.reasons.insert(allergen.to_string(), why);
+        assert!(self.definite_allergen.is_none());
+        self.definite_allergen = Some(allergen.to_string());
+        self.possible_allergens.remove(allergen);
+    }
+
+    fn conclude_must_not_contain(
+        &mut self,
+        allergen: &str,
+        why: Reason,
+        log: &mut Vec<Conclusion>,
+    ) {
+        match &self.definite_allergen {
+            None => (),
+            Some(a) => {
+                assert!(
+                    a != allergen,
+                    "we already thought this ingredient did contain that"
+                );
+            }
+        }
+        if self.excluded_allergens.insert(allergen.to_string()) {
+            log.push(Conclusion {
+                ingredient: self.name.clone(),
+                allergen: allergen.to_string(),
+                contains: false,
+                reason: why.clone(),
+            });
+        }
+        self.possible_allergens.remove(allergen);
+        self.reasons.entry(allergen.to_string()).or_insert(why);
+    }
+}
+
+/// Makes an ingredient for each ingredient name in `foods`, ruling
+/// out the allergens which the input says each cannot contain.
+fn constrain(foods: &[Food], log: &mut Vec<Conclusion>) -> HashMap<String, Ingredient> {
+    let all_allergens: StringSet = foods.iter().flat_map(|f| &f.allergens).cloned().collect();
+    let mut all_ingredients: HashMap<String, Ingredient> = foods
+        .iter()
+        .flat_map(|f| &f.ingredients)
+        .map(|ing_name| {
+            (
+                ing_name.to_string(),
+                Ingredient::new(ing_name, &all_allergens),
+            )
+        })
+        .collect();
+    let all_ingredient_names: StringSet = all_ingredients.keys().cloned().collect();
+
+    for food in foods {
+        // One of these ingredients listed on this line contains each
+        // of these allergens.  Therefore any ingredient not listed
+        // here cannot contain any of these allergens, since we are
+        // told that any allergen occurs in just one ingredient.
+        // (Sorting keeps the order of the conclusions the same each
+        // time.)
+        let mut allergens: Vec<&String> = food.allergens.iter().collect();
+        allergens.sort();
+        let mut absent: Vec<&String> = all_ingredient_names.difference(&food.ingredients).collect();
+        absent.sort();
+        for allergen in allergens {
+            for ing_name in absent.iter() {
+                all_ingredients
+                    .get_mut(*ing_name)
+                    .unwrap()
+                    .conclude_must_not_contain(allergen, Reason::NotOnLine(food.line), log);
+            }
+        }
+    }
+    all_ingredients
+}
+
+/// Works out as much as we can about which ingredient contains each
+/// allergen.  Returns what we know about each ingredient, and whether
+/// that settles which ingredient contains each allergen.  Each
+/// conclusion is added to `log`.
+fn deduce(
+    foods: &[Food],
+    log: &mut Vec<Conclusion>,
+) -> Result<(HashMap<String, Ingredient>, bool), String> {
+    let mut all_ingredients = constrain(foods, log);
+    // Each allergen is a variable whose value is the ingredient which
+    // contains it.
+    let mut allergens: Vec<String> = foods
+        .iter()
+        .flat_map(|f| &f.allergens)
+        .cloned()
+        .collect::<StringSet>()
+        .into_iter()
+        .collect();
+    allergens.sort();
+    let mut names: Vec<String> = all_ingredients.keys().cloned().collect();
+    names.sort();
+    let candidates = allergens
+        .iter()
+        .map(|a| {
+            names
+                .iter()
+                .enumerate()
+                .filter(|(_, name)| all_ingredients[*name].possible_allergens.contains(a))
+                .map(|(i, _)| i)
+                .collect()
+        })
+        .collect();
+    let mut problem = Problem::new(names.len(), candidates);
+    if problem.propagate().is_err() {
+        return Err("the allergens cannot all be in different ingredients".to_string());
+    }
+    for deduction in problem.deductions() {
+        match *deduction {
+            Deduction::Assigned {
+                var, value, round, ..
+            } => all_ingredients
+                .get_mut(&names[value])
+                .unwrap()
+                .conclude_must_contain(&allergens[var], Reason::SoleCandidate(round), log),
+            Deduction::Eliminated { var, value, cause } => {
+                let why = match cause {
+                    Cause::ValueTaken(other) => Reason::ContainsAnother(allergens[other].clone()),
+                    Cause::VariableAssigned(other) => Reason::Elsewhere(names[other].clone()),
+                    Cause::Unmatchable => Reason::Unmatchable,
+                };
+                all_ingredients
+                    .get_mut(&names[value])
+                    .unwrap()
+                    .conclude_must_not_contain(&allergens[var], why, log);
+            }
+        }
+    }
+    Ok((all_ingredients, problem.is_solved()))
+}
+
+/// What we know once we have worked out which ingredient contains
+/// each allergen.
+#[derive(Debug, Serialize)]
+pub struct Solution {
+    /// The ingredient containing each allergen.
+    pub allergens: BTreeMap<String, String>,
+    /// The ingredients which contain no allergen, in alphabetical
+    /// order.
+    pub safe: Vec<String>,
+    /// The number of times the safe ingredients appear in the input
+    /// (the answer to part 1).
+    pub safe_mentions: usize,
+    /// Why we concluded that each ingredient does or does not contain
+    /// each allergen.
+    #[serde(skip)]
+    pub reasons: BTreeMap<String, BTreeMap<String, Reason>>,
+    /// Everything we concluded, in order.
+    #[serde(skip)]
+    pub conclusions: Vec<Conclusion>,
+}
+
+impl Solution {
+    /// The dangerous ingredients, ordered by the name of the allergen
+    /// they contain and separated by commas (the answer to part 2).
+    pub fn canonical_dangerous_list(&self) -> String {
+        self.allergens
+            .values()
+            .map(String::as_str)
+            .collect::<Vec<&str>>()
+            .join(",")
+    }
+
+    /// Describes each deduction in the order we made it, and then the
+    /// answers to each part.
+    pub fn explain(&self) -> Explanation {
+        let mut explanation = Explanation::new();
+        for c in self.conclusions.iter() {
+            let (kind, verb) = if c.contains {
+                ("contains", "contains")
+            } else {
+                ("excluded", "does not contain")
+            };
+            explanation
+                .step(
+                    1,
+                    kind,
+                    format!("{} {} {}: {}", c.ingredient, verb, c.allergen, c.reason),
+                )
+                .fact("ingredient", c.ingredient.as_str())
+                .fact("allergen", c.allergen.as_str());
+        }
+        explanation
+            .step(
+                1,
+                "safe",
+                format!(
+                    "so {} contain no allergen, and they appear {} times",
+                    self.safe.join(", "),
+                    self.safe_mentions
+                ),
+            )
+            .fact("safe", self.safe.clone())
+            .fact("mentions", self.safe_mentions);
+        for (allergen, ing_name) in self.allergens.iter() {
+            explanation
+                .step(
+                    2,
+                    "dangerous",
+                    format!("{} contains {}", ing_name, allergen),
+                )
+                .fact("ingredient", ing_name.as_str())
+                .fact("allergen", allergen.as_str());
+        }
+        explanation
+            .step(
+                2,
+                "list",
+                format!(
+                    "in order of allergen, the dangerous ingredients are {}",
+                    self.canonical_dangerous_list()
+                ),
+            )
+            .fact("list", self.canonical_dangerous_list());
+        explanation
+    }
+}
+
+/// Works out which ingredient contains each allergen.
+pub fn solve(foods: &[Food]) -> Result<Solution, String> {
+    let mut conclusions = Vec::new();
+    let (all_ingredients, solved) = deduce(foods, &mut conclusions)?;
+    if !solved {
+        return Err("not solvable".to_string());
+    }
+
+    let allergens: BTreeMap<String, String> = all_ingredients
+        .values()
+        .filter_map(|ing| {
+            ing.definite_allergen
+                .as_ref()
+                .map(|a| (a.to_string(), ing.name.to_string()))
+        })
+        .collect();
+    let mut safe: Vec<String> = all_ingredients
+        .values()
+        .filter(|ing| ing.is_non_allergenic() == Some(true))
+        .map(|ing| ing.name.to_string())
+        .collect();
+    safe.sort();
+    let safe_mentions = foods
+        .iter()
+        .flat_map(|f| f.ingredients.iter())
+        .filter(|ing_name| safe.binary_search(ing_name).is_ok())
+        .count();
+    let reasons = all_ingredients
+        .into_iter()
+        .map(|(name, ing)| (name, ing.reasons))
+        .collect();
+    Ok(Solution {
+        allergens,
+        safe,
+        safe_mentions,
+        reasons,
+        conclusions,
+    })
+}
+
+pub fn self_test() -> Result<(), String> {
+    // Only z could contain c, though z could contain a or b too.
+    let lines: Vec<String> = [
+        "x y z (contains a)",
+        "x y z (contains b)",
+        "z w (contains c)",
+        "z q (contains c)",
+    ]
+    .iter()
+    .map(|s| s.to_string())
+    .collect();
+    let (ingredients, solved) = deduce(&parse_strict(&lines)?, &mut Vec::new())?;
+    let possible = |name: &str| {
+        let mut v: Vec<String> = ingredients[name]
+            .possible_allergens
+            .iter()
+            .cloned()
+            .collect();
+        v.sort();
+        v.join(",")
+    };
+    if ingredients["z"].definite_allergen.as_deref() != Some("c") || possible("x") != "a,b" {
+        return Err(format!(
+            "FAIL: z contains {:?} and x could contain {}",
+            ingredients["z"].definite_allergen,
+            possible("x")
+        ));
+    }
+    // x and y could each contain either of a and b.
+    if solved || solve(&parse_strict(&lines)?).is_ok() {
+        return Err("FAIL: an ambiguous input was solved".to_string());
+    }
+    let conflicting: Vec<String> = ["x (contains a)", "x (contains b)"]
+        .iter()
+        .map(|s| s.to_string())
+        .collect();
+    if deduce(&parse_strict(&conflicting)?, &mut Vec::new()).is_ok() {
+        return Err("FAIL: two allergens cannot both be in x".to_string());
+    }
+    // Where there are as many ingredients as allergens, an ingredient
+    // which could contain only one allergen must contain it.
+    let square: Vec<String> = ["x y (contains a, b)", "y (contains b)"]
+        .iter()
+        .map(|s| s.to_string())
+        .collect();
+    let solution = solve(&parse_strict(&square)?)?;
+    if solution.canonical_dangerous_list() != "x,y" {
+        return Err(format!(
+            "FAIL: wrong dangerous ingredient list {}",
+            solution.canonical_dangerous_list()
+        ));
+    }
+
+    let sample: Vec<String> = [
+        "mxmxvkd kfcds sqjhc nhms (contains dairy, fish)",
+        "trh fvjkl sbzzf mxmxvkd (contains dairy)",
+        "sqjhc fvjkl (contains soy)",
+        "sqjhc mxmxvkd sbzzf (contains fish)",
+    ]
+    .iter()
+    .map(|s| s.to_string())
+    .collect();
+    let solution = solve(&parse_strict(&sample)?)?;
+    if solution.safe != ["kfcds", "nhms", "sbzzf", "trh"] || solution.safe_mentions != 5 {
+        return Err(format!(
+            "FAIL: wrong safe ingredients {:?} ({} mentions)",
+            solution.safe, solution.safe_mentions
+        ));
+    }
+    if solution.canonical_dangerous_list() != "mxmxvkd,sqjhc,fvjkl" {
+        return Err(format!(
+            "FAIL: wrong dangerous ingredient list {}",
+            solution.canonical_dangerous_list()
+        ));
+    }
+    let reasons = &solution.reasons["mxmxvkd"];
+    let expected = [
+        ("dairy", Reason::SoleCandidate(1)),
+        ("fish", Reason::ContainsAnother("dairy".to_string())),
+        ("soy", Reason::NotOnLine(3)),
+    ];
+    if reasons.len() != 3 || expected.iter().any(|(a, why)| reasons.get(*a) != Some(why)) {
+        return Err(format!("FAIL: wrong reasons for mxmxvkd: {:?}", reasons));
+    }
+    // Each conclusion is made once, so there is one for each pairing
+    // of an ingredient and an allergen.
+    if solution.conclusions.len() != 7 * 3 {
+        return Err(format!(
+            "FAIL: made {} conclusions",
+            solution.conclusions.len()
+        ));
+    }
+    let explanation = solution.explain();
+    let first = &explanation.steps()[0].text;
+    if first != "fvjkl does not contain dairy: line 1 lists it but not this ingredient" {
+        return Err(format!("FAIL: explanation began with '{}'", first));
+    }
+    self_test_parse()?;
+    Ok(())
+}
+
+fn self_test_parse() -> Result<(), String> {
+    let set = |items: &[&str]| -> StringSet { items.iter().map(|s| s.to_string()).collect() };
+    let good: &[(&str, &[&str], &[&str])] = &[
+        ("a b (contains x, y)", &["a", "b"], &["x", "y"]),
+        ("  a\tb   (  contains x,y )  ", &["a", "b"], &["x", "y"]),
+        ("a b", &["a", "b"], &[]),
+        ("a (contains)", &["a"], &[]),
+        (
+            "crème brûlée (contains œufs)",
+            &["crème", "brûlée"],
+            &["œufs"],
+        ),
+    ];
+    for (line, ingredients, allergens) in good {
+        let expected = (set(ingredients), set(allergens));
+        if parse_line(line) != Ok(Some(expected)) {
+            return Err(format!("FAIL: parsed {:?} as {:?}", line, parse_line(line)));
+        }
+    }
+    for line in &[
+        "a (contains x",
+        "a (has x)",
+        "a (containsx)",
+        "(contains x)",
+        "a (contains x,, y)",
+        "a, b (contains x)",
+        "a (contains x) b",
+    ] {
+        if parse_line(line).is_ok() {
+            return Err(format!("FAIL: accepted invalid line {:?}", line));
+        }
+    }
+    let lines: Vec<String> = ["a (contains x)", "", "b (contains"]
+        .iter()
+        .map(|s| s.to_string())
+        .collect();
+    let (foods, errors) = parse(&lines);
+    if foods.len() != 1 || errors.iter().map(|e| e.line).collect::<Vec<_>>() != [3] {
+        return Err(format!("FAIL: wrong errors {:?}", errors));
+    }
+    Ok(())
+}
@@ -0,0 +1,421 @@
+//! Decks of cards for Combat (day 22).
+//!
+//! This holds the state of a game and the parts of the rules which do
+//! not involve sub-games: parsing the decks, playing a round by the
+//! highest card, and scoring.  Hands and game states can be built
+//! from slices of cards and serialised, so they need not come from
+//! the text format of the puzzle input.
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::collections::VecDeque;
+use std::fmt;
+use std::mem::size_of;
+use thiserror::Error;
+
+pub type Card = u32;
+
+/// A player, numbered from 0 in the order they appear in the input.
+pub type Player = usize;
+
+/// A problem with the decks in the input.  Line numbers start at 1.
+#[derive(Debug, Error, PartialEq, Eq)]
+pub enum DeckError {
+    #[error("line {line}: '{text}' is not a card: {reason}")]
+    BadCard {
+        line: usize,
+        text: String,
+        reason: String,
+    },
+    #[error("line {line}: card {card} comes before the name of any player")]
+    NoPlayer { line: usize, card: Card },
+    #[error("line {line}: card {card} was already dealt to {owner} on line {first_line}")]
+    DuplicateCard {
+        line: usize,
+        card: Card,
+        owner: String,
+        first_line: usize,
+    },
+    #[error("line {line}: {name} already has a deck, starting on line {first_line}")]
+    DuplicatePlayer {
+        line: usize,
+        name: String,
+        first_line: usize,
+    },
+    #[error("there must be at least two players, but there are {0}")]
+    TooFewPlayers(usize),
+}
+
+/// Parses the decks in the input.  Each deck starts with a line
+/// giving the name of its player followed by a colon (for example
+/// "Player 1:"), and each following line holds a card; blank lines
+/// are ignored.  Returns the name and deck of each player.
+pub fn parse_decks(lines: &[String]) -> Result<Vec<(String, Vec<Card>)>, DeckError> {
+    let mut decks: Vec<(String, Vec<Card>)> = Vec::new();
+    // The line on which each player's name appears.
+    let mut players: HashMap<String, usize> = HashMap::new();
+    // The player holding each card, and the line it is on.
+    let mut dealt: HashMap<Card, (Player, usize)> = HashMap::new();
+    for (n, line) in lines.iter().enumerate() {
+        let line_number = n + 1;
+        let text = line.trim();
+        if text.is_empty() {
+            continue;
+        }
+        if let Some(name) = text.strip_suffix(':') {
+            let name = name.trim().to_string();
+            if let Some(first_line) = players.get(&name) {
+                return Err(DeckError::DuplicatePlayer {
+                    line: line_number,
+                    name,
+                    first_line: *first_line,
+                });
+            }
+            players.insert(name.clone(), line_number);
+            decks.push((name, Vec::new()));
+            continue;
+        }
+        let card: Card = text.parse().map_err(|e| DeckError::BadCard {
+            line: line_number,
+            text: text.to_string(),
+            reason: format!("{}", e),
+        })?;
+        let player = match decks.len() {
+            0 => {
+                return Err(DeckError::NoPlayer {
+                    line: line_number,
+                    card,
+                })
+            }
+            n => n - 1,
+        };
+        if let Some((owner, first_line)) = dealt.get(&card) {
+            return Err(DeckError::DuplicateCard {
+                line: line_number,
+                card,
+                owner: decks[*owner].0.clone(),
+                first_line: *first_line,
+            });
+        }
+        dealt.insert(card, (player, line_number));
+        decks[player].1.push(card);
+    }
+    if decks.len() < 2 {
+        return Err(DeckError::TooFewPlayers(decks.len()));
+    }
+    Ok(decks)
+}
+
+/// The cards held by a player, from the top of the deck down.  A
+/// hand serialises as a list of cards.
+#[derive(Debug, Clone, PartialOrd, Ord, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(transparent)]
+pub struct PlayerHand {
+    cards: VecDeque<Card>,
+}
+
+impl PlayerHand {
+    pub fn new(cards: &[Card]) -> PlayerHand {
+        PlayerHand {
+            cards: cards.iter().copied().collect(),
+        }
+    }
+
+    /// The cards, from the top of the deck down.
+    pub fn cards(&self) -> impl Iterator<Item = &Card> {
+        self.cards.iter()
+    }
+
+    /// Multiplies each card by its position counting up from the
+    /// bottom of the deck (which is 1), and adds up the results.
+    pub fn score(&self) -> usize {
+        self.cards
+            .iter()
+            .rev()
+            .enumerate()
+            .map(|(i, c)| (i + 1) * (*c) as usize)
+            .sum()
+    }
+
+    pub fn play_next_card(&mut self) -> Card {
+        self.cards.pop_front().expect("hand should not be empty")
+    }
+
+    /// Puts `cards` at the bottom of the deck, in order.
+    pub fn win_cards(&mut self, cards: &[Card]) {
+        self.cards.extend(cards);
+    }
+
+    /// Keeps only the top `max_cards` cards.
+    pub fn truncate(&mut self, max_cards: usize) {
+        self.cards.truncate(max_cards);
+    }
+
+    pub fn num_cards(&self) -> usize {
+        self.cards.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.cards.is_empty()
+    }
+
+    /// Appends the number of cards and then the cards themselves to
+    /// `out`, each as a LEB128 varint.  Since card values are usually
+    /// small, this mostly takes one byte per card.
+    pub fn pack_into(&self, out: &mut Vec<u8>) {
+        fn push_varint(mut n: u32, out: &mut Vec<u8>) {
+            while n >= 0x80 {
+                out.push((n & 0x7f) as u8 | 0x80);
+                n >>= 7;
+            }
+            out.push(n as u8);
+        }
+        push_varint(self.cards.len() as u32, out);
+        for c in self.cards.iter() {
+            push_varint(*c, out);
+        }
+    }
+
+    /// The number of bytes of memory used by this hand.
+    pub fn bytes(&self) -> usize {
+        size_of::<PlayerHand>() + self.cards.capacity() * size_of::<Card>()
+    }
+}
+
+impl fmt::Display for PlayerHand {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(&itertools::join(
+            self.cards.iter().map(|c| format!("{}", c)),
+            ", ",
+        ))
+    }
+}
+
+/// The player who wins a round without a sub-game; this is the first
+/// of the players with the highest card.
+pub fn highest_card_winner(played: &[(Player, Card)]) -> Player {
+    played
+        .iter()
+        .rev()
+        .max_by_key(|(_, c)| *c)
+        .map(|(p, _)| *p)
+        .expect("cards were played")
+}
+
+/// The cards the winner of a round takes, in order: their own card,
+/// followed by the others from highest to lowest.
+pub fn winnings(played: &[(Player, Card)], winner: Player) -> Vec<Card> {
+    let mut result: Vec<Card> = played
+        .iter()
+        .filter(|(p, _)| *p != winner)
+        .map(|(_, c)| *c)
+        .collect();
+    result.sort_unstable_by(|a, b| b.cmp(a));
+    let winning_card = played
+        .iter()
+        .find(|(p, _)| *p == winner)
+        .map(|(_, c)| *c)
+        .expect("the winner played a card");
+    result.insert(0, winning_card);
+    result
+}
+
+/// The hands of all the players, in order.  A game state serialises
+/// as a list of hands, each of which is a list of cards.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(transparent)]
+pub struct GameState {
+    hands: Vec<PlayerHand>,
+}
+
+impl GameState {
+    /// Parses the decks in the format of the puzzle input (see
+    /// `parse_decks`).
+    pub fn new(lines: &[String]) -> Result<GameState, String> {
+        let decks = parse_decks(lines).map_err(|e| e.to_string())?;
+        let cards: Vec<Vec<Card>> = decks.into_iter().map(|(_, cards)| cards).collect();
+        Ok(GameState::from_cards(&cards))
+    }
+
+    pub fn from_cards(cards: &[Vec<Card>]) -> GameState {
+        GameState {
+            hands: cards.iter().map(|c| PlayerHand::new(c)).collect(),
+        }
+    }
+
+    pub fn from_slices(cards: &[&[Card]]) -> GameState {
+        GameState {
+            hands: cards.iter().map(|c| PlayerHand::new(c)).collect(),
+        }
+    }
+
+    pub fn cards(&self) -> Vec<Vec<Card>> {
+        self.hands
+            .iter()
+            .map(|h| h.cards.iter().copied().collect())
+            .collect()
+    }
+
+    pub fn hands(&self) -> &[PlayerHand] {
+        &self.hands
+    }
+
+    pub fn hand(&self, player: Player) -> &PlayerHand {
+        &self.hands[player]
+    }
+
+    pub fn hand_mut(&mut self, player: Player) -> &mut PlayerHand {
+        &mut self.hands[player]
+    }
+
+    /// The score of each player's hand.
+    pub fn scores(&self) -> Vec<usize> {
+        self.hands.iter().map(PlayerHand::score).collect()
+    }
+
+    /// Encodes the state as a byte string; different states always
+    /// have different encodings.
+    pub fn packed(&self) -> Vec<u8> {
+        let mut result = Vec::with_capacity(self.total_card_count() + self.hands.len());
+        for hand in self.hands.iter() {
+            hand.pack_into(&mut result);
+        }
+        result.shrink_to_fit();
+        result
+    }
+
+    /// The number of bytes of memory used by the hands.
+    pub fn bytes(&self) -> usize {
+        self.hands.iter().map(PlayerHand::bytes).sum()
+    }
+
+    pub fn total_card_count(&self) -> usize {
+        self.hands.iter().map(PlayerHand::num_cards).sum()
+    }
+
+    /// The players who still have cards.
+    pub fn players_in(&self) -> Vec<Player> {
+        (0..self.hands.len())
+            .filter(|p| !self.hands[*p].is_empty())
+            .collect()
+    }
+
+    /// Takes the top card of each player who is still in.
+    pub fn play_cards(&mut self) -> Vec<(Player, Card)> {
+        self.players_in()
+            .iter()
+            .map(|p| (*p, self.hands[*p].play_next_card()))
+            .collect()
+    }
+
+    /// Plays a round in which the highest card wins, returning the
+    /// winner.
+    pub fn play_round(&mut self) -> Player {
+        let played = self.play_cards();
+        let winner = highest_card_winner(&played);
+        self.hands[winner].win_cards(&winnings(&played, winner));
+        winner
+    }
+}
+
+impl fmt::Display for GameState {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(&itertools::join(
+            self.hands
+                .iter()
+                .enumerate()
+                .map(|(i, hand)| format!("Player {}: {}", i + 1, hand)),
+            "\n",
+        ))
+    }
+}
+
+/// Splits `s` into lines.
+fn lines_of(s: &str) -> Vec<String> {
+    s.lines().map(str::to_string).collect()
+}
+
+fn self_test_parse() -> Result<(), String> {
+    let decks = parse_decks(&lines_of(
+        "Alice:\n 3 \n1\n\nBob the Builder :\n2\nCarol:\n",
+    ))
+    .map_err(|e| e.to_string())?;
+    let expected = vec![
+        ("Alice".to_string(), vec![3, 1]),
+        ("Bob the Builder".to_string(), vec![2]),
+        ("Carol".to_string(), vec![]),
+    ];
+    if decks != expected {
+        return Err(format!("FAIL: parsed decks as {:?}", decks));
+    }
+    let cases: &[(&str, DeckError)] = &[
+        (
+            "Player 1:\n1\nPlayer 2:\nten\n",
+            DeckError::BadCard {
+                line: 4,
+                text: "ten".to_string(),
+                reason: "invalid digit found in string".to_string(),
+            },
+        ),
+        ("7\nPlayer 1:\n", DeckError::NoPlayer { line: 1, card: 7 }),
+        (
+            "A:\n1\n2\nB:\n3\n2\n",
+            DeckError::DuplicateCard {
+                line: 6,
+                card: 2,
+                owner: "A".to_string(),
+                first_line: 3,
+            },
+        ),
+        (
+            "A:\n1\nB:\n2\nA:\n3\n",
+            DeckError::DuplicatePlayer {
+                line: 5,
+                name: "A".to_string(),
+                first_line: 1,
+            },
+        ),
+        ("Solo:\n1\n2\n", DeckError::TooFewPlayers(1)),
+    ];
+    for (input, expected) in cases.iter() {
+        match parse_decks(&lines_of(input)) {
+            Err(e) if e == *expected => (),
+            other => {
+                return Err(format!(
+                    "FAIL: parsing {:?} gave {:?}, expected {:?}",
+                    input, other, expected
+                ));
+            }
+        }
+    }
+    Ok(())
+}
+
+pub fn self_test() -> Result<(), String> {
+    let mut state = GameState::new(&lines_of(
+        "Player 1:\n9\n2\n6\n3\n1\n\nPlayer 2:\n5\n8\n4\n7\n10\n",
+    ))?;
+    if state != GameState::from_slices(&[&[9, 2, 6, 3, 1], &[5, 8, 4, 7, 10]]) {
+        return Err(format!("FAIL: parsed the sample as\n{}", state));
+    }
+    // The first round of the sample.
+    if state.play_round() != 0 || state.cards() != vec![vec![2, 6, 3, 1, 9, 5], vec![8, 4, 7, 10]] {
+        return Err(format!("FAIL: wrong state after one round:\n{}", state));
+    }
+    while state.players_in().len() > 1 {
+        state.play_round();
+    }
+    if state.scores() != vec![0, 306] || state.hand(1).num_cards() != 10 {
+        return Err(format!("FAIL: wrong final state of the sample:\n{}", state));
+    }
+    let expected = "Player 1: \nPlayer 2: 3, 2, 10, 6, 8, 5, 9, 4, 7, 1";
+    if state.to_string() != expected {
+        return Err(format!("FAIL: displayed the sample as\n{}", state));
+    }
+    // With three players, the winner takes the other cards highest
+    // first.
+    let mut three = GameState::from_slices(&[&[2], &[7], &[5]]);
+    if three.play_round() != 1 || three.hand(1).cards().copied().collect::<Vec<_>>() != [7, 5, 2] {
+        return Err(format!("FAIL: wrong three-player round:\n{}", three));
+    }
+    self_test_parse()
+}
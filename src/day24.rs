@@ -0,0 +1,927 @@
+//! The hexagonal floor of the lobby, for day 24.
+use crate::hex::{Axial, Direction, Position};
+use serde::Serialize;
+use std::collections::HashMap;
+use std::collections::HashSet;
+use std::fmt;
+use std::str::FromStr;
+use thiserror::Error;
+
+/// A line of the input which could not be parsed.
+#[derive(Error, Debug, PartialEq, Eq)]
+#[error("line {line}, offset {offset}: {reason}")]
+pub struct ParseError {
+    /// The line number, starting at 1.
+    pub line: usize,
+    /// The byte offset within the line of the bad sequence.
+    pub offset: usize,
+    pub reason: String,
+}
+
+/// Splits a line of the input (line number `line`) into directions.
+pub fn split_directions(line: usize, s: &str) -> Result<Vec<Direction>, ParseError> {
+    let fail = |offset: usize, reason: String| ParseError {
+        line,
+        offset,
+        reason,
+    };
+    // The capacity here is an overestimate, but by at most a factor
+    // of 2.
+    let mut result = Vec::with_capacity(s.len());
+    let mut chars = s.char_indices();
+    while let Some((offset, ch)) = chars.next() {
+        let dir = match ch {
+            'e' => Direction::E,
+            'w' => Direction::W,
+            // Note that there is no "North" or "South" output, so
+            // both 'n' and 's' must be followed by 'e' or 'w'.
+            'n' | 's' => match (ch, chars.next()) {
+                ('n', Some((_, 'e'))) => Direction::NE,
+                ('n', Some((_, 'w'))) => Direction::NW,
+                ('s', Some((_, 'e'))) => Direction::SE,
+                ('s', Some((_, 'w'))) => Direction::SW,
+                (_, Some((_, other))) => {
+                    return Err(fail(
+                        offset,
+                        format!("'{}' should be followed by 'e' or 'w', not '{}'", ch, other),
+                    ));
+                }
+                (_, None) => {
+                    return Err(fail(
+                        offset,
+                        format!(
+                            "the line ends after '{}', which should be followed by 'e' or 'w'",
+                            ch
+                        ),
+                    ));
+                }
+            },
+            other => {
+                return Err(fail(offset, format!("unexpected character '{}'", other)));
+            }
+        };
+        result.push(dir);
+    }
+    Ok(result)
+}
+
+/// The inverse of `split_directions`: writes directions as a line of
+/// the input.
+pub fn join_directions(directions: &[Direction]) -> String {
+    directions.iter().map(|d| d.to_string()).collect()
+}
+
+/// The rule by which the tiles of the exhibit flip each day: a white
+/// tile becomes black when its number of black neighbours is one of
+/// the birth counts, and a black tile stays black when its number of
+/// black neighbours is one of the survival counts.
+///
+/// Rules are written in the `B2/S12` notation used for life-like
+/// automata (that one is the rule of the puzzle).
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Rule {
+    birth: Vec<usize>,
+    survival: Vec<usize>,
+}
+
+impl Rule {
+    pub fn new(birth: &[usize], survival: &[usize]) -> Result<Rule, String> {
+        if birth.contains(&0) {
+            // Every tile of the infinite floor would become black.
+            return Err("a rule cannot flip tiles with no black neighbours".to_string());
+        }
+        if let Some(n) = birth.iter().chain(survival.iter()).find(|n| **n > 6) {
+            return Err(format!("a tile cannot have {} neighbours", n));
+        }
+        let normalise = |counts: &[usize]| -> Vec<usize> {
+            let mut v = counts.to_vec();
+            v.sort_unstable();
+            v.dedup();
+            v
+        };
+        Ok(Rule {
+            birth: normalise(birth),
+            survival: normalise(survival),
+        })
+    }
+
+    pub fn birth(&self) -> &[usize] {
+        &self.birth
+    }
+
+    pub fn survival(&self) -> &[usize] {
+        &self.survival
+    }
+
+    /// Decides whether a tile is black on the next day.
+    pub fn next_state(&self, black: bool, neighbours: usize) -> bool {
+        if black {
+            self.survival.contains(&neighbours)
+        } else {
+            self.birth.contains(&neighbours)
+        }
+    }
+}
+
+impl Default for Rule {
+    /// The rule of the puzzle: a black tile with zero or more than 2
+    /// black neighbours becomes white, and a white tile with exactly
+    /// 2 black neighbours becomes black.
+    fn default() -> Rule {
+        Rule {
+            birth: vec![2],
+            survival: vec![1, 2],
+        }
+    }
+}
+
+impl fmt::Display for Rule {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let digits =
+            |counts: &[usize]| -> String { counts.iter().map(|n| n.to_string()).collect() };
+        write!(f, "B{}/S{}", digits(&self.birth), digits(&self.survival))
+    }
+}
+
+fn parse_counts(s: &str) -> Result<Vec<usize>, String> {
+    s.chars()
+        .map(|ch| {
+            ch.to_digit(10)
+                .map(|d| d as usize)
+                .ok_or_else(|| format!("invalid neighbour count '{}'", ch))
+        })
+        .collect()
+}
+
+impl FromStr for Rule {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Rule, String> {
+        let bad = || format!("rule '{}' should look like B2/S12", s);
+        let (b, s) = s.split_once('/').ok_or_else(bad)?;
+        match (b.strip_prefix('B'), s.strip_prefix('S')) {
+            (Some(birth), Some(survival)) => {
+                Rule::new(&parse_counts(birth)?, &parse_counts(survival)?)
+            }
+            _ => Err(bad()),
+        }
+    }
+}
+
+/// A rectangle (in doubled coordinates) containing some tiles.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub struct Bounds {
+    pub min_x: i32,
+    pub max_x: i32,
+    pub min_y: i32,
+    pub max_y: i32,
+}
+
+impl Bounds {
+    pub fn around(p: Position) -> Bounds {
+        Bounds {
+            min_x: p.x,
+            max_x: p.x,
+            min_y: p.y,
+            max_y: p.y,
+        }
+    }
+
+    pub fn include(&mut self, p: Position) {
+        self.min_x = self.min_x.min(p.x);
+        self.max_x = self.max_x.max(p.x);
+        self.min_y = self.min_y.min(p.y);
+        self.max_y = self.max_y.max(p.y);
+    }
+
+    /// Grows the rectangle by `steps` tiles in every direction.
+    pub fn grown(&self, steps: u32) -> Bounds {
+        let steps = steps as i32;
+        Bounds {
+            min_x: self.min_x - 2 * steps,
+            max_x: self.max_x + 2 * steps,
+            min_y: self.min_y - steps,
+            max_y: self.max_y + steps,
+        }
+    }
+}
+
+/// Statistics describing one day of the exhibit.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct Stats {
+    pub black_tiles: usize,
+    /// The smallest rectangle containing the black tiles; None if
+    /// there are none.
+    pub bounds: Option<Bounds>,
+    /// The number of tiles which were flipped from white to black to
+    /// produce this floor.
+    pub white_to_black: usize,
+    /// The number of tiles which were flipped from black to white to
+    /// produce this floor.
+    pub black_to_white: usize,
+}
+
+/// The distance from the centre of a tile to its corners in rendered
+/// images, in SVG user units.
+const TILE_SIZE: f64 = 10.0;
+
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct Floor {
+    // The default state of a tile is white, so we only record the
+    // locations of the black tiles.
+    black_tiles: HashSet<Position>,
+}
+
+impl Floor {
+    pub fn new() -> Floor {
+        Floor {
+            black_tiles: HashSet::new(),
+        }
+    }
+
+    pub fn flip(&mut self, p: Position) {
+        if self.black_tiles.contains(&p) {
+            self.black_tiles.remove(&p);
+        } else {
+            self.black_tiles.insert(p);
+        }
+    }
+
+    pub fn count_black_tiles(&self) -> usize {
+        self.black_tiles.len()
+    }
+
+    pub fn black_tiles(&self) -> &HashSet<Position> {
+        &self.black_tiles
+    }
+
+    /// The smallest rectangle containing all the black tiles, if there
+    /// are any.
+    pub fn bounds(&self) -> Option<Bounds> {
+        let mut tiles = self.black_tiles.iter();
+        let mut result = Bounds::around(*tiles.next()?);
+        for p in tiles {
+            result.include(*p);
+        }
+        Some(result)
+    }
+
+    /// Draws the tiles within `bounds` as an SVG image, with the tile
+    /// at the origin marked in red.  North is at the top.
+    pub fn render_svg(&self, bounds: &Bounds) -> String {
+        let half_width = TILE_SIZE * 3.0_f64.sqrt() / 2.0;
+        let row_height = TILE_SIZE * 1.5;
+        let centre = |p: &Position| -> (f64, f64) {
+            (
+                half_width * (p.x - bounds.min_x + 1) as f64,
+                TILE_SIZE + row_height * (bounds.max_y - p.y) as f64,
+            )
+        };
+        let width = half_width * (bounds.max_x - bounds.min_x + 2) as f64;
+        let height = row_height * (bounds.max_y - bounds.min_y) as f64 + 2.0 * TILE_SIZE;
+        let corners: Vec<String> = (0..6)
+            .map(|i| {
+                let angle = (30.0 + 60.0 * i as f64).to_radians();
+                format!(
+                    "{:.2},{:.2}",
+                    TILE_SIZE * angle.cos(),
+                    TILE_SIZE * angle.sin()
+                )
+            })
+            .collect();
+        let mut svg = format!(
+            concat!(
+                "<svg xmlns=\"http://www.w3.org/2000/svg\" ",
+                "xmlns:xlink=\"http://www.w3.org/1999/xlink\" ",
+                "width=\"{:.0}\" height=\"{:.0}\" viewBox=\"0 0 {:.2} {:.2}\">\n",
+                "<defs><polygon id=\"tile\" points=\"{}\" stroke=\"grey\" ",
+                "stroke-width=\"0.5\"/></defs>\n"
+            ),
+            width.ceil(),
+            height.ceil(),
+            width,
+            height,
+            corners.join(" ")
+        );
+        for y in bounds.min_y..=bounds.max_y {
+            for x in bounds.min_x..=bounds.max_x {
+                if (x + y).rem_euclid(2) != 0 {
+                    continue; // not the centre of a tile
+                }
+                let p = Position { x, y };
+                let (cx, cy) = centre(&p);
+                let fill = if self.black_tiles.contains(&p) {
+                    "black"
+                } else {
+                    "white"
+                };
+                svg.push_str(&format!(
+                    "<use xlink:href=\"#tile\" x=\"{:.2}\" y=\"{:.2}\" fill=\"{}\"/>\n",
+                    cx, cy, fill
+                ));
+            }
+        }
+        let origin = Position::origin();
+        if origin.x >= bounds.min_x
+            && origin.x <= bounds.max_x
+            && origin.y >= bounds.min_y
+            && origin.y <= bounds.max_y
+        {
+            let (cx, cy) = centre(&origin);
+            svg.push_str(&format!(
+                "<circle cx=\"{:.2}\" cy=\"{:.2}\" r=\"{:.2}\" fill=\"red\"/>\n",
+                cx,
+                cy,
+                TILE_SIZE / 3.0
+            ));
+        }
+        svg.push_str("</svg>\n");
+        svg
+    }
+
+    /// Flips the tile reached by following `directions` from the
+    /// origin.
+    pub fn obey(&mut self, directions: &[Direction]) {
+        let final_pos = directions
+            .iter()
+            .fold(Position::origin(), |p, d| p.delta(d));
+        self.flip(final_pos)
+    }
+
+    /// Describes the floor, with no flips.
+    pub fn stats(&self) -> Stats {
+        Stats {
+            black_tiles: self.count_black_tiles(),
+            bounds: self.bounds(),
+            white_to_black: 0,
+            black_to_white: 0,
+        }
+    }
+
+    /// Works out the floor for the next day of the exhibit, and
+    /// describes it.
+    pub fn iterate(&self, rule: &Rule) -> (Floor, Stats) {
+        let mut count_of_black_neighbours: HashMap<Position, usize> = HashMap::new();
+        for current_black_tile_pos in &self.black_tiles {
+            // Black tiles with no black neighbours need to be
+            // counted too, in case the rule keeps them black.
+            count_of_black_neighbours
+                .entry(*current_black_tile_pos)
+                .or_insert(0);
+            for neighbour in current_black_tile_pos.neighbours() {
+                (*count_of_black_neighbours.entry(neighbour).or_insert(0)) += 1;
+            }
+        }
+        let mut white_to_black: usize = 0;
+        let mut stays_black: usize = 0;
+        let next = Floor {
+            black_tiles: count_of_black_neighbours
+                .iter()
+                .filter(|(pos, count)| {
+                    let black = self.black_tiles.contains(pos);
+                    let next_black = rule.next_state(black, **count);
+                    match (black, next_black) {
+                        (false, true) => white_to_black += 1,
+                        (true, true) => stays_black += 1,
+                        _ => (),
+                    }
+                    next_black
+                })
+                .map(|(pos, _)| *pos)
+                .collect(),
+        };
+        let stats = Stats {
+            white_to_black,
+            black_to_white: self.count_black_tiles() - stays_black,
+            ..next.stats()
+        };
+        (next, stats)
+    }
+}
+
+/// The number of tiles along each side of a chunk of a `ChunkedFloor`.
+const CHUNK_SIZE: i32 = 64;
+
+/// A square of tiles, one row per u64.
+type Chunk = [u64; CHUNK_SIZE as usize];
+
+/// Identifies a chunk by its position in the grid of chunks.
+type ChunkKey = (i32, i32);
+
+/// A floor stored as a map of square chunks of tiles, one bit per
+/// tile, which is much more compact than a `Floor` when there are a
+/// lot of black tiles.
+///
+/// Chunks are laid out in axial coordinates, since the neighbours of
+/// a tile (q, r) are then (q±1, r), (q, r±1), (q+1, r-1) and (q-1,
+/// r+1).  Row n of chunk (cq, cr) holds the tiles whose r is cr *
+/// CHUNK_SIZE + n, and bit i of the row is the tile whose q is cq *
+/// CHUNK_SIZE + i.  So the neighbours of a whole row of tiles can be
+/// found by shifting the rows next to it.
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct ChunkedFloor {
+    chunks: HashMap<ChunkKey, Chunk>,
+}
+
+/// A chunk and its eight neighbours (some of which may not exist).
+struct ChunkWindow<'a> {
+    chunks: [[Option<&'a Chunk>; 3]; 3],
+}
+
+impl ChunkWindow<'_> {
+    /// Row `r` of the chunk `dq` chunks East of the middle one.  `r`
+    /// may be just outside the middle chunk (-1 or CHUNK_SIZE).
+    fn row(&self, dq: i32, r: i32) -> u64 {
+        let dr = r.div_euclid(CHUNK_SIZE);
+        match self.chunks[(dr + 1) as usize][(dq + 1) as usize] {
+            Some(chunk) => chunk[r.rem_euclid(CHUNK_SIZE) as usize],
+            None => 0,
+        }
+    }
+
+    /// Row `r` shifted so that each bit holds the tile to its West.
+    fn row_from_west(&self, r: i32) -> u64 {
+        (self.row(0, r) << 1) | (self.row(-1, r) >> (CHUNK_SIZE - 1))
+    }
+
+    /// Row `r` shifted so that each bit holds the tile to its East.
+    fn row_from_east(&self, r: i32) -> u64 {
+        (self.row(0, r) >> 1) | (self.row(1, r) << (CHUNK_SIZE - 1))
+    }
+}
+
+/// Adds the bits of `x` to the 3-bit counters whose bits are in
+/// `sum` (least significant first).  The counters cannot overflow
+/// since a tile has only 6 neighbours.
+fn add_bits(sum: &mut [u64; 3], x: u64) {
+    let carry0 = sum[0] & x;
+    sum[0] ^= x;
+    let carry1 = sum[1] & carry0;
+    sum[1] ^= carry0;
+    sum[2] |= carry1;
+}
+
+impl ChunkedFloor {
+    /// The chunk, row and bit which hold the tile at `p`.
+    fn locate(p: &Position) -> (ChunkKey, usize, i32) {
+        let a = p.to_axial();
+        (
+            (a.q.div_euclid(CHUNK_SIZE), a.r.div_euclid(CHUNK_SIZE)),
+            a.r.rem_euclid(CHUNK_SIZE) as usize,
+            a.q.rem_euclid(CHUNK_SIZE),
+        )
+    }
+
+    pub fn from_floor(floor: &Floor) -> ChunkedFloor {
+        let mut chunks: HashMap<ChunkKey, Chunk> = HashMap::new();
+        for p in floor.black_tiles() {
+            let (key, row, bit) = ChunkedFloor::locate(p);
+            chunks.entry(key).or_insert([0; CHUNK_SIZE as usize])[row] |= 1 << bit;
+        }
+        ChunkedFloor { chunks }
+    }
+
+    /// Calls `f` with the positions of each row of black tiles: the
+    /// row's r and the smallest q in its chunk, and the row itself.
+    fn for_each_row<F: FnMut(i32, i32, u64)>(&self, mut f: F) {
+        for ((cq, cr), chunk) in &self.chunks {
+            for (n, row) in chunk.iter().enumerate() {
+                if *row != 0 {
+                    f(cr * CHUNK_SIZE + n as i32, cq * CHUNK_SIZE, *row);
+                }
+            }
+        }
+    }
+
+    pub fn to_floor(&self) -> Floor {
+        let mut black_tiles = HashSet::with_capacity(self.count_black_tiles());
+        self.for_each_row(|r, q0, row| {
+            for bit in 0..CHUNK_SIZE {
+                if row & (1 << bit) != 0 {
+                    black_tiles.insert(Position::from_axial(Axial { q: q0 + bit, r }));
+                }
+            }
+        });
+        Floor { black_tiles }
+    }
+
+    pub fn count_black_tiles(&self) -> usize {
+        self.chunks
+            .values()
+            .flat_map(|chunk| chunk.iter())
+            .map(|row| row.count_ones() as usize)
+            .sum()
+    }
+
+    pub fn bounds(&self) -> Option<Bounds> {
+        let mut result: Option<Bounds> = None;
+        self.for_each_row(|r, q0, row| {
+            let west = q0 + row.trailing_zeros() as i32;
+            let east = q0 + CHUNK_SIZE - 1 - row.leading_zeros() as i32;
+            for q in &[west, east] {
+                let p = Position::from_axial(Axial { q: *q, r });
+                match result.as_mut() {
+                    Some(b) => b.include(p),
+                    None => result = Some(Bounds::around(p)),
+                }
+            }
+        });
+        result
+    }
+
+    /// Describes the floor, with no flips.
+    pub fn stats(&self) -> Stats {
+        Stats {
+            black_tiles: self.count_black_tiles(),
+            bounds: self.bounds(),
+            white_to_black: 0,
+            black_to_white: 0,
+        }
+    }
+
+    fn window(&self, (cq, cr): ChunkKey) -> ChunkWindow<'_> {
+        let mut chunks = [[None; 3]; 3];
+        for (dr, row) in chunks.iter_mut().enumerate() {
+            for (dq, chunk) in row.iter_mut().enumerate() {
+                *chunk = self.chunks.get(&(cq + dq as i32 - 1, cr + dr as i32 - 1));
+            }
+        }
+        ChunkWindow { chunks }
+    }
+
+    /// Works out the floor for the next day of the exhibit, and
+    /// describes it; the same as `Floor::iterate`.
+    pub fn iterate(&self, rule: &Rule) -> (ChunkedFloor, Stats) {
+        // Tiles can only turn black next to black tiles, so only
+        // the chunks next to the current ones need to be visited.
+        let mut keys: HashSet<ChunkKey> = HashSet::with_capacity(self.chunks.len() * 9);
+        for (cq, cr) in self.chunks.keys() {
+            for dr in -1..=1 {
+                for dq in -1..=1 {
+                    keys.insert((cq + dq, cr + dr));
+                }
+            }
+        }
+        // For each number of neighbours, whether a white tile turns
+        // black and whether a black tile stays black.
+        let outcomes: Vec<(usize, bool, bool)> = (0..=6)
+            .map(|n| (n, rule.next_state(false, n), rule.next_state(true, n)))
+            .filter(|(_, birth, survival)| *birth || *survival)
+            .collect();
+        let mut chunks = HashMap::with_capacity(keys.len());
+        let mut white_to_black: usize = 0;
+        let mut black_to_white: usize = 0;
+        for key in keys {
+            let window = self.window(key);
+            let mut chunk: Chunk = [0; CHUNK_SIZE as usize];
+            for (n, next_row) in chunk.iter_mut().enumerate() {
+                let r = n as i32;
+                let current = window.row(0, r);
+                let mut sum = [0_u64; 3];
+                for neighbours in &[
+                    window.row_from_west(r),
+                    window.row_from_east(r),
+                    window.row(0, r + 1),
+                    window.row_from_west(r + 1),
+                    window.row(0, r - 1),
+                    window.row_from_east(r - 1),
+                ] {
+                    add_bits(&mut sum, *neighbours);
+                }
+                for (count, birth, survival) in &outcomes {
+                    let mut equal = !0_u64;
+                    for (bit, sum_bit) in sum.iter().enumerate() {
+                        equal &= if count & (1 << bit) != 0 {
+                            *sum_bit
+                        } else {
+                            !sum_bit
+                        };
+                    }
+                    if *birth {
+                        *next_row |= equal & !current;
+                    }
+                    if *survival {
+                        *next_row |= equal & current;
+                    }
+                }
+                white_to_black += (*next_row & !current).count_ones() as usize;
+                black_to_white += (current & !*next_row).count_ones() as usize;
+            }
+            if chunk.iter().any(|row| *row != 0) {
+                chunks.insert(key, chunk);
+            }
+        }
+        let next = ChunkedFloor { chunks };
+        let stats = Stats {
+            white_to_black,
+            black_to_white,
+            ..next.stats()
+        };
+        (next, stats)
+    }
+}
+
+/// The representation used to run the exhibit.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Engine {
+    /// Only the black tiles are stored, in a hash set.
+    Sparse,
+    /// Tiles are stored one bit each in chunks (see `ChunkedFloor`).
+    Chunked,
+}
+
+impl fmt::Display for Engine {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match self {
+            Engine::Sparse => "sparse",
+            Engine::Chunked => "chunked",
+        })
+    }
+}
+
+impl FromStr for Engine {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Engine, String> {
+        match s {
+            "sparse" => Ok(Engine::Sparse),
+            "chunked" => Ok(Engine::Chunked),
+            _ => Err(format!("unknown engine '{}'", s)),
+        }
+    }
+}
+
+/// Runs the exhibit for `days` days with rule `rule`, returning the
+/// final floor and the statistics of each day.
+pub fn simulate(initial: &Floor, days: usize, rule: &Rule, engine: Engine) -> (Floor, Vec<Stats>) {
+    let mut series = Vec::with_capacity(days);
+    match engine {
+        Engine::Sparse => {
+            let mut floor = initial.clone();
+            for _ in 0..days {
+                let (next, stats) = floor.iterate(rule);
+                floor = next;
+                series.push(stats);
+            }
+            (floor, series)
+        }
+        Engine::Chunked => {
+            let mut floor = ChunkedFloor::from_floor(initial);
+            for _ in 0..days {
+                let (next, stats) = floor.iterate(rule);
+                floor = next;
+                series.push(stats);
+            }
+            (floor.to_floor(), series)
+        }
+    }
+}
+
+/// Lays out the floor by following each line of instructions.
+pub fn lay_floor(lines: &[String]) -> Result<Floor, ParseError> {
+    let mut floor = Floor::new();
+    for (i, line) in lines.iter().enumerate() {
+        floor.obey(&split_directions(i + 1, line)?);
+    }
+    Ok(floor)
+}
+
+pub fn self_test() -> Result<(), String> {
+    let example: Vec<String> = [
+        "sesenwnenenewseeswwswswwnenewsewsw",
+        "neeenesenwnwwswnenewnwwsewnenwseswesw",
+        "seswneswswsenwwnwse",
+        "nwnwneseeswswnenewneswwnewseswneseene",
+        "swweswneswnenwsewnwneneseenw",
+        "eesenwseswswnenwswnwnwsewwnwsene",
+        "sewnenenenesenwsewnenwwwse",
+        "wenwwweseeeweswwwnwwe",
+        "wsweesenenewnwwnwsenewsenwwsesesenwne",
+        "neeswseenwwswnwswswnw",
+        "nenwswwsewswnenenewsenwsenwnesesenew",
+        "enewnwewneswsewnwswenweswnenwsenwsw",
+        "sweneswneswneneenwnewenewwneswswnese",
+        "swwesenesewenwneswnwwneseswwne",
+        "enesenwswwswneneswsenwnewswseenwsese",
+        "wnwnesenesenenwwnenwsewesewsesesew",
+        "nenewswnwewswnenesenwnesewesw",
+        "eneswnwswnwsenenwnwnwwseeswneewsenese",
+        "neswnwewnwnwseenwseesewsenwsweewe",
+        "wseweeenwnesenwwwswnew",
+    ]
+    .iter()
+    .map(|s| s.to_string())
+    .collect();
+    let mut floor = lay_floor(&example).map_err(|e| format!("FAIL: {}", e))?;
+    if floor.count_black_tiles() != 10 {
+        return Err(format!(
+            "FAIL: expected 10 black tiles, got {}",
+            floor.count_black_tiles()
+        ));
+    }
+    let rule = Rule::default();
+    for day in 1..=100 {
+        let (next, stats) = floor.iterate(&rule);
+        if stats.black_tiles != next.count_black_tiles()
+            || stats.black_tiles + stats.black_to_white
+                != floor.count_black_tiles() + stats.white_to_black
+        {
+            return Err(format!(
+                "FAIL: inconsistent stats for day {}: {:?}",
+                day, stats
+            ));
+        }
+        floor = next;
+        let expected = match day {
+            1 => 15,
+            2 => 12,
+            10 => 37,
+            50 => 566,
+            100 => 2208,
+            _ => continue,
+        };
+        if floor.count_black_tiles() != expected {
+            return Err(format!(
+                "FAIL: expected {} black tiles on day {}, got {}",
+                expected,
+                day,
+                floor.count_black_tiles()
+            ));
+        }
+    }
+
+    if rule.to_string() != "B2/S12" || "B2/S12".parse::<Rule>()? != rule {
+        return Err(format!("FAIL: the default rule is shown as {}", rule));
+    }
+    for bad in &["B0/S12", "B2/S17", "B2S12", "S12/B2", "B2/Sx"] {
+        if bad.parse::<Rule>().is_ok() {
+            return Err(format!("FAIL: '{}' should not be a valid rule", bad));
+        }
+    }
+    let mut floor = Floor::new();
+    floor.flip(Position { x: 1, y: 1 });
+    floor.flip(Position { x: -2, y: 0 });
+    let bounds = floor
+        .bounds()
+        .ok_or("FAIL: no bounds for a floor with black tiles")?;
+    let expected = Bounds {
+        min_x: -2,
+        max_x: 1,
+        min_y: 0,
+        max_y: 1,
+    };
+    if bounds != expected {
+        return Err(format!(
+            "FAIL: expected bounds {:?}, got {:?}",
+            expected, bounds
+        ));
+    }
+    if Floor::new().bounds().is_some() {
+        return Err("FAIL: an all-white floor should have no bounds".to_string());
+    }
+    // Neither of these tiles has a black neighbour, so on the next
+    // day both turn white, and the two tiles adjacent to both turn
+    // black.
+    let (_, stats) = floor.iterate(&Rule::default());
+    let expected = Stats {
+        black_tiles: 2,
+        bounds: Some(Bounds {
+            min_x: -1,
+            max_x: 0,
+            min_y: 0,
+            max_y: 1,
+        }),
+        white_to_black: 2,
+        black_to_white: 2,
+    };
+    if stats != expected {
+        return Err(format!("FAIL: expected {:?}, got {:?}", expected, stats));
+    }
+    // Four tiles: (-2,0), (0,0), (-1,1) and (1,1).
+    let svg = floor.render_svg(&bounds);
+    let count = |needle: &str| svg.matches(needle).count();
+    if !svg.starts_with("<svg ")
+        || count("<use ") != 4
+        || count("fill=\"black\"") != 2
+        || count("<circle ") != 1
+    {
+        return Err(format!("FAIL: unexpected SVG:\n{}", svg));
+    }
+    self_test_parse()?;
+    self_test_engines(&example)?;
+    // With S0, a lone black tile stays black.
+    let mut lonely = Floor::new();
+    lonely.flip(Position::origin());
+    for (rule, expected) in &[("B2/S12", 0), ("B2/S0", 1)] {
+        let n = lonely.iterate(&rule.parse()?).0.count_black_tiles();
+        if n != *expected {
+            return Err(format!(
+                "FAIL: with rule {} a lone tile leaves {} black tiles",
+                rule, n
+            ));
+        }
+    }
+    Ok(())
+}
+
+fn self_test_parse() -> Result<(), String> {
+    let got = split_directions(1, "nwwswee").map_err(|e| format!("FAIL: {}", e))?;
+    let expected = [
+        Direction::NW,
+        Direction::W,
+        Direction::SW,
+        Direction::E,
+        Direction::E,
+    ];
+    if got != expected {
+        return Err(format!("FAIL: expected {:?}, got {:?}", expected, got));
+    }
+    if join_directions(&got) != "nwwswee" {
+        return Err(format!(
+            "FAIL: {:?} was joined as {}",
+            got,
+            join_directions(&got)
+        ));
+    }
+    for (input, offset) in &[
+        // Truncated
+        ("esen", 3),
+        ("s", 0),
+        // Invalid
+        ("enx", 1),
+        ("ex", 1),
+        ("wnn", 1),
+        ("eN", 1),
+        ("e\u{e9}w", 1),
+        ("e\u{e9}x", 1),
+        ("ne\u{e9}x", 2),
+        ("swe ", 3),
+    ] {
+        match split_directions(7, input) {
+            Ok(dirs) => {
+                return Err(format!(
+                    "FAIL: '{}' should not be valid, but gave {:?}",
+                    input, dirs
+                ));
+            }
+            Err(e) if e.line != 7 || e.offset != *offset => {
+                return Err(format!(
+                    "FAIL: '{}' gave the wrong location for the error: {}",
+                    input, e
+                ));
+            }
+            Err(_) => (),
+        }
+    }
+    let lines = vec!["esew".to_string(), "nwe".to_string(), "sx".to_string()];
+    match lay_floor(&lines) {
+        Err(e)
+            if e.to_string()
+                == "line 3, offset 0: 's' should be followed by 'e' or 'w', not 'x'" =>
+        {
+            Ok(())
+        }
+        other => Err(format!("FAIL: unexpected result {:?}", other)),
+    }
+}
+
+fn self_test_engines(example: &[String]) -> Result<(), String> {
+    let initial = lay_floor(example).map_err(|e| format!("FAIL: {}", e))?;
+    let chunked = ChunkedFloor::from_floor(&initial);
+    if chunked.to_floor() != initial || chunked.stats() != initial.stats() {
+        return Err("FAIL: the floor does not survive conversion to chunks".to_string());
+    }
+    // A floor which straddles the corners of several chunks.
+    let mut corners = Floor::new();
+    for p in &[
+        Position::from_axial(Axial { q: -1, r: -1 }),
+        Position::from_axial(Axial { q: 63, r: 0 }),
+        Position::from_axial(Axial { q: 64, r: -1 }),
+        Position::from_axial(Axial { q: 0, r: 63 }),
+        Position::from_axial(Axial { q: -1, r: 64 }),
+    ] {
+        corners.flip(*p);
+        corners.flip(p.delta(&Direction::E));
+    }
+    for rule in &["B2/S12", "B13/S0", "B1/S", "B23456/S123456"] {
+        let rule: Rule = rule.parse()?;
+        for floor in &[&initial, &corners] {
+            let (sparse, sparse_series) = simulate(floor, 30, &rule, Engine::Sparse);
+            let (chunked, chunked_series) = simulate(floor, 30, &rule, Engine::Chunked);
+            if sparse != chunked || sparse_series != chunked_series {
+                return Err(format!(
+                    "FAIL: the engines disagree with rule {}: {:?} versus {:?}",
+                    rule,
+                    sparse_series.last(),
+                    chunked_series.last()
+                ));
+            }
+        }
+    }
+    for engine in &[Engine::Sparse, Engine::Chunked] {
+        if engine.to_string().parse::<Engine>()? != *engine {
+            return Err(format!("FAIL: engine {} does not survive parsing", engine));
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,154 @@
+//! Step-by-step accounts of how a solution was reached, for the
+//! `--explain` option.
+//!
+//! A solver describes each step it takes with a sentence, and can
+//! attach named facts (such as the numbers involved) to it.  The
+//! result can be printed as a narrative, numbered within each part of
+//! the puzzle, or serialised (for example as JSON) for other programs.
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::fmt;
+use std::str::FromStr;
+
+/// A fact attached to a step.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(untagged)]
+pub enum Value {
+    Number(i64),
+    Text(String),
+    List(Vec<Value>),
+}
+
+impl From<i64> for Value {
+    fn from(n: i64) -> Value {
+        Value::Number(n)
+    }
+}
+
+impl From<usize> for Value {
+    fn from(n: usize) -> Value {
+        Value::Number(n as i64)
+    }
+}
+
+impl From<&str> for Value {
+    fn from(s: &str) -> Value {
+        Value::Text(s.to_string())
+    }
+}
+
+impl From<String> for Value {
+    fn from(s: String) -> Value {
+        Value::Text(s)
+    }
+}
+
+impl<T: Into<Value>> From<Vec<T>> for Value {
+    fn from(items: Vec<T>) -> Value {
+        Value::List(items.into_iter().map(Into::into).collect())
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct Step {
+    /// The part of the puzzle (1 or 2) the step belongs to.
+    pub part: u32,
+    /// What sort of step this is, such as "congruence" or "merge";
+    /// each solver has its own.
+    pub kind: &'static str,
+    /// A description of the step.
+    pub text: String,
+    pub facts: BTreeMap<&'static str, Value>,
+}
+
+impl Step {
+    pub fn fact<V: Into<Value>>(&mut self, name: &'static str, value: V) -> &mut Step {
+        self.facts.insert(name, value.into());
+        self
+    }
+}
+
+#[derive(Debug, Default, Clone, PartialEq, Eq, Serialize)]
+pub struct Explanation {
+    steps: Vec<Step>,
+}
+
+impl Explanation {
+    pub fn new() -> Explanation {
+        Explanation::default()
+    }
+
+    /// Adds a step, returning it so that facts can be attached.
+    pub fn step(&mut self, part: u32, kind: &'static str, text: String) -> &mut Step {
+        self.steps.push(Step {
+            part,
+            kind,
+            text,
+            facts: BTreeMap::new(),
+        });
+        self.steps.last_mut().unwrap()
+    }
+
+    pub fn steps(&self) -> &[Step] {
+        &self.steps
+    }
+}
+
+/// The narrative form: the steps of each part, numbered from 1.
+impl fmt::Display for Explanation {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let mut part = None;
+        let mut number = 0;
+        for step in self.steps.iter() {
+            if part != Some(step.part) {
+                part = Some(step.part);
+                number = 0;
+                writeln!(f, "Part {}:", step.part)?;
+            }
+            number += 1;
+            writeln!(f, "{:>4}. {}", number, step.text)?;
+        }
+        Ok(())
+    }
+}
+
+/// How `--explain` prints an explanation.  A JSON explanation is
+/// printed instead of the usual output, so that it can be read by
+/// another program; a narrative comes before the usual output.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Format {
+    Text,
+    Json,
+}
+
+impl FromStr for Format {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Format, String> {
+        match s {
+            "text" => Ok(Format::Text),
+            "json" => Ok(Format::Json),
+            _ => Err(format!(
+                "unknown explanation format '{}'; choose text or json",
+                s
+            )),
+        }
+    }
+}
+
+pub fn self_test() -> Result<(), String> {
+    let mut e = Explanation::new();
+    e.step(1, "note", "first".to_string()).fact("n", 3usize);
+    e.step(1, "note", "second".to_string());
+    e.step(2, "note", "third".to_string())
+        .fact("list", vec![1i64, -2])
+        .fact("name", "x");
+    let expected = "Part 1:\n   1. first\n   2. second\nPart 2:\n   1. third\n";
+    if e.to_string() != expected {
+        return Err(format!("FAIL: explanation was rendered as:\n{}", e));
+    }
+    if e.steps()[2].facts["list"] != Value::List(vec![Value::Number(1), Value::Number(-2)]) {
+        return Err(format!("FAIL: wrong facts {:?}", e.steps()[2].facts));
+    }
+    Ok(())
+}
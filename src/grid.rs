@@ -0,0 +1,99 @@
+//! A rectangular map of cells which are either blocked ('#') or open
+//! (anything else), like the map of trees on day 3.  Beyond its edges
+//! the map repeats, so it covers the whole plane.
+use std::fmt;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Grid {
+    width: usize,
+    height: usize,
+    /// Row by row, starting at the top left.
+    blocked: Vec<bool>,
+}
+
+impl Grid {
+    /// Reads a map, one row per line.  The width of the map is the
+    /// length of the longest line, and short lines are open at the
+    /// end.
+    pub fn parse(text: &str) -> Grid {
+        let lines: Vec<&str> = text.lines().collect();
+        let width = lines
+            .iter()
+            .map(|line| line.chars().count())
+            .max()
+            .unwrap_or(0);
+        let mut blocked = vec![false; width * lines.len()];
+        for (y, line) in lines.iter().enumerate() {
+            for (x, c) in line.chars().enumerate() {
+                blocked[y * width + x] = c == '#';
+            }
+        }
+        Grid {
+            width,
+            height: lines.len(),
+            blocked,
+        }
+    }
+
+    pub fn width(&self) -> usize {
+        self.width
+    }
+
+    pub fn height(&self) -> usize {
+        self.height
+    }
+
+    /// Whether the cell in column `x` of row `y` (counting from the top
+    /// left) is blocked.  Since the map repeats, any position is
+    /// allowed, but an empty map is open everywhere.
+    pub fn is_blocked(&self, x: i64, y: i64) -> bool {
+        if self.blocked.is_empty() {
+            return false;
+        }
+        let x = x.rem_euclid(self.width as i64) as usize;
+        let y = y.rem_euclid(self.height as i64) as usize;
+        self.blocked[y * self.width + x]
+    }
+}
+
+impl fmt::Display for Grid {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for row in self.blocked.chunks(self.width.max(1)) {
+            for blocked in row {
+                f.write_str(if *blocked { "#" } else { "." })?;
+            }
+            writeln!(f)?;
+        }
+        Ok(())
+    }
+}
+
+pub fn self_test() -> Result<(), String> {
+    let grid = Grid::parse("..#\n#\n.#.\n");
+    if grid.width() != 3 || grid.height() != 3 || grid.to_string() != "..#\n#..\n.#.\n" {
+        return Err(format!("FAIL: wrong map:\n{}", grid));
+    }
+    for (x, y, expected) in &[
+        (2, 0, true),
+        (1, 0, false),
+        (0, 1, true),
+        (1, 1, false),
+        (5, 3, true),
+        (-1, 0, true),
+        (-3, -2, true),
+        (1, -1, true),
+    ] {
+        if grid.is_blocked(*x, *y) != *expected {
+            return Err(format!(
+                "FAIL: ({}, {}) should {}be blocked",
+                x,
+                y,
+                if *expected { "" } else { "not " }
+            ));
+        }
+    }
+    if Grid::parse("").is_blocked(4, 4) {
+        return Err("FAIL: an empty map should be open".to_string());
+    }
+    Ok(())
+}
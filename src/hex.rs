@@ -0,0 +1,297 @@
+//! Coordinates on a grid of hexagons.
+//!
+//! The hexagons have corners pointing North and South, so each one
+//! has neighbours to the East and West but none directly to the North
+//! or South.  A `Position` uses "doubled" coordinates: moving E or W
+//! changes x by 2, and moving in any of the diagonal directions
+//! changes both x and y by 1.  So x + y is always even.
+//!
+//! Axial and cube coordinates are more convenient for some
+//! calculations, and positions can be converted to and from those.
+use std::fmt;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Direction {
+    // note that N and S are deliberately missing.
+    NE,
+    E,
+    SE,
+    SW,
+    W,
+    NW,
+}
+
+impl Direction {
+    // Directions here are vectors on a modified cartesian coordinate
+    // plane.  The locations are hexagons.  Visualise the hexagons
+    // with corners pointing North/South.  The X-axis runs directly
+    // East/West (through the centres of two of the sides).  If we
+    // move NE then NW, this should leave the X co-ordinate unchanged.
+    // Moving only NE, though, does change the X co-ordinate.  To
+    // obtain these properties while keeping things simple, we adopt
+    // the convention that moving E or W changes the X coordinate by
+    // 2, not 1.
+    //
+    // You can visualise this as if alternate rows of hexagons have X
+    // coordinates in between the X coordinates of the rows above and
+    // below.
+    pub fn delta(&self) -> (i32, i32) {
+        // (dx, dy)
+        match self {
+            Direction::NE => (1, 1),
+            Direction::E => (2, 0),
+            Direction::SE => (1, -1),
+            Direction::SW => (-1, -1),
+            Direction::W => (-2, 0),
+            Direction::NW => (-1, 1),
+        }
+    }
+
+    /// All the directions, clockwise starting from NE.
+    pub fn all() -> &'static [Direction; 6] {
+        &[
+            Direction::NE,
+            Direction::E,
+            Direction::SE,
+            Direction::SW,
+            Direction::W,
+            Direction::NW,
+        ]
+    }
+
+    pub fn opposite(&self) -> Direction {
+        match self {
+            Direction::NE => Direction::SW,
+            Direction::E => Direction::W,
+            Direction::SE => Direction::NW,
+            Direction::SW => Direction::NE,
+            Direction::W => Direction::E,
+            Direction::NW => Direction::SE,
+        }
+    }
+}
+
+impl fmt::Display for Direction {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match self {
+            Direction::NE => "ne",
+            Direction::E => "e",
+            Direction::SE => "se",
+            Direction::SW => "sw",
+            Direction::W => "w",
+            Direction::NW => "nw",
+        })
+    }
+}
+
+/// A position in doubled coordinates (see the module documentation).
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Position {
+    pub x: i32,
+    pub y: i32,
+}
+
+/// Axial coordinates: q increases to the East, r to the North.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Axial {
+    pub q: i32,
+    pub r: i32,
+}
+
+/// Cube coordinates, which always satisfy q + r + s = 0.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Cube {
+    pub q: i32,
+    pub r: i32,
+    pub s: i32,
+}
+
+impl Position {
+    pub fn origin() -> Position {
+        Position { x: 0, y: 0 }
+    }
+
+    pub fn delta(&self, d: &Direction) -> Position {
+        let delta = d.delta();
+        Position {
+            x: self.x + delta.0,
+            y: self.y + delta.1,
+        }
+    }
+
+    /// The position reached by moving `steps` times in direction `d`.
+    pub fn moved(&self, d: &Direction, steps: i32) -> Position {
+        let delta = d.delta();
+        Position {
+            x: self.x + delta.0 * steps,
+            y: self.y + delta.1 * steps,
+        }
+    }
+
+    /// The six adjacent positions, in the order of `Direction::all`.
+    pub fn neighbours(self) -> impl Iterator<Item = Position> {
+        Direction::all().iter().map(move |d| self.delta(d))
+    }
+
+    /// The number of steps needed to get from here to `other`.
+    pub fn distance(&self, other: &Position) -> u32 {
+        let dx = (self.x - other.x).unsigned_abs();
+        let dy = (self.y - other.y).unsigned_abs();
+        // Each diagonal step covers one unit of dy and one of dx;
+        // any remaining dx is covered two at a time.
+        dy + dx.saturating_sub(dy) / 2
+    }
+
+    /// A shortest sequence of moves from here to `other`.  Each move
+    /// is the first direction (in the order of `Direction::all`) which
+    /// brings us one step closer.
+    pub fn path_to(&self, other: &Position) -> Vec<Direction> {
+        let mut here = *self;
+        let mut path = Vec::with_capacity(self.distance(other) as usize);
+        while here != *other {
+            let remaining = here.distance(other);
+            let d = Direction::all()
+                .iter()
+                .find(|d| here.delta(d).distance(other) < remaining)
+                .expect("some neighbour is always closer");
+            path.push(*d);
+            here = here.delta(d);
+        }
+        path
+    }
+
+    /// The positions at exactly `radius` steps from here, clockwise
+    /// starting from the one due West.
+    pub fn ring(self, radius: u32) -> impl Iterator<Item = Position> {
+        if radius == 0 {
+            return vec![self].into_iter();
+        }
+        let mut result = Vec::with_capacity(6 * radius as usize);
+        let mut p = self.moved(&Direction::W, radius as i32);
+        for d in Direction::all() {
+            for _ in 0..radius {
+                result.push(p);
+                p = p.delta(d);
+            }
+        }
+        result.into_iter()
+    }
+
+    /// The positions within `radius` steps of here (including this
+    /// one), ring by ring outwards.
+    pub fn range(self, radius: u32) -> impl Iterator<Item = Position> {
+        (0..=radius).flat_map(move |r| self.ring(r))
+    }
+
+    pub fn to_axial(self) -> Axial {
+        Axial {
+            q: (self.x - self.y) / 2,
+            r: self.y,
+        }
+    }
+
+    pub fn from_axial(a: Axial) -> Position {
+        Position {
+            x: 2 * a.q + a.r,
+            y: a.r,
+        }
+    }
+
+    pub fn to_cube(self) -> Cube {
+        let a = self.to_axial();
+        Cube {
+            q: a.q,
+            r: a.r,
+            s: -a.q - a.r,
+        }
+    }
+
+    pub fn from_cube(c: Cube) -> Position {
+        Position::from_axial(Axial { q: c.q, r: c.r })
+    }
+}
+
+impl fmt::Display for Position {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "({},{})", self.x, self.y)
+    }
+}
+
+pub fn self_test() -> Result<(), String> {
+    let origin = Position::origin();
+    let some_positions: Vec<Position> = origin.range(3).collect();
+    for p in &some_positions {
+        if (p.x + p.y) % 2 != 0 {
+            return Err(format!("FAIL: {} is not a valid position", p));
+        }
+        for d in Direction::all() {
+            if p.delta(d).delta(&d.opposite()) != *p {
+                return Err(format!(
+                    "FAIL: moving {} then {} from {} does not return there",
+                    d,
+                    d.opposite(),
+                    p
+                ));
+            }
+        }
+        if p.neighbours().any(|n| n.distance(p) != 1) {
+            return Err(format!("FAIL: a neighbour of {} is not adjacent", p));
+        }
+        if Position::from_axial(p.to_axial()) != *p || Position::from_cube(p.to_cube()) != *p {
+            return Err(format!("FAIL: {} does not survive conversion", p));
+        }
+        let c = p.to_cube();
+        if c.q + c.r + c.s != 0 {
+            return Err(format!("FAIL: bad cube coordinates {:?} for {}", c, p));
+        }
+        let cube_distance = (c.q.abs() + c.r.abs() + c.s.abs()) / 2;
+        if cube_distance as u32 != p.distance(&origin) {
+            return Err(format!("FAIL: wrong distance from origin to {}", p));
+        }
+        let from = Position { x: 3, y: -1 };
+        let path = from.path_to(p);
+        let end = path.iter().fold(from, |q, d| q.delta(d));
+        if end != *p || path.len() as u32 != from.distance(p) {
+            return Err(format!(
+                "FAIL: path {:?} from {} leads to {}, not {}",
+                path, from, end, p
+            ));
+        }
+    }
+    if origin.delta(&Direction::NE).delta(&Direction::NW) != (Position { x: 0, y: 2 }) {
+        return Err("FAIL: NE then NW should lead due North".to_string());
+    }
+    for radius in 0..5 {
+        let ring: Vec<Position> = Position { x: 3, y: -1 }.ring(radius).collect();
+        let expected_len = if radius == 0 { 1 } else { 6 * radius as usize };
+        if ring.len() != expected_len {
+            return Err(format!(
+                "FAIL: ring of radius {} has {} members",
+                radius,
+                ring.len()
+            ));
+        }
+        for (i, p) in ring.iter().enumerate() {
+            if p.distance(&Position { x: 3, y: -1 }) != radius {
+                return Err(format!("FAIL: {} is not on ring {}", p, radius));
+            }
+            // Consecutive members of the ring are adjacent.
+            let next = ring[(i + 1) % ring.len()];
+            if radius > 0 && p.distance(&next) != 1 {
+                return Err(format!("FAIL: ring {} has a gap at {}", radius, p));
+            }
+        }
+        let mut range: Vec<Position> = origin.range(radius).collect();
+        let total = range.len();
+        range.sort();
+        range.dedup();
+        let r = radius as usize;
+        if range.len() != total || total != 3 * r * (r + 1) + 1 {
+            return Err(format!(
+                "FAIL: range of radius {} has {} members",
+                radius, total
+            ));
+        }
+    }
+    Ok(())
+}
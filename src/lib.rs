@@ -0,0 +1,33 @@
+//! Code shared between the solutions, or which is useful to other programs.
+extern crate fxhash;
+extern crate itertools;
+extern crate lazy_static;
+extern crate ndarray;
+extern crate png;
+extern crate regex;
+extern crate serde;
+extern crate smallvec;
+extern crate thiserror;
+
+pub mod algorithm;
+pub mod constraint;
+pub mod day05;
+pub mod day06;
+pub mod day07;
+pub mod day14;
+pub mod day16;
+pub mod day17;
+pub mod day18;
+pub mod day19;
+pub mod day20;
+pub mod day21;
+pub mod day22;
+pub mod day24;
+pub mod explain;
+pub mod grid;
+pub mod hex;
+pub mod modular;
+pub mod output;
+pub mod progress;
+pub mod rng;
+pub mod visualization;
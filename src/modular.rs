@@ -0,0 +1,450 @@
+//! Arithmetic modulo an integer, matrix powers, and discrete
+//! logarithms.
+//!
+//! A discrete logarithm of h to the base g (modulo m) is an x such
+//! that g^x = h (mod m).  There are three ways to find one here:
+//! trying each x in turn, the baby-step giant-step algorithm (which
+//! takes time proportional to the square root of the order of g),
+//! and the Pohlig-Hellman algorithm, which is much faster still when
+//! the order of the group factors into small primes.
+use std::collections::HashMap;
+use std::fmt;
+use std::str::FromStr;
+
+/// Computes a * b mod m without overflow.
+pub fn mul_mod(a: u64, b: u64, m: u64) -> u64 {
+    ((a as u128 * b as u128) % m as u128) as u64
+}
+
+/// Computes base^exp mod m.
+pub fn pow_mod(base: u64, exp: u64, m: u64) -> u64 {
+    let mut result = 1 % m;
+    let mut base = base % m;
+    let mut exp = exp;
+    while exp > 0 {
+        if exp & 1 != 0 {
+            result = mul_mod(result, base, m);
+        }
+        base = mul_mod(base, base, m);
+        exp >>= 1;
+    }
+    result
+}
+
+/// Arithmetic on natural numbers, either exact (giving None on
+/// overflow) or modulo some number.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Arithmetic {
+    pub modulus: Option<u64>,
+}
+
+impl Arithmetic {
+    pub fn exact() -> Arithmetic {
+        Arithmetic { modulus: None }
+    }
+
+    pub fn modulo(m: u64) -> Arithmetic {
+        Arithmetic { modulus: Some(m) }
+    }
+
+    pub fn add(&self, a: u64, b: u64) -> Option<u64> {
+        match self.modulus {
+            Some(m) => Some(((a as u128 + b as u128) % m as u128) as u64),
+            None => a.checked_add(b),
+        }
+    }
+
+    pub fn mul(&self, a: u64, b: u64) -> Option<u64> {
+        match self.modulus {
+            Some(m) => Some(mul_mod(a, b, m)),
+            None => a.checked_mul(b),
+        }
+    }
+}
+
+/// A square matrix of N rows and N columns.
+pub type Matrix<const N: usize> = [[u64; N]; N];
+
+pub fn mat_identity<const N: usize>() -> Matrix<N> {
+    let mut result = [[0; N]; N];
+    for (i, row) in result.iter_mut().enumerate() {
+        row[i] = 1;
+    }
+    result
+}
+
+/// Computes the matrix product a * b, or None if (with exact
+/// arithmetic) an element overflows.
+pub fn mat_mul<const N: usize>(
+    a: &Matrix<N>,
+    b: &Matrix<N>,
+    arith: Arithmetic,
+) -> Option<Matrix<N>> {
+    let mut result = [[0; N]; N];
+    for (i, row) in result.iter_mut().enumerate() {
+        for (j, cell) in row.iter_mut().enumerate() {
+            for k in 0..N {
+                *cell = arith.add(*cell, arith.mul(a[i][k], b[k][j])?)?;
+            }
+        }
+    }
+    Some(result)
+}
+
+/// Computes a^exp by repeated squaring, or None if (with exact
+/// arithmetic) an element overflows.
+pub fn mat_pow<const N: usize>(a: &Matrix<N>, exp: u64, arith: Arithmetic) -> Option<Matrix<N>> {
+    let mut result = mat_identity();
+    let mut base = *a;
+    let mut exp = exp;
+    while exp > 0 {
+        if exp & 1 != 0 {
+            result = mat_mul(&result, &base, arith)?;
+        }
+        exp >>= 1;
+        if exp > 0 {
+            base = mat_mul(&base, &base, arith)?;
+        }
+    }
+    Some(result)
+}
+
+/// Finds the multiplicative inverse of a modulo m, if there is one.
+pub fn inverse_mod(a: u64, m: u64) -> Option<u64> {
+    // The extended Euclidean algorithm, keeping only the
+    // coefficients of a.
+    let (mut r0, mut r1) = (m as i128, (a % m) as i128);
+    let (mut t0, mut t1) = (0_i128, 1_i128);
+    while r1 != 0 {
+        let q = r0 / r1;
+        (r0, r1) = (r1, r0 - q * r1);
+        (t0, t1) = (t1, t0 - q * t1);
+    }
+    if r0 == 1 {
+        Some(t0.rem_euclid(m as i128) as u64)
+    } else {
+        None
+    }
+}
+
+/// Finds t such that t = r (mod m) for each (r, m) in
+/// `residues_and_moduli`, which must have pairwise coprime moduli.
+/// Returns t and the product of the moduli (t is the smallest
+/// non-negative solution), or None if the moduli are not coprime or
+/// their product is too large.
+pub fn crt(residues_and_moduli: &[(u64, u64)]) -> Option<(u64, u64)> {
+    let mut t: u64 = 0;
+    let mut product: u64 = 1;
+    for (r, m) in residues_and_moduli {
+        // We have t mod product; find k such that t + k * product =
+        // r (mod m).
+        let inverse = inverse_mod(product % m, *m)?;
+        let difference = (r % m + m - t % m) % m;
+        let k = mul_mod(difference, inverse, *m);
+        let next_product = product.checked_mul(*m)?;
+        t = (t as u128 + k as u128 * product as u128) as u64;
+        product = next_product;
+    }
+    Some((t, product))
+}
+
+/// Factorises n by trial division, returning its prime factors in
+/// increasing order, each with its multiplicity.
+pub fn factorise(n: u64) -> Vec<(u64, u32)> {
+    let mut result = Vec::new();
+    let mut n = n;
+    let mut p: u64 = 2;
+    while p.saturating_mul(p) <= n {
+        if n % p == 0 {
+            let mut e = 0;
+            while n % p == 0 {
+                n /= p;
+                e += 1;
+            }
+            result.push((p, e));
+        }
+        p += if p == 2 { 1 } else { 2 };
+    }
+    if n > 1 {
+        result.push((n, 1));
+    }
+    result
+}
+
+pub fn is_prime(n: u64) -> bool {
+    n > 1 && factorise(n) == [(n, 1)]
+}
+
+/// Finds the multiplicative order of g modulo the prime p.
+pub fn order(g: u64, p: u64) -> u64 {
+    let mut n = p - 1;
+    for (q, _) in factorise(p - 1) {
+        while n % q == 0 && pow_mod(g, n / q, p) == 1 {
+            n /= q;
+        }
+    }
+    n
+}
+
+/// Finds the smallest x such that g^x = h (mod m) by trying each x
+/// in turn.
+pub fn log_brute_force(g: u64, h: u64, m: u64) -> Option<u64> {
+    let h = h % m;
+    let mut v = 1 % m;
+    for x in 0..m {
+        if v == h {
+            return Some(x);
+        }
+        v = mul_mod(v, g, m);
+    }
+    None
+}
+
+/// Finds the smallest x less than `n` such that g^x = h (mod m), using
+/// the baby-step giant-step algorithm.  `n` should be at least the
+/// order of g.
+pub fn log_bsgs(g: u64, h: u64, m: u64, n: u64) -> Option<u64> {
+    let steps = (n as f64).sqrt().ceil() as u64;
+    let steps = if steps.saturating_mul(steps) < n {
+        steps + 1
+    } else {
+        steps
+    };
+    // Baby steps: g^j for j < steps.
+    let mut baby: HashMap<u64, u64> = HashMap::with_capacity(steps as usize);
+    let mut v = 1 % m;
+    for j in 0..steps {
+        baby.entry(v).or_insert(j);
+        v = mul_mod(v, g, m);
+    }
+    // Giant steps: h * g^(-i * steps) for i < steps.
+    let giant = inverse_mod(pow_mod(g, steps, m), m)?;
+    let mut gamma = h % m;
+    for i in 0..steps {
+        if let Some(j) = baby.get(&gamma) {
+            let x = i * steps + j;
+            return if x < n { Some(x) } else { None };
+        }
+        gamma = mul_mod(gamma, giant, m);
+    }
+    None
+}
+
+/// Finds x such that g^x = h (mod p), where g has order q^e and q is
+/// prime.  The digits of x in base q are found one at a time, each
+/// with a discrete logarithm in the subgroup of order q.
+fn log_prime_power(g: u64, h: u64, p: u64, q: u64, e: u32) -> Option<u64> {
+    let g_inverse = inverse_mod(g, p)?;
+    // gamma has order q.
+    let gamma = pow_mod(g, q.pow(e - 1), p);
+    let mut x: u64 = 0;
+    let mut q_to_k: u64 = 1;
+    for k in 0..e {
+        // Remove the digits we know, and project into the subgroup
+        // of order q.
+        let h_k = pow_mod(mul_mod(pow_mod(g_inverse, x, p), h, p), q.pow(e - 1 - k), p);
+        let digit = log_bsgs(gamma, h_k, p, q)?;
+        x += digit * q_to_k;
+        q_to_k = q_to_k.saturating_mul(q);
+    }
+    Some(x)
+}
+
+/// Finds the smallest x such that g^x = h (mod p), for prime p, using
+/// the Pohlig-Hellman algorithm.  The work is dominated by discrete
+/// logarithms in subgroups whose orders are the prime factors of the
+/// order of g, so this is fast when those are all small.
+pub fn log_pohlig_hellman(g: u64, h: u64, p: u64) -> Option<u64> {
+    let n = order(g, p);
+    let mut congruences = Vec::new();
+    for (q, e) in factorise(n) {
+        let q_to_e = q.pow(e);
+        let cofactor = n / q_to_e;
+        let x = log_prime_power(pow_mod(g, cofactor, p), pow_mod(h, cofactor, p), p, q, e)?;
+        congruences.push((x, q_to_e));
+    }
+    let (x, _) = crt(&congruences)?;
+    // If h is not a power of g, the projections may still have had
+    // logarithms.
+    if pow_mod(g, x, p) == h % p {
+        Some(x)
+    } else {
+        None
+    }
+}
+
+/// The ways of finding a discrete logarithm.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Solver {
+    BruteForce,
+    BabyStepGiantStep,
+    PohligHellman,
+}
+
+impl Solver {
+    pub fn all() -> &'static [Solver] {
+        &[
+            Solver::BruteForce,
+            Solver::BabyStepGiantStep,
+            Solver::PohligHellman,
+        ]
+    }
+
+    /// Finds the smallest x such that g^x = h (mod p), for prime p.
+    pub fn log(&self, g: u64, h: u64, p: u64) -> Option<u64> {
+        match self {
+            Solver::BruteForce => log_brute_force(g, h, p),
+            Solver::BabyStepGiantStep => log_bsgs(g, h, p, p - 1),
+            Solver::PohligHellman => log_pohlig_hellman(g, h, p),
+        }
+    }
+}
+
+impl fmt::Display for Solver {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match self {
+            Solver::BruteForce => "brute-force",
+            Solver::BabyStepGiantStep => "bsgs",
+            Solver::PohligHellman => "pohlig-hellman",
+        })
+    }
+}
+
+impl FromStr for Solver {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Solver, String> {
+        match s {
+            "brute-force" => Ok(Solver::BruteForce),
+            "bsgs" => Ok(Solver::BabyStepGiantStep),
+            "pohlig-hellman" => Ok(Solver::PohligHellman),
+            _ => Err(format!("unknown solver '{}'", s)),
+        }
+    }
+}
+
+pub fn self_test() -> Result<(), String> {
+    if pow_mod(7, 8, 20201227) != 5764801 || pow_mod(17807724, 8, 20201227) != 14897079 {
+        return Err("FAIL: wrong result from pow_mod".to_string());
+    }
+    for (a, m, expected) in &[(3, 11, Some(4)), (10, 17, Some(12)), (6, 9, None)] {
+        if inverse_mod(*a, *m) != *expected {
+            return Err(format!("FAIL: wrong inverse of {} mod {}", a, m));
+        }
+    }
+    // The bus timetable example from day 13: 7,13,x,x,59,x,31,19.
+    let buses = [(7, 0), (13, 1), (59, 4), (31, 6), (19, 7)];
+    let congruences: Vec<(u64, u64)> = buses.iter().map(|(m, i)| ((m - i) % m, *m)).collect();
+    if crt(&congruences) != Some((1068781, 7 * 13 * 59 * 31 * 19)) {
+        return Err(format!("FAIL: wrong CRT result {:?}", crt(&congruences)));
+    }
+    if crt(&[(1, 4), (3, 6)]).is_some() {
+        return Err("FAIL: CRT should reject moduli which are not coprime".to_string());
+    }
+    if factorise(20201226) != [(2, 1), (3, 1), (29, 1), (116099, 1)] || !is_prime(20201227) {
+        return Err("FAIL: wrong factorisation of 20201226".to_string());
+    }
+    // The example from the puzzle.
+    for solver in Solver::all() {
+        for (public_key, loop_size) in &[(5764801, 8), (17807724, 11)] {
+            let got = solver.log(7, *public_key, 20201227);
+            if got != Some(*loop_size) {
+                return Err(format!(
+                    "FAIL: {} found loop size {:?} for {}, expected {}",
+                    solver, got, public_key, loop_size
+                ));
+            }
+        }
+    }
+    // Moduli p for which p - 1 has only small prime factors.  The
+    // second is 2^22 * 3 * 5^8 * 7^6 + 1, far too big for the other
+    // solvers.
+    for (p, g, x) in &[
+        (65537, 3, 40000),
+        (998244353, 3, 123456789),
+        (998244353, 9, 123456789),
+        (578268364800000001, 5, 31415926535897932),
+    ] {
+        let h = pow_mod(*g, *x, *p);
+        let n = order(*g, *p);
+        let expected = x % n;
+        let mut solvers = vec![Solver::PohligHellman];
+        if *p < 1 << 32 {
+            solvers.push(Solver::BabyStepGiantStep);
+        }
+        for solver in solvers {
+            let got = solver.log(*g, h, *p);
+            if got != Some(expected) {
+                return Err(format!(
+                    "FAIL: {} found {:?} for {}^x = {} mod {}, expected {}",
+                    solver, got, g, h, p, expected
+                ));
+            }
+        }
+    }
+    // 3 generates only the squares modulo 11, so 2 is not a power of
+    // 3.
+    for solver in Solver::all() {
+        if let Some(x) = solver.log(3, 2, 11) {
+            return Err(format!("FAIL: {} found 3^{} = 2 mod 11", solver, x));
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const FIBONACCI: Matrix<2> = [[1, 1], [1, 0]];
+
+    #[test]
+    fn identity() {
+        assert_eq!(mat_identity::<3>(), [[1, 0, 0], [0, 1, 0], [0, 0, 1]]);
+        assert_eq!(
+            mat_pow(&FIBONACCI, 0, Arithmetic::exact()),
+            Some(mat_identity())
+        );
+    }
+
+    #[test]
+    fn product() {
+        let a: Matrix<2> = [[1, 2], [3, 4]];
+        let b: Matrix<2> = [[5, 6], [7, 8]];
+        assert_eq!(
+            mat_mul(&a, &b, Arithmetic::exact()),
+            Some([[19, 22], [43, 50]])
+        );
+        assert_eq!(
+            mat_mul(&a, &b, Arithmetic::modulo(10)),
+            Some([[9, 2], [3, 0]])
+        );
+    }
+
+    #[test]
+    fn fibonacci() {
+        // The top right element of FIBONACCI^n is the nth Fibonacci
+        // number.
+        let f = |n, arith| mat_pow(&FIBONACCI, n, arith).map(|m| m[0][1]);
+        assert_eq!(f(10, Arithmetic::exact()), Some(55));
+        assert_eq!(f(92, Arithmetic::exact()), Some(7540113804746346429));
+        // The 94th Fibonacci number, in the top left of FIBONACCI^93,
+        // is too large for a u64.
+        assert_eq!(f(93, Arithmetic::exact()), None);
+        assert_eq!(
+            f(1_000_000_000_000, Arithmetic::modulo(1_000_000_007)),
+            Some(730695249)
+        );
+    }
+
+    #[test]
+    fn powers_agree_with_products() {
+        let a: Matrix<3> = [[1, 1, 1], [1, 0, 0], [0, 1, 0]];
+        let arith = Arithmetic::modulo(1_000_003);
+        let mut product = mat_identity();
+        for n in 0..50 {
+            assert_eq!(mat_pow(&a, n, arith), Some(product));
+            product = mat_mul(&product, &a, arith).unwrap();
+        }
+    }
+}
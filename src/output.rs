@@ -0,0 +1,11 @@
+//! Writing the answers.
+//!
+//! Each day writes its answers to a writer given to its `run`
+//! function, so that tests can capture them.  Traces and the results
+//! of self-tests go straight to the standard output instead.
+use std::io;
+
+/// Describes a failure to write the answers.
+pub fn write_error(e: io::Error) -> String {
+    format!("failed to write the output: {}", e)
+}
@@ -0,0 +1,114 @@
+//! Reporting the progress of long computations.
+//!
+//! A `Progress` counts units of work (moves, iterations and so on)
+//! towards a known total, and every so often prints how far it has
+//! got, how fast it is going and how long the rest should take.
+//! Callers check `due` between batches of work rather than after each
+//! unit, so that reporting costs nothing in the inner loop.
+use std::time::{Duration, Instant};
+
+pub struct Progress {
+    /// What the units are called, for example "moves".
+    unit: String,
+    total: u64,
+    /// The number of units between reports.
+    every: u64,
+    /// The number of units done when the next report is due.
+    next_report: u64,
+    start: Instant,
+}
+
+/// Formats a duration as seconds, or minutes and seconds.
+fn format_duration(d: Duration) -> String {
+    let secs = d.as_secs_f64();
+    if secs < 60.0 {
+        format!("{:.1}s", secs)
+    } else {
+        format!("{}m{:02}s", d.as_secs() / 60, d.as_secs() % 60)
+    }
+}
+
+impl Progress {
+    /// Starts timing `total` units of work, reporting after every
+    /// `every` units.
+    pub fn new(unit: &str, total: u64, every: u64) -> Progress {
+        let every = every.max(1);
+        Progress {
+            unit: unit.to_string(),
+            total,
+            every,
+            next_report: every,
+            start: Instant::now(),
+        }
+    }
+
+    /// The number of units done when the next report is due; this is
+    /// never more than the total.
+    pub fn due(&self) -> u64 {
+        self.next_report.min(self.total)
+    }
+
+    /// Describes the state of the work after `done` units have taken
+    /// `elapsed`.
+    pub fn describe(&self, done: u64, elapsed: Duration) -> String {
+        let percent = if self.total == 0 {
+            100.0
+        } else {
+            100.0 * done as f64 / self.total as f64
+        };
+        let secs = elapsed.as_secs_f64();
+        let mut result = format!(
+            "{}/{} {} ({:.1}%) in {}",
+            done,
+            self.total,
+            self.unit,
+            percent,
+            format_duration(elapsed)
+        );
+        if secs > 0.0 && done > 0 {
+            let rate = done as f64 / secs;
+            result.push_str(&format!(", {:.0} {}/s", rate, self.unit));
+            if done < self.total {
+                let remaining = (self.total - done) as f64 / rate;
+                result.push_str(&format!(
+                    ", ETA {}",
+                    format_duration(Duration::from_secs_f64(remaining))
+                ));
+            }
+        }
+        result
+    }
+
+    /// Reports that `done` units are complete (on stderr) and works
+    /// out when the next report is due.
+    pub fn report(&mut self, done: u64) {
+        eprintln!("{}", self.describe(done, self.start.elapsed()));
+        self.advance(done);
+    }
+
+    fn advance(&mut self, done: u64) {
+        self.next_report = (done / self.every + 1) * self.every;
+    }
+}
+
+pub fn self_test() -> Result<(), String> {
+    let mut p = Progress::new("moves", 1000, 300);
+    if p.due() != 300 {
+        return Err(format!("FAIL: first report due at {}", p.due()));
+    }
+    let got = p.describe(250, Duration::from_secs(5));
+    let expected = "250/1000 moves (25.0%) in 5.0s, 50 moves/s, ETA 15.0s";
+    if got != expected {
+        return Err(format!("FAIL: expected '{}', got '{}'", expected, got));
+    }
+    let got = p.describe(1000, Duration::from_secs(125));
+    let expected = "1000/1000 moves (100.0%) in 2m05s, 8 moves/s";
+    if got != expected {
+        return Err(format!("FAIL: expected '{}', got '{}'", expected, got));
+    }
+    p.advance(900);
+    if p.due() != 1000 {
+        return Err(format!("FAIL: last report due at {}", p.due()));
+    }
+    Ok(())
+}
@@ -0,0 +1,54 @@
+//! A small pseudo-random number generator for the programs which make
+//! up inputs, benchmarks and simulations.  The numbers it produces
+//! depend only on the seed, so anything made with a given seed can be
+//! made again.
+
+/// A linear congruential generator (using Knuth's MMIX multiplier).
+/// This is nowhere near good enough for cryptography or serious
+/// statistics, but it is quite good enough for making up puzzles.
+#[derive(Debug, Clone)]
+pub struct Lcg {
+    seed: u64,
+}
+
+impl Lcg {
+    pub fn new(seed: u64) -> Lcg {
+        Lcg { seed }
+    }
+
+    /// Returns the next 31 random bits.  The low bits of an LCG are
+    /// not very random, so they are discarded.
+    pub fn next_bits(&mut self) -> u64 {
+        self.seed = self.seed.wrapping_mul(6364136223846793005).wrapping_add(1);
+        self.seed >> 33
+    }
+
+    /// Returns a number in the range 0..limit.
+    pub fn below(&mut self, limit: usize) -> usize {
+        (self.next_bits() % (limit as u64)) as usize
+    }
+
+    /// Returns a number in the range low..=high.
+    pub fn between(&mut self, low: i32, high: i32) -> i32 {
+        low + self.below((high - low + 1) as usize) as i32
+    }
+
+    /// Returns one of `items`, which must not be empty.
+    pub fn choose<'a, T>(&mut self, items: &'a [T]) -> &'a T {
+        &items[self.below(items.len())]
+    }
+
+    /// Returns a string of `n` decimal digits.
+    pub fn digits(&mut self, n: usize) -> String {
+        (0..n)
+            .map(|_| (b'0' + self.below(10) as u8) as char)
+            .collect()
+    }
+
+    /// Puts `items` in a random order (by the Fisher-Yates shuffle).
+    pub fn shuffle<T>(&mut self, items: &mut [T]) {
+        for i in (1..items.len()).rev() {
+            items.swap(i, self.below(i + 1));
+        }
+    }
+}
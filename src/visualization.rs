@@ -0,0 +1,323 @@
+//! Rendering puzzle states as images.
+//!
+//! A `Canvas` is an RGB image held in memory.  Most puzzle states are
+//! grids of cells, and `render_grid` draws one of those with each cell
+//! as a square block of pixels.  Canvases can be written as PNG
+//! images, and read from PNG or (uncompressed) BMP images.
+use png::{BitDepth, ColorType, Decoder, Encoder, Transformations};
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, BufWriter, Read, Write};
+use std::path::Path;
+
+/// A colour, as red, green and blue intensities.
+pub type Rgb = [u8; 3];
+
+pub const BLACK: Rgb = [0, 0, 0];
+pub const WHITE: Rgb = [255, 255, 255];
+pub const GREY: Rgb = [192, 192, 192];
+pub const RED: Rgb = [220, 40, 40];
+pub const GREEN: Rgb = [40, 160, 40];
+pub const BLUE: Rgb = [40, 80, 220];
+
+/// The size of the file header and information header of a BMP image
+/// written by `Canvas::write_bmp`.
+const BMP_HEADER_SIZE: usize = 14 + 40;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Canvas {
+    width: usize,
+    height: usize,
+    /// Three bytes per pixel, row by row from the top left.
+    pixels: Vec<u8>,
+}
+
+impl Canvas {
+    pub fn new(width: usize, height: usize, background: Rgb) -> Canvas {
+        Canvas {
+            width,
+            height,
+            pixels: background.repeat(width * height),
+        }
+    }
+
+    pub fn width(&self) -> usize {
+        self.width
+    }
+
+    pub fn height(&self) -> usize {
+        self.height
+    }
+
+    fn offset(&self, x: usize, y: usize) -> usize {
+        assert!(x < self.width && y < self.height);
+        (y * self.width + x) * 3
+    }
+
+    pub fn get(&self, x: usize, y: usize) -> Rgb {
+        let i = self.offset(x, y);
+        [self.pixels[i], self.pixels[i + 1], self.pixels[i + 2]]
+    }
+
+    pub fn set(&mut self, x: usize, y: usize, colour: Rgb) {
+        let i = self.offset(x, y);
+        self.pixels[i..i + 3].copy_from_slice(&colour);
+    }
+
+    /// Fills the rectangle whose top left corner is at (x, y); the
+    /// parts of it which lie outside the canvas are ignored.
+    pub fn fill_rect(&mut self, x: usize, y: usize, width: usize, height: usize, colour: Rgb) {
+        for py in y..(y + height).min(self.height) {
+            for px in x..(x + width).min(self.width) {
+                self.set(px, py, colour);
+            }
+        }
+    }
+
+    pub fn write_png<W: Write>(&self, w: W) -> Result<(), String> {
+        let mut encoder = Encoder::new(w, self.width as u32, self.height as u32);
+        encoder.set_color(ColorType::Rgb);
+        encoder.set_depth(BitDepth::Eight);
+        let mut writer = encoder.write_header().map_err(|e| e.to_string())?;
+        writer
+            .write_image_data(&self.pixels)
+            .map_err(|e| e.to_string())?;
+        writer.finish().map_err(|e| e.to_string())
+    }
+
+    pub fn save_png(&self, path: &Path) -> Result<(), String> {
+        let fail = |e: String| format!("failed to write {}: {}", path.display(), e);
+        let f = File::create(path).map_err(|e| fail(e.to_string()))?;
+        let mut w = BufWriter::new(f);
+        self.write_png(&mut w).map_err(fail)?;
+        w.flush().map_err(|e: io::Error| fail(e.to_string()))
+    }
+
+    /// Reads a PNG image; any transparency is ignored.
+    pub fn read_png<R: Read>(r: R) -> Result<Canvas, String> {
+        let mut decoder = Decoder::new(r);
+        decoder.set_transformations(Transformations::EXPAND | Transformations::STRIP_16);
+        let mut reader = decoder.read_info().map_err(|e| e.to_string())?;
+        let mut buf = vec![0; reader.output_buffer_size()];
+        let info = reader.next_frame(&mut buf).map_err(|e| e.to_string())?;
+        let (width, height) = (info.width as usize, info.height as usize);
+        let channels = match info.color_type {
+            ColorType::Grayscale => 1,
+            ColorType::GrayscaleAlpha => 2,
+            ColorType::Rgb => 3,
+            ColorType::Rgba => 4,
+            ColorType::Indexed => {
+                // EXPAND should have turned this into RGB.
+                return Err("unsupported indexed colour image".to_string());
+            }
+        };
+        let mut pixels = Vec::with_capacity(width * height * 3);
+        for y in 0..height {
+            let row = &buf[y * info.line_size..];
+            for x in 0..width {
+                let px = &row[x * channels..(x + 1) * channels];
+                if channels < 3 {
+                    pixels.extend_from_slice(&[px[0], px[0], px[0]]);
+                } else {
+                    pixels.extend_from_slice(&px[0..3]);
+                }
+            }
+        }
+        Ok(Canvas {
+            width,
+            height,
+            pixels,
+        })
+    }
+
+    pub fn load_png(path: &Path) -> Result<Canvas, String> {
+        let f =
+            File::open(path).map_err(|e| format!("failed to open {}: {}", path.display(), e))?;
+        Canvas::read_png(BufReader::new(f))
+            .map_err(|e| format!("failed to read {}: {}", path.display(), e))
+    }
+
+    /// Writes a 24-bit BMP image.
+    pub fn write_bmp<W: Write>(&self, mut w: W) -> Result<(), String> {
+        let row_size = (self.width * 3).next_multiple_of(4);
+        let image_size = row_size * self.height;
+        let mut out: Vec<u8> = Vec::with_capacity(BMP_HEADER_SIZE + image_size);
+        out.extend_from_slice(b"BM");
+        out.extend_from_slice(&((BMP_HEADER_SIZE + image_size) as u32).to_le_bytes());
+        out.extend_from_slice(&[0; 4]);
+        out.extend_from_slice(&(BMP_HEADER_SIZE as u32).to_le_bytes());
+        out.extend_from_slice(&40u32.to_le_bytes());
+        out.extend_from_slice(&(self.width as i32).to_le_bytes());
+        out.extend_from_slice(&(self.height as i32).to_le_bytes());
+        out.extend_from_slice(&1u16.to_le_bytes());
+        out.extend_from_slice(&24u16.to_le_bytes());
+        // No compression, and the remaining fields can be zero.
+        out.extend_from_slice(&[0; 24]);
+        // Rows are stored from the bottom up, as BGR.
+        for y in (0..self.height).rev() {
+            for x in 0..self.width {
+                let [r, g, b] = self.get(x, y);
+                out.extend_from_slice(&[b, g, r]);
+            }
+            out.resize(out.len() + row_size - self.width * 3, 0);
+        }
+        w.write_all(&out).map_err(|e| e.to_string())
+    }
+
+    /// Reads an uncompressed BMP image with 1, 4, 8, 24 or 32 bits
+    /// per pixel.  Compressed images are not supported.
+    pub fn read_bmp<R: Read>(mut r: R) -> Result<Canvas, String> {
+        let mut data: Vec<u8> = Vec::new();
+        r.read_to_end(&mut data).map_err(|e| e.to_string())?;
+        let truncated = || "the BMP image is truncated".to_string();
+        let u16_at = |i: usize| -> Result<u16, String> {
+            data.get(i..i + 2)
+                .map(|b| u16::from_le_bytes([b[0], b[1]]))
+                .ok_or_else(truncated)
+        };
+        let u32_at = |i: usize| -> Result<u32, String> {
+            data.get(i..i + 4)
+                .map(|b| u32::from_le_bytes([b[0], b[1], b[2], b[3]]))
+                .ok_or_else(truncated)
+        };
+        if !data.starts_with(b"BM") {
+            return Err("not a BMP image".to_string());
+        }
+        let pixel_offset = u32_at(10)? as usize;
+        let header_size = u32_at(14)? as usize;
+        if header_size < 40 {
+            return Err("unsupported BMP header (too old)".to_string());
+        }
+        let width = u32_at(18)? as i32;
+        let height = u32_at(22)? as i32;
+        let bits = u16_at(28)? as usize;
+        if u32_at(30)? != 0 {
+            return Err("compressed BMP images are not supported".to_string());
+        }
+        if width <= 0 || height == 0 {
+            return Err(format!("bad BMP image size {}x{}", width, height));
+        }
+        let palette: Vec<Rgb> = match bits {
+            1 | 4 | 8 => {
+                let count = match u32_at(46)? {
+                    0 => 1 << bits,
+                    n => n as usize,
+                };
+                let start = 14 + header_size;
+                let table = data.get(start..start + count * 4).ok_or_else(truncated)?;
+                table.chunks(4).map(|c| [c[2], c[1], c[0]]).collect()
+            }
+            24 | 32 => Vec::new(),
+            _ => return Err(format!("unsupported BMP depth of {} bits", bits)),
+        };
+        let (width, top_down) = (width as usize, height < 0);
+        let height = height.unsigned_abs() as usize;
+        let row_size = (width * bits).div_ceil(32) * 4;
+        let mut canvas = Canvas::new(width, height, BLACK);
+        for row in 0..height {
+            let start = pixel_offset + row * row_size;
+            let bytes = data.get(start..start + row_size).ok_or_else(truncated)?;
+            let y = if top_down { row } else { height - 1 - row };
+            for x in 0..width {
+                let colour = match bits {
+                    24 | 32 => {
+                        let px = &bytes[x * bits / 8..];
+                        [px[2], px[1], px[0]]
+                    }
+                    _ => {
+                        let bit = x * bits;
+                        let index = (bytes[bit / 8] >> (8 - bits - bit % 8)) & ((1 << bits) - 1);
+                        *palette
+                            .get(index as usize)
+                            .ok_or_else(|| format!("BMP colour {} is not in the palette", index))?
+                    }
+                };
+                canvas.set(x, y, colour);
+            }
+        }
+        Ok(canvas)
+    }
+
+    /// Reads a PNG or BMP image, whichever the file turns out to be.
+    pub fn load(path: &Path) -> Result<Canvas, String> {
+        let fail = |e: String| format!("failed to read {}: {}", path.display(), e);
+        let mut f = BufReader::new(
+            File::open(path).map_err(|e| format!("failed to open {}: {}", path.display(), e))?,
+        );
+        let is_bmp = f
+            .fill_buf()
+            .map_err(|e| fail(e.to_string()))?
+            .starts_with(b"BM");
+        if is_bmp {
+            Canvas::read_bmp(f).map_err(fail)
+        } else {
+            Canvas::read_png(f).map_err(fail)
+        }
+    }
+}
+
+/// Draws a grid of `width` by `height` cells, each of which is a
+/// square of `scale` pixels.  `colour_of(x, y)` gives the colour of
+/// the cell in column x and row y.
+pub fn render_grid<F>(width: usize, height: usize, scale: usize, colour_of: F) -> Canvas
+where
+    F: Fn(usize, usize) -> Rgb,
+{
+    let mut canvas = Canvas::new(width * scale, height * scale, WHITE);
+    for y in 0..height {
+        for x in 0..width {
+            canvas.fill_rect(x * scale, y * scale, scale, scale, colour_of(x, y));
+        }
+    }
+    canvas
+}
+
+pub fn self_test() -> Result<(), String> {
+    let canvas = render_grid(3, 2, 2, |x, y| if (x + y) % 2 == 0 { BLACK } else { RED });
+    if (canvas.width(), canvas.height()) != (6, 4) {
+        return Err(format!(
+            "FAIL: expected a 6x4 canvas, got {}x{}",
+            canvas.width(),
+            canvas.height()
+        ));
+    }
+    for (x, y, expected) in &[(0, 0, BLACK), (1, 1, BLACK), (2, 0, RED), (5, 3, RED)] {
+        if canvas.get(*x, *y) != *expected {
+            return Err(format!(
+                "FAIL: pixel ({}, {}) should be {:?} but is {:?}",
+                x,
+                y,
+                expected,
+                canvas.get(*x, *y)
+            ));
+        }
+    }
+    let mut encoded: Vec<u8> = Vec::new();
+    canvas.write_png(&mut encoded)?;
+    let decoded = Canvas::read_png(encoded.as_slice())?;
+    if decoded != canvas {
+        return Err("FAIL: canvas does not survive a round trip through PNG".to_string());
+    }
+    // Rows of BMP images are padded to a multiple of four bytes.
+    let odd = render_grid(3, 2, 1, |x, y| if (x + y) % 2 == 0 { BLUE } else { GREEN });
+    for c in &[&canvas, &odd] {
+        let mut encoded: Vec<u8> = Vec::new();
+        c.write_bmp(&mut encoded)?;
+        if Canvas::read_bmp(encoded.as_slice())? != **c {
+            return Err("FAIL: canvas does not survive a round trip through BMP".to_string());
+        }
+    }
+    // A 2x2 top-down 1-bit image with a palette of black and white.
+    let mut mono: Vec<u8> = Vec::new();
+    mono.extend_from_slice(b"BM");
+    mono.extend_from_slice(&[70, 0, 0, 0, 0, 0, 0, 0, 62, 0, 0, 0, 40, 0, 0, 0]);
+    mono.extend_from_slice(&[2, 0, 0, 0, 0xfe, 0xff, 0xff, 0xff, 1, 0, 1, 0]);
+    mono.extend_from_slice(&[0; 24]);
+    mono.extend_from_slice(&[0, 0, 0, 0, 255, 255, 255, 0]);
+    mono.extend_from_slice(&[0b0100_0000, 0, 0, 0, 0b1000_0000, 0, 0, 0]);
+    let decoded = Canvas::read_bmp(mono.as_slice())?;
+    let expected = [(0, 0, BLACK), (1, 0, WHITE), (0, 1, WHITE), (1, 1, BLACK)];
+    if expected.iter().any(|(x, y, c)| decoded.get(*x, *y) != *c) {
+        return Err(format!("FAIL: wrong 1-bit BMP image {:?}", decoded));
+    }
+    Ok(())
+}
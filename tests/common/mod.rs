@@ -0,0 +1,38 @@
+//! Helpers for running the programs in tests.
+use std::io::Write;
+use std::process::{Command, Output, Stdio};
+
+/// Runs `program` with `args`, feeding it `input`.
+pub fn output(program: &str, args: &[&str], input: &[u8]) -> Output {
+    let mut child = Command::new(program)
+        .args(args)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .unwrap_or_else(|e| panic!("failed to start {}: {}", program, e));
+    child
+        .stdin
+        .take()
+        .unwrap()
+        .write_all(input)
+        .unwrap_or_else(|e| panic!("failed to write the input of {}: {}", program, e));
+    child
+        .wait_with_output()
+        .unwrap_or_else(|e| panic!("failed to wait for {}: {}", program, e))
+}
+
+/// Runs `program` with `args`, feeding it `input`, and returns its
+/// standard output.  The program has to succeed.
+pub fn run(program: &str, args: &[&str], input: &[u8]) -> String {
+    let output = output(program, args, input);
+    assert!(
+        output.status.success(),
+        "{} {:?} failed ({}):\n{}",
+        program,
+        args,
+        output.status,
+        String::from_utf8_lossy(&output.stderr)
+    );
+    String::from_utf8(output.stdout).expect("output should be UTF-8")
+}
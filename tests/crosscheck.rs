@@ -0,0 +1,158 @@
+//! Checks that the algorithms of each day which has several agree on
+//! the samples, using `aoc crosscheck`, and that the old names of the
+//! options choosing an algorithm still work.
+mod common;
+
+use common::{output, run};
+use std::fs;
+use std::path::Path;
+
+fn sample(name: &str) -> Vec<u8> {
+    let path = Path::new(env!("CARGO_MANIFEST_DIR"))
+        .join("samples")
+        .join(name);
+    fs::read(&path).unwrap_or_else(|e| panic!("failed to read {}: {}", path.display(), e))
+}
+
+fn crosscheck(day: &str, options: &[&str], input: &[u8]) {
+    let mut args = vec!["crosscheck", day];
+    args.extend_from_slice(options);
+    let out = run(env!("CARGO_BIN_EXE_aoc"), &args, input);
+    assert!(
+        out.trim_end().ends_with("algorithms agree"),
+        "unexpected output from {}:\n{}",
+        day,
+        out
+    );
+}
+
+#[test]
+fn day01() {
+    crosscheck("1", &[], &sample("day01-sample.txt"));
+}
+
+#[test]
+fn day02() {
+    crosscheck("2", &[], &sample("day02-sample.txt"));
+}
+
+#[test]
+fn day09() {
+    crosscheck("9", &["--preamble", "5"], &sample("day09-pt1.txt"));
+}
+
+#[test]
+fn day10() {
+    crosscheck("10", &[], &sample("day10-2.txt"));
+}
+
+#[test]
+fn day13() {
+    crosscheck("13", &[], &sample("day13.txt"));
+}
+
+#[test]
+fn day17() {
+    crosscheck("17", &[], &sample("day17.txt"));
+}
+
+#[test]
+fn day18() {
+    crosscheck("18", &[], &sample("day18.txt"));
+}
+
+#[test]
+fn day19() {
+    crosscheck("19", &[], &sample("day19-4.txt"));
+}
+
+#[test]
+fn day20() {
+    crosscheck("20", &[], &sample("day20.txt"));
+}
+
+#[test]
+fn day24() {
+    crosscheck("24", &[], &sample("day24.txt"));
+}
+
+#[test]
+fn day25() {
+    crosscheck("25", &[], b"5764801\n17807724\n");
+}
+
+/// Checks that `option` chooses `algorithm` just as `--algorithm`
+/// does.
+fn old_option(program: &str, option: &str, algorithm: &str, input: &[u8]) {
+    assert_eq!(
+        run(program, &[option, algorithm], input),
+        run(program, &["--algorithm", algorithm], input)
+    );
+}
+
+#[test]
+fn day01_engine() {
+    old_option(
+        env!("CARGO_BIN_EXE_day01"),
+        "--engine",
+        "bitset",
+        &sample("day01-sample.txt"),
+    );
+}
+
+#[test]
+fn day02_engine() {
+    old_option(
+        env!("CARGO_BIN_EXE_day02"),
+        "--engine",
+        "bytes",
+        &sample("day02-sample.txt"),
+    );
+}
+
+#[test]
+fn day17_engine() {
+    old_option(
+        env!("CARGO_BIN_EXE_day17"),
+        "--engine",
+        "memo",
+        &sample("day17.txt"),
+    );
+}
+
+#[test]
+fn day18_engine() {
+    old_option(
+        env!("CARGO_BIN_EXE_day18"),
+        "--engine",
+        "vm",
+        &sample("day18.txt"),
+    );
+}
+
+#[test]
+fn day24_engine() {
+    old_option(
+        env!("CARGO_BIN_EXE_day24"),
+        "--engine",
+        "chunked",
+        &sample("day24.txt"),
+    );
+}
+
+#[test]
+fn day25_solver() {
+    old_option(
+        env!("CARGO_BIN_EXE_day25"),
+        "--solver",
+        "bsgs",
+        b"5764801\n17807724\n",
+    );
+}
+
+#[test]
+fn single_algorithm() {
+    let out = output(env!("CARGO_BIN_EXE_aoc"), &["crosscheck", "5"], &[]);
+    assert!(!out.status.success());
+    assert!(String::from_utf8_lossy(&out.stderr).contains("day05 has only one algorithm"));
+}
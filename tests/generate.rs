@@ -0,0 +1,28 @@
+//! Checks that the puzzles made up by `day20 generate` can be made
+//! again from the same seed, and that they can be solved.
+mod common;
+
+use common::run;
+
+#[test]
+fn day20_generate_is_repeatable() {
+    let day20 = env!("CARGO_BIN_EXE_day20");
+    let args = [
+        "generate",
+        "--tiles",
+        "12",
+        "--tile-size",
+        "10",
+        "--seed",
+        "7",
+    ];
+    let puzzle = run(day20, &args, &[]);
+    assert_eq!(puzzle, run(day20, &args, &[]));
+    let solution = run(day20, &[], puzzle.as_bytes());
+    assert!(
+        solution.contains("Part 1: corner product is ")
+            && solution.contains("Part 2: roughness is "),
+        "the generated puzzle was not solved:\n{}",
+        solution
+    );
+}